@@ -1,18 +1,24 @@
 use std::{fs::File, sync::Arc};
 
 use anyhow::Result;
-use primitives::{shared_object::SharedObject, ThinIdx};
+use parking_lot::RwLockReadGuard;
+use primitives::{shared_object::SharedObject, InternalString, LockFairness, ThinIdx};
 
 use crate::{
-    block::inner::BlockInner,
+    block::inner::{BlockAdvice, BlockInner, ScrubOutcome},
     object_ids::{RecordId, TableId},
-    slot::{SlotHandle, SlotTuple},
-    store::result::InsertError,
+    slot::{catch_callback, RemovedSlot, SlotHandle, SlotTuple},
+    store::{
+        io::IoReporter,
+        result::{CorruptBlock, InsertError},
+    },
 };
 
-pub use config::BlockConfig;
-pub use meta::BlockMeta;
+pub use advise::{BlockAdvisor, Madvise, ScanOptions, SequentialBlocks};
+pub use config::{BlockConfig, MAX_BLOCK_CAPACITY};
+pub use meta::{slots_checksum, BlockMeta};
 
+pub mod advise;
 pub mod config;
 pub mod inner;
 pub mod meta;
@@ -26,9 +32,26 @@ pub enum InsertState<T: 'static> {
     },
 }
 
+/// A slot byte for byte, with where it lives, for looking into by hand. See `Block::slot_dump`.
+#[derive(Debug, Clone)]
+pub struct SlotDump {
+    pub block: usize,
+    pub slot: usize,
+    /// Where the slot starts in the file backing its block, `None` for a memory-only block.
+    pub offset: Option<u64>,
+    pub bytes: Vec<u8>,
+    pub block_meta: BlockMeta,
+}
+
+/// Long scans of a fair block let waiting writers in every this many slots.
+const SCAN_BUMP_INTERVAL: usize = 64;
+
 pub struct Block<T: 'static> {
     index: ThinIdx,
     pub(crate) inner: SharedObject<BlockInner<T>>,
+    fairness: LockFairness,
+    /// The label of the store the block belongs to, for its debug output.
+    label: Option<InternalString>,
 }
 
 impl<T> Clone for Block<T> {
@@ -36,6 +59,8 @@ impl<T> Clone for Block<T> {
         Self {
             index: self.index,
             inner: self.inner.clone(),
+            fairness: self.fairness,
+            label: self.label,
         }
     }
 }
@@ -49,12 +74,15 @@ impl<T> Block<T> {
         table: TableId,
         file: Arc<File>,
         offset: usize,
+        config: Option<BlockConfig>,
     ) -> Result<Self> {
         let index = index.into();
 
         Ok(Self {
             index,
-            inner: SharedObject::new(BlockInner::new(index, table, file, offset)?),
+            inner: SharedObject::new(BlockInner::new(index, table, file, offset, config)?),
+            fairness: LockFairness::default(),
+            label: None,
         })
     }
 
@@ -69,9 +97,62 @@ impl<T> Block<T> {
         Ok(Self {
             index,
             inner: SharedObject::new(BlockInner::new_anon(index, table, config)?),
+            fairness: LockFairness::default(),
+            label: None,
         })
     }
 
+    pub fn with_lock_fairness(self, fairness: LockFairness) -> Self {
+        Self { fairness, ..self }
+    }
+
+    pub fn lock_fairness(&self) -> LockFairness {
+        self.fairness
+    }
+
+    pub fn with_label(self, label: Option<InternalString>) -> Self {
+        Self { label, ..self }
+    }
+
+    pub fn label(&self) -> Option<InternalString> {
+        self.label
+    }
+
+    /// Reports the failed flushes of the block, those made when it's dropped included, to a
+    /// store.
+    pub(crate) fn with_io(self, io: Arc<IoReporter>) -> Self {
+        self.inner.write_with(|inner| inner.io = Some(io));
+        self
+    }
+
+    /// Whether the last flush of the block failed, see `Store::failed_blocks`.
+    pub fn flush_failed(&self) -> bool {
+        self.inner.read_with(|inner| inner.flush_failed())
+    }
+
+    /// Flushes a checksum of the slots with the meta from now on, see `StoreConfig::checksum`.
+    pub(crate) fn with_checksum(self, checksum: bool) -> Self {
+        self.inner.write_with(|inner| inner.checksum = checksum);
+        self
+    }
+
+    /// Checks the slots of a block just loaded against the checksum flushed with them, flagging
+    /// the block as corrupt if they don't match. Blocks without one pass.
+    pub(crate) fn verify_checksum(&self) -> Result<(), CorruptBlock> {
+        self.inner.write_with(|inner| inner.verify_checksum())
+    }
+
+    /// The checksum the block failed when it was loaded, see `Store::corrupt_blocks`.
+    pub fn corrupt(&self) -> Option<CorruptBlock> {
+        self.inner.read_with(|inner| inner.corrupt)
+    }
+
+    /// Reads the block back from its file and checks it against its checksum there, see
+    /// `Store::scrub`. Write locks the block while it's read. `None` for a memory-only block.
+    pub fn scrub(&self) -> Result<Option<ScrubOutcome>> {
+        self.inner.write_with_fairness(self.fairness).scrub()
+    }
+
     pub fn index(&self) -> ThinIdx {
         self.index
     }
@@ -113,10 +194,104 @@ impl<T> Block<T> {
         self.inner.read_with(|inner| inner.is_empty())
     }
 
+    pub fn unused_slots(&self) -> usize {
+        self.inner.read_with(|inner| inner.unused_slots())
+    }
+
     pub fn sync_all(&self) -> Result<()> {
         self.inner.read_with(|inner| inner.sync_all())
     }
 
+    /// Tells the OS the block is about to be read front to back, so its pages are read ahead of
+    /// the scan. Only persisted blocks on unix are advised, the rest is a no-op.
+    pub fn advise_sequential(&self) -> Result<()> {
+        self.inner
+            .read_with(|inner| inner.advise(BlockAdvice::Sequential))
+    }
+
+    /// Tells the OS the block is about to be read, so its pages are read in ahead of need. Only
+    /// persisted blocks on unix are advised, the rest is a no-op.
+    pub fn prefetch(&self) -> Result<()> {
+        self.inner
+            .read_with(|inner| inner.advise(BlockAdvice::WillNeed))
+    }
+
+    /// Iterates the handles of every live slot, in slot order. The block stays read locked until
+    /// the iterator is dropped, so slots can be read and written through the handles in the
+    /// meantime, but nothing can be inserted into or removed from this block. Blocks with
+    /// `LockFairness::Latency` instead let waiting writers in every so often, so slots inserted or
+    /// removed during the scan may or may not be seen, and the scanning thread must not hold any
+    /// other lock on the block.
+    pub fn iter_live(&self) -> LiveSlots<'_, T> {
+        LiveSlots {
+            block: self,
+            inner: self.inner.read_recursive(),
+            next: 0,
+        }
+    }
+
+    /// The slot at `idx` as it's laid out in the block, gap or not, with the block's meta. `None`
+    /// for a slot past the ones handed out so far.
+    pub fn slot_dump(&self, idx: ThinIdx) -> Option<SlotDump> {
+        let inner = self.inner.read_recursive();
+        let slot = idx.into_usize();
+
+        if slot >= inner.meta.length {
+            return None;
+        }
+
+        // held so the bytes aren't copied halfway through a write
+        let _guard = inner.slots_by_index[slot].read();
+        let start = slot * Self::SLOT_BYTE_COUNT;
+
+        Some(SlotDump {
+            block: self.index.into_usize(),
+            slot,
+            offset: inner.slot_offset(slot),
+            bytes: inner.slot_bytes()[start..start + Self::SLOT_BYTE_COUNT].to_vec(),
+            block_meta: inner.meta,
+        })
+    }
+
+    /// Calls `f` with the handle of every live slot, in slot order. Stops at the first panic, which
+    /// is returned as `CallbackPanicked`.
+    pub fn foreach_slot<F>(&self, mut f: F) -> Result<()>
+    where
+        F: FnMut(SlotHandle<T>),
+    {
+        for handle in self.iter_live() {
+            catch_callback(|| {
+                f(handle);
+                Ok(())
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Calls `f` with the handle of every slot that has been claimed, including the ones that have
+    /// since been removed. Handles to removed slots don't have any data.
+    pub fn foreach_slot_raw<F>(&self, mut f: F) -> Result<()>
+    where
+        F: FnMut(SlotHandle<T>),
+    {
+        let length = self.inner.read_with(|inner| inner.meta.length);
+
+        for index in 0..length {
+            let handle = SlotHandle {
+                block: self.clone(),
+                idx: ThinIdx::new(index).into_maybe_thin(),
+            };
+
+            catch_callback(|| {
+                f(handle);
+                Ok(())
+            })?;
+        }
+
+        Ok(())
+    }
+
     #[must_use]
     pub fn insert_one(
         &self,
@@ -137,6 +312,65 @@ impl<T> Block<T> {
         })
     }
 
+    /// Drops every value of the block and hands its slots out again from the first. Every slot is
+    /// left a gap, which is what handles to them find from then on, and the emptied meta is
+    /// written to the file right away. Returns the number of values dropped.
+    pub fn truncate(&self) -> Result<usize> {
+        let mut inner = self.inner.write_with_fairness(self.fairness);
+        let mut dropped = 0;
+
+        for slot in inner.slots_by_index[..inner.meta.length].iter() {
+            let mut slot = slot.write();
+            let slot_data = unsafe { slot.as_mut() };
+
+            if let Some(parts) = unsafe { slot_data.read_parts() } {
+                drop(parts);
+                dropped += 1;
+            }
+
+            slot_data.create_gap(ThinIdx::NIL);
+        }
+
+        inner.meta.length = 0;
+        inner.meta.gap_count = 0;
+        inner.meta.gap_tail = ThinIdx::NIL;
+        inner.index_by_record.clear();
+        inner.mark_dirty();
+        inner.sync_all()?;
+
+        Ok(dropped)
+    }
+
+    /// Drops the block if nothing else holds on to it, e.g. a handle to one of its slots, or hands
+    /// it back.
+    pub(crate) fn try_release(self) -> Result<(), Self> {
+        let Self {
+            index,
+            inner,
+            fairness,
+            label,
+        } = self;
+
+        match SharedObject::try_unwrap(inner) {
+            Ok(inner) => {
+                drop(inner);
+                Ok(())
+            }
+            Err(inner) => Err(Self {
+                index,
+                inner,
+                fairness,
+                label,
+            }),
+        }
+    }
+
+    /// Puts back what `SlotHandle::remove_self` took out, under the record id it had. The slot may
+    /// differ from the one it was removed from.
+    pub fn reinsert(&self, removed: RemovedSlot<T>) -> Result<SlotHandle<T>, InsertError<T>> {
+        self.insert_one(removed.record, removed.data)
+    }
+
     #[must_use]
     pub(super) fn insert_one_with(
         &self,
@@ -144,11 +378,38 @@ impl<T> Block<T> {
         record: Option<RecordId>,
         data: T,
     ) -> Result<SlotHandle<T>, InsertError<T>> {
+        self._insert_one_with(inner, record, data, true)
+    }
+
+    /// Without `reuse_gaps`, the item goes to the first unused slot even if the block has gaps,
+    /// which the caller makes sure it has.
+    fn _insert_one_with(
+        &self,
+        inner: &mut BlockInner<T>,
+        record: Option<RecordId>,
+        data: T,
+        reuse_gaps: bool,
+    ) -> Result<SlotHandle<T>, InsertError<T>> {
+        let thin_record = record.map(|r| r.into_thin());
+
+        // checked before a slot is claimed so a rejected item leaves the block untouched
+        if let Some(thin_record) = thin_record {
+            if inner.index_by_record.contains_key(&thin_record) {
+                return Err(InsertError::AlreadyExists {
+                    item: (record, data),
+                    iter: None,
+                });
+            }
+        }
+
         let is_gap;
         let index;
 
-        if inner.meta.gap_count > 0 {
-            index = inner.meta.gap_tail.expect("gap count > 0");
+        if reuse_gaps && inner.meta.gap_count > 0 {
+            index = inner
+                .meta
+                .gap_tail
+                .expect("a block with gaps has a gap list");
             inner.meta.gap_count -= 1;
             is_gap = true;
         } else {
@@ -157,33 +418,34 @@ impl<T> Block<T> {
             is_gap = false;
         }
 
-        let mut new_tail = None;
-
         let mut slot = inner.slots_by_index[index].write();
         let slot_data = unsafe { slot.as_mut() };
 
         if is_gap {
-            new_tail = Some(ThinIdx::new(unsafe { slot_data.previous_gap_unchecked() }));
+            debug_assert!(slot_data.is_gap(), "slot {} on the gap list is live", index);
+
+            // the list is empty once its last gap is taken, whatever that gap links to, which
+            // for blocks written before the end of the list was marked is no gap at all
+            inner.meta.gap_tail = match inner.meta.gap_count {
+                0 => None,
+                _ => {
+                    let next = slot_data.previous_gap();
+                    debug_assert!(next.is_some(), "gap list ends before the gap count");
+                    next
+                }
+            };
         } else {
             slot_data.create_gap(ThinIdx::NIL);
         }
 
-        if let Some(thin_record) = record.map(|r| r.into_thin()) {
-            if inner.index_by_record.contains_key(&thin_record) {
-                return Err(InsertError::AlreadyExists {
-                    item: (record, data),
-                    iter: None,
-                });
-            } else {
-                inner.index_by_record.insert(thin_record, index);
-            }
+        if let Some(thin_record) = thin_record {
+            inner.index_by_record.insert(thin_record, index);
         }
 
         slot_data.fill_gap(record, data);
 
-        if let Some(new_tail) = new_tail {
-            inner.meta.gap_tail = Some(new_tail);
-        }
+        drop(slot);
+        inner.mark_dirty();
 
         Ok(SlotHandle {
             block: self.clone(),
@@ -196,6 +458,38 @@ impl<T> Block<T> {
     where
         I: IntoIterator<Item = SlotTuple<T>> + 'static,
     {
+        self._insert(iter, index_offset, true)
+    }
+
+    /// Like `insert`, but only into the unused slots at the end of the block, leaving its gaps to
+    /// later inserts. The block counts as full once those run out.
+    pub fn insert_unused<I>(
+        &self,
+        iter: I,
+        index_offset: usize,
+    ) -> Result<InsertState<T>, InsertError<T>>
+    where
+        I: IntoIterator<Item = SlotTuple<T>> + 'static,
+    {
+        self._insert(iter, index_offset, false)
+    }
+
+    fn _insert<I>(
+        &self,
+        iter: I,
+        index_offset: usize,
+        reuse_gaps: bool,
+    ) -> Result<InsertState<T>, InsertError<T>>
+    where
+        I: IntoIterator<Item = SlotTuple<T>> + 'static,
+    {
+        let no_room = |inner: &BlockInner<T>| {
+            if reuse_gaps {
+                inner.is_full()
+            } else {
+                inner.unused_slots() == 0
+            }
+        };
         let mut iter = iter.into_iter();
         let (low, high) = iter.size_hint();
 
@@ -207,7 +501,7 @@ impl<T> Block<T> {
 
         let inner = self.inner.upgradable();
 
-        if inner.is_full() {
+        if no_room(&inner) {
             return Err(InsertError::BlockFull {
                 item: None,
                 iter: Some(Box::new(iter)),
@@ -222,14 +516,16 @@ impl<T> Block<T> {
 
         loop {
             match iter.next() {
-                Some((record, data)) => match self.insert_one_with(&mut inner, record, data) {
-                    Ok(handle) => {
-                        handles.push((index, handle));
-                    }
-                    Err(err) => {
-                        errors.push((index, err));
+                Some((record, data)) => {
+                    match self._insert_one_with(&mut inner, record, data, reuse_gaps) {
+                        Ok(handle) => {
+                            handles.push((index, handle));
+                        }
+                        Err(err) => {
+                            errors.push((index, err));
+                        }
                     }
-                },
+                }
                 None => {
                     exhausted = true;
                     break;
@@ -238,7 +534,7 @@ impl<T> Block<T> {
 
             index += 1;
 
-            if inner.is_full() {
+            if no_room(&inner) {
                 exhausted = false;
                 break;
             }
@@ -264,34 +560,82 @@ impl<T> Block<T> {
     }
 }
 
+pub struct LiveSlots<'a, T: 'static> {
+    block: &'a Block<T>,
+    inner: RwLockReadGuard<'a, BlockInner<T>>,
+    next: usize,
+}
+
+impl<T> Iterator for LiveSlots<'_, T> {
+    type Item = SlotHandle<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.next < self.inner.meta.length {
+            let index = self.next;
+            self.next += 1;
+
+            if self.block.fairness.is_fair()
+                && index > 0
+                && index.is_multiple_of(SCAN_BUMP_INTERVAL)
+            {
+                RwLockReadGuard::bump(&mut self.inner);
+            }
+
+            let is_gap = unsafe { self.inner.slots_by_index[index].read().as_ref().is_gap() };
+
+            if !is_gap {
+                return Some(SlotHandle {
+                    block: self.block.clone(),
+                    idx: ThinIdx::new(index).into_maybe_thin(),
+                });
+            }
+        }
+
+        None
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.inner.meta.length - self.next))
+    }
+}
+
 impl<T: std::fmt::Debug> std::fmt::Debug for Block<T> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        enum DebugSlot<'a, T> {
+            Live(&'a T),
+            Gap,
+        }
+
+        impl<T: std::fmt::Debug> std::fmt::Debug for DebugSlot<'_, T> {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                match self {
+                    DebugSlot::Live(data) => data.fmt(f),
+                    DebugSlot::Gap => write!(f, "<gap>"),
+                }
+            }
+        }
+
         let inner = self.inner.read_recursive();
 
         let mut d = f.debug_struct("Block");
 
-        d.field("meta", &inner.meta);
-
-        let mut slots = Vec::with_capacity(inner.meta.len());
-        let mut needed = inner.meta.len();
+        if let Some(label) = self.label {
+            d.field("label", &label);
+        }
 
-        if needed != 0 {
-            inner
-                .slots_by_index
-                .iter()
-                .take_while(|slot| {
-                    let slot = slot.read();
-                    let slot = unsafe { slot.as_ref() };
+        d.field("meta", &inner.meta);
 
-                    if !slot.is_gap() {
-                        slots.push(unsafe { slot.data_unchecked() });
-                        needed -= 1;
-                    }
+        let slots = inner.slots_by_index[..inner.meta.length]
+            .iter()
+            .map(|slot| {
+                let slot = unsafe { slot.read().as_ref() };
 
-                    needed > 0
-                })
-                .count();
-        }
+                match slot.data() {
+                    Some(data) => DebugSlot::Live(data),
+                    None => DebugSlot::Gap,
+                }
+            })
+            .collect::<Vec<_>>();
 
         d.field("slots", &slots);
 
@@ -308,6 +652,7 @@ mod tests {
     };
 
     use super::*;
+    use crate::slot::StaleHandleError;
 
     #[test]
     fn test_block_config() -> Result<()> {
@@ -370,26 +715,138 @@ mod tests {
             .insert_one(None, Item { a: 5, b: 6 })
             .map_err(unwrap_insert_err)?;
 
-        let (r2, i2) = h2
-            .remove_self()
-            .ok_or_else(|| anyhow::anyhow!("first h2 remove failed"))?;
+        let removed = h2.remove_self()?;
 
         let h4 = block
             .insert_one(None, Item { a: 7, b: 8 })
             .map_err(unwrap_insert_err)?;
 
-        let h2 = block.insert_one(r2, i2).map_err(unwrap_insert_err)?;
+        let h2 = block.reinsert(removed).map_err(unwrap_insert_err)?;
 
-        let _ = h4
-            .remove_self()
-            .ok_or_else(|| anyhow::anyhow!("h4 remove failed"))?;
-
-        let _ = h2
-            .remove_self()
-            .ok_or_else(|| anyhow::anyhow!("second h2 remove failed"))?;
+        let _ = h4.remove_self()?;
+        let _ = h2.remove_self()?;
 
         println!("{:#?}", block);
 
         Ok(())
     }
+
+    #[test]
+    fn test_stale_handle_remove() -> Result<()> {
+        let block = Block::new_anon(0usize, TableId::new(), None)?;
+        let insert = |n: usize| {
+            block
+                .insert_one(None, n)
+                .map_err(|err| anyhow::anyhow!("insert error: {:?}", err))
+        };
+
+        let handle = insert(1)?;
+        let _other = insert(2)?;
+        let clone = handle.clone();
+
+        assert_eq!(handle.remove_self()?.into_data(), 1);
+        assert_eq!(block.gap_count(), 1);
+
+        let err = clone.remove_self().unwrap_err();
+        assert!(matches!(err, StaleHandleError::Removed { .. }), "{}", err);
+        assert_eq!(block.gap_count(), 1);
+        assert_eq!(block.len(), 1);
+
+        // the gap chain is intact, so the slot is handed out once more and no further
+        insert(3)?;
+        insert(4)?;
+        assert_eq!(block.gap_count(), 0);
+        assert_eq!(block.len(), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_iter_live_with_gaps() -> Result<()> {
+        let block = Block::new_anon(0usize, TableId::new(), None)?;
+        let mut handles = Vec::new();
+
+        for n in 0..6usize {
+            handles.push(
+                block
+                    .insert_one(None, n)
+                    .map_err(|err| anyhow::anyhow!("insert error: {:?}", err))?,
+            );
+        }
+
+        for n in [1, 3] {
+            handles[n].clone().remove_self().expect("slot is live");
+        }
+
+        let live = block
+            .iter_live()
+            .map(|handle| handle.read_with(|data| Ok(*data.data().expect("slot is live"))))
+            .collect::<Result<Vec<_>>>()?;
+
+        assert_eq!(live, vec![0, 2, 4, 5]);
+
+        let mut raw = 0;
+        block.foreach_slot_raw(|_| raw += 1)?;
+        assert_eq!(raw, 6);
+
+        let debug = format!("{:?}", block);
+        assert!(
+            debug.contains("slots: [0, <gap>, 2, <gap>, 4, 5]"),
+            "{}",
+            debug
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_gap_list_stress() -> Result<()> {
+        use rand::{rngs::StdRng, Rng, SeedableRng};
+        use std::collections::HashSet;
+
+        for seed in 0..8 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let block = Block::new_anon(0usize, TableId::new(), Some(BlockConfig::new(16)?))?;
+            let mut live = Vec::<(SlotHandle<usize>, usize)>::new();
+
+            for n in 0..2_000usize {
+                if !block.is_full() && (live.is_empty() || rng.gen_bool(0.5)) {
+                    let handle = block
+                        .insert_one(None, n)
+                        .map_err(|err| anyhow::anyhow!("insert error: {:?}", err))?;
+                    live.push((handle, n));
+                } else {
+                    let (handle, value) = live.swap_remove(rng.gen_range(0..live.len()));
+                    assert_eq!(handle.remove_self()?.into_data(), value);
+                }
+
+                // no two live handles share a slot, and each reads back what was put in it
+                let slots = live
+                    .iter()
+                    .map(|(handle, _)| handle.idx.into_usize())
+                    .collect::<HashSet<_>>();
+                assert_eq!(slots.len(), live.len(), "seed {} op {}", seed, n);
+                assert_eq!(block.len(), live.len());
+
+                for (handle, value) in live.iter() {
+                    assert_eq!(
+                        handle.read_with(|data| Ok(data.data().copied()))?,
+                        Some(*value)
+                    );
+                }
+            }
+
+            // every gap is handed out again before the block counts as full
+            while !block.is_full() {
+                block
+                    .insert_one(None, 0)
+                    .map_err(|err| anyhow::anyhow!("insert error: {:?}", err))?;
+            }
+
+            assert_eq!(block.gap_count(), 0);
+            assert_eq!(block.iter_live().count(), 16);
+        }
+
+        Ok(())
+    }
 }
@@ -0,0 +1,123 @@
+use std::vec;
+
+use anyhow::Result;
+
+use crate::block::Block;
+
+/// How scans go about reading the blocks of a store.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ScanOptions {
+    /// Tells the OS that each block of a persisted store is read front to back as the scan gets to
+    /// it, and to start reading in the block after it. Costs page cache, so it can be turned off
+    /// where memory is tight.
+    pub prefetch: bool,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        Self { prefetch: true }
+    }
+}
+
+impl ScanOptions {
+    pub fn with_prefetch(self, prefetch: bool) -> Self {
+        Self { prefetch }
+    }
+}
+
+/// What `SequentialBlocks` tells about the blocks it goes through. Only a hint, so whatever fails
+/// is logged and the scan carries on.
+pub trait BlockAdvisor<T> {
+    fn advise_sequential(&self, block: &Block<T>) -> Result<()>;
+
+    fn prefetch(&self, block: &Block<T>) -> Result<()>;
+}
+
+impl<T, A: BlockAdvisor<T>> BlockAdvisor<T> for &A {
+    fn advise_sequential(&self, block: &Block<T>) -> Result<()> {
+        A::advise_sequential(*self, block)
+    }
+
+    fn prefetch(&self, block: &Block<T>) -> Result<()> {
+        A::prefetch(*self, block)
+    }
+}
+
+/// Advises the OS through `madvise`, see `Block::advise_sequential` and `Block::prefetch`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Madvise;
+
+impl<T> BlockAdvisor<T> for Madvise {
+    fn advise_sequential(&self, block: &Block<T>) -> Result<()> {
+        block.advise_sequential()
+    }
+
+    fn prefetch(&self, block: &Block<T>) -> Result<()> {
+        block.prefetch()
+    }
+}
+
+/// The blocks of a scan, in order. As each is handed out it's advised to be read sequentially,
+/// and the one after it is prefetched, unless the scan's options say not to.
+pub struct SequentialBlocks<T: 'static, A = Madvise> {
+    blocks: vec::IntoIter<Block<T>>,
+    options: ScanOptions,
+    advisor: A,
+}
+
+impl<T> SequentialBlocks<T> {
+    pub fn new(blocks: impl IntoIterator<Item = Block<T>>, options: ScanOptions) -> Self {
+        Self {
+            blocks: blocks.into_iter().collect::<Vec<_>>().into_iter(),
+            options,
+            advisor: Madvise,
+        }
+    }
+}
+
+impl<T, A: BlockAdvisor<T>> SequentialBlocks<T, A> {
+    pub fn with_advisor<B: BlockAdvisor<T>>(self, advisor: B) -> SequentialBlocks<T, B> {
+        SequentialBlocks {
+            blocks: self.blocks,
+            options: self.options,
+            advisor,
+        }
+    }
+
+    fn _advise(&self, block: &Block<T>, advice: &str, res: Result<()>) {
+        if let Err(err) = res {
+            eprintln!(
+                "WARNING: failed to advise {} for block {}: {:?}",
+                advice,
+                block.index(),
+                err
+            );
+        }
+    }
+}
+
+impl<T, A: BlockAdvisor<T>> Iterator for SequentialBlocks<T, A> {
+    type Item = Block<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let block = self.blocks.next()?;
+
+        if self.options.prefetch {
+            self._advise(
+                &block,
+                "sequential reads",
+                self.advisor.advise_sequential(&block),
+            );
+
+            if let Some(ahead) = self.blocks.as_slice().first() {
+                self._advise(ahead, "a prefetch", self.advisor.prefetch(ahead));
+            }
+        }
+
+        Some(block)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.blocks.size_hint()
+    }
+}
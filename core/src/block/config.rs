@@ -7,6 +7,9 @@ use primitives::{
     impl_access_bytes_for_into_bytes_type,
 };
 
+/// Blocks are mapped in full, so their slot count is capped.
+pub const MAX_BLOCK_CAPACITY: usize = 1 << 16;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct BlockConfig {
     block_capacity: NonZeroUsize,
@@ -39,10 +42,9 @@ impl FromBytes for BlockConfig {
 impl BlockConfig {
     #[must_use]
     pub fn new(block_capacity: usize) -> Result<Self> {
-        let block_capacity = NonZeroUsize::new(block_capacity)
-            .ok_or_else(|| anyhow::anyhow!("Block capacity must be greater than zero"))?;
-
-        Ok(Self { block_capacity })
+        Ok(Self {
+            block_capacity: validate_block_capacity(block_capacity)?,
+        })
     }
 
     pub fn block_capacity(&self) -> usize {
@@ -51,9 +53,21 @@ impl BlockConfig {
 
     #[must_use]
     pub fn set_block_capacity(&mut self, block_capacity: usize) -> Result<()> {
-        self.block_capacity = NonZeroUsize::new(block_capacity)
-            .ok_or_else(|| anyhow::anyhow!("Block capacity must be greater than zero"))?;
+        self.block_capacity = validate_block_capacity(block_capacity)?;
 
         Ok(())
     }
 }
+
+pub fn validate_block_capacity(block_capacity: usize) -> Result<NonZeroUsize> {
+    if block_capacity > MAX_BLOCK_CAPACITY {
+        anyhow::bail!(
+            "Block capacity {} exceeds MAX_BLOCK_CAPACITY ({})",
+            block_capacity,
+            MAX_BLOCK_CAPACITY
+        );
+    }
+
+    NonZeroUsize::new(block_capacity)
+        .ok_or_else(|| anyhow::anyhow!("Block capacity must be greater than zero"))
+}
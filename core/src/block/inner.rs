@@ -1,4 +1,15 @@
-use std::{alloc::Layout, fs::File, iter, os::unix::fs::FileExt, ptr::NonNull, sync::Arc};
+use std::{
+    alloc::Layout,
+    fs::File,
+    iter,
+    num::NonZeroU32,
+    os::unix::fs::FileExt,
+    ptr::NonNull,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
 
 use anyhow::Result;
 use indexmap::IndexMap;
@@ -6,27 +17,72 @@ use memmap2::{MmapMut, MmapOptions};
 use parking_lot::RwLock;
 use primitives::{
     byte_encoding::{FromBytes, IntoBytes},
-    ThinIdx,
+    into_bytes, ThinIdx,
 };
 
 use crate::{
-    block::{BlockConfig, BlockMeta},
+    block::{
+        meta::{checksum_of_crc, slots_checksum},
+        BlockConfig, BlockMeta,
+    },
     object_ids::{TableId, ThinRecordId},
     slot::SlotData,
+    store::{
+        io::{IoOp, IoReporter, SystemFileOps},
+        CorruptBlock,
+    },
 };
 
+/// What a block's mapping can be advised of, see `BlockInner::advise`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockAdvice {
+    /// The slots are read front to back, so pages can be read ahead and dropped once passed.
+    Sequential,
+    /// The slots are about to be read, so their pages can be read in now.
+    WillNeed,
+}
+
+/// What `BlockInner::scrub` found reading a block back from its file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScrubOutcome {
+    /// The slots match the checksum in the meta.
+    Verified,
+    /// The meta has no checksum, e.g. it was flushed by a store without them.
+    Unchecked,
+    /// A slot was written since the last flush, so the checksum in the file is behind.
+    Unsynced,
+    Corrupt(CorruptBlock),
+}
+
 pub struct BlockInner<T: 'static> {
     pub(crate) meta: BlockMeta,
+    file: Option<Arc<File>>,
+    offset: usize,
     data: Arc<MmapMut>,
     pub(crate) slots_by_index: Vec<RwLock<NonNull<SlotData<T>>>>,
     pub(crate) index_by_record: IndexMap<ThinRecordId, ThinIdx>,
+    /// Where failed flushes are reported, for a block that belongs to a store.
+    pub(crate) io: Option<Arc<IoReporter>>,
+    /// Whether the last flush failed, leaving changes to the slots that may not be in the file.
+    flush_failed: AtomicBool,
+    /// Whether a checksum of the slots is flushed with the meta, see `StoreConfig::checksum`.
+    pub(crate) checksum: bool,
+    /// Whether a slot was written since the last flush.
+    dirty: AtomicBool,
+    /// The checksum the block failed when it was loaded, for a store that reads corrupt blocks.
+    pub(crate) corrupt: Option<CorruptBlock>,
 }
 
+// The slot pointers only point into the block's own mapping, and every access to them goes through
+// their lock.
+unsafe impl<T: Send> Send for BlockInner<T> {}
+unsafe impl<T: Send + Sync> Sync for BlockInner<T> {}
+
 impl<T> Drop for BlockInner<T> {
     fn drop(&mut self) {
-        match self.sync_all() {
-            Ok(_) => {}
-            Err(err) => {
+        // a store's sink has already been sent the failure by `sync_all`
+        if let Err(err) = self.sync_all() {
+            if !self.io.as_ref().is_some_and(|io| io.has_sink()) {
                 eprintln!("WARNING: failed to flush block data: {:?}", err);
             }
         }
@@ -50,9 +106,11 @@ impl<T> BlockInner<T> {
         table: TableId,
         file: Arc<File>,
         offset: usize,
+        config: Option<BlockConfig>,
     ) -> Result<Self> {
         Self::_check_layout();
 
+        let index = index.into();
         let fs_meta = file.metadata()?;
 
         let end = offset + BlockMeta::BYTE_COUNT;
@@ -65,16 +123,31 @@ impl<T> BlockInner<T> {
             let mut meta_bytes = [0u8; BlockMeta::BYTE_COUNT];
             file.read_exact_at(&mut meta_bytes, offset as u64)?;
 
-            let mut this = BlockMeta::new(index, table, None);
-            this.init_from_bytes(&meta_bytes)?;
+            let mut this = BlockMeta::new(index, table, config);
+
+            // a zeroed meta means the block has never been written
+            if meta_bytes.iter().all(|b| *b == 0) {
+                file.write_all_at(&into_bytes!(this, BlockMeta)?, offset as u64)?;
+            } else {
+                this.init_from_bytes(&meta_bytes)?;
+
+                if this.index != index {
+                    anyhow::bail!("block meta index does not match");
+                }
+            }
+
             this
         };
         let block_capacity = meta.block_capacity();
         let content_len = meta.block_capacity() * Self::SLOT_BYTE_COUNT;
 
+        if (fs_meta.len() as usize) < end + content_len {
+            anyhow::bail!("file is too small");
+        }
+
         let data = Arc::new(unsafe {
             MmapOptions::new()
-                .offset(BlockMeta::BYTE_COUNT as u64)
+                .offset(end as u64)
                 .len(content_len)
                 .map_mut(&*file)?
         });
@@ -92,13 +165,29 @@ impl<T> BlockInner<T> {
             .take(block_capacity)
             .collect::<Vec<_>>();
 
-        let index_by_record = IndexMap::with_capacity(block_capacity);
+        let mut index_by_record = IndexMap::with_capacity(block_capacity);
+
+        for (i, slot) in slots_by_index.iter().enumerate().take(meta.length) {
+            let slot = slot.read();
+            let slot = unsafe { slot.as_ref() };
+
+            if let Some(record) = slot.thin_record_id() {
+                index_by_record.insert(record, ThinIdx::new(i));
+            }
+        }
 
         Ok(Self {
+            file: Some(file),
+            offset,
             data,
             meta,
             slots_by_index,
             index_by_record,
+            io: None,
+            flush_failed: AtomicBool::new(false),
+            checksum: false,
+            dirty: AtomicBool::new(false),
+            corrupt: None,
         })
     }
 
@@ -131,10 +220,17 @@ impl<T> BlockInner<T> {
         let index_by_record = IndexMap::with_capacity(block_capacity);
 
         Ok(Self {
+            file: None,
+            offset: 0,
             data,
             meta,
             slots_by_index,
             index_by_record,
+            io: None,
+            flush_failed: AtomicBool::new(false),
+            checksum: false,
+            dirty: AtomicBool::new(false),
+            corrupt: None,
         })
     }
 
@@ -166,13 +262,187 @@ impl<T> BlockInner<T> {
         self.len() == self.capacity()
     }
 
+    /// The slots at the end of the block that were never used, gaps left out.
+    pub fn unused_slots(&self) -> usize {
+        self.capacity() - self.meta.length
+    }
+
     pub fn next_available_index(&self) -> ThinIdx {
         self.meta.next_available_index()
     }
 
+    /// The raw slots of the block, exactly as they are laid out in a persisted store.
+    pub(crate) fn slot_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Where slot `index` starts in the backing file, or `None` for a memory-only block.
+    pub(crate) fn slot_offset(&self, index: usize) -> Option<u64> {
+        self.file
+            .as_ref()
+            .map(|_| (self.offset + BlockMeta::BYTE_COUNT + index * Self::SLOT_BYTE_COUNT) as u64)
+    }
+
+    /// Passes `advice` on to the OS for the slots of a persisted block. Memory-only blocks, and
+    /// platforms without `madvise`, have nothing to advise.
+    pub fn advise(&self, advice: BlockAdvice) -> Result<()> {
+        #[cfg(unix)]
+        if self.file.is_some() {
+            self.data.advise(match advice {
+                BlockAdvice::Sequential => memmap2::Advice::Sequential,
+                BlockAdvice::WillNeed => memmap2::Advice::WillNeed,
+            })?;
+        }
+
+        #[cfg(not(unix))]
+        let _ = advice;
+
+        Ok(())
+    }
+
+    /// Flushes the slots and writes the meta, with a checksum of the slots for a block that keeps
+    /// them. A failure is reported to the store the block
+    /// belongs to, and the block is marked as failed until a flush goes through.
     #[must_use]
     pub fn sync_all(&self) -> Result<()> {
-        self.data.flush()?;
+        // cleared before the slots are hashed, so a write the checksum misses marks it again
+        self.dirty.store(false, Ordering::Release);
+
+        let res = self._sync_all();
+
+        self.flush_failed.store(res.is_err(), Ordering::Release);
+
+        if res.is_err() {
+            self.mark_dirty();
+        }
+
+        res.map_err(|(op, err)| {
+            if let Some(io) = self.io.as_ref() {
+                io.report(Some(self.meta.index.into_usize()), op, &err);
+            }
+
+            err
+        })
+    }
+
+    fn _sync_all(&self) -> Result<(), (IoOp, anyhow::Error)> {
+        let ops = self.io.as_ref().map(|io| io.ops());
+        let ops = ops.as_deref().unwrap_or(&SystemFileOps);
+
+        ops.flush(&self.data)
+            .map_err(|err| (IoOp::Flush, err.into()))?;
+
+        if let Some(file) = self.file.as_ref() {
+            let meta = into_bytes!(self.meta_to_write(), BlockMeta)
+                .map_err(|err| (IoOp::WriteMeta, err))?;
+
+            ops.write_all_at(file, &meta, self.offset as u64)
+                .map_err(|err| (IoOp::WriteMeta, err.into()))?;
+        }
+
         Ok(())
     }
+
+    pub fn flush_failed(&self) -> bool {
+        self.flush_failed.load(Ordering::Acquire)
+    }
+
+    /// Notes that a slot was written. Called once the write is done, so a flush hashing the slots
+    /// before it always leaves the block marked.
+    pub(crate) fn mark_dirty(&self) {
+        self.dirty.store(true, Ordering::Release);
+    }
+
+    /// Whether a slot was written since the last flush, so the checksum in the file may not match
+    /// the slots.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty.load(Ordering::Acquire)
+    }
+
+    /// The checksum of the slots as they are now, each hashed under its lock.
+    fn _checksum(&self) -> NonZeroU32 {
+        let mut hasher = crc32fast::Hasher::new();
+
+        for (index, slot) in self.slots_by_index.iter().enumerate() {
+            let _guard = slot.read();
+            let start = index * Self::SLOT_BYTE_COUNT;
+
+            hasher.update(&self.data[start..start + Self::SLOT_BYTE_COUNT]);
+        }
+
+        checksum_of_crc(hasher.finalize())
+    }
+
+    /// The meta as it's written to the file: with the checksum of the slots as they are now, or
+    /// without one when the block doesn't keep them. A corrupt block keeps the checksum it failed,
+    /// so it's caught again the next time it's loaded.
+    pub(crate) fn meta_to_write(&self) -> BlockMeta {
+        let mut meta = self.meta;
+
+        if self.corrupt.is_none() {
+            meta.checksum = self.checksum.then(|| self._checksum());
+        }
+
+        meta
+    }
+
+    /// Checks the slots of a block just loaded against the checksum flushed with them, if it has
+    /// one, and flags the block as corrupt if they don't match.
+    pub(crate) fn verify_checksum(&mut self) -> Result<(), CorruptBlock> {
+        let Some(expected) = self.meta.checksum else {
+            return Ok(());
+        };
+
+        let found = self._checksum();
+
+        if found == expected {
+            return Ok(());
+        }
+
+        let corrupt = CorruptBlock {
+            idx: self.meta.index.into_usize(),
+            expected: expected.get(),
+            found: found.get(),
+        };
+
+        self.corrupt = Some(corrupt);
+
+        Err(corrupt)
+    }
+
+    /// Reads the block back from its file into a buffer of its own, leaving the mapping alone, and
+    /// checks the slots there against the checksum of the meta there. `None` for a memory-only
+    /// block. The caller holds the block write locked, so no slot is written halfway through.
+    pub(crate) fn scrub(&self) -> Result<Option<ScrubOutcome>> {
+        let Some(file) = self.file.as_ref() else {
+            return Ok(None);
+        };
+
+        if self.is_dirty() {
+            return Ok(Some(ScrubOutcome::Unsynced));
+        }
+
+        let mut bytes = vec![0u8; BlockMeta::BYTE_COUNT + self.capacity_as_bytes()];
+        file.read_exact_at(&mut bytes, self.offset as u64)?;
+
+        let (meta_bytes, slots) = bytes.split_at(BlockMeta::BYTE_COUNT);
+        let mut meta = self.meta;
+        meta.init_from_bytes(meta_bytes)?;
+
+        let Some(expected) = meta.checksum else {
+            return Ok(Some(ScrubOutcome::Unchecked));
+        };
+
+        let found = slots_checksum(slots);
+
+        Ok(Some(if found == expected {
+            ScrubOutcome::Verified
+        } else {
+            ScrubOutcome::Corrupt(CorruptBlock {
+                idx: self.meta.index.into_usize(),
+                expected: expected.get(),
+                found: found.get(),
+            })
+        }))
+    }
 }
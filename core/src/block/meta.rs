@@ -1,3 +1,5 @@
+use std::num::NonZeroU32;
+
 use anyhow::Result;
 use primitives::{
     byte_encoding::{ByteDecoder, ByteEncoder, FromBytes, IntoBytes},
@@ -12,9 +14,15 @@ pub struct BlockMeta {
     pub length: usize,
     pub gap_tail: Option<ThinIdx>,
     pub gap_count: usize,
+    /// No longer read: the block inserts continue with is the next one of the store's
+    /// `BlockChain`. Kept so block metas keep their layout.
     pub next_block: Option<ThinIdx>,
     pub table: TableId,
     pub config: BlockConfig,
+    /// The checksum of the slots as of the last flush, see `StoreConfig::checksum`. It takes up
+    /// what used to be padding, so block metas keep their size, and a meta written without one
+    /// reads back as `None`.
+    pub checksum: Option<NonZeroU32>,
 }
 
 impl std::fmt::Debug for BlockMeta {
@@ -37,7 +45,15 @@ impl std::fmt::Debug for BlockMeta {
             d.field("next_block", &Option::<ThinIdx>::None);
         }
 
-        d.field("config", &self.config).finish()
+        d.field("config", &self.config);
+
+        if let Some(checksum) = self.checksum {
+            d.field("checksum", &format_args!("{:08x}", checksum));
+        } else {
+            d.field("checksum", &Option::<u32>::None);
+        }
+
+        d.finish()
     }
 }
 
@@ -47,11 +63,12 @@ impl IntoBytes for BlockMeta {
     fn encode_bytes(&self, x: &mut ByteEncoder<'_>) -> Result<()> {
         x.encode(self.index)?;
         x.encode(self.length)?;
-        x.encode(self.gap_tail)?;
+        x.encode_bytes(&encode_optional_idx(self.gap_tail))?;
         x.encode(self.gap_count)?;
-        x.encode(self.next_block)?;
+        x.encode_bytes(&encode_optional_idx(self.next_block))?;
         x.encode(self.table)?;
-        x.encode_bytes(&into_bytes!(&self.config, BlockConfig)?)?;
+        x.encode_bytes(&into_bytes!(self.config, BlockConfig)?)?;
+        x.encode(self.checksum.map_or(0, NonZeroU32::get))?;
         Ok(())
     }
 }
@@ -60,15 +77,41 @@ impl FromBytes for BlockMeta {
     fn decode_bytes(this: &mut Self, x: &mut ByteDecoder<'_>) -> Result<()> {
         x.decode(&mut this.index)?;
         x.decode(&mut this.length)?;
-        x.decode(&mut this.gap_tail)?;
+        this.gap_tail = decode_optional_idx(x)?;
         x.decode(&mut this.gap_count)?;
-        x.decode(&mut this.next_block)?;
+        this.next_block = decode_optional_idx(x)?;
         x.decode(&mut this.table)?;
         x.delegate(&mut this.config)?;
+
+        let mut checksum = 0u32;
+        x.decode(&mut checksum)?;
+        this.checksum = NonZeroU32::new(checksum);
+
         Ok(())
     }
 }
 
+/// The checksum of the slots of a block, as kept in `BlockMeta::checksum`: a CRC-32 of the slots
+/// as they're laid out in the file, with `u32::MAX` standing in for zero.
+pub fn slots_checksum(slots: &[u8]) -> NonZeroU32 {
+    checksum_of_crc(crc32fast::hash(slots))
+}
+
+pub(crate) fn checksum_of_crc(crc: u32) -> NonZeroU32 {
+    NonZeroU32::new(crc).unwrap_or(NonZeroU32::MAX)
+}
+
+/// `Option<ThinIdx>` is encoded with a fixed width so that `None` still occupies its bytes.
+fn encode_optional_idx(idx: Option<ThinIdx>) -> [u8; 8] {
+    idx.map_or([0u8; 8], |idx| idx.into_array())
+}
+
+fn decode_optional_idx(x: &mut ByteDecoder<'_>) -> Result<Option<ThinIdx>> {
+    let mut bytes = [0u8; 8];
+    x.read_exact(&mut bytes)?;
+    Ok(ThinIdx::from_array(bytes))
+}
+
 impl BlockMeta {
     pub fn new(index: impl Into<ThinIdx>, table: TableId, config: Option<BlockConfig>) -> Self {
         Self {
@@ -79,6 +122,7 @@ impl BlockMeta {
             next_block: ThinIdx::NIL,
             table,
             config: config.unwrap_or_default(),
+            checksum: None,
         }
     }
 
@@ -105,14 +149,4 @@ impl BlockMeta {
             self.index + 1
         }
     }
-
-    #[must_use]
-    pub(crate) fn take_next_block_index(&mut self) -> Option<ThinIdx> {
-        if let Some(index) = self.next_block {
-            self.next_block = ThinIdx::NIL;
-            Some(index)
-        } else {
-            None
-        }
-    }
 }
@@ -4,7 +4,7 @@ use anyhow::Result;
 use primitives::{
     byte_encoding::{AccessBytes, ByteDecoder, ByteEncoder, FromBytes, IntoBytes, ScalarFromBytes},
     idx::MaybeThinIdx,
-    ThinIdx,
+    ThinIdx, O64,
 };
 
 use crate::{slot::SlotHandle, values::DataValue};
@@ -127,14 +127,17 @@ impl CellIdx {
     }
 }
 
+/// The column cells of a record along with the sequence number the record was inserted with and
+/// its generation, which changes every time the record is updated.
 #[derive(Clone, Copy)]
-#[repr(C, align(16))]
-pub struct ColumnIndices(NonZeroUsize, [Option<CellIdx>; MAX_COLUMNS]);
+#[repr(C)]
+pub struct ColumnIndices(NonZeroUsize, u64, O64, [Option<CellIdx>; MAX_COLUMNS]);
 
 impl IntoBytes for ColumnIndices {
     fn encode_bytes(&self, x: &mut ByteEncoder<'_>) -> Result<()> {
         x.encode(self.0.get() as u64)?;
-        x.skip(8)?;
+        x.encode(self.1)?;
+        x.encode_bytes(&self.2.into_array())?;
         x.encode_bytes(self.raw_buckets_as_bytes())?;
 
         Ok(())
@@ -149,7 +152,10 @@ impl FromBytes for ColumnIndices {
         this.0 = NonZeroUsize::new(u64::from_ne_bytes(count_bytes) as usize)
             .ok_or_else(|| anyhow::anyhow!("invalid count"))?;
 
-        x.skip(8)?;
+        x.decode(&mut this.1)?;
+        let mut gen_bytes = [0u8; 8];
+        x.read_exact(&mut gen_bytes)?;
+        this.2 = O64::try_from_array(gen_bytes)?;
 
         unsafe {
             let buckets = this.raw_buckets_as_bytes_mut();
@@ -165,7 +171,7 @@ impl std::fmt::Debug for ColumnIndices {
         let mut d = f.debug_list();
 
         for i in 0..self.0.get() {
-            if let Some(cell) = self.1[i] {
+            if let Some(cell) = self.3[i] {
                 d.entry(&cell);
             } else {
                 d.entry(&"None");
@@ -176,37 +182,62 @@ impl std::fmt::Debug for ColumnIndices {
     }
 }
 
+// The record slot layout is derived from `MAX_COLUMNS`; changing either without the other must not
+// compile, otherwise existing record slots would be read with the wrong layout.
+const _: () = {
+    assert!(MAX_COLUMNS > 0);
+    assert!(std::mem::size_of::<ColumnIndices>() == ColumnIndices::BYTES);
+    assert!(ColumnIndices::BYTES == <ColumnIndices as IntoBytes>::BYTE_COUNT);
+    assert!(
+        std::mem::size_of::<[Option<CellIdx>; MAX_COLUMNS]>()
+            == ColumnIndices::ITEM_BYTES * MAX_COLUMNS
+    );
+};
+
 impl ColumnIndices {
-    pub const ITEM_BYTES: usize = 16;
-    pub const BYTES: usize = Self::ITEM_BYTES + (Self::ITEM_BYTES * MAX_COLUMNS);
-    pub const INVALID: Self = Self(NonZeroUsize::MAX, [None; MAX_COLUMNS]);
+    pub const HEADER_BYTES: usize = 24;
+    pub const ITEM_BYTES: usize = std::mem::size_of::<Option<CellIdx>>();
+    pub const BYTES: usize = Self::HEADER_BYTES + (Self::ITEM_BYTES * MAX_COLUMNS);
+    pub const INVALID: Self = Self(NonZeroUsize::MAX, 0, O64::INVALID, [None; MAX_COLUMNS]);
 
     pub fn new(count: NonZeroUsize) -> Self {
-        Self(count, [None; MAX_COLUMNS])
+        Self(count, 0, O64::new_gen(), [None; MAX_COLUMNS])
+    }
+
+    pub fn with_seq(mut self, seq: u64) -> Self {
+        self.1 = seq;
+        self
     }
 
     pub(self) fn raw_buckets_as_bytes(&self) -> &[u8] {
         let count = self.0.get();
-        let ptr = self.1.as_ptr() as *const u8;
+        let ptr = self.3.as_ptr() as *const u8;
 
         unsafe { std::slice::from_raw_parts(ptr, count * Self::ITEM_BYTES) }
     }
 
     pub(self) unsafe fn raw_buckets_as_bytes_mut(&mut self) -> &mut [u8] {
         let count = self.0.get();
-        let ptr = self.1.as_mut_ptr() as *mut u8;
+        let ptr = self.3.as_mut_ptr() as *mut u8;
 
         std::slice::from_raw_parts_mut(ptr, count * Self::ITEM_BYTES)
     }
 
+    /// Points `column` at `value`. A record written before the column was added has fewer cells,
+    /// and grows to hold it.
     #[must_use]
     pub fn replace(&mut self, column: usize, value: CellIdx) -> Result<()> {
-        if column >= self.0.get() {
+        if column >= MAX_COLUMNS {
             anyhow::bail!("column index out of bounds");
         }
 
+        if column >= self.0.get() {
+            // the cells past the count are empty, see `new` and `take`
+            self.0 = NonZeroUsize::new(column + 1).expect("column + 1 is never zero");
+        }
+
         unsafe {
-            self.1.get_unchecked_mut(column).replace(value);
+            self.3.get_unchecked_mut(column).replace(value);
         }
 
         Ok(())
@@ -217,14 +248,42 @@ impl ColumnIndices {
             return None;
         }
 
-        self.1.get(column).copied().flatten()
+        self.3.get(column).copied().flatten()
     }
 
     pub fn count(&self) -> usize {
         self.0.get()
     }
 
+    pub fn seq(&self) -> u64 {
+        self.1
+    }
+
+    pub fn gen(&self) -> O64 {
+        self.2
+    }
+
+    /// Assigns a new generation, invalidating any version token handed out for the old one.
+    pub fn bump_gen(&mut self) -> O64 {
+        let prev = self.2;
+
+        while self.2 == prev {
+            self.2 = O64::new_gen();
+        }
+
+        self.2
+    }
+
+    /// Clears a column, returning the cell it pointed at.
+    pub fn take(&mut self, column: usize) -> Option<CellIdx> {
+        if column >= self.0.get() {
+            return None;
+        }
+
+        self.3.get_mut(column).and_then(Option::take)
+    }
+
     pub fn buckets(&self) -> &[Option<CellIdx>] {
-        &self.1[..self.0.get()]
+        &self.3[..self.0.get()]
     }
 }
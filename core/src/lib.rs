@@ -8,5 +8,7 @@ pub mod object_ids;
 pub mod records;
 pub mod slot;
 pub mod store;
+#[cfg(any(test, feature = "testing"))]
+pub mod test_util;
 pub mod values;
 pub mod varcap;
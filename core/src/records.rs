@@ -1,12 +1,27 @@
-use std::{iter, num::NonZeroUsize, ops::RangeBounds};
+use std::{
+    iter,
+    num::NonZeroUsize,
+    ops::RangeBounds,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::Sender,
+        Arc,
+    },
+};
 
 use anyhow::Result;
+use primitives::ThinIdx;
 
 use crate::{
+    block::{Block, ScanOptions, SequentialBlocks},
     indices::{ColumnIndices, MAX_COLUMNS},
     object_ids::{RecordId, TableId},
-    slot::SlotHandle,
-    store::{InsertError, InsertState, Store, StoreConfig, StoreError},
+    slot::{SlotDataRef, SlotHandle},
+    store::{
+        inner::StoreInner, AllocationStats, CountMismatch, FragReport, InsertError, InsertState,
+        IoIncident, Region, ScrubReport, Store, StoreConfig, StoreError, StoreMeta,
+    },
 };
 
 pub type RecordsError = StoreError<ColumnIndices>;
@@ -17,6 +32,9 @@ pub struct Records {
     store: Store<ColumnIndices>,
     table: TableId,
     columns: NonZeroUsize,
+    block_capacity: usize,
+    /// The last sequence number handed out. Sequence numbers start at 1 and are never reused.
+    seq: Arc<AtomicU64>,
 }
 
 impl Records {
@@ -25,22 +43,313 @@ impl Records {
         table: Option<TableId>,
         config: Option<StoreConfig>,
         columns: usize,
+    ) -> Result<Self> {
+        Self::_new(table, columns, |table| Store::new(Some(table), config))
+    }
+
+    /// Like `new`, but a relative persistance path is resolved against the database `root`.
+    pub fn new_in(
+        table: Option<TableId>,
+        config: Option<StoreConfig>,
+        columns: usize,
+        root: impl AsRef<Path>,
+    ) -> Result<Self> {
+        Self::_new(table, columns, |table| {
+            Store::new_in(Some(table), config, root)
+        })
+    }
+
+    /// Like `new`, but kept in `region` of a table file, see `Store::new_in_region`.
+    pub fn new_in_region(
+        table: Option<TableId>,
+        config: Option<StoreConfig>,
+        columns: usize,
+        region: Region,
+    ) -> Result<Self> {
+        Self::_new(table, columns, |table| {
+            Store::new_in_region(Some(table), config, region)
+        })
+    }
+
+    fn _new(
+        table: Option<TableId>,
+        columns: usize,
+        open: impl FnOnce(TableId) -> Result<Store<ColumnIndices>>,
     ) -> Result<Self> {
         if columns > MAX_COLUMNS {
-            anyhow::bail!("column count exceeds maximum");
+            anyhow::bail!(
+                "column count {} exceeds MAX_COLUMNS ({})",
+                columns,
+                MAX_COLUMNS
+            );
         } else if columns == 0 {
             anyhow::bail!("column count must be greater than zero");
         }
 
         let table = table.unwrap_or_default();
+        let store = open(table)?;
+
+        // the slots may have been flushed after the meta was last written, so take whichever is higher
+        let block_capacity = store.read().meta.config.block_capacity.get();
+
+        let seq = {
+            let inner = store.read();
+            let mut seq = inner.meta.seq;
+
+            Self::_for_each_live(&inner, |_, indices| seq = seq.max(indices.seq()));
+            seq
+        };
 
         Ok(Self {
-            store: Store::new(Some(table), config)?,
+            store,
             table,
             columns: unsafe { NonZeroUsize::new_unchecked(columns) },
+            block_capacity,
+            seq: Arc::new(AtomicU64::new(seq)),
+        })
+    }
+
+    /// The id of the record a handle points at. Ids are the record's position across every block of
+    /// the store, so they stay unique once the store grows past its first block.
+    pub fn record_id(&self, handle: &RecordHandle) -> RecordId {
+        let position = handle.block.index().into_usize() * self.block_capacity
+            + handle.idx.into_thin().into_usize();
+
+        RecordId::new(ThinIdx::new(position), self.table)
+    }
+
+    /// How many records a block of the store holds, which record ids are positions in blocks of.
+    pub fn block_capacity(&self) -> usize {
+        self.block_capacity
+    }
+
+    /// The number of live records, read from the store meta without scanning.
+    pub fn row_count(&self) -> usize {
+        self.store.read().meta.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.row_count() == 0
+    }
+
+    /// The number of removed records whose slots haven't been reused yet.
+    pub fn gap_count(&self) -> usize {
+        self.store.read().meta.gap_count
+    }
+
+    /// Whether a live record sits at the position the id points at.
+    pub fn contains(&self, record: RecordId) -> bool {
+        self.get(record).is_some()
+    }
+
+    /// The handle of the live record at the position the id points at.
+    pub fn get(&self, record: RecordId) -> Option<RecordHandle> {
+        if record.table() != self.table {
+            return None;
+        }
+
+        let position = Into::<ThinIdx>::into(record).into_usize();
+        let row = position % self.block_capacity;
+
+        let inner = self.store.read();
+        let block = inner
+            .blocks
+            .get(&ThinIdx::new(position / self.block_capacity))?;
+
+        let is_live = {
+            let block_inner = block.inner.read_recursive();
+
+            row < block_inner.meta.length
+                && SlotDataRef::new(&block_inner.slots_by_index[row])
+                    .data()
+                    .is_some()
+        };
+
+        is_live.then(|| {
+            SlotHandle {
+                block: block.clone(),
+                idx: ThinIdx::new(row).into_maybe_thin(),
+            }
+            .ensure_idx_has_gen()
         })
     }
 
+    /// Removes a record, returning the column cells it pointed at.
+    pub fn remove(&self, handle: RecordHandle) -> Result<Option<ColumnIndices>> {
+        let mut store = self.store.write();
+        let removed = self.store.remove_with(&mut store, handle);
+
+        self._sync_seq(&mut store)?;
+        Ok(removed.map(|(_, indices)| indices))
+    }
+
+    /// Removes every record at once, see `Store::truncate`. Sequence numbers carry on from where
+    /// they were, so records inserted afterwards still come after the ones removed. Returns the
+    /// number of records removed.
+    pub fn truncate(&self) -> Result<usize> {
+        let mut store = self.store.write();
+        let dropped = self.store.truncate_with(&mut store)?;
+
+        self._sync_seq(&mut store)?;
+        Ok(dropped)
+    }
+
+    /// The last sequence number assigned to a record, or `0` if nothing has been inserted.
+    pub fn current_seq(&self) -> u64 {
+        self.seq.load(Ordering::Acquire)
+    }
+
+    /// Returns every live record inserted with a sequence number greater than `seq`, ordered by
+    /// sequence number.
+    pub fn scan_since(&self, seq: u64) -> impl Iterator<Item = (u64, RecordHandle)> {
+        let inner = self.store.read();
+        let mut found = Vec::new();
+
+        Self::_for_each_live(&inner, |handle, indices| {
+            if indices.seq() > seq {
+                found.push((indices.seq(), handle.ensure_idx_has_gen()));
+            }
+        });
+
+        found.sort_unstable_by_key(|(seq, _)| *seq);
+        found.into_iter()
+    }
+
+    /// Looks up a live record by the sequence number it was inserted with.
+    pub fn get_by_seq(&self, seq: u64) -> Option<RecordHandle> {
+        let inner = self.store.read();
+        let mut found = None;
+
+        Self::_for_each_live(&inner, |handle, indices| {
+            if found.is_none() && indices.seq() == seq {
+                found = Some(handle);
+            }
+        });
+
+        found.map(SlotHandle::ensure_idx_has_gen)
+    }
+
+    /// The blocks of the store, in order, for a scan reading records a block at a time with
+    /// `live_in_block` instead of listing them all up front.
+    pub fn scan_blocks(&self, options: ScanOptions) -> SequentialBlocks<ColumnIndices> {
+        self.store.scan_blocks(options)
+    }
+
+    /// Adds the live records of `block` to `found` with their sequence numbers, in slot order.
+    pub fn live_in_block(block: &Block<ColumnIndices>, found: &mut Vec<(u64, RecordHandle)>) {
+        Self::_for_each_live_in(block, |handle, indices| {
+            found.push((indices.seq(), handle.ensure_idx_has_gen()));
+        });
+    }
+
+    fn _for_each_live<F>(inner: &StoreInner<ColumnIndices>, mut f: F)
+    where
+        F: FnMut(RecordHandle, &ColumnIndices),
+    {
+        for block in inner.blocks.values() {
+            Self::_for_each_live_in(block, &mut f);
+        }
+    }
+
+    fn _for_each_live_in<F>(block: &Block<ColumnIndices>, mut f: F)
+    where
+        F: FnMut(RecordHandle, &ColumnIndices),
+    {
+        for handle in block.iter_live() {
+            let indices = handle.read_with(|slot| Ok(slot.data().copied()));
+
+            if let Ok(Some(indices)) = indices {
+                f(handle, &indices);
+            }
+        }
+    }
+
+    fn _next_indices(&self) -> ColumnIndices {
+        let seq = self.seq.fetch_add(1, Ordering::AcqRel) + 1;
+        ColumnIndices::new(self.columns).with_seq(seq)
+    }
+
+    fn _sync_seq(&self, inner: &mut StoreInner<ColumnIndices>) -> Result<()> {
+        inner.meta.seq = self.current_seq();
+        inner.sync_meta()
+    }
+
+    /// Writes out the current sequence number and flushes the underlying store.
+    pub fn sync_all(&self) -> Result<()> {
+        self._sync_seq(&mut self.store.write())?;
+        self.store.sync_all()
+    }
+
+    pub fn release_lock(&self) -> Result<()> {
+        self.store.release_lock()
+    }
+
+    pub fn fragmentation_report(&self) -> FragReport {
+        self.store.fragmentation_report()
+    }
+
+    /// Where inserts have put records since the store was opened, for tuning the
+    /// `AllocationPolicy` of its config.
+    pub fn allocation_stats(&self) -> AllocationStats {
+        self.store.allocation_stats()
+    }
+
+    /// See `Store::set_io_error_sink`.
+    pub fn set_io_error_sink(&self, sink: Option<Sender<IoIncident>>) {
+        self.store.set_io_error_sink(sink)
+    }
+
+    /// See `Store::flush_failures`.
+    pub fn flush_failures(&self) -> u64 {
+        self.store.flush_failures()
+    }
+
+    /// Whether the last flush of any block of the store failed, see `Store::failed_blocks`.
+    pub fn has_failed_flush(&self) -> bool {
+        !self.store.failed_blocks().is_empty()
+    }
+
+    pub fn check_counts(&self) -> Result<Vec<CountMismatch>> {
+        self.store.check_counts()
+    }
+
+    /// Checks the blocks of the store against their checksums, see `Store::scrub`.
+    pub fn scrub(&self) -> Result<ScrubReport> {
+        self.store.scrub()
+    }
+
+    /// Calls `f` with every live record and the column cells it points at.
+    pub fn foreach_live(&self, mut f: impl FnMut(RecordHandle, &ColumnIndices)) {
+        Self::_for_each_live(&self.store.read(), |handle, indices| {
+            f(handle.ensure_idx_has_gen(), indices)
+        });
+    }
+
+    /// The file the record store is persisted in. See `Store::backing_path`.
+    pub fn backing_path(&self) -> Option<PathBuf> {
+        self.store.backing_path()
+    }
+
+    /// Writes the record store to `dest` as a persisted store file. See `Store::write_image`.
+    pub fn write_image(&self, dest: impl AsRef<Path>, options: ScanOptions) -> Result<()> {
+        self.store.write_image(dest, options)
+    }
+
+    /// See `Store::retag_image`.
+    pub fn retag_image(path: impl AsRef<Path>, table: TableId) -> Result<()> {
+        Store::<ColumnIndices>::retag_image(path, table)
+    }
+
+    /// See `Store::import_image`.
+    pub fn import_image(path: impl AsRef<Path>, region: &Region) -> Result<StoreMeta> {
+        Store::<ColumnIndices>::import_image(path, region)
+    }
+
+    /// See `Store::read_image_meta`.
+    pub fn read_image_meta(path: impl AsRef<Path>) -> Result<StoreMeta> {
+        Store::<ColumnIndices>::read_image_meta(path)
+    }
+
     #[must_use]
     pub fn load(&self, range: impl RangeBounds<usize>) -> Result<()> {
         self.store.load(range)
@@ -48,14 +357,14 @@ impl Records {
 
     #[must_use]
     pub fn insert_one(&self) -> Result<(RecordId, RecordHandle), RecordsError> {
-        let table = self.table;
-        let columns = self.columns;
+        let mut store = self.store.write();
+        let handle = self
+            .store
+            .insert_one_with(&mut store, None, self._next_indices())?;
 
-        let store = self.store.write();
-        let record = RecordId::new(store.next_available_index(), table);
-        let handle = self.store.insert_one(None, ColumnIndices::new(columns))?;
+        self._sync_seq(&mut store)?;
 
-        Ok((record, handle.ensure_idx_has_gen()))
+        Ok((self.record_id(&handle), handle.ensure_idx_has_gen()))
     }
 
     #[must_use]
@@ -64,23 +373,28 @@ impl Records {
             return Ok(Vec::new());
         }
 
-        let table = self.table;
-        let columns = self.columns;
+        let insert_state = {
+            let mut store = self.store.write();
+            let items = (0..count)
+                .map(|_| (None, self._next_indices()))
+                .collect::<Vec<_>>();
 
-        match self
-            .store
-            .insert(vec![(None, ColumnIndices::new(columns)); count])?
-        {
+            let res = self.store.insert_with(&mut store, items);
+            self._sync_seq(&mut store)?;
+            res?
+        };
+
+        match insert_state {
             InsertState::Done(handles) => Ok(handles
                 .into_iter()
-                .map(|h| (RecordId::new(h.idx, table), h.ensure_idx_has_gen()))
+                .map(|h| (self.record_id(&h), h.ensure_idx_has_gen()))
                 .collect::<Vec<_>>()),
             InsertState::Partial {
                 errors, handles, ..
             } => {
                 let mut tuples = handles
                     .into_iter()
-                    .map(|(_, h)| (RecordId::new(h.idx, table), h.ensure_idx_has_gen()))
+                    .map(|(_, h)| (self.record_id(&h), h.ensure_idx_has_gen()))
                     .collect::<Vec<_>>();
 
                 for (_, error) in errors {
@@ -127,39 +441,37 @@ impl Records {
         I: IntoIterator<Item = U>,
         U: IntoIterator<Item = T>,
     {
-        let table = self.table;
-        let columns = self.columns;
+        let mut store = self.store.write();
 
         let mut values = iter
             .into_iter()
             .enumerate()
             .map(|(index, values)| {
-                let columns = ColumnIndices::new(columns);
+                let columns = self._next_indices();
 
                 (index, columns, values.into_iter().collect::<Vec<_>>())
             })
             .collect::<Vec<_>>();
 
-        let record_insert_state = self.store.insert(
+        let record_insert_state = self.store.insert_with(
+            &mut store,
             values
                 .iter()
                 .map(|(_, columns, _)| (None, *columns))
                 .collect::<Vec<_>>(),
-        )?;
+        );
+
+        self._sync_seq(&mut store)?;
+        drop(store);
+
+        let record_insert_state = record_insert_state?;
 
         match record_insert_state {
             InsertState::Done(handles) => Ok(iter::zip(
                 values.into_iter().map(|(idx, _, values)| (idx, values)),
                 handles.into_iter(),
             )
-            .map(|((index, values), h)| {
-                (
-                    index,
-                    RecordId::new(h.idx, table),
-                    h.ensure_idx_has_gen(),
-                    values,
-                )
-            })
+            .map(|((index, values), h)| (index, self.record_id(&h), h.ensure_idx_has_gen(), values))
             .collect::<Vec<_>>()),
             InsertState::Partial { errors, handles } => {
                 fn new_invalid_entry<T>() -> (usize, ColumnIndices, Vec<T>) {
@@ -173,12 +485,7 @@ impl Records {
                             let entry = values.get_mut(i).unwrap();
                             let (index, _, values) = std::mem::replace(entry, new_invalid_entry());
 
-                            (
-                                index,
-                                RecordId::new(h.idx, table),
-                                h.ensure_idx_has_gen(),
-                                values,
-                            )
+                            (index, self.record_id(&h), h.ensure_idx_has_gen(), values)
                         })
                         .collect::<Vec<_>>()
                 };
@@ -1,13 +1,17 @@
+pub mod callback;
 pub mod data;
 pub mod handle;
 
 use crate::object_ids::RecordId;
 
 pub use {
+    callback::{catch_callback, CallbackPanicked},
     data::{SlotData, SlotDataRef},
-    handle::SlotHandle,
+    handle::{RemovedSlot, SlotHandle, StaleHandleError},
 };
 
+/// What the gap at the end of a block's gap list links to, where any other gap links to the one
+/// handed out after it.
 pub(super) const GAP_HEAD: usize = usize::MAX;
 
 pub type SlotTuple<T> = (Option<RecordId>, T);
@@ -0,0 +1,34 @@
+use std::panic::{self, AssertUnwindSafe};
+
+use anyhow::Result;
+
+/// A callback handed to a slot or block panicked. Whatever the callback changed before panicking
+/// is left as is, so callers treat this like any other error that needs rolling back.
+#[derive(thiserror::Error, Debug)]
+#[error("callback panicked: {message}")]
+pub struct CallbackPanicked {
+    pub message: String,
+}
+
+/// Runs a caller provided callback, turning a panic into a `CallbackPanicked` error. Locks taken
+/// before the callback are released while unwinding, and since they don't poison, the slot stays
+/// usable afterwards.
+pub fn catch_callback<F, R>(f: F) -> Result<R>
+where
+    F: FnOnce() -> Result<R>,
+{
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(res) => res,
+        Err(payload) => {
+            let message = if let Some(message) = payload.downcast_ref::<&str>() {
+                message.to_string()
+            } else if let Some(message) = payload.downcast_ref::<String>() {
+                message.clone()
+            } else {
+                "unknown panic".to_string()
+            };
+
+            Err(CallbackPanicked { message }.into())
+        }
+    }
+}
@@ -6,7 +6,7 @@ use primitives::{idx::Gen, ThinIdx};
 
 use crate::object_ids::ThinRecordId;
 
-use super::GAP_HEAD;
+use super::{callback::catch_callback, GAP_HEAD};
 
 #[repr(C)]
 pub struct SlotData<T> {
@@ -48,16 +48,9 @@ impl<T> Default for SlotData<T> {
 impl<T: std::fmt::Debug> std::fmt::Debug for SlotData<T> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         if self.is_gap {
-            let mut d = f.debug_struct("Gap");
-            let prev = unsafe { self.previous_gap_unchecked() };
-
-            if prev != GAP_HEAD {
-                d.field("next", &prev);
-            } else {
-                d.field("next", &Option::<usize>::None);
-            }
-
-            d.finish()
+            f.debug_struct("Gap")
+                .field("next", &self.previous_gap().map(ThinIdx::into_usize))
+                .finish()
         } else {
             unsafe { std::fmt::Debug::fmt(self.data_unchecked(), f) }
         }
@@ -77,12 +70,16 @@ impl<T> SlotData<T> {
         self.is_gap
     }
 
-    /// Returns the previous gap idx in the chain.
-    pub fn previous_gap(&self) -> Option<usize> {
-        if self.is_gap {
-            Some(unsafe { self.previous_gap_unchecked() })
-        } else {
-            None
+    /// The gap that was the tail of the block's gap list before this one became it, which is the
+    /// next one handed out after it. `None` at the end of the list, and for a live slot.
+    pub fn previous_gap(&self) -> Option<ThinIdx> {
+        if !self.is_gap {
+            return None;
+        }
+
+        match unsafe { self.previous_gap_unchecked() } {
+            GAP_HEAD => None,
+            prev => Some(ThinIdx::new(prev)),
         }
     }
 
@@ -122,7 +119,8 @@ impl<T> SlotData<T> {
         std::ptr::read(self.data.as_ptr())
     }
 
-    /// Blocks the previous gap idx in the chain.
+    /// Turns the slot into a gap, linked to `previous_gap`, the tail of the gap list it becomes
+    /// the new tail of. A slot that already is a gap is left as it is.
     pub fn create_gap(&mut self, previous_gap: Option<impl Into<ThinIdx>>) {
         if self.is_gap {
             return;
@@ -134,9 +132,7 @@ impl<T> SlotData<T> {
         unsafe {
             std::ptr::write_unaligned(
                 self.data.as_mut_ptr() as *mut _,
-                previous_gap
-                    .map_or(ThinIdx::INVALID, |x| x.into())
-                    .into_usize(),
+                previous_gap.map_or(GAP_HEAD, |x| Into::<ThinIdx>::into(x).into_usize()),
             );
         }
     }
@@ -177,7 +173,7 @@ impl<T> SlotData<T> {
             }
         }
 
-        f(unsafe { self.data_unchecked_mut() })
+        catch_callback(|| f(unsafe { self.data_unchecked_mut() }))
     }
 
     /// Returns the record and data if the slot is not a gap.
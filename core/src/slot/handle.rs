@@ -1,13 +1,58 @@
 use anyhow::Result;
-use primitives::idx::MaybeThinIdx;
+use primitives::{idx::MaybeThinIdx, ThinIdx};
 
-use crate::{block::Block, object_ids::RecordId};
+use crate::{
+    block::{Block, SlotDump},
+    object_ids::RecordId,
+};
 
 use super::{
+    callback::catch_callback,
     data::{SlotDataMut, SlotDataRef},
     SlotTuple,
 };
 
+/// Returned when removing through a handle whose slot is no longer the one it was handed out for.
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StaleHandleError {
+    #[error("slot {} was already removed", .idx.into_usize())]
+    Removed { idx: ThinIdx },
+    #[error("slot {} was reused since the handle was made", .idx.into_usize())]
+    Reused { idx: ThinIdx },
+}
+
+/// What a slot held before `SlotHandle::remove_self` turned it into a gap. Hand it to
+/// `Block::reinsert` to put it back under the same record id.
+#[derive(Debug)]
+pub struct RemovedSlot<T> {
+    pub(crate) record: Option<RecordId>,
+    pub(crate) data: T,
+    idx: ThinIdx,
+}
+
+impl<T> RemovedSlot<T> {
+    pub fn record(&self) -> Option<RecordId> {
+        self.record
+    }
+
+    pub fn data(&self) -> &T {
+        &self.data
+    }
+
+    /// The slot the data was removed from, which may be handed out again by now.
+    pub fn idx(&self) -> ThinIdx {
+        self.idx
+    }
+
+    pub fn into_data(self) -> T {
+        self.data
+    }
+
+    pub fn into_tuple(self) -> SlotTuple<T> {
+        (self.record, self.data)
+    }
+}
+
 pub struct SlotHandle<T: 'static> {
     pub block: Block<T>,
     pub idx: MaybeThinIdx,
@@ -30,6 +75,11 @@ impl<T> SlotHandle<T> {
         }
     }
 
+    /// The slot byte for byte, see `Block::slot_dump`.
+    pub fn slot_dump(&self) -> Option<SlotDump> {
+        self.block.slot_dump(self.idx.into_thin())
+    }
+
     #[must_use]
     pub fn read_with<F, R>(&self, f: F) -> Result<R>
     where
@@ -57,30 +107,46 @@ impl<T> SlotHandle<T> {
             slot.check_gen(expected_gen)?;
         }
 
-        f(slot)
+        let res = catch_callback(|| f(slot));
+        outer.mark_dirty();
+
+        res
     }
 
-    #[must_use]
-    pub fn remove_self(self) -> Option<SlotTuple<T>> {
+    /// Turns the slot into a gap and hands back what it held. Any other handle to the slot fails to
+    /// remove it afterwards rather than counting the gap twice: the gap itself gives away a second
+    /// removal, and a handle with a generation also notices when the slot was filled again. A
+    /// handle without one can't tell a refilled slot from its own.
+    pub fn remove_self(self) -> Result<RemovedSlot<T>, StaleHandleError> {
         let mut outer = self.block.inner.write();
-        let prev_tail = outer.meta.gap_tail;
+        // a block without gaps has an empty list, whatever an older block left its tail at
+        let prev_tail = match outer.meta.gap_count {
+            0 => None,
+            _ => outer.meta.gap_tail,
+        };
+        let idx = self.idx.into_thin();
 
         let (record, data) = {
             let mut slot = SlotDataMut::new(&outer.slots_by_index[self.idx]);
 
             if let Some(expected_gen) = self.idx.into_gen() {
-                // When generation id is invalid, it means the slot is no longer owned by this handle and we can't remove it.
-                slot.check_gen(expected_gen).ok()?;
+                if !slot.is_gap() && slot.check_gen(expected_gen).is_err() {
+                    return Err(StaleHandleError::Reused { idx });
+                }
             }
 
-            let (record, data) = unsafe { slot.read_parts()? };
+            // the gap drops the record id, which is what a later handle's generation is checked
+            // against
+            let (record, data) =
+                unsafe { slot.read_parts() }.ok_or(StaleHandleError::Removed { idx })?;
             slot.create_gap(prev_tail);
 
             (record, data)
         };
 
-        outer.meta.gap_tail = Some(self.idx.into_thin());
+        outer.meta.gap_tail = Some(idx);
         outer.meta.gap_count += 1;
+        outer.mark_dirty();
 
         let record = if let Some(thin) = record {
             outer.index_by_record.shift_remove(&thin);
@@ -89,7 +155,7 @@ impl<T> SlotHandle<T> {
             None
         };
 
-        Some((record, data))
+        Ok(RemovedSlot { record, data, idx })
     }
 }
 
@@ -1,14 +1,22 @@
-use std::ops::RangeBounds;
+use std::{
+    fs::File,
+    ops::RangeBounds,
+    os::unix::fs::FileExt,
+    path::{Path, PathBuf},
+    sync::mpsc::Sender,
+};
 
 use anyhow::Result;
 
 use primitives::{
-    shared_object::{SharedObject, SharedObjectReadGuard, SharedObjectWriteGuard},
-    ThinIdx,
+    byte_encoding::{FromBytes, IntoBytes},
+    into_bytes,
+    shared_object::{SharedObject, SharedObjectFairReadGuard, SharedObjectFairWriteGuard},
+    LockFairness, ThinIdx,
 };
 
 use crate::{
-    block::{self, Block},
+    block::{inner::ScrubOutcome, Block, BlockMeta, ScanOptions, SequentialBlocks},
     object_ids::{RecordId, TableId},
     slot::{SlotHandle, SlotTuple},
 };
@@ -16,15 +24,37 @@ use crate::{
 use self::inner::StoreInner;
 
 pub use self::{
-    config::StoreConfig,
+    chain::{BlockChain, ChainInsert},
+    config::{AllocationPolicy, StoreConfig},
+    io::{FileOps, IoIncident, IoOp, SystemFileOps},
+    lock::DirLock,
     meta::StoreMeta,
-    result::{BlockCreationError, InsertError, StoreError},
+    region::{Backing, Region, TableFile},
+    report::{AllocationStats, BlockUtil, CountMismatch, FragReport, ScrubReport},
+    result::{
+        BlockCreationError, CorruptBlock, CorruptValue, InsertError, StoreError, StoreFull,
+        StoreLocked, UnconvertibleValue,
+    },
 };
 
+#[cfg(any(test, feature = "faults"))]
+pub use self::io::FaultyFileOps;
+#[cfg(any(test, feature = "trace"))]
+use self::trace::loc;
+#[cfg(any(test, feature = "trace"))]
+pub use self::trace::{replay, Divergence, StoreState, StoreTrace, TraceOp, TracedOp};
+
+pub mod chain;
 pub mod config;
 pub mod inner;
+pub mod io;
+pub mod lock;
 pub mod meta;
+pub mod region;
+pub mod report;
 pub mod result;
+#[cfg(any(test, feature = "trace"))]
+pub mod trace;
 
 #[derive(Debug)]
 pub enum InsertState<T: 'static> {
@@ -35,18 +65,40 @@ pub enum InsertState<T: 'static> {
     },
 }
 
-pub struct Store<T: 'static>(SharedObject<StoreInner<T>>);
+pub struct Store<T: 'static>(SharedObject<StoreInner<T>>, LockFairness);
 
 impl<T> Clone for Store<T> {
     fn clone(&self) -> Self {
-        Self(self.0.clone())
+        Self(self.0.clone(), self.1)
     }
 }
 
 impl<T> Store<T> {
     pub fn new(table: Option<TableId>, config: Option<StoreConfig>) -> Result<Self> {
         let config = config.unwrap_or_default();
-        let store = Self(SharedObject::new(StoreInner::new(table, Some(config))?));
+        let store = Self(
+            SharedObject::new(StoreInner::new(table, Some(config))?),
+            config.lock_fairness,
+        );
+
+        if config.persistance.is_empty() {
+            store.load(..)?;
+        }
+
+        Ok(store)
+    }
+
+    /// Like `new`, but a relative persistance path is resolved against the database `root`.
+    pub fn new_in(
+        table: Option<TableId>,
+        config: Option<StoreConfig>,
+        root: impl AsRef<Path>,
+    ) -> Result<Self> {
+        let config = config.unwrap_or_default();
+        let store = Self(
+            SharedObject::new(StoreInner::new_in(table, Some(config), root)?),
+            config.lock_fairness,
+        );
 
         if config.persistance.is_empty() {
             store.load(..)?;
@@ -55,6 +107,20 @@ impl<T> Store<T> {
         Ok(store)
     }
 
+    /// A store kept in `region` of a table file instead of a file of its own, see `TableFile`.
+    pub fn new_in_region(
+        table: Option<TableId>,
+        config: Option<StoreConfig>,
+        region: Region,
+    ) -> Result<Self> {
+        let config = config.unwrap_or_default();
+
+        Ok(Self(
+            SharedObject::new(StoreInner::new_in_region(table, Some(config), region)?),
+            config.lock_fairness,
+        ))
+    }
+
     pub fn load(&self, r: impl RangeBounds<usize>) -> Result<()> {
         let inner = self.0.upgradable();
 
@@ -85,12 +151,431 @@ impl<T> Store<T> {
         Ok(())
     }
 
-    pub fn read(&self) -> SharedObjectReadGuard<StoreInner<T>> {
-        self.0.upgradable()
+    /// A shared read of the store, released according to its `lock_fairness`. Readers don't
+    /// exclude each other, but queue behind a writer that is already waiting.
+    pub fn read(&self) -> SharedObjectFairReadGuard<'_, StoreInner<T>> {
+        self.0.read_with_fairness(self.1)
+    }
+
+    pub fn write(&self) -> SharedObjectFairWriteGuard<'_, StoreInner<T>> {
+        self.0.write_with_fairness(self.1)
+    }
+
+    pub fn lock_fairness(&self) -> LockFairness {
+        self.1
+    }
+
+    /// Writes the store meta, with the counts inserts and removals keep, to its file. It's also
+    /// written by `sync_all`, when an insert adds blocks, and when the store is dropped.
+    pub fn sync_meta(&self) -> Result<()> {
+        self.read().sync_meta()
+    }
+
+    /// Flushes every loaded block along with the store meta.
+    pub fn sync_all(&self) -> Result<()> {
+        let inner = self.read();
+
+        for block in inner.blocks.values() {
+            block.sync_all()?;
+        }
+
+        #[cfg(any(test, feature = "trace"))]
+        inner._trace(|| TraceOp::Sync);
+
+        inner.sync_meta()
+    }
+
+    /// Sends every failed flush or write of the store to `sink` from now on, those made when its
+    /// blocks are dropped included, or stops sending them with `None`. A store without a sink
+    /// only warns on stderr about the ones nobody is there to get back.
+    pub fn set_io_error_sink(&self, sink: Option<Sender<IoIncident>>) {
+        self.read().io.set_sink(sink);
+    }
+
+    /// The flushes and writes that failed since the store was opened.
+    pub fn flush_failures(&self) -> u64 {
+        self.read().io.failures()
+    }
+
+    /// The blocks whose last flush failed, so their changes may not be in the file.
+    pub fn failed_blocks(&self) -> Vec<usize> {
+        self.read()
+            .blocks
+            .values()
+            .filter(|block| block.flush_failed())
+            .map(|block| block.index().into_usize())
+            .collect()
+    }
+
+    /// Flushes the blocks through `ops` instead of the OS, for tests that make them fail.
+    #[cfg(any(test, feature = "faults"))]
+    pub fn set_file_ops(&self, ops: std::sync::Arc<dyn FileOps>) {
+        self.read().io.set_ops(ops);
+    }
+
+    /// Creates `additional` empty blocks up front, see `StoreInner::reserve_blocks`.
+    pub fn reserve_blocks(&self, additional: usize) -> Result<()> {
+        self.write().reserve_blocks(additional)
     }
 
-    pub fn write(&self) -> SharedObjectWriteGuard<StoreInner<T>> {
-        self.0.upgradable().upgrade()
+    /// Makes sure `additional` more items fit without creating blocks one at a time.
+    pub fn reserve(&self, additional: usize) -> Result<()> {
+        self.write().reserve(additional)
+    }
+
+    /// The file the store is persisted in, with its path as configured, or `None` for a
+    /// memory-only store. A store in a region of a table file gives the table file's path.
+    pub fn backing_path(&self) -> Option<PathBuf> {
+        let inner = self.read();
+
+        match inner.backing.as_ref()? {
+            Backing::File(_) => Some(inner.meta.config.persistance.to_path_buf()),
+            Backing::Region(region) => Some(region.table_file().path().to_path_buf()),
+        }
+    }
+
+    /// Releases the lock on the backing file, so the store can be opened again while this one is
+    /// still around. Only for a store that won't be written to anymore.
+    pub fn release_lock(&self) -> Result<()> {
+        self.read().release_lock()
+    }
+
+    /// The loaded blocks, in order, for a scan going through them one after the other. See
+    /// `SequentialBlocks` for what `options` change.
+    pub fn scan_blocks(&self, options: ScanOptions) -> SequentialBlocks<T> {
+        let blocks = self.read().blocks.values().cloned().collect::<Vec<_>>();
+
+        SequentialBlocks::new(blocks, options)
+    }
+
+    /// Writes the store to `dest` in the persisted store layout, so it can be opened as a persisted
+    /// store whether or not this one is. Blocks are read one at a time, as `options` say, so
+    /// writers have to be held off by the caller for the copy to be consistent.
+    pub fn write_image(&self, dest: impl AsRef<Path>, options: ScanOptions) -> Result<()> {
+        let inner = self.read();
+        let meta = inner.meta;
+        let file = File::create_new(dest)?;
+
+        file.set_len((StoreMeta::BYTE_COUNT + meta.capacity_as_bytes::<T>()) as u64)?;
+        file.write_all_at(&into_bytes!(meta, StoreMeta)?, 0)?;
+
+        let blocks = (0..meta.block_count.get())
+            .map(|index| {
+                let index = ThinIdx::new(index);
+
+                inner
+                    .blocks
+                    .get(&index)
+                    .cloned()
+                    .ok_or_else(|| anyhow::anyhow!("block {} is not loaded", index))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        for block in SequentialBlocks::new(blocks, options) {
+            let offset = meta.block_offset::<T>(block.index()) as u64;
+
+            block.inner.read_with(|block| -> Result<()> {
+                file.write_all_at(&into_bytes!(block.meta_to_write(), BlockMeta)?, offset)?;
+                file.write_all_at(block.slot_bytes(), offset + BlockMeta::BYTE_COUNT as u64)?;
+                Ok(())
+            })?;
+        }
+
+        file.sync_all()?;
+
+        Ok(())
+    }
+
+    /// Rewrites the table a persisted store file at `path` belongs to, for when a copy of it is
+    /// opened as a different table.
+    pub fn retag_image(path: impl AsRef<Path>, table: TableId) -> Result<()> {
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)?;
+
+        let mut meta_bytes = [0u8; StoreMeta::BYTE_COUNT];
+        file.read_exact_at(&mut meta_bytes, 0)?;
+
+        let mut meta = StoreMeta::from_bytes(&meta_bytes)?;
+        meta.table = table;
+        file.write_all_at(&into_bytes!(meta, StoreMeta)?, 0)?;
+
+        for index in 0..meta.block_count.get() {
+            let index = ThinIdx::new(index);
+            let offset = meta.block_offset::<T>(index) as u64;
+            let mut block_bytes = [0u8; BlockMeta::BYTE_COUNT];
+            file.read_exact_at(&mut block_bytes, offset)?;
+
+            // blocks that were never written are initialized with the store's table on open
+            if block_bytes.iter().all(|b| *b == 0) {
+                continue;
+            }
+
+            let mut block = BlockMeta::new(index, table, None);
+            block.init_from_bytes(&block_bytes)?;
+            block.table = table;
+            file.write_all_at(&into_bytes!(block, BlockMeta)?, offset)?;
+        }
+
+        file.sync_all()?;
+
+        Ok(())
+    }
+
+    /// Copies the persisted store file at `path` into `region` of a table file, block for block,
+    /// for moving a table into a single file. The region has to be empty, and the file is left
+    /// as it was.
+    pub fn import_image(path: impl AsRef<Path>, region: &Region) -> Result<StoreMeta> {
+        let path = path.as_ref();
+        let meta = Self::read_image_meta(path)?;
+
+        if region.read_meta().is_some() {
+            anyhow::bail!("region {} already has a store", region.id());
+        }
+
+        let file = File::open(path)?;
+        let dest = region.table_file().file();
+        let block_size = meta.block_size_as_bytes::<T>();
+        let mut bytes = vec![0u8; block_size];
+
+        region.grow(meta.block_count.get(), block_size)?;
+
+        for index in 0..meta.block_count.get() {
+            let index = ThinIdx::new(index);
+
+            file.read_exact_at(&mut bytes, meta.block_offset::<T>(index) as u64)?;
+            dest.write_all_at(&bytes, region.block_offset(index, block_size)? as u64)?;
+        }
+
+        // the meta goes in last, so a copy cut short leaves the region without a store
+        region.write_meta(&meta)?;
+
+        Ok(meta)
+    }
+
+    /// Reads the meta at the start of a persisted store file without opening the store, failing if
+    /// the file isn't laid out the way a store of `T` with that meta would be.
+    pub fn read_image_meta(path: impl AsRef<Path>) -> Result<StoreMeta> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+
+        if len < StoreMeta::BYTE_COUNT as u64 {
+            anyhow::bail!("file is too small");
+        }
+
+        let mut meta_bytes = [0u8; StoreMeta::BYTE_COUNT];
+        file.read_exact_at(&mut meta_bytes, 0)?;
+
+        let meta = StoreMeta::from_bytes(&meta_bytes)?;
+        let expected = (StoreMeta::BYTE_COUNT + meta.capacity_as_bytes::<T>()) as u64;
+
+        if len != expected {
+            anyhow::bail!(
+                "file is {} bytes but its metadata describes {} bytes",
+                len,
+                expected
+            );
+        }
+
+        Ok(meta)
+    }
+
+    /// How full each loaded block is, read from the block metas without touching any slot.
+    pub fn fragmentation_report(&self) -> FragReport {
+        let inner = self.read();
+
+        FragReport::new(
+            inner.meta.config.label,
+            inner
+                .blocks
+                .values()
+                .map(|block| BlockUtil {
+                    idx: block.index().into_usize(),
+                    live: block.len(),
+                    gaps: block.gap_count(),
+                    capacity: block.capacity(),
+                    wasted_bytes: block.capacity_as_bytes() - block.len_as_bytes(),
+                })
+                .collect(),
+        )
+    }
+
+    /// Reads every block of a persisted store back from its file, one at a time, and checks its
+    /// slots against the checksum flushed with them, see `StoreConfig::checksum`. The bytes are
+    /// read into a buffer of their own rather than through the block's mapping, and each block is
+    /// write locked while it's read. A block written since its last flush can't be checked, and
+    /// is only counted as unsynced. Memory-only stores have nothing to scrub.
+    pub fn scrub(&self) -> Result<ScrubReport> {
+        let (label, blocks) = {
+            let inner = self.read();
+
+            (
+                inner.meta.config.label,
+                inner.blocks.values().cloned().collect::<Vec<_>>(),
+            )
+        };
+        let mut report = ScrubReport {
+            label: label.map(|label| label.as_str().to_string()),
+            ..ScrubReport::default()
+        };
+
+        for block in blocks {
+            match block.scrub()? {
+                Some(ScrubOutcome::Verified) => report.verified += 1,
+                Some(ScrubOutcome::Unchecked) => report.unchecked += 1,
+                Some(ScrubOutcome::Unsynced) => report.unsynced += 1,
+                Some(ScrubOutcome::Corrupt(corrupt)) => report.corrupt.push(corrupt),
+                None => {}
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// The blocks that failed their checksum when they were loaded, which only a store opened with
+    /// `read_corrupt_blocks` has. Their values are read as they are, so any of them may be wrong.
+    pub fn corrupt_blocks(&self) -> Vec<CorruptBlock> {
+        self.read()
+            .blocks
+            .values()
+            .filter_map(|block| block.corrupt())
+            .collect()
+    }
+
+    /// Where inserts have put their items since the store was opened, see `AllocationPolicy`.
+    pub fn allocation_stats(&self) -> AllocationStats {
+        self.read().stats
+    }
+
+    /// Walks the slots of every loaded block and compares what's there to the counts of the block
+    /// and store metas. For persisted stores, the meta at the start of the file is compared too,
+    /// though only the fields that don't change with every write.
+    pub fn check_counts(&self) -> Result<Vec<CountMismatch>> {
+        let inner = self.read();
+        let mut mismatches = Vec::new();
+        let (mut live, mut gaps) = (0, 0);
+
+        let mut check = |block: Option<usize>, what, expected, found| {
+            if expected != found {
+                mismatches.push(CountMismatch {
+                    block,
+                    what,
+                    expected,
+                    found,
+                });
+            }
+        };
+
+        for block in inner.blocks.values() {
+            let idx = block.index().into_usize();
+            let found = block.iter_live().count();
+            let mut found_gaps = 0;
+
+            block.foreach_slot_raw(|handle| {
+                if let Ok(true) = handle.read_with(|slot| Ok(slot.data().is_none())) {
+                    found_gaps += 1;
+                }
+            })?;
+
+            check(Some(idx), "live slot count", block.len(), found);
+            check(Some(idx), "gap count", block.gap_count(), found_gaps);
+
+            live += found;
+            gaps += found_gaps;
+        }
+
+        check(None, "live slot count", inner.meta.len(), live);
+        check(None, "gap count", inner.meta.gap_count, gaps);
+        check(
+            None,
+            "block count",
+            inner.meta.block_count.get(),
+            inner.blocks.len(),
+        );
+
+        if let Some(backing) = inner.backing.as_ref() {
+            let persisted = backing.read_meta()?;
+
+            check(
+                None,
+                "persisted block count",
+                inner.meta.block_count.get(),
+                persisted.block_count.get(),
+            );
+            check(
+                None,
+                "persisted block capacity",
+                inner.meta.config.block_capacity.get(),
+                persisted.config.block_capacity.get(),
+            );
+
+            if persisted.table != inner.meta.table {
+                anyhow::bail!(
+                    "persisted store belongs to table {:?}, not {:?}",
+                    persisted.table,
+                    inner.meta.table
+                );
+            }
+        }
+
+        Ok(mismatches)
+    }
+
+    /// Calls `f` with the handle of every live slot in the loaded blocks, block by block. Each block
+    /// is read locked while its slots are visited.
+    pub fn foreach_live<F>(&self, mut f: F) -> Result<()>
+    where
+        F: FnMut(SlotHandle<T>),
+    {
+        let inner = self.read();
+
+        for block in inner.blocks.values() {
+            block.foreach_slot(&mut f)?;
+        }
+
+        Ok(())
+    }
+
+    /// Calls `f` with the record and value of every live slot, block by block, loading the blocks
+    /// of a persisted store that aren't yet. Slots inserted without a record have none. Values are
+    /// cloned out of their slots and nothing is locked while `f` runs, so it can read other
+    /// stores, or this one. Stops at the first error `f` returns.
+    pub fn scan<F>(&self, mut f: F) -> Result<()>
+    where
+        T: Clone,
+        F: FnMut(Option<RecordId>, &T) -> Result<()>,
+    {
+        self.load(..)?;
+
+        let (table, blocks) = {
+            let inner = self.read();
+            let blocks = inner.blocks.values().cloned().collect::<Vec<_>>();
+
+            (inner.meta.table, blocks)
+        };
+
+        for block in blocks {
+            let handles = block.iter_live().collect::<Vec<_>>();
+
+            for handle in handles {
+                let parts = handle.read_with(|slot| {
+                    Ok(slot
+                        .data()
+                        .cloned()
+                        .map(|data| (slot.thin_record_id(), data)))
+                })?;
+
+                // removed since the block was listed
+                let Some((thin, data)) = parts else {
+                    continue;
+                };
+
+                f(thin.map(|thin| RecordId::from_thin(thin, table)), &data)?;
+            }
+        }
+
+        Ok(())
     }
 
     pub fn insert_one(
@@ -98,7 +583,7 @@ impl<T> Store<T> {
         record: Option<RecordId>,
         data: T,
     ) -> Result<SlotHandle<T>, StoreError<T>> {
-        let mut inner = self.0.write();
+        let mut inner = self.write();
         self.insert_one_with(&mut inner, record, data)
     }
 
@@ -107,44 +592,137 @@ impl<T> Store<T> {
         inner: &mut StoreInner<T>,
         record: Option<RecordId>,
         data: T,
+    ) -> Result<SlotHandle<T>, StoreError<T>> {
+        let label = inner.meta.config.label;
+        let res = Self::_insert_one_with(inner, record, data).map_err(|e| e.labeled(label));
+
+        #[cfg(any(test, feature = "trace"))]
+        inner._trace(|| TraceOp::Insert {
+            record,
+            slot: res.as_ref().ok().map(loc),
+        });
+
+        res
+    }
+
+    fn _insert_one_with(
+        inner: &mut StoreInner<T>,
+        record: Option<RecordId>,
+        data: T,
     ) -> Result<SlotHandle<T>, StoreError<T>> {
         // blocks should never be left in a full state... If it is filled during an insert, then a new block should be created
+        let create = inner._block_maker();
+        let blocks_before = inner.blocks.len();
+        let (handle, filled) =
+            inner
+                .blocks
+                .insert_one(&mut inner.meta.cur_block, record, data, create)?;
 
-        let block = inner
-            .blocks
-            .get(&inner.meta.cur_block)
-            .ok_or(StoreError::BlockNotFound)?;
+        if filled {
+            inner.meta.gap_count -= 1;
+            inner.stats.gap_reuses += 1;
+        } else {
+            inner.meta.item_count += 1;
+            inner.stats.appends += 1;
+        }
 
-        let mut block_inner = block.inner.write();
+        inner.stats.new_blocks += inner.blocks.len() - blocks_before;
 
-        let res = block.insert_one_with(&mut block_inner, record, data)?;
+        inner._sync_block_count()?;
+        inner
+            ._sync_grown_meta(blocks_before)
+            .map_err(|e| StoreError::BlockCreationError(BlockCreationError { error: e }))?;
 
-        if block_inner.is_full() {
-            if let Some(index) = block_inner.meta.take_next_block_index() {
-                inner.meta.cur_block = index;
-            } else {
-                drop(block_inner);
+        Ok(handle)
+    }
 
-                let index = ThinIdx::new_validated(inner.meta.block_count.get())?;
+    /// Removes the slot a handle points at, keeping the store counts in step with the block.
+    pub fn remove(&self, handle: SlotHandle<T>) -> Option<SlotTuple<T>> {
+        let mut inner = self.write();
+        self.remove_with(&mut inner, handle)
+    }
 
-                inner
-                    ._create_block(index)
-                    .map_err(|e| StoreError::BlockCreationError(BlockCreationError { error: e }))?;
+    pub fn remove_with(
+        &self,
+        inner: &mut StoreInner<T>,
+        handle: SlotHandle<T>,
+    ) -> Option<SlotTuple<T>> {
+        #[cfg(any(test, feature = "trace"))]
+        let slot = loc(&handle);
+        let removed = handle.remove_self().ok();
+
+        if removed.is_some() {
+            inner.meta.gap_count += 1;
+        }
 
-                inner.meta.cur_block = index;
-            }
+        #[cfg(any(test, feature = "trace"))]
+        inner._trace(|| TraceOp::Remove {
+            slot,
+            removed: removed.is_some(),
+        });
+
+        Some(removed?.into_tuple())
+    }
+
+    /// Drops every item of the store at once, leaving it as it was created. Every slot becomes a
+    /// gap, so handles handed out before fail to read from then on, and the blocks past the
+    /// `initial_block_count` are dropped along with their bytes in the file, unless a handle still
+    /// holds on to one of them. Returns the number of items dropped.
+    pub fn truncate(&self) -> Result<usize> {
+        let mut inner = self.write();
+        self.truncate_with(&mut inner)
+    }
+
+    pub fn truncate_with(&self, inner: &mut StoreInner<T>) -> Result<usize> {
+        let dropped = inner.meta.len();
+
+        for block in inner.blocks.values() {
+            block.truncate()?;
         }
 
-        inner.meta.item_count += 1;
+        inner.meta.item_count = 0;
+        inner.meta.gap_count = 0;
+        inner.meta.cur_block = ThinIdx::new(0);
+        inner._shrink_to(inner.meta.config.initial_block_count.get())?;
+        inner.sync_meta()?;
+
+        #[cfg(any(test, feature = "trace"))]
+        inner._trace(|| TraceOp::Truncate { dropped });
 
-        Ok(res)
+        Ok(dropped)
     }
 
     pub fn insert<I>(&self, iter: I) -> Result<InsertState<T>, StoreError<T>>
     where
         I: IntoIterator<Item = SlotTuple<T>> + 'static,
     {
-        let mut iter: Box<dyn Iterator<Item = SlotTuple<T>>> = Box::new(iter.into_iter());
+        let mut inner = self.write();
+        self.insert_with(&mut inner, iter)
+    }
+
+    pub fn insert_with<I>(
+        &self,
+        inner: &mut StoreInner<T>,
+        iter: I,
+    ) -> Result<InsertState<T>, StoreError<T>>
+    where
+        I: IntoIterator<Item = SlotTuple<T>> + 'static,
+    {
+        let label = inner.meta.config.label;
+
+        #[cfg(any(test, feature = "trace"))]
+        if inner.trace.is_some() {
+            return Self::_insert_traced(inner, iter).map_err(|e| e.labeled(label));
+        }
+
+        Self::_insert_with(inner, iter).map_err(|e| e.labeled(label))
+    }
+
+    fn _insert_with<I>(inner: &mut StoreInner<T>, iter: I) -> Result<InsertState<T>, StoreError<T>>
+    where
+        I: IntoIterator<Item = SlotTuple<T>> + 'static,
+    {
+        let iter: Box<dyn Iterator<Item = SlotTuple<T>>> = Box::new(iter.into_iter());
         let (low, high) = iter.size_hint();
 
         if let Some(high) = high {
@@ -153,80 +731,51 @@ impl<T> Store<T> {
             }
         }
 
-        let mut inner = self.0.write();
-        let mut all_errors = Vec::new();
-        let mut all_handles = Vec::with_capacity(high.unwrap_or(low));
-        let mut index = 0;
-
-        loop {
-            let block = inner
-                .blocks
-                .get(&inner.meta.cur_block)
-                .ok_or(StoreError::BlockNotFound)?;
-
-            match block.insert(iter.into_iter(), index) {
-                Ok(block::InsertState::Done(handles)) => {
-                    inner.meta.item_count += handles.len();
+        // a batch that won't fit reserves at least half again as many blocks, so a long load
+        // grows the file a few times instead of once per block
+        let blocks_reserved_from = inner.blocks.len();
+        let available = inner.available_slots();
 
-                    return Ok(InsertState::Done(handles));
-                }
-                Ok(block::InsertState::Partial {
-                    errors,
-                    handles,
-                    iter: rest,
-                }) => {
-                    index += errors.len() + handles.len();
-
-                    if !errors.is_empty() {
-                        inner.meta.item_count += handles.len();
-
-                        all_errors.extend(errors);
-                        all_handles.extend(handles);
-                        break;
-                    } else {
-                        iter = rest.expect("rest should be Some if errors is empty");
-                        let mut block_inner = block.inner.write();
-
-                        // NOTE: we know the block is full but there is still more data to insert
-                        if let Some(index) = block_inner.meta.take_next_block_index() {
-                            drop(block_inner);
-
-                            inner.meta.item_count += handles.len();
-                            inner.meta.cur_block = index;
-                        } else {
-                            drop(block_inner);
-
-                            let index = ThinIdx::new_validated(inner.meta.block_count.get())?;
-
-                            inner._create_block(index).map_err(|e| {
-                                StoreError::BlockCreationError(BlockCreationError { error: e })
-                            })?;
+        if low > available {
+            let block_capacity = inner.meta.config.block_capacity.get();
+            let needed = (low - available).div_ceil(block_capacity);
+            let additional = needed.max(inner.meta.block_count.get() / 2);
 
-                            inner.meta.item_count += handles.len();
-                            inner.meta.cur_block = index;
-                        }
-                    }
-                }
-                Err(InsertError::BlockFull { .. }) => {
-                    // this should never happen (current block should always have at least one slot available)
-                    unreachable!("block is full")
-                }
-                Err(e) => {
-                    return Err(StoreError::InsertError(e));
-                }
-            }
+            inner
+                .reserve_blocks(additional)
+                .map_err(|e| StoreError::BlockCreationError(BlockCreationError { error: e }))?;
         }
 
-        if !all_errors.is_empty() {
-            Ok(InsertState::Partial {
-                errors: all_errors,
-                handles: all_handles,
-            })
-        } else {
-            Ok(InsertState::Done(
-                all_handles.into_iter().map(|(_, h)| h).collect(),
-            ))
-        }
+        let mut create = inner._block_maker();
+        let blocks_before = inner.blocks.len();
+        let cur = &mut inner.meta.cur_block;
+        let ChainInsert {
+            state,
+            appended,
+            filled,
+        } = match inner.meta.config.allocation {
+            AllocationPolicy::AppendPreferred if low > AllocationPolicy::APPEND_THRESHOLD => {
+                let from = *cur;
+                inner.blocks.insert_unused(cur, from, iter, create)?
+            }
+            AllocationPolicy::Clustered { batch } if low >= batch => {
+                let from = inner.blocks.block_with_unused(*cur, low, &mut create)?;
+                inner.blocks.insert_unused(cur, from, iter, create)?
+            }
+            _ => inner.blocks.insert(cur, iter, create)?,
+        };
+
+        inner.meta.gap_count -= filled;
+        inner.meta.item_count += appended;
+        inner.stats.gap_reuses += filled;
+        inner.stats.appends += appended;
+        inner.stats.new_blocks += inner.blocks.len() - blocks_reserved_from;
+        inner._sync_block_count()?;
+        inner
+            ._sync_grown_meta(blocks_before)
+            .map_err(|e| StoreError::BlockCreationError(BlockCreationError { error: e }))?;
+
+        Ok(state)
     }
 }
 
@@ -234,6 +783,11 @@ impl<T: std::fmt::Debug> std::fmt::Debug for Store<T> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let inner = self.0.read_recursive();
         let mut d = f.debug_struct("Store");
+
+        if let Some(label) = inner.meta.config.label {
+            d.field("label", &label);
+        }
+
         d.field("meta", &inner.meta);
         d.field("blocks", &inner.blocks);
         d.finish()
@@ -245,11 +799,26 @@ impl<T: std::fmt::Debug> std::fmt::Debug for Store<T> {
 mod test {
     use primitives::{
         byte_encoding::{FromBytes, IntoBytes},
-        into_bytes, O64,
+        into_bytes, InternalString, O64,
+    };
+    use std::{
+        iter,
+        num::NonZeroUsize,
+        path::PathBuf,
+        process::Command,
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            Arc,
+        },
+        thread,
+        time::Duration,
     };
-    use std::{iter, num::NonZeroUsize};
 
     use super::*;
+    use crate::{
+        block::{BlockAdvisor, BlockConfig},
+        test_util::TempDir,
+    };
 
     #[test]
     fn test_store_config() -> Result<()> {
@@ -321,4 +890,1205 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn test_insert_partial_indices() -> Result<()> {
+        let table = TableId::new();
+        let store = Store::<O64>::new(
+            Some(table),
+            Some(StoreConfig {
+                block_capacity: NonZeroUsize::new(5).unwrap(),
+                ..Default::default()
+            }),
+        )?;
+
+        let mut records = (0..10)
+            .map(|n| Some(RecordId::new(ThinIdx::new(n), table)))
+            .collect::<Vec<_>>();
+
+        // item 7 lands in the second block along with item 6
+        records[3] = records[2];
+        records[7] = records[6];
+
+        let items = records
+            .into_iter()
+            .map(|record| (record, O64::new()))
+            .collect::<Vec<_>>();
+
+        let InsertState::Partial { errors, handles } =
+            store.insert(items).map_err(StoreError::thread_safe)?
+        else {
+            panic!("expected a partial insert");
+        };
+
+        assert_eq!(
+            errors.iter().map(|(i, _)| *i).collect::<Vec<_>>(),
+            vec![3, 7]
+        );
+        assert!(errors
+            .iter()
+            .all(|(_, e)| matches!(e, InsertError::AlreadyExists { .. })));
+        assert_eq!(
+            handles.iter().map(|(i, _)| *i).collect::<Vec<_>>(),
+            vec![0, 1, 2, 4, 5, 6, 8, 9]
+        );
+
+        let inner = store.read();
+
+        assert_eq!(inner.meta.item_count, 8);
+        assert_eq!(
+            inner.blocks().values().map(|b| b.len()).collect::<Vec<_>>(),
+            vec![5, 3]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_store_label() -> Result<()> {
+        let table = TableId::new();
+        let store = Store::<O64>::new(
+            Some(table),
+            Some(
+                StoreConfig::default().with_label(InternalString::new("column 'email' (index 0)")?),
+            ),
+        )?;
+
+        let record = Some(RecordId::new(ThinIdx::new(0), table));
+        store
+            .insert_one(record, O64::new())
+            .map_err(StoreError::thread_safe)?;
+
+        let err = store.insert_one(record, O64::new()).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "column 'email' (index 0): record already exists"
+        );
+        assert_eq!(
+            StoreError::thread_safe(store.insert_one(record, O64::new()).unwrap_err()).to_string(),
+            "column 'email' (index 0): record already exists"
+        );
+        assert!(matches!(
+            err.into_unlabeled(),
+            StoreError::InsertError(InsertError::AlreadyExists { .. })
+        ));
+
+        assert!(format!("{:?}", store).starts_with("Store { label: \"column 'email' (index 0)\""));
+
+        let report = store.fragmentation_report();
+
+        assert_eq!(report.label.as_deref(), Some("column 'email' (index 0)"));
+        assert!(report
+            .to_string()
+            .starts_with("column 'email' (index 0): 1 blocks"));
+
+        // errors of unlabeled stores are left as they were
+        let unlabeled = Store::<O64>::new(Some(table), None)?;
+        unlabeled
+            .insert_one(record, O64::new())
+            .map_err(StoreError::thread_safe)?;
+
+        let err = unlabeled.insert_one(record, O64::new()).unwrap_err();
+
+        assert_eq!(err.label(), None);
+        assert_eq!(err.to_string(), "record already exists");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fair_locks_make_progress() -> Result<()> {
+        const WRITERS: usize = 4;
+        const SCANNERS: usize = 4;
+
+        let store = Store::<O64>::new(
+            None,
+            Some(StoreConfig::default().with_lock_fairness(LockFairness::Latency)),
+        )?;
+
+        store
+            .insert(iter::repeat_with(|| (None, O64::new())).take(1000))
+            .map_err(StoreError::thread_safe)?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let counters = (0..WRITERS + SCANNERS)
+            .map(|_| Arc::new(AtomicUsize::new(0)))
+            .collect::<Vec<_>>();
+
+        let threads = counters
+            .iter()
+            .enumerate()
+            .map(|(n, counter)| {
+                let (store, stop, counter) = (store.clone(), stop.clone(), counter.clone());
+
+                thread::spawn(move || {
+                    while !stop.load(Ordering::Relaxed) {
+                        if n < WRITERS {
+                            let handle = store
+                                .insert_one(None, O64::new())
+                                .map_err(|e| e.to_string())
+                                .unwrap();
+                            store.remove(handle);
+                        } else {
+                            let blocks =
+                                store.read().blocks().values().cloned().collect::<Vec<_>>();
+
+                            for block in blocks {
+                                assert!(block.iter_live().count() <= block.capacity());
+                            }
+                        }
+
+                        counter.fetch_add(1, Ordering::Relaxed);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut last = vec![0; counters.len()];
+
+        for _ in 0..4 {
+            thread::sleep(Duration::from_millis(500));
+
+            for (n, counter) in counters.iter().enumerate() {
+                let count = counter.load(Ordering::Relaxed);
+                assert!(
+                    count > last[n],
+                    "{} {} made no progress",
+                    if n < WRITERS { "writer" } else { "scanner" },
+                    n
+                );
+                last[n] = count;
+            }
+        }
+
+        stop.store(true, Ordering::Relaxed);
+
+        for thread in threads {
+            thread.join().expect("worker panicked");
+        }
+
+        Ok(())
+    }
+
+    /// Set for the copies of the test binary `test_lock_across_processes` spawns, to the store
+    /// file they should try to open.
+    const LOCK_CHILD_ENV: &str = "DBEXP_LOCK_CHILD";
+    /// Whether those copies should expect the file to be `locked` or `open`.
+    const LOCK_EXPECT_ENV: &str = "DBEXP_LOCK_EXPECT";
+
+    #[test]
+    fn test_lock_across_processes() -> Result<()> {
+        if let Ok(path) = std::env::var(LOCK_CHILD_ENV) {
+            let opened = Store::<O64>::new(None, Some(StoreConfig::new(1, 8, Some(&path))?));
+
+            match std::env::var(LOCK_EXPECT_ENV)?.as_str() {
+                "locked" => {
+                    let err = opened.unwrap_err();
+                    let locked = err
+                        .downcast_ref::<StoreLocked>()
+                        .expect("a typed lock error");
+
+                    assert_eq!(locked.path, PathBuf::from(&path));
+                }
+                _ => drop(opened?),
+            }
+
+            return Ok(());
+        }
+
+        let dir = TempDir::new("core_store_lock");
+        let path = dir.join("items.store");
+        let store = Store::<O64>::new(None, Some(StoreConfig::new(1, 8, Some(&path))?))?;
+
+        let child = |expect: &str| -> Result<()> {
+            let output = Command::new(std::env::current_exe()?)
+                .args(["--exact", "store::test::test_lock_across_processes"])
+                .env(LOCK_CHILD_ENV, &path)
+                .env(LOCK_EXPECT_ENV, expect)
+                .output()?;
+
+            assert!(
+                output.status.success(),
+                "child expecting the store {}: {}",
+                expect,
+                String::from_utf8_lossy(&output.stdout)
+            );
+
+            Ok(())
+        };
+
+        child("locked")?;
+        drop(store);
+        child("open")?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lock_timeout() -> Result<()> {
+        let dir = TempDir::new("core_store_timeout");
+        let config = StoreConfig::new(1, 8, Some(dir.join("items.store")))?;
+        let store = Store::<O64>::new(None, Some(config))?;
+
+        // a second open file is refused the lock just like another process would be
+        let err = Store::<O64>::new(None, Some(config)).unwrap_err();
+        assert!(err.downcast_ref::<StoreLocked>().is_some(), "{}", err);
+
+        let holder = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(200));
+            drop(store);
+        });
+
+        let waited = Store::<O64>::new(
+            None,
+            Some(config.with_lock_timeout(Duration::from_secs(10))),
+        )?;
+
+        holder.join().expect("holder panicked");
+        assert!(waited.release_lock().is_ok());
+        drop(Store::<O64>::new(None, Some(config))?);
+
+        drop(waited);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reserve_blocks() -> Result<()> {
+        let dir = TempDir::new("core_store_reserve");
+        let path = dir.join("items.store");
+        let config = StoreConfig::new(1, 4, Some(path.clone()))?;
+        let store = Store::<O64>::new(None, Some(config))?;
+
+        store.reserve_blocks(3)?;
+        assert_eq!(store.read().available_slots(), 16);
+        assert_eq!(
+            std::fs::metadata(&path)?.len() as usize,
+            StoreMeta::BYTE_COUNT + store.read().meta().capacity_as_bytes::<O64>()
+        );
+
+        // the batch fits in the reserved blocks, so none are added
+        store
+            .insert(iter::repeat_with(|| (None, O64::new())).take(10))
+            .map_err(StoreError::thread_safe)?;
+        assert_eq!(store.read().meta().block_count.get(), 4);
+
+        store.sync_all()?;
+        drop(store);
+
+        let store = Store::<O64>::new(None, Some(config))?;
+        let mut live = 0;
+        store.foreach_live(|_| live += 1)?;
+
+        assert_eq!(store.read().meta().item_count, 10);
+        assert_eq!(live, 10);
+        assert!(store.check_counts()?.is_empty());
+
+        store
+            .insert(iter::repeat_with(|| (None, O64::new())).take(6))
+            .map_err(StoreError::thread_safe)?;
+        // filling the last reserved block moves the store on to a new one, as it never leaves its
+        // current block full, so there's already room for one more
+        assert_eq!(store.read().meta().block_count.get(), 5);
+        store.reserve(1)?;
+        assert_eq!(store.read().meta().block_count.get(), 5);
+
+        // a batch that doesn't fit grows by at least half the blocks there are
+        store
+            .insert(iter::repeat_with(|| (None, O64::new())).take(5))
+            .map_err(StoreError::thread_safe)?;
+        assert_eq!(store.read().meta().block_count.get(), 7);
+
+        drop(store);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_new_blocks_grow_file() -> Result<()> {
+        let dir = TempDir::new("core_store_grow");
+        let path = dir.join("items.store");
+        let config = StoreConfig::new(1, 4, Some(path.clone()))?;
+        let store = Store::<O64>::new(None, Some(config))?;
+
+        // one at a time, then a batch, both spilling into blocks the file wasn't created with
+        for _ in 0..6 {
+            store
+                .insert_one(None, O64::new())
+                .map_err(StoreError::thread_safe)?;
+        }
+
+        store
+            .insert(iter::repeat_with(|| (None, O64::new())).take(9))
+            .map_err(StoreError::thread_safe)?;
+
+        let block_count = store.read().meta().block_count.get();
+        assert!(block_count >= 4);
+
+        // the file and the meta on disk have both grown with the blocks, before any flush
+        let on_disk = Store::<O64>::read_image_meta(&path)?;
+        assert_eq!(on_disk.block_count.get(), block_count);
+
+        let mut items = Vec::new();
+        store
+            .foreach_live(|handle| items.push(handle.read_with(|slot| Ok(slot.data().copied()))))?;
+
+        store.sync_all()?;
+        drop(store);
+
+        let store = Store::<O64>::new(None, Some(config))?;
+        assert_eq!(store.read().meta().block_count.get(), block_count);
+        assert_eq!(
+            std::fs::metadata(&path)?.len() as usize,
+            StoreMeta::BYTE_COUNT + store.read().meta().capacity_as_bytes::<O64>()
+        );
+
+        let mut reopened = Vec::new();
+        store.foreach_live(|handle| {
+            reopened.push(handle.read_with(|slot| Ok(slot.data().copied())))
+        })?;
+
+        assert_eq!(
+            reopened.into_iter().collect::<Result<Vec<_>>>()?,
+            items.into_iter().collect::<Result<Vec<_>>>()?
+        );
+        assert_eq!(store.read().meta().item_count, 15);
+
+        drop(store);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_meta_survives_drop() -> Result<()> {
+        let dir = TempDir::new("core_store_meta_drop");
+        let path = dir.join("items.store");
+        let config = StoreConfig::new(2, 4, Some(path.clone()))?;
+
+        let expected = {
+            let store = Store::<O64>::new(None, Some(config))?;
+            let InsertState::Done(handles) = store
+                .insert(iter::repeat_with(|| (None, O64::new())).take(6))
+                .map_err(StoreError::thread_safe)?
+            else {
+                panic!("expected every item to be inserted");
+            };
+
+            store.remove(handles[0].clone()).expect("item is live");
+
+            // the counts are on disk once they're synced, without the blocks
+            store.sync_meta()?;
+            let on_disk = Store::<O64>::read_image_meta(&path)?;
+            assert_eq!((on_disk.item_count, on_disk.gap_count), (6, 1));
+
+            // and when the store is dropped without either being synced
+            store
+                .insert_one(None, O64::new())
+                .map_err(StoreError::thread_safe)?;
+            let meta = *store.read().meta();
+            (meta.item_count, meta.gap_count, meta.cur_block)
+        };
+
+        let store = Store::<O64>::new(None, Some(config))?;
+        let meta = *store.read().meta();
+        assert_eq!((meta.item_count, meta.gap_count, meta.cur_block), expected);
+
+        let mut live = 0;
+        store.foreach_live(|_| live += 1)?;
+        assert_eq!(live, 6);
+        assert!(store.check_counts()?.is_empty());
+
+        drop(store);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_truncate() -> Result<()> {
+        let dir = TempDir::new("core_store_truncate");
+        let path = dir.join("items.store");
+        let config = StoreConfig::new(1, 4, Some(path.clone()))?;
+        let store = Store::<O64>::new(None, Some(config))?;
+
+        let InsertState::Done(handles) = store
+            .insert(iter::repeat_with(|| (None, O64::new())).take(10))
+            .map_err(StoreError::thread_safe)?
+        else {
+            panic!("expected every item to be inserted");
+        };
+
+        assert_eq!(store.read().meta().block_count.get(), 3);
+
+        // a handle into the second block keeps it mapped, so the file only shrinks down to it
+        let held = handles[5].clone();
+        drop(handles);
+
+        assert_eq!(store.truncate()?, 10);
+        assert_eq!(store.read().meta().len(), 0);
+        assert_eq!(store.read().meta().block_count.get(), 2);
+        assert!(held.read_with(|slot| Ok(slot.data().copied()))?.is_none());
+        assert!(store.check_counts()?.is_empty());
+
+        drop(held);
+        assert_eq!(store.truncate()?, 0);
+
+        let block_size = store.read().meta().block_size_as_bytes::<O64>();
+
+        assert_eq!(store.read().meta().block_count.get(), 1);
+        assert_eq!(
+            std::fs::metadata(&path)?.len() as usize,
+            StoreMeta::BYTE_COUNT + block_size
+        );
+
+        store
+            .insert(iter::repeat_with(|| (None, O64::new())).take(6))
+            .map_err(StoreError::thread_safe)?;
+        store.sync_all()?;
+        drop(store);
+
+        let store = Store::<O64>::new(None, Some(config))?;
+        let mut live = 0;
+        store.foreach_live(|_| live += 1)?;
+
+        assert_eq!(live, 6);
+        assert_eq!(store.read().meta().block_count.get(), 2);
+        assert!(store.check_counts()?.is_empty());
+
+        drop(store);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_table_file_regions() -> Result<()> {
+        let dir = TempDir::new("core_store_regions");
+        let path = dir.join("table.store");
+        let config = StoreConfig::new(1, 4, Some(path.clone()))?;
+        let table = TableId::new();
+        let read_all = |store: &Store<O64>| -> Result<Vec<O64>> {
+            let mut found = Vec::new();
+            store.foreach_live(|handle| {
+                found.push(handle.read_with(|slot| Ok(*slot.data().unwrap())).unwrap())
+            })?;
+            Ok(found)
+        };
+
+        let file = TableFile::open(&path, None)?;
+        let a = Store::<O64>::new_in_region(Some(table), Some(config), file.region(0))?;
+        let b = Store::<O64>::new_in_region(Some(table), Some(config), file.region(1))?;
+        let (mut in_a, mut in_b) = (Vec::new(), Vec::new());
+
+        // both regions grow past their first block while sharing the file
+        for _ in 0..10 {
+            in_a.push(O64::new());
+            in_b.push(O64::new());
+            a.insert_one(None, *in_a.last().unwrap())
+                .map_err(StoreError::thread_safe)?;
+            b.insert_one(None, *in_b.last().unwrap())
+                .map_err(StoreError::thread_safe)?;
+        }
+
+        a.sync_all()?;
+        b.sync_all()?;
+        assert_eq!(file.region_ids(), vec![0, 1]);
+        assert_eq!(a.read().meta().block_count.get(), 3);
+
+        // a store file of its own, copied into a third region
+        let separate = dir.join("c.store");
+        let c = Store::<O64>::new(
+            Some(table),
+            Some(StoreConfig::new(1, 4, Some(separate.clone()))?),
+        )?;
+        let in_c = iter::repeat_with(O64::new).take(6).collect::<Vec<_>>();
+        c.insert(in_c.clone().into_iter().map(|value| (None, value)))
+            .map_err(StoreError::thread_safe)?;
+        c.sync_all()?;
+        drop(c);
+
+        Store::<O64>::import_image(&separate, &file.region(2))?;
+        assert!(Store::<O64>::import_image(&separate, &file.region(2)).is_err());
+
+        drop((a, b, file));
+        assert!(TableFile::read_metas(&path)?.contains_key(&2));
+
+        let file = TableFile::open(&path, None)?;
+        let a = Store::<O64>::new_in_region(None, Some(config), file.region(0))?;
+        let b = Store::<O64>::new_in_region(None, Some(config), file.region(1))?;
+        let c = Store::<O64>::new_in_region(None, Some(config), file.region(2))?;
+
+        assert_eq!(read_all(&a)?, in_a);
+        assert_eq!(read_all(&b)?, in_b);
+        assert_eq!(read_all(&c)?, in_c);
+        assert!(a.check_counts()?.is_empty());
+        assert!(c.check_counts()?.is_empty());
+        assert_eq!(a.read().meta().table, table);
+
+        // the file is locked once for every region in it
+        assert!(TableFile::open(&path, None)
+            .unwrap_err()
+            .downcast_ref::<StoreLocked>()
+            .is_some());
+
+        drop((a, b, c, file));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_block_chain_order() -> Result<()> {
+        let table = TableId::new();
+        let mut chain = BlockChain::<O64>::new();
+
+        for index in [2, 0, 3, 1] {
+            let config = BlockConfig::new(4)?;
+            chain.add_block(Block::new_anon(ThinIdx::new(index), table, Some(config))?);
+        }
+
+        let order = chain
+            .values()
+            .map(|block| block.index().into_usize())
+            .collect::<Vec<_>>();
+
+        assert_eq!(order, vec![0, 1, 2, 3]);
+        assert_eq!(
+            chain.continuation(ThinIdx::new(1)).map(Block::index),
+            Some(ThinIdx::new(2))
+        );
+        assert!(chain.continuation(ThinIdx::new(3)).is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_block_chain_insert_spans_blocks() -> Result<()> {
+        let table = TableId::new();
+        let mut chain = BlockChain::<O64>::new();
+        chain.add_block(Block::new_anon(
+            ThinIdx::new(0),
+            table,
+            Some(BlockConfig::new(4)?),
+        )?);
+
+        let items = iter::repeat_with(|| (None, O64::new()))
+            .take(10)
+            .collect::<Vec<_>>();
+        let expected = items
+            .iter()
+            .map(|(_, item)| Some(*item))
+            .collect::<Vec<_>>();
+        let mut cur = ThinIdx::new(0);
+        let mut created = Vec::new();
+
+        let inserted = chain
+            .insert(&mut cur, items, |index| {
+                created.push(index.into_usize());
+                Block::new_anon(index, table, Some(BlockConfig::new(4)?))
+            })
+            .map_err(StoreError::thread_safe)?;
+
+        assert_eq!(created, vec![1, 2]);
+        assert_eq!(cur, ThinIdx::new(2));
+        assert_eq!((inserted.appended, inserted.filled), (10, 0));
+        assert!(matches!(inserted.state, InsertState::Done(ref handles) if handles.len() == 10));
+
+        // the items come back in insertion order when the chain is walked in order
+        let mut stored = Vec::new();
+
+        for block in chain.values() {
+            for handle in block.iter_live() {
+                stored.push(handle.read_with(|slot| Ok(slot.data().copied()))?);
+            }
+        }
+
+        assert_eq!(stored, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_scan_blocks_advise() -> Result<()> {
+        /// Keeps what it was asked to advise, and fails every prefetch when `fail` is set.
+        #[derive(Default)]
+        struct Recorder {
+            calls: std::cell::RefCell<Vec<(&'static str, usize)>>,
+            fail: bool,
+        }
+
+        impl BlockAdvisor<O64> for Recorder {
+            fn advise_sequential(&self, block: &Block<O64>) -> Result<()> {
+                let index = block.index().into_usize();
+                self.calls.borrow_mut().push(("sequential", index));
+                Ok(())
+            }
+
+            fn prefetch(&self, block: &Block<O64>) -> Result<()> {
+                let index = block.index().into_usize();
+                self.calls.borrow_mut().push(("prefetch", index));
+
+                match self.fail {
+                    true => anyhow::bail!("prefetch refused"),
+                    false => Ok(()),
+                }
+            }
+        }
+
+        let dir = TempDir::new("core_store_advise");
+        let config = StoreConfig::new(1, 4, Some(dir.join("items.store")))?;
+        let store = Store::<O64>::new(None, Some(config))?;
+
+        store
+            .insert(iter::repeat_with(|| (None, O64::new())).take(10))
+            .map_err(StoreError::thread_safe)?;
+        let count = store.read().meta().block_count.get();
+        assert!(count >= 3);
+
+        // each block is advised as it's reached, along with the one after it
+        let recorder = Recorder::default();
+        let scanned = store
+            .scan_blocks(ScanOptions::default())
+            .with_advisor(&recorder)
+            .map(|block| block.index().into_usize())
+            .collect::<Vec<_>>();
+
+        let expected = (0..count)
+            .flat_map(|index| [("sequential", index), ("prefetch", index + 1)])
+            .take(count * 2 - 1)
+            .collect::<Vec<_>>();
+
+        assert_eq!(scanned, (0..count).collect::<Vec<_>>());
+        assert_eq!(recorder.calls.into_inner(), expected);
+
+        // advice that fails doesn't stop the scan
+        let failing = Recorder {
+            fail: true,
+            ..Recorder::default()
+        };
+        assert_eq!(
+            store
+                .scan_blocks(ScanOptions::default())
+                .with_advisor(&failing)
+                .count(),
+            count
+        );
+        assert_eq!(failing.calls.borrow().len(), count * 2 - 1);
+
+        let off = Recorder::default();
+        assert_eq!(
+            store
+                .scan_blocks(ScanOptions::default().with_prefetch(false))
+                .with_advisor(&off)
+                .count(),
+            count
+        );
+        assert!(off.calls.borrow().is_empty());
+
+        // the real thing on the blocks' mappings
+        for block in store.scan_blocks(ScanOptions::default()) {
+            block.advise_sequential()?;
+            block.prefetch()?;
+        }
+
+        drop(store);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_replay_concurrent_ops() -> Result<()> {
+        use rand::{rngs::StdRng, Rng, SeedableRng};
+
+        const THREADS: u64 = 4;
+        const OPS: usize = 200;
+
+        for seed in 0..8 {
+            let table = TableId::new();
+            let store =
+                Store::<O64>::new(Some(table), Some(StoreConfig::new(1, 8, None::<PathBuf>)?))?;
+            let trace = store.start_trace()?;
+            let next_record = Arc::new(AtomicUsize::new(0));
+
+            let workers = (0..THREADS)
+                .map(|worker| {
+                    let store = store.clone();
+                    let next_record = next_record.clone();
+
+                    thread::spawn(move || -> Result<()> {
+                        let mut rng = StdRng::seed_from_u64(seed * THREADS + worker);
+                        let mut handles = Vec::new();
+                        let record = || {
+                            let n = next_record.fetch_add(1, Ordering::Relaxed);
+                            Some(RecordId::new(ThinIdx::new(n), table))
+                        };
+
+                        for _ in 0..OPS {
+                            match rng.gen_range(0..10) {
+                                0..=3 => handles.push(
+                                    store
+                                        .insert_one(record(), O64::new())
+                                        .map_err(StoreError::thread_safe)?,
+                                ),
+                                4..=5 => {
+                                    let items = (0..rng.gen_range(1..6))
+                                        .map(|_| (record(), O64::new()))
+                                        .collect::<Vec<_>>();
+
+                                    if let InsertState::Done(done) =
+                                        store.insert(items).map_err(StoreError::thread_safe)?
+                                    {
+                                        handles.extend(done);
+                                    }
+                                }
+                                6..=8 if !handles.is_empty() => {
+                                    let handle =
+                                        handles.swap_remove(rng.gen_range(0..handles.len()));
+                                    store.remove(handle);
+                                }
+                                _ => store.sync_all()?,
+                            }
+                        }
+
+                        Ok(())
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            for worker in workers {
+                worker.join().unwrap()?;
+            }
+
+            let claimed = StoreState::of(&store)?;
+
+            if let Err(divergence) = replay(&trace, &claimed, |_| O64::new()) {
+                panic!("seed {}: {}", seed, divergence.dump());
+            }
+
+            assert_eq!(claimed.item_count - claimed.gap_count, claimed.live.len());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_replay_finds_divergence() -> Result<()> {
+        let store = Store::<O64>::new(None, Some(StoreConfig::new(1, 4, None::<PathBuf>)?))?;
+        let trace = store.start_trace()?;
+        let handles = (0..6)
+            .map(|_| {
+                store
+                    .insert_one(None, O64::new())
+                    .map_err(StoreError::thread_safe)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        store.remove(handles[1].clone());
+        store
+            .insert_one(None, O64::new())
+            .map_err(StoreError::thread_safe)?;
+
+        // only an empty store can be traced
+        assert!(store.start_trace().is_err());
+
+        let claimed = StoreState::of(&store)?;
+        let replayed =
+            replay(&trace, &claimed, |_| O64::new()).map_err(|d| anyhow::anyhow!(d.dump()))?;
+        assert_eq!(replayed, claimed);
+
+        // an insert claimed to land in a block the store doesn't have diverges right there, and
+        // the inserts it lands after can't be minimized away
+        let bogus = TraceOp::Insert {
+            record: None,
+            slot: Some((5, 0)),
+        };
+        trace.push(bogus.clone());
+
+        let divergence = replay(&trace, &claimed, |_| O64::new()).unwrap_err();
+
+        assert_eq!(divergence.op, 8);
+        assert_eq!(divergence.what, "insert slot");
+        assert_eq!(divergence.minimized.last().map(|op| &op.op), Some(&bogus));
+        assert!(divergence.minimized.len() >= 7);
+        assert!(divergence.dump().contains("Some((5, 0))"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_allocation_policies() -> Result<()> {
+        let policies = [
+            AllocationPolicy::GapsFirst,
+            AllocationPolicy::AppendPreferred,
+            AllocationPolicy::Clustered { batch: 8 },
+        ];
+
+        for policy in policies {
+            let config = StoreConfig::new(1, 32, None::<PathBuf>)?.with_allocation_policy(policy);
+            let store = Store::<O64>::new(None, Some(config))?;
+            let batch = |count: usize| -> Result<Vec<(usize, usize)>> {
+                let items = (0..count).map(|_| (None, O64::new())).collect::<Vec<_>>();
+
+                match store.insert(items).map_err(StoreError::thread_safe)? {
+                    InsertState::Done(handles) => Ok(handles.iter().map(loc).collect()),
+                    InsertState::Partial { errors, .. } => {
+                        anyhow::bail!("{} items failed", errors.len())
+                    }
+                }
+            };
+
+            // half of the first block used, with every other of those slots freed again
+            let handles = (0..16)
+                .map(|_| {
+                    store
+                        .insert_one(None, O64::new())
+                        .map_err(StoreError::thread_safe)
+                })
+                .collect::<Result<Vec<_>>>()?;
+
+            for handle in handles.into_iter().step_by(2) {
+                store.remove(handle);
+            }
+
+            let before = store.allocation_stats();
+            let slots = batch(20)?;
+            let after = store.allocation_stats();
+            let stats = AllocationStats {
+                gap_reuses: after.gap_reuses - before.gap_reuses,
+                appends: after.appends - before.appends,
+                new_blocks: after.new_blocks - before.new_blocks,
+            };
+
+            match policy {
+                AllocationPolicy::GapsFirst => {
+                    // the freed slots first, the last freed first, then the unused ones after them
+                    let expected = (0..16).step_by(2).rev().chain(16..28).map(|i| (0, i));
+                    assert_eq!(slots, expected.collect::<Vec<_>>());
+                    assert_eq!(
+                        stats,
+                        AllocationStats {
+                            gap_reuses: 8,
+                            appends: 12,
+                            new_blocks: 0,
+                        }
+                    );
+                }
+                AllocationPolicy::AppendPreferred => {
+                    // the unused end of the first block, then the start of a new one
+                    let expected = (16..32).map(|i| (0, i)).chain((0..4).map(|i| (1, i)));
+                    assert_eq!(slots, expected.collect::<Vec<_>>());
+                    assert_eq!(
+                        stats,
+                        AllocationStats {
+                            gap_reuses: 0,
+                            appends: 20,
+                            new_blocks: 1,
+                        }
+                    );
+
+                    // a small batch still goes to the gaps
+                    assert!(batch(4)?.iter().all(|(block, i)| *block == 0 && i % 2 == 0));
+                }
+                AllocationPolicy::Clustered { .. } => {
+                    // the first block only has 16 unused slots left, so a new one takes all 20
+                    assert_eq!(slots, (0..20).map(|i| (1, i)).collect::<Vec<_>>());
+                    assert_eq!(
+                        stats,
+                        AllocationStats {
+                            gap_reuses: 0,
+                            appends: 20,
+                            new_blocks: 1,
+                        }
+                    );
+
+                    // one that fits stays in the block inserts are at, and single items still
+                    // fill its gaps
+                    assert_eq!(batch(10)?, (16..26).map(|i| (0, i)).collect::<Vec<_>>());
+                    let handle = store
+                        .insert_one(None, O64::new())
+                        .map_err(StoreError::thread_safe)?;
+                    assert_eq!(loc(&handle).0, 0);
+                    assert_eq!(store.allocation_stats().gap_reuses, after.gap_reuses + 1);
+                }
+            }
+
+            assert!(store.check_counts()?.is_empty());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_io_incidents() -> Result<()> {
+        let dir = TempDir::new("core_store_io");
+        let path = dir.join("items.store");
+        let config =
+            StoreConfig::new(1, 4, Some(path.clone()))?.with_label(InternalString::new("items")?);
+        let store = Store::<O64>::new(None, Some(config))?;
+        let faulty = FaultyFileOps::new();
+        let (sink, incidents) = std::sync::mpsc::channel();
+
+        store.set_file_ops(faulty.clone());
+        store
+            .insert(iter::repeat_with(|| (None, O64::new())).take(6))
+            .map_err(StoreError::thread_safe)?;
+        store.sync_all()?;
+
+        // without a sink, failures are still returned and counted
+        faulty.fail(true);
+        assert!(store.sync_all().is_err());
+        assert_eq!(store.flush_failures(), 1);
+        assert_eq!(store.failed_blocks(), vec![0]);
+
+        store.set_io_error_sink(Some(sink));
+        assert!(store.sync_all().is_err());
+
+        let incident = incidents.try_recv()?;
+        assert_eq!(incident.path.as_deref(), Some(path.as_path()));
+        assert_eq!(incident.label.as_deref(), Some("items"));
+        assert_eq!(incident.block, Some(0));
+        assert_eq!(incident.op, IoOp::Flush);
+        assert_eq!(incident.errno, Some(FaultyFileOps::EIO));
+
+        faulty.fail(false);
+        store.sync_all()?;
+        assert!(store.failed_blocks().is_empty());
+        assert_eq!(store.flush_failures(), 2);
+
+        // blocks dropped while the disk fails have nobody to return the error to
+        faulty.fail(true);
+        drop(store);
+
+        let dropped = incidents.try_iter().filter_map(|incident| incident.block);
+        assert_eq!(dropped.collect::<Vec<_>>(), vec![0, 1]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_block_checksums() -> Result<()> {
+        let dir = TempDir::new("core_store_checksum");
+        let path = dir.join("items.store");
+        let config = StoreConfig::new(1, 4, Some(path.clone()))?.with_checksum(true);
+        let open = |config: StoreConfig| Store::<O64>::new(None, Some(config));
+
+        // inserts, removals and flushes across reopens never fail a checksum
+        for round in 0..3 {
+            let store = open(config)?;
+            let InsertState::Done(handles) = store
+                .insert(iter::repeat_with(|| (None, O64::new())).take(6))
+                .map_err(StoreError::thread_safe)?
+            else {
+                panic!("expected every item to be inserted");
+            };
+
+            store.remove(handles[round].clone()).expect("item is live");
+            drop(handles);
+
+            // written since the last flush, so the file isn't checked yet
+            assert!(store.scrub()?.unsynced > 0);
+
+            store.sync_all()?;
+
+            let report = store.scrub()?;
+            assert!(report.is_ok(), "{}", report);
+            assert_eq!(report.verified, store.read().meta().block_count.get());
+            assert_eq!(report.unsynced, 0);
+        }
+
+        // a block that's never written is still covered, so the byte flipped below is never read
+        let store = open(config)?;
+        store.reserve_blocks(1)?;
+
+        let last = store.read().meta().block_count.get() - 1;
+        let offset = store.read().meta().block_offset::<O64>(ThinIdx::new(last))
+            + BlockMeta::BYTE_COUNT
+            + Block::<O64>::SLOT_BYTE_COUNT;
+        drop(store);
+
+        let flip = || -> Result<()> {
+            let file = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&path)?;
+            let mut byte = [0u8];
+
+            file.read_exact_at(&mut byte, offset as u64)?;
+            byte[0] ^= 0xff;
+            file.write_all_at(&byte, offset as u64)?;
+
+            Ok(())
+        };
+
+        flip()?;
+
+        let Err(error) = open(config) else {
+            panic!("expected the corrupt block to fail the open");
+        };
+        assert_eq!(
+            error
+                .downcast_ref::<CorruptBlock>()
+                .map(|corrupt| corrupt.idx),
+            Some(last)
+        );
+
+        // read anyway, the block is flagged, and keeps failing its checksum once flushed
+        let store = open(config.with_corrupt_block_reads(true))?;
+        assert_eq!(
+            store
+                .corrupt_blocks()
+                .iter()
+                .map(|corrupt| corrupt.idx)
+                .collect::<Vec<_>>(),
+            vec![last]
+        );
+
+        store.sync_all()?;
+
+        let report = store.scrub()?;
+        assert_eq!(report.corrupt, store.corrupt_blocks());
+        assert_eq!(report.verified, last);
+        drop(store);
+
+        assert!(open(config).is_err());
+
+        flip()?;
+        assert!(open(config)?.scrub()?.is_ok());
+
+        // a store opened without checksums drops them
+        open(StoreConfig::new(1, 4, Some(path.clone()))?)?.sync_all()?;
+        assert_eq!(open(config)?.scrub()?.unchecked, last + 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_scan() -> Result<()> {
+        let dir = TempDir::new("core_store_scan");
+        let table = TableId::new();
+        let config = StoreConfig::new(2, 4, Some(dir.join("items.store")))?;
+
+        let records = (0..10)
+            .map(|n| RecordId::new(ThinIdx::new(n), table))
+            .collect::<Vec<_>>();
+        let values = records.iter().map(|_| O64::new()).collect::<Vec<_>>();
+
+        {
+            let store = Store::<O64>::new(Some(table), Some(config))?;
+            store.load(..)?;
+
+            let InsertState::Done(handles) = store
+                .insert(records.clone().into_iter().map(Some).zip(values.clone()))
+                .map_err(StoreError::thread_safe)?
+            else {
+                panic!("expected every item to be inserted");
+            };
+
+            store.remove(handles[3].clone()).expect("item is live");
+            store.sync_all()?;
+        }
+
+        // reopened, so every value is read back from the file
+        let store = Store::<O64>::new(Some(table), Some(config))?;
+
+        let mut seen = Vec::new();
+
+        store.scan(|record, value| {
+            // the store isn't locked while the callback runs
+            let _ = store.read().meta().item_count;
+
+            seen.push((record, *value));
+            Ok(())
+        })?;
+
+        let expected = records
+            .iter()
+            .zip(values.iter())
+            .enumerate()
+            .filter(|(n, _)| *n != 3)
+            .map(|(_, (record, value))| (Some(*record), *value))
+            .collect::<Vec<_>>();
+
+        assert_eq!(seen, expected);
+
+        // the first error stops the scan
+        let mut visited = 0;
+        let res = store.scan(|_, _| {
+            visited += 1;
+            anyhow::ensure!(visited < 2, "stop");
+            Ok(())
+        });
+
+        assert!(res.is_err());
+        assert_eq!(visited, 2);
+
+        drop(store);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_collect_as() -> Result<()> {
+        use crate::values::DataValue;
+        use primitives::Number;
+
+        let table = TableId::new();
+        let store = Store::<DataValue>::new(Some(table), None)?;
+        let records = (0..4)
+            .map(|n| RecordId::new(ThinIdx::new(n), table))
+            .collect::<Vec<_>>();
+
+        let numbers = [
+            Number::Integer(-1),
+            Number::Unsigned(2),
+            Number::Float(3.0),
+            Number::Float(3.5),
+        ];
+
+        let handles = records
+            .iter()
+            .zip(numbers)
+            .map(|(record, number)| {
+                store
+                    .insert_one(Some(*record), DataValue::Number(number))
+                    .map_err(StoreError::thread_safe)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // a float without a fraction is a whole number, but 3.5 isn't one
+        let err = store.collect_as::<i64>().unwrap_err();
+        let unconvertible = err
+            .downcast_ref::<UnconvertibleValue>()
+            .expect("an UnconvertibleValue");
+
+        assert_eq!(unconvertible.record, records[3]);
+        assert_eq!((unconvertible.block, unconvertible.slot), (0, 3));
+
+        assert_eq!(
+            store.collect_as::<f64>()?,
+            records
+                .iter()
+                .copied()
+                .zip([-1.0, 2.0, 3.0, 3.5])
+                .collect::<Vec<_>>()
+        );
+
+        store.remove(handles[3].clone()).expect("item is live");
+
+        assert_eq!(
+            store.collect_as::<i64>()?,
+            records.iter().copied().zip([-1, 2, 3]).collect::<Vec<_>>()
+        );
+
+        // -1 is no u64, and a number is no string
+        let err = store.collect_as::<u64>().unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<UnconvertibleValue>().unwrap().record,
+            records[0]
+        );
+        assert!(store
+            .collect_as::<String>()
+            .unwrap_err()
+            .is::<UnconvertibleValue>());
+
+        Ok(())
+    }
 }
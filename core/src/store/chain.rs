@@ -0,0 +1,323 @@
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use primitives::ThinIdx;
+
+use crate::{
+    block::{self, Block},
+    object_ids::RecordId,
+    slot::{SlotHandle, SlotTuple},
+    store::{BlockCreationError, InsertError, InsertState, StoreError},
+};
+
+/// The blocks of a store, kept in index order whatever order they were added in. Inserts go to a
+/// current block and continue with the block after it once it's full, so a chain grows by
+/// continuation blocks appended to its end, made by the caller's `create` callback to be anon or
+/// file backed.
+pub struct BlockChain<T: 'static> {
+    blocks: BTreeMap<ThinIdx, Block<T>>,
+}
+
+/// What `BlockChain::insert` did, with the counts a store keeps in its meta.
+#[derive(Debug)]
+pub struct ChainInsert<T: 'static> {
+    pub state: InsertState<T>,
+    /// Items that went into slots never used before.
+    pub appended: usize,
+    /// Items that went into the gaps left by removed items.
+    pub filled: usize,
+}
+
+impl<T> Default for BlockChain<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> BlockChain<T> {
+    pub fn new() -> Self {
+        Self {
+            blocks: BTreeMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    pub fn get(&self, index: &ThinIdx) -> Option<&Block<T>> {
+        self.blocks.get(index)
+    }
+
+    pub fn contains(&self, index: &ThinIdx) -> bool {
+        self.blocks.contains_key(index)
+    }
+
+    /// Adds a block at its own index, replacing the one that was there.
+    pub fn add_block(&mut self, block: Block<T>) -> Option<Block<T>> {
+        self.blocks.insert(block.index(), block)
+    }
+
+    /// Takes the block with the highest index out of the chain.
+    pub fn pop_last(&mut self) -> Option<Block<T>> {
+        self.blocks.pop_last().map(|(_, block)| block)
+    }
+
+    /// The blocks in index order.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (&ThinIdx, &Block<T>)> {
+        self.blocks.iter()
+    }
+
+    pub fn values(&self) -> impl DoubleEndedIterator<Item = &Block<T>> {
+        self.blocks.values()
+    }
+
+    /// The block inserts continue with once the block at `index` is full.
+    pub fn continuation(&self, index: ThinIdx) -> Option<&Block<T>> {
+        self.blocks
+            .range((std::ops::Bound::Excluded(index), std::ops::Bound::Unbounded))
+            .next()
+            .map(|(_, block)| block)
+    }
+
+    /// The number of items that fit in the block at `index` and every block after it.
+    pub fn available_from(&self, index: ThinIdx) -> usize {
+        self.blocks
+            .range(index..)
+            .map(|(_, block)| {
+                block
+                    .inner
+                    .read_with(|inner| inner.capacity() - inner.len())
+            })
+            .sum()
+    }
+
+    /// Moves `cur` on to the continuation of a full block, appending one made by `create` when
+    /// the chain ends there.
+    pub fn advance<F>(&mut self, cur: &mut ThinIdx, create: F) -> Result<(), StoreError<T>>
+    where
+        F: FnMut(ThinIdx) -> Result<Block<T>>,
+    {
+        if let Some(next) = self.continuation(*cur) {
+            *cur = next.index();
+            return Ok(());
+        }
+
+        *cur = self.append_block(create)?;
+
+        Ok(())
+    }
+
+    /// Adds a block made by `create` to the end of the chain, returning its index.
+    pub fn append_block<F>(&mut self, mut create: F) -> Result<ThinIdx, StoreError<T>>
+    where
+        F: FnMut(ThinIdx) -> Result<Block<T>>,
+    {
+        let index = ThinIdx::new_validated(self.blocks.len())?;
+        let block = create(index)
+            .map_err(|e| StoreError::BlockCreationError(BlockCreationError { error: e }))?;
+
+        self.add_block(block);
+
+        Ok(index)
+    }
+
+    /// The first block from `cur` on with `count` unused slots, or with every slot unused if a
+    /// block doesn't have `count`. Appends one made by `create` when there's no such block.
+    pub fn block_with_unused<F>(
+        &mut self,
+        cur: ThinIdx,
+        count: usize,
+        create: F,
+    ) -> Result<ThinIdx, StoreError<T>>
+    where
+        F: FnMut(ThinIdx) -> Result<Block<T>>,
+    {
+        let found = self.blocks.range(cur..).find_map(|(index, block)| {
+            block.inner.read_with(|inner| {
+                (inner.unused_slots() >= count.min(inner.capacity())).then_some(*index)
+            })
+        });
+
+        match found {
+            Some(index) => Ok(index),
+            None => self.append_block(create),
+        }
+    }
+
+    /// Inserts into the block at `cur`, moving `cur` on once it fills so that it never points at
+    /// a full block. Returns whether the item went into a gap.
+    pub fn insert_one<F>(
+        &mut self,
+        cur: &mut ThinIdx,
+        record: Option<RecordId>,
+        data: T,
+        create: F,
+    ) -> Result<(SlotHandle<T>, bool), StoreError<T>>
+    where
+        F: FnMut(ThinIdx) -> Result<Block<T>>,
+    {
+        let block = self.blocks.get(cur).ok_or(StoreError::BlockNotFound)?;
+        let mut block_inner = block.inner.write_with_fairness(block.lock_fairness());
+        let gaps_before = block_inner.meta.gap_count;
+
+        let handle = block.insert_one_with(&mut block_inner, record, data)?;
+        let filled = block_inner.meta.gap_count < gaps_before;
+        let full = block_inner.is_full();
+
+        drop(block_inner);
+
+        if full {
+            self.advance(cur, create)?;
+        }
+
+        Ok((handle, filled))
+    }
+
+    /// Inserts every item, starting with the block at `cur` and moving on to its continuations as
+    /// they fill. Positions in the returned state count from the first item.
+    pub fn insert<I, F>(
+        &mut self,
+        cur: &mut ThinIdx,
+        iter: I,
+        create: F,
+    ) -> Result<ChainInsert<T>, StoreError<T>>
+    where
+        I: IntoIterator<Item = SlotTuple<T>> + 'static,
+        F: FnMut(ThinIdx) -> Result<Block<T>>,
+    {
+        self._insert(cur, iter, create, true)
+    }
+
+    /// Like `insert`, but only into slots never used before, starting with the block at `from`
+    /// and leaving the gaps of the blocks it goes through to later inserts. `cur` is only moved on
+    /// once the blocks it points at are full.
+    pub fn insert_unused<I, F>(
+        &mut self,
+        cur: &mut ThinIdx,
+        from: ThinIdx,
+        iter: I,
+        mut create: F,
+    ) -> Result<ChainInsert<T>, StoreError<T>>
+    where
+        I: IntoIterator<Item = SlotTuple<T>> + 'static,
+        F: FnMut(ThinIdx) -> Result<Block<T>>,
+    {
+        let mut at = from;
+        let res = self._insert(&mut at, iter, &mut create, false)?;
+
+        while self
+            .blocks
+            .get(cur)
+            .ok_or(StoreError::BlockNotFound)?
+            .is_full()
+        {
+            self.advance(cur, &mut create)?;
+        }
+
+        Ok(res)
+    }
+
+    fn _insert<I, F>(
+        &mut self,
+        cur: &mut ThinIdx,
+        iter: I,
+        mut create: F,
+        reuse_gaps: bool,
+    ) -> Result<ChainInsert<T>, StoreError<T>>
+    where
+        I: IntoIterator<Item = SlotTuple<T>> + 'static,
+        F: FnMut(ThinIdx) -> Result<Block<T>>,
+    {
+        let mut iter: Box<dyn Iterator<Item = SlotTuple<T>>> = Box::new(iter.into_iter());
+        let (low, high) = iter.size_hint();
+
+        let mut all_errors = Vec::new();
+        let mut all_handles = Vec::with_capacity(high.unwrap_or(low));
+        let (mut appended, mut filled) = (0, 0);
+        let mut index = 0;
+
+        loop {
+            let block = self.blocks.get(cur).ok_or(StoreError::BlockNotFound)?;
+
+            let gaps_before = block.gap_count();
+            let res = if reuse_gaps {
+                block.insert(iter, index)
+            } else {
+                block.insert_unused(iter, index)
+            };
+            let block_filled = gaps_before - block.gap_count();
+
+            filled += block_filled;
+
+            match res {
+                Ok(block::InsertState::Done(handles)) => {
+                    appended += handles.len() - block_filled;
+
+                    // a block only reports `Done` when none of its items failed, so the positions
+                    // continue on from `index`
+                    all_handles
+                        .extend(handles.into_iter().enumerate().map(|(i, h)| (index + i, h)));
+                    break;
+                }
+                Ok(block::InsertState::Partial {
+                    errors,
+                    handles,
+                    iter: rest,
+                }) => {
+                    index += errors.len() + handles.len();
+                    appended += handles.len() - block_filled;
+
+                    all_errors.extend(errors);
+                    all_handles.extend(handles);
+
+                    let Some(rest) = rest else {
+                        break;
+                    };
+
+                    // NOTE: we know the block is full but there is still more data to insert
+                    iter = rest;
+                    self.advance(cur, &mut create)?;
+                }
+                Err(InsertError::BlockFull { iter: rest, .. }) => {
+                    // a store never leaves its current block full, but a chain put together by
+                    // hand may start on one
+                    let Some(rest) = rest else {
+                        break;
+                    };
+
+                    iter = rest;
+                    self.advance(cur, &mut create)?;
+                }
+                Err(e) => {
+                    return Err(StoreError::InsertError(e));
+                }
+            }
+        }
+
+        let state = if !all_errors.is_empty() {
+            InsertState::Partial {
+                errors: all_errors,
+                handles: all_handles,
+            }
+        } else {
+            InsertState::Done(all_handles.into_iter().map(|(_, h)| h).collect())
+        };
+
+        Ok(ChainInsert {
+            state,
+            appended,
+            filled,
+        })
+    }
+}
+
+impl<T: std::fmt::Debug> std::fmt::Debug for BlockChain<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_map().entries(self.blocks.iter()).finish()
+    }
+}
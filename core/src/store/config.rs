@@ -1,16 +1,62 @@
-use std::{num::NonZeroUsize, path::Path};
+use std::{num::NonZeroUsize, path::Path, time::Duration};
 
 use anyhow::Result;
 use primitives::{
     byte_encoding::{ByteDecoder, ByteEncoder, FromBytes, IntoBytes},
-    impl_access_bytes_for_into_bytes_type, InternalPath,
+    impl_access_bytes_for_into_bytes_type, InternalPath, InternalString, LockFairness,
 };
 
+use crate::block::config::validate_block_capacity;
+
+/// Where a batch insert puts its items, see `Store::insert`. Single items always go to the first
+/// free slot of the current block, gap or not.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum AllocationPolicy {
+    /// Fills the gaps of the current block before its unused slots, whatever the size of the
+    /// batch.
+    #[default]
+    GapsFirst,
+    /// Batches of more than `APPEND_THRESHOLD` items go to slots never used before, starting with
+    /// those left in the current block, and leave the gaps to smaller inserts.
+    AppendPreferred,
+    /// Batches of `batch` items or more go to unused slots that are all in one block, the current
+    /// one if it has room for them and a block after it otherwise, created if none has. A batch
+    /// bigger than a block starts an empty one and continues with those after it.
+    Clustered { batch: usize },
+}
+
+impl AllocationPolicy {
+    pub const APPEND_THRESHOLD: usize = 16;
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct StoreConfig {
     pub initial_block_count: NonZeroUsize,
     pub block_capacity: NonZeroUsize,
     pub persistance: InternalPath,
+    /// How the store and block locks are handed over. Like the path, it's a property of how the
+    /// store is opened and isn't written into the store.
+    pub lock_fairness: LockFairness,
+    /// How long opening a persisted store waits for another process to release its file. Without
+    /// one, the open fails as soon as the file is found locked.
+    pub lock_timeout: Option<Duration>,
+    /// Skips checking text read back from the store is still valid UTF-8, for stores whose files
+    /// nothing else writes to. Also a property of how the store is opened.
+    pub trusted_input: bool,
+    /// What the store is called in its errors, debug output and reports, e.g. the column it holds.
+    /// It's given again whenever the store is opened, so it isn't written into the store either.
+    pub label: Option<InternalString>,
+    /// Where batch inserts put their items. Tuned for how the store is used, so it isn't written
+    /// into the store either.
+    pub allocation: AllocationPolicy,
+    /// Keeps a checksum of the slots of every block of a persisted store, written into the block's
+    /// meta when it's flushed and checked when it's loaded, so a damaged file is caught before its
+    /// values are read. Not written into the store either, and a store opened without it drops the
+    /// checksums of the blocks it flushes, since it doesn't keep them up to date.
+    pub checksum: bool,
+    /// Loads the blocks that fail their checksum instead of failing to open the store, for reading
+    /// what can still be read from a damaged file. See `Store::corrupt_blocks`.
+    pub read_corrupt_blocks: bool,
 }
 
 impl Default for StoreConfig {
@@ -19,6 +65,13 @@ impl Default for StoreConfig {
             initial_block_count: unsafe { NonZeroUsize::new_unchecked(1) },
             block_capacity: unsafe { NonZeroUsize::new_unchecked(128) },
             persistance: Default::default(),
+            lock_fairness: Default::default(),
+            lock_timeout: None,
+            trusted_input: false,
+            label: None,
+            allocation: AllocationPolicy::GapsFirst,
+            checksum: false,
+            read_corrupt_blocks: false,
         }
     }
 }
@@ -29,7 +82,8 @@ impl IntoBytes for StoreConfig {
     fn encode_bytes(&self, x: &mut ByteEncoder<'_>) -> Result<()> {
         x.encode(self.initial_block_count)?;
         x.encode(self.block_capacity)?;
-        x.encode_bytes(&self.persistance.into_vec()?)?;
+        // the path is where the store lives, so it isn't written into the store itself
+        x.skip(InternalPath::BYTE_COUNT)?;
         Ok(())
     }
 }
@@ -38,12 +92,55 @@ impl FromBytes for StoreConfig {
     fn decode_bytes(this: &mut Self, x: &mut ByteDecoder<'_>) -> Result<()> {
         x.decode(&mut this.initial_block_count)?;
         x.decode(&mut this.block_capacity)?;
-        x.delegate(&mut this.persistance)?;
+        x.skip(InternalPath::BYTE_COUNT)?;
         Ok(())
     }
 }
 
 impl StoreConfig {
+    pub fn with_lock_fairness(self, lock_fairness: LockFairness) -> Self {
+        Self {
+            lock_fairness,
+            ..self
+        }
+    }
+
+    pub fn with_lock_timeout(self, lock_timeout: Duration) -> Self {
+        Self {
+            lock_timeout: Some(lock_timeout),
+            ..self
+        }
+    }
+
+    pub fn with_trusted_input(self, trusted_input: bool) -> Self {
+        Self {
+            trusted_input,
+            ..self
+        }
+    }
+
+    pub fn with_label(self, label: InternalString) -> Self {
+        Self {
+            label: Some(label),
+            ..self
+        }
+    }
+
+    pub fn with_allocation_policy(self, allocation: AllocationPolicy) -> Self {
+        Self { allocation, ..self }
+    }
+
+    pub fn with_checksum(self, checksum: bool) -> Self {
+        Self { checksum, ..self }
+    }
+
+    pub fn with_corrupt_block_reads(self, read_corrupt_blocks: bool) -> Self {
+        Self {
+            read_corrupt_blocks,
+            ..self
+        }
+    }
+
     #[must_use]
     pub fn new(
         initial_block_count: usize,
@@ -53,8 +150,7 @@ impl StoreConfig {
         let initial_block_count = NonZeroUsize::new(initial_block_count)
             .ok_or_else(|| anyhow::anyhow!("Initial block count must be greater than zero"))?;
 
-        let block_capacity = NonZeroUsize::new(block_capacity)
-            .ok_or_else(|| anyhow::anyhow!("Block capacity must be greater than zero"))?;
+        let block_capacity = validate_block_capacity(block_capacity)?;
 
         let persistance = persistance
             .map(|x| InternalPath::new(x.as_ref()))
@@ -65,6 +161,13 @@ impl StoreConfig {
             initial_block_count,
             block_capacity,
             persistance,
+            lock_fairness: LockFairness::default(),
+            lock_timeout: None,
+            trusted_input: false,
+            label: None,
+            allocation: AllocationPolicy::GapsFirst,
+            checksum: false,
+            read_corrupt_blocks: false,
         })
     }
 }
@@ -3,12 +3,12 @@ use std::{
     num::NonZeroUsize,
     ops::RangeBounds,
     os::unix::fs::FileExt,
+    path::{Path, PathBuf},
     sync::Arc,
 };
 
 use anyhow::Result;
 
-use indexmap::IndexMap;
 use primitives::{
     byte_encoding::{FromBytes, IntoBytes},
     into_bytes, ThinIdx,
@@ -17,13 +17,101 @@ use primitives::{
 use crate::{
     block::{self, BlockConfig},
     object_ids::TableId,
-    store::{Block, StoreConfig, StoreMeta},
+    store::{
+        io::{IoOp, IoReporter},
+        lock::lock_file,
+        region::{Backing, Region},
+        AllocationStats, Block, BlockChain, StoreConfig, StoreMeta,
+    },
 };
 
+#[cfg(any(test, feature = "trace"))]
+use crate::store::trace::{StoreTrace, TraceOp};
+
 pub struct StoreInner<T: 'static> {
     pub(crate) meta: StoreMeta,
-    pub(super) file: Option<Arc<File>>,
-    pub(crate) blocks: IndexMap<ThinIdx, Block<T>>,
+    pub(super) backing: Option<Backing>,
+    pub(crate) blocks: BlockChain<T>,
+    /// Where inserts have put their items since the store was opened.
+    pub(crate) stats: AllocationStats,
+    /// Where the store and its blocks report failed flushes.
+    pub(crate) io: Arc<IoReporter>,
+    /// Where the ops of the store are recorded, once `Store::start_trace` is called.
+    #[cfg(any(test, feature = "trace"))]
+    pub(crate) trace: Option<Arc<StoreTrace>>,
+}
+
+/// Makes the block at `index` of a store, mapped from its file when it has one.
+fn new_block<T>(
+    meta: &StoreMeta,
+    backing: Option<&Backing>,
+    io: &Arc<IoReporter>,
+    index: ThinIdx,
+) -> Result<Block<T>> {
+    let config = BlockConfig::new(meta.config.block_capacity.get())?;
+
+    let block = if let Some(backing) = backing {
+        // blocks appended as inserts fill the chain haven't been reserved
+        backing.grow::<T>(meta, index.into_usize() + 1)?;
+        let offset = backing.block_offset::<T>(meta, index)?;
+
+        block::Block::new(
+            index,
+            meta.table,
+            backing.file().clone(),
+            offset,
+            Some(config),
+        )?
+    } else {
+        block::Block::new_anon(index, meta.table, Some(config))?
+    };
+
+    let block = block
+        .with_lock_fairness(meta.config.lock_fairness)
+        .with_label(meta.config.label)
+        .with_io(io.clone())
+        .with_checksum(meta.config.checksum);
+
+    if meta.config.checksum {
+        if let Err(corrupt) = block.verify_checksum() {
+            if !meta.config.read_corrupt_blocks {
+                let error = anyhow::Error::from(corrupt);
+
+                return Err(match meta.config.label {
+                    Some(label) => error.context(format!("{}: {}", label, corrupt)),
+                    None => error,
+                });
+            }
+        }
+    }
+
+    Ok(block)
+}
+
+/// A persisted meta, with what isn't written into stores taken from the `config` it's reopened
+/// with.
+fn reopened(mut meta: StoreMeta, config: StoreConfig) -> StoreMeta {
+    meta.config.persistance = config.persistance;
+    meta.config.lock_fairness = config.lock_fairness;
+    meta.config.lock_timeout = config.lock_timeout;
+    meta.config.trusted_input = config.trusted_input;
+    meta.config.label = config.label;
+    meta.config.allocation = config.allocation;
+    meta.config.checksum = config.checksum;
+    meta.config.read_corrupt_blocks = config.read_corrupt_blocks;
+    meta
+}
+
+impl<T> Drop for StoreInner<T> {
+    fn drop(&mut self) {
+        // inserts only write the meta when they add blocks, so the counts they change since the
+        // last sync would otherwise be lost; a failure has already gone to the store's sink
+        if let Err(err) = self.sync_meta() {
+            if !self.io.has_sink() {
+                eprintln!("WARNING: failed to write store meta: {:?}", err);
+            }
+        }
+    }
 }
 
 impl<T> StoreInner<T> {
@@ -38,6 +126,21 @@ impl<T> StoreInner<T> {
         }
     }
 
+    /// Like `new`, but relative persistance paths are resolved against `root`.
+    pub fn new_in(
+        table: Option<TableId>,
+        config: Option<StoreConfig>,
+        root: impl AsRef<Path>,
+    ) -> Result<Self> {
+        let config = config.unwrap_or_default();
+
+        if config.persistance.is_empty() {
+            Self::new_memory_only(table, Some(config))
+        } else {
+            Self::new_persisted_in(table, Some(config), root)
+        }
+    }
+
     #[must_use]
     pub fn new_memory_only(table: Option<TableId>, config: Option<StoreConfig>) -> Result<Self> {
         let config = config.unwrap_or_default();
@@ -48,13 +151,33 @@ impl<T> StoreInner<T> {
 
         Ok(Self {
             meta: StoreMeta::new(table, Some(config)),
-            file: None,
-            blocks: IndexMap::with_capacity(config.initial_block_count.get()),
+            backing: None,
+            blocks: BlockChain::new(),
+            stats: AllocationStats::default(),
+            io: IoReporter::new(None, config.label),
+            #[cfg(any(test, feature = "trace"))]
+            trace: None,
         })
     }
 
     #[must_use]
     pub fn new_persisted(table: Option<TableId>, config: Option<StoreConfig>) -> Result<Self> {
+        Self::_new_persisted(table, config, None)
+    }
+
+    pub fn new_persisted_in(
+        table: Option<TableId>,
+        config: Option<StoreConfig>,
+        root: impl AsRef<Path>,
+    ) -> Result<Self> {
+        Self::_new_persisted(table, config, Some(root.as_ref()))
+    }
+
+    fn _new_persisted(
+        table: Option<TableId>,
+        config: Option<StoreConfig>,
+        root: Option<&Path>,
+    ) -> Result<Self> {
         let table = table.unwrap_or_else(|| TableId::new());
         let config = config.unwrap_or_default();
 
@@ -62,7 +185,15 @@ impl<T> StoreInner<T> {
             anyhow::bail!("persistance path is required for persisted store");
         }
 
-        let path = config.persistance.as_path();
+        let path = match root {
+            Some(root) => config.persistance.resolve(root),
+            None if config.persistance.is_relative() => anyhow::bail!(
+                "persistance path {:?} is relative but no database root was given",
+                config.persistance
+            ),
+            None => config.persistance.to_path_buf(),
+        };
+        let path = path.as_path();
         let parent_dir = path
             .parent()
             .ok_or_else(|| anyhow::anyhow!("path has no parent"))?;
@@ -73,13 +204,17 @@ impl<T> StoreInner<T> {
             let meta = StoreMeta::new(Some(table), Some(config));
 
             let file = File::create_new(path)?;
-            file.set_len(meta.capacity_as_bytes::<T>() as u64)?;
+            lock_file(&file, path, false, config.lock_timeout)?;
+            file.set_len((StoreMeta::BYTE_COUNT + meta.capacity_as_bytes::<T>()) as u64)?;
             file.write_all_at(&into_bytes!(meta, StoreMeta)?, 0)?;
 
             (meta, file)
         } else {
             let file = fs::OpenOptions::new().read(true).write(true).open(&path)?;
 
+            // another process writing through its own maps would corrupt ours
+            lock_file(&file, path, false, config.lock_timeout)?;
+
             let fs_meta = file.metadata()?;
 
             if fs_meta.len() < StoreMeta::BYTE_COUNT as u64 {
@@ -89,7 +224,7 @@ impl<T> StoreInner<T> {
             let mut meta_bytes = [0u8; StoreMeta::BYTE_COUNT];
             file.read_exact_at(&mut meta_bytes, 0)?;
 
-            let meta = StoreMeta::from_bytes(&meta_bytes)?;
+            let meta = reopened(StoreMeta::from_bytes(&meta_bytes)?, config);
 
             let expected_size = meta.capacity_as_bytes::<T>() as usize;
             let actual_len = (fs_meta.len() - StoreMeta::BYTE_COUNT as u64) as usize;
@@ -101,25 +236,149 @@ impl<T> StoreInner<T> {
             (meta, file)
         };
 
-        Ok(Self {
+        Self::_open_blocks(meta, Backing::File(Arc::new(file)), path.to_path_buf())
+    }
+
+    /// Opens the store kept in `region` of a table file, or creates it there if the region has
+    /// none yet. The file was locked when it was opened, so the region isn't locked again.
+    pub fn new_in_region(
+        table: Option<TableId>,
+        config: Option<StoreConfig>,
+        region: Region,
+    ) -> Result<Self> {
+        let table = table.unwrap_or_default();
+        let config = config.unwrap_or_default();
+
+        let meta = match region.read_meta() {
+            Some(meta) => {
+                let meta = reopened(meta, config);
+
+                if region.block_count() < meta.block_count.get() {
+                    anyhow::bail!("region does not match metadata");
+                }
+
+                meta
+            }
+            None => {
+                let meta = StoreMeta::new(Some(table), Some(config));
+                let backing = Backing::Region(region.clone());
+
+                backing.grow::<T>(&meta, meta.block_count.get())?;
+                backing.write_meta(&meta)?;
+                meta
+            }
+        };
+
+        let path = region.table_file().path().to_path_buf();
+
+        Self::_open_blocks(meta, Backing::Region(region), path)
+    }
+
+    fn _open_blocks(meta: StoreMeta, backing: Backing, path: PathBuf) -> Result<Self> {
+        let mut this = Self {
             meta,
-            file: Some(Arc::new(file)),
-            blocks: IndexMap::with_capacity(meta.block_count.get()),
-        })
+            backing: Some(backing),
+            blocks: BlockChain::new(),
+            stats: AllocationStats::default(),
+            io: IoReporter::new(Some(path), meta.config.label),
+            #[cfg(any(test, feature = "trace"))]
+            trace: None,
+        };
+
+        for index in 0..meta.block_count.get() {
+            if let Err(err) = this._create_block(ThinIdx::new(index)) {
+                // dropped without the backing, so the meta in the file keeps the blocks this
+                // didn't get to rather than counting only the ones it opened
+                this.backing = None;
+                return Err(err);
+            }
+        }
+
+        Ok(this)
     }
 
     pub fn meta(&self) -> &StoreMeta {
         &self.meta
     }
 
-    pub fn blocks(&self) -> &IndexMap<ThinIdx, Block<T>> {
+    pub fn blocks(&self) -> &BlockChain<T> {
         &self.blocks
     }
 
-    pub fn blocks_mut(&mut self) -> &mut IndexMap<ThinIdx, Block<T>> {
+    pub fn blocks_mut(&mut self) -> &mut BlockChain<T> {
         &mut self.blocks
     }
 
+    /// Writes the store meta back to the start of the backing file, or the superblock of a table
+    /// file. Memory-only stores are a no-op.
+    pub fn sync_meta(&self) -> Result<()> {
+        if let Some(backing) = self.backing.as_ref() {
+            if let Err(err) = backing.write_meta(&self.meta) {
+                self.io.report(None, IoOp::WriteMeta, &err);
+                return Err(err);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// What the store is persisted in, or `None` for a memory-only store.
+    pub fn backing(&self) -> Option<&Backing> {
+        self.backing.as_ref()
+    }
+
+    /// Releases the lock on the backing file early, for a store that won't be written again but
+    /// may still be referenced. Memory-only stores are a no-op.
+    pub fn release_lock(&self) -> Result<()> {
+        if let Some(backing) = self.backing.as_ref() {
+            backing.unlock()?;
+        }
+
+        Ok(())
+    }
+
+    /// The number of items that fit before a block has to be created: what's left of the current
+    /// block and of every block reserved after it.
+    pub fn available_slots(&self) -> usize {
+        self.blocks.available_from(self.meta.cur_block)
+    }
+
+    /// Makes room for `additional` more items beyond `available_slots`, reserving only the blocks
+    /// that are missing.
+    pub fn reserve(&mut self, additional: usize) -> Result<()> {
+        let available = self.available_slots();
+
+        if additional <= available {
+            return Ok(());
+        }
+
+        let block_capacity = self.meta.config.block_capacity.get();
+        self.reserve_blocks((additional - available).div_ceil(block_capacity))
+    }
+
+    /// Creates `additional` empty blocks after the last one, growing a persisted file to fit all
+    /// of them at once. They continue the chain after the current block, so inserts move on to
+    /// them as it fills instead of creating blocks of their own. The new block count is written
+    /// out right away, so the file is never larger than its meta says.
+    pub fn reserve_blocks(&mut self, additional: usize) -> Result<()> {
+        if additional == 0 {
+            return Ok(());
+        }
+
+        let first = self.meta.block_count.get();
+        let total = first + additional;
+
+        if let Some(backing) = self.backing.as_ref() {
+            backing.grow::<T>(&self.meta, total)?;
+        }
+
+        for index in first..total {
+            self._create_block(ThinIdx::new_validated(index)?)?;
+        }
+
+        self.sync_meta()
+    }
+
     pub fn next_available_index(&self) -> ThinIdx {
         let block = self
             .blocks
@@ -130,31 +389,66 @@ impl<T> StoreInner<T> {
     }
 
     pub(crate) fn _create_block(&mut self, index: ThinIdx) -> Result<()> {
-        let table = self.meta.table;
-        let block_capacity = self.meta.config.block_capacity.get();
+        let block = new_block(&self.meta, self.backing.as_ref(), &self.io, index)?;
+        self.blocks.add_block(block);
+        self._sync_block_count()
+    }
 
-        if let Some(file) = self.file.as_ref().cloned() {
-            let block_capacity_as_bytes = block_capacity * Block::<T>::SLOT_BYTE_COUNT;
-            let offset = StoreMeta::BYTE_COUNT + (index * block_capacity_as_bytes);
+    /// What the chain continues with, made from a copy of the meta since the chain is borrowed
+    /// along with the current block while it inserts.
+    pub(crate) fn _block_maker(&self) -> impl FnMut(ThinIdx) -> Result<Block<T>> {
+        let (meta, backing, io) = (self.meta, self.backing.clone(), self.io.clone());
 
-            self.blocks
-                .insert(index, block::Block::new(index, table, file, offset)?);
-        } else {
-            self.blocks.insert(
-                index,
-                block::Block::new_anon(index, table, Some(BlockConfig::new(block_capacity)?))?,
-            );
-        }
-
-        let new_block_count = self.blocks.len();
+        move |index| new_block(&meta, backing.as_ref(), &io, index)
+    }
 
-        self.meta.block_count = NonZeroUsize::new(new_block_count).ok_or_else(|| {
+    /// Brings the block count of the meta in line with the chain after blocks were added to it.
+    pub(crate) fn _sync_block_count(&mut self) -> Result<()> {
+        self.meta.block_count = NonZeroUsize::new(self.blocks.len()).ok_or_else(|| {
             anyhow::anyhow!("block count should never be zero after creating a block")
         })?;
 
         Ok(())
     }
 
+    /// Drops the blocks from `keep` on, last first, and gives their bytes back to the backing. A
+    /// block something still holds on to stops it there, since its mapping has to stay valid, so
+    /// more than `keep` blocks may be left.
+    pub(crate) fn _shrink_to(&mut self, keep: usize) -> Result<()> {
+        while self.blocks.len() > keep.max(1) {
+            let block = self.blocks.pop_last().expect("more blocks than kept");
+
+            if let Err(block) = block.try_release() {
+                self.blocks.add_block(block);
+                break;
+            }
+        }
+
+        if let Some(backing) = self.backing.as_ref() {
+            backing.shrink::<T>(&self.meta, self.blocks.len())?;
+        }
+
+        self._sync_block_count()
+    }
+
+    /// Writes the meta out once the chain has appended blocks during an insert, which grew the
+    /// file past what the meta on disk describes.
+    pub(crate) fn _sync_grown_meta(&self, blocks_before: usize) -> Result<()> {
+        if self.blocks.len() == blocks_before {
+            return Ok(());
+        }
+
+        self.sync_meta()
+    }
+
+    /// Records an op into the store's trace, if it's traced.
+    #[cfg(any(test, feature = "trace"))]
+    pub(crate) fn _trace(&self, op: impl FnOnce() -> TraceOp) {
+        if let Some(trace) = self.trace.as_ref() {
+            trace.push(op());
+        }
+    }
+
     pub(crate) fn _resolve_range(&self, r: impl RangeBounds<usize>) -> Result<(ThinIdx, ThinIdx)> {
         let start = ThinIdx::new_validated(match r.start_bound() {
             std::ops::Bound::Included(&start) => start,
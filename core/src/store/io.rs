@@ -0,0 +1,213 @@
+//! Where the IO errors of a store's flushes go. A failed flush is returned to whoever asked for it,
+//! but blocks also flush when they're dropped, where there's nobody to return it to. Every failure
+//! is counted, and sent as an `IoIncident` to the sink set with `Store::set_io_error_sink`, so it
+//! isn't lost either way.
+
+use std::{
+    fmt,
+    fs::File,
+    io,
+    os::unix::fs::FileExt,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::Sender,
+        Arc, Mutex,
+    },
+};
+
+use memmap2::MmapMut;
+use parking_lot::RwLock;
+use primitives::InternalString;
+use serde::Serialize;
+
+/// What a store was doing when it failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IoOp {
+    /// Flushing the slots of a block to its file.
+    Flush,
+    /// Writing the meta of a block, or of the store.
+    WriteMeta,
+}
+
+/// A failed flush or write of a store, see `Store::set_io_error_sink`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct IoIncident {
+    /// The file of the store, or `None` for a memory-only store.
+    pub path: Option<PathBuf>,
+    pub label: Option<String>,
+    /// The block that failed, or `None` for the store meta.
+    pub block: Option<usize>,
+    pub op: IoOp,
+    /// The OS error code, when the error came from the OS.
+    pub errno: Option<i32>,
+    pub message: String,
+}
+
+impl fmt::Display for IoIncident {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(label) = self.label.as_deref() {
+            write!(f, "{}: ", label)?;
+        }
+
+        match self.block {
+            Some(block) => write!(f, "{:?} of block {} failed", self.op, block)?,
+            None => write!(f, "{:?} of the store meta failed", self.op)?,
+        }
+
+        if let Some(path) = self.path.as_ref() {
+            write!(f, " in {}", path.display())?;
+        }
+
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// The file operations blocks flush through. Only replaced by tests, to make them fail, see
+/// `Store::set_file_ops`.
+pub trait FileOps: Send + Sync {
+    fn flush(&self, map: &MmapMut) -> io::Result<()>;
+    fn write_all_at(&self, file: &File, buf: &[u8], offset: u64) -> io::Result<()>;
+}
+
+/// The file operations of the OS.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemFileOps;
+
+impl FileOps for SystemFileOps {
+    fn flush(&self, map: &MmapMut) -> io::Result<()> {
+        map.flush()
+    }
+
+    fn write_all_at(&self, file: &File, buf: &[u8], offset: u64) -> io::Result<()> {
+        file.write_all_at(buf, offset)
+    }
+}
+
+/// The OS file operations, failing with `EIO` while `fail` is set, as a full or broken disk would.
+#[cfg(any(test, feature = "faults"))]
+#[derive(Debug, Default)]
+pub struct FaultyFileOps {
+    failing: std::sync::atomic::AtomicBool,
+}
+
+#[cfg(any(test, feature = "faults"))]
+impl FaultyFileOps {
+    /// `EIO` on Linux and the BSDs.
+    pub const EIO: i32 = 5;
+
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    pub fn fail(&self, failing: bool) {
+        self.failing.store(failing, Ordering::Release);
+    }
+
+    fn check(&self) -> io::Result<()> {
+        if self.failing.load(Ordering::Acquire) {
+            return Err(io::Error::from_raw_os_error(Self::EIO));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(any(test, feature = "faults"))]
+impl FileOps for FaultyFileOps {
+    fn flush(&self, map: &MmapMut) -> io::Result<()> {
+        self.check()?;
+        map.flush()
+    }
+
+    fn write_all_at(&self, file: &File, buf: &[u8], offset: u64) -> io::Result<()> {
+        self.check()?;
+        file.write_all_at(buf, offset)
+    }
+}
+
+/// Shared by a store and its blocks, so a block reports to the store's sink even once it's
+/// dropped.
+pub(crate) struct IoReporter {
+    path: Option<PathBuf>,
+    label: Option<InternalString>,
+    sink: Mutex<Option<Sender<IoIncident>>>,
+    failures: AtomicU64,
+    ops: RwLock<Arc<dyn FileOps>>,
+}
+
+impl IoReporter {
+    pub(crate) fn new(path: Option<PathBuf>, label: Option<InternalString>) -> Arc<Self> {
+        Arc::new(Self {
+            path,
+            label,
+            sink: Mutex::new(None),
+            failures: AtomicU64::new(0),
+            ops: RwLock::new(Arc::new(SystemFileOps)),
+        })
+    }
+
+    pub(crate) fn ops(&self) -> Arc<dyn FileOps> {
+        self.ops.read().clone()
+    }
+
+    #[cfg(any(test, feature = "faults"))]
+    pub(crate) fn set_ops(&self, ops: Arc<dyn FileOps>) {
+        *self.ops.write() = ops;
+    }
+
+    pub(crate) fn set_sink(&self, sink: Option<Sender<IoIncident>>) {
+        *self
+            .sink
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = sink;
+    }
+
+    pub(crate) fn has_sink(&self) -> bool {
+        self.sink
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .is_some()
+    }
+
+    pub(crate) fn failures(&self) -> u64 {
+        self.failures.load(Ordering::Acquire)
+    }
+
+    /// Counts a failure and sends it to the sink. A sink whose receiver is gone is dropped.
+    pub(crate) fn report(&self, block: Option<usize>, op: IoOp, error: &anyhow::Error) {
+        self.failures.fetch_add(1, Ordering::AcqRel);
+
+        let incident = IoIncident {
+            path: self.path.clone(),
+            label: self.label.map(|label| label.as_str().to_string()),
+            block,
+            op,
+            errno: error
+                .downcast_ref::<io::Error>()
+                .and_then(io::Error::raw_os_error),
+            message: format!("{:#}", error),
+        };
+        let mut sink = self
+            .sink
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        if let Some(sender) = sink.as_ref() {
+            if sender.send(incident).is_err() {
+                *sink = None;
+            }
+        }
+    }
+}
+
+impl fmt::Debug for IoReporter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IoReporter")
+            .field("path", &self.path)
+            .field("failures", &self.failures())
+            .field("has_sink", &self.has_sink())
+            .finish()
+    }
+}
@@ -0,0 +1,109 @@
+use std::{
+    fs::{self, File, TryLockError},
+    io::Write,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+
+use crate::store::StoreLocked;
+
+/// How long to wait between attempts while waiting out `StoreConfig::lock_timeout`.
+const RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The file a database root is locked through.
+pub const DIR_LOCK_FILE: &str = "LOCK";
+
+/// Takes an advisory lock on `file`, exclusive unless `shared`. Without a `timeout` it fails with
+/// `StoreLocked` as soon as another process holds a conflicting lock, otherwise only once the
+/// timeout has passed. The lock is released when every handle to the file is closed.
+pub(crate) fn lock_file(
+    file: &File,
+    path: &Path,
+    shared: bool,
+    timeout: Option<Duration>,
+) -> Result<()> {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+    loop {
+        let attempt = if shared {
+            file.try_lock_shared()
+        } else {
+            file.try_lock()
+        };
+
+        match attempt {
+            Ok(()) => return Ok(()),
+            Err(TryLockError::Error(error)) => return Err(error.into()),
+            Err(TryLockError::WouldBlock) => {}
+        }
+
+        match deadline {
+            Some(deadline) if Instant::now() < deadline => thread::sleep(RETRY_INTERVAL),
+            _ => {
+                return Err(StoreLocked {
+                    path: path.to_path_buf(),
+                    holder_hint: holder_hint(path),
+                }
+                .into())
+            }
+        }
+    }
+}
+
+/// The holder named by the nearest `DirLock` file at or above `path`. Store files don't name
+/// theirs, but the database they're in usually does.
+fn holder_hint(path: &Path) -> Option<String> {
+    path.ancestors()
+        .skip(1)
+        .map(|dir| dir.join(DIR_LOCK_FILE))
+        .find_map(|lock| {
+            let holder = fs::read_to_string(lock).ok()?;
+            let holder = holder.trim();
+            (!holder.is_empty()).then(|| holder.to_string())
+        })
+}
+
+/// An exclusive lock on a database root, held through a `LOCK` file in it until dropped. The file
+/// names the process holding it, which is what `StoreLocked::holder_hint` reports to the others.
+#[derive(Debug)]
+pub struct DirLock {
+    path: PathBuf,
+    file: File,
+}
+
+impl DirLock {
+    pub fn acquire(dir: impl AsRef<Path>, timeout: Option<Duration>) -> Result<Self> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+
+        let path = dir.join(DIR_LOCK_FILE);
+        let mut file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+
+        lock_file(&file, &path, false, timeout)?;
+
+        file.set_len(0)?;
+        writeln!(file, "pid {}", std::process::id())?;
+        file.sync_all()?;
+
+        Ok(Self { path, file })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for DirLock {
+    fn drop(&mut self) {
+        // the file is left behind, so only its contents say nobody holds it anymore
+        let _ = self.file.set_len(0);
+    }
+}
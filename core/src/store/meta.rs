@@ -6,7 +6,11 @@ use primitives::{
     impl_access_bytes_for_into_bytes_type, into_bytes, ThinIdx,
 };
 
-use crate::{block::Block, object_ids::TableId, store::config::StoreConfig};
+use crate::{
+    block::{Block, BlockMeta},
+    object_ids::TableId,
+    store::config::StoreConfig,
+};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct StoreMeta {
@@ -15,6 +19,8 @@ pub struct StoreMeta {
     pub item_count: usize,
     pub gap_count: usize,
     pub cur_block: ThinIdx,
+    /// High-water mark of any sequence numbers handed out by the owner of the store.
+    pub seq: u64,
     pub config: StoreConfig,
 }
 
@@ -28,6 +34,7 @@ impl Default for StoreMeta {
             item_count: 0,
             gap_count: 0,
             cur_block: ThinIdx::new(0),
+            seq: 0,
             config,
         }
     }
@@ -42,6 +49,7 @@ impl IntoBytes for StoreMeta {
         x.encode(self.item_count)?;
         x.encode(self.gap_count)?;
         x.encode(self.cur_block)?;
+        x.encode(self.seq)?;
         x.encode_bytes(&into_bytes!(self.config, StoreConfig)?)?;
         Ok(())
     }
@@ -54,6 +62,7 @@ impl FromBytes for StoreMeta {
         x.decode(&mut this.item_count)?;
         x.decode(&mut this.gap_count)?;
         x.decode(&mut this.cur_block)?;
+        x.decode(&mut this.seq)?;
         x.delegate(&mut this.config)?;
         Ok(())
     }
@@ -70,15 +79,35 @@ impl StoreMeta {
             item_count: 0,
             gap_count: 0,
             cur_block: ThinIdx::new(0),
+            seq: 0,
             config,
         }
     }
 
+    /// The number of live items, i.e. every slot handed out minus the ones removed since.
+    pub fn len(&self) -> usize {
+        self.item_count - self.gap_count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     pub fn len_as_bytes<T: 'static>(&self) -> usize {
         self.item_count * Block::<T>::SLOT_BYTE_COUNT
     }
 
     pub fn capacity_as_bytes<T: 'static>(&self) -> usize {
-        self.block_count.get() * self.config.block_capacity.get() * Block::<T>::SLOT_BYTE_COUNT
+        self.block_count.get() * self.block_size_as_bytes::<T>()
+    }
+
+    /// The number of bytes a single block occupies in a persisted store, including its meta.
+    pub fn block_size_as_bytes<T: 'static>(&self) -> usize {
+        BlockMeta::BYTE_COUNT + self.config.block_capacity.get() * Block::<T>::SLOT_BYTE_COUNT
+    }
+
+    /// The offset of a block within a persisted store.
+    pub fn block_offset<T: 'static>(&self, index: ThinIdx) -> usize {
+        Self::BYTE_COUNT + index.into_usize() * self.block_size_as_bytes::<T>()
     }
 }
@@ -0,0 +1,523 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    os::unix::fs::FileExt,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::Result;
+use parking_lot::Mutex;
+use primitives::{
+    byte_encoding::{FromBytes, IntoBytes},
+    into_bytes, ThinIdx,
+};
+
+use crate::store::{lock::lock_file, StoreFull, StoreMeta};
+
+/// What a table file starts with.
+const MAGIC: [u8; 8] = *b"DBEXPTBL";
+const VERSION: u32 = 1;
+
+/// The bytes at the start of a table file kept for its superblock. Extents start after it.
+pub const SUPERBLOCK_BYTE_COUNT: usize = 64 * 1024;
+
+/// Regions grow by at least this many bytes at a time, and by at least as much as they already
+/// hold, so a region of any size is made of a handful of extents.
+pub const MIN_EXTENT_BYTE_COUNT: usize = 1 << 20;
+
+/// How many extents a region can be made of.
+pub const MAX_EXTENTS: usize = 48;
+
+/// Extents start on page boundaries.
+const EXTENT_ALIGN: u64 = 4096;
+
+/// A run of whole blocks of one region, somewhere in its table file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Extent {
+    /// Where the extent starts in the table file.
+    pub offset: u64,
+    pub block_count: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+struct RegionEntry {
+    /// The meta of the store in the region, once one has been created there.
+    meta: Option<StoreMeta>,
+    /// The bytes of one block of the region's store, which its extents are counted in. Zero until
+    /// the region first grows.
+    block_size: u64,
+    extents: Vec<Extent>,
+}
+
+impl RegionEntry {
+    fn block_count(&self) -> usize {
+        self.extents.iter().map(|e| e.block_count as usize).sum()
+    }
+}
+
+/// Reads a superblock front to back.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if self.0.len() < n {
+            anyhow::bail!("superblock is truncated");
+        }
+
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+
+        Ok(head)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into()?))
+    }
+}
+
+/// The map at the start of a table file from each region to its store meta and extents.
+#[derive(Debug, Clone)]
+struct Superblock {
+    regions: BTreeMap<u32, RegionEntry>,
+    /// Where the next extent goes, past the end of every extent so far.
+    end: u64,
+}
+
+impl Default for Superblock {
+    fn default() -> Self {
+        Self {
+            regions: BTreeMap::new(),
+            end: SUPERBLOCK_BYTE_COUNT as u64,
+        }
+    }
+}
+
+impl Superblock {
+    fn encode(&self) -> Result<Vec<u8>> {
+        let mut bytes = Vec::with_capacity(SUPERBLOCK_BYTE_COUNT);
+
+        bytes.extend(MAGIC);
+        bytes.extend(VERSION.to_le_bytes());
+        bytes.extend((self.regions.len() as u32).to_le_bytes());
+
+        for (id, entry) in self.regions.iter() {
+            bytes.extend(id.to_le_bytes());
+
+            match entry.meta {
+                Some(meta) => {
+                    bytes.push(1);
+                    bytes.extend(into_bytes!(meta, StoreMeta)?);
+                }
+                None => {
+                    bytes.push(0);
+                    bytes.extend([0u8; StoreMeta::BYTE_COUNT]);
+                }
+            }
+
+            bytes.extend(entry.block_size.to_le_bytes());
+            bytes.extend((entry.extents.len() as u32).to_le_bytes());
+
+            for extent in entry.extents.iter() {
+                bytes.extend(extent.offset.to_le_bytes());
+                bytes.extend(extent.block_count.to_le_bytes());
+            }
+        }
+
+        if bytes.len() > SUPERBLOCK_BYTE_COUNT {
+            anyhow::bail!(
+                "superblock of {} bytes exceeds SUPERBLOCK_BYTE_COUNT ({})",
+                bytes.len(),
+                SUPERBLOCK_BYTE_COUNT
+            );
+        }
+
+        Ok(bytes)
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self> {
+        let mut x = Reader(bytes);
+
+        if x.take(MAGIC.len())? != MAGIC {
+            anyhow::bail!("not a table file");
+        }
+
+        let version = x.u32()?;
+
+        if version != VERSION {
+            anyhow::bail!(
+                "unsupported table file version {}, expected {}",
+                version,
+                VERSION
+            );
+        }
+
+        let mut this = Self::default();
+        let count = x.u32()?;
+
+        for _ in 0..count {
+            let id = x.u32()?;
+            let has_meta = x.take(1)?[0] != 0;
+            let meta_bytes = x.take(StoreMeta::BYTE_COUNT)?;
+            let meta = match has_meta {
+                true => Some(StoreMeta::from_bytes(meta_bytes)?),
+                false => None,
+            };
+
+            let block_size = x.u64()?;
+            let extent_count = x.u32()? as usize;
+
+            if extent_count > MAX_EXTENTS {
+                anyhow::bail!("region {} has {} extents", id, extent_count);
+            }
+
+            let mut extents = Vec::with_capacity(extent_count);
+
+            for _ in 0..extent_count {
+                let offset = x.u64()?;
+                let block_count = x.u64()?;
+                let end = offset + block_count * block_size;
+
+                this.end = this.end.max(end.next_multiple_of(EXTENT_ALIGN));
+                extents.push(Extent {
+                    offset,
+                    block_count,
+                });
+            }
+
+            this.regions.insert(
+                id,
+                RegionEntry {
+                    meta,
+                    block_size,
+                    extents,
+                },
+            );
+        }
+
+        Ok(this)
+    }
+
+    fn read_from(file: &File) -> Result<Self> {
+        if file.metadata()?.len() < SUPERBLOCK_BYTE_COUNT as u64 {
+            anyhow::bail!("file is too small");
+        }
+
+        let mut bytes = vec![0u8; SUPERBLOCK_BYTE_COUNT];
+        file.read_exact_at(&mut bytes, 0)?;
+
+        let this = Self::decode(&bytes)?;
+
+        if file.metadata()?.len() < this.end {
+            anyhow::bail!("file is smaller than its extents");
+        }
+
+        Ok(this)
+    }
+}
+
+/// A single file holding every store of a table, each in a region of its own. The file starts
+/// with a superblock mapping region ids to the meta of their store and the extents their blocks
+/// are in, and regions grow by appending extents at the end of the file. The file is opened and
+/// locked once, however many regions are used.
+#[derive(Debug)]
+pub struct TableFile {
+    path: PathBuf,
+    file: Arc<File>,
+    superblock: Mutex<Superblock>,
+}
+
+impl TableFile {
+    /// Opens the table file at `path`, creating an empty one if there's none, and locks it the way
+    /// a store file is locked.
+    pub fn open(path: impl AsRef<Path>, lock_timeout: Option<Duration>) -> Result<Arc<Self>> {
+        let path = path.as_ref();
+
+        let file = if path.exists() {
+            let file = fs::OpenOptions::new().read(true).write(true).open(path)?;
+            lock_file(&file, path, false, lock_timeout)?;
+            file
+        } else {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+
+            let file = File::create_new(path)?;
+            lock_file(&file, path, false, lock_timeout)?;
+            file.set_len(SUPERBLOCK_BYTE_COUNT as u64)?;
+            file.write_all_at(&Superblock::default().encode()?, 0)?;
+            file
+        };
+
+        let superblock = Superblock::read_from(&file)?;
+
+        Ok(Arc::new(Self {
+            path: path.to_path_buf(),
+            file: Arc::new(file),
+            superblock: Mutex::new(superblock),
+        }))
+    }
+
+    /// The store metas of every region with a store, by region id, read without opening or
+    /// locking the file.
+    pub fn read_metas(path: impl AsRef<Path>) -> Result<BTreeMap<u32, StoreMeta>> {
+        let superblock = Superblock::read_from(&File::open(path)?)?;
+
+        Ok(superblock
+            .regions
+            .into_iter()
+            .filter_map(|(id, entry)| Some((id, entry.meta?)))
+            .collect())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn file(&self) -> &Arc<File> {
+        &self.file
+    }
+
+    /// The region `id`, which stays empty until a store is created in it.
+    pub fn region(self: &Arc<Self>, id: u32) -> Region {
+        Region {
+            file: self.clone(),
+            id,
+        }
+    }
+
+    /// The ids of the regions that have a store in them.
+    pub fn region_ids(&self) -> Vec<u32> {
+        self.superblock
+            .lock()
+            .regions
+            .iter()
+            .filter(|(_, entry)| entry.meta.is_some())
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    pub fn sync_all(&self) -> Result<()> {
+        Ok(self.file.sync_all()?)
+    }
+
+    fn _write_superblock(&self, superblock: &Superblock) -> Result<()> {
+        self.file.write_all_at(&superblock.encode()?, 0)?;
+        Ok(())
+    }
+}
+
+/// The part of a `TableFile` one store is kept in.
+#[derive(Debug, Clone)]
+pub struct Region {
+    file: Arc<TableFile>,
+    id: u32,
+}
+
+impl Region {
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn table_file(&self) -> &Arc<TableFile> {
+        &self.file
+    }
+
+    /// The meta of the store in the region, or `None` if no store was created in it yet.
+    pub fn read_meta(&self) -> Option<StoreMeta> {
+        self.file
+            .superblock
+            .lock()
+            .regions
+            .get(&self.id)
+            .and_then(|entry| entry.meta)
+    }
+
+    pub fn write_meta(&self, meta: &StoreMeta) -> Result<()> {
+        let mut superblock = self.file.superblock.lock();
+
+        superblock.regions.entry(self.id).or_default().meta = Some(*meta);
+        self.file._write_superblock(&superblock)
+    }
+
+    /// How many blocks fit in the region's extents.
+    pub fn block_count(&self) -> usize {
+        self.file
+            .superblock
+            .lock()
+            .regions
+            .get(&self.id)
+            .map_or(0, RegionEntry::block_count)
+    }
+
+    /// Makes room for `block_count` blocks of `block_size` bytes, appending an extent to the end of
+    /// the file if the ones the region has are too small.
+    pub fn grow(&self, block_count: usize, block_size: usize) -> Result<()> {
+        let mut superblock = self.file.superblock.lock();
+        let offset = superblock.end;
+        let entry = superblock.regions.entry(self.id).or_default();
+
+        if entry.block_size == 0 {
+            entry.block_size = block_size as u64;
+        } else if entry.block_size != block_size as u64 {
+            anyhow::bail!(
+                "region {} has blocks of {} bytes, not {}",
+                self.id,
+                entry.block_size,
+                block_size
+            );
+        }
+
+        let held = entry.block_count();
+
+        if held >= block_count {
+            return Ok(());
+        }
+
+        if entry.extents.len() >= MAX_EXTENTS {
+            return Err(StoreFull {
+                reason: format!("region {} exceeds MAX_EXTENTS ({})", self.id, MAX_EXTENTS),
+            }
+            .into());
+        }
+
+        let blocks = (block_count - held)
+            .max(held)
+            .max(MIN_EXTENT_BYTE_COUNT.div_ceil(block_size));
+        let end = (offset + (blocks * block_size) as u64).next_multiple_of(EXTENT_ALIGN);
+
+        self.file.file.set_len(end)?;
+
+        entry.extents.push(Extent {
+            offset,
+            block_count: blocks as u64,
+        });
+        superblock.end = end;
+
+        self.file._write_superblock(&superblock)
+    }
+
+    /// Where the block at `index` starts in the table file.
+    pub fn block_offset(&self, index: ThinIdx, block_size: usize) -> Result<usize> {
+        let superblock = self.file.superblock.lock();
+        let entry = superblock
+            .regions
+            .get(&self.id)
+            .ok_or_else(|| anyhow::anyhow!("region {} has no extents", self.id))?;
+
+        if entry.block_size != block_size as u64 {
+            anyhow::bail!(
+                "region {} has blocks of {} bytes, not {}",
+                self.id,
+                entry.block_size,
+                block_size
+            );
+        }
+
+        let mut index = index.into_usize() as u64;
+
+        for extent in entry.extents.iter() {
+            if index < extent.block_count {
+                return Ok((extent.offset + index * entry.block_size) as usize);
+            }
+
+            index -= extent.block_count;
+        }
+
+        anyhow::bail!("block is past the extents of region {}", self.id)
+    }
+}
+
+/// What a persisted store keeps its meta and blocks in. Blocks are mapped from the file at
+/// whatever offset the backing gives them, so they don't tell the two apart.
+#[derive(Debug, Clone)]
+pub enum Backing {
+    /// A file of the store's own, with the meta at the start and the blocks after it.
+    File(Arc<File>),
+    /// A region of a table file the store shares with the rest of its table.
+    Region(Region),
+}
+
+impl Backing {
+    pub fn file(&self) -> &Arc<File> {
+        match self {
+            Self::File(file) => file,
+            Self::Region(region) => region.file.file(),
+        }
+    }
+
+    pub fn read_meta(&self) -> Result<StoreMeta> {
+        match self {
+            Self::File(file) => {
+                let mut bytes = [0u8; StoreMeta::BYTE_COUNT];
+                file.read_exact_at(&mut bytes, 0)?;
+                StoreMeta::from_bytes(&bytes)
+            }
+            Self::Region(region) => region
+                .read_meta()
+                .ok_or_else(|| anyhow::anyhow!("region {} has no store", region.id)),
+        }
+    }
+
+    pub fn write_meta(&self, meta: &StoreMeta) -> Result<()> {
+        match self {
+            Self::File(file) => Ok(file.write_all_at(&into_bytes!(*meta, StoreMeta)?, 0)?),
+            Self::Region(region) => region.write_meta(meta),
+        }
+    }
+
+    /// Makes room for `block_count` blocks of the store `meta` describes.
+    pub fn grow<T: 'static>(&self, meta: &StoreMeta, block_count: usize) -> Result<()> {
+        let block_size = meta.block_size_as_bytes::<T>();
+
+        match self {
+            Self::File(file) => {
+                let len = (StoreMeta::BYTE_COUNT + block_count * block_size) as u64;
+
+                if file.metadata()?.len() < len {
+                    file.set_len(len)?;
+                }
+
+                Ok(())
+            }
+            Self::Region(region) => region.grow(block_count, block_size),
+        }
+    }
+
+    /// Gives back the bytes of the blocks past `block_count` of a store file. A region keeps its
+    /// extents, which the store grows back into.
+    pub fn shrink<T: 'static>(&self, meta: &StoreMeta, block_count: usize) -> Result<()> {
+        match self {
+            Self::File(file) => {
+                let len =
+                    (StoreMeta::BYTE_COUNT + block_count * meta.block_size_as_bytes::<T>()) as u64;
+
+                if file.metadata()?.len() > len {
+                    file.set_len(len)?;
+                }
+
+                Ok(())
+            }
+            Self::Region(_) => Ok(()),
+        }
+    }
+
+    /// Where the block at `index` starts in the file.
+    pub fn block_offset<T: 'static>(&self, meta: &StoreMeta, index: ThinIdx) -> Result<usize> {
+        match self {
+            Self::File(_) => Ok(meta.block_offset::<T>(index)),
+            Self::Region(region) => region.block_offset(index, meta.block_size_as_bytes::<T>()),
+        }
+    }
+
+    /// Releases the lock on the file. A region's file is shared with the rest of its table, so
+    /// this releases theirs as well.
+    pub fn unlock(&self) -> Result<()> {
+        Ok(self.file().unlock()?)
+    }
+}
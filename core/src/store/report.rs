@@ -0,0 +1,170 @@
+use primitives::InternalString;
+use serde::Serialize;
+
+use crate::store::CorruptBlock;
+
+/// How full one block of a store is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct BlockUtil {
+    pub idx: usize,
+    pub live: usize,
+    /// Slots freed by removals that haven't been reused yet.
+    pub gaps: usize,
+    pub capacity: usize,
+    /// The bytes of every slot not holding a live value, gaps included.
+    pub wasted_bytes: usize,
+}
+
+impl BlockUtil {
+    pub fn utilization(&self) -> f64 {
+        if self.capacity == 0 {
+            0.0
+        } else {
+            self.live as f64 / self.capacity as f64
+        }
+    }
+}
+
+/// How full the loaded blocks of a store are, from `Store::fragmentation_report`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FragReport {
+    /// The label of the store, if it has one.
+    pub label: Option<String>,
+    pub blocks: Vec<BlockUtil>,
+    /// Live slots over the capacity of every block.
+    pub avg_utilization: f64,
+    pub total_wasted_bytes: usize,
+}
+
+impl FragReport {
+    pub fn new(label: Option<InternalString>, blocks: Vec<BlockUtil>) -> Self {
+        let live = blocks.iter().map(|block| block.live).sum::<usize>();
+        let capacity = blocks.iter().map(|block| block.capacity).sum::<usize>();
+
+        Self {
+            label: label.map(|label| label.as_str().to_string()),
+            avg_utilization: if capacity == 0 {
+                0.0
+            } else {
+                live as f64 / capacity as f64
+            },
+            total_wasted_bytes: blocks.iter().map(|block| block.wasted_bytes).sum(),
+            blocks,
+        }
+    }
+
+    pub fn gap_count(&self) -> usize {
+        self.blocks.iter().map(|block| block.gaps).sum()
+    }
+}
+
+impl std::fmt::Display for FragReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(label) = self.label.as_deref() {
+            write!(f, "{}: ", label)?;
+        }
+
+        writeln!(
+            f,
+            "{} blocks, {:.1}% used, {} bytes wasted",
+            self.blocks.len(),
+            self.avg_utilization * 100.0,
+            self.total_wasted_bytes
+        )?;
+
+        for block in self.blocks.iter() {
+            writeln!(
+                f,
+                "  block {:>4}: {:>6} / {:<6} live ({:>5.1}%), {} gaps, {} bytes wasted",
+                block.idx,
+                block.live,
+                block.capacity,
+                block.utilization() * 100.0,
+                block.gaps,
+                block.wasted_bytes
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Where the inserts of a store have put their items since it was opened, from
+/// `Store::allocation_stats`, for tuning its `AllocationPolicy`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct AllocationStats {
+    /// Items that went into the gaps left by removed items.
+    pub gap_reuses: usize,
+    /// Items that went into slots never used before.
+    pub appends: usize,
+    /// Blocks created to make room for inserts, those reserved ahead of a batch included.
+    pub new_blocks: usize,
+}
+
+/// A count kept by a store's metas that disagrees with its slots, from `Store::check_counts`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CountMismatch {
+    /// The block whose meta is off, or `None` for the store meta.
+    pub block: Option<usize>,
+    pub what: &'static str,
+    pub expected: usize,
+    pub found: usize,
+}
+
+impl std::fmt::Display for CountMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.block {
+            Some(block) => write!(f, "block {}: ", block)?,
+            None => write!(f, "store: ")?,
+        }
+
+        write!(
+            f,
+            "{} is {} by the meta but {} by the slots",
+            self.what, self.expected, self.found
+        )
+    }
+}
+
+/// What `Store::scrub` found reading the blocks of a store back from its file.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ScrubReport {
+    /// The label of the store, if it has one.
+    pub label: Option<String>,
+    /// Blocks whose slots match their checksum.
+    pub verified: usize,
+    /// Blocks flushed without a checksum, e.g. by a store opened without them.
+    pub unchecked: usize,
+    /// Blocks written since their last flush, whose checksum in the file is behind their slots.
+    pub unsynced: usize,
+    pub corrupt: Vec<CorruptBlock>,
+}
+
+impl ScrubReport {
+    pub fn is_ok(&self) -> bool {
+        self.corrupt.is_empty()
+    }
+}
+
+impl std::fmt::Display for ScrubReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(label) = self.label.as_deref() {
+            write!(f, "{}: ", label)?;
+        }
+
+        writeln!(
+            f,
+            "{} blocks verified, {} without a checksum, {} unsynced, {} corrupt",
+            self.verified,
+            self.unchecked,
+            self.unsynced,
+            self.corrupt.len()
+        )?;
+
+        for corrupt in self.corrupt.iter() {
+            writeln!(f, "  {}", corrupt)?;
+        }
+
+        Ok(())
+    }
+}
@@ -1,3 +1,8 @@
+use std::path::PathBuf;
+
+use primitives::InternalString;
+use serde::Serialize;
+
 use crate::{object_ids::RecordId, slot::SlotTuple};
 
 #[derive(thiserror::Error)]
@@ -111,6 +116,26 @@ impl std::fmt::Display for BlockCreationError {
     }
 }
 
+/// A persisted store file or database root another process holds a conflicting lock on.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error(
+    "{} is locked by {}",
+    .path.display(),
+    .holder_hint.as_deref().unwrap_or("another process")
+)]
+pub struct StoreLocked {
+    pub path: PathBuf,
+    /// Who holds the lock, when they left a note saying so.
+    pub holder_hint: Option<String>,
+}
+
+/// A store that can't take any more values, e.g. a table file region out of extents.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("{reason}")]
+pub struct StoreFull {
+    pub reason: String,
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum StoreError<T> {
     #[error(transparent)]
@@ -120,15 +145,64 @@ pub enum StoreError<T> {
     #[error("block was not found??? (this should never happen)")]
     BlockNotFound,
     #[error(transparent)]
+    Locked(#[from] StoreLocked),
+    /// An error of a labeled store, see `StoreConfig::label`. The message of the error is kept
+    /// along with it, since the error can't be displayed in terms of itself.
+    #[error("{label}: {message}")]
+    Labeled {
+        label: InternalString,
+        message: String,
+        error: Box<StoreError<T>>,
+    },
+    #[error(transparent)]
     Unexpected(#[from] anyhow::Error),
 }
 
+impl<T> StoreError<T> {
+    /// Names the store the error came from, unless it has no label or the error already names
+    /// one.
+    pub fn labeled(self, label: Option<InternalString>) -> Self {
+        match (label, self) {
+            (_, this @ Self::Labeled { .. }) | (None, this) => this,
+            (Some(label), error) => Self::Labeled {
+                label,
+                message: error.to_string(),
+                error: Box::new(error),
+            },
+        }
+    }
+
+    /// The label of the store the error came from, if it has one.
+    pub fn label(&self) -> Option<InternalString> {
+        match self {
+            Self::Labeled { label, .. } => Some(*label),
+            _ => None,
+        }
+    }
+
+    /// The error without the label of its store, for matching on what went wrong.
+    pub fn into_unlabeled(self) -> Self {
+        match self {
+            Self::Labeled { error, .. } => *error,
+            error => error,
+        }
+    }
+}
+
 impl<T: std::fmt::Debug> StoreError<T> {
     pub fn thread_safe(self) -> anyhow::Error {
         match self {
             Self::BlockCreationError(e) => e.error,
             Self::Unexpected(e) => e,
             Self::BlockNotFound => anyhow::Error::msg(self.to_string()),
+            Self::Locked(e) => e.into(),
+            Self::Labeled { label, error, .. } => {
+                let error = error.thread_safe();
+                let message = format!("{}: {}", label, error);
+
+                // the message is the context, so the error can still be downcast to its type
+                error.context(message)
+            }
             Self::InsertError(e) => {
                 let s = e.to_string();
 
@@ -137,11 +211,11 @@ impl<T: std::fmt::Debug> StoreError<T> {
                     InsertError::TableMismatch { .. } => anyhow::Error::msg(s),
                     InsertError::AlreadyExists { .. } => anyhow::Error::msg(s),
                     InsertError::BlockFull { item, .. } => match item {
-                        Some((record, data)) => anyhow::Error::msg(format!(
-                            "BlockFull: record: {:?}, data: {:?}",
-                            record, data
-                        )),
-                        None => anyhow::Error::msg(s),
+                        Some((record, data)) => StoreFull {
+                            reason: format!("BlockFull: record: {:?}, data: {:?}", record, data),
+                        }
+                        .into(),
+                        None => StoreFull { reason: s }.into(),
                     },
                     InsertError::InvalidValue { .. } => anyhow::Error::msg(s),
                 }
@@ -149,3 +223,33 @@ impl<T: std::fmt::Debug> StoreError<T> {
         }
     }
 }
+
+/// A value read back from a store that isn't what was written, e.g. text that's no longer valid
+/// UTF-8 after its file was damaged or edited by hand.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("slot {slot} of block {block} holds a corrupt value: {reason}")]
+pub struct CorruptValue {
+    pub block: usize,
+    pub slot: usize,
+    pub reason: String,
+}
+
+/// A value that doesn't convert to the type a store is read as, see `Store::collect_as`.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("slot {slot} of block {block}, of record {record}, doesn't convert: {reason}")]
+pub struct UnconvertibleValue {
+    pub record: RecordId,
+    pub block: usize,
+    pub slot: usize,
+    pub reason: String,
+}
+
+/// A persisted block whose slots don't match the checksum flushed with them, e.g. after its file
+/// was damaged. See `StoreConfig::checksum`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, thiserror::Error)]
+#[error("block {idx} fails its checksum: expected {expected:08x}, found {found:08x}")]
+pub struct CorruptBlock {
+    pub idx: usize,
+    pub expected: u32,
+    pub found: u32,
+}
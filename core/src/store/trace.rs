@@ -0,0 +1,486 @@
+//! Records what a store is asked to do, in the order it does it, so a run that leaves the counts
+//! wrong can be replayed single threaded and the first op that went astray found. Only built for
+//! tests, or with the `trace` feature.
+//!
+//! Ops are recorded while the store is write locked, so the trace is the order they took effect
+//! in, whatever threads they came from. Along with each op is what the traced run claims came of
+//! it: the slot an insert landed in, or whether a remove found anything. `replay` runs the ops
+//! against a fresh store and compares each claim, and then the state both stores end up in.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex},
+    thread::{self, ThreadId},
+};
+
+use anyhow::Result;
+use primitives::{InternalPath, ThinIdx};
+
+use crate::{
+    object_ids::{RecordId, TableId, ThinRecordId},
+    slot::{SlotHandle, SlotTuple},
+    store::{inner::StoreInner, InsertState, Store, StoreConfig, StoreError},
+};
+
+/// The block and slot a handle points at.
+pub type SlotLoc = (usize, usize);
+
+pub(crate) fn loc<T: 'static>(handle: &SlotHandle<T>) -> SlotLoc {
+    (handle.block.index().into_usize(), handle.idx.into_usize())
+}
+
+/// The items of a traced batch, handed out with the size hint the batch had.
+struct Hinted<T> {
+    items: std::vec::IntoIter<SlotTuple<T>>,
+    hint: (usize, Option<usize>),
+}
+
+impl<T> Iterator for Hinted<T> {
+    type Item = SlotTuple<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.items.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.hint
+    }
+}
+
+/// The handles of a batch insert, with the index of the item each is for.
+fn inserted<T: 'static>(state: InsertState<T>) -> Vec<(usize, SlotHandle<T>)> {
+    match state {
+        InsertState::Done(handles) => handles.into_iter().enumerate().collect(),
+        InsertState::Partial { handles, .. } => handles,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TraceOp {
+    /// `None` for an insert that failed.
+    Insert {
+        record: Option<RecordId>,
+        slot: Option<SlotLoc>,
+    },
+    /// The records of a batch, and the slot each item landed in, `None` for the ones that failed.
+    /// The size hint of the batch is kept too, since it decides how many blocks are reserved.
+    InsertBatch {
+        hint: (usize, Option<usize>),
+        records: Vec<Option<RecordId>>,
+        slots: Vec<Option<SlotLoc>>,
+    },
+    Remove {
+        slot: SlotLoc,
+        removed: bool,
+    },
+    Truncate {
+        dropped: usize,
+    },
+    Sync,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TracedOp {
+    pub thread: ThreadId,
+    pub op: TraceOp,
+}
+
+impl fmt::Display for TracedOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {:?}", self.thread, self.op)
+    }
+}
+
+/// The ops of a traced store, see `Store::start_trace`.
+#[derive(Debug)]
+pub struct StoreTrace {
+    table: TableId,
+    config: StoreConfig,
+    ops: Mutex<Vec<TracedOp>>,
+}
+
+impl StoreTrace {
+    pub(crate) fn new(table: TableId, config: StoreConfig) -> Self {
+        Self {
+            table,
+            config,
+            ops: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub(crate) fn push(&self, op: TraceOp) {
+        let traced = TracedOp {
+            thread: thread::current().id(),
+            op,
+        };
+
+        self.ops
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .push(traced);
+    }
+
+    /// The ops recorded so far, in the order they took effect.
+    pub fn ops(&self) -> Vec<TracedOp> {
+        self.ops
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+}
+
+/// What a store holds, read from its metas and its slots, for comparing a traced store with its
+/// replay.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoreState {
+    pub item_count: usize,
+    pub gap_count: usize,
+    pub block_count: usize,
+    /// The live slots, with the record each holds, in block and slot order.
+    pub live: Vec<(SlotLoc, Option<ThinRecordId>)>,
+    /// Where the blocks index each record, sorted.
+    pub index: Vec<(ThinRecordId, SlotLoc)>,
+}
+
+impl StoreState {
+    pub fn of<T: 'static>(store: &Store<T>) -> Result<Self> {
+        let inner = store.read();
+        let mut live = Vec::new();
+        let mut index = Vec::new();
+
+        for block in inner.blocks.values() {
+            for handle in block.iter_live() {
+                let record = handle.read_with(|slot| Ok(slot.thin_record_id()))?;
+                live.push((loc(&handle), record));
+            }
+
+            let block_idx = block.index().into_usize();
+
+            block.inner.read_with(|block_inner| {
+                index.extend(
+                    block_inner
+                        .index_by_record
+                        .iter()
+                        .map(|(record, slot)| (*record, (block_idx, slot.into_usize()))),
+                )
+            });
+        }
+
+        index.sort();
+
+        Ok(Self {
+            item_count: inner.meta.item_count,
+            gap_count: inner.meta.gap_count,
+            block_count: inner.meta.block_count.get(),
+            live,
+            index,
+        })
+    }
+}
+
+/// Where a replay first did something other than what the traced run claims.
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("replay diverged at op {op}: {what} was {traced} when traced, {replayed} when replayed")]
+pub struct Divergence {
+    /// The index of the op in the trace, or the length of the trace when only the state the stores
+    /// end up in differs.
+    pub op: usize,
+    pub what: &'static str,
+    pub traced: String,
+    pub replayed: String,
+    /// The fewest ops found that still diverge the same way, see `replay`.
+    pub minimized: Vec<TracedOp>,
+}
+
+impl Divergence {
+    /// The divergence along with its minimized trace, one op per line.
+    pub fn dump(&self) -> String {
+        let mut dump = format!("{}\n", self);
+
+        for (i, op) in self.minimized.iter().enumerate() {
+            dump.push_str(&format!("  {:>4} {}\n", i, op));
+        }
+
+        dump
+    }
+}
+
+/// The first op the replay disagrees with, without its minimized trace.
+struct Diverged {
+    op: usize,
+    what: &'static str,
+    traced: String,
+    replayed: String,
+}
+
+impl Diverged {
+    fn check<V: fmt::Debug + PartialEq>(
+        op: usize,
+        what: &'static str,
+        traced: V,
+        replayed: V,
+    ) -> Result<(), Self> {
+        if traced == replayed {
+            return Ok(());
+        }
+
+        Err(Self {
+            op,
+            what,
+            traced: format!("{:?}", traced),
+            replayed: format!("{:?}", replayed),
+        })
+    }
+
+    fn same_as(&self, other: &Self) -> bool {
+        self.what == other.what && self.traced == other.traced && self.replayed == other.replayed
+    }
+}
+
+/// Runs `ops` one after the other against a fresh memory-only store configured like the traced
+/// one, checking each op comes out as traced. `data` makes the value of each item inserted, from
+/// the index of its op.
+fn _run<T: 'static>(
+    trace: &StoreTrace,
+    ops: &[TracedOp],
+    data: &mut impl FnMut(usize) -> T,
+) -> Result<Store<T>, Diverged> {
+    let config = StoreConfig {
+        persistance: InternalPath::default(),
+        ..trace.config
+    };
+    let unexpected = |op, error: anyhow::Error| Diverged {
+        op,
+        what: "result",
+        traced: "Ok".to_string(),
+        replayed: format!("{:#}", error),
+    };
+    let store = Store::new(Some(trace.table), Some(config)).map_err(|e| unexpected(0, e))?;
+    let mut handles = HashMap::new();
+
+    for (i, traced) in ops.iter().enumerate() {
+        match &traced.op {
+            TraceOp::Insert { record, slot } => {
+                let handle = store.insert_one(*record, data(i)).ok();
+                Diverged::check(i, "insert slot", *slot, handle.as_ref().map(loc))?;
+
+                if let Some(handle) = handle {
+                    handles.insert(loc(&handle), handle);
+                }
+            }
+            TraceOp::InsertBatch {
+                hint,
+                records,
+                slots,
+            } => {
+                let items = records
+                    .iter()
+                    .map(|record| (*record, data(i)))
+                    .collect::<Vec<SlotTuple<T>>>();
+                let mut landed = items.iter().map(|_| None).collect::<Vec<_>>();
+                let items = Hinted {
+                    items: items.into_iter(),
+                    hint: *hint,
+                };
+
+                // a batch that failed as a whole has no slots, traced or replayed
+                if let Ok(state) = store.insert(items) {
+                    for (n, handle) in inserted(state) {
+                        landed[n] = Some(handle);
+                    }
+                }
+
+                let replayed = landed
+                    .iter()
+                    .map(|handle| handle.as_ref().map(loc))
+                    .collect::<Vec<_>>();
+                Diverged::check(i, "batch slots", slots, &replayed)?;
+
+                for handle in landed.into_iter().flatten() {
+                    handles.insert(loc(&handle), handle);
+                }
+            }
+            TraceOp::Remove { slot, removed } => {
+                // a remove that found nothing left nothing to replay
+                if !removed {
+                    continue;
+                }
+
+                let found = handles
+                    .remove(slot)
+                    .and_then(|handle| store.remove(handle))
+                    .is_some();
+                Diverged::check(i, "remove", *removed, found)?;
+            }
+            TraceOp::Truncate { dropped } => {
+                let replayed = store.truncate().map_err(|e| unexpected(i, e))?;
+                handles.clear();
+                Diverged::check(i, "truncated items", *dropped, replayed)?;
+            }
+            TraceOp::Sync => store.sync_all().map_err(|e| unexpected(i, e))?,
+        }
+    }
+
+    Ok(store)
+}
+
+/// Drops each op before the one that diverged in turn, keeping the drop if the shorter trace
+/// still diverges the same way at its last op.
+fn _minimize<T: 'static>(
+    trace: &StoreTrace,
+    ops: &[TracedOp],
+    diverged: &Diverged,
+    data: &mut impl FnMut(usize) -> T,
+) -> Vec<TracedOp> {
+    let mut kept = ops[..=diverged.op].to_vec();
+    let mut i = kept.len() - 1;
+
+    while i > 0 {
+        i -= 1;
+
+        let mut shorter = kept.clone();
+        shorter.remove(i);
+
+        if let Err(again) = _run(trace, &shorter, data) {
+            if again.op == shorter.len() - 1 && again.same_as(diverged) {
+                kept = shorter;
+            }
+        }
+    }
+
+    kept
+}
+
+/// Replays a trace single threaded against a fresh store and compares every op with what the
+/// traced run claims came of it, then the state the replay ends up in with `claimed`, the state of
+/// the traced store, see `StoreState::of`. `data` makes the value of each item inserted, from the
+/// index of its op. Returns the state of the replay if nothing diverged.
+///
+/// When an op diverges, the trace is cut after it and then minimized by dropping the ops before it
+/// that it doesn't need to diverge the same way. A divergence of the end state alone can't be
+/// minimized, and comes with the whole trace.
+pub fn replay<T: 'static>(
+    trace: &StoreTrace,
+    claimed: &StoreState,
+    mut data: impl FnMut(usize) -> T,
+) -> Result<StoreState, Divergence> {
+    let ops = trace.ops();
+
+    let diverged = match _run(trace, &ops, &mut data) {
+        Ok(store) => {
+            let end = ops.len();
+            let replayed = StoreState::of(&store).map_err(|error| Divergence {
+                op: end,
+                what: "end state",
+                traced: format!("{:?}", claimed),
+                replayed: format!("{:#}", error),
+                minimized: ops.clone(),
+            })?;
+
+            let check = Diverged::check(end, "item count", claimed.item_count, replayed.item_count)
+                .and_then(|_| {
+                    Diverged::check(end, "gap count", claimed.gap_count, replayed.gap_count)
+                })
+                .and_then(|_| {
+                    Diverged::check(
+                        end,
+                        "block count",
+                        claimed.block_count,
+                        replayed.block_count,
+                    )
+                })
+                .and_then(|_| Diverged::check(end, "live slots", &claimed.live, &replayed.live))
+                .and_then(|_| Diverged::check(end, "index", &claimed.index, &replayed.index));
+
+            match check {
+                Ok(()) => return Ok(replayed),
+                Err(diverged) => {
+                    return Err(Divergence {
+                        op: diverged.op,
+                        what: diverged.what,
+                        traced: diverged.traced,
+                        replayed: diverged.replayed,
+                        minimized: ops,
+                    })
+                }
+            }
+        }
+        Err(diverged) => diverged,
+    };
+
+    let minimized = _minimize(trace, &ops, &diverged, &mut data);
+
+    Err(Divergence {
+        op: diverged.op,
+        what: diverged.what,
+        traced: diverged.traced,
+        replayed: diverged.replayed,
+        minimized,
+    })
+}
+
+impl<T> Store<T> {
+    /// Starts recording the ops of the store into a `StoreTrace`, see `replay`. The store must not
+    /// have been written to yet, since a replay starts from an empty one.
+    pub fn start_trace(&self) -> Result<Arc<StoreTrace>> {
+        let mut inner = self.write();
+
+        if inner.meta.item_count != 0 || inner.meta.cur_block != ThinIdx::new(0) {
+            anyhow::bail!("only a store that hasn't been written to can be traced");
+        }
+
+        let trace = Arc::new(StoreTrace::new(inner.meta.table, inner.meta.config));
+        inner.trace = Some(trace.clone());
+
+        Ok(trace)
+    }
+
+    /// `_insert_with`, recording the records of the batch as they're taken from `iter` and the
+    /// slots they land in.
+    pub(super) fn _insert_traced<I>(
+        inner: &mut StoreInner<T>,
+        iter: I,
+    ) -> Result<InsertState<T>, StoreError<T>>
+    where
+        I: IntoIterator<Item = SlotTuple<T>> + 'static,
+    {
+        let records = Arc::new(Mutex::new(Vec::new()));
+        let taken = records.clone();
+        let iter = iter.into_iter();
+        let hint = iter.size_hint();
+        let iter = iter.inspect(move |item: &SlotTuple<T>| {
+            taken
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .push(item.0);
+        });
+
+        let res = Self::_insert_with(inner, iter);
+        let records = std::mem::take(&mut *records.lock().unwrap_or_else(|p| p.into_inner()));
+        let mut slots = vec![None; records.len()];
+
+        if let Ok(state) = res.as_ref() {
+            let handles = match state {
+                InsertState::Done(handles) => handles.iter().enumerate().collect::<Vec<_>>(),
+                InsertState::Partial { handles, .. } => {
+                    handles.iter().map(|(n, handle)| (*n, handle)).collect()
+                }
+            };
+
+            for (n, handle) in handles {
+                if let Some(slot) = slots.get_mut(n) {
+                    *slot = Some(loc(handle));
+                }
+            }
+        }
+
+        inner._trace(|| TraceOp::InsertBatch {
+            hint,
+            records,
+            slots,
+        });
+
+        res
+    }
+}
@@ -0,0 +1,47 @@
+//! Fixtures for tests of stores and of the crates built on them. Built for this crate's own tests,
+//! and for other crates with the `testing` feature.
+
+use std::{
+    ops::Deref,
+    path::{Path, PathBuf},
+};
+
+use crate::object_ids::TableId;
+
+/// A fresh directory under the system temp dir, removed with everything in it when dropped, so a
+/// failing test doesn't leave it behind either.
+#[derive(Debug)]
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(prefix: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("{}_{}", prefix, TableId::new()));
+
+        std::fs::create_dir_all(&path).expect("temp dir");
+        Self(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
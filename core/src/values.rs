@@ -1,14 +1,17 @@
 use anyhow::Result;
 
 use crate::{
-    object_ids::TableId,
+    object_ids::{RecordId, TableId},
     slot::SlotHandle,
-    store::{Store, StoreConfig, StoreError},
+    store::{CorruptValue, Store, StoreConfig, StoreError, UnconvertibleValue},
 };
 
-// mod math;
+pub mod cmp;
+pub mod math;
+pub mod plain;
 pub mod value;
 
+pub use math::MathOp;
 pub use value::DataValue;
 
 pub type ValueError = StoreError<DataValue>;
@@ -37,3 +40,85 @@ impl Values {
         Ok(Self(Store::new(table, config)?))
     }
 }
+
+impl Store<DataValue> {
+    /// Reads the value in a slot, checked with `DataValue::read_stored` as the store's
+    /// `trusted_input` says. A value that fails the check is a `CorruptValue`, and a gap is `None`.
+    pub fn read_value(&self, handle: &ValueHandle) -> Result<Option<DataValue>> {
+        let trusted = self.read().meta().config.trusted_input;
+
+        handle.read_with(|slot| {
+            let Some(value) = slot.data() else {
+                return Ok(None);
+            };
+
+            value.read_stored(trusted).map(Some).map_err(|error| {
+                CorruptValue {
+                    block: handle.block.index().into_usize(),
+                    slot: handle.idx.into_thin().into_usize(),
+                    reason: error.to_string(),
+                }
+                .into()
+            })
+        })
+    }
+
+    /// Reads every live value of the store as a `T`, with the record it belongs to, so a column
+    /// can be had as plain Rust values in one go. Values are checked as `read_value` checks them,
+    /// and slots without a record are left out. The first value that doesn't convert fails the
+    /// read with an `UnconvertibleValue` saying where it is.
+    pub fn collect_as<T>(&self) -> Result<Vec<(RecordId, T)>>
+    where
+        T: TryFrom<DataValue>,
+        T::Error: std::fmt::Display,
+    {
+        self.load(..)?;
+
+        let (table, trusted, blocks) = {
+            let inner = self.read();
+            let meta = inner.meta();
+
+            (
+                meta.table,
+                meta.config.trusted_input,
+                inner.blocks().values().cloned().collect::<Vec<_>>(),
+            )
+        };
+
+        let mut collected = Vec::new();
+
+        for block in blocks {
+            let handles = block.iter_live().collect::<Vec<_>>();
+
+            for handle in handles {
+                let parts =
+                    handle.read_with(|slot| Ok(slot.thin_record_id().zip(slot.data().cloned())))?;
+
+                let Some((thin, value)) = parts else {
+                    continue;
+                };
+
+                let record = RecordId::from_thin(thin, table);
+                let block = handle.block.index().into_usize();
+                let slot = handle.idx.into_thin().into_usize();
+
+                let value = value.read_stored(trusted).map_err(|error| CorruptValue {
+                    block,
+                    slot,
+                    reason: error.to_string(),
+                })?;
+
+                let value = T::try_from(value).map_err(|error| UnconvertibleValue {
+                    record,
+                    block,
+                    slot,
+                    reason: error.to_string(),
+                })?;
+
+                collected.push((record, value));
+            }
+        }
+
+        Ok(collected)
+    }
+}
@@ -0,0 +1,193 @@
+//! Comparison between values, for queries to order and filter by. Numbers of any kind compare by
+//! their value, so an integer, an unsigned integer and a float that are the same number are equal,
+//! and an infinity sorts past every finite number. Text and bytes compare by their contents,
+//! timestamps by when they are, and booleans with `false` first. Values of types that have no
+//! order between them, or NaN, can't be compared at all, rather than being given one that means
+//! nothing as the derived `Ord` does; that one is still what orders values kept as map keys.
+
+use std::cmp::Ordering;
+
+use anyhow::Result;
+use primitives::Number;
+
+use super::DataValue;
+
+impl DataValue {
+    /// Compares the value with `other`, see the module docs.
+    pub fn try_cmp(&self, other: &DataValue) -> Result<Ordering> {
+        match (self, other) {
+            (DataValue::Number(a), DataValue::Number(b)) => number_cmp(*a, *b),
+            (DataValue::Timestamp(a), DataValue::Timestamp(b)) => Ok(a.as_i128().cmp(&b.as_i128())),
+            (DataValue::Text(a), DataValue::Text(b)) => Ok(a.as_str().cmp(b.as_str())),
+            (DataValue::Bytes(a), DataValue::Bytes(b)) => Ok(a.as_slice().cmp(b.as_slice())),
+            (DataValue::Bool(a), DataValue::Bool(b)) => Ok(a.cmp(b)),
+            (DataValue::O16(a), DataValue::O16(b)) => Ok(a.cmp(b)),
+            (DataValue::O32(a), DataValue::O32(b)) => Ok(a.cmp(b)),
+            (DataValue::O64(a), DataValue::O64(b)) => Ok(a.cmp(b)),
+            _ => anyhow::bail!(
+                "cannot compare {:?} with {:?}",
+                self.get_type().into_inner(),
+                other.get_type().into_inner()
+            ),
+        }
+    }
+
+    /// Like `try_cmp`, for values that may be missing, as column values may. A missing value
+    /// sorts before every other one, and equals another missing one.
+    pub fn try_cmp_nullable(lhs: Option<&DataValue>, rhs: Option<&DataValue>) -> Result<Ordering> {
+        match (lhs, rhs) {
+            (Some(lhs), Some(rhs)) => lhs.try_cmp(rhs),
+            (None, Some(_)) => Ok(Ordering::Less),
+            (Some(_), None) => Ok(Ordering::Greater),
+            (None, None) => Ok(Ordering::Equal),
+        }
+    }
+}
+
+fn number_cmp(a: Number, b: Number) -> Result<Ordering> {
+    match (a, b) {
+        (Number::NaN, _) | (_, Number::NaN) => {
+            anyhow::bail!("cannot compare {} with {}, NaN has no order", a, b)
+        }
+        (Number::Infinity(x), Number::Infinity(y)) => Ok(x.cmp(&y)),
+        (Number::Infinity(positive), _) => Ok(if positive {
+            Ordering::Greater
+        } else {
+            Ordering::Less
+        }),
+        (_, Number::Infinity(positive)) => Ok(if positive {
+            Ordering::Less
+        } else {
+            Ordering::Greater
+        }),
+        (Number::Float(x), Number::Float(y)) => Ok(x.partial_cmp(&y).unwrap_or(Ordering::Equal)),
+        (Number::Float(x), _) => Ok(float_cmp(x, exact(b))),
+        (_, Number::Float(y)) => Ok(float_cmp(y, exact(a)).reverse()),
+        _ => Ok(exact(a).cmp(&exact(b))),
+    }
+}
+
+/// The value of an integer or unsigned integer, which every one of either fits.
+fn exact(n: Number) -> i128 {
+    match n {
+        Number::Integer(i) => i as i128,
+        Number::Unsigned(u) => u as i128,
+        _ => unreachable!("only integers are exact"),
+    }
+}
+
+/// Orders a finite float against an integer without losing the integer to rounding.
+fn float_cmp(f: f64, i: i128) -> Ordering {
+    // the integer rounds to the float nearest it, so a float on either side of that is on the same
+    // side of the integer, and one equal to it is a whole number small enough to compare exactly
+    match f.partial_cmp(&(i as f64)) {
+        Some(Ordering::Equal) => (f as i128).cmp(&i),
+        ordering => ordering.unwrap_or(Ordering::Equal),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn int(i: i64) -> DataValue {
+        DataValue::Number(Number::Integer(i))
+    }
+
+    fn uint(u: u64) -> DataValue {
+        DataValue::Number(Number::Unsigned(u))
+    }
+
+    fn float(f: f64) -> DataValue {
+        DataValue::Number(Number::Float(f))
+    }
+
+    fn text(s: &str, capacity: usize) -> Result<DataValue> {
+        Ok(DataValue::Text(primitives::Text::try_from_str(
+            s, capacity,
+        )?))
+    }
+
+    #[test]
+    fn test_number_cmp() -> Result<()> {
+        use Ordering::{Equal, Greater, Less};
+
+        let cases = [
+            (int(1), uint(1), Equal),
+            (int(-1), uint(0), Less),
+            (uint(u64::MAX), int(i64::MAX), Greater),
+            (float(1.0), int(1), Equal),
+            (float(-0.0), uint(0), Equal),
+            (float(-0.0), float(0.0), Equal),
+            (float(1.5), uint(1), Greater),
+            (int(-2), float(-1.5), Less),
+            // both round to 2^63 as floats, but only one of them is
+            (float(9_223_372_036_854_775_808.0), int(i64::MAX), Greater),
+            (uint(1 << 63), float(9_223_372_036_854_775_808.0), Equal),
+            (uint(u64::MAX), float(18_446_744_073_709_551_616.0), Less),
+            (
+                DataValue::Number(Number::Infinity(true)),
+                uint(u64::MAX),
+                Greater,
+            ),
+            (
+                float(f64::MIN),
+                DataValue::Number(Number::Infinity(false)),
+                Greater,
+            ),
+        ];
+
+        for (a, b, expected) in cases {
+            assert_eq!(a.try_cmp(&b)?, expected, "{} vs {}", a, b);
+            assert_eq!(b.try_cmp(&a)?, expected.reverse(), "{} vs {}", b, a);
+        }
+
+        assert!(DataValue::Number(Number::NaN).try_cmp(&int(1)).is_err());
+        assert!(float(1.0).try_cmp(&DataValue::Number(Number::NaN)).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_cmp_types() -> Result<()> {
+        let early = DataValue::Timestamp(primitives::Timestamp::try_from_number(1_000i64)?);
+        let late = DataValue::Timestamp(primitives::Timestamp::try_from_number(2_000i64)?);
+
+        assert_eq!(early.try_cmp(&late)?, Ordering::Less);
+
+        // capacity doesn't count, only the contents do
+        assert_eq!(text("b", 4)?.try_cmp(&text("ab", 16)?)?, Ordering::Greater);
+        assert_eq!(text("ab", 4)?.try_cmp(&text("ab", 16)?)?, Ordering::Equal);
+
+        assert_eq!(
+            DataValue::Bool(false).try_cmp(&DataValue::Bool(true))?,
+            Ordering::Less
+        );
+
+        assert!(text("1", 4)?.try_cmp(&int(1)).is_err());
+        assert!(early.try_cmp(&int(1_000)).is_err());
+        assert!(DataValue::Bool(true).try_cmp(&int(1)).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_nullable_cmp() -> Result<()> {
+        assert_eq!(
+            DataValue::try_cmp_nullable(None, Some(&int(i64::MIN)))?,
+            Ordering::Less
+        );
+        assert_eq!(
+            DataValue::try_cmp_nullable(Some(&int(1)), None)?,
+            Ordering::Greater
+        );
+        assert_eq!(DataValue::try_cmp_nullable(None, None)?, Ordering::Equal);
+        assert_eq!(
+            DataValue::try_cmp_nullable(Some(&int(1)), Some(&float(1.0)))?,
+            Ordering::Equal
+        );
+        assert!(DataValue::try_cmp_nullable(Some(&int(1)), Some(&text("1", 4)?)).is_err());
+
+        Ok(())
+    }
+}
@@ -1,107 +1,395 @@
+//! Arithmetic between values. Numbers of any kind combine with each other, and a timestamp moves by
+//! a number of milliseconds. A result is always a valid `Number`: an operation that overflows,
+//! divides by zero or would give NaN or an infinity fails instead, as does one given NaN or an
+//! infinity.
+//!
+//! Integers and unsigned integers are combined exactly. The result is unsigned when both operands
+//! were and it isn't negative, and an integer otherwise, as long as it fits one or the other. A
+//! division of integers stays an integer when it's exact and gives a float when it isn't. Once a
+//! float is involved, the operation is done in floats.
+
 use anyhow::Result;
-use fraction::CheckedAdd;
+use primitives::{DataType, Number, Timestamp};
+
+use super::DataValue;
 
-use crate::{decimal, integer, ratio, timestamp};
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MathOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
 
-use super::{DataType, DataValue};
-use DataType::{
-    Decimal as DecimalTy, Integer as IntegerTy, Ratio as RatioTy, Timestamp as TimestampTy,
-};
-use DataValue::{Decimal, Integer, Ratio, Timestamp};
+impl MathOp {
+    fn verb(self) -> &'static str {
+        match self {
+            MathOp::Add => "add",
+            MathOp::Sub => "subtract",
+            MathOp::Mul => "multiply",
+            MathOp::Div => "divide",
+        }
+    }
+
+    /// Whether a value of type `ty` can be either operand of the operation, with a value of some
+    /// type.
+    fn takes(self, ty: DataType) -> bool {
+        match ty {
+            DataType::Number => true,
+            DataType::Timestamp => matches!(self, MathOp::Add | MathOp::Sub),
+            _ => false,
+        }
+    }
+}
+
+impl std::fmt::Display for MathOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MathOp::Add => write!(f, "+"),
+            MathOp::Sub => write!(f, "-"),
+            MathOp::Mul => write!(f, "*"),
+            MathOp::Div => write!(f, "/"),
+        }
+    }
+}
 
 impl DataValue {
     pub fn try_add(&self, other: &DataValue) -> Result<DataValue> {
-        if self.is_nil() || other.is_nil() {
-            anyhow::bail!("cannot add nil value");
-        }
+        self.try_math(MathOp::Add, other)
+    }
+
+    pub fn try_sub(&self, other: &DataValue) -> Result<DataValue> {
+        self.try_math(MathOp::Sub, other)
+    }
+
+    pub fn try_mul(&self, other: &DataValue) -> Result<DataValue> {
+        self.try_math(MathOp::Mul, other)
+    }
 
+    pub fn try_div(&self, other: &DataValue) -> Result<DataValue> {
+        self.try_math(MathOp::Div, other)
+    }
+
+    /// Applies `op` to the value and `other`, see the module docs. Adding a number to a timestamp,
+    /// or a timestamp to a number, or subtracting one from a timestamp, moves it by that many
+    /// milliseconds, and subtracting two timestamps gives the milliseconds between them.
+    pub fn try_math(&self, op: MathOp, other: &DataValue) -> Result<DataValue> {
         match (self, other) {
-            (Integer(a), b) => b
-                .try_cast(IntegerTy(a.size()))
-                .and_then(|b| {
-                    a.into_inner()
-                        .checked_add(match b {
-                            Integer(b) => b.into_inner(),
-                            _ => unreachable!(),
-                        })
-                        .ok_or_else(|| anyhow::anyhow!("overflow"))
-                })
-                .and_then(integer::Integer::try_from_number)
-                .and_then(|i| i.try_to_fit(a.size()))
-                .map(Integer),
-            (Ratio(a), b) => b
-                .try_cast(RatioTy)
-                .and_then(|b| {
-                    a.into_inner()
-                        .checked_add(&match b {
-                            Ratio(b) => b.into_inner(),
-                            _ => unreachable!(),
-                        })
-                        .ok_or_else(|| anyhow::anyhow!("overflow"))
-                })
-                .map(|r| Ratio(unsafe { ratio::Ratio::from_raw_ratio(r) })),
-            (Decimal(a), b) => b
-                .try_cast(DecimalTy)
-                .and_then(|b| {
-                    a.into_inner()
-                        .checked_add(match b {
-                            Decimal(b) => b.into_inner(),
-                            _ => unreachable!(),
-                        })
-                        .ok_or_else(|| anyhow::anyhow!("overflow"))
-                })
-                .map(|d| Decimal(decimal::Decimal::from_raw_decimal(d))),
-            (Timestamp(a), b) => {
-                let a = a.to_integer() as i128;
-                let b = b.try_cast(TimestampTy).map(|b| match b {
-                    Timestamp(t) => t.to_integer() as i128,
-                    _ => unreachable!(),
-                })?;
-
-                let sum = a
-                    .checked_add(b)
-                    .ok_or_else(|| anyhow::anyhow!("overflow"))?;
-
-                Ok(Timestamp(timestamp::Timestamp::from_integer(
-                    sum.try_into()?,
-                )?))
+            (DataValue::Number(a), DataValue::Number(b)) => {
+                Ok(DataValue::Number(number_math(op, *a, *b)?))
+            }
+            (DataValue::Timestamp(a), DataValue::Number(b))
+                if matches!(op, MathOp::Add | MathOp::Sub) =>
+            {
+                Ok(DataValue::Timestamp(shift(*a, op, *b)?))
+            }
+            (DataValue::Number(a), DataValue::Timestamp(b)) if op == MathOp::Add => {
+                Ok(DataValue::Timestamp(shift(*b, op, *a)?))
+            }
+            (DataValue::Timestamp(a), DataValue::Timestamp(b)) if op == MathOp::Sub => {
+                let millis = i64::try_from(a.as_i128() - b.as_i128())
+                    .map_err(|_| anyhow::anyhow!("{} - {} overflows", a, b))?;
+
+                Ok(DataValue::Number(Number::Integer(millis)))
             }
-            _ => anyhow::bail!("cannot add {:?} and {:?}", self, other),
+            _ => anyhow::bail!(
+                "cannot {} {:?} and {:?}",
+                op.verb(),
+                self.get_type().into_inner(),
+                other.get_type().into_inner()
+            ),
         }
     }
+
+    /// Like `try_math`, for operands that may be missing, as column values may. A missing operand
+    /// gives a missing result, as long as the other one could take part in `op` at all.
+    pub fn try_math_nullable(
+        op: MathOp,
+        lhs: Option<&DataValue>,
+        rhs: Option<&DataValue>,
+    ) -> Result<Option<DataValue>> {
+        match (lhs, rhs) {
+            (Some(lhs), Some(rhs)) => lhs.try_math(op, rhs).map(Some),
+            (Some(value), None) | (None, Some(value)) => {
+                let ty = value.get_type().into_inner();
+
+                if !op.takes(ty) {
+                    anyhow::bail!("cannot {} {:?}", op.verb(), ty);
+                }
+
+                Ok(None)
+            }
+            (None, None) => Ok(None),
+        }
+    }
+}
+
+fn number_math(op: MathOp, a: Number, b: Number) -> Result<Number> {
+    if !a.is_valid() || !b.is_valid() {
+        anyhow::bail!(
+            "cannot {} {} and {}, only finite numbers can be",
+            op.verb(),
+            a,
+            b
+        );
+    }
+
+    if op == MathOp::Div && b.is_zero() {
+        anyhow::bail!("cannot divide {} by zero", a);
+    }
+
+    match (exact(a), exact(b)) {
+        (Some(x), Some(y)) => {
+            let unsigned = matches!((a, b), (Number::Unsigned(_), Number::Unsigned(_)));
+            let result = match op {
+                MathOp::Add => x.checked_add(y),
+                MathOp::Sub => x.checked_sub(y),
+                MathOp::Mul => x.checked_mul(y),
+                MathOp::Div if x % y != 0 => return float_math(op, x as f64, y as f64),
+                MathOp::Div => x.checked_div(y),
+            };
+
+            result
+                .and_then(|result| fit(result, unsigned))
+                .ok_or_else(|| anyhow::anyhow!("{} {} {} overflows", a, op, b))
+        }
+        _ => float_math(op, f64::from(a), f64::from(b)),
+    }
+}
+
+/// The value of an integer or unsigned integer, which every one of either fits.
+fn exact(n: Number) -> Option<i128> {
+    match n {
+        Number::Integer(i) => Some(i as i128),
+        Number::Unsigned(u) => Some(u as i128),
+        _ => None,
+    }
+}
+
+/// The result of an exact operation as a number, unsigned if both operands were and it can be.
+fn fit(n: i128, unsigned: bool) -> Option<Number> {
+    match (unsigned, u64::try_from(n), i64::try_from(n)) {
+        (true, Ok(u), _) => Some(Number::Unsigned(u)),
+        (_, _, Ok(i)) => Some(Number::Integer(i)),
+        (_, Ok(u), _) => Some(Number::Unsigned(u)),
+        _ => None,
+    }
+}
+
+fn float_math(op: MathOp, a: f64, b: f64) -> Result<Number> {
+    let result = match op {
+        MathOp::Add => a + b,
+        MathOp::Sub => a - b,
+        MathOp::Mul => a * b,
+        MathOp::Div => a / b,
+    };
+
+    match Number::from(result) {
+        Number::Float(f) => Ok(Number::Float(f)),
+        _ => anyhow::bail!("{} {} {} is not a finite number", a, op, b),
+    }
+}
+
+/// Moves a timestamp by `millis` milliseconds, which have to be a whole number.
+fn shift(timestamp: Timestamp, op: MathOp, millis: Number) -> Result<Timestamp> {
+    let Some(by) = exact(millis) else {
+        anyhow::bail!(
+            "a timestamp moves by a whole number of milliseconds, not {}",
+            millis
+        );
+    };
+
+    let moved = match op {
+        MathOp::Sub => timestamp.as_i128() - by,
+        _ => timestamp.as_i128() + by,
+    };
+
+    i64::try_from(moved)
+        .map_err(anyhow::Error::from)
+        .and_then(Timestamp::try_from_number)
+        .map_err(|_| anyhow::anyhow!("{} {} {}ms is out of range", timestamp, op, by))
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
 
+    fn int(i: i64) -> DataValue {
+        DataValue::Number(Number::Integer(i))
+    }
+
+    fn uint(u: u64) -> DataValue {
+        DataValue::Number(Number::Unsigned(u))
+    }
+
+    fn float(f: f64) -> DataValue {
+        DataValue::Number(Number::Float(f))
+    }
+
+    fn at(millis: i64) -> Result<DataValue> {
+        Ok(DataValue::Timestamp(Timestamp::try_from_number(millis)?))
+    }
+
+    /// The number a result holds with its kind, since numbers of different kinds compare equal.
+    fn kind(value: &DataValue) -> String {
+        match value {
+            DataValue::Number(n) => format!("{:?}", n),
+            other => format!("{:?}", other),
+        }
+    }
+
     #[test]
-    fn test_addition() -> Result<()> {
-        let a = DataValue::Integer(integer::Integer::try_from_number(1)?);
-        let b = DataValue::Integer(integer::Integer::try_from_number(2)?);
-        let c = a.try_add(&b)?;
-        assert_eq!(c, DataValue::Integer(integer::Integer::try_from_number(3)?));
-
-        let a = DataValue::Ratio(ratio::Ratio::try_from_parts(1, 2)?);
-        let b = DataValue::Ratio(ratio::Ratio::try_from_parts(1, 3)?);
-        let c = a.try_add(&b)?;
-        assert_eq!(c, DataValue::Ratio(ratio::Ratio::try_from_parts(5, 6)?));
-
-        let a = DataValue::Decimal(decimal::Decimal::try_from_number(1.0)?);
-        let b = DataValue::Decimal(decimal::Decimal::try_from_number(2.0)?);
-        let c = a.try_add(&b)?;
+    fn test_number_matrix() -> Result<()> {
+        use MathOp::{Add, Div, Mul, Sub};
+
+        let cases = [
+            // integers with integers
+            (int(2), Add, int(3), int(5)),
+            (int(2), Sub, int(3), int(-1)),
+            (int(-4), Mul, int(3), int(-12)),
+            (int(12), Div, int(-3), int(-4)),
+            (int(7), Div, int(2), float(3.5)),
+            // unsigned with unsigned, falling back to an integer once negative
+            (uint(2), Add, uint(3), uint(5)),
+            (uint(2), Sub, uint(3), int(-1)),
+            (uint(4), Mul, uint(3), uint(12)),
+            (uint(12), Div, uint(4), uint(3)),
+            (uint(1), Div, uint(4), float(0.25)),
+            // integers with unsigned, unsigned only when an integer doesn't fit
+            (int(-2), Add, uint(3), int(1)),
+            (uint(3), Sub, int(5), int(-2)),
+            (uint(u64::MAX), Sub, int(0), uint(u64::MAX)),
+            (int(i64::MAX), Add, uint(1), uint(i64::MAX as u64 + 1)),
+            (int(-6), Div, uint(3), int(-2)),
+            // anything with a float
+            (float(1.5), Add, int(2), float(3.5)),
+            (uint(3), Sub, float(0.5), float(2.5)),
+            (float(0.5), Mul, uint(4), float(2.0)),
+            (int(1), Div, float(4.0), float(0.25)),
+            (float(1.0), Div, float(3.0), float(1.0 / 3.0)),
+        ];
+
+        for (a, op, b, expected) in cases {
+            let result = a.try_math(op, &b)?;
+            assert_eq!(
+                kind(&result),
+                kind(&expected),
+                "{} {} {}",
+                kind(&a),
+                op,
+                kind(&b)
+            );
+        }
+
+        assert_eq!(kind(&int(2).try_add(&int(3))?), kind(&int(5)));
+        assert_eq!(kind(&int(2).try_sub(&int(3))?), kind(&int(-1)));
+        assert_eq!(kind(&int(2).try_mul(&int(3))?), kind(&int(6)));
+        assert_eq!(kind(&int(6).try_div(&int(3))?), kind(&int(2)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_number_errors() {
+        use MathOp::{Add, Div, Mul, Sub};
+
+        let nan = DataValue::Number(Number::NaN);
+        let inf = DataValue::Number(Number::Infinity(true));
+        let neg_inf = DataValue::Number(Number::Infinity(false));
+
+        let cases = [
+            // overflow is an error rather than a float or a wrapped integer
+            (int(i64::MIN), Sub, uint(u64::MAX), "overflows"),
+            (uint(u64::MAX), Add, uint(1), "overflows"),
+            (uint(u64::MAX), Mul, uint(u64::MAX), "overflows"),
+            (int(i64::MIN), Mul, int(i64::MAX), "overflows"),
+            // division by any kind of zero
+            (int(1), Div, int(0), "zero"),
+            (uint(1), Div, uint(0), "zero"),
+            (float(1.0), Div, float(0.0), "zero"),
+            (float(1.0), Div, float(-0.0), "zero"),
+            // results that aren't valid numbers
+            (float(f64::MAX), Add, float(f64::MAX), "finite"),
+            (float(f64::MAX), Mul, int(2), "finite"),
+            (float(f64::MAX), Div, float(0.5), "finite"),
+            // operands that aren't valid numbers
+            (nan.clone(), Add, int(1), "finite"),
+            (int(1), Sub, nan.clone(), "finite"),
+            (inf.clone(), Mul, int(0), "finite"),
+            (float(1.0), Div, neg_inf.clone(), "finite"),
+            (inf.clone(), Sub, inf.clone(), "finite"),
+        ];
+
+        for (a, op, b, expected) in cases {
+            let err = a.try_math(op, &b).unwrap_err();
+            assert!(
+                err.to_string().contains(expected),
+                "{} {} {}: {}",
+                kind(&a),
+                op,
+                kind(&b),
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn test_timestamp_math() -> Result<()> {
+        let day = 24 * 60 * 60 * 1000;
+        let start = at(1_700_000_000_000)?;
+
+        assert_eq!(start.try_add(&int(day))?, at(1_700_000_000_000 + day)?);
         assert_eq!(
-            c,
-            DataValue::Decimal(decimal::Decimal::try_from_number(3.0)?)
+            uint(day as u64).try_add(&start)?,
+            at(1_700_000_000_000 + day)?
         );
+        assert_eq!(start.try_sub(&int(day))?, at(1_700_000_000_000 - day)?);
+        assert_eq!(start.try_add(&int(-day))?, at(1_700_000_000_000 - day)?);
+
+        let later = start.try_add(&int(day))?;
+        assert_eq!(kind(&later.try_sub(&start)?), kind(&int(day)));
+        assert_eq!(kind(&start.try_sub(&later)?), kind(&int(-day)));
+
+        for (a, op, b) in [
+            (start.clone(), MathOp::Add, float(1.5)),
+            (start.clone(), MathOp::Add, int(i64::MAX)),
+            (start.clone(), MathOp::Sub, uint(u64::MAX)),
+            (start.clone(), MathOp::Add, later.clone()),
+            (start.clone(), MathOp::Mul, int(2)),
+            (int(2), MathOp::Sub, start.clone()),
+            (int(2), MathOp::Div, start.clone()),
+        ] {
+            assert!(a.try_math(op, &b).is_err(), "{:?} {} {:?}", a, op, b);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_nullable_math() -> Result<()> {
+        let text = DataValue::Text(primitives::Text::try_from_str("a", 4)?);
 
-        let a = DataValue::Timestamp(timestamp::Timestamp::from_integer(1)?);
-        let b = DataValue::Timestamp(timestamp::Timestamp::from_integer(2)?);
-        let c = a.try_add(&b)?;
         assert_eq!(
-            c,
-            DataValue::Timestamp(timestamp::Timestamp::from_integer(3)?)
+            DataValue::try_math_nullable(MathOp::Add, Some(&int(1)), Some(&int(2)))?,
+            Some(int(3))
         );
+        assert_eq!(
+            DataValue::try_math_nullable(MathOp::Mul, Some(&int(1)), None)?,
+            None
+        );
+        assert_eq!(
+            DataValue::try_math_nullable(MathOp::Sub, None, Some(&at(0)?))?,
+            None
+        );
+        assert_eq!(DataValue::try_math_nullable(MathOp::Div, None, None)?, None);
+
+        // a missing operand doesn't hide one that could never take part
+        assert!(DataValue::try_math_nullable(MathOp::Add, Some(&text), None).is_err());
+        assert!(DataValue::try_math_nullable(MathOp::Mul, None, Some(&at(0)?)).is_err());
+
+        // nor does a present one hide a failure
+        assert!(DataValue::try_math_nullable(MathOp::Div, Some(&int(1)), Some(&int(0))).is_err());
+        assert!(DataValue::try_math_nullable(MathOp::Add, Some(&text), Some(&int(1))).is_err());
 
         Ok(())
     }
@@ -0,0 +1,421 @@
+//! The plain serde form of column values, for handing them to clients rather than storing them.
+//! A missing value is `null`, numbers are numbers, with integers kept apart from floats, text is a
+//! string, bytes are a base64 string, and object ids are their string form. Timestamps are an
+//! RFC 3339 string or the milliseconds since the epoch, as the caller picks. Unlike the tagged form
+//! `DataValue` serializes as, none of this says what type a value has, so reading one back needs
+//! the type of its column, given by `DataValue::deserialize_with_type`.
+
+use primitives::{Bytes, DataType, ExpectedType, Number, Text, Timestamp, O16, O32, O64};
+use serde::{
+    de::{self, DeserializeSeed, IntoDeserializer, Visitor},
+    Deserialize, Serialize, Serializer,
+};
+
+use super::DataValue;
+
+/// How timestamps are written in the plain form. Either is read back, whichever is set.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TimestampFormat {
+    #[default]
+    Rfc3339,
+    Millis,
+}
+
+/// Serializes a value that may be missing in the plain form, see the module docs.
+#[derive(Debug, Clone, Copy)]
+pub struct PlainValue<'a> {
+    pub value: Option<&'a DataValue>,
+    pub timestamps: TimestampFormat,
+}
+
+impl<'a> PlainValue<'a> {
+    pub fn new(value: Option<&'a DataValue>) -> Self {
+        Self {
+            value,
+            timestamps: TimestampFormat::default(),
+        }
+    }
+
+    pub fn with_timestamps(mut self, timestamps: TimestampFormat) -> Self {
+        self.timestamps = timestamps;
+        self
+    }
+}
+
+impl Serialize for PlainValue<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let Some(value) = self.value else {
+            return serializer.serialize_none();
+        };
+
+        match value {
+            DataValue::O16(val) => val.serialize(serializer),
+            DataValue::O32(val) => val.serialize(serializer),
+            DataValue::O64(val) => val.serialize(serializer),
+            DataValue::Bool(val) => serializer.serialize_bool(*val),
+            DataValue::Number(Number::Integer(i)) => serializer.serialize_i64(*i),
+            DataValue::Number(Number::Unsigned(u)) => serializer.serialize_u64(*u),
+            DataValue::Number(Number::Float(f)) => serializer.serialize_f64(*f),
+            // JSON has no number for these, so they're written as `Number` displays them
+            DataValue::Number(val) => serializer.collect_str(val),
+            DataValue::Timestamp(val) => match self.timestamps {
+                TimestampFormat::Rfc3339 => val.serialize(serializer),
+                TimestampFormat::Millis => serializer.serialize_i64(val.timestamp_millis()),
+            },
+            DataValue::Text(val) => serializer.serialize_str(val.as_str()),
+            DataValue::Bytes(val) => serializer.serialize_str(&encode_base64(val.as_slice())),
+        }
+    }
+}
+
+/// Reads a value of the plain form as a value of the type it holds, see
+/// `DataValue::deserialize_with_type`.
+#[derive(Debug, Clone, Copy)]
+pub struct TypedValue(pub ExpectedType);
+
+impl<'de> DeserializeSeed<'de> for TypedValue {
+    type Value = Option<DataValue>;
+
+    fn deserialize<D: de::Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_option(OptionVisitor(self.0))
+    }
+}
+
+impl DataValue {
+    /// Reads a value of type `ty` in the plain form, see the module docs.
+    pub fn deserialize_with_type<'de, D: de::Deserializer<'de>>(
+        ty: ExpectedType,
+        deserializer: D,
+    ) -> Result<Option<DataValue>, D::Error> {
+        TypedValue(ty).deserialize(deserializer)
+    }
+}
+
+struct OptionVisitor(ExpectedType);
+
+impl<'de> Visitor<'de> for OptionVisitor {
+    type Value = Option<DataValue>;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "null or a value of type {:?}", self.0)
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_some<D: de::Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_any(ValueVisitor(self.0)).map(Some)
+    }
+}
+
+struct ValueVisitor(ExpectedType);
+
+impl ValueVisitor {
+    fn mismatch<E: de::Error>(&self, unexpected: de::Unexpected) -> E {
+        E::invalid_type(unexpected, self)
+    }
+
+    fn number<E: de::Error>(&self, number: Number) -> Result<DataValue, E> {
+        match *self.0 {
+            DataType::Number => Ok(DataValue::Number(number)),
+            DataType::Timestamp => Timestamp::try_from_number(number_as_i64(number)?)
+                .map(DataValue::Timestamp)
+                .map_err(E::custom),
+            _ => Err(self.mismatch(de::Unexpected::Other("a number"))),
+        }
+    }
+}
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = DataValue;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "a value of type {:?}", self.0)
+    }
+
+    fn visit_bool<E: de::Error>(self, v: bool) -> Result<Self::Value, E> {
+        match *self.0 {
+            DataType::Bool => Ok(DataValue::Bool(v)),
+            _ => Err(self.mismatch(de::Unexpected::Bool(v))),
+        }
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+        self.number(Number::Integer(v))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+        self.number(Number::Unsigned(v))
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<Self::Value, E> {
+        self.number(Number::from(v))
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        match *self.0 {
+            DataType::O16 => O16::deserialize(v.into_deserializer()).map(DataValue::O16),
+            DataType::O32 => O32::deserialize(v.into_deserializer()).map(DataValue::O32),
+            DataType::O64 => O64::deserialize(v.into_deserializer()).map(DataValue::O64),
+            DataType::Number => match v {
+                "NaN" => Ok(DataValue::Number(Number::NaN)),
+                "Infinity" => Ok(DataValue::Number(Number::Infinity(true))),
+                "-Infinity" => Ok(DataValue::Number(Number::Infinity(false))),
+                _ => Err(self.mismatch(de::Unexpected::Str(v))),
+            },
+            DataType::Timestamp => Timestamp::try_from_str(v)
+                .map(DataValue::Timestamp)
+                .map_err(E::custom),
+            DataType::Text(cap) => Text::try_from_str(v, cap as usize)
+                .map(DataValue::Text)
+                .map_err(E::custom),
+            DataType::Bytes(cap) => {
+                let bytes = decode_base64(v).map_err(E::custom)?;
+
+                Bytes::try_from_slice(&bytes, cap as usize)
+                    .map(DataValue::Bytes)
+                    .map_err(E::custom)
+            }
+            DataType::Bool => Err(self.mismatch(de::Unexpected::Str(v))),
+        }
+    }
+}
+
+/// The milliseconds a number read for a timestamp stands for, which have to be whole.
+fn number_as_i64<E: de::Error>(number: Number) -> Result<i64, E> {
+    match number {
+        Number::Integer(i) => Ok(i),
+        Number::Unsigned(u) => i64::try_from(u).map_err(E::custom),
+        Number::Float(f) if f.fract() == 0.0 && f >= i64::MIN as f64 && f < i64::MAX as f64 => {
+            Ok(f as i64)
+        }
+        _ => Err(E::custom(format!(
+            "{} isn't a whole number of milliseconds",
+            number
+        ))),
+    }
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// The digit of every character of `BASE64`, by character, with `NOT_BASE64` for the rest.
+const BASE64_DIGITS: [u8; 256] = {
+    let mut digits = [NOT_BASE64; 256];
+    let mut digit = 0;
+
+    while digit < BASE64.len() {
+        digits[BASE64[digit] as usize] = digit as u8;
+        digit += 1;
+    }
+
+    digits
+};
+
+const NOT_BASE64: u8 = 0xff;
+
+/// Standard base64, padded.
+fn encode_base64(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, b)| n | ((*b as u32) << (16 - i * 8)));
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64[(n >> (18 - i * 6)) as usize & 0x3f] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+
+    out
+}
+
+/// Reads bytes written in the plain form, which is standard base64, padded.
+pub fn decode_base64(s: &str) -> Result<Vec<u8>, String> {
+    if !s.len().is_multiple_of(4) {
+        return Err(format!("invalid base64 length {}", s.len()));
+    }
+
+    let mut out = Vec::with_capacity(s.len() / 4 * 3);
+    let chunks = s.as_bytes().chunks(4);
+    let last = chunks.len().saturating_sub(1);
+
+    for (index, chunk) in chunks.enumerate() {
+        let padding = chunk.iter().rev().take_while(|c| **c == b'=').count();
+
+        if padding > 2 || (padding > 0 && index != last) {
+            return Err("invalid base64 padding".to_string());
+        }
+
+        let mut n = 0u32;
+
+        for (i, c) in chunk[..4 - padding].iter().enumerate() {
+            let digit = BASE64_DIGITS[*c as usize];
+
+            if digit == NOT_BASE64 {
+                return Err(format!("invalid base64 character {:?}", *c as char));
+            }
+
+            n |= (digit as u32) << (18 - i * 6);
+        }
+
+        out.extend_from_slice(&n.to_be_bytes()[1..4 - padding]);
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use serde_json::json;
+
+    use super::*;
+
+    fn round_trip(ty: DataType, value: Option<DataValue>, json: serde_json::Value) -> Result<()> {
+        for timestamps in [TimestampFormat::Rfc3339, TimestampFormat::Millis] {
+            let plain = PlainValue::new(value.as_ref()).with_timestamps(timestamps);
+            let written = serde_json::to_value(plain)?;
+
+            if timestamps == TimestampFormat::Rfc3339 {
+                assert_eq!(written, json, "{:?}", value);
+            }
+
+            let read = DataValue::deserialize_with_type(ty.into(), written)?;
+            assert_eq!(read, value, "{:?} as {:?}", value, timestamps);
+
+            // through the text too, so the deserializer sees `null` as a missing option
+            let text = serde_json::to_string(&plain)?;
+            let read = TypedValue(ty.into())
+                .deserialize(&mut serde_json::Deserializer::from_str(&text))?;
+            assert_eq!(read, value, "{} as {:?}", text, timestamps);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_round_trip() -> Result<()> {
+        let o16 = O16::new();
+        let o32 = O32::new();
+        let o64 = O64::new();
+        let timestamp = Timestamp::try_from_number(1_700_000_000_123i64)?;
+
+        let cases = [
+            (DataType::O16, DataValue::O16(o16), json!(o16.to_string())),
+            (DataType::O32, DataValue::O32(o32), json!(o32.to_string())),
+            (DataType::O64, DataValue::O64(o64), json!(o64.to_string())),
+            (DataType::Bool, DataValue::Bool(true), json!(true)),
+            (
+                DataType::Number,
+                DataValue::Number(Number::Integer(-5)),
+                json!(-5),
+            ),
+            (
+                DataType::Number,
+                DataValue::Number(Number::Unsigned(u64::MAX)),
+                json!(u64::MAX),
+            ),
+            (
+                DataType::Number,
+                DataValue::Number(Number::Float(1.5)),
+                json!(1.5),
+            ),
+            (
+                DataType::Number,
+                DataValue::Number(Number::Float(2.0)),
+                json!(2.0),
+            ),
+            (
+                DataType::Timestamp,
+                DataValue::Timestamp(timestamp),
+                json!("2023-11-14T22:13:20.123+00:00"),
+            ),
+            (
+                DataType::Text(8),
+                DataValue::Text(Text::try_from_str("café", 8)?),
+                json!("café"),
+            ),
+            (
+                DataType::Bytes(8),
+                DataValue::Bytes(Bytes::try_from_slice(b"\0\xffab", 8)?),
+                json!("AP9hYg=="),
+            ),
+        ];
+
+        for (ty, value, json) in cases {
+            round_trip(ty, Some(value), json)?;
+            round_trip(ty, None, serde_json::Value::Null)?;
+        }
+
+        // the kind of a number survives, not just its value
+        let read = DataValue::deserialize_with_type(DataType::Number.into(), json!(2.0))?;
+        assert!(matches!(read, Some(DataValue::Number(Number::Float(_)))));
+
+        // infinities don't equal themselves, see `Number`, so they're only checked by kind
+        let infinity = DataValue::Number(Number::Infinity(false));
+        let written = serde_json::to_value(PlainValue::new(Some(&infinity)))?;
+        assert_eq!(written, json!("-Infinity"));
+        let read = DataValue::deserialize_with_type(DataType::Number.into(), written)?;
+        assert!(matches!(
+            read,
+            Some(DataValue::Number(Number::Infinity(false)))
+        ));
+
+        let timestamp = DataValue::Timestamp(timestamp);
+        let millis = PlainValue::new(Some(&timestamp)).with_timestamps(TimestampFormat::Millis);
+        assert_eq!(serde_json::to_value(millis)?, json!(1_700_000_000_123i64));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_wrong_type() {
+        let read = |ty: DataType, json: serde_json::Value| {
+            DataValue::deserialize_with_type(ty.into(), json)
+        };
+
+        assert!(read(DataType::Bool, json!(1)).is_err());
+        assert!(read(DataType::Number, json!("1")).is_err());
+        assert!(read(DataType::Text(8), json!(1)).is_err());
+        assert!(read(DataType::Text(2), json!("café")).is_err());
+        assert!(read(DataType::Bytes(8), json!("AP9")).is_err());
+        assert!(read(DataType::Timestamp, json!(1.5)).is_err());
+        assert!(read(DataType::O64, json!([])).is_err());
+    }
+
+    #[test]
+    fn test_base64() -> Result<()> {
+        for len in 0..8usize {
+            let bytes = (0..len).map(|i| (i * 37 + 200) as u8).collect::<Vec<_>>();
+            let encoded = encode_base64(&bytes);
+
+            assert_eq!(encoded.len(), len.div_ceil(3) * 4);
+            assert_eq!(decode_base64(&encoded).map_err(anyhow::Error::msg)?, bytes);
+        }
+
+        assert_eq!(encode_base64(b"foobar"), "Zm9vYmFy");
+        assert_eq!(encode_base64(b"fo"), "Zm8=");
+        assert!(decode_base64("Zm=8").is_err());
+        assert!(decode_base64("Zm8=Zm8=").is_err());
+        assert!(decode_base64("Zm8*").is_err());
+
+        Ok(())
+    }
+}
@@ -1,7 +1,8 @@
 use anyhow::Result;
 
 use primitives::{
-    number::Builtin, Bytes, DataType, ExpectedType, Number, Text, Timestamp, O16, O32, O64,
+    number::Builtin, Bytes, CastKind, DataType, ExpectedType, Number, Text, Timestamp, O16, O32,
+    O64,
 };
 
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -49,6 +50,64 @@ impl std::fmt::Display for DataValue {
     }
 }
 
+/// How a value is written by serde. Text and bytes keep their capacity so the value comes back with
+/// the same type, and numbers keep their kind.
+#[derive(serde::Serialize, serde::Deserialize)]
+enum EncodedValue {
+    O16(O16),
+    O32(O32),
+    O64(O64),
+    Bool(bool),
+    Number(Vec<u8>),
+    Timestamp(Timestamp),
+    Text(u32, String),
+    Bytes(u32, Vec<u8>),
+}
+
+impl serde::Serialize for DataValue {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let encoded = match self {
+            DataValue::O16(val) => EncodedValue::O16(*val),
+            DataValue::O32(val) => EncodedValue::O32(*val),
+            DataValue::O64(val) => EncodedValue::O64(*val),
+            DataValue::Bool(val) => EncodedValue::Bool(*val),
+            DataValue::Number(val) => EncodedValue::Number(val.into_array().to_vec()),
+            DataValue::Timestamp(val) => EncodedValue::Timestamp(*val),
+            DataValue::Text(val) => {
+                EncodedValue::Text(val.capacity() as u32, val.as_str().to_string())
+            }
+            DataValue::Bytes(val) => {
+                EncodedValue::Bytes(val.capacity() as u32, val.as_slice().to_vec())
+            }
+        };
+
+        encoded.serialize(serializer)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for DataValue {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+
+        Ok(match EncodedValue::deserialize(deserializer)? {
+            EncodedValue::O16(val) => DataValue::O16(val),
+            EncodedValue::O32(val) => DataValue::O32(val),
+            EncodedValue::O64(val) => DataValue::O64(val),
+            EncodedValue::Bool(val) => DataValue::Bool(val),
+            EncodedValue::Number(val) => {
+                DataValue::Number(Number::try_from_slice(&val).map_err(D::Error::custom)?)
+            }
+            EncodedValue::Timestamp(val) => DataValue::Timestamp(val),
+            EncodedValue::Text(cap, val) => {
+                DataValue::Text(Text::try_from_str(&val, cap as usize).map_err(D::Error::custom)?)
+            }
+            EncodedValue::Bytes(cap, val) => DataValue::Bytes(
+                Bytes::try_from_slice(&val, cap as usize).map_err(D::Error::custom)?,
+            ),
+        })
+    }
+}
+
 impl PartialOrd<Option<DataValue>> for DataValue {
     fn partial_cmp(&self, other: &Option<DataValue>) -> Option<std::cmp::Ordering> {
         match other {
@@ -229,6 +288,106 @@ impl From<Bytes> for DataValue {
     }
 }
 
+/// Fails for a value that isn't the `expected` kind, naming its type.
+fn mismatch<T>(expected: &str, value: &DataValue) -> Result<T> {
+    anyhow::bail!(
+        "expected {}, found {:?} {}",
+        expected,
+        value.get_type().into_inner(),
+        value
+    )
+}
+
+/// The whole number a number is, which a float is too when it has no fraction.
+fn whole(value: &DataValue) -> Result<i128> {
+    match value {
+        DataValue::Number(Number::Integer(i)) => Ok(*i as i128),
+        DataValue::Number(Number::Unsigned(u)) => Ok(*u as i128),
+        // both bounds are powers of two, so they're exact as floats
+        DataValue::Number(Number::Float(f))
+            if f.fract() == 0.0 && *f >= -(2f64.powi(63)) && *f < 2f64.powi(64) =>
+        {
+            Ok(*f as i128)
+        }
+        DataValue::Number(n) => anyhow::bail!("{} is not a whole number", n),
+        _ => mismatch("a number", value),
+    }
+}
+
+impl TryFrom<DataValue> for i64 {
+    type Error = anyhow::Error;
+
+    fn try_from(value: DataValue) -> Result<Self> {
+        let n = whole(&value)?;
+        i64::try_from(n).map_err(|_| anyhow::anyhow!("{} doesn't fit an i64", n))
+    }
+}
+
+impl TryFrom<DataValue> for u64 {
+    type Error = anyhow::Error;
+
+    fn try_from(value: DataValue) -> Result<Self> {
+        let n = whole(&value)?;
+        u64::try_from(n).map_err(|_| anyhow::anyhow!("{} doesn't fit a u64", n))
+    }
+}
+
+impl TryFrom<DataValue> for f64 {
+    type Error = anyhow::Error;
+
+    fn try_from(value: DataValue) -> Result<Self> {
+        match value {
+            DataValue::Number(n) if n.is_valid() => Ok(f64::from(n)),
+            DataValue::Number(n) => anyhow::bail!("{} is not a finite number", n),
+            _ => mismatch("a number", &value),
+        }
+    }
+}
+
+impl TryFrom<DataValue> for String {
+    type Error = anyhow::Error;
+
+    fn try_from(value: DataValue) -> Result<Self> {
+        match value {
+            DataValue::Text(text) => Ok(text.as_str().to_string()),
+            _ => mismatch("text", &value),
+        }
+    }
+}
+
+impl TryFrom<DataValue> for Vec<u8> {
+    type Error = anyhow::Error;
+
+    fn try_from(value: DataValue) -> Result<Self> {
+        match value {
+            DataValue::Bytes(bytes) => Ok(bytes.as_slice().to_vec()),
+            _ => mismatch("bytes", &value),
+        }
+    }
+}
+
+impl TryFrom<DataValue> for bool {
+    type Error = anyhow::Error;
+
+    fn try_from(value: DataValue) -> Result<Self> {
+        match value {
+            DataValue::Bool(b) => Ok(b),
+            _ => mismatch("a bool", &value),
+        }
+    }
+}
+
+impl TryFrom<DataValue> for Timestamp {
+    type Error = anyhow::Error;
+
+    fn try_from(value: DataValue) -> Result<Self> {
+        match value {
+            DataValue::Timestamp(timestamp) => Ok(timestamp),
+            _ => mismatch("a timestamp", &value),
+        }
+    }
+}
+
 impl DataValue {
     pub fn get_type(&self) -> ExpectedType {
         match self {
@@ -243,6 +402,21 @@ impl DataValue {
         }
     }
 
+    /// About how many bytes of memory the value takes, itself and whatever it allocated, for
+    /// keeping track of how much a query holds.
+    pub fn size_hint(&self) -> usize {
+        use std::mem::size_of;
+
+        let heap = match self {
+            // the text is shared behind an `Arc`, whose counts are allocated along with it
+            DataValue::Text(val) => 2 * size_of::<usize>() + size_of::<Bytes>() + val.capacity(),
+            DataValue::Bytes(val) => val.capacity(),
+            _ => 0,
+        };
+
+        size_of::<Self>() + heap
+    }
+
     #[must_use]
     pub fn write_to(&self, dest: &mut [u8]) -> Result<()> {
         use std::ptr;
@@ -292,6 +466,26 @@ impl DataValue {
         Ok(())
     }
 
+    /// Copies a value read back from a store. Text is rebuilt through `Text::from_stored_bytes`,
+    /// so bytes changed underneath it are caught here rather than trusted by `as_str`, unless the
+    /// store is `trusted`.
+    pub fn read_stored(&self, trusted: bool) -> Result<Self> {
+        let DataValue::Text(text) = self else {
+            return Ok(self.clone());
+        };
+
+        let (bytes, len, cap) = (text.as_bytes(), text.len() as u32, text.capacity());
+
+        let text = if trusted {
+            // SAFETY: the store was opened promising its files are only written through it
+            unsafe { Text::from_stored_bytes_unchecked(bytes, len, cap)? }
+        } else {
+            Text::from_stored_bytes(bytes, len, cap)?
+        };
+
+        Ok(DataValue::Text(text))
+    }
+
     #[must_use]
     pub fn try_integer_from_number<T: Builtin>(x: T) -> Result<Self> {
         Ok(DataValue::Number(Number::try_from_builtin(x)?))
@@ -322,6 +516,32 @@ impl DataValue {
         Ok(std::mem::replace(self, value))
     }
 
+    /// Like `try_from_any`, but a string too long for a text column is cut short to fit it, see
+    /// `Text::try_from_str_truncated`, instead of failing. Returns how many bytes were cut off
+    /// along with the value, which is 0 for every other type and value.
+    pub fn try_from_any_lossy<T: Into<ExpectedType>, V: std::any::Any>(
+        ty: T,
+        value: V,
+    ) -> Result<(Self, usize)> {
+        let expected_ty: ExpectedType = ty.into();
+
+        if let DataType::Text(cap) = expected_ty.into_inner() {
+            let any = &value as &dyn std::any::Any;
+            let s = match (any.downcast_ref::<&str>(), any.downcast_ref::<String>()) {
+                (Some(s), _) => Some(*s),
+                (_, Some(s)) => Some(s.as_str()),
+                _ => None,
+            };
+
+            if let Some(s) = s {
+                let (text, dropped) = Text::try_from_str_truncated(s, cap as usize)?;
+                return Ok((DataValue::Text(text), dropped));
+            }
+        }
+
+        Ok((Self::try_from_any(expected_ty, value)?, 0))
+    }
+
     #[must_use]
     pub fn try_from_any<T: Into<ExpectedType>, V: std::any::Any>(ty: T, value: V) -> Result<Self> {
         let expected_ty: ExpectedType = ty.into();
@@ -534,7 +754,7 @@ impl DataValue {
                 } else if let Some(val) = value.downcast_ref::<Vec<u8>>() {
                     return Ok(DataValue::Text(Text::try_from_slice(&val, cap)?));
                 } else if let Some(val) = value.downcast_ref::<Number>() {
-                    return Ok(DataValue::Text(Text::try_from_str(&val.to_string(), cap)?));
+                    return Ok(DataValue::Text(Text::try_from_number(val, cap)?));
                 } else if let Some(val) = value.downcast_ref::<Timestamp>() {
                     return Ok(DataValue::Text(Text::try_from_i128(val.as_i128(), cap)?));
                 }
@@ -576,6 +796,12 @@ impl DataValue {
             return Ok(self.clone());
         }
 
+        let from = self.get_type().into_inner();
+
+        if from.can_cast_to(ty) == CastKind::Forbidden {
+            anyhow::bail!("cannot cast {:?} to {:?}", from, ty);
+        }
+
         match self {
             Self::Bool(x) => match ty {
                 DataType::Bool => Ok(Self::Bool(*x)),
@@ -584,10 +810,7 @@ impl DataValue {
             Self::Number(x) => match ty {
                 DataType::Bool => Ok(Self::Bool(x.is_zero())),
                 DataType::Number => Ok(Self::Number(*x)),
-                DataType::Text(cap) => Ok(Self::Text(Text::try_from_str(
-                    &x.to_string(),
-                    cap as usize,
-                )?)),
+                DataType::Text(cap) => Ok(Self::Text(Text::try_from_number(x, cap as usize)?)),
                 DataType::Bytes(cap) => Ok(Self::Bytes(Bytes::try_from_slice(
                     &x.to_string().as_bytes(),
                     cap as usize,
@@ -635,3 +858,87 @@ impl DataValue {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A value of `ty` that every cast `can_cast_to` allows from it succeeds for.
+    fn representative(ty: DataType) -> Result<DataValue> {
+        Ok(match ty {
+            DataType::O16 => DataValue::O16(O16::new()),
+            DataType::O32 => DataValue::O32(O32::new()),
+            DataType::O64 => DataValue::O64(O64::new()),
+            DataType::Bool => DataValue::Bool(true),
+            DataType::Number => DataValue::Number(Number::Integer(42)),
+            DataType::Timestamp => {
+                DataValue::Timestamp(Timestamp::try_from_number(1_700_000_000_000i64)?)
+            }
+            DataType::Text(cap) => DataValue::Text(Text::try_from_str("42", cap as usize)?),
+            DataType::Bytes(cap) => DataValue::Bytes(Bytes::try_from_slice(b"42", cap as usize)?),
+        })
+    }
+
+    #[test]
+    fn test_cast_matrix() -> Result<()> {
+        for (from, to, kind) in DataType::cast_matrix() {
+            let value = representative(from)?;
+            let cast = value.try_cast(to);
+
+            match kind {
+                CastKind::Forbidden => {
+                    assert!(cast.is_err(), "{:?} to {:?} is forbidden", from, to)
+                }
+                _ => {
+                    let cast = cast.map_err(|e| e.context(format!("{:?} to {:?}", from, to)))?;
+                    assert_eq!(cast.get_type().into_inner(), to);
+
+                    match kind {
+                        CastKind::Identity => assert_eq!(cast, value),
+                        CastKind::Lossless => assert_eq!(cast.try_cast(from)?, value),
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        // lossy casts are the ones that can fail
+        let long = DataValue::Text(Text::try_from_str(&"x".repeat(40), 64)?);
+        assert_eq!(
+            DataType::Text(64).can_cast_to(DataType::Text(16)),
+            CastKind::Lossy
+        );
+        assert!(long.try_cast(DataType::Text(16)).is_err());
+
+        let invalid = DataValue::Bytes(Bytes::try_from_slice(&[0xff], 16)?);
+        assert_eq!(
+            DataType::Bytes(16).can_cast_to(DataType::Text(64)),
+            CastKind::Lossy
+        );
+        assert!(invalid.try_cast(DataType::Text(64)).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_try_from_any_lossy() -> Result<()> {
+        let (value, dropped) = DataValue::try_from_any_lossy(DataType::Text(3), "naïve")?;
+        assert_eq!(value, DataValue::Text(Text::try_from_str("na", 3)?));
+        assert_eq!(dropped, 4);
+
+        let (value, dropped) =
+            DataValue::try_from_any_lossy(DataType::Text(8), String::from("naïve"))?;
+        assert_eq!(value, DataValue::Text(Text::try_from_str("naïve", 8)?));
+        assert_eq!(dropped, 0);
+
+        // the strict conversion still refuses it, and other types aren't touched
+        assert!(DataValue::try_from_any(DataType::Text(3), "naïve").is_err());
+        assert_eq!(
+            DataValue::try_from_any_lossy(DataType::Number, 7i64)?,
+            (DataValue::Number(Number::Integer(7)), 0)
+        );
+        assert!(DataValue::try_from_any_lossy(DataType::Bytes(2), b"abc".as_slice()).is_err());
+
+        Ok(())
+    }
+}
@@ -44,6 +44,13 @@ impl From<VarcapConfig> for StoreConfig {
             initial_block_count: value.initial_block_count,
             block_capacity: value.block_capacity,
             persistance: value.persistance,
+            lock_fairness: Default::default(),
+            lock_timeout: None,
+            trusted_input: false,
+            label: None,
+            allocation: Default::default(),
+            checksum: false,
+            read_corrupt_blocks: false,
         }
     }
 }
@@ -0,0 +1,237 @@
+//! Runs bound queries over the rows of a table. Rows are pulled from a `QueryIter` one at a time
+//! as they're matched, reading the table a block at a time, so only the rows of one block are held
+//! however many rows match, and a caller that stops early never reads the blocks after it. A `Row`
+//! reads a column only once it's asked for, and the columns the query compared only once.
+//!
+//! A query asking for a bool column to equal `true` or `false`, on its own or alongside other
+//! conditions it has to meet as well, first picks the rows it could match from the column's bitmap,
+//! so the rows it can't are passed over without reading any of their values.
+
+use std::{cell::OnceCell, fmt, sync::Arc};
+
+use dbexp::{
+    block::SequentialBlocks, indices::ColumnIndices, records::RecordHandle, values::DataValue,
+};
+use mem_table::{BoolColumnView, OnCorrupt, Table, TableError, TableRead};
+
+use crate::{CmpOp, Predicate};
+
+/// A row matched by a query, see `execute`.
+pub struct Row {
+    table: Arc<Table>,
+    seq: u64,
+    handle: RecordHandle,
+    /// The values read so far, by column.
+    values: Vec<OnceCell<Option<DataValue>>>,
+}
+
+impl fmt::Debug for Row {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Row")
+            .field("seq", &self.seq)
+            .field("values", &self.values)
+            .finish()
+    }
+}
+
+impl Row {
+    fn new(table: Arc<Table>, seq: u64, handle: RecordHandle) -> Self {
+        let values = (0..table.config().columns.len())
+            .map(|_| OnceCell::new())
+            .collect();
+
+        Self {
+            table,
+            seq,
+            handle,
+            values,
+        }
+    }
+
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    pub fn handle(&self) -> &RecordHandle {
+        &self.handle
+    }
+
+    /// The value of `column`, read from the table the first time it's asked for. A row removed
+    /// before then is `NotFound`.
+    pub fn get(&self, column: usize) -> Result<Option<&DataValue>, TableError> {
+        let cell = self
+            .values
+            .get(column)
+            .ok_or_else(|| TableError::not_found(format!("column {}", column)))?;
+
+        if cell.get().is_none() {
+            let _ = cell.set(self.table.get_value(&self.handle, column)?);
+        }
+
+        Ok(cell.get().and_then(Option::as_ref))
+    }
+
+    /// Like `get`, for the column called `name`.
+    pub fn get_named(&self, name: &str) -> Result<Option<&DataValue>, TableError> {
+        let column = self
+            .table
+            .column_index(name)
+            .ok_or_else(|| TableError::not_found(format!("column {}", name)))?;
+
+        self.get(column)
+    }
+}
+
+/// The rows matched by a query, see `execute`.
+pub struct QueryIter<T> {
+    /// Kept for as long as the query runs, so a snapshot holds for every row it reads.
+    source: T,
+    table: Arc<Table>,
+    predicate: Predicate,
+    blocks: SequentialBlocks<ColumnIndices>,
+    /// The rows of the block being read that weren't checked yet, last first.
+    rows: Vec<(u64, RecordHandle)>,
+    /// The rows the query could match as the bitmaps of its bool columns had them when it started,
+    /// with the last seq inserted by then. Rows inserted since are checked whatever it says.
+    selection: Option<(BoolColumnView, u64)>,
+    blocks_scanned: usize,
+    rows_skipped: usize,
+}
+
+impl<T> fmt::Debug for QueryIter<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("QueryIter")
+            .field("predicate", &self.predicate)
+            .field("blocks_scanned", &self.blocks_scanned)
+            .field("rows_skipped", &self.rows_skipped)
+            .finish()
+    }
+}
+
+impl<T: TableRead> QueryIter<T> {
+    /// How many blocks of rows were read so far.
+    pub fn blocks_scanned(&self) -> usize {
+        self.blocks_scanned
+    }
+
+    /// How many rows were passed over without being read, because the bitmaps of the query's bool
+    /// columns ruled them out.
+    pub fn rows_skipped(&self) -> usize {
+        self.rows_skipped
+    }
+
+    /// Whether the bitmaps of the query's bool columns rule a row out.
+    fn _ruled_out(&self, seq: u64, handle: &RecordHandle) -> bool {
+        match &self.selection {
+            Some((selection, last_seq)) => {
+                seq <= *last_seq && !selection.is_true(self.table.row_position(handle))
+            }
+            None => false,
+        }
+    }
+
+    /// Reads the rows of the next block, `None` once there are no blocks left.
+    fn _next_block(&mut self) -> Option<Result<(), TableError>> {
+        let block = self.blocks.next()?;
+
+        if let Err(err) = self.table.cancellation().check() {
+            return Some(Err(err.into()));
+        }
+
+        self.blocks_scanned += 1;
+        self.source.rows_in_block(&block, &mut self.rows);
+
+        if let Err(err) = self.table.throttle_read(self.rows.len()) {
+            self.rows.clear();
+            return Some(Err(err));
+        }
+
+        self.rows.reverse();
+
+        Some(Ok(()))
+    }
+}
+
+impl<T: TableRead> Iterator for QueryIter<T> {
+    type Item = Result<Row, TableError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let Some((seq, handle)) = self.rows.pop() else {
+                if let Err(err) = self._next_block()? {
+                    return Some(Err(err));
+                }
+
+                continue;
+            };
+
+            if self._ruled_out(seq, &handle) {
+                self.rows_skipped += 1;
+                continue;
+            }
+
+            let row = Row::new(self.table.clone(), seq, handle);
+            let matched = self.predicate.try_matches(&mut |column| row.get(column));
+
+            match matched {
+                Ok(true) => return Some(Ok(row)),
+                Ok(false) => {}
+                // a row removed since its block was read is left out
+                Err(TableError::NotFound(_)) => {}
+                Err(TableError::Corrupt(_))
+                    if self.table.config().on_corrupt == OnCorrupt::Skip => {}
+                Err(err) => return Some(Err(err)),
+            }
+        }
+    }
+}
+
+/// Runs `predicate` over the rows of `source`, a table or a snapshot of one, handing out the rows
+/// that match as they're found. Rows come in the order they're stored, which is insertion order
+/// until removed rows have their slots reused. The iterator holds no lock between rows, so writes
+/// carry on while it's read, and dropping it early is all it takes to stop the query. Rows with a
+/// corrupt value fail the query or are left out, as the table's `on_corrupt` says. A query over a
+/// cancelled table fails when it gets to its next block, and the table's rate limiter is asked
+/// before the rows of each block are read.
+///
+/// The rows ruled out by the bitmaps of the bool columns the query compares are picked when it
+/// starts, so a row updated since may be left out by the value it had then.
+pub fn execute<T: TableRead>(source: T, predicate: Predicate) -> QueryIter<T> {
+    let table = Arc::new(source.table().clone());
+    let last_seq = table.current_seq();
+    let selection = bool_selection(&table, &predicate).map(|selection| (selection, last_seq));
+    let blocks = table.scan_row_blocks();
+
+    QueryIter {
+        source,
+        table,
+        predicate,
+        blocks,
+        rows: Vec::new(),
+        selection,
+        blocks_scanned: 0,
+        rows_skipped: 0,
+    }
+}
+
+/// The rows matching every comparison of a bool column with `true` or `false` that a row has to
+/// match for `predicate` to, from the bitmaps of those columns. `None` for a predicate without one.
+fn bool_selection(table: &Table, predicate: &Predicate) -> Option<BoolColumnView> {
+    match predicate {
+        Predicate::And(left, right) => {
+            match (bool_selection(table, left), bool_selection(table, right)) {
+                (Some(left), Some(right)) => Some(left.and(&right)),
+                (left, right) => left.or(right),
+            }
+        }
+        Predicate::Compare {
+            column,
+            op: CmpOp::Eq,
+            value: Some(DataValue::Bool(value)),
+        } => table
+            .bool_view(*column)
+            .ok()
+            .map(|view| view.where_eq(*value)),
+        _ => None,
+    }
+}
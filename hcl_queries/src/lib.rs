@@ -1,14 +1,517 @@
-pub fn add(left: usize, right: usize) -> usize {
-    left + right
-}
+//! Queries over the rows of a table, written as HCL expressions such as
+//! `age > ${min_age} && status == ${status}`. A query is prepared once against the table's columns
+//! with `prepare`, and bound to the values of its parameters each time it's run with `execute`.
+
+pub use execute::{execute, QueryIter, Row};
+pub use predicate::{CmpOp, Predicate};
+pub use prepare::{
+    prepare, BindError, Param, PrepareError, PreparedQuery, QueryColumn, QuerySchema,
+};
+
+mod execute;
+mod parse;
+mod predicate;
+mod prepare;
 
 #[cfg(test)]
 mod tests {
+    use std::{
+        alloc::{GlobalAlloc, Layout, System},
+        cell::Cell,
+    };
+
+    use anyhow::Result;
+    use dbexp::{object_ids::TableId, values::DataValue};
+    use hcl_schemas::{parse_hcl, TableDef};
+    use mem_table::{DataConfig, InsertState, Table, TableConfig, TableError};
+    use primitives::{DataType, ExpectedType, InternalString};
+
     use super::*;
 
+    thread_local! {
+        static LIVE_BYTES: Cell<isize> = const { Cell::new(0) };
+    }
+
+    /// Counts the bytes each thread allocated and hasn't freed yet, so a test can tell how much
+    /// it holds on to whatever the tests running next to it do.
+    struct CountingAlloc;
+
+    unsafe impl GlobalAlloc for CountingAlloc {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let _ = LIVE_BYTES.try_with(|live| live.set(live.get() + layout.size() as isize));
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            let _ = LIVE_BYTES.try_with(|live| live.set(live.get() - layout.size() as isize));
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOC: CountingAlloc = CountingAlloc;
+
+    fn live_bytes() -> isize {
+        LIVE_BYTES.with(Cell::get)
+    }
+
+    fn users() -> Result<TableDef> {
+        let input = r#"
+            table "users" {
+                age    = Number
+                status = Text(8)
+                admin  = Bool
+            }
+        "#;
+
+        Ok(parse_hcl(input)?.remove(0))
+    }
+
+    fn number(n: i64) -> Result<DataValue> {
+        DataValue::try_from_any(DataType::Number, n)
+    }
+
+    fn text(s: &str) -> Result<DataValue> {
+        DataValue::try_from_any(DataType::Text(s.len() as u32), s.to_string())
+    }
+
+    fn row(age: i64, status: &str, admin: bool) -> Result<Vec<Option<DataValue>>> {
+        Ok(vec![
+            Some(number(age)?),
+            Some(DataValue::try_from_any(
+                DataType::Text(8),
+                status.to_string(),
+            )?),
+            Some(DataValue::Bool(admin)),
+        ])
+    }
+
+    #[test]
+    fn test_prepare_and_bind() -> Result<()> {
+        let query = prepare(
+            &users()?,
+            "age > ${min_age} && (status == ${status} || !(admin == false))",
+        )?;
+
+        assert_eq!(
+            query
+                .params()
+                .iter()
+                .map(|param| param.name.as_str())
+                .collect::<Vec<_>>(),
+            ["min_age", "status"]
+        );
+
+        let predicate = query.bind(&[("min_age", number(30)?), ("status", text("active")?)])?;
+
+        assert!(predicate.matches(&row(40, "active", false)?));
+        assert!(predicate.matches(&row(40, "gone", true)?));
+        assert!(!predicate.matches(&row(40, "gone", false)?));
+        assert!(!predicate.matches(&row(20, "active", true)?));
+
+        // the same query bound again doesn't need preparing again
+        let predicate = query.bind(&[("status", text("gone")?), ("min_age", number(10)?)])?;
+        assert!(predicate.matches(&row(20, "gone", false)?));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_literals_and_nulls() -> Result<()> {
+        let table = users()?;
+
+        let predicate = prepare(&table, r#"18 <= age && status != "x""#)?.bind(&[])?;
+        assert!(predicate.matches(&row(18, "y", false)?));
+        assert!(!predicate.matches(&row(17, "y", false)?));
+
+        // an empty string is a value like any other
+        let predicate = prepare(&table, r#"status == """#)?.bind(&[])?;
+        assert!(predicate.matches(&row(1, "", false)?));
+        assert!(!predicate.matches(&row(1, "y", false)?));
+
+        let predicate = prepare(&table, "status == null")?.bind(&[])?;
+        assert!(predicate.matches(&[Some(number(1)?), None, None]));
+        assert!(!predicate.matches(&row(1, "y", false)?));
+
+        Ok(())
+    }
+
+    fn people() -> Result<(TableDef, Table)> {
+        let input = r#"
+            table "people" {
+                email = Text(16, { trim = true, lowercase = true })
+            }
+        "#;
+        let def = parse_hcl(input)?.remove(0);
+        let names = [(InternalString::new("email")?, 0)];
+        let table = Table::new(
+            TableId::new(),
+            def.table_config()?,
+            Some(names.into_iter().collect()),
+        )?;
+
+        // stored as `foo@x.com`
+        table.insert_one(vec![Some(text(" Foo@X.com ")?)])?;
+        table.insert_one(vec![Some(text("bar@x.com")?)])?;
+
+        Ok((def, table))
+    }
+
+    fn emails(table: &Table, predicate: Predicate) -> Result<Vec<Option<DataValue>>> {
+        execute(table.clone(), predicate)
+            .map(|row| Ok(row?.get(0)?.cloned()))
+            .collect()
+    }
+
+    #[test]
+    fn test_raw_literals() -> Result<()> {
+        let (def, table) = people()?;
+
+        // a bare column is compared with literals and parameters as they are
+        for query in [
+            prepare(&def, r#"email == "Foo@X.com ""#)?.bind(&[])?,
+            prepare(&table, r#"email == "Foo@X.com ""#)?.bind(&[])?,
+            prepare(&table, "email == ${email}")?.bind(&[("email", text("  FOO@x.com")?)])?,
+        ] {
+            assert!(emails(&table, query)?.is_empty());
+        }
+
+        let query = prepare(&table, r#"email == "foo@x.com""#)?.bind(&[])?;
+        assert_eq!(emails(&table, query)?, [Some(text("foo@x.com")?)]);
+
+        Ok(())
+    }
+
     #[test]
-    fn it_works() {
-        let result = add(2, 2);
-        assert_eq!(result, 4);
+    fn test_normalized_helper() -> Result<()> {
+        let (def, table) = people()?;
+
+        // `normalized(col)` normalizes them like the column's writes, whether the query is
+        // prepared against the definition or the open table, and on either side
+        for query in [
+            prepare(&def, r#"normalized(email) == "Foo@X.com ""#)?.bind(&[])?,
+            prepare(&table, r#"" Foo@X.com" == normalized(email)"#)?.bind(&[])?,
+            prepare(&table, "normalized(email) == ${email}")?
+                .bind(&[("email", text("  FOO@x.com")?)])?,
+        ] {
+            assert_eq!(emails(&table, query)?, [Some(text("foo@x.com")?)]);
+        }
+
+        // a value only fits the column once trimmed, as on insert
+        let padded = format!("{:^20}", "BAR@x.com");
+        let query = prepare(&table, &format!("normalized(email) == {:?}", padded))?;
+        assert_eq!(
+            emails(&table, query.bind(&[])?)?,
+            [Some(text("bar@x.com")?)]
+        );
+        assert!(matches!(
+            prepare(&table, &format!("email == {:?}", padded)),
+            Err(PrepareError::InvalidLiteral { .. })
+        ));
+
+        let query = prepare(&table, "normalized(email) == ${email}")?;
+        assert_eq!(
+            emails(&table, query.bind(&[("email", text(&padded)?)])?)?,
+            [Some(text("bar@x.com")?)]
+        );
+
+        assert!(matches!(
+            prepare(&table, "normalized(email == 1"),
+            Err(PrepareError::Syntax { at: 11, .. })
+        ));
+        assert_eq!(
+            prepare(&table, "email == ${e} || normalized(email) == ${e}"),
+            Err(PrepareError::MixedNormalization {
+                param: "e".to_string()
+            })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_mixed_numbers() -> Result<()> {
+        let query = prepare(&users()?, "age > ${min_age}")?;
+
+        let predicate = query.bind(&[(
+            "min_age",
+            DataValue::Number(primitives::Number::Float(17.5)),
+        )])?;
+        assert!(predicate.matches(&row(18, "y", false)?));
+        assert!(!predicate.matches(&row(17, "y", false)?));
+
+        // an unsigned row compares with an integer by value
+        let predicate = prepare(&users()?, "age == 18")?.bind(&[])?;
+        let mut unsigned = row(0, "y", false)?;
+        unsigned[0] = Some(DataValue::Number(primitives::Number::Unsigned(18)));
+        assert!(predicate.matches(&unsigned));
+
+        // and NaN compares with nothing
+        unsigned[0] = Some(DataValue::Number(primitives::Number::NaN));
+        assert!(!predicate.matches(&unsigned));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_prepare_errors() -> Result<()> {
+        let table = users()?;
+
+        assert!(matches!(
+            prepare(&table, "age >"),
+            Err(PrepareError::Syntax { at: 5, .. })
+        ));
+        assert_eq!(
+            prepare(&table, "height > 1"),
+            Err(PrepareError::UnknownColumn {
+                column: "height".to_string()
+            })
+        );
+        assert_eq!(
+            prepare(&table, "age == status"),
+            Err(PrepareError::NoColumn { at: 0 })
+        );
+        assert!(matches!(
+            prepare(&table, "age == \"old\""),
+            Err(PrepareError::InvalidLiteral { .. })
+        ));
+        assert_eq!(
+            prepare(&table, "age == ${x} || status == ${x}"),
+            Err(PrepareError::ConflictingParam {
+                param: "x".to_string(),
+                first: ExpectedType::new(DataType::Number),
+                second: ExpectedType::new(DataType::Text(8)),
+            })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bind_errors() -> Result<()> {
+        let query = prepare(&users()?, "age > ${min_age} && status == ${status}")?;
+        let param = |name: &str| name.to_string();
+
+        assert_eq!(
+            query.bind(&[("min_age", number(1)?)]),
+            Err(BindError::Missing {
+                param: param("status")
+            })
+        );
+        assert_eq!(
+            query.bind(&[
+                ("min_age", number(1)?),
+                ("status", text("a")?),
+                ("limit", number(1)?)
+            ]),
+            Err(BindError::Extra {
+                param: param("limit")
+            })
+        );
+        assert_eq!(
+            query.bind(&[("min_age", number(1)?), ("min_age", number(2)?)]),
+            Err(BindError::Duplicate {
+                param: param("min_age")
+            })
+        );
+        assert_eq!(
+            query.bind(&[("min_age", text("1")?), ("status", text("a")?)]),
+            Err(BindError::Mismatched {
+                param: param("min_age"),
+                expected: ExpectedType::new(DataType::Number),
+                found: ExpectedType::new(DataType::Text(1)),
+            })
+        );
+        assert_eq!(
+            query.bind(&[("min_age", number(1)?), ("status", text("much too long")?)]),
+            Err(BindError::TooLong {
+                param: param("status"),
+                len: 13,
+                cap: 8,
+            })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_execute_streams() -> Result<()> {
+        const ROWS: usize = 500_000;
+
+        let columns = vec![
+            DataConfig::new(DataType::Number),
+            DataConfig::new(DataType::Text(40)),
+        ];
+        let names = [
+            (InternalString::new("n")?, 0),
+            (InternalString::new("email")?, 1),
+        ];
+        let table = Table::new(
+            TableId::new(),
+            TableConfig::new(&columns)?,
+            Some(names.into_iter().collect()),
+        )?;
+        let block_capacity = table.config().block_capacity.get();
+        let email = |n: usize| {
+            DataValue::try_from_any(DataType::Text(40), format!("user{}@example.com", n))
+        };
+
+        for start in (0..ROWS).step_by(10_000) {
+            let rows = (start..start + 10_000)
+                .map(|n| Ok(vec![Some(number(n as i64)?), Some(email(n)?)]))
+                .collect::<Result<Vec<_>>>()?;
+            assert!(matches!(table.insert(rows)?, InsertState::Done(_)));
+        }
+
+        let query = prepare(&table, "n >= ${min}")?;
+
+        // the first match is in the block of row 1000, and nothing after the tenth is read
+        let mut rows = execute(table.snapshot(), query.bind(&[("min", number(1000)?)])?);
+
+        for (n, row) in (1000..).zip(rows.by_ref().take(10)) {
+            let row = row?;
+
+            assert_eq!(row.seq(), n as u64 + 1);
+            assert_eq!(row.get(0)?, Some(&number(n)?));
+            assert_eq!(row.get_named("email")?, Some(&email(n as usize)?));
+        }
+
+        assert_eq!(rows.blocks_scanned(), 1000 / block_capacity + 1);
+        assert!(rows.blocks_scanned() < ROWS.div_ceil(block_capacity));
+
+        // the snapshot leaves out what was inserted since the iterator was made
+        table.insert_one(vec![Some(number(ROWS as i64)?), Some(email(ROWS)?)])?;
+        assert_eq!(rows.count(), ROWS - 1000 - 10);
+
+        // going through every row holds on to a block of them at a time, not to all of them
+        let every = query.bind(&[("min", number(0)?)])?;
+        let start = live_bytes();
+        let mut peak = 0;
+        let mut count = 0;
+
+        for row in execute(table.clone(), every) {
+            row?.get_named("email")?;
+            count += 1;
+            peak = peak.max(live_bytes() - start);
+        }
+
+        assert_eq!(count, ROWS + 1);
+        assert!(peak < 1 << 20, "{} bytes held", peak);
+
+        Ok(())
+    }
+
+    /// A table like `users` with `rows` rows, every `admin_every`th of them an admin and every
+    /// thirteenth without a status or admin value.
+    fn user_table(rows: usize, admin_every: usize) -> Result<Table> {
+        let columns = vec![
+            DataConfig::new(DataType::Number),
+            DataConfig::new(DataType::Text(8)),
+            DataConfig::new(DataType::Bool),
+        ];
+        let names = [
+            (InternalString::new("age")?, 0),
+            (InternalString::new("status")?, 1),
+            (InternalString::new("admin")?, 2),
+        ];
+        let table = Table::new(
+            TableId::new(),
+            TableConfig::new(&columns)?,
+            Some(names.into_iter().collect()),
+        )?;
+        let status = ["active", "gone"];
+
+        for start in (0..rows).step_by(10_000) {
+            let rows = (start..rows.min(start + 10_000))
+                .map(|n| match n % 13 {
+                    0 => Ok(vec![Some(number(n as i64 % 90)?)]),
+                    _ => row(n as i64 % 90, status[n % 2], n % admin_every == 0),
+                })
+                .collect::<Result<Vec<_>>>()?;
+            assert!(matches!(table.insert(rows)?, InsertState::Done(_)));
+        }
+
+        Ok(table)
+    }
+
+    /// The seqs of the rows of `table` matching `predicate`, checked a row at a time.
+    fn naive_matches(table: &Table, predicate: &Predicate) -> Result<Vec<u64>> {
+        let mut seqs = Vec::new();
+
+        for (seq, handle) in table.scan_since(0) {
+            if predicate.matches(&table.get_versioned(&handle)?.0) {
+                seqs.push(seq);
+            }
+        }
+
+        Ok(seqs)
+    }
+
+    #[test]
+    fn test_bool_selection() -> Result<()> {
+        let table = user_table(20_000, 10)?;
+
+        for (query, skipped) in [
+            ("admin == true && age > 40", true),
+            ("status == \"gone\" && (admin == false && age < 10)", true),
+            ("admin == true || age > 40", false),
+            ("!(admin == true)", false),
+        ] {
+            let predicate = prepare(&table, query)?.bind(&[])?;
+            let mut rows = execute(table.clone(), predicate.clone());
+            let seqs = rows
+                .by_ref()
+                .map(|row| Ok(row?.seq()))
+                .collect::<Result<Vec<_>, TableError>>()?;
+
+            assert_eq!(seqs, naive_matches(&table, &predicate)?, "{}", query);
+            assert_eq!(rows.rows_skipped() > 0, skipped, "{}", query);
+        }
+
+        // rows inserted after the query started are checked whatever the bitmap had
+        let predicate = prepare(&table, "admin == true")?.bind(&[])?;
+        let admins = naive_matches(&table, &predicate)?.len();
+        let mut rows = execute(table.clone(), predicate);
+
+        rows.next().transpose()?;
+        table.insert_one(row(1, "active", true)?)?;
+        assert_eq!(rows.count(), admins);
+
+        Ok(())
+    }
+
+    /// A coarse comparison of a query picking its rows from the bitmap of a bool column with the
+    /// same query checked a row at a time, for running by hand with
+    /// `cargo test --release -p hcl_queries bench_bool_selection -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_bool_selection() -> Result<()> {
+        let table = user_table(1_000_000, 100)?;
+        let predicate = prepare(&table, "admin == true && age > 40")?.bind(&[])?;
+
+        let started = std::time::Instant::now();
+        let naive = naive_matches(&table, &predicate)?;
+        let row_at_a_time = started.elapsed();
+
+        let started = std::time::Instant::now();
+        let mut rows = execute(table.clone(), predicate);
+        let selected = rows
+            .by_ref()
+            .map(|row| Ok(row?.seq()))
+            .collect::<Result<Vec<_>, TableError>>()?;
+        let bitmap = started.elapsed();
+
+        println!(
+            "{} rows matched, {:?} a row at a time, {:?} from the bitmap with {} rows skipped",
+            naive.len(),
+            row_at_a_time,
+            bitmap,
+            rows.rows_skipped()
+        );
+
+        assert_eq!(selected, naive);
+        assert!(bitmap < row_at_a_time);
+
+        Ok(())
     }
 }
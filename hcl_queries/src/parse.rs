@@ -0,0 +1,322 @@
+//! Turns the text of a query into an `Expr`. The grammar is a small part of HCL's expression
+//! syntax:
+//!
+//! ```text
+//! expr    = and ( "||" and )*
+//! and     = unary ( "&&" unary )*
+//! unary   = "!" unary | "(" expr ")" | operand op operand
+//! operand = column | "normalized(" column ")" | literal | "${" param "}"
+//! op      = "==" | "!=" | "<" | "<=" | ">" | ">="
+//! ```
+//!
+//! where columns and params are identifiers, and literals are numbers, double quoted strings,
+//! `true`, `false` and `null`. A column wrapped in `normalized(..)` is compared with its literal or
+//! parameter normalized the way the column's writes are; a bare column is compared with it as is.
+
+use crate::{predicate::CmpOp, prepare::PrepareError};
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Literal {
+    Null,
+    Bool(bool),
+    Integer(i128),
+    Float(f64),
+    Text(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Operand {
+    Column(String),
+    /// `normalized(column)`
+    Normalized(String),
+    Literal(Literal),
+    Param(String),
+}
+
+impl Operand {
+    /// The name of the column this operand is, and whether it's wrapped in `normalized(..)`, or
+    /// the operand back if it isn't a column.
+    pub(crate) fn into_column(self) -> Result<(String, bool), Self> {
+        match self {
+            Operand::Column(name) => Ok((name, false)),
+            Operand::Normalized(name) => Ok((name, true)),
+            other => Err(other),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare {
+        left: Operand,
+        op: CmpOp,
+        right: Operand,
+        /// Where the comparison starts in the text, for errors.
+        at: usize,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Param(String),
+    Literal(Literal),
+    Op(CmpOp),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+fn syntax(at: usize, message: impl Into<String>) -> PrepareError {
+    PrepareError::Syntax {
+        at,
+        message: message.into(),
+    }
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_'
+}
+
+fn is_ident(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Splits `text` into tokens, each with the byte offset it starts at.
+fn tokenize(text: &str) -> Result<Vec<(usize, Token)>, PrepareError> {
+    let mut tokens = Vec::new();
+    let mut chars = text.char_indices().peekable();
+
+    while let Some(&(at, c)) = chars.peek() {
+        let rest = &text[at..];
+        let two = |s: &str| rest.starts_with(s);
+
+        let (len, token) = if c.is_whitespace() {
+            chars.next();
+            continue;
+        } else if two("&&") {
+            (2, Token::And)
+        } else if two("||") {
+            (2, Token::Or)
+        } else if two("==") {
+            (2, Token::Op(CmpOp::Eq))
+        } else if two("!=") {
+            (2, Token::Op(CmpOp::Ne))
+        } else if two("<=") {
+            (2, Token::Op(CmpOp::Le))
+        } else if two(">=") {
+            (2, Token::Op(CmpOp::Ge))
+        } else if c == '<' {
+            (1, Token::Op(CmpOp::Lt))
+        } else if c == '>' {
+            (1, Token::Op(CmpOp::Gt))
+        } else if c == '!' {
+            (1, Token::Not)
+        } else if c == '(' {
+            (1, Token::Open)
+        } else if c == ')' {
+            (1, Token::Close)
+        } else if two("${") {
+            let end = rest
+                .find('}')
+                .ok_or_else(|| syntax(at, "parameter isn't closed with }"))?;
+            let name = rest[2..end].trim();
+
+            if !name.starts_with(is_ident_start) || !name.chars().all(is_ident) {
+                return Err(syntax(at, format!("invalid parameter name {:?}", name)));
+            }
+
+            (end + 1, Token::Param(name.to_string()))
+        } else if c == '"' {
+            let (len, text) = lex_string(rest).ok_or_else(|| syntax(at, "string isn't closed"))?;
+
+            (len, Token::Literal(Literal::Text(text)))
+        } else if c.is_ascii_digit()
+            || (c == '-' && rest[1..].starts_with(|c: char| c.is_ascii_digit()))
+        {
+            let len = 1 + rest[1..]
+                .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '_'))
+                .unwrap_or(rest.len() - 1);
+            let number = rest[..len].replace('_', "");
+            let literal = if number.contains('.') {
+                number.parse().map(Literal::Float).ok()
+            } else {
+                number.parse().map(Literal::Integer).ok()
+            };
+
+            (
+                len,
+                Token::Literal(
+                    literal
+                        .ok_or_else(|| syntax(at, format!("invalid number {}", &rest[..len])))?,
+                ),
+            )
+        } else if is_ident_start(c) {
+            let len = rest.find(|c| !is_ident(c)).unwrap_or(rest.len());
+            let token = match &rest[..len] {
+                "true" => Token::Literal(Literal::Bool(true)),
+                "false" => Token::Literal(Literal::Bool(false)),
+                "null" => Token::Literal(Literal::Null),
+                ident => Token::Ident(ident.to_string()),
+            };
+
+            (len, token)
+        } else {
+            return Err(syntax(at, format!("unexpected {:?}", c)));
+        };
+
+        tokens.push((at, token));
+
+        while chars.peek().is_some_and(|&(i, _)| i < at + len) {
+            chars.next();
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Reads the double quoted string `rest` starts with, returning how many bytes it takes up and
+/// the string it stands for.
+fn lex_string(rest: &str) -> Option<(usize, String)> {
+    let mut text = String::new();
+    let mut chars = rest.char_indices().skip(1);
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Some((i + 1, text)),
+            '\\' => match chars.next()?.1 {
+                'n' => text.push('\n'),
+                't' => text.push('\t'),
+                c => text.push(c),
+            },
+            c => text.push(c),
+        }
+    }
+
+    None
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    /// Where the text ends, for errors about running out of tokens.
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, token)| token)
+    }
+
+    fn at(&self) -> usize {
+        self.tokens.get(self.pos).map_or(self.end, |(at, _)| *at)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).map(|(_, token)| token.clone());
+        self.pos += 1;
+        token
+    }
+
+    fn or(&mut self) -> Result<Expr, PrepareError> {
+        let mut expr = self.and()?;
+
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, PrepareError> {
+        let mut expr = self.unary()?;
+
+        while self.peek() == Some(&Token::And) {
+            self.next();
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, PrepareError> {
+        match self.peek() {
+            Some(Token::Not) => {
+                self.next();
+                Ok(Expr::Not(Box::new(self.unary()?)))
+            }
+            Some(Token::Open) => {
+                self.next();
+                let expr = self.or()?;
+
+                match self.next() {
+                    Some(Token::Close) => Ok(expr),
+                    _ => Err(syntax(self.at(), "expected )")),
+                }
+            }
+            _ => self.compare(),
+        }
+    }
+
+    fn compare(&mut self) -> Result<Expr, PrepareError> {
+        let at = self.at();
+        let left = self.operand()?;
+        let op = match self.next() {
+            Some(Token::Op(op)) => op,
+            _ => return Err(syntax(at, "expected a comparison")),
+        };
+        let right = self.operand()?;
+
+        Ok(Expr::Compare {
+            left,
+            op,
+            right,
+            at,
+        })
+    }
+
+    fn operand(&mut self) -> Result<Operand, PrepareError> {
+        let at = self.at();
+
+        match self.next() {
+            Some(Token::Ident(name))
+                if name == "normalized" && self.peek() == Some(&Token::Open) =>
+            {
+                self.next();
+                let at = self.at();
+
+                match (self.next(), self.next()) {
+                    (Some(Token::Ident(column)), Some(Token::Close)) => {
+                        Ok(Operand::Normalized(column))
+                    }
+                    _ => Err(syntax(at, "normalized(..) takes the name of a column")),
+                }
+            }
+            Some(Token::Ident(name)) => Ok(Operand::Column(name)),
+            Some(Token::Param(name)) => Ok(Operand::Param(name)),
+            Some(Token::Literal(literal)) => Ok(Operand::Literal(literal)),
+            _ => Err(syntax(at, "expected a column, a literal or a parameter")),
+        }
+    }
+}
+
+pub(crate) fn parse(text: &str) -> Result<Expr, PrepareError> {
+    let mut parser = Parser {
+        tokens: tokenize(text)?,
+        pos: 0,
+        end: text.len(),
+    };
+    let expr = parser.or()?;
+
+    if parser.peek().is_some() {
+        return Err(syntax(parser.at(), "unexpected input after the query"));
+    }
+
+    Ok(expr)
+}
@@ -0,0 +1,92 @@
+use std::{cmp::Ordering, convert::Infallible};
+
+use dbexp::values::DataValue;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl CmpOp {
+    /// The operator that gives the same answer with its sides swapped, so `1 < age` can be
+    /// checked as `age > 1`.
+    pub fn flip(self) -> Self {
+        match self {
+            Self::Eq | Self::Ne => self,
+            Self::Lt => Self::Gt,
+            Self::Le => Self::Ge,
+            Self::Gt => Self::Lt,
+            Self::Ge => Self::Le,
+        }
+    }
+
+    fn holds(self, ord: Ordering) -> bool {
+        match self {
+            Self::Eq => ord.is_eq(),
+            Self::Ne => ord.is_ne(),
+            Self::Lt => ord.is_lt(),
+            Self::Le => ord.is_le(),
+            Self::Gt => ord.is_gt(),
+            Self::Ge => ord.is_ge(),
+        }
+    }
+}
+
+/// A query with every parameter bound, ready to be checked against rows.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Predicate {
+    And(Box<Predicate>, Box<Predicate>),
+    Or(Box<Predicate>, Box<Predicate>),
+    Not(Box<Predicate>),
+    /// Compares the value of `column` with `value`. A `None` value is `null`, which only `==` and
+    /// `!=` compare with, and which only an empty column equals. Values are compared with
+    /// `DataValue::try_cmp`, and ones it can't compare don't match.
+    Compare {
+        column: usize,
+        op: CmpOp,
+        value: Option<DataValue>,
+    },
+}
+
+impl Predicate {
+    /// Whether a row, given as every value of the table's columns in order, matches.
+    pub fn matches(&self, row: &[Option<DataValue>]) -> bool {
+        let mut value =
+            |column: usize| Ok::<_, Infallible>(row.get(column).and_then(Option::as_ref));
+
+        match self.try_matches(&mut value) {
+            Ok(matches) => matches,
+            Err(never) => match never {},
+        }
+    }
+
+    /// Like `matches`, reading the value of a column with `value` only once a comparison needs
+    /// it, so the columns of a row that aren't compared, or are only compared after the answer is
+    /// known, are never read.
+    pub fn try_matches<'a, E>(
+        &self,
+        value: &mut impl FnMut(usize) -> Result<Option<&'a DataValue>, E>,
+    ) -> Result<bool, E> {
+        Ok(match self {
+            Self::And(left, right) => left.try_matches(value)? && right.try_matches(value)?,
+            Self::Or(left, right) => left.try_matches(value)? || right.try_matches(value)?,
+            Self::Not(inner) => !inner.try_matches(value)?,
+            Self::Compare {
+                column,
+                op,
+                value: other,
+            } => match (value(*column)?, other) {
+                (Some(cell), Some(other)) => cell.try_cmp(other).is_ok_and(|ord| op.holds(ord)),
+                (None, None) => *op == CmpOp::Eq,
+                (Some(_), None) | (None, Some(_)) => *op == CmpOp::Ne,
+            },
+        })
+    }
+}
@@ -0,0 +1,386 @@
+use std::mem;
+
+use dbexp::values::DataValue;
+use hcl_schemas::TableDef;
+use mem_table::{DataConfig, Table};
+use primitives::{DataType, ExpectedType};
+use serde::Serialize;
+
+use crate::{
+    parse::{parse, Expr, Literal, Operand},
+    predicate::{CmpOp, Predicate},
+};
+
+/// Why a query can't be prepared against a table.
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum PrepareError {
+    #[error("syntax error at {at}: {message}")]
+    Syntax { at: usize, message: String },
+    #[error("unknown column {column}")]
+    UnknownColumn { column: String },
+    /// Both sides of a comparison are columns, or neither is.
+    #[error("comparison at {at} has to compare one column with a literal or a parameter")]
+    NoColumn { at: usize },
+    #[error("{column} expects {expected:?}: {message}")]
+    InvalidLiteral {
+        column: String,
+        expected: ExpectedType,
+        message: String,
+    },
+    #[error("parameter {param} is compared with both {first:?} and {second:?}")]
+    ConflictingParam {
+        param: String,
+        first: ExpectedType,
+        second: ExpectedType,
+    },
+    /// A parameter is compared with a column in one place and with `normalized(..)` of one in
+    /// another, so it can't tell whether to normalize its value.
+    #[error("parameter {param} is compared with both a column and a normalized one")]
+    MixedNormalization { param: String },
+}
+
+/// Why parameters can't be bound to a prepared query.
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum BindError {
+    #[error("parameter {param} is missing")]
+    Missing { param: String },
+    #[error("the query has no parameter {param}")]
+    Extra { param: String },
+    #[error("parameter {param} is bound more than once")]
+    Duplicate { param: String },
+    #[error("parameter {param} expects {expected:?} but got {found:?}")]
+    Mismatched {
+        param: String,
+        expected: ExpectedType,
+        found: ExpectedType,
+    },
+    #[error("parameter {param} is {len} bytes long but its column holds at most {cap}")]
+    TooLong {
+        param: String,
+        len: usize,
+        cap: usize,
+    },
+    /// A value sent as JSON that can't be turned into the type its parameter expects.
+    #[error("parameter {param} expects {expected:?}: {message}")]
+    Unparsable {
+        param: String,
+        expected: ExpectedType,
+        message: String,
+    },
+}
+
+/// What a query needs to know about a column it compares against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueryColumn {
+    pub index: usize,
+    pub data_type: DataType,
+    /// Text compared with `normalized(column)` is normalized with `DataConfig::normalized`, as the
+    /// column's writes are. Text compared with the bare column is left as it is.
+    pub config: DataConfig,
+}
+
+/// Looks up the columns a query names, so queries can be prepared against the definition of a
+/// table as well as an open one.
+pub trait QuerySchema {
+    fn query_column(&self, name: &str) -> Option<QueryColumn>;
+}
+
+impl QuerySchema for TableDef {
+    fn query_column(&self, name: &str) -> Option<QueryColumn> {
+        let index = self
+            .columns()
+            .iter()
+            .position(|column| column.name().as_str() == name)?;
+        let column = &self.columns()[index];
+
+        Some(QueryColumn {
+            index,
+            data_type: column.data_type(),
+            config: column.data_config(),
+        })
+    }
+}
+
+impl QuerySchema for Table {
+    fn query_column(&self, name: &str) -> Option<QueryColumn> {
+        let index = self.column_index(name)?;
+        let config = self.config().columns.get(index)?;
+
+        Some(QueryColumn {
+            index,
+            data_type: config.data_type.into_inner(),
+            config: *config,
+        })
+    }
+}
+
+/// A parameter of a prepared query, typed by the column it's compared with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Param {
+    pub name: String,
+    pub column: QueryColumn,
+    /// Whether it's compared with `normalized(column)`, so its value is normalized when bound.
+    pub normalized: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Slot {
+    Value(Option<DataValue>),
+    /// The index of the parameter in `PreparedQuery::params`.
+    Param(usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+    Not(Box<Node>),
+    Compare {
+        column: usize,
+        op: CmpOp,
+        slot: Slot,
+    },
+}
+
+/// A query parsed and type checked once, to be bound to parameters as many times as needed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PreparedQuery {
+    text: String,
+    root: Node,
+    params: Vec<Param>,
+}
+
+/// Parses `text` and checks it against the columns of `table`, see the `parse` module for the
+/// grammar. Every `${name}` in it becomes a parameter of the type of the column it's compared
+/// with, so user input is bound to it instead of being pasted into the text.
+pub fn prepare(table: &impl QuerySchema, text: &str) -> Result<PreparedQuery, PrepareError> {
+    let mut params = Vec::new();
+    let root = compile(table, parse(text)?, &mut params)?;
+
+    Ok(PreparedQuery {
+        text: text.to_string(),
+        root,
+        params,
+    })
+}
+
+fn compile(
+    table: &impl QuerySchema,
+    expr: Expr,
+    params: &mut Vec<Param>,
+) -> Result<Node, PrepareError> {
+    Ok(match expr {
+        Expr::And(left, right) => Node::And(
+            Box::new(compile(table, *left, params)?),
+            Box::new(compile(table, *right, params)?),
+        ),
+        Expr::Or(left, right) => Node::Or(
+            Box::new(compile(table, *left, params)?),
+            Box::new(compile(table, *right, params)?),
+        ),
+        Expr::Not(inner) => Node::Not(Box::new(compile(table, *inner, params)?)),
+        Expr::Compare {
+            left,
+            op,
+            right,
+            at,
+        } => {
+            let ((name, normalized), op, other) = match (left.into_column(), right.into_column()) {
+                (Ok(column), Err(other)) => (column, op, other),
+                (Err(other), Ok(column)) => (column, op.flip(), other),
+                _ => return Err(PrepareError::NoColumn { at }),
+            };
+            let column = table
+                .query_column(&name)
+                .ok_or_else(|| PrepareError::UnknownColumn {
+                    column: name.clone(),
+                })?;
+            let slot = match other {
+                Operand::Column(_) | Operand::Normalized(_) => {
+                    return Err(PrepareError::NoColumn { at })
+                }
+                Operand::Literal(literal) => {
+                    Slot::Value(literal_value(&name, column, normalized, literal)?)
+                }
+                Operand::Param(param) => Slot::Param(add_param(params, param, column, normalized)?),
+            };
+
+            Node::Compare {
+                column: column.index,
+                op,
+                slot,
+            }
+        }
+    })
+}
+
+/// Adds a parameter, or finds it if it's already there and compared with a column of the same
+/// type, normalized the same way.
+fn add_param(
+    params: &mut Vec<Param>,
+    name: String,
+    column: QueryColumn,
+    normalized: bool,
+) -> Result<usize, PrepareError> {
+    match params.iter().position(|param| param.name == name) {
+        Some(i) if params[i].column.data_type != column.data_type => {
+            Err(PrepareError::ConflictingParam {
+                param: name,
+                first: params[i].column.data_type.into(),
+                second: column.data_type.into(),
+            })
+        }
+        Some(i) if params[i].normalized != normalized => {
+            Err(PrepareError::MixedNormalization { param: name })
+        }
+        Some(i) => Ok(i),
+        None => {
+            params.push(Param {
+                name,
+                column,
+                normalized,
+            });
+            Ok(params.len() - 1)
+        }
+    }
+}
+
+fn literal_value(
+    name: &str,
+    column: QueryColumn,
+    normalized: bool,
+    literal: Literal,
+) -> Result<Option<DataValue>, PrepareError> {
+    let ty = column.data_type;
+    let value = match (literal, ty) {
+        (Literal::Null, _) => return Ok(None),
+        (Literal::Bool(val), DataType::Bool) => DataValue::try_from_any(ty, val),
+        (Literal::Integer(val), DataType::Number | DataType::Timestamp) => {
+            DataValue::try_from_any(ty, val)
+        }
+        (Literal::Float(val), DataType::Number) => DataValue::try_from_any(ty, val),
+        (Literal::Text(val), DataType::Text(_)) if normalized => {
+            DataValue::try_from_any(ty, column.config.normalize.apply(&val).into_owned())
+        }
+        (Literal::Text(val), DataType::Text(_) | DataType::Timestamp) => {
+            DataValue::try_from_any(ty, val)
+        }
+        (literal, _) => Err(anyhow::anyhow!("can't be compared with {:?}", literal)),
+    };
+
+    value.map(Some).map_err(|err| PrepareError::InvalidLiteral {
+        column: name.to_string(),
+        expected: ty.into(),
+        message: err.to_string(),
+    })
+}
+
+impl PreparedQuery {
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// The parameters in the order they first appear in the text.
+    pub fn params(&self) -> &[Param] {
+        &self.params
+    }
+
+    /// Binds a value to every parameter, checking that each is of its column's type and fits in
+    /// it. A parameter left out, bound twice or not in the query at all is an error too.
+    pub fn bind(&self, values: &[(&str, DataValue)]) -> Result<Predicate, BindError> {
+        let mut bound = vec![None; self.params.len()];
+
+        for (name, value) in values {
+            let i = self
+                .params
+                .iter()
+                .position(|param| param.name == *name)
+                .ok_or_else(|| BindError::Extra {
+                    param: name.to_string(),
+                })?;
+
+            if bound[i].is_some() {
+                return Err(BindError::Duplicate {
+                    param: name.to_string(),
+                });
+            }
+
+            bound[i] = Some(bind_value(&self.params[i], value.clone())?);
+        }
+
+        let bound = bound
+            .into_iter()
+            .zip(&self.params)
+            .map(|(value, param)| {
+                value.ok_or_else(|| BindError::Missing {
+                    param: param.name.clone(),
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(build(&self.root, &bound))
+    }
+}
+
+fn bind_value(param: &Param, value: DataValue) -> Result<DataValue, BindError> {
+    let expected = param.column.data_type;
+    let found = value.get_type().into_inner();
+
+    if mem::discriminant(&expected) != mem::discriminant(&found) {
+        return Err(BindError::Mismatched {
+            param: param.name.clone(),
+            expected: expected.into(),
+            found: found.into(),
+        });
+    }
+
+    let too_long = |len: usize, cap: u32| BindError::TooLong {
+        param: param.name.clone(),
+        len,
+        cap: cap as usize,
+    };
+
+    match (&value, expected) {
+        (DataValue::Text(_), DataType::Text(cap)) if param.normalized => {
+            let normalized = param.column.config.normalized(value);
+
+            return match &normalized {
+                DataValue::Text(text) if text.len() > cap as usize => {
+                    Err(too_long(text.len(), cap))
+                }
+                _ => Ok(normalized),
+            };
+        }
+        (DataValue::Text(text), DataType::Text(cap)) if text.len() > cap as usize => {
+            return Err(too_long(text.len(), cap));
+        }
+        (DataValue::Bytes(bytes), DataType::Bytes(cap)) if bytes.len() > cap as usize => {
+            return Err(too_long(bytes.len(), cap));
+        }
+        _ => {}
+    }
+
+    Ok(value)
+}
+
+fn build(node: &Node, bound: &[DataValue]) -> Predicate {
+    match node {
+        Node::And(left, right) => {
+            Predicate::And(Box::new(build(left, bound)), Box::new(build(right, bound)))
+        }
+        Node::Or(left, right) => {
+            Predicate::Or(Box::new(build(left, bound)), Box::new(build(right, bound)))
+        }
+        Node::Not(inner) => Predicate::Not(Box::new(build(inner, bound))),
+        Node::Compare { column, op, slot } => Predicate::Compare {
+            column: *column,
+            op: *op,
+            value: match slot {
+                Slot::Value(value) => value.clone(),
+                Slot::Param(i) => Some(bound[*i].clone()),
+            },
+        },
+    }
+}
@@ -0,0 +1,219 @@
+//! A record of the operations that change a database's tables rather than their rows, for telling
+//! who changed what and when. Each operation appends an entry saying it's about to happen before
+//! it runs, and one saying how it went once it's done, so an operation that never finished still
+//! shows up as an intent without an outcome. The entries are rows of a small table of their own,
+//! persisted under the database root and flushed as they're appended.
+
+use std::{
+    fs,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{Context, Result};
+use dbexp::{object_ids::TableId, values::DataValue};
+use mem_table::{DataConfig, Table, TableConfig};
+use primitives::{Bytes, DataType, Timestamp};
+use serde::{Deserialize, Serialize};
+
+use crate::Database;
+
+/// The directory the audit log is kept in, inside the database root.
+pub const AUDIT_DIR: &str = "_audit";
+
+/// How many bytes an entry may take once encoded.
+pub const AUDIT_ENTRY_LEN: usize = 4096;
+
+/// The file the id of the log's table is kept in, since its store files are only opened by it.
+const ID_FILE: &str = "table_id";
+
+const ENTRY_COLUMN: usize = 0;
+
+/// An operation the audit log records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditOp {
+    RenameTable,
+    Truncate,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "phase", rename_all = "snake_case")]
+pub enum AuditPhase {
+    /// The operation is about to run.
+    Intent,
+    /// The operation of the intent entry `intent` went through.
+    Succeeded { intent: u64 },
+    /// The operation of the intent entry `intent` failed, and left things as they were.
+    Failed { intent: u64, error: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// Where the entry is in the log, counting from 1.
+    pub seq: u64,
+    pub timestamp: Timestamp,
+    pub operation: AuditOp,
+    pub table: String,
+    pub details: String,
+    /// Who asked for the operation, as the caller named them.
+    pub principal: Option<String>,
+    #[serde(flatten)]
+    pub phase: AuditPhase,
+}
+
+/// The audit log of a database, see the module docs. Entries are only ever appended.
+#[derive(Debug, Clone)]
+pub struct AuditLog {
+    table: Table,
+    /// Serializes appends, so the seq an entry is given is the one its row gets.
+    write_lock: Arc<Mutex<()>>,
+}
+
+impl AuditLog {
+    /// Opens the audit log persisted in `AUDIT_DIR` under `root`, or an empty one kept in memory
+    /// without a root.
+    pub fn open(root: Option<&Path>) -> Result<Self> {
+        let columns = [DataConfig::new(DataType::Bytes(AUDIT_ENTRY_LEN as u32))];
+
+        let table = match root {
+            Some(root) => {
+                let dir = root.join(AUDIT_DIR);
+                let id_path = dir.join(ID_FILE);
+
+                fs::create_dir_all(&dir)?;
+
+                let id = match fs::read(&id_path) {
+                    Ok(bytes) => TableId::try_from_array(bytes)
+                        .with_context(|| format!("{} is corrupt", id_path.display()))?,
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                        let id = TableId::new();
+                        fs::write(&id_path, id.into_array())?;
+                        id
+                    }
+                    Err(err) => return Err(err.into()),
+                };
+
+                let config =
+                    TableConfig::new_persisted_relative(columns, Path::new(AUDIT_DIR).join("log"))?;
+
+                Table::new_in(id, config, None, root)?
+            }
+            None => Table::new(TableId::new(), TableConfig::new(columns)?, None)?,
+        };
+
+        Ok(Self {
+            table,
+            write_lock: Arc::new(Mutex::new(())),
+        })
+    }
+
+    /// Appends an entry and flushes it to disk, returning its seq.
+    fn _append(
+        &self,
+        operation: AuditOp,
+        table: &str,
+        details: &str,
+        principal: Option<&str>,
+        phase: AuditPhase,
+    ) -> Result<u64> {
+        let _guard = self.write_lock.lock().unwrap_or_else(|e| e.into_inner());
+
+        let entry = AuditEntry {
+            seq: self.table.current_seq() + 1,
+            timestamp: Timestamp::new(),
+            operation,
+            table: table.to_string(),
+            details: details.to_string(),
+            principal: principal.map(str::to_string),
+            phase,
+        };
+        let encoded = serde_json::to_vec(&entry)?;
+        let encoded = Bytes::try_from_slice(&encoded, AUDIT_ENTRY_LEN).map_err(|e| {
+            e.context(format!(
+                "audit entry is {} bytes once encoded, max is {}",
+                encoded.len(),
+                AUDIT_ENTRY_LEN
+            ))
+        })?;
+
+        self.table
+            .insert_one(vec![Some(DataValue::Bytes(encoded))])?;
+        self.table.flush_all()?;
+
+        Ok(entry.seq)
+    }
+
+    /// Runs `operation` on `table`, with an intent entry appended before it runs and an outcome
+    /// entry once it's done. Without its intent recorded the operation doesn't run, and without
+    /// its outcome recorded it fails even if it went through.
+    pub fn record<T>(
+        &self,
+        op: AuditOp,
+        table: &str,
+        details: &str,
+        principal: Option<&str>,
+        operation: impl FnOnce() -> Result<T>,
+    ) -> Result<T> {
+        let intent = self
+            ._append(op, table, details, principal, AuditPhase::Intent)
+            .context("failed to record the intent of the operation")?;
+
+        let res = operation();
+        let phase = match &res {
+            Ok(_) => AuditPhase::Succeeded { intent },
+            Err(err) => AuditPhase::Failed {
+                intent,
+                error: format!("{:#}", err),
+            },
+        };
+
+        self._append(op, table, details, principal, phase)
+            .context("failed to record the outcome of the operation")?;
+
+        res
+    }
+
+    /// Every entry appended at or after `since`, in the order they were appended.
+    pub fn since(&self, since: Timestamp) -> Result<Vec<AuditEntry>> {
+        let mut entries = Vec::new();
+
+        for (_, handle) in self.table.scan_since(0) {
+            let entry: AuditEntry = match self.table.get_value(&handle, ENTRY_COLUMN)? {
+                Some(DataValue::Bytes(bytes)) => serde_json::from_slice(bytes.as_slice())?,
+                other => anyhow::bail!("audit entry is corrupt: {:?}", other),
+            };
+
+            if entry.timestamp >= since {
+                entries.push(entry);
+            }
+        }
+
+        Ok(entries)
+    }
+
+    /// Flushes the log and stops it taking entries, see `Table::close`.
+    pub fn close(&self) -> Result<()> {
+        Ok(self.table.close()?)
+    }
+}
+
+impl Database {
+    pub fn audit(&self) -> &AuditLog {
+        &self.audit
+    }
+
+    /// The entries of the audit log appended at or after `since`, see `AuditLog::since`.
+    pub fn audit_log(&self, since: Timestamp) -> Result<Vec<AuditEntry>> {
+        self.audit.since(since)
+    }
+
+    /// Removes every row of the table `name`, see `Table::truncate`, recorded in the audit log as
+    /// asked for by `principal`. Returns the number of rows removed.
+    pub fn truncate_table(&self, name: &str, principal: Option<&str>) -> Result<usize> {
+        self.audit
+            .record(AuditOp::Truncate, name, "", principal, || {
+                Ok(self.table(name)?.truncate()?)
+            })
+    }
+}
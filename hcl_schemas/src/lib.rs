@@ -1,16 +1,51 @@
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
 use anyhow::Result;
+use dbexp::{object_ids::TableId, values::DataValue};
 use hcl::{
     eval::{Context, Evaluate},
-    Block, Body, Expression,
+    Block, Body, Expression, ObjectKey, Structure,
 };
-use primitives::{bytes::Bytes, text::Text, DataType};
+use indexmap::IndexMap;
+use mem_table::{
+    limits::{MAX_BYTES_LEN, MAX_COLUMNS, MAX_TEXT_LEN},
+    DataConfig, GeneratorKind, InsertState, LogicalType, Normalization, OverflowPolicy, Table,
+    TableConfig,
+};
+use primitives::{DataType, InternalPath, O64};
 
 use primitives::InternalString;
 
+pub use audit::{AuditEntry, AuditLog, AuditOp, AuditPhase};
+pub use maintenance::{
+    compactable, fragmentation, Compaction, CompactionProgress, Maintenance, MaintenanceConfig,
+    MaintenanceMetrics, MaintenanceStopped, Scrub, TickReport,
+};
+pub use rename::rename_table;
+pub use validate::{
+    open, open_eager, validate, CatalogTable, Database, DatabaseMetrics, Issue, IssueKind,
+    LazyTable, ValidationReport,
+};
+
+pub mod audit;
+pub mod maintenance;
+pub mod rename;
+pub mod validate;
+
 #[derive(Debug, Clone, Copy)]
 pub struct ColumnDef {
     name: InternalString,
     data_type: DataType,
+    overflow: OverflowPolicy,
+    normalize: Normalization,
+    logical_type: Option<LogicalType>,
+    check_logical_type: bool,
+    default: Option<GeneratorKind>,
+    unique: bool,
 }
 
 impl ColumnDef {
@@ -21,10 +56,82 @@ impl ColumnDef {
     pub fn data_type(&self) -> DataType {
         self.data_type
     }
+
+    pub fn overflow(&self) -> OverflowPolicy {
+        self.overflow
+    }
+
+    /// How text written to the column is cleaned up. Queries compare against it as written, so
+    /// literals have to be normalized the same way to match.
+    pub fn normalize(&self) -> Normalization {
+        self.normalize
+    }
+
+    /// Set for columns declared with one of the logical aliases, `Email`, `Phone`, `Url` or `Uuid`.
+    pub fn logical_type(&self) -> Option<LogicalType> {
+        self.logical_type
+    }
+
+    /// Whether values are checked against the logical type, unless turned off with
+    /// `validate = false` on the column or its table.
+    pub fn check_logical_type(&self) -> bool {
+        self.check_logical_type
+    }
+
+    /// What rows inserted without a value for the column get, from its `default` option.
+    pub fn default(&self) -> Option<GeneratorKind> {
+        self.default
+    }
+
+    /// Whether no two rows may hold the same value in the column, for columns whose type is
+    /// wrapped in `unique(..)`, as in `email = unique(Email)`.
+    pub fn unique(&self) -> bool {
+        self.unique
+    }
+
+    /// The config of a table column as the definition declares it.
+    pub fn data_config(&self) -> DataConfig {
+        let config = DataConfig::new(self.data_type)
+            .with_overflow(self.overflow)
+            .with_normalization(self.normalize)
+            .with_logical_check(self.check_logical_type);
+
+        let config = match self.logical_type {
+            Some(logical_type) => config.with_logical_type(logical_type),
+            None => config,
+        };
+
+        let config = match self.default {
+            Some(default) => config.with_default(default),
+            None => config,
+        };
+
+        if self.unique {
+            config.with_unique()
+        } else {
+            config
+        }
+    }
+}
+
+/// The logical type an alias like `Email` stands for.
+fn parse_logical_alias(name: &str) -> Option<LogicalType> {
+    match name {
+        "Email" => Some(LogicalType::Email),
+        "Phone" => Some(LogicalType::Phone),
+        "Url" => Some(LogicalType::Url),
+        "Uuid" => Some(LogicalType::Uuid),
+        _ => None,
+    }
 }
 
-const EMAIL_TYPE: DataType = DataType::Text(120);
-const PHONE_TYPE: DataType = DataType::Text(20);
+fn parse_logical_type(input: &Expression) -> Option<LogicalType> {
+    match input {
+        Expression::Variable(name) => parse_logical_alias(name.as_str()),
+        Expression::FuncCall(f) => parse_logical_alias(f.name.as_str()),
+        _ => None,
+    }
+}
 
 fn parse_data_type(input: &Expression, ctx: &Context) -> Result<DataType> {
     use Expression::{FuncCall, Variable};
@@ -32,16 +139,47 @@ fn parse_data_type(input: &Expression, ctx: &Context) -> Result<DataType> {
     match input {
         Variable(name) => match name.as_str() {
             "Number" => Ok(DataType::Number),
-            "Email" => Ok(EMAIL_TYPE),
-            "Phone" => Ok(PHONE_TYPE),
             "Timestamp" => Ok(DataType::Timestamp),
+            "O64" => Ok(DataType::O64),
+            "Bool" => Ok(DataType::Bool),
             "Text" => anyhow::bail!("Expected Text to have a length"),
-            _ => anyhow::bail!("Unknown data type: {}", name.as_str()),
+            name => match parse_logical_alias(name) {
+                Some(logical_type) => Ok(logical_type.base_type()),
+                None => anyhow::bail!("Unknown data type: {}", name),
+            },
         },
         FuncCall(f) => {
             let name = InternalString::new(f.name.as_str())?;
 
-            if f.args.len() != 1 {
+            // logical aliases only take options, as in `Email({ validate = false })`
+            if let Some(logical_type) = parse_logical_alias(name.as_str()) {
+                if f.args.len() != 1 {
+                    anyhow::bail!("Expected {} to only be given options", name.as_str());
+                }
+
+                return Ok(logical_type.base_type());
+            }
+
+            // neither do the types without a length, as in `Number({ default = sequence(1, 1) })`
+            let unsized_type = match name.as_str() {
+                "Number" => Some(DataType::Number),
+                "Timestamp" => Some(DataType::Timestamp),
+                "O64" => Some(DataType::O64),
+                "Bool" => Some(DataType::Bool),
+                _ => None,
+            };
+
+            if let Some(data_type) = unsized_type {
+                if f.args.len() != 1 {
+                    anyhow::bail!("Expected {} to only be given options", name.as_str());
+                }
+
+                return Ok(data_type);
+            }
+
+            let options = matches!(name.as_str(), "Text" | "Bytes") as usize;
+
+            if f.args.is_empty() || f.args.len() > 1 + options {
                 anyhow::bail!("Expected exactly one argument for type constructor");
             }
 
@@ -51,22 +189,32 @@ fn parse_data_type(input: &Expression, ctx: &Context) -> Result<DataType> {
                         anyhow::anyhow!("Expected positive integer argument for Text")
                     })?;
 
-                    if max_len > Text::MAX_LEN as u64 {
-                        anyhow::bail!("Text length is too large");
+                    if max_len > MAX_TEXT_LEN as u64 {
+                        anyhow::bail!("Text({}) exceeds MAX_TEXT_LEN ({})", max_len, MAX_TEXT_LEN);
                     }
 
-                    Ok(DataType::Text(max_len as u32))
+                    let data_type = DataType::Text(max_len as u32);
+                    data_type.validate()?;
+
+                    Ok(data_type)
                 }
                 "Bytes" => {
                     let max_len = f.args[0].evaluate(ctx)?.as_u64().ok_or_else(|| {
                         anyhow::anyhow!("Expected positive integer argument for Bytes")
                     })?;
 
-                    if max_len > Bytes::MAX_LEN as u64 {
-                        anyhow::bail!("Bytes length is too large");
+                    if max_len > MAX_BYTES_LEN as u64 {
+                        anyhow::bail!(
+                            "Bytes({}) exceeds MAX_BYTES_LEN ({})",
+                            max_len,
+                            MAX_BYTES_LEN
+                        );
                     }
 
-                    Ok(DataType::Bytes(max_len as u32))
+                    let data_type = DataType::Bytes(max_len as u32);
+                    data_type.validate()?;
+
+                    Ok(data_type)
                 }
                 _ => anyhow::bail!("Unknown data type: {}", name.as_str()),
             }
@@ -75,10 +223,362 @@ fn parse_data_type(input: &Expression, ctx: &Context) -> Result<DataType> {
     }
 }
 
+const DEFAULT: &str = "default";
+
+const COLUMN_OPTIONS: [&str; 6] = [
+    "overflow",
+    "trim",
+    "collapse_whitespace",
+    "lowercase",
+    "validate",
+    DEFAULT,
+];
+
+/// The options argument of a column type, if it was given one.
+fn column_options(input: &Expression) -> Option<&Expression> {
+    let Expression::FuncCall(f) = input else {
+        return None;
+    };
+
+    let sized = parse_logical_alias(f.name.as_str()).is_none()
+        && !matches!(f.name.as_str(), "Number" | "Timestamp" | "O64" | "Bool");
+
+    f.args.get(sized as usize)
+}
+
+/// The name of an option, which may be written as an identifier or a string.
+fn option_name(key: &ObjectKey) -> Option<&str> {
+    match key {
+        ObjectKey::Identifier(name) => Some(name.as_str()),
+        ObjectKey::Expression(Expression::String(name)) => Some(name.as_str()),
+        _ => None,
+    }
+}
+
+/// Reads the options a `Text` or `Bytes` column may be given after its length, as in
+/// `Text(100, { overflow = "truncate", trim = true })`, or a logical alias or a type without a
+/// length on its own, as in `Email({ trim = true })`. The `default` option is left out, since it
+/// is only read, not evaluated, see `parse_default`.
+fn parse_column_options(input: &Expression, ctx: &Context) -> Result<hcl::Map<String, hcl::Value>> {
+    let Some(options) = column_options(input) else {
+        return Ok(Default::default());
+    };
+
+    let options = match options {
+        Expression::Object(options) => Expression::Object(
+            options
+                .iter()
+                .filter(|(key, _)| option_name(key) != Some(DEFAULT))
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect(),
+        ),
+        options => options.clone(),
+    };
+
+    let options = match options.evaluate(ctx)? {
+        hcl::Value::Object(options) => options,
+        _ => anyhow::bail!("Expected column options to be an object"),
+    };
+
+    if options.contains_key(DEFAULT) {
+        anyhow::bail!("Expected default to be written out in the column options");
+    }
+
+    if let Some(key) = options
+        .keys()
+        .find(|key| !COLUMN_OPTIONS.contains(&key.as_str()))
+    {
+        anyhow::bail!("Unknown column option: {}", key);
+    }
+
+    Ok(options)
+}
+
+fn parse_overflow(input: &Expression, ctx: &Context) -> Result<OverflowPolicy> {
+    match parse_column_options(input, ctx)?.get("overflow") {
+        Some(policy) => policy
+            .as_str()
+            .ok_or_else(|| anyhow::anyhow!("Expected overflow to be a string"))?
+            .parse(),
+        None => Ok(OverflowPolicy::default()),
+    }
+}
+
+fn parse_normalization(input: &Expression, ctx: &Context) -> Result<Normalization> {
+    let options = parse_column_options(input, ctx)?;
+    let flag = |name: &str| match options.get(name) {
+        Some(value) => value
+            .as_bool()
+            .ok_or_else(|| anyhow::anyhow!("Expected {} to be a bool", name)),
+        None => Ok(false),
+    };
+
+    Ok(Normalization {
+        trim: flag("trim")?,
+        collapse_whitespace: flag("collapse_whitespace")?,
+        lowercase: flag("lowercase")?,
+    })
+}
+
+/// Whether a column's values are checked against its logical type. The column's `validate` option
+/// wins over its table's `validate` attribute, and both default to checking.
+fn parse_check_logical_type(
+    input: &Expression,
+    ctx: &Context,
+    table_default: bool,
+) -> Result<bool> {
+    let validate = match parse_column_options(input, ctx)?.get("validate") {
+        Some(validate) => validate
+            .as_bool()
+            .ok_or_else(|| anyhow::anyhow!("Expected validate to be a bool"))?,
+        None => return Ok(table_default),
+    };
+
+    if parse_logical_type(input).is_none() {
+        anyhow::bail!("Expected validate to only be given to Email, Phone, Url or Uuid columns");
+    }
+
+    Ok(validate)
+}
+
+/// Reads a column's `default` option, one of `sequence(start, step)`, where both are optional and
+/// default to 1, `random_o64()` or `now()`. These name what to make up a value with on every
+/// insert, so they're read as written rather than evaluated.
+fn parse_default(input: &Expression, ctx: &Context) -> Result<Option<GeneratorKind>> {
+    let Some(Expression::Object(options)) = column_options(input) else {
+        return Ok(None);
+    };
+
+    let Some((_, default)) = options
+        .iter()
+        .find(|(key, _)| option_name(key) == Some(DEFAULT))
+    else {
+        return Ok(None);
+    };
+
+    let Expression::FuncCall(f) = default else {
+        anyhow::bail!("Expected default to be sequence(..), random_o64() or now()");
+    };
+
+    let generator = match f.name.as_str() {
+        "sequence" => {
+            if f.args.len() > 2 {
+                anyhow::bail!("Expected sequence to be given at most a start and a step");
+            }
+
+            let arg = |idx: usize, name: &str| match f.args.get(idx) {
+                Some(arg) => arg
+                    .evaluate(ctx)?
+                    .as_i64()
+                    .ok_or_else(|| anyhow::anyhow!("Expected sequence {} to be an integer", name)),
+                None => Ok(1),
+            };
+
+            GeneratorKind::Sequence {
+                start: arg(0, "start")?,
+                step: arg(1, "step")?,
+            }
+        }
+        "random_o64" => GeneratorKind::RandomO64,
+        "now" => GeneratorKind::NowTimestamp,
+        name => anyhow::bail!("Unknown column default: {}", name),
+    };
+
+    if !matches!(generator, GeneratorKind::Sequence { .. }) && !f.args.is_empty() {
+        anyhow::bail!("Expected {} to take no arguments", f.name.as_str());
+    }
+
+    if let GeneratorKind::Sequence { step: 0, .. } = generator {
+        anyhow::bail!("Expected sequence step to not be zero");
+    }
+
+    let data_type = parse_data_type(input, ctx)?;
+
+    if data_type != generator.data_type() {
+        anyhow::bail!(
+            "Expected {} default to be given to a {:?} column, not {:?}",
+            f.name.as_str(),
+            generator.data_type(),
+            data_type
+        );
+    }
+
+    Ok(Some(generator))
+}
+
+fn parse_primary_key(input: &Expression, ctx: &Context) -> Result<Vec<InternalString>> {
+    let value = input.evaluate(ctx)?;
+    let names = value
+        .as_array()
+        .ok_or_else(|| anyhow::anyhow!("Expected primary_key to be a list of column names"))?;
+
+    if names.is_empty() {
+        anyhow::bail!("Expected primary_key to name at least one column");
+    }
+
+    names
+        .iter()
+        .map(|name| {
+            let name = name.as_str().ok_or_else(|| {
+                anyhow::anyhow!("Expected primary_key column name to be a string")
+            })?;
+
+            InternalString::new(name)
+        })
+        .collect()
+}
+
+/// Unwraps the type of a column declared unique, as in `email = unique(Email)` or
+/// `handle = unique(Text(32, { lowercase = true }))`, returning it and whether it was.
+fn parse_unique(input: &Expression) -> Result<(&Expression, bool)> {
+    match input {
+        Expression::FuncCall(f) if f.name.as_str() == UNIQUE => match f.args.as_slice() {
+            [inner] => Ok((inner, true)),
+            _ => anyhow::bail!("Expected unique to be given a single column type"),
+        },
+        input => Ok((input, false)),
+    }
+}
+
+const UNIQUE: &str = "unique";
+
+const PRIMARY_KEY: &str = "primary_key";
+/// Turns off logical type checks for every column of a table that doesn't say otherwise.
+const VALIDATE: &str = "validate";
+/// Seed rows written as a list of objects, as in `seed = [{ email = "a@b.c" }]`.
+const SEED: &str = "seed";
+/// The attributes of a table block that aren't columns.
+const TABLE_ATTRIBUTES: [&str; 3] = [PRIMARY_KEY, VALIDATE, SEED];
+/// Seed rows written as blocks, as in `rows { row { email = "a@b.c" } }`.
+const ROWS: &str = "rows";
+const ROW: &str = "row";
+
+/// A row a table is seeded with once it's created, with a value, or none, for every column.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeedRow {
+    values: Vec<Option<DataValue>>,
+}
+
+impl SeedRow {
+    /// The values of the row in column order, missing for the columns it didn't set.
+    pub fn values(&self) -> &[Option<DataValue>] {
+        &self.values
+    }
+
+    pub fn into_values(self) -> Vec<Option<DataValue>> {
+        self.values
+    }
+}
+
+/// The column values each seed row of a table block sets, from both its `rows` blocks and its
+/// `seed` attribute, in the order they're written.
+fn parse_seed_values(block: &Block, ctx: &Context) -> Result<Vec<Vec<(String, hcl::Value)>>> {
+    let mut rows = Vec::new();
+
+    for structure in block.body.iter() {
+        match structure {
+            Structure::Block(rows_block) if rows_block.identifier() == ROWS => {
+                for row in rows_block.body.blocks() {
+                    if row.identifier() != ROW {
+                        anyhow::bail!(
+                            "Expected rows to only contain row blocks, not {}",
+                            row.identifier()
+                        );
+                    }
+
+                    rows.push(
+                        row.body
+                            .attributes()
+                            .map(|attr| Ok((attr.key().to_string(), attr.expr().evaluate(ctx)?)))
+                            .collect::<Result<Vec<_>>>()?,
+                    );
+                }
+            }
+            Structure::Attribute(attr) if attr.key() == SEED => {
+                let value = attr.expr().evaluate(ctx)?;
+                let seed = value
+                    .as_array()
+                    .ok_or_else(|| anyhow::anyhow!("Expected seed to be a list of objects"))?;
+
+                for row in seed {
+                    let row = row
+                        .as_object()
+                        .ok_or_else(|| anyhow::anyhow!("Expected seed row to be an object"))?;
+
+                    rows.push(
+                        row.iter()
+                            .map(|(key, value)| (key.clone(), value.clone()))
+                            .collect(),
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(rows)
+}
+
+/// Converts the values a seed row sets to the types of the columns they're set for, leaving the
+/// columns it doesn't set without a value.
+fn parse_seed_row(
+    table: &InternalString,
+    columns: &[ColumnDef],
+    row: Vec<(String, hcl::Value)>,
+) -> Result<SeedRow> {
+    let mut values = vec![None; columns.len()];
+
+    for (key, value) in row {
+        let Some(idx) = columns
+            .iter()
+            .position(|column| column.name.as_str() == key)
+        else {
+            anyhow::bail!(
+                "seed row of table {} sets unknown column {}",
+                table.as_str(),
+                key
+            );
+        };
+
+        let data_type = columns[idx].data_type;
+        let converted = match value {
+            hcl::Value::Null => None,
+            hcl::Value::Bool(val) => Some(DataValue::try_from_any(data_type, val)),
+            hcl::Value::Number(val) => Some(if let Some(val) = val.as_u64() {
+                DataValue::try_from_any(data_type, val)
+            } else if let Some(val) = val.as_i64() {
+                DataValue::try_from_any(data_type, val)
+            } else {
+                DataValue::try_from_any(data_type, val.as_f64().unwrap_or(f64::NAN))
+            }),
+            // ids are written in their string form, which `try_from_any` doesn't read
+            hcl::Value::String(val) if data_type == DataType::O64 => {
+                Some(val.parse::<O64>().map(DataValue::O64))
+            }
+            hcl::Value::String(val) => Some(DataValue::try_from_any(data_type, val)),
+            _ => Some(Err(anyhow::anyhow!("Expected a bool, number or string"))),
+        };
+
+        values[idx] = converted.transpose().map_err(|e| {
+            e.context(format!(
+                "invalid seed value for column {} of table {}",
+                key,
+                table.as_str()
+            ))
+        })?;
+    }
+
+    Ok(SeedRow { values })
+}
+
 #[derive(Debug, Clone)]
 pub struct TableDef {
     name: InternalString,
     columns: Vec<ColumnDef>,
+    primary_key: Vec<InternalString>,
+    seed: Vec<SeedRow>,
+    /// The file the table was defined in. Empty for tables parsed from a string.
+    source: InternalPath,
 }
 
 impl<'a> TryFrom<(&Block, &Context<'a>)> for TableDef {
@@ -91,32 +591,171 @@ impl<'a> TryFrom<(&Block, &Context<'a>)> for TableDef {
             return Err(anyhow::anyhow!("Expected block identifier 'table'"));
         }
 
+        Ok(TableDef::parse(block, ctx)?)
+    }
+}
+
+/// Parses the column an attribute of a table block defines, given whether the table checks logical
+/// types.
+fn parse_column(attr: &hcl::Attribute, ctx: &Context, validate: bool) -> Result<ColumnDef> {
+    let name = InternalString::new(attr.key())?;
+    let (expr, unique) = parse_unique(attr.expr())?;
+
+    Ok(ColumnDef {
+        name,
+        data_type: parse_data_type(expr, ctx)?,
+        overflow: parse_overflow(expr, ctx)?,
+        normalize: parse_normalization(expr, ctx)?,
+        logical_type: parse_logical_type(expr),
+        check_logical_type: parse_check_logical_type(expr, ctx, validate)?,
+        default: parse_default(expr, ctx)?,
+        unique,
+    })
+}
+
+/// A table block that didn't parse, and the attribute it failed at, when it was one in particular.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error, serde::Serialize)]
+#[error(
+    "table \"{table}\"{}: {reason}",
+    .attribute.as_ref().map(|attr| format!(", attribute {}", attr)).unwrap_or_default()
+)]
+pub struct BlockError {
+    pub table: String,
+    pub attribute: Option<String>,
+    pub reason: String,
+}
+
+impl BlockError {
+    fn new(table: &str, attribute: Option<&str>, error: anyhow::Error) -> Self {
+        Self {
+            table: table.to_string(),
+            attribute: attribute.map(str::to_string),
+            reason: format!("{:#}", error),
+        }
+    }
+}
+
+/// A schema that didn't parse, see `parse_hcl`.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum SchemaError {
+    #[error("schema isn't valid HCL: {0}")]
+    Syntax(String),
+    #[error(
+        "{} table block(s) failed to parse: {}",
+        .0.len(),
+        .0.iter().map(ToString::to_string).collect::<Vec<_>>().join("; ")
+    )]
+    Blocks(Vec<BlockError>),
+}
+
+impl TableDef {
+    /// Parses a `table` block.
+    fn parse(block: &Block, ctx: &Context) -> Result<Self, BlockError> {
         let labels = block.labels();
+        let label = labels.first().map_or("", |label| label.as_str());
+        let fail = |attribute: Option<&str>, error: anyhow::Error| {
+            BlockError::new(label, attribute, error)
+        };
 
         if labels.len() != 1 {
-            return Err(anyhow::anyhow!("Expected exactly one label"));
+            return Err(fail(None, anyhow::anyhow!("Expected exactly one label")));
+        }
+
+        let name = InternalString::new(label).map_err(|e| fail(None, e))?;
+
+        let mut primary_key = Vec::new();
+        let mut validate = true;
+
+        for attr in block.body.attributes() {
+            match attr.key() {
+                PRIMARY_KEY => {
+                    primary_key = parse_primary_key(attr.expr(), ctx)
+                        .map_err(|e| fail(Some(PRIMARY_KEY), e))?;
+                }
+                VALIDATE => {
+                    validate = attr
+                        .expr()
+                        .evaluate(ctx)
+                        .map_err(anyhow::Error::from)
+                        .and_then(|validate| {
+                            validate.as_bool().ok_or_else(|| {
+                                anyhow::anyhow!(
+                                    "Expected validate of table {} to be a bool",
+                                    name.as_str()
+                                )
+                            })
+                        })
+                        .map_err(|e| fail(Some(VALIDATE), e))?;
+                }
+                _ => {}
+            }
         }
 
-        let name = InternalString::new(labels[0].as_str())?;
+        let mut columns: Vec<ColumnDef> = Vec::new();
 
-        let columns = block
+        for attr in block
             .body
             .attributes()
-            .map(|attr| {
-                let name = InternalString::new(attr.key())?;
+            .filter(|attr| !TABLE_ATTRIBUTES.contains(&attr.key()))
+        {
+            let column =
+                parse_column(attr, ctx, validate).map_err(|e| fail(Some(attr.key()), e))?;
 
-                Ok(ColumnDef {
-                    name: InternalString::from(name),
-                    data_type: parse_data_type(attr.expr(), ctx)?,
-                })
+            columns.push(column);
+        }
+
+        if columns.len() > MAX_COLUMNS {
+            return Err(fail(
+                None,
+                anyhow::anyhow!(
+                    "table {} has {} columns which exceeds MAX_COLUMNS ({})",
+                    name.as_str(),
+                    columns.len(),
+                    MAX_COLUMNS
+                ),
+            ));
+        }
+
+        let seed = parse_seed_values(block, ctx)
+            .and_then(|rows| {
+                rows.into_iter()
+                    .map(|row| parse_seed_row(&name, &columns, row))
+                    .collect::<Result<Vec<_>>>()
             })
-            .collect::<Result<Vec<_>>>()?;
+            .map_err(|e| fail(None, e))?;
 
-        Ok(Self { name, columns })
-    }
-}
+        for (i, key) in primary_key.iter().enumerate() {
+            if !columns.iter().any(|column| column.name == *key) {
+                return Err(fail(
+                    Some(PRIMARY_KEY),
+                    anyhow::anyhow!(
+                        "primary key of table {} names unknown column {}",
+                        name.as_str(),
+                        key.as_str()
+                    ),
+                ));
+            }
 
-impl TableDef {
+            if primary_key[..i].contains(key) {
+                return Err(fail(
+                    Some(PRIMARY_KEY),
+                    anyhow::anyhow!(
+                        "primary key of table {} lists column {} more than once",
+                        name.as_str(),
+                        key.as_str()
+                    ),
+                ));
+            }
+        }
+
+        Ok(Self {
+            name,
+            columns,
+            primary_key,
+            seed,
+            source: InternalPath::default(),
+        })
+    }
     pub fn name(&self) -> &str {
         &self.name
     }
@@ -124,25 +763,241 @@ impl TableDef {
     pub fn columns(&self) -> &[ColumnDef] {
         &self.columns
     }
+
+    pub fn primary_key(&self) -> &[InternalString] {
+        &self.primary_key
+    }
+
+    pub fn source(&self) -> InternalPath {
+        self.source
+    }
+
+    /// The rows the table is seeded with once it's created, from its `rows` blocks and its `seed`
+    /// attribute.
+    pub fn seed(&self) -> &[SeedRow] {
+        &self.seed
+    }
+
+    /// The positions of the primary key columns, in key order.
+    pub fn primary_key_columns(&self) -> Vec<usize> {
+        self.primary_key
+            .iter()
+            .filter_map(|key| self.columns.iter().position(|column| column.name == *key))
+            .collect()
+    }
+
+    /// The config of a table with the columns and primary key of the definition.
+    pub fn table_config(&self) -> Result<TableConfig> {
+        let columns = self
+            .columns
+            .iter()
+            .map(ColumnDef::data_config)
+            .collect::<Vec<_>>();
+
+        let config = TableConfig::new(&columns)?;
+
+        if self.primary_key.is_empty() {
+            Ok(config)
+        } else {
+            config.with_primary_key(self.primary_key_columns())
+        }
+    }
+
+    /// Creates the table the definition describes in memory, with its columns named and its seed
+    /// rows inserted. Fails if any seed row isn't inserted, rather than leaving the table short.
+    pub fn create_table(&self, id: TableId) -> Result<Table> {
+        let name_mapping = self
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(idx, column_def)| (*column_def.name(), idx))
+            .collect::<IndexMap<_, _>>();
+
+        let table = Table::new(id, self.table_config()?, Some(name_mapping))?;
+
+        if !self.seed.is_empty() {
+            let state = table.insert(self.seed.iter().map(|row| row.values().to_vec()))?;
+
+            if let InsertState::Partial { mut errors, .. } = state {
+                let (idx, error) = errors.remove(0);
+
+                return Err(anyhow::Error::new(error).context(format!(
+                    "seed row {} of table {} wasn't inserted",
+                    idx, self.name
+                )));
+            }
+        }
+
+        Ok(table)
+    }
 }
 
-pub fn parse_hcl(input: &str) -> Result<Vec<TableDef>> {
-    let body: Body = hcl::from_str(input)?;
+/// Parses every table defined in `input`. Fails with the errors of every table block that doesn't
+/// parse, see `parse_hcl_partial` for leaving them out instead.
+pub fn parse_hcl(input: &str) -> Result<Vec<TableDef>, SchemaError> {
+    let (tables, errors) = parse_hcl_partial(input)?;
+
+    if !errors.is_empty() {
+        return Err(SchemaError::Blocks(errors));
+    }
+
+    Ok(tables)
+}
+
+/// Parses the tables defined in `input` that do parse, returning the errors of the table blocks
+/// that don't along with them. Only fails when `input` isn't HCL at all.
+pub fn parse_hcl_partial(input: &str) -> Result<(Vec<TableDef>, Vec<BlockError>), SchemaError> {
+    let body: Body = hcl::from_str(input).map_err(|e| SchemaError::Syntax(e.to_string()))?;
     let ctx = Context::default();
+    let mut tables = Vec::new();
+    let mut errors = Vec::new();
+
+    for block in body.blocks().filter(|block| block.identifier() == "table") {
+        match TableDef::parse(block, &ctx) {
+            Ok(table) => tables.push(table),
+            Err(e) => errors.push(e),
+        }
+    }
+
+    Ok((tables, errors))
+}
+
+/// Parses the schema in the file at `path` along with every file it includes. Files include
+/// others, relative to themselves, with either an `include "other.hcl" {}` block or an
+/// `include = "other.hcl"` attribute, which may also be a list of paths. A file included more
+/// than once is only parsed the first time, but a file including itself, directly or not, is an
+/// error, as is the same table being defined in two files.
+pub fn parse_hcl_file(path: impl AsRef<Path>) -> Result<Vec<TableDef>> {
+    let mut schema = SchemaFiles::default();
+    schema.parse(path.as_ref())?;
+
+    Ok(schema.tables)
+}
+
+#[derive(Default)]
+struct SchemaFiles {
+    /// The files being parsed, each included by the one before it.
+    chain: Vec<PathBuf>,
+    parsed: HashSet<PathBuf>,
+    tables: Vec<TableDef>,
+}
+
+impl SchemaFiles {
+    fn parse(&mut self, path: &Path) -> Result<()> {
+        let path = fs::canonicalize(path)
+            .map_err(|e| anyhow::anyhow!("can't open schema {}: {}", path.display(), e))?;
+
+        if let Some(start) = self.chain.iter().position(|file| *file == path) {
+            let cycle = self.chain[start..]
+                .iter()
+                .chain([&path])
+                .map(|file| file.display().to_string())
+                .collect::<Vec<_>>();
+
+            anyhow::bail!("include cycle: {}", cycle.join(" -> "));
+        }
+
+        if !self.parsed.insert(path.clone()) {
+            return Ok(());
+        }
+
+        let body: Body = hcl::from_str(&fs::read_to_string(&path)?)
+            .map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?;
+        let ctx = Context::default();
+        let source = InternalPath::new(&path)?;
+        let dir = path.parent().unwrap_or(Path::new("/")).to_path_buf();
+
+        self.chain.push(path.clone());
+
+        let mut errors = Vec::new();
+
+        for structure in body.iter() {
+            match structure {
+                Structure::Attribute(attr) if attr.key() == INCLUDE => {
+                    for include in parse_includes(attr.expr(), &ctx)? {
+                        self.parse(&dir.join(include))?;
+                    }
+                }
+                Structure::Block(block) if block.identifier() == INCLUDE => {
+                    let [label] = block.labels() else {
+                        anyhow::bail!("Expected include to have exactly one path");
+                    };
+
+                    self.parse(&dir.join(label.as_str()))?;
+                }
+                Structure::Block(block) if block.identifier() == "table" => {
+                    match TableDef::parse(block, &ctx) {
+                        Ok(table) => self.add(TableDef { source, ..table })?,
+                        Err(e) => errors.push(e),
+                    }
+                }
+                Structure::Block(_) | Structure::Attribute(_) => {}
+            }
+        }
+
+        if !errors.is_empty() {
+            anyhow::bail!("{}: {}", path.display(), SchemaError::Blocks(errors));
+        }
+
+        self.chain.pop();
+
+        Ok(())
+    }
+
+    fn add(&mut self, table: TableDef) -> Result<()> {
+        if let Some(existing) = self.tables.iter().find(|t| t.name == table.name) {
+            anyhow::bail!(
+                "table {} is defined in both {} and {}",
+                table.name(),
+                existing.source.as_path().display(),
+                table.source.as_path().display()
+            );
+        }
+
+        self.tables.push(table);
+
+        Ok(())
+    }
+}
+
+const INCLUDE: &str = "include";
+
+fn parse_includes(input: &Expression, ctx: &Context) -> Result<Vec<String>> {
+    let value = input.evaluate(ctx)?;
+
+    if let Some(path) = value.as_str() {
+        return Ok(vec![path.to_string()]);
+    }
 
-    Ok(body
-        .blocks()
-        .filter_map(|block| match TableDef::try_from((block, &ctx)) {
-            Ok(table) => Some(table),
-            _ => None,
+    value
+        .as_array()
+        .ok_or_else(|| anyhow::anyhow!("Expected include to be a path or a list of paths"))?
+        .iter()
+        .map(|path| {
+            path.as_str()
+                .map(str::to_string)
+                .ok_or_else(|| anyhow::anyhow!("Expected include path to be a string"))
         })
-        .collect::<Vec<_>>())
+        .collect()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    thread_local! {
+        static FAIL_MOVE: std::cell::Cell<Option<usize>> = const { std::cell::Cell::new(None) };
+    }
+
+    /// Fails the move of a renamed table's files numbered with `FAIL_MOVE`, like a full disk would.
+    pub(crate) fn fail_move(n: usize) -> Result<()> {
+        if FAIL_MOVE.get() == Some(n) {
+            anyhow::bail!("fail point hit for move {}", n);
+        }
+
+        Ok(())
+    }
+
     #[test]
     fn test_parse_hcl() {
         let input = r#"
@@ -156,4 +1011,1393 @@ mod tests {
 
         assert!(parse_hcl(input).is_ok());
     }
+
+    #[test]
+    fn test_parse_hcl_errors() -> Result<()> {
+        let input = r#"
+            table "users" {
+                emial = Emial
+                first = Text(100)
+            }
+
+            table "notes" {
+                body = Text
+            }
+
+            table "orders" {
+                total = Number
+                primary_key = ["id"]
+            }
+
+            table "audit" {
+                at = Timestamp
+            }
+        "#;
+
+        let Err(SchemaError::Blocks(errors)) = parse_hcl(input) else {
+            panic!("expected every invalid table block to fail");
+        };
+
+        assert_eq!(
+            errors
+                .iter()
+                .map(|e| (e.table.as_str(), e.attribute.as_deref()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("users", Some("emial")),
+                ("notes", Some("body")),
+                ("orders", Some("primary_key")),
+            ]
+        );
+        assert!(errors[0].reason.contains("Unknown data type: Emial"));
+        assert!(errors[1].reason.contains("Expected Text to have a length"));
+        assert!(errors[2].reason.contains("names unknown column id"));
+        assert!(errors[0]
+            .to_string()
+            .starts_with("table \"users\", attribute emial: "));
+
+        // the lenient form keeps the valid tables, and the errors of the rest
+        let (tables, errors) = parse_hcl_partial(input)?;
+        assert_eq!(
+            tables.iter().map(TableDef::name).collect::<Vec<_>>(),
+            vec!["audit"]
+        );
+        assert_eq!(errors.len(), 3);
+
+        assert!(matches!(
+            parse_hcl("table \"users\" {"),
+            Err(SchemaError::Syntax(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_primary_key() -> Result<()> {
+        let input = r#"
+            table "users" {
+                first = Text(100)
+                last  = Text(100)
+                primary_key = ["last", "first"]
+            }
+        "#;
+
+        let tables = parse_hcl(input)?;
+
+        assert_eq!(tables[0].columns().len(), 2);
+        assert_eq!(tables[0].primary_key_columns(), vec![1, 0]);
+
+        let unknown = r#"
+            table "users" {
+                first = Text(100)
+                primary_key = ["last"]
+            }
+        "#;
+
+        let body: Body = hcl::from_str(unknown)?;
+        let block = body.blocks().next().unwrap();
+        let err = TableDef::try_from((block, &Context::default())).unwrap_err();
+        assert!(err.to_string().contains("unknown column last"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_duplicate_columns() -> Result<()> {
+        let input = r#"
+            table "users" {
+                email = Email
+                email = Text(50)
+            }
+        "#;
+
+        // hcl-rs rejects a second attribute by the same name, so the schema never gets as far as
+        // its table blocks
+        let Err(SchemaError::Syntax(err)) = parse_hcl(input) else {
+            panic!("expected the duplicate column to fail the parse");
+        };
+        assert!(err.contains("expected unique attribute key"), "{}", err);
+
+        let too_many = (0..=MAX_COLUMNS)
+            .map(|i| format!("c{} = Number", i))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let body: Body = hcl::from_str(&format!("table \"wide\" {{\n{}\n}}", too_many))?;
+        let block = body.blocks().next().unwrap();
+        let err = TableDef::try_from((block, &Context::default())).unwrap_err();
+        assert!(
+            err.to_string().contains(&format!(
+                "table wide has {} columns which exceeds MAX_COLUMNS ({})",
+                MAX_COLUMNS + 1,
+                MAX_COLUMNS
+            )),
+            "{}",
+            err
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_data_type_limits() {
+        let ctx = Context::default();
+        let too_long: Expression =
+            hcl::from_str::<Body>(&format!("x = Text({})", MAX_TEXT_LEN + 1))
+                .unwrap()
+                .attributes()
+                .next()
+                .unwrap()
+                .expr()
+                .clone();
+
+        let err = parse_data_type(&too_long, &ctx).unwrap_err();
+        assert!(err.to_string().contains("MAX_TEXT_LEN"));
+
+        for empty in ["x = Text(0)", "x = Bytes(0)"] {
+            let empty: Expression = hcl::from_str::<Body>(empty)
+                .unwrap()
+                .attributes()
+                .next()
+                .unwrap()
+                .expr()
+                .clone();
+
+            let err = parse_data_type(&empty, &ctx).unwrap_err();
+            assert!(err.to_string().contains("at least 1"));
+        }
+    }
+
+    #[test]
+    fn test_parse_overflow() -> Result<()> {
+        let input = r#"
+            table "imports" {
+                first = Text(100, { overflow = "truncate" })
+                last  = Text(100, { overflow = "reject_row" })
+                notes = Text(100)
+            }
+        "#;
+
+        let tables = parse_hcl(input)?;
+        let overflow = tables[0]
+            .columns()
+            .iter()
+            .map(ColumnDef::overflow)
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            overflow,
+            vec![
+                OverflowPolicy::Truncate,
+                OverflowPolicy::RejectRow,
+                OverflowPolicy::Error
+            ]
+        );
+        assert_eq!(tables[0].columns()[0].data_type(), DataType::Text(100));
+
+        let unknown: Body = hcl::from_str(r#"x = Text(10, { overflow = "wrap" })"#)?;
+        let expr = unknown.attributes().next().unwrap().expr();
+        let err = parse_overflow(expr, &Context::default()).unwrap_err();
+        assert!(err.to_string().contains("wrap"), "{}", err);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_normalization() -> Result<()> {
+        let input = r#"
+            table "people" {
+                name  = Text(40, { trim = true, collapse_whitespace = true, lowercase = true })
+                email = Text(120, { trim = true, overflow = "reject_row" })
+                notes = Text(100)
+            }
+        "#;
+
+        let tables = parse_hcl(input)?;
+        let columns = tables[0].columns();
+
+        assert_eq!(
+            columns[0].normalize(),
+            Normalization {
+                trim: true,
+                collapse_whitespace: true,
+                lowercase: true,
+            }
+        );
+        assert_eq!(
+            columns[1].normalize(),
+            Normalization {
+                trim: true,
+                ..Normalization::NONE
+            }
+        );
+        assert_eq!(columns[1].overflow(), OverflowPolicy::RejectRow);
+        assert!(columns[2].normalize().is_none());
+
+        let invalid: Body = hcl::from_str(r#"x = Text(10, { trim = "yes" })"#)?;
+        let expr = invalid.attributes().next().unwrap().expr();
+        let err = parse_normalization(expr, &Context::default()).unwrap_err();
+        assert!(err.to_string().contains("trim"), "{}", err);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_logical_types() -> Result<()> {
+        let input = r#"
+            table "users" {
+                email   = Email
+                phone   = Phone({ validate = false })
+                site    = Url({ trim = true })
+                id      = Uuid
+                nick    = Text(20)
+            }
+
+            table "legacy" {
+                validate = false
+
+                email = Email
+                phone = Phone({ validate = true })
+            }
+        "#;
+
+        let tables = parse_hcl(input)?;
+        let users = tables[0].columns();
+
+        assert_eq!(users.len(), 5);
+        assert_eq!(users[0].data_type(), DataType::Text(120));
+        assert_eq!(users[0].logical_type(), Some(LogicalType::Email));
+        assert!(users[0].check_logical_type());
+        assert_eq!(users[1].data_type(), DataType::Text(20));
+        assert_eq!(users[1].logical_type(), Some(LogicalType::Phone));
+        assert!(!users[1].check_logical_type());
+        assert_eq!(users[2].logical_type(), Some(LogicalType::Url));
+        assert!(users[2].normalize().trim);
+        assert_eq!(users[3].data_type(), DataType::Text(36));
+        assert_eq!(users[4].logical_type(), None);
+
+        let legacy = tables[1].columns();
+
+        assert_eq!(legacy.len(), 2);
+        assert!(!legacy[0].check_logical_type());
+        assert!(legacy[1].check_logical_type());
+
+        let invalid: Body = hcl::from_str(r#"x = Text(10, { validate = false })"#)?;
+        let expr = invalid.attributes().next().unwrap().expr();
+        let err = parse_check_logical_type(expr, &Context::default(), true).unwrap_err();
+        assert!(err.to_string().contains("validate"), "{}", err);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_defaults() -> Result<()> {
+        let input = r#"
+            table "orders" {
+                number  = Number({ default = sequence(1000, 1) })
+                id      = O64({ default = random_o64() })
+                placed  = Timestamp({ default = now() })
+                counter = Number({ default = sequence() })
+                note    = Text(100, { trim = true })
+            }
+        "#;
+
+        let tables = parse_hcl(input)?;
+        let defaults = tables[0]
+            .columns()
+            .iter()
+            .map(ColumnDef::default)
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            defaults,
+            vec![
+                Some(GeneratorKind::Sequence {
+                    start: 1000,
+                    step: 1
+                }),
+                Some(GeneratorKind::RandomO64),
+                Some(GeneratorKind::NowTimestamp),
+                Some(GeneratorKind::Sequence { start: 1, step: 1 }),
+                None,
+            ]
+        );
+        assert_eq!(tables[0].columns()[1].data_type(), DataType::O64);
+        assert!(tables[0].columns()[4].normalize().trim);
+
+        for (invalid, expected) in [
+            ("x = Timestamp({ default = sequence(1, 1) })", "Number"),
+            ("x = Number({ default = sequence(1, 0) })", "zero"),
+            ("x = Number({ default = uuid() })", "uuid"),
+            ("x = Number({ default = 5 })", "default"),
+        ] {
+            let body: Body = hcl::from_str(invalid)?;
+            let expr = body.attributes().next().unwrap().expr();
+            let err = parse_default(expr, &Context::default()).unwrap_err();
+            assert!(err.to_string().contains(expected), "{}", err);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_unique() -> Result<()> {
+        let input = r#"
+            table "users" {
+                email  = unique(Email)
+                handle = unique(Text(32, { lowercase = true }))
+                badge  = unique(Number({ default = sequence() }))
+                name   = Text(100)
+            }
+        "#;
+
+        let tables = parse_hcl(input)?;
+        let columns = tables[0].columns();
+
+        assert_eq!(
+            columns.iter().map(ColumnDef::unique).collect::<Vec<_>>(),
+            vec![true, true, true, false]
+        );
+        assert_eq!(columns[0].logical_type(), Some(LogicalType::Email));
+        assert_eq!(columns[1].data_type(), DataType::Text(32));
+        assert!(columns[1].normalize().lowercase);
+        assert_eq!(
+            columns[2].default(),
+            Some(GeneratorKind::Sequence { start: 1, step: 1 })
+        );
+
+        let body: Body = hcl::from_str("x = unique(Email, Phone)")?;
+        let expr = body.attributes().next().unwrap().expr();
+        let err = parse_unique(expr).unwrap_err();
+        assert!(err.to_string().contains("single column type"), "{}", err);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_seed() -> Result<()> {
+        let id = O64::new();
+        let input = format!(
+            r#"
+            table "users" {{
+                id    = O64
+                email = Email
+                first = Text(20)
+                age   = Number
+
+                rows {{
+                    row {{
+                        email = "a@b.c"
+                        first = "A"
+                    }}
+                    row {{
+                        id  = "{}"
+                        age = 40
+                    }}
+                }}
+
+                seed = [{{ email = "d@e.f", age = null }}]
+            }}
+        "#,
+            id
+        );
+
+        let tables = parse_hcl(&input)?;
+        let seed = tables[0].seed();
+        let text = |s: &str, cap: u32| DataValue::try_from_any(DataType::Text(cap), s.to_string());
+
+        assert_eq!(seed.len(), 3);
+        assert_eq!(
+            seed[0].values(),
+            &[None, Some(text("a@b.c", 120)?), Some(text("A", 20)?), None]
+        );
+        assert_eq!(
+            seed[1].values(),
+            &[
+                Some(DataValue::O64(id)),
+                None,
+                None,
+                Some(DataValue::try_from_any(DataType::Number, 40u64)?)
+            ]
+        );
+        assert_eq!(
+            seed[2].values(),
+            &[None, Some(text("d@e.f", 120)?), None, None]
+        );
+
+        for (invalid, expected) in [
+            (
+                r#"table "users" {
+                    first = Text(20)
+                    rows {
+                        row { last = "B" }
+                    }
+                }"#,
+                "seed row of table users sets unknown column last",
+            ),
+            (
+                r#"table "users" {
+                    first = Text(20)
+                    seed = [{ nick = "B" }]
+                }"#,
+                "seed row of table users sets unknown column nick",
+            ),
+            (
+                r#"table "users" {
+                    first = Text(2)
+                    seed = [{ first = "Bob" }]
+                }"#,
+                "invalid seed value for column first of table users",
+            ),
+            (
+                r#"table "users" {
+                    first = Text(20)
+                    rows {
+                        user { first = "B" }
+                    }
+                }"#,
+                "only contain row blocks",
+            ),
+        ] {
+            let body: Body = hcl::from_str(invalid)?;
+            let block = body.blocks().next().unwrap();
+            let err = TableDef::try_from((block, &Context::default())).unwrap_err();
+            assert!(err.to_string().contains(expected), "{:#}", err);
+        }
+
+        Ok(())
+    }
+
+    /// Writes `files` into a fresh directory under the system temp dir.
+    fn fixture(name: &str, files: &[(&str, &str)]) -> Result<PathBuf> {
+        let dir = std::env::temp_dir().join(format!("hcl_schemas_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        for (file, contents) in files {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap())?;
+            fs::write(path, contents)?;
+        }
+
+        Ok(dir)
+    }
+
+    #[test]
+    fn test_parse_hcl_file_includes() -> Result<()> {
+        let dir = fixture(
+            "includes",
+            &[
+                (
+                    "schema.hcl",
+                    r#"
+                        include "tables/users.hcl" {}
+
+                        table "orders" {
+                            user  = Email
+                            total = Number
+                        }
+                    "#,
+                ),
+                (
+                    "tables/users.hcl",
+                    r#"
+                        include = ["../shared/audit.hcl"]
+
+                        table "users" {
+                            email = Email
+                        }
+                    "#,
+                ),
+                (
+                    "shared/audit.hcl",
+                    r#"
+                        table "audit" {
+                            at = Timestamp
+                        }
+                    "#,
+                ),
+            ],
+        )?;
+
+        let tables = parse_hcl_file(dir.join("schema.hcl"))?;
+
+        assert_eq!(
+            tables.iter().map(TableDef::name).collect::<Vec<_>>(),
+            vec!["audit", "users", "orders"]
+        );
+        assert!(tables[0].source().as_path().ends_with("shared/audit.hcl"));
+        assert!(tables[2].source().as_path().ends_with("schema.hcl"));
+
+        fs::remove_dir_all(dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_hcl_file_cycle() -> Result<()> {
+        let dir = fixture(
+            "cycle",
+            &[
+                ("a.hcl", r#"include = "b.hcl""#),
+                ("b.hcl", r#"include = "c.hcl""#),
+                ("c.hcl", r#"include = "a.hcl""#),
+            ],
+        )?;
+
+        let err = parse_hcl_file(dir.join("a.hcl")).unwrap_err().to_string();
+
+        assert!(err.starts_with("include cycle"), "{}", err);
+        assert_eq!(err.matches("a.hcl").count(), 2, "{}", err);
+        assert!(
+            err.contains("b.hcl -> ") && err.contains("c.hcl -> "),
+            "{}",
+            err
+        );
+
+        fs::remove_dir_all(dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_hcl_file_duplicate_table() -> Result<()> {
+        let dir = fixture(
+            "duplicate",
+            &[
+                (
+                    "a.hcl",
+                    r#"
+                        include = "b.hcl"
+                        table "users" {
+                            email = Email
+                        }
+                    "#,
+                ),
+                (
+                    "b.hcl",
+                    r#"
+                        table "users" {
+                            phone = Phone
+                        }
+                    "#,
+                ),
+            ],
+        )?;
+
+        let err = parse_hcl_file(dir.join("a.hcl")).unwrap_err().to_string();
+
+        assert!(err.contains("table users"), "{}", err);
+        assert!(err.contains("a.hcl") && err.contains("b.hcl"), "{}", err);
+
+        fs::remove_dir_all(dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate() -> Result<()> {
+        use dbexp::{object_ids::TableId, store::StoreLocked, values::DataValue};
+        use mem_table::{DataConfig, TableConfig};
+
+        let dir = fixture(
+            "validate",
+            &[
+                (
+                    "schema.hcl",
+                    r#"
+                        table "users" {
+                            email = Email
+                            age   = Number
+                            primary_key = ["email"]
+                        }
+
+                        table "orders" {
+                            total = Number
+                        }
+                    "#,
+                ),
+                (
+                    "drift.hcl",
+                    r#"
+                        table "users" {
+                            email = Email
+                            age   = Text(10)
+                            primary_key = ["email"]
+                        }
+
+                        table "audit" {
+                            at = Timestamp
+                        }
+                    "#,
+                ),
+            ],
+        )?;
+
+        let users = [
+            DataConfig::new(LogicalType::Email.base_type()).with_logical_type(LogicalType::Email),
+            DataConfig::new(DataType::Number),
+        ];
+        let catalog = vec![
+            CatalogTable {
+                name: "users".to_string(),
+                id: TableId::new(),
+                config: TableConfig::new_persisted_relative(users, "tables/users")?
+                    .with_primary_key([0])?,
+            },
+            CatalogTable {
+                name: "orders".to_string(),
+                id: TableId::new(),
+                config: TableConfig::new_persisted_relative(
+                    [DataConfig::new(DataType::Number)],
+                    "tables/orders",
+                )?,
+            },
+        ];
+
+        let schema = dir.join("schema.hcl");
+
+        {
+            let db = open(&schema, &catalog, Some(&dir), false)?;
+
+            // the root stays locked for as long as the tables are open
+            let err = open(&schema, &catalog, Some(&dir), false).unwrap_err();
+            assert!(err.downcast_ref::<StoreLocked>().is_some(), "{}", err);
+
+            db.table("users")?.insert_one(vec![
+                Some(DataValue::try_from_any(
+                    LogicalType::Email.base_type(),
+                    "a@example.com",
+                )?),
+                Some(DataValue::try_from_any(DataType::Number, 30)?),
+            ])?;
+
+            let snapshot = db.read_snapshot()?;
+            assert_eq!(snapshot.len(), 2);
+            assert_eq!(snapshot.get("users").map(|users| users.seq()), Some(1));
+
+            for (_, table) in db.opened() {
+                table.close()?;
+            }
+        }
+
+        assert!(validate(&schema, &catalog, Some(&dir))?.is_ok());
+
+        let drift = validate(dir.join("drift.hcl"), &catalog, Some(&dir))?;
+
+        assert_eq!(
+            drift.of_kind(IssueKind::SchemaDrift).count(),
+            3,
+            "{}",
+            drift
+        );
+        assert_eq!(drift.issues.len(), 3, "{}", drift);
+        assert!(drift.issues.iter().any(|issue| issue.table == "audit"));
+        assert!(drift.ensure_startable(false).is_err());
+        assert!(drift.ensure_startable(true).is_ok());
+        assert_eq!(
+            open(dir.join("drift.hcl"), &catalog, Some(&dir), true)?
+                .tables
+                .len(),
+            2
+        );
+
+        let orders = dir.join("tables/orders");
+        fs::write(orders.join("column_7.store"), b"")?;
+        fs::remove_file(orders.join("records.store"))?;
+
+        let records = fs::OpenOptions::new()
+            .write(true)
+            .open(dir.join("tables/users/records.store"))?;
+        records.set_len(records.metadata()?.len() - 1)?;
+
+        let broken = validate(&schema, &catalog, Some(&dir))?;
+
+        assert_eq!(
+            broken.of_kind(IssueKind::SchemaDrift).count(),
+            0,
+            "{}",
+            broken
+        );
+        assert_eq!(broken.of_kind(IssueKind::Files).count(), 2, "{}", broken);
+        assert_eq!(broken.of_kind(IssueKind::Format).count(), 1, "{}", broken);
+        assert!(broken.ensure_startable(true).is_err());
+        assert!(open_eager(&schema, &catalog, Some(&dir), true).is_err());
+
+        // opened lazily, the broken tables only fail once they're asked for
+        let db = open(&schema, &catalog, Some(&dir), true)?;
+        let err = db.table("users").unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("Format, table users"),
+            "{:#}",
+            err
+        );
+        assert!(db.table("orders").is_err());
+        drop(db);
+
+        // a store file of another table is just as incompatible as a damaged one
+        let moved = vec![CatalogTable {
+            id: TableId::new(),
+            ..catalog[0].clone()
+        }];
+        let moved = validate(&schema, &moved, Some(&dir))?;
+
+        assert_eq!(moved.of_kind(IssueKind::Format).count(), 3, "{}", moved);
+
+        fs::remove_dir_all(dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rename_table() -> Result<()> {
+        use dbexp::{object_ids::TableId, values::DataValue};
+        use mem_table::{DataConfig, Table, TableConfig};
+
+        let dir = fixture(
+            "rename",
+            &[(
+                "schema.hcl",
+                r#"
+                    table "users" {
+                        email = Email
+                    }
+
+                    table "orders" {
+                        total = Number
+                    }
+                "#,
+            )],
+        )?;
+
+        let email = |email: &str| -> Result<_> {
+            Ok(vec![Some(DataValue::try_from_any(
+                LogicalType::Email.base_type(),
+                email.to_string(),
+            )?)])
+        };
+        let mut catalog = vec![
+            CatalogTable {
+                name: "users".to_string(),
+                id: TableId::new(),
+                config: TableConfig::new_persisted_relative(
+                    [DataConfig::new(LogicalType::Email.base_type())
+                        .with_logical_type(LogicalType::Email)],
+                    "tables/users",
+                )?,
+            },
+            CatalogTable {
+                name: "orders".to_string(),
+                id: TableId::new(),
+                config: TableConfig::new_persisted_relative(
+                    [DataConfig::new(DataType::Number)],
+                    "tables/orders",
+                )?,
+            },
+        ];
+
+        let mut db = open(dir.join("schema.hcl"), &catalog, Some(&dir), false)?;
+        let users = db.table("users")?.clone();
+
+        users.insert_one(email("a@example.com")?)?;
+
+        let err = db
+            .rename_table(&mut catalog, "users", "orders", None)
+            .unwrap_err();
+        assert!(err.to_string().contains("already"), "{}", err);
+        assert!(db.rename_table(&mut catalog, "users", "a/b", None).is_err());
+        assert!(db
+            .rename_table(&mut catalog, "nobody", "people", None)
+            .is_err());
+
+        // a move failing halfway puts back the files moved before it
+        FAIL_MOVE.set(Some(1));
+        let err = db
+            .rename_table(&mut catalog, "users", "people", None)
+            .unwrap_err();
+        FAIL_MOVE.set(None);
+
+        assert!(format!("{:#}", err).contains("fail point"), "{:#}", err);
+        assert_eq!(catalog[0].name, "users");
+        assert_eq!(db.tables[0].0, "users");
+        assert!(dir.join("tables/users/records.store").exists());
+        assert!(!dir.join("tables/people").exists());
+
+        db.rename_table(&mut catalog, "users", "people", None)?;
+
+        assert_eq!(catalog[0].name, "people");
+        assert_eq!(
+            catalog[0].config.persistance.as_path(),
+            Path::new("tables/people")
+        );
+        assert!(dir.join("tables/people/records.store").exists());
+        assert!(!dir.join("tables/users").exists());
+        assert_eq!(db.tables[0].0, "people");
+        assert!(db.read_snapshot()?.get("people").is_some());
+
+        // the handle opened under the old name writes where the table went
+        users.insert_one(email("b@example.com")?)?;
+        users.meta()?.set("renamed", DataValue::Bool(true))?;
+
+        for (_, table) in db.opened() {
+            table.close()?;
+        }
+
+        drop(db);
+
+        let people = Table::new_in(catalog[0].id, catalog[0].config, None, &dir)?;
+        assert_eq!(people.row_count(), 2);
+        assert_eq!(people.meta()?.get("renamed")?, Some(DataValue::Bool(true)));
+        people.close()?;
+
+        fs::remove_dir_all(dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_audit_log() -> Result<()> {
+        use dbexp::{object_ids::TableId, values::DataValue};
+        use mem_table::{DataConfig, TableConfig};
+        use primitives::Timestamp;
+
+        let dir = fixture(
+            "audit",
+            &[(
+                "schema.hcl",
+                r#"
+                    table "users" {
+                        age = Number
+                    }
+
+                    table "orders" {
+                        total = Number
+                    }
+                "#,
+            )],
+        )?;
+
+        let mut catalog = ["users", "orders"]
+            .into_iter()
+            .map(|name| {
+                Ok(CatalogTable {
+                    name: name.to_string(),
+                    id: TableId::new(),
+                    config: TableConfig::new_persisted_relative(
+                        [DataConfig::new(DataType::Number)],
+                        format!("tables/{}", name),
+                    )?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let started = Timestamp::new();
+
+        {
+            let mut db = open(dir.join("schema.hcl"), &catalog, Some(&dir), false)?;
+
+            db.table("orders")?
+                .insert_one(vec![Some(DataValue::try_from_any(DataType::Number, 1)?)])?;
+
+            db.rename_table(&mut catalog, "users", "people", Some("ops"))?;
+            assert!(db
+                .rename_table(&mut catalog, "people", "orders", Some("ops"))
+                .is_err());
+            assert_eq!(db.truncate_table("orders", None)?, 1);
+
+            // an operation that never gets to its outcome leaves its intent behind
+            let crashed = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                db.audit().record(
+                    AuditOp::Truncate,
+                    "people",
+                    "",
+                    Some("ops"),
+                    || -> Result<()> { panic!("crashed halfway") },
+                )
+            }));
+            assert!(crashed.is_err());
+
+            for (_, table) in db.opened() {
+                table.close()?;
+            }
+
+            db.audit().close()?;
+        }
+
+        // the log is read back from disk once the database is opened again, with the schema file
+        // still naming the table as it was
+        let db = open(dir.join("schema.hcl"), &catalog, Some(&dir), true)?;
+        let log = db.audit_log(started)?;
+        let summary = log
+            .iter()
+            .map(|entry| {
+                (
+                    entry.seq,
+                    entry.operation,
+                    entry.table.as_str(),
+                    &entry.phase,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            summary,
+            [
+                (1, AuditOp::RenameTable, "users", &AuditPhase::Intent),
+                (
+                    2,
+                    AuditOp::RenameTable,
+                    "users",
+                    &AuditPhase::Succeeded { intent: 1 }
+                ),
+                (3, AuditOp::RenameTable, "people", &AuditPhase::Intent),
+                (4, AuditOp::RenameTable, "people", &log[3].phase),
+                (5, AuditOp::Truncate, "orders", &AuditPhase::Intent),
+                (
+                    6,
+                    AuditOp::Truncate,
+                    "orders",
+                    &AuditPhase::Succeeded { intent: 5 }
+                ),
+                (7, AuditOp::Truncate, "people", &AuditPhase::Intent),
+            ]
+        );
+
+        let AuditPhase::Failed { intent, error } = &log[3].phase else {
+            panic!("expected the rename to fail, got {:?}", log[3].phase);
+        };
+        assert_eq!(*intent, 3);
+        assert!(error.contains("already"), "{}", error);
+
+        assert_eq!(log[0].details, "to people");
+        assert_eq!(log[0].principal.as_deref(), Some("ops"));
+        assert_eq!(log[4].principal, None);
+        assert!(log.windows(2).all(|w| w[0].timestamp <= w[1].timestamp));
+
+        let later = db.audit_log(Timestamp::try_from_number(
+            log[6].timestamp.timestamp_millis() + 1,
+        )?)?;
+        assert!(later.is_empty());
+
+        for (_, table) in db.opened() {
+            table.close()?;
+        }
+
+        db.audit().close()?;
+        drop(db);
+        fs::remove_dir_all(dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lazy_open() -> Result<()> {
+        use dbexp::{object_ids::TableId, values::DataValue};
+        use mem_table::{DataConfig, TableConfig};
+
+        let names = (0..50).map(|n| format!("t{}", n)).collect::<Vec<_>>();
+        let schema = names
+            .iter()
+            .map(|name| format!("table \"{}\" {{\n  n = Number\n}}\n", name))
+            .collect::<String>();
+        let dir = fixture("lazy_open", &[("schema.hcl", schema.as_str())])?;
+        let schema = dir.join("schema.hcl");
+        let catalog = names
+            .iter()
+            .map(|name| {
+                Ok(CatalogTable {
+                    name: name.clone(),
+                    id: TableId::new(),
+                    config: TableConfig::new_persisted_relative(
+                        [DataConfig::new(DataType::Number)],
+                        format!("tables/{}", name),
+                    )?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let opened_dirs = || -> Result<usize> {
+            match fs::read_dir(dir.join("tables")) {
+                Ok(entries) => Ok(entries.count()),
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(0),
+                Err(err) => Err(err.into()),
+            }
+        };
+
+        {
+            let db = open(&schema, &catalog, Some(&dir), false)?;
+
+            // nothing but the catalog is looked at until a table is asked for
+            assert_eq!(
+                db.metrics(),
+                DatabaseMetrics {
+                    cataloged_tables: 50,
+                    opened_tables: 0,
+                }
+            );
+            assert_eq!(opened_dirs()?, 0);
+
+            db.table("t7")?
+                .insert_one(vec![Some(DataValue::try_from_any(DataType::Number, 7)?)])?;
+            assert_eq!(db.metrics().opened_tables, 1);
+            assert_eq!(opened_dirs()?, 1);
+            assert!(db.table("t50").is_err());
+
+            // concurrent first accesses share the one table opened
+            let tables = std::thread::scope(|scope| {
+                let threads = (0..4)
+                    .map(|_| scope.spawn(|| db.table("t9")))
+                    .collect::<Vec<_>>();
+
+                threads
+                    .into_iter()
+                    .map(|thread| thread.join().unwrap())
+                    .collect::<Result<Vec<_>>>()
+            })?;
+            assert!(tables.windows(2).all(|w| std::ptr::eq(w[0], w[1])));
+            assert_eq!(db.metrics().opened_tables, 2);
+
+            db.warm(&["t1", "t2", "t7"])?;
+            assert_eq!(db.metrics().opened_tables, 4);
+            assert!(db.warm(&["t3", "nobody"]).is_err());
+            assert_eq!(db.metrics().opened_tables, 5);
+
+            for (_, table) in db.opened() {
+                table.close()?;
+            }
+        }
+
+        let records = fs::OpenOptions::new()
+            .write(true)
+            .open(dir.join("tables/t7/records.store"))?;
+        records.set_len(records.metadata()?.len() - 1)?;
+
+        // a broken table fails neither the open nor the tables around it, only itself
+        {
+            let db = open(&schema, &catalog, Some(&dir), false)?;
+            assert_eq!(db.table("t1")?.row_count(), 0);
+
+            for _ in 0..2 {
+                let err = db.table("t7").unwrap_err();
+                let message = format!("{:#}", err);
+                assert!(message.contains("failed to open table t7"), "{}", message);
+                assert!(message.contains("Format, table t7"), "{}", message);
+            }
+
+            assert_eq!(db.metrics().opened_tables, 1);
+
+            for (_, table) in db.opened() {
+                table.close()?;
+            }
+        }
+
+        let report = validate(&schema, &catalog, Some(&dir))?;
+        assert_eq!(report.of_kind(IssueKind::Format).count(), 1, "{}", report);
+        assert!(open_eager(&schema, &catalog, Some(&dir), false).is_err());
+
+        fs::remove_dir_all(dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_maintenance() -> Result<()> {
+        use std::{num::NonZeroUsize, time::Duration};
+
+        use dbexp::{object_ids::TableId, values::DataValue};
+        use mem_table::{DataConfig, TableConfig};
+
+        let dir = fixture(
+            "maintenance",
+            &[(
+                "schema.hcl",
+                r#"
+                    table "a" {
+                        n = Number
+                    }
+
+                    table "b" {
+                        n = Number
+                    }
+
+                    table "c" {
+                        n = Number
+                    }
+                "#,
+            )],
+        )?;
+        let catalog = ["a", "b", "c"]
+            .into_iter()
+            .map(|name| {
+                let mut config = TableConfig::new([DataConfig::new(DataType::Number)])?;
+                config.block_capacity = NonZeroUsize::new(4).unwrap();
+
+                Ok(CatalogTable {
+                    name: name.to_string(),
+                    id: TableId::new(),
+                    config,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut db = open(dir.join("schema.hcl"), &catalog, None, false)?;
+
+        let values = |name: &str| -> Result<Vec<Option<DataValue>>> {
+            let table = db.table(name)?;
+
+            table
+                .scan_since(0)
+                .map(|(_, handle)| Ok(table.get_value(&handle, 0)?))
+                .collect()
+        };
+
+        // 40 rows in 10 blocks of their own, of which every `keep`th is left
+        let fill = |name: &str, keep: i64| -> Result<Vec<Option<DataValue>>> {
+            let table = db.table(name)?;
+            let start = table.current_seq() as i64;
+            let mut removed = Vec::new();
+
+            for n in start..start + 40 {
+                let handle = table
+                    .insert_one(vec![Some(DataValue::try_from_any(DataType::Number, n)?)])?
+                    .handle;
+
+                if n % keep != 0 {
+                    removed.push(handle);
+                }
+            }
+
+            // removed once they're all in, so none of their slots go to the rows after them
+            for handle in removed {
+                table.delete(handle)?;
+            }
+
+            values(name)
+        };
+
+        let a = fill("a", 2)?;
+        let b = fill("b", 5)?;
+        let c = fill("c", 1)?;
+
+        let maintenance = db.maintenance(MaintenanceConfig::default());
+
+        // the most fragmented store goes first, one per tick
+        let report = maintenance.tick_once()?;
+        assert!(!report.busy);
+        assert_eq!(report.compacted.len(), 1, "{:?}", report);
+        assert_eq!(report.compacted[0].table, "b");
+        assert!(report.compacted[0].before > 0.7, "{:?}", report);
+        assert!(report.compacted[0].after < 0.3, "{:?}", report);
+        assert_eq!(report.compacted[0].rows.snapshot_rows, 8);
+        assert_eq!(values("b")?, b);
+        assert_eq!(db.table("b")?.row_count(), 8);
+
+        let report = maintenance.tick_once()?;
+        assert_eq!(report.compacted.len(), 1, "{:?}", report);
+        assert_eq!(report.compacted[0].table, "a");
+        assert_eq!(values("a")?, a);
+
+        assert!(maintenance.tick_once()?.compacted.is_empty());
+        assert_eq!(values("c")?, c);
+
+        // stopped, a compaction under way gives up at a block and leaves its store as it was
+        let c = fill("c", 4)?;
+        let store = db.table("c")?.get_column_store(0)?;
+        let before = fragmentation(&store.fragmentation_report());
+        assert!(before > 0.3);
+
+        maintenance.stop();
+
+        let report = maintenance.tick_once()?;
+        assert!(report.compacted.is_empty());
+        assert_eq!(report.interrupted, 1);
+        assert_eq!(fragmentation(&store.fragmentation_report()), before);
+        assert_eq!(values("c")?, c);
+
+        let metrics = maintenance.metrics();
+        assert_eq!(metrics.ticks, 4);
+        assert_eq!(metrics.compactions, 2);
+        assert_eq!(metrics.interrupted, 1);
+        assert_eq!(metrics.failures, 0);
+        assert!(metrics.running.is_empty());
+
+        // while rows are going in, an idle only scheduler waits
+        let idle_only = db.maintenance(MaintenanceConfig {
+            idle_only: true,
+            busy_rows_per_sec: 10.0,
+            ..MaintenanceConfig::default()
+        });
+        let a = fill("a", 1)?;
+
+        assert!(idle_only.tick_once()?.busy);
+
+        let report = idle_only.tick_once()?;
+        assert!(!report.busy);
+        assert_eq!(report.compacted.len(), 1, "{:?}", report);
+        assert_eq!(report.compacted[0].table, "c");
+        assert_eq!(values("a")?, a);
+        assert_eq!(values("c")?, c);
+        assert_eq!(idle_only.metrics().busy_ticks, 1);
+
+        let config = MaintenanceConfig {
+            schedule_interval: Duration::from_millis(10),
+            ..MaintenanceConfig::default()
+        };
+
+        db.start_maintenance(config)?;
+        assert!(db.start_maintenance(config).is_err());
+        assert!(db.maintenance_metrics().is_some());
+
+        db.stop_maintenance();
+        assert!(db.maintenance_metrics().is_none());
+
+        fs::remove_dir_all(dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_persisted_maintenance() -> Result<()> {
+        use std::num::NonZeroUsize;
+
+        use dbexp::{object_ids::TableId, values::DataValue};
+        use mem_table::{DataConfig, StorageLayout, TableConfig};
+
+        let dir = fixture(
+            "persisted_maintenance",
+            &[
+                (
+                    "schema.hcl",
+                    r#"
+                        table "a" {
+                            n = Number
+                        }
+
+                        table "single" {
+                            n = Number
+                        }
+                    "#,
+                ),
+                (
+                    "single.hcl",
+                    r#"
+                        table "single" {
+                            n = Number
+                        }
+                    "#,
+                ),
+            ],
+        )?;
+        let persisted = |name: &str| -> Result<TableConfig> {
+            let mut config = TableConfig::new_persisted_relative(
+                [DataConfig::new(DataType::Number)],
+                format!("tables/{}", name),
+            )?;
+            config.block_capacity = NonZeroUsize::new(4).unwrap();
+
+            Ok(config)
+        };
+        let mut catalog = vec![
+            CatalogTable {
+                name: "a".to_string(),
+                id: TableId::new(),
+                config: persisted("a")?,
+            },
+            CatalogTable {
+                name: "single".to_string(),
+                id: TableId::new(),
+                config: persisted("single")?.with_storage_layout(StorageLayout::SingleFile),
+            },
+        ];
+
+        let values = |db: &Database| -> Result<Vec<Option<DataValue>>> {
+            let table = db.table("a")?;
+
+            table
+                .scan_since(0)
+                .map(|(_, handle)| Ok(table.get_value(&handle, 0)?))
+                .collect()
+        };
+
+        let a = {
+            let db = open(dir.join("schema.hcl"), &catalog, Some(&dir), false)?;
+            let table = db.table("a")?;
+            let mut removed = Vec::new();
+
+            for n in 0..40 {
+                let handle = table
+                    .insert_one(vec![Some(DataValue::try_from_any(DataType::Number, n)?)])?
+                    .handle;
+
+                if n % 5 != 0 {
+                    removed.push(handle);
+                }
+            }
+
+            for handle in removed {
+                table.delete(handle)?;
+            }
+
+            db.table("single")?;
+
+            let a = values(&db)?;
+
+            // the stores of persisted tables are compacted too, but not those sharing a file
+            let report = db.maintenance(MaintenanceConfig::default()).tick_once()?;
+            assert_eq!(report.compacted.len(), 1, "{:?}", report);
+            assert_eq!(report.compacted[0].table, "a");
+            assert!(!report.compacted[0].upgrade);
+            assert!(report.compacted[0].after < 0.3, "{:?}", report);
+            assert_eq!(report.skipped, ["single"]);
+            assert_eq!(values(&db)?, a);
+
+            for (_, table) in db.opened() {
+                table.close()?;
+            }
+
+            a
+        };
+
+        // flushed without checksums, the store is written again with them once the table keeps
+        // them
+        catalog[0].config = catalog[0].config.with_checksum(true);
+
+        let db = open(dir.join("schema.hcl"), &catalog, Some(&dir), false)?;
+        assert_eq!(values(&db)?, a);
+
+        let maintenance = db.maintenance(MaintenanceConfig {
+            scrub: true,
+            upgrade_format: true,
+            ..MaintenanceConfig::default()
+        });
+
+        let report = maintenance.tick_once()?;
+        assert_eq!(report.compacted.len(), 1, "{:?}", report);
+        assert!(report.compacted[0].upgrade);
+        assert_eq!(report.scrubbed.len(), 1);
+        assert!(report.scrubbed[0].report.columns[0].store.unchecked > 0);
+        assert_eq!(values(&db)?, a);
+
+        db.table("a")?.flush_all()?;
+
+        let report = maintenance.tick_once()?;
+        assert!(report.compacted.is_empty(), "{:?}", report);
+        assert!(report.scrubbed[0].report.is_ok());
+        assert_eq!(report.scrubbed[0].report.columns[0].store.unchecked, 0);
+        assert_eq!(maintenance.metrics().scrubs, 2);
+        assert_eq!(maintenance.metrics().corrupt_blocks, 0);
+
+        drop(db);
+
+        // a scheduler with nothing to compact doesn't start, unless it scrubs
+        let mut db = open(dir.join("single.hcl"), &catalog[1..], Some(&dir), false)?;
+        assert!(db.start_maintenance(MaintenanceConfig::default()).is_err());
+
+        db.start_maintenance(MaintenanceConfig {
+            scrub: true,
+            ..MaintenanceConfig::default()
+        })?;
+        db.stop_maintenance();
+
+        drop(db);
+        fs::remove_dir_all(dir)?;
+
+        Ok(())
+    }
 }
@@ -0,0 +1,539 @@
+//! Compacting the column stores of a database in the background, a few at a time. Every tick of
+//! the scheduler looks at how fragmented the column stores of the opened tables are, and rebuilds
+//! the worst ones past a threshold with `Table::rebuild_column_online`, which copies their values
+//! into a store with no gaps. The copy stops to let other threads run after every block's worth
+//! of values, and stopping the scheduler stops a compaction under way there, leaving its store as
+//! it was.
+//!
+//! Only the stores `rebuild_column_online` can rebuild are compacted, so neither record stores,
+//! primary key columns nor the stores of `SingleFile` tables are. Ticks can also scrub the stores
+//! of the opened tables, and bring stores flushed without checksums up to tables that keep them
+//! now by compacting them as well.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Arc, Mutex, OnceLock,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use dbexp::store::FragReport;
+use mem_table::{RebuildReport, StorageLayout, Table, TableConfig, TableScrubReport};
+use serde::Serialize;
+
+use crate::Database;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MaintenanceConfig {
+    /// How many stores a tick compacts at most, all at once.
+    pub max_concurrent: usize,
+    /// The `fragmentation` above which a store is compacted.
+    pub fragmentation_threshold: f64,
+    /// Skips ticks while the database is busy, see `busy_rows_per_sec`.
+    pub idle_only: bool,
+    /// The rate of inserts across the opened tables, since the tick before, from which the
+    /// database counts as busy.
+    pub busy_rows_per_sec: f64,
+    pub schedule_interval: Duration,
+    /// Scrubs the stores of the opened tables every tick, see `Table::scrub`.
+    pub scrub: bool,
+    /// Compacts the stores of tables kept `with_checksum` that have blocks flushed without one,
+    /// whatever their fragmentation, which writes every block again with its checksum.
+    pub upgrade_format: bool,
+}
+
+impl Default for MaintenanceConfig {
+    fn default() -> Self {
+        Self {
+            max_concurrent: 1,
+            fragmentation_threshold: 0.3,
+            idle_only: false,
+            busy_rows_per_sec: 100.0,
+            schedule_interval: Duration::from_secs(60),
+            scrub: false,
+            upgrade_format: false,
+        }
+    }
+}
+
+/// What a compaction stopped with when the scheduler was stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("maintenance was stopped")]
+pub struct MaintenanceStopped;
+
+/// The share of a store's blocks compacting it would free, those past the ones its live values
+/// need. A store never leaves its current block full, so that counts the empty block after the
+/// last full one, and a store just compacted is at `0.0`. `0.0` for a store without blocks.
+pub fn fragmentation(store: &FragReport) -> f64 {
+    let Some(capacity) = store
+        .blocks
+        .first()
+        .map(|block| block.capacity)
+        .filter(|capacity| *capacity > 0)
+    else {
+        return 0.0;
+    };
+
+    let live = store.blocks.iter().map(|block| block.live).sum::<usize>();
+
+    let needed = (live / capacity + 1).min(store.blocks.len());
+
+    1.0 - needed as f64 / store.blocks.len() as f64
+}
+
+/// Whether a table with `config` has a store a tick can compact.
+pub fn compactable(config: &TableConfig) -> bool {
+    let single_file =
+        !config.persistance.is_empty() && config.storage_layout == StorageLayout::SingleFile;
+
+    !single_file
+        && (0..config.columns.len())
+            .any(|column| !config.primary_key.columns().any(|key| key == column))
+}
+
+/// A column store compacted by a tick.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Compaction {
+    pub table: String,
+    pub column: usize,
+    /// The `fragmentation` of the store before it was compacted.
+    pub before: f64,
+    pub after: f64,
+    /// Whether the store was compacted for blocks flushed without a checksum, see
+    /// `MaintenanceConfig::upgrade_format`, rather than for its fragmentation.
+    pub upgrade: bool,
+    pub rows: RebuildReport,
+}
+
+/// A table scrubbed by a tick.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Scrub {
+    pub table: String,
+    pub report: TableScrubReport,
+}
+
+/// What a tick of the scheduler did, from `Maintenance::tick_once`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct TickReport {
+    /// Whether the tick was skipped for the database being busy.
+    pub busy: bool,
+    /// The stores compacted, most fragmented first.
+    pub compacted: Vec<Compaction>,
+    /// Compactions stopped by `Maintenance::stop`, whose stores are as they were.
+    pub interrupted: usize,
+    /// Compactions that failed, as `table.column: error`.
+    pub failed: Vec<String>,
+    /// The opened tables without a store a tick can compact.
+    pub skipped: Vec<String>,
+    /// With `MaintenanceConfig::scrub`, what scrubbing the opened tables found.
+    pub scrubbed: Vec<Scrub>,
+}
+
+/// A compaction under way.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CompactionProgress {
+    pub table: String,
+    pub column: usize,
+    pub values_copied: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct MaintenanceMetrics {
+    pub ticks: u64,
+    /// Ticks skipped for the database being busy.
+    pub busy_ticks: u64,
+    pub compactions: u64,
+    pub interrupted: u64,
+    pub failures: u64,
+    pub scrubs: u64,
+    /// Blocks found failing their checksum by the scrubs.
+    pub corrupt_blocks: u64,
+    pub running: Vec<CompactionProgress>,
+}
+
+#[derive(Debug)]
+struct Running {
+    table: String,
+    column: usize,
+    values_copied: Arc<AtomicU64>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    stop: AtomicBool,
+    /// When the rows inserted were last counted, and how many there were.
+    sampled: Mutex<(Instant, u64)>,
+    metrics: Mutex<MaintenanceMetrics>,
+    running: Mutex<Vec<Running>>,
+}
+
+/// The scheduler of a database's compactions, see the module docs. Made with
+/// `Database::maintenance`, and ticked by `Database::start_maintenance` or by hand with
+/// `tick_once`. Tables opened after it was made are looked at too, but it goes on calling them by
+/// the names they had then.
+#[derive(Debug, Clone)]
+pub struct Maintenance {
+    config: MaintenanceConfig,
+    tables: Vec<(String, Arc<OnceLock<Table>>)>,
+    shared: Arc<Shared>,
+}
+
+impl Maintenance {
+    fn _opened(&self) -> impl Iterator<Item = (&str, &Table)> {
+        self.tables
+            .iter()
+            .filter_map(|(name, table)| Some((name.as_str(), table.get()?)))
+    }
+
+    fn _inserted(&self) -> u64 {
+        self._opened().map(|(_, table)| table.current_seq()).sum()
+    }
+
+    /// Whether rows went in faster than `busy_rows_per_sec` since the last time it was asked.
+    fn _is_busy(&self) -> bool {
+        let inserted = self._inserted();
+        let now = Instant::now();
+        let mut sampled = self
+            .shared
+            .sampled
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let (at, before) = std::mem::replace(&mut *sampled, (now, inserted));
+        let secs = now.duration_since(at).as_secs_f64().max(f64::EPSILON);
+
+        inserted.saturating_sub(before) as f64 / secs > self.config.busy_rows_per_sec
+    }
+
+    /// Stops the compactions under way at their next block, and any started after. A scheduler
+    /// once stopped stays stopped.
+    pub fn stop(&self) {
+        self.shared.stop.store(true, Ordering::Release);
+    }
+
+    pub fn metrics(&self) -> MaintenanceMetrics {
+        let mut metrics = self
+            .shared
+            .metrics
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+
+        metrics.running = self
+            .shared
+            .running
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .map(|running| CompactionProgress {
+                table: running.table.clone(),
+                column: running.column,
+                values_copied: running.values_copied.load(Ordering::Relaxed),
+            })
+            .collect();
+
+        metrics
+    }
+
+    /// Runs one tick of the scheduler: unless it's skipped for the database being busy, scrubs the
+    /// opened tables if it's configured to, and compacts the `max_concurrent` most fragmented
+    /// column stores past the threshold, at the same time.
+    pub fn tick_once(&self) -> Result<TickReport> {
+        let mut report = TickReport::default();
+        let busy = self._is_busy();
+
+        {
+            let mut metrics = self
+                .shared
+                .metrics
+                .lock()
+                .unwrap_or_else(|e| e.into_inner());
+            metrics.ticks += 1;
+
+            if busy && self.config.idle_only {
+                metrics.busy_ticks += 1;
+                report.busy = true;
+
+                return Ok(report);
+            }
+        }
+
+        let mut candidates = Vec::new();
+
+        for (name, table) in self._opened() {
+            if table.is_closed() {
+                continue;
+            }
+
+            let upgrade = self.config.upgrade_format && table.config().checksum;
+            let scrubbed = match self.config.scrub || upgrade {
+                true => Some(table.scrub()?),
+                false => None,
+            };
+
+            if let (true, Some(scrubbed)) = (self.config.scrub, scrubbed.as_ref()) {
+                let corrupt = std::iter::once(&scrubbed.records)
+                    .chain(scrubbed.columns.iter().map(|column| &column.store))
+                    .map(|store| store.corrupt.len() as u64)
+                    .sum::<u64>();
+
+                let mut metrics = self
+                    .shared
+                    .metrics
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner());
+                metrics.scrubs += 1;
+                metrics.corrupt_blocks += corrupt;
+
+                report.scrubbed.push(Scrub {
+                    table: name.to_string(),
+                    report: scrubbed.clone(),
+                });
+            }
+
+            if !compactable(table.config()) {
+                report.skipped.push(name.to_string());
+                continue;
+            }
+
+            for column in 0..table.config().columns.len() {
+                if table
+                    .config()
+                    .primary_key
+                    .columns()
+                    .any(|key| key == column)
+                {
+                    continue;
+                }
+
+                let store = table.get_column_store(column)?.fragmentation_report();
+                let before = fragmentation(&store);
+                let unchecked = scrubbed.as_ref().is_some_and(|scrubbed| {
+                    scrubbed
+                        .columns
+                        .iter()
+                        .any(|scrubbed| scrubbed.column == column && scrubbed.store.unchecked > 0)
+                });
+
+                if before > self.config.fragmentation_threshold || (upgrade && unchecked) {
+                    let capacity = store.blocks[0].capacity;
+                    let upgrade = before <= self.config.fragmentation_threshold;
+                    candidates.push((before, name, table, column, capacity, upgrade));
+                }
+            }
+        }
+
+        candidates.sort_by(|a, b| b.0.total_cmp(&a.0));
+        candidates.truncate(self.config.max_concurrent.max(1));
+
+        let outcomes = thread::scope(|scope| {
+            let compactions = candidates
+                .iter()
+                .map(|&(before, name, table, column, capacity, upgrade)| {
+                    scope.spawn(move || {
+                        self._compact(name, table, column, before, capacity, upgrade)
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            compactions
+                .into_iter()
+                .map(|compaction| {
+                    compaction
+                        .join()
+                        .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+                })
+                .collect::<Vec<_>>()
+        });
+
+        let mut metrics = self
+            .shared
+            .metrics
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+
+        for (outcome, (_, name, _, column, ..)) in outcomes.into_iter().zip(candidates) {
+            match outcome {
+                Ok(compaction) => {
+                    metrics.compactions += 1;
+                    report.compacted.push(compaction);
+                }
+                Err(err) if err.is::<MaintenanceStopped>() => {
+                    metrics.interrupted += 1;
+                    report.interrupted += 1;
+                }
+                Err(err) => {
+                    metrics.failures += 1;
+                    report
+                        .failed
+                        .push(format!("{}.{}: {:#}", name, column, err));
+                }
+            }
+        }
+
+        Ok(report)
+    }
+
+    fn _compact(
+        &self,
+        name: &str,
+        table: &Table,
+        column: usize,
+        before: f64,
+        block_capacity: usize,
+        upgrade: bool,
+    ) -> Result<Compaction> {
+        let values_copied = Arc::new(AtomicU64::new(0));
+
+        self.shared
+            .running
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(Running {
+                table: name.to_string(),
+                column,
+                values_copied: values_copied.clone(),
+            });
+
+        let rows = table.rebuild_column_online(column, |value| {
+            let copied = values_copied.fetch_add(1, Ordering::Relaxed) + 1;
+
+            // a failed transform leaves the column as it was, which is what makes this safe
+            if copied.is_multiple_of(block_capacity as u64) {
+                if self.shared.stop.load(Ordering::Acquire) {
+                    return Err(MaintenanceStopped.into());
+                }
+
+                thread::yield_now();
+            }
+
+            Ok(value.clone())
+        });
+
+        self.shared
+            .running
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|running| !(running.table == name && running.column == column));
+
+        let rows = rows.map_err(|err| match err.downcast_ref::<MaintenanceStopped>() {
+            Some(stopped) => anyhow::Error::new(*stopped),
+            None => err.into(),
+        })?;
+
+        Ok(Compaction {
+            table: name.to_string(),
+            column,
+            before,
+            after: fragmentation(&table.get_column_store(column)?.fragmentation_report()),
+            upgrade,
+            rows,
+        })
+    }
+}
+
+/// Ticks a `Maintenance` every `schedule_interval` on a thread of its own, see
+/// `Database::start_maintenance`. Stops once dropped, interrupting a compaction under way.
+#[derive(Debug)]
+pub(crate) struct MaintenanceWorker {
+    maintenance: Maintenance,
+    stop: mpsc::Sender<()>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl Drop for MaintenanceWorker {
+    fn drop(&mut self) {
+        self.maintenance.stop();
+        let _ = self.stop.send(());
+
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl Database {
+    /// A scheduler of compactions for the tables of the database, to be ticked by hand, see
+    /// `Maintenance::tick_once`.
+    pub fn maintenance(&self, config: MaintenanceConfig) -> Maintenance {
+        let maintenance = Maintenance {
+            config,
+            tables: self
+                .tables
+                .iter()
+                .map(|(name, table)| (name.clone(), table.opened.clone()))
+                .collect(),
+            shared: Arc::new(Shared {
+                stop: AtomicBool::new(false),
+                sampled: Mutex::new((Instant::now(), 0)),
+                metrics: Mutex::new(MaintenanceMetrics::default()),
+                running: Mutex::new(Vec::new()),
+            }),
+        };
+
+        *maintenance
+            .shared
+            .sampled
+            .lock()
+            .unwrap_or_else(|e| e.into_inner()) = (Instant::now(), maintenance._inserted());
+
+        maintenance
+    }
+
+    /// Starts ticking a scheduler of compactions every `schedule_interval`, see the module docs.
+    /// A tick that fails is tried again the next time. Fails if one is running already, or if it
+    /// would have nothing to do, with no table it could compact and no scrub configured.
+    pub fn start_maintenance(&mut self, config: MaintenanceConfig) -> Result<Maintenance> {
+        if self.maintenance.is_some() {
+            anyhow::bail!("maintenance is running already");
+        }
+
+        let compacts = self
+            .tables
+            .iter()
+            .any(|(_, table)| compactable(&table.config));
+
+        if !config.scrub && !compacts {
+            anyhow::bail!("none of the tables has a store maintenance could compact");
+        }
+
+        let maintenance = self.maintenance(config);
+        let (stop, stopped) = mpsc::channel();
+
+        let worker = thread::spawn({
+            let maintenance = maintenance.clone();
+
+            move || {
+                while let Err(RecvTimeoutError::Timeout) =
+                    stopped.recv_timeout(config.schedule_interval)
+                {
+                    let _ = maintenance.tick_once();
+                }
+            }
+        });
+
+        self.maintenance = Some(MaintenanceWorker {
+            maintenance: maintenance.clone(),
+            stop,
+            worker: Some(worker),
+        });
+
+        Ok(maintenance)
+    }
+
+    /// Stops the scheduler started by `start_maintenance`, interrupting a compaction under way at
+    /// its next block, and waits for it. Does nothing if none is running.
+    pub fn stop_maintenance(&mut self) {
+        self.maintenance = None;
+    }
+
+    /// What the scheduler started by `start_maintenance` has done, if one is running.
+    pub fn maintenance_metrics(&self) -> Option<MaintenanceMetrics> {
+        self.maintenance
+            .as_ref()
+            .map(|worker| worker.maintenance.metrics())
+    }
+}
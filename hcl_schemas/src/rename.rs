@@ -0,0 +1,192 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use primitives::InternalPath;
+
+use crate::{AuditOp, CatalogTable, Database};
+
+/// Table names end up as directory names, so they're kept to what any file system takes.
+fn check_table_name(name: &str) -> Result<()> {
+    if name.is_empty() {
+        anyhow::bail!("a table name can't be empty");
+    }
+
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        anyhow::bail!(
+            "table name {:?} may only have ASCII letters, digits, `_` and `-`",
+            name
+        );
+    }
+
+    Ok(())
+}
+
+/// Moves every entry of `from` into `to`, one at a time. If a move fails, the entries moved so
+/// far are moved back and `to` is removed, so the table is left in one directory or the other.
+fn move_entries(from: &Path, to: &Path) -> Result<()> {
+    let mut entries = fs::read_dir(from)?
+        .map(|entry| Ok(entry?.file_name()))
+        .collect::<Result<Vec<_>>>()?;
+    entries.sort();
+
+    fs::create_dir(to)?;
+
+    let mut moved = Vec::<(PathBuf, PathBuf)>::with_capacity(entries.len());
+
+    for name in entries {
+        let (src, dest) = (from.join(&name), to.join(&name));
+
+        #[cfg(test)]
+        let res = crate::tests::fail_move(moved.len()).and_then(|()| Ok(fs::rename(&src, &dest)?));
+        #[cfg(not(test))]
+        let res = fs::rename(&src, &dest).map_err(anyhow::Error::from);
+
+        if let Err(error) = res {
+            for (src, dest) in moved.into_iter().rev() {
+                // if this fails too, the error says which file was left behind
+                fs::rename(&dest, &src).with_context(|| {
+                    format!(
+                        "failed to move {} back to {} after: {}",
+                        dest.display(),
+                        src.display(),
+                        error
+                    )
+                })?;
+            }
+
+            let _ = fs::remove_dir(to);
+
+            return Err(error.context(format!(
+                "failed to move {} to {}",
+                src.display(),
+                dest.display()
+            )));
+        }
+
+        moved.push((src, dest));
+    }
+
+    fs::remove_dir(from)?;
+
+    Ok(())
+}
+
+/// Renames the table `old` of `catalog` to `new`. A persisted table whose directory is named
+/// after it has the directory moved along, with relative paths resolved against `root`, and
+/// keeps its files where they were if any of them can't be moved. The table's id doesn't change,
+/// so neither do its stores.
+///
+/// A table open elsewhere has to have opened all of its stores first, see
+/// `Table::open_all_stores`, or it would look for the ones it hasn't where they no longer are.
+/// `Database::rename_table` takes care of it for its own tables.
+pub fn rename_table(
+    catalog: &mut [CatalogTable],
+    root: Option<&Path>,
+    old: &str,
+    new: &str,
+) -> Result<()> {
+    check_table_name(new)?;
+
+    if catalog.iter().any(|table| table.name == new) {
+        anyhow::bail!("there already is a table named {}", new);
+    }
+
+    let table = catalog
+        .iter_mut()
+        .find(|table| table.name == old)
+        .ok_or_else(|| anyhow::anyhow!("there is no table named {}", old))?;
+
+    let persistance = table.config.persistance;
+    let named_after = persistance
+        .as_path()
+        .file_name()
+        .is_some_and(|name| name == old);
+
+    if !persistance.is_empty() && named_after {
+        let renamed = persistance.as_path().with_file_name(new);
+        let renamed = match persistance.is_relative() {
+            true => InternalPath::relative(renamed)?,
+            false => InternalPath::new(renamed)?,
+        };
+
+        let resolve = |path: InternalPath| match root {
+            Some(root) => Ok(path.resolve(root)),
+            None if path.is_relative() => anyhow::bail!(
+                "persistance path {:?} is relative but no database root was given",
+                path
+            ),
+            None => Ok(path.as_path().to_path_buf()),
+        };
+        let (from, to) = (resolve(persistance)?, resolve(renamed)?);
+
+        if to.exists() {
+            anyhow::bail!("{} is in the way of renaming {}", to.display(), old);
+        }
+
+        // a table that hasn't written anything yet has no directory to move
+        if from.is_dir() {
+            move_entries(&from, &to)?;
+        }
+
+        table.config.persistance = renamed;
+    }
+
+    table.name = new.to_string();
+
+    Ok(())
+}
+
+impl Database {
+    /// Renames the table `old` in `catalog` and among the database's tables, see `rename_table`.
+    /// An opened table keeps its handles, which go on working under the new name. The rename is
+    /// recorded in the audit log as asked for by `principal`.
+    pub fn rename_table(
+        &mut self,
+        catalog: &mut [CatalogTable],
+        old: &str,
+        new: &str,
+        principal: Option<&str>,
+    ) -> Result<()> {
+        let audit = self.audit.clone();
+        let details = format!("to {}", new);
+
+        audit.record(AuditOp::RenameTable, old, &details, principal, || {
+            self._rename_table(catalog, old, new)
+        })
+    }
+
+    fn _rename_table(&mut self, catalog: &mut [CatalogTable], old: &str, new: &str) -> Result<()> {
+        if self.tables.iter().any(|(name, _)| name == new) {
+            anyhow::bail!("there already is a table named {}", new);
+        }
+
+        let idx = self
+            .tables
+            .iter()
+            .position(|(name, _)| name == old)
+            .ok_or_else(|| anyhow::anyhow!("there is no table named {}", old))?;
+
+        // a table not opened yet has no handles to keep working, and opens where it went
+        if let Some(table) = self.tables[idx].1.get() {
+            if !table.config().persistance.is_empty() {
+                table.open_all_stores()?;
+            }
+        }
+
+        rename_table(catalog, self.root.as_deref(), old, new)?;
+
+        if let Some(renamed) = catalog.iter().find(|table| table.name == new) {
+            self.tables[idx].1.config = renamed.config;
+        }
+
+        self.tables[idx].0 = new.to_string();
+
+        Ok(())
+    }
+}
@@ -0,0 +1,491 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use anyhow::{Context, Result};
+use dbexp::{object_ids::TableId, store::DirLock};
+use indexmap::IndexMap;
+use mem_table::{snapshot_tables, LogicalType, ReadSnapshot, StoreFileIssue, Table, TableConfig};
+use serde::Serialize;
+
+use crate::{maintenance::MaintenanceWorker, parse_hcl_file, AuditLog, TableDef};
+
+/// A table as the database last recorded it, which the schema file and the files on disk are
+/// checked against. Column names are compared where the config has them.
+#[derive(Debug, Clone)]
+pub struct CatalogTable {
+    pub name: String,
+    pub id: TableId,
+    pub config: TableConfig,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IssueKind {
+    /// The schema file and the catalog disagree about a table.
+    SchemaDrift,
+    /// A store file is missing, or one is there that no column would open.
+    Files,
+    /// A store file can't be opened as the store the catalog describes.
+    Format,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Issue {
+    pub kind: IssueKind,
+    pub table: String,
+    pub detail: String,
+}
+
+impl std::fmt::Display for Issue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}, table {}: {}", self.kind, self.table, self.detail)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValidationReport {
+    pub issues: Vec<Issue>,
+}
+
+impl std::fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} issues", self.issues.len())?;
+
+        for issue in self.issues.iter() {
+            writeln!(f, "  {}", issue)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl ValidationReport {
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    pub fn of_kind(&self, kind: IssueKind) -> impl Iterator<Item = &Issue> {
+        self.issues.iter().filter(move |issue| issue.kind == kind)
+    }
+
+    /// Fails if anything was found that the database shouldn't start with. Missing, extra and
+    /// incompatible files always are, schema drift only when it isn't allowed.
+    pub fn ensure_startable(&self, allow_drift: bool) -> Result<()> {
+        let errors = self
+            .issues
+            .iter()
+            .filter(|issue| !(allow_drift && issue.kind == IssueKind::SchemaDrift))
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+
+        if !errors.is_empty() {
+            anyhow::bail!("refusing to start: {}", errors.join("; "));
+        }
+
+        Ok(())
+    }
+
+    fn push(&mut self, kind: IssueKind, table: &str, detail: impl ToString) {
+        self.issues.push(Issue {
+            kind,
+            table: table.to_string(),
+            detail: detail.to_string(),
+        });
+    }
+}
+
+fn diff_table(def: &TableDef, config: &TableConfig, report: &mut ValidationReport) {
+    let name = def.name();
+    let columns = def.columns();
+
+    if columns.len() != config.columns.len() {
+        report.push(
+            IssueKind::SchemaDrift,
+            name,
+            format!(
+                "the schema has {} columns, the catalog {}",
+                columns.len(),
+                config.columns.len()
+            ),
+        );
+    }
+
+    for (idx, (column, catalog)) in columns
+        .iter()
+        .zip((0..config.columns.len()).filter_map(|idx| config.columns.get(idx)))
+        .enumerate()
+    {
+        if let Some(catalog_name) = catalog.name {
+            if catalog_name != *column.name() {
+                report.push(
+                    IssueKind::SchemaDrift,
+                    name,
+                    format!(
+                        "column {} is {} in the schema but {} in the catalog",
+                        idx,
+                        column.name().as_str(),
+                        catalog_name.as_str()
+                    ),
+                );
+            }
+        }
+
+        if catalog.data_type.into_inner() != column.data_type() {
+            report.push(
+                IssueKind::SchemaDrift,
+                name,
+                format!(
+                    "column {} is {:?} in the schema but {:?} in the catalog",
+                    column.name().as_str(),
+                    column.data_type(),
+                    catalog.data_type.into_inner()
+                ),
+            );
+        }
+
+        if catalog.overflow != column.overflow() {
+            report.push(
+                IssueKind::SchemaDrift,
+                name,
+                format!(
+                    "column {} overflows with {:?} in the schema but {:?} in the catalog",
+                    column.name().as_str(),
+                    column.overflow(),
+                    catalog.overflow
+                ),
+            );
+        }
+
+        if catalog.normalize != column.normalize() {
+            report.push(
+                IssueKind::SchemaDrift,
+                name,
+                format!(
+                    "column {} is normalized with {:?} in the schema but {:?} in the catalog",
+                    column.name().as_str(),
+                    column.normalize(),
+                    catalog.normalize
+                ),
+            );
+        }
+
+        if catalog.logical_type != column.logical_type()
+            || (column.logical_type().is_some()
+                && catalog.check_logical_type != column.check_logical_type())
+        {
+            let describe = |logical_type: Option<LogicalType>, checked: bool| match logical_type {
+                Some(logical_type) if checked => format!("checked {}", logical_type),
+                Some(logical_type) => format!("unchecked {}", logical_type),
+                None => "plain text".to_string(),
+            };
+
+            report.push(
+                IssueKind::SchemaDrift,
+                name,
+                format!(
+                    "column {} is {} in the schema but {} in the catalog",
+                    column.name().as_str(),
+                    describe(column.logical_type(), column.check_logical_type()),
+                    describe(catalog.logical_type, catalog.check_logical_type)
+                ),
+            );
+        }
+
+        if catalog.unique != column.unique() {
+            let describe = |unique: bool| if unique { "unique" } else { "not unique" };
+
+            report.push(
+                IssueKind::SchemaDrift,
+                name,
+                format!(
+                    "column {} is {} in the schema but {} in the catalog",
+                    column.name().as_str(),
+                    describe(column.unique()),
+                    describe(catalog.unique)
+                ),
+            );
+        }
+
+        if catalog.default != column.default() {
+            report.push(
+                IssueKind::SchemaDrift,
+                name,
+                format!(
+                    "column {} defaults to {:?} in the schema but {:?} in the catalog",
+                    column.name().as_str(),
+                    column.default(),
+                    catalog.default
+                ),
+            );
+        }
+    }
+
+    let catalog_key = config.primary_key.columns().collect::<Vec<_>>();
+
+    if def.primary_key_columns() != catalog_key {
+        report.push(
+            IssueKind::SchemaDrift,
+            name,
+            format!(
+                "the primary key is columns {:?} in the schema but {:?} in the catalog",
+                def.primary_key_columns(),
+                catalog_key
+            ),
+        );
+    }
+}
+
+/// Checks the schema file at `schema_path`, the `catalog` and the store files of its tables
+/// against each other, with relative store paths resolved against `root`. Nothing is opened or
+/// changed.
+pub fn validate(
+    schema_path: impl AsRef<Path>,
+    catalog: &[CatalogTable],
+    root: Option<&Path>,
+) -> Result<ValidationReport> {
+    let mut report = validate_schema(schema_path, catalog)?;
+
+    for table in catalog.iter() {
+        check_files(table, root, &mut report)?;
+    }
+
+    Ok(report)
+}
+
+/// The schema drift `validate` finds, without looking at any store files.
+fn validate_schema(
+    schema_path: impl AsRef<Path>,
+    catalog: &[CatalogTable],
+) -> Result<ValidationReport> {
+    let defs = parse_hcl_file(schema_path)?;
+    let mut report = ValidationReport::default();
+
+    for def in defs.iter() {
+        match catalog.iter().find(|table| table.name == def.name()) {
+            Some(table) => diff_table(def, &table.config, &mut report),
+            None => report.push(
+                IssueKind::SchemaDrift,
+                def.name(),
+                "defined in the schema but not in the catalog",
+            ),
+        }
+    }
+
+    for table in catalog.iter() {
+        if !defs.iter().any(|def| def.name() == table.name) {
+            report.push(
+                IssueKind::SchemaDrift,
+                &table.name,
+                "in the catalog but not defined in the schema",
+            );
+        }
+    }
+
+    Ok(report)
+}
+
+fn check_files(
+    table: &CatalogTable,
+    root: Option<&Path>,
+    report: &mut ValidationReport,
+) -> Result<()> {
+    for issue in table.config.check_store_files(table.id, root)? {
+        let kind = match issue {
+            StoreFileIssue::Incompatible { .. } => IssueKind::Format,
+            StoreFileIssue::Missing(_) | StoreFileIssue::Extra(_) => IssueKind::Files,
+        };
+
+        report.push(kind, &table.name, issue);
+    }
+
+    Ok(())
+}
+
+/// A table of the catalog, opened the first time it's asked for.
+#[derive(Debug)]
+pub struct LazyTable {
+    id: TableId,
+    pub(crate) config: TableConfig,
+    pub(crate) opened: Arc<OnceLock<Table>>,
+    /// Held while the table is opened, so concurrent first accesses open it only once.
+    opening: Mutex<()>,
+}
+
+impl LazyTable {
+    fn new(table: &CatalogTable) -> Self {
+        Self {
+            id: table.id,
+            config: table.config,
+            opened: Arc::new(OnceLock::new()),
+            opening: Mutex::new(()),
+        }
+    }
+
+    pub fn id(&self) -> TableId {
+        self.id
+    }
+
+    /// The table, if it's been opened.
+    pub fn get(&self) -> Option<&Table> {
+        self.opened.get()
+    }
+
+    pub fn is_opened(&self) -> bool {
+        self.opened.get().is_some()
+    }
+
+    /// Opens the table unless it already is, with its store files checked first. Failing leaves
+    /// it unopened, so the next access tries again.
+    fn _open(&self, name: &str, root: Option<&Path>) -> Result<&Table> {
+        if let Some(table) = self.opened.get() {
+            return Ok(table);
+        }
+
+        let _guard = self.opening.lock().unwrap_or_else(|e| e.into_inner());
+
+        if let Some(table) = self.opened.get() {
+            return Ok(table);
+        }
+
+        let catalog = CatalogTable {
+            name: name.to_string(),
+            id: self.id,
+            config: self.config,
+        };
+        let mut report = ValidationReport::default();
+
+        check_files(&catalog, root, &mut report)?;
+        report
+            .ensure_startable(false)
+            .with_context(|| format!("failed to open table {}", name))?;
+
+        let names = (0..self.config.columns.len())
+            .filter_map(|idx| Some((self.config.columns.get(idx)?.name?, idx)))
+            .collect::<IndexMap<_, _>>();
+        let names = (!names.is_empty()).then_some(names);
+
+        let table = match root {
+            Some(root) => Table::new_in(self.id, self.config, names, root),
+            None => Table::new(self.id, self.config, names),
+        }
+        .with_context(|| format!("failed to open table {}", name))?;
+
+        Ok(self.opened.get_or_init(|| table))
+    }
+}
+
+/// How many of a database's tables have been opened, from `Database::metrics`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct DatabaseMetrics {
+    pub cataloged_tables: usize,
+    pub opened_tables: usize,
+}
+
+/// The tables of a database opened by `open`. Its root stays locked against other processes until
+/// this is dropped, so two of them can't write to the same stores.
+#[derive(Debug)]
+pub struct Database {
+    pub tables: Vec<(String, LazyTable)>,
+    pub(crate) root: Option<PathBuf>,
+    pub(crate) audit: AuditLog,
+    pub(crate) maintenance: Option<MaintenanceWorker>,
+    _lock: Option<DirLock>,
+}
+
+impl Database {
+    /// The table `name`, opened if it isn't yet. A table whose files are broken fails with what's
+    /// wrong with them, every time it's asked for until they're fixed.
+    pub fn table(&self, name: &str) -> Result<&Table> {
+        let (_, table) = self
+            .tables
+            .iter()
+            .find(|(table, _)| table == name)
+            .ok_or_else(|| anyhow::anyhow!("there is no table named {}", name))?;
+
+        table._open(name, self.root.as_deref())
+    }
+
+    /// Opens the tables `names` ahead of their first access, stopping at the first that fails.
+    pub fn warm(&self, names: &[&str]) -> Result<()> {
+        for name in names {
+            self.table(name)?;
+        }
+
+        Ok(())
+    }
+
+    /// Every table opened so far, by name.
+    pub fn opened(&self) -> impl Iterator<Item = (&str, &Table)> {
+        self.tables
+            .iter()
+            .filter_map(|(name, table)| Some((name.as_str(), table.get()?)))
+    }
+
+    pub fn metrics(&self) -> DatabaseMetrics {
+        DatabaseMetrics {
+            cataloged_tables: self.tables.len(),
+            opened_tables: self.opened().count(),
+        }
+    }
+
+    /// Snapshots every table at the same instant, so a report reading several of them sees one
+    /// consistent cut, opening the ones that aren't yet. See `mem_table::snapshot_tables`.
+    pub fn read_snapshot(&self) -> Result<ReadSnapshot> {
+        for (name, table) in self.tables.iter() {
+            table._open(name, self.root.as_deref())?;
+        }
+
+        Ok(snapshot_tables(self.opened()))
+    }
+}
+
+/// Locks the database `root`, checks the schema file against the `catalog` and takes in every
+/// table of it, refusing to if they disagree. With `allow_drift`, tables are opened as the catalog
+/// has them even when the schema file disagrees. Without a root there's no directory to lock, and
+/// only the store files themselves are, and the audit log is kept in memory.
+///
+/// Tables aren't opened until they're first asked for, see `Database::table`, so the store files
+/// of a table aren't checked until then either, and a table whose files are broken fails the
+/// first access to it rather than the open. `validate` still checks every table's files.
+pub fn open(
+    schema_path: impl AsRef<Path>,
+    catalog: &[CatalogTable],
+    root: Option<&Path>,
+    allow_drift: bool,
+) -> Result<Database> {
+    let lock = root.map(|root| DirLock::acquire(root, None)).transpose()?;
+
+    validate_schema(schema_path, catalog)?.ensure_startable(allow_drift)?;
+
+    Ok(Database {
+        tables: catalog
+            .iter()
+            .map(|table| (table.name.clone(), LazyTable::new(table)))
+            .collect(),
+        root: root.map(Path::to_path_buf),
+        audit: AuditLog::open(root)?,
+        maintenance: None,
+        _lock: lock,
+    })
+}
+
+/// Like `open`, but validates the database with `validate` and opens every table before
+/// returning, so a table whose files are broken fails the open.
+pub fn open_eager(
+    schema_path: impl AsRef<Path>,
+    catalog: &[CatalogTable],
+    root: Option<&Path>,
+    allow_drift: bool,
+) -> Result<Database> {
+    let schema_path = schema_path.as_ref();
+    let db = open(schema_path, catalog, root, allow_drift)?;
+
+    validate(schema_path, catalog, root)?.ensure_startable(allow_drift)?;
+
+    for (name, table) in db.tables.iter() {
+        table._open(name, root)?;
+    }
+
+    Ok(db)
+}
@@ -0,0 +1,403 @@
+//! Bitmaps of the bool columns, kept next to their stores so predicates on them can be checked
+//! without reading a row. A `BoolColumnView` holds two bits per row, whether the row has a value
+//! and the value, by the row's position across the blocks of records, so the bitmaps of several
+//! columns line up and combine a word at a time. Every write of a bool column updates its bitmap
+//! as it updates the column's store, and the bitmaps are rebuilt from the stores when a table is
+//! opened.
+//!
+//! Each block of rows has its bits in a `BitBlock` of its own, tagged with the layout it's in.
+//! Written out with `BoolColumnView::block_bytes`, a block is the layout version, a byte, then its
+//! words in little endian. Row `i` of a block is bits `2 * (i % 32)`, set when it has a value, and
+//! `2 * (i % 32) + 1`, set when that value is `true`, of word `i / 32`.
+
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use dbexp::{object_ids::RecordId, records::RecordHandle, values::DataValue};
+use primitives::{DataType, ThinIdx};
+
+use crate::{Table, TableError};
+
+/// The layout of the bits of a block, see the module docs.
+pub const BITMAP_FORMAT_VERSION: u8 = 1;
+
+/// How many rows a word holds the bits of.
+const ROWS_PER_WORD: usize = 32;
+
+/// The bit of every row in a word telling whether it has a value.
+const PRESENT: u64 = 0x5555_5555_5555_5555;
+
+/// The bits of one block of rows.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct BitBlock {
+    version: u8,
+    words: Vec<u64>,
+}
+
+impl BitBlock {
+    fn new(block_capacity: usize) -> Self {
+        Self {
+            version: BITMAP_FORMAT_VERSION,
+            words: vec![0; block_capacity.div_ceil(ROWS_PER_WORD)],
+        }
+    }
+
+    /// Combines two blocks a word at a time, `f` taking the words of each and giving those of the
+    /// result.
+    fn zip(&self, other: &Self, f: impl Fn(u64, u64) -> u64) -> Self {
+        Self {
+            version: BITMAP_FORMAT_VERSION,
+            words: self
+                .words
+                .iter()
+                .zip(&other.words)
+                .map(|(a, b)| f(*a, *b))
+                .collect(),
+        }
+    }
+
+    fn map(&self, f: impl Fn(u64) -> u64) -> Self {
+        Self {
+            version: BITMAP_FORMAT_VERSION,
+            words: self.words.iter().map(|word| f(*word)).collect(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.words.iter().all(|word| *word == 0)
+    }
+}
+
+/// The rows of a word that are `true`, as their present bits.
+fn trues(word: u64) -> u64 {
+    word & (word >> 1) & PRESENT
+}
+
+/// The rows of a word that are `false`, as their present bits.
+fn falses(word: u64) -> u64 {
+    word & !(word >> 1) & PRESENT
+}
+
+/// A bitmap of the values of a bool column, or of rows picked by combining them, by row position.
+/// Rows past the last one set have no value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BoolColumnView {
+    block_capacity: usize,
+    /// The bits of every block of rows that had a bit set, by block.
+    blocks: BTreeMap<usize, BitBlock>,
+}
+
+impl BoolColumnView {
+    /// An empty bitmap for rows in blocks of `block_capacity`.
+    pub fn new(block_capacity: usize) -> Self {
+        Self {
+            block_capacity,
+            blocks: BTreeMap::new(),
+        }
+    }
+
+    pub fn block_capacity(&self) -> usize {
+        self.block_capacity
+    }
+
+    fn _locate(&self, row: usize) -> (usize, usize, u32) {
+        let slot = row % self.block_capacity;
+        let shift = 2 * (slot % ROWS_PER_WORD) as u32;
+
+        (row / self.block_capacity, slot / ROWS_PER_WORD, shift)
+    }
+
+    /// The value of a row, `None` for a row without one.
+    pub fn get(&self, row: usize) -> Option<bool> {
+        let (block, word, shift) = self._locate(row);
+        let bits = self.blocks.get(&block)?.words[word] >> shift;
+
+        (bits & 1 != 0).then_some(bits & 2 != 0)
+    }
+
+    /// Whether a row holds `true`.
+    pub fn is_true(&self, row: usize) -> bool {
+        self.get(row) == Some(true)
+    }
+
+    pub(crate) fn set(&mut self, row: usize, value: Option<bool>) {
+        let (block, word, shift) = self._locate(row);
+        let bits = match value {
+            None => 0,
+            Some(false) => 1,
+            Some(true) => 3,
+        };
+
+        let block_capacity = self.block_capacity;
+        let word = &mut self
+            .blocks
+            .entry(block)
+            .or_insert_with(|| BitBlock::new(block_capacity))
+            .words[word];
+
+        *word = (*word & !(3 << shift)) | (bits << shift);
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.blocks.clear();
+    }
+
+    /// How many rows hold `true`, counted a word at a time.
+    pub fn count_true(&self) -> usize {
+        self.blocks
+            .values()
+            .flat_map(|block| &block.words)
+            .map(|word| trues(*word).count_ones() as usize)
+            .sum()
+    }
+
+    /// Every row with a value and its value, in row order.
+    pub fn iter_bits(&self) -> impl Iterator<Item = (usize, bool)> + '_ {
+        self.blocks.iter().flat_map(move |(block, bits)| {
+            let start = block * self.block_capacity;
+
+            bits.words.iter().enumerate().flat_map(move |(word, bits)| {
+                let mut present = bits & PRESENT;
+
+                std::iter::from_fn(move || {
+                    let shift = present.trailing_zeros();
+
+                    if shift == u64::BITS {
+                        return None;
+                    }
+
+                    present &= present - 1;
+
+                    Some((
+                        start + word * ROWS_PER_WORD + shift as usize / 2,
+                        (bits >> shift) & 2 != 0,
+                    ))
+                })
+            })
+        })
+    }
+
+    /// The rows with a value in both bitmaps, `true` where both are.
+    pub fn and(&self, other: &Self) -> Self {
+        let blocks = self
+            .blocks
+            .iter()
+            .filter_map(|(block, bits)| {
+                let theirs = other.blocks.get(block)?;
+
+                Some((
+                    *block,
+                    bits.zip(theirs, |a, b| {
+                        let both = a & b & PRESENT;
+                        both | ((trues(a) & trues(b)) << 1)
+                    }),
+                ))
+            })
+            .collect();
+
+        Self {
+            block_capacity: self.block_capacity,
+            blocks,
+        }
+    }
+
+    /// The rows with a value in either bitmap, `true` where either is.
+    pub fn or(&self, other: &Self) -> Self {
+        let mut blocks = self.blocks.clone();
+
+        for (block, theirs) in &other.blocks {
+            let bits = match blocks.get(block) {
+                Some(ours) => ours.zip(theirs, |a, b| {
+                    let either = (a | b) & PRESENT;
+                    either | ((trues(a) | trues(b)) << 1)
+                }),
+                None => theirs.clone(),
+            };
+
+            blocks.insert(*block, bits);
+        }
+
+        Self {
+            block_capacity: self.block_capacity,
+            blocks,
+        }
+    }
+
+    /// The rows with a value, `true` where it's `value`, for picking the rows a comparison with
+    /// `value` matches.
+    pub fn where_eq(&self, value: bool) -> Self {
+        let matching = if value { trues } else { falses };
+
+        Self {
+            block_capacity: self.block_capacity,
+            blocks: self
+                .blocks
+                .iter()
+                .map(|(block, bits)| {
+                    (
+                        *block,
+                        bits.map(|word| (word & PRESENT) | (matching(word) << 1)),
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    /// Whether any row of a block of rows holds `true`. Blocks without one can be passed over
+    /// without reading their rows.
+    pub fn block_has_true(&self, block: usize) -> bool {
+        self.blocks
+            .get(&block)
+            .is_some_and(|bits| bits.words.iter().any(|word| trues(*word) != 0))
+    }
+
+    /// The bits of a block of rows as written out, see the module docs. `None` for a block without
+    /// any set.
+    pub fn block_bytes(&self, block: usize) -> Option<Vec<u8>> {
+        let bits = self.blocks.get(&block).filter(|bits| !bits.is_empty())?;
+        let mut bytes = Vec::with_capacity(1 + bits.words.len() * 8);
+
+        bytes.push(bits.version);
+
+        for word in &bits.words {
+            bytes.extend(word.to_le_bytes());
+        }
+
+        Some(bytes)
+    }
+
+    /// Replaces the bits of a block of rows with ones written by `block_bytes`. Fails for a layout
+    /// it doesn't know, or bytes that aren't a whole block.
+    pub fn load_block(&mut self, block: usize, bytes: &[u8]) -> Result<()> {
+        let Some((&version, words)) = bytes.split_first() else {
+            anyhow::bail!("bitmap block is empty");
+        };
+
+        if version != BITMAP_FORMAT_VERSION {
+            anyhow::bail!(
+                "bitmap block is in layout {}, expected {}",
+                version,
+                BITMAP_FORMAT_VERSION
+            );
+        }
+
+        let mut bits = BitBlock::new(self.block_capacity);
+
+        if words.len() != bits.words.len() * 8 {
+            anyhow::bail!(
+                "bitmap block is {} bytes, expected {}",
+                words.len(),
+                bits.words.len() * 8
+            );
+        }
+
+        for (word, bytes) in bits.words.iter_mut().zip(words.chunks_exact(8)) {
+            *word = u64::from_le_bytes(bytes.try_into()?);
+        }
+
+        self.blocks.insert(block, bits);
+
+        Ok(())
+    }
+}
+
+impl Table {
+    fn _is_bool(&self, column: usize) -> bool {
+        self.config
+            .columns
+            .get(column)
+            .is_some_and(|config| config.data_type.into_inner() == DataType::Bool)
+    }
+
+    /// The position of a row across the blocks of records, which is what the bits of a
+    /// `BoolColumnView` are by.
+    pub fn row_position(&self, handle: &RecordHandle) -> usize {
+        Into::<ThinIdx>::into(self.records.record_id(handle)).into_usize()
+    }
+
+    /// The bitmap of a bool column as it is now. Rows written after it's taken aren't in it.
+    pub fn bool_view(&self, column: usize) -> Result<BoolColumnView, TableError> {
+        if column >= self.config.columns.len() {
+            return Err(TableError::not_found(format!("column {}", column)));
+        }
+
+        if let Some(unavailable) = self._unavailable(column) {
+            return Err(unavailable.into());
+        }
+
+        self.bitmaps
+            .read()
+            .get(&column)
+            .cloned()
+            .ok_or_else(|| TableError::invalid(format!("column {} isn't a bool column", column)))
+    }
+
+    /// Builds the bitmap of every bool column from the values already stored.
+    pub(crate) fn _build_bitmaps(&self) -> Result<()> {
+        let mut bitmaps = self.bitmaps.write();
+
+        for column in 0..self.config.columns.len() {
+            if !self._is_bool(column) || self._is_unavailable(column) {
+                continue;
+            }
+
+            let mut view = BoolColumnView::new(self.records.block_capacity());
+            let store = self.get_column_store(column)?;
+            let inner = store.read();
+
+            for block in inner.blocks().values() {
+                for handle in block.iter_live() {
+                    let bit = handle.read_with(|slot| {
+                        Ok(match (slot.thin_record_id(), slot.data()) {
+                            (Some(record), Some(DataValue::Bool(value))) => {
+                                Some((Into::<ThinIdx>::into(record).into_usize(), *value))
+                            }
+                            _ => None,
+                        })
+                    })?;
+
+                    if let Some((row, value)) = bit {
+                        view.set(row, Some(value));
+                    }
+                }
+            }
+
+            bitmaps.insert(column, view);
+        }
+
+        Ok(())
+    }
+
+    /// Sets the bits of a row for a value written to `column`, or clears them for `None`, if the
+    /// column is a bool column.
+    pub(crate) fn _bitmap_set(&self, column: usize, record: RecordId, value: Option<&DataValue>) {
+        if !self._is_bool(column) {
+            return;
+        }
+
+        let value = match value {
+            Some(DataValue::Bool(value)) => Some(*value),
+            _ => None,
+        };
+
+        if let Some(view) = self.bitmaps.write().get_mut(&column) {
+            view.set(Into::<ThinIdx>::into(record).into_usize(), value);
+        }
+    }
+
+    /// Clears the bits of a removed row in the bitmap of every bool column.
+    pub(crate) fn _bitmap_removed(&self, record: RecordId) {
+        let row = Into::<ThinIdx>::into(record).into_usize();
+
+        for view in self.bitmaps.write().values_mut() {
+            view.set(row, None);
+        }
+    }
+
+    /// Clears the bitmap of every bool column, for a table whose values were all removed at once.
+    pub(crate) fn _clear_bitmaps(&self) {
+        for view in self.bitmaps.write().values_mut() {
+            view.clear();
+        }
+    }
+}
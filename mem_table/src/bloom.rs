@@ -0,0 +1,341 @@
+use std::{
+    hash::{DefaultHasher, Hash, Hasher},
+    num::NonZeroU8,
+    ops::ControlFlow,
+};
+
+use anyhow::Result;
+use dbexp::{
+    block::Block, object_ids::RecordId, records::RecordHandle, slot::SlotHandle, values::DataValue,
+};
+use indexmap::IndexMap;
+use primitives::ThinIdx;
+
+use crate::{cancel::CancelCheck, Table, TableError};
+
+pub const DEFAULT_BLOOM_BITS_PER_KEY: NonZeroU8 = NonZeroU8::new(10).unwrap();
+
+/// The two halves of a key's hash, from which every bit position of the key is derived.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BloomProbe(u32, u32);
+
+impl BloomProbe {
+    /// Only text and bytes are filtered, by their contents, so values of different capacities
+    /// probe the same bits.
+    pub fn of(value: &DataValue) -> Option<Self> {
+        let key = match value {
+            DataValue::Text(text) => text.as_str().as_bytes(),
+            DataValue::Bytes(bytes) => bytes.as_slice(),
+            _ => return None,
+        };
+
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        Some(Self(hash as u32, (hash >> 32) as u32 | 1))
+    }
+
+    fn positions(self, hashes: u32, bit_count: usize) -> impl Iterator<Item = usize> {
+        (0..hashes).map(move |i| self.0.wrapping_add(i.wrapping_mul(self.1)) as usize % bit_count)
+    }
+}
+
+/// A fixed size bloom filter. Answers whether a key may have been inserted, and is never wrong
+/// when it says no.
+#[derive(Debug, Clone)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    hashes: u32,
+}
+
+impl BloomFilter {
+    pub fn new(keys: usize, bits_per_key: NonZeroU8) -> Self {
+        let bits_per_key = bits_per_key.get() as usize;
+        let words = (keys.max(1) * bits_per_key).div_ceil(64);
+
+        Self {
+            bits: vec![0; words],
+            // ln(2) * bits per key minimizes the false positive rate
+            hashes: ((bits_per_key * 69 + 50) / 100).clamp(1, 30) as u32,
+        }
+    }
+
+    pub fn insert(&mut self, probe: BloomProbe) {
+        let bit_count = self.bits.len() * 64;
+
+        for bit in probe.positions(self.hashes, bit_count) {
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        }
+    }
+
+    pub fn may_contain(&self, probe: BloomProbe) -> bool {
+        let bit_count = self.bits.len() * 64;
+
+        probe
+            .positions(self.hashes, bit_count)
+            .all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    pub fn byte_count(&self) -> usize {
+        self.bits.len() * size_of::<u64>()
+    }
+}
+
+#[derive(Debug)]
+struct BlockBloom {
+    filter: BloomFilter,
+    inserted: usize,
+    /// Keys still set in the filter whose values have since been removed or overwritten.
+    removed: usize,
+}
+
+/// The bloom filters of one column, one per block of its store.
+#[derive(Debug)]
+pub(crate) struct ColumnBlooms {
+    bits_per_key: NonZeroU8,
+    block_capacity: usize,
+    blocks: IndexMap<ThinIdx, BlockBloom>,
+}
+
+impl ColumnBlooms {
+    pub(crate) fn new(bits_per_key: NonZeroU8, block_capacity: usize) -> Self {
+        Self {
+            bits_per_key,
+            block_capacity,
+            blocks: IndexMap::new(),
+        }
+    }
+
+    fn _block(&mut self, block: ThinIdx) -> &mut BlockBloom {
+        let (bits_per_key, block_capacity) = (self.bits_per_key, self.block_capacity);
+
+        self.blocks.entry(block).or_insert_with(|| BlockBloom {
+            filter: BloomFilter::new(block_capacity, bits_per_key),
+            inserted: 0,
+            removed: 0,
+        })
+    }
+
+    pub(crate) fn insert(&mut self, block: ThinIdx, probe: BloomProbe) {
+        let bloom = self._block(block);
+
+        bloom.filter.insert(probe);
+        bloom.inserted += 1;
+    }
+
+    /// Notes that a value of the block went away. Once most of the keys in a block's filter are
+    /// stale, the filter is rebuilt from the values still in it.
+    pub(crate) fn removed(&mut self, block: &Block<DataValue>) {
+        let bloom = self._block(block.index());
+        bloom.removed += 1;
+
+        if bloom.removed * 2 > bloom.inserted {
+            self.rebuild(block);
+        }
+    }
+
+    pub(crate) fn rebuild(&mut self, block: &Block<DataValue>) {
+        let filter = BloomFilter::new(self.block_capacity, self.bits_per_key);
+        let bloom = self._block(block.index());
+        bloom.filter = filter;
+        bloom.inserted = 0;
+        bloom.removed = 0;
+
+        for handle in block.iter_live() {
+            let probe = handle.read_with(|slot| Ok(slot.data().and_then(BloomProbe::of)));
+
+            if let Ok(Some(probe)) = probe {
+                bloom.filter.insert(probe);
+                bloom.inserted += 1;
+            }
+        }
+    }
+
+    /// Drops the filter of every block, for a column whose values were all removed at once.
+    pub(crate) fn clear(&mut self) {
+        self.blocks.clear();
+    }
+
+    /// Every value written to a filtered column goes through `insert`, so a block without a filter
+    /// has never held one.
+    pub(crate) fn may_contain(&self, block: ThinIdx, probe: BloomProbe) -> bool {
+        self.blocks
+            .get(&block)
+            .is_some_and(|bloom| bloom.filter.may_contain(probe))
+    }
+
+    pub(crate) fn byte_count(&self) -> usize {
+        self.blocks
+            .values()
+            .map(|bloom| bloom.filter.byte_count())
+            .sum()
+    }
+}
+
+/// The rows found by `Table::scan_eq`, along with how many blocks of the column were read and how
+/// many were ruled out by their bloom filter.
+#[derive(Debug, Clone, Default)]
+pub struct EqScan {
+    pub rows: Vec<RecordHandle>,
+    pub blocks_scanned: usize,
+    pub blocks_skipped: usize,
+    /// Whether the scan stopped at its limit. Rows past the limit may be left unread, even when the
+    /// limit is met by the very last slot of the column.
+    pub limited: bool,
+}
+
+impl Table {
+    /// Builds the filters of every column that asks for one from the values already stored.
+    /// Filters are kept in memory only, so this is where persisted tables get theirs back.
+    pub(crate) fn _build_blooms(&self) -> Result<()> {
+        let mut blooms = self.blooms.write();
+
+        for column in 0..self.config.columns.len() {
+            let config = unsafe { self.config.columns.get_unchecked(column) };
+
+            let Some(bits_per_key) = config.bloom else {
+                continue;
+            };
+
+            if self._is_unavailable(column) {
+                continue;
+            }
+
+            let store = self.get_column_store(column)?;
+            let inner = store.read();
+            let mut column_blooms =
+                ColumnBlooms::new(bits_per_key, inner.meta().config.block_capacity.get());
+
+            for block in inner.blocks().values() {
+                column_blooms.rebuild(block);
+            }
+
+            blooms.insert(column, column_blooms);
+        }
+
+        Ok(())
+    }
+
+    /// The probe of a value about to be written to `column`, if the column is filtered.
+    pub(crate) fn _bloom_probe(&self, column: usize, value: &DataValue) -> Option<BloomProbe> {
+        self.config.columns.get(column)?.bloom?;
+        BloomProbe::of(value)
+    }
+
+    pub(crate) fn _bloom_insert(
+        &self,
+        column: usize,
+        handle: &SlotHandle<DataValue>,
+        probe: Option<BloomProbe>,
+    ) {
+        if let Some(probe) = probe {
+            if let Some(blooms) = self.blooms.write().get_mut(&column) {
+                blooms.insert(handle.block.index(), probe);
+            }
+        }
+    }
+
+    pub(crate) fn _bloom_removed(&self, column: usize, block: &Block<DataValue>) {
+        if let Some(blooms) = self.blooms.write().get_mut(&column) {
+            blooms.removed(block);
+        }
+    }
+
+    /// Every row whose `column` equals `value`. Blocks whose bloom filter rules the value out are
+    /// skipped without reading any of their slots.
+    pub fn scan_eq(&self, column: usize, value: &DataValue) -> Result<EqScan, TableError> {
+        Ok(self._scan_eq(column, value, None)?)
+    }
+
+    /// Like `scan_eq`, but stops reading blocks as soon as `limit` rows were found. Which rows
+    /// those are depends on where they're stored, not on when they were inserted.
+    pub fn scan_eq_limit(
+        &self,
+        column: usize,
+        value: &DataValue,
+        limit: usize,
+    ) -> Result<EqScan, TableError> {
+        Ok(self._scan_eq(column, value, Some(limit))?)
+    }
+
+    fn _scan_eq(&self, column: usize, value: &DataValue, limit: Option<usize>) -> Result<EqScan> {
+        let store = self.get_column_store(column)?;
+        let probe = self._bloom_probe(column, value);
+        let blocks = store.scan_blocks(self.config.scan);
+        let mut scan = EqScan::default();
+        let mut cancel = self._cancel_check();
+
+        for block in blocks {
+            if limit.is_some_and(|limit| scan.rows.len() >= limit) {
+                scan.limited = true;
+                break;
+            }
+
+            if let Some(probe) = probe {
+                let may_contain = self
+                    .blooms
+                    .read()
+                    .get(&column)
+                    .is_none_or(|blooms| blooms.may_contain(block.index(), probe));
+
+                if !may_contain {
+                    scan.blocks_skipped += 1;
+                    continue;
+                }
+            }
+
+            scan.blocks_scanned += 1;
+
+            if let ControlFlow::Break(()) =
+                self._scan_eq_block(&block, value, &mut scan.rows, limit, &mut cancel)?
+            {
+                scan.limited = true;
+                break;
+            }
+        }
+
+        Ok(scan)
+    }
+
+    /// Adds the rows of one block matching `value`, breaking once `rows` reaches `limit`.
+    fn _scan_eq_block(
+        &self,
+        block: &Block<DataValue>,
+        value: &DataValue,
+        rows: &mut Vec<RecordHandle>,
+        limit: Option<usize>,
+        cancel: &mut CancelCheck<'_>,
+    ) -> Result<ControlFlow<()>> {
+        for handle in block.iter_live() {
+            cancel.tick()?;
+
+            let record = handle.read_with(|slot| {
+                Ok(match slot.data() {
+                    Some(data) if data == value => slot.thin_record_id(),
+                    _ => None,
+                })
+            })?;
+
+            if let Some(record) = record {
+                rows.extend(self.records.get(RecordId::from_thin(record, self.id)));
+
+                // checked as soon as a row is added, so a limit met by the last slot still counts
+                if limit.is_some_and(|limit| rows.len() >= limit) {
+                    return Ok(ControlFlow::Break(()));
+                }
+            }
+        }
+
+        Ok(ControlFlow::Continue(()))
+    }
+
+    /// Memory held by the bloom filters of every column.
+    pub fn bloom_byte_count(&self) -> usize {
+        self.blooms
+            .read()
+            .values()
+            .map(ColumnBlooms::byte_count)
+            .sum()
+    }
+}
@@ -0,0 +1,116 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+
+use crate::Table;
+
+/// How many slots a scan reads between looking at its token, which keeps the clock out of the
+/// inner loops.
+pub const CHECK_EVERY_SLOTS: usize = 64;
+
+/// The query was cancelled with `CancellationToken::cancel`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("query cancelled")]
+pub struct QueryCancelled;
+
+/// The query ran past the deadline of its token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("query timed out after {timeout:?}")]
+pub struct QueryTimeout {
+    pub timeout: Duration,
+}
+
+/// Stops the queries of a table handed out by `Table::with_cancellation`, when cancelled or once
+/// its deadline passes. Clones share whether the token was cancelled, so one can be kept to cancel
+/// a query running elsewhere. The default token never stops anything.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+    deadline: Option<(Instant, Duration)>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A token whose queries time out `timeout` from now.
+    pub fn with_timeout(timeout: Duration) -> Self {
+        Self {
+            deadline: Some((Instant::now() + timeout, timeout)),
+            ..Self::default()
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Release);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Acquire)
+    }
+
+    /// Fails with `QueryCancelled` once the token is cancelled, or with `QueryTimeout` once its
+    /// deadline has passed.
+    pub fn check(&self) -> Result<()> {
+        if self.is_cancelled() {
+            return Err(QueryCancelled.into());
+        }
+
+        match self.deadline {
+            Some((deadline, timeout)) if Instant::now() >= deadline => {
+                Err(QueryTimeout { timeout }.into())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Counts the slots a scan reads, checking its token on the first and every `CHECK_EVERY_SLOTS`
+/// after.
+#[derive(Debug)]
+pub(crate) struct CancelCheck<'a> {
+    token: &'a CancellationToken,
+    slots: usize,
+}
+
+impl CancelCheck<'_> {
+    pub(crate) fn tick(&mut self) -> Result<()> {
+        let check = self.slots.is_multiple_of(CHECK_EVERY_SLOTS);
+        self.slots += 1;
+
+        match check {
+            true => self.token.check(),
+            false => Ok(()),
+        }
+    }
+}
+
+impl Table {
+    /// This table with its scans, aggregations and joins stopped by `token`. A stopped query
+    /// fails with `QueryCancelled` or `QueryTimeout`, and whatever it read so far is thrown away
+    /// along with the locks it held. Writes go ahead whatever the token says.
+    pub fn with_cancellation(&self, token: CancellationToken) -> Table {
+        Table {
+            cancel: token,
+            ..self.clone()
+        }
+    }
+
+    pub fn cancellation(&self) -> &CancellationToken {
+        &self.cancel
+    }
+
+    pub(crate) fn _cancel_check(&self) -> CancelCheck<'_> {
+        CancelCheck {
+            token: &self.cancel,
+            slots: 0,
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use primitives::DataType;
+use serde::Serialize;
+
+use crate::{Table, TableError};
+
+/// The share of values a recommended capacity is sized from. Longer ones past it only raise the
+/// recommendation as far as the longest value.
+const PERCENTILE: f64 = 0.99;
+
+/// How much room a recommended capacity leaves above the values it's sized from, as a fraction of
+/// their length.
+const HEADROOM: f64 = 0.25;
+
+/// Recommended capacities are rounded up to a multiple of this.
+const CAPACITY_STEP: u32 = 8;
+
+/// How much of its capacity a text or bytes column uses, from `Table::capacity_report`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ColumnCapacityAdvice {
+    pub column: usize,
+    pub name: Option<String>,
+    pub data_type: DataType,
+    pub declared_cap: u32,
+    /// How many values the column holds.
+    pub values: usize,
+    /// The length of the longest value, in bytes.
+    pub observed_max: usize,
+    /// The length 99% of the values are no longer than, in bytes.
+    pub p99_len: usize,
+    /// A capacity that fits every value with some room to grow, never above the declared one.
+    pub recommended_cap: u32,
+    /// The padding the values would lose at the recommended capacity, in bytes.
+    pub potential_savings_bytes: usize,
+}
+
+impl std::fmt::Display for ColumnCapacityAdvice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.name.as_deref() {
+            Some(name) => write!(f, "column {} ({}): ", self.column, name)?,
+            None => write!(f, "column {}: ", self.column)?,
+        }
+
+        writeln!(
+            f,
+            "{:?}, {} values, longest {}, p99 {}",
+            self.data_type, self.values, self.observed_max, self.p99_len
+        )?;
+
+        if self.recommended_cap < self.declared_cap {
+            writeln!(
+                f,
+                "  capacity {} would do, saving {} bytes",
+                self.recommended_cap, self.potential_savings_bytes
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The capacity recommended for values whose longest is `max` and 99th percentile `p99`: the
+/// percentile with headroom, rounded up, but never below the longest value or above `declared`.
+fn recommend(declared: u32, max: usize, p99: usize) -> u32 {
+    let padded = (p99 as f64 * (1.0 + HEADROOM)).ceil() as u32;
+    let recommended = padded.max(max as u32).max(1).div_ceil(CAPACITY_STEP) * CAPACITY_STEP;
+
+    recommended.min(declared)
+}
+
+impl Table {
+    /// How much of their capacity the values of every text and bytes column use, with a capacity
+    /// that would do, for choosing capacities once a table has real data. Scans the lengths of
+    /// every value of those columns. A column without values keeps its capacity.
+    pub fn capacity_report(&self) -> Result<Vec<ColumnCapacityAdvice>, TableError> {
+        let mut report = Vec::new();
+
+        for column in 0..self.config.columns.len() {
+            let config = unsafe { self.config.columns.get_unchecked(column) };
+            let data_type = config.data_type.into_inner();
+
+            let (DataType::Text(declared_cap) | DataType::Bytes(declared_cap)) = data_type else {
+                continue;
+            };
+
+            if self._is_unavailable(column) {
+                continue;
+            }
+
+            let mut lengths = Vec::new();
+
+            self._for_each_length(column, |_, len| {
+                lengths.push(len);
+                Ok(())
+            })?;
+
+            lengths.sort_unstable();
+
+            let values = lengths.len();
+            let observed_max = lengths.last().copied().unwrap_or(0);
+            let p99_len = match values {
+                0 => 0,
+                _ => lengths[(values as f64 * PERCENTILE).ceil() as usize - 1],
+            };
+            let recommended_cap = match values {
+                0 => declared_cap,
+                _ => recommend(declared_cap, observed_max, p99_len),
+            };
+
+            report.push(ColumnCapacityAdvice {
+                column,
+                name: config.name.map(|name| name.as_str().to_string()),
+                data_type,
+                declared_cap,
+                values,
+                observed_max,
+                p99_len,
+                recommended_cap,
+                potential_savings_bytes: (declared_cap - recommended_cap) as usize * values,
+            });
+        }
+
+        Ok(report)
+    }
+}
@@ -0,0 +1,358 @@
+//! Distinct-count estimates for the columns that ask for them with
+//! `DataConfig::with_cardinality_tracking`, from a HyperLogLog sketch of `SKETCH_REGISTERS` one
+//! byte registers. Every value written to the column goes into its sketch, so reading the estimate
+//! takes no scan, but values are never taken back out: the estimate counts every value the column
+//! has held since the sketch was started, not only those it holds now. Truncating the table starts
+//! the sketches over.
+//!
+//! Sketches are kept in the table's annotations, see `Table::meta`, and written there when the
+//! table is flushed or closed. A table opened without one builds it from the values it holds, and
+//! one written before the last rows were inserted takes their values. Only the values of updates
+//! made since can go missing from it.
+
+use anyhow::Result;
+use dbexp::values::DataValue;
+use primitives::Text;
+
+use crate::{Table, TableError};
+
+/// log2 of `SKETCH_REGISTERS`.
+const PRECISION: u32 = 11;
+
+/// How many registers a sketch has, which is also its size in bytes. The standard error of an
+/// estimate is about `1.04 / sqrt(SKETCH_REGISTERS)`, a little over 2%.
+pub const SKETCH_REGISTERS: usize = 1 << PRECISION;
+
+/// The digits registers are written with when the sketch is persisted, one per register. None of
+/// them needs escaping in JSON.
+const DIGITS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// The annotation a column's sketch is kept in, see `Table::meta`. Keyed by the column's cell,
+/// like `sequence_key`.
+pub(crate) fn sketch_key(cell: usize) -> String {
+    format!("cardinality.{}", cell)
+}
+
+/// A HyperLogLog sketch of the distinct values added to it.
+#[derive(Clone, PartialEq, Eq)]
+pub struct CardinalitySketch {
+    registers: Box<[u8; SKETCH_REGISTERS]>,
+}
+
+impl std::fmt::Debug for CardinalitySketch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CardinalitySketch")
+            .field("estimate", &self.estimate())
+            .finish()
+    }
+}
+
+impl Default for CardinalitySketch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CardinalitySketch {
+    pub fn new() -> Self {
+        Self {
+            registers: Box::new([0; SKETCH_REGISTERS]),
+        }
+    }
+
+    pub fn insert(&mut self, value: &DataValue) {
+        self.insert_hash(value_hash(value));
+    }
+
+    fn insert_hash(&mut self, hash: u64) {
+        let register = (hash >> (64 - PRECISION)) as usize;
+        // the guard bit caps the rank for hashes whose remaining bits are all zero
+        let rank = ((hash << PRECISION) | (1 << (PRECISION - 1))).leading_zeros() as u8 + 1;
+
+        if self.registers[register] < rank {
+            self.registers[register] = rank;
+        }
+    }
+
+    /// Adds every value added to `other`, as if they had been added to `self`.
+    pub fn merge(&mut self, other: &Self) {
+        for (register, theirs) in self.registers.iter_mut().zip(other.registers.iter()) {
+            *register = (*register).max(*theirs);
+        }
+    }
+
+    /// The registers as they are, one byte each, for writing the sketch out.
+    pub(crate) fn registers(&self) -> &[u8; SKETCH_REGISTERS] {
+        &self.registers
+    }
+
+    /// The sketch with the `registers` of another.
+    pub(crate) fn from_registers(registers: [u8; SKETCH_REGISTERS]) -> Self {
+        Self {
+            registers: Box::new(registers),
+        }
+    }
+
+    /// About how many distinct values were added.
+    pub fn estimate(&self) -> u64 {
+        let m = SKETCH_REGISTERS as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum = self
+            .registers
+            .iter()
+            .map(|&rank| 2f64.powi(-(rank as i32)))
+            .sum::<f64>();
+        let zeros = self.registers.iter().filter(|&&rank| rank == 0).count();
+        let raw = alpha * m * m / sum;
+
+        // the raw estimate is biased while many registers are still empty, where counting the
+        // empty ones is closer
+        let estimate = if raw <= 2.5 * m && zeros > 0 {
+            m * (m / zeros as f64).ln()
+        } else {
+            raw
+        };
+
+        estimate.round() as u64
+    }
+
+    /// The sketch as persisted: the seq of the last row it has seen, then a digit per register.
+    fn to_text(&self, seq: u64) -> Result<DataValue> {
+        let mut text = format!("{}:", seq);
+
+        text.extend(
+            self.registers
+                .iter()
+                .map(|&rank| DIGITS[rank as usize] as char),
+        );
+
+        Ok(DataValue::Text(Text::try_from_str(&text, text.len())?))
+    }
+
+    fn try_from_text(value: &DataValue) -> Result<(Self, u64)> {
+        let DataValue::Text(text) = value else {
+            anyhow::bail!("cardinality sketch is corrupt: {:?}", value);
+        };
+
+        let (seq, digits) = text
+            .as_str()
+            .split_once(':')
+            .ok_or_else(|| anyhow::anyhow!("cardinality sketch is corrupt"))?;
+
+        if digits.len() != SKETCH_REGISTERS {
+            anyhow::bail!(
+                "cardinality sketch has {} registers, expected {}",
+                digits.len(),
+                SKETCH_REGISTERS
+            );
+        }
+
+        let mut sketch = Self::new();
+
+        for (register, digit) in sketch.registers.iter_mut().zip(digits.bytes()) {
+            *register = DIGITS
+                .iter()
+                .position(|&d| d == digit)
+                .ok_or_else(|| anyhow::anyhow!("cardinality sketch is corrupt"))?
+                as u8;
+        }
+
+        Ok((sketch, seq.parse()?))
+    }
+}
+
+/// A hash of a value that doesn't change between runs, so a persisted sketch can keep taking
+/// values. Text and bytes are hashed by their contents alone, whatever their capacity.
+fn value_hash(value: &DataValue) -> u64 {
+    let (kind, bytes) = match value {
+        DataValue::Text(text) => (0u8, text.as_str().as_bytes().to_vec()),
+        DataValue::Bytes(bytes) => (1, bytes.as_slice().to_vec()),
+        other => (2, other.to_string().into_bytes()),
+    };
+
+    // FNV-1a, then the splitmix64 finalizer to spread the bits FNV leaves clustered
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+
+    for byte in std::iter::once(kind).chain(bytes) {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+
+    hash ^= hash >> 30;
+    hash = hash.wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash ^= hash >> 27;
+    hash = hash.wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^ (hash >> 31)
+}
+
+/// A column's sketch, and whether it changed since it was last written to the annotations.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ColumnSketch {
+    sketch: CardinalitySketch,
+    dirty: bool,
+}
+
+impl Table {
+    /// Loads the sketch of every column that tracks its cardinality. A sketch that wasn't
+    /// persisted is built from the values already stored, and one persisted before rows were
+    /// inserted, by a table that wasn't flushed before it went away, takes their values.
+    pub(crate) fn _load_sketches(&self) -> Result<()> {
+        let columns = (0..self.config.columns.len())
+            .filter(|&column| {
+                self.config
+                    .columns
+                    .get(column)
+                    .is_some_and(|config| config.track_cardinality)
+                    && !self._is_unavailable(column)
+            })
+            .collect::<Vec<_>>();
+
+        if columns.is_empty() {
+            return Ok(());
+        }
+
+        let meta = self.meta()?;
+        let mut sketches = self.sketches.write();
+
+        for column in columns {
+            let (mut sketch, seq) = match meta.get(&sketch_key(self._cell(column)))? {
+                Some(value) => CardinalitySketch::try_from_text(&value)
+                    .map_err(|e| e.context(format!("column {}", column)))?,
+                None => (CardinalitySketch::new(), 0),
+            };
+            let dirty = seq < self.current_seq();
+
+            if seq == 0 {
+                self._column_values(column, |value| sketch.insert(value))?;
+            } else {
+                for (_, handle) in self.scan_since(seq) {
+                    if let Some(value) = self.get_value(&handle, column)? {
+                        sketch.insert(&value);
+                    }
+                }
+            }
+
+            sketches.insert(column, ColumnSketch { sketch, dirty });
+        }
+
+        Ok(())
+    }
+
+    /// Calls `f` with every value stored in `column`.
+    fn _column_values(&self, column: usize, mut f: impl FnMut(&DataValue)) -> Result<()> {
+        let store = self.get_column_store(column)?;
+        let inner = store.read();
+
+        for block in inner.blocks().values() {
+            for handle in block.iter_live() {
+                handle.read_with(|slot| {
+                    if let Some(value) = slot.data() {
+                        f(value);
+                    }
+
+                    Ok(())
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Adds a value written to `column` to its sketch, if the column has one.
+    pub(crate) fn _sketch_insert(&self, column: usize, value: &DataValue) {
+        if self.sketches.read().is_empty() {
+            return;
+        }
+
+        if let Some(sketch) = self.sketches.write().get_mut(&column) {
+            sketch.sketch.insert(value);
+            sketch.dirty = true;
+        }
+    }
+
+    /// Starts every sketch over, for a table whose values were all removed at once.
+    pub(crate) fn _clear_sketches(&self) {
+        for sketch in self.sketches.write().values_mut() {
+            *sketch = ColumnSketch {
+                sketch: CardinalitySketch::new(),
+                dirty: true,
+            };
+        }
+    }
+
+    /// Starts the sketch of a column over, if it tracks its cardinality, for a column whose
+    /// store was rebuilt empty.
+    pub(crate) fn _reset_sketch(&self, column: usize) {
+        let tracked = self
+            .config
+            .columns
+            .get(column)
+            .is_some_and(|config| config.track_cardinality);
+
+        if tracked {
+            self.sketches.write().insert(
+                column,
+                ColumnSketch {
+                    sketch: CardinalitySketch::new(),
+                    dirty: true,
+                },
+            );
+        }
+    }
+
+    /// Builds the sketch of a column over from the values it holds, if it tracks its
+    /// cardinality, for a column whose values were all rewritten.
+    pub(crate) fn _rebuild_sketch(&self, column: usize) -> Result<()> {
+        if !self.sketches.read().contains_key(&column) {
+            return Ok(());
+        }
+
+        let mut sketch = CardinalitySketch::new();
+        self._column_values(column, |value| sketch.insert(value))?;
+        self.sketches.write().insert(
+            column,
+            ColumnSketch {
+                sketch,
+                dirty: true,
+            },
+        );
+
+        Ok(())
+    }
+
+    /// Writes the sketches that changed to the annotations, along with the last row they've seen.
+    pub(crate) fn _save_sketches(&self) -> Result<()> {
+        let mut sketches = self.sketches.write();
+
+        if !sketches.values().any(|sketch| sketch.dirty) {
+            return Ok(());
+        }
+
+        let meta = self.meta()?;
+        let seq = self.current_seq();
+
+        for (column, sketch) in sketches.iter_mut().filter(|(_, sketch)| sketch.dirty) {
+            meta.set(
+                &sketch_key(self._cell(*column)),
+                sketch.sketch.to_text(seq)?,
+            )?;
+            sketch.dirty = false;
+        }
+
+        Ok(())
+    }
+
+    /// About how many distinct values `column` has held, read from its sketch without a scan.
+    /// See the module docs for what the estimate counts. Only for columns tracking their
+    /// cardinality.
+    pub fn estimated_distinct(&self, column: usize) -> Result<u64, TableError> {
+        match self.sketches.read().get(&column) {
+            Some(sketch) => Ok(sketch.sketch.estimate()),
+            None if column < self.config.columns.len() => Err(TableError::invalid(format!(
+                "column {} doesn't track its cardinality",
+                column
+            ))),
+            None => Err(TableError::not_found(format!("column {}", column))),
+        }
+    }
+}
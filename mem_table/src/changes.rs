@@ -0,0 +1,96 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use dbexp::{records::RecordHandle, values::DataValue};
+use indexmap::IndexMap;
+use primitives::shared_object::SharedObject;
+
+use crate::Table;
+
+/// A row written to a table, as seen by the listeners registered with `Table::on_change`. Rows
+/// hold a value for every column of the table. Truncating a table is a single change rather than
+/// a deletion per row.
+#[derive(Debug, Clone)]
+pub enum Change {
+    Inserted {
+        handle: RecordHandle,
+        values: Vec<Option<DataValue>>,
+    },
+    Updated {
+        handle: RecordHandle,
+        old: Vec<Option<DataValue>>,
+        new: Vec<Option<DataValue>>,
+    },
+    Deleted {
+        handle: RecordHandle,
+        values: Vec<Option<DataValue>>,
+    },
+    /// Every row was removed at once by `Table::truncate`.
+    Truncated { rows: usize },
+}
+
+pub type ChangeListener = Arc<dyn Fn(&Change) + Send + Sync>;
+
+/// Identifies a listener for `Table::remove_listener`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ListenerId(u64);
+
+#[derive(Clone, Default)]
+pub(crate) struct Listeners {
+    next_id: Arc<AtomicU64>,
+    listeners: SharedObject<IndexMap<u64, ChangeListener>>,
+}
+
+impl std::fmt::Debug for Listeners {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Listeners")
+            .field("count", &self.listeners.read().len())
+            .finish()
+    }
+}
+
+impl Listeners {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.listeners.read().is_empty()
+    }
+
+    pub(crate) fn notify(&self, changes: impl IntoIterator<Item = Change>) {
+        // listeners may add or remove listeners, so they're called without holding the lock
+        let listeners = self.listeners.read().values().cloned().collect::<Vec<_>>();
+
+        for change in changes {
+            for listener in listeners.iter() {
+                listener(&change);
+            }
+        }
+    }
+}
+
+impl Table {
+    /// Calls `f` with every row inserted, updated or deleted from now on, after the write is done.
+    /// Listeners run on the writing thread while other writes may be waiting, so they should hand
+    /// the change off rather than do much with it.
+    pub fn on_change(&self, f: impl Fn(&Change) + Send + Sync + 'static) -> ListenerId {
+        let id = self.listeners.next_id.fetch_add(1, Ordering::Relaxed);
+        self.listeners.listeners.write().insert(id, Arc::new(f));
+
+        ListenerId(id)
+    }
+
+    /// Returns `false` if the listener was already removed.
+    pub fn remove_listener(&self, id: ListenerId) -> bool {
+        self.listeners
+            .listeners
+            .write()
+            .shift_remove(&id.0)
+            .is_some()
+    }
+
+    /// Pads a written row out to every column, the way listeners get rows.
+    pub(crate) fn _full_row(&self, mut values: Vec<Option<DataValue>>) -> Vec<Option<DataValue>> {
+        values.resize(self.config.columns.len(), None);
+        values
+    }
+}
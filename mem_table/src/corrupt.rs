@@ -0,0 +1,77 @@
+use anyhow::Result;
+use dbexp::{indices::CellIdx, records::RecordHandle, store::CorruptValue, values::DataValue};
+
+use crate::{Table, TableError};
+
+/// What a scan does about a row whose value turns out to be corrupt, see `CorruptValue`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum OnCorrupt {
+    /// The scan fails with the `CorruptValue`.
+    #[default]
+    Fail,
+    /// The row is left out, and the `CorruptValue` reported along with the rows found.
+    Skip,
+}
+
+/// What `Table::read_rows` read.
+#[derive(Debug, Default)]
+pub struct RowScan {
+    /// Every row read, with its values, in insertion order.
+    pub rows: Vec<(RecordHandle, Vec<Option<DataValue>>)>,
+    /// The corrupt values of the rows left out, when the table's `on_corrupt` is `Skip`.
+    pub corrupt: Vec<CorruptValue>,
+}
+
+impl Table {
+    /// Reads every row of the table. A row with a corrupt value fails the scan or is left out, as
+    /// the table's `on_corrupt` says.
+    pub fn read_rows(&self) -> Result<RowScan, TableError> {
+        self.throttle_read(self.row_count())?;
+
+        let mut scan = RowScan::default();
+        let mut cancel = self._cancel_check();
+
+        for (_, handle) in self.scan_since(0) {
+            cancel.tick()?;
+
+            match self.get_versioned(&handle) {
+                Ok((values, _)) => scan.rows.push((handle, values)),
+                Err(TableError::Corrupt(value)) => {
+                    self._skip_corrupt(value.into(), &mut scan.corrupt)?
+                }
+                // a row removed since it was listed isn't read
+                Err(_) => continue,
+            }
+        }
+
+        Ok(scan)
+    }
+
+    /// Reads the value a record's cell points at, checked as the column store's `trusted_input`
+    /// says. Fails with a `CorruptValue` whatever `on_corrupt` is, since a single row can't be
+    /// skipped.
+    pub(crate) fn _read_cell(&self, column: usize, cell: CellIdx) -> Result<DataValue> {
+        let handle = self._column_handle(column, cell)?;
+
+        self.get_column_store(column)?
+            .read_value(&handle)?
+            .ok_or_else(|| TableError::not_found("column value").into())
+    }
+
+    /// Sorts out a failed read during a scan: a `CorruptValue` is added to `corrupt` when the
+    /// table's `on_corrupt` is `Skip`, and anything else is handed back.
+    pub(crate) fn _skip_corrupt(
+        &self,
+        error: anyhow::Error,
+        corrupt: &mut Vec<CorruptValue>,
+    ) -> Result<()> {
+        match (self.config.on_corrupt, error.downcast::<CorruptValue>()) {
+            (OnCorrupt::Skip, Ok(value)) => {
+                corrupt.push(value);
+                Ok(())
+            }
+            (OnCorrupt::Fail, Ok(value)) => Err(value.into()),
+            (_, Err(error)) => Err(error),
+        }
+    }
+}
@@ -0,0 +1,451 @@
+//! Pseudo-random rows that fit a table's schema, for load tests, benchmarks and sizing hardware.
+//! `for_table` generates rows for a `TableConfig`, each column drawn from the `Distribution` the
+//! `DataSpec` gives it, and the same spec and seed always give the same rows. Values fit their
+//! column: text and bytes are never longer than the column holds, text of a column with a logical
+//! type has its shape, and primary key columns are never empty and count up from
+//! `DataSpec::sequence_start`, so the rows of one load never share a key.
+
+use std::time::Duration;
+
+use dbexp::values::DataValue;
+use indexmap::IndexMap;
+use primitives::{Bytes, DataType, Number, Text, Timestamp, O16, O32, O64};
+use rand::{distributions::Alphanumeric, rngs::StdRng, Rng, SeedableRng};
+use serde::Serialize;
+
+use crate::{DataConfig, InsertState, LogicalType, Table, TableConfig, TableError};
+
+/// 2000-01-01 and 2100-01-01, in milliseconds.
+pub(crate) const TIMESTAMP_RANGE: std::ops::Range<i64> = 946_684_800_000..4_102_444_800_000;
+
+/// How many distinct values a `Zipf` column draws from.
+pub const ZIPF_POOL: usize = 1000;
+
+/// How many rows `Table::load_synthetic` inserts at once.
+const LOAD_BATCH_ROWS: usize = 1000;
+
+/// How the values of a column are drawn.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Distribution {
+    /// Any value the column holds, all equally likely. Text and bytes are anywhere from empty to
+    /// the column's capacity.
+    Uniform,
+    /// One of `ZIPF_POOL` values, the `k`th most common drawn with a weight of `1 / k^s`, as for a
+    /// low-cardinality text column such as a country or a status.
+    Zipf { s: f64 },
+    /// The row's number in the load, starting at `DataSpec::sequence_start`, in the column's
+    /// type.
+    Sequential,
+    /// A time up to `window` before `DataSpec::now`. Number columns get it in milliseconds, and
+    /// other columns are drawn as for `Uniform`.
+    RecentTimestamps { window: Duration },
+}
+
+/// How one column is generated.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ColumnSpec {
+    pub distribution: Distribution,
+    /// The chance of leaving the column empty, the spec's `null_chance` if `None`.
+    pub null_chance: Option<f64>,
+}
+
+impl ColumnSpec {
+    pub fn new(distribution: Distribution) -> Self {
+        Self {
+            distribution,
+            null_chance: None,
+        }
+    }
+
+    pub fn with_nulls(self, null_chance: f64) -> Self {
+        Self {
+            null_chance: Some(null_chance),
+            ..self
+        }
+    }
+}
+
+/// How the rows of a table are generated. Columns it leaves out are `Uniform`, or `Sequential` if
+/// they're part of the primary key.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DataSpec {
+    pub seed: u64,
+    /// The chance of leaving a column empty, between 0 and 1. Primary key columns never are.
+    pub null_chance: f64,
+    /// By column index.
+    pub columns: IndexMap<usize, ColumnSpec>,
+    /// The number of the first row, for `Sequential` columns. A second load into a table with a
+    /// primary key has to start past the first to not repeat its keys.
+    pub sequence_start: u64,
+    /// What `RecentTimestamps` are before, in milliseconds since the epoch. The time the rows
+    /// start being generated if `None`, which is the only thing that makes a spec give different
+    /// rows from one run to the next.
+    pub now: Option<i64>,
+}
+
+impl DataSpec {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            null_chance: 0.0,
+            columns: IndexMap::new(),
+            sequence_start: 1,
+            now: None,
+        }
+    }
+
+    pub fn with_nulls(self, null_chance: f64) -> Self {
+        Self {
+            null_chance,
+            ..self
+        }
+    }
+
+    pub fn with_column(mut self, column: usize, spec: ColumnSpec) -> Self {
+        self.columns.insert(column, spec);
+        self
+    }
+
+    pub fn with_sequence_start(self, sequence_start: u64) -> Self {
+        Self {
+            sequence_start,
+            ..self
+        }
+    }
+
+    pub fn with_now(self, now: i64) -> Self {
+        Self {
+            now: Some(now),
+            ..self
+        }
+    }
+}
+
+/// The rows of `config` for `seed`, with every column `Uniform` but the primary key, and none
+/// empty. See `with_spec` to choose how each column is drawn.
+pub fn for_table(config: &TableConfig, seed: u64) -> Generator {
+    with_spec(config, &DataSpec::new(seed))
+}
+
+/// The rows of `config` as `spec` says. The iterator never ends.
+pub fn with_spec(config: &TableConfig, spec: &DataSpec) -> Generator {
+    let mut rng = StdRng::seed_from_u64(spec.seed);
+    let now = spec
+        .now
+        .unwrap_or_else(|| Timestamp::new().timestamp_millis());
+    let key = config.primary_key.columns().collect::<Vec<_>>();
+
+    let columns = (0..config.columns.len())
+        .filter_map(|column| {
+            let config = *config.columns.get(column)?;
+            let is_key = key.contains(&column);
+            let own = spec.columns.get(&column);
+            let distribution = match (own, is_key) {
+                (Some(own), _) => own.distribution,
+                (None, true) => Distribution::Sequential,
+                (None, false) => Distribution::Uniform,
+            };
+            let null_chance = match is_key {
+                true => 0.0,
+                false => own
+                    .and_then(|own| own.null_chance)
+                    .unwrap_or(spec.null_chance),
+            };
+            let pool = match distribution {
+                Distribution::Zipf { s } => Some(ZipfPool::new(&mut rng, &config, s)),
+                _ => None,
+            };
+
+            Some(ColumnGen {
+                config,
+                distribution,
+                null_chance: null_chance.clamp(0.0, 1.0),
+                pool,
+            })
+        })
+        .collect();
+
+    Generator {
+        rng,
+        columns,
+        next: spec.sequence_start,
+        now,
+    }
+}
+
+#[derive(Debug, Clone)]
+struct ColumnGen {
+    config: DataConfig,
+    distribution: Distribution,
+    null_chance: f64,
+    pool: Option<ZipfPool>,
+}
+
+/// The values a `Zipf` column draws from, with the running sum of their weights.
+#[derive(Debug, Clone)]
+struct ZipfPool {
+    values: Vec<DataValue>,
+    cumulative: Vec<f64>,
+}
+
+impl ZipfPool {
+    fn new(rng: &mut StdRng, config: &DataConfig, s: f64) -> Self {
+        let values = (0..ZIPF_POOL).map(|_| value(rng, config)).collect();
+        let mut total = 0.0;
+        let cumulative = (1..=ZIPF_POOL)
+            .map(|k| {
+                total += 1.0 / (k as f64).powf(s);
+                total
+            })
+            .collect();
+
+        Self { values, cumulative }
+    }
+
+    fn sample(&self, rng: &mut StdRng) -> DataValue {
+        let total = self.cumulative.last().copied().unwrap_or_default();
+        let drawn = rng.gen_range(0.0..total.max(f64::MIN_POSITIVE));
+        let k = self.cumulative.partition_point(|&sum| sum <= drawn);
+
+        self.values[k.min(self.values.len() - 1)].clone()
+    }
+}
+
+/// Generated rows, see `with_spec`.
+#[derive(Debug, Clone)]
+pub struct Generator {
+    rng: StdRng,
+    columns: Vec<ColumnGen>,
+    /// The number of the next row, for `Sequential` columns.
+    next: u64,
+    now: i64,
+}
+
+impl Generator {
+    /// A row with at least one value, since tables turn away rows without any.
+    pub fn row(&mut self) -> Vec<Option<DataValue>> {
+        let n = self.next;
+        self.next += 1;
+
+        let mut row = (0..self.columns.len())
+            .map(
+                |column| match self.rng.gen_bool(self.columns[column].null_chance) {
+                    true => None,
+                    false => Some(self._value(column, n)),
+                },
+            )
+            .collect::<Vec<_>>();
+
+        if !row.is_empty() && row.iter().all(Option::is_none) {
+            let column = self.rng.gen_range(0..row.len());
+            row[column] = Some(self._value(column, n));
+        }
+
+        row
+    }
+
+    fn _value(&mut self, column: usize, n: u64) -> DataValue {
+        let gen = &self.columns[column];
+        let rng = &mut self.rng;
+
+        match gen.distribution {
+            Distribution::Uniform => value(rng, &gen.config),
+            Distribution::Zipf { .. } => match gen.pool.as_ref() {
+                Some(pool) => pool.sample(rng),
+                None => value(rng, &gen.config),
+            },
+            Distribution::Sequential => sequential(&gen.config, n),
+            Distribution::RecentTimestamps { window } => {
+                let window = window.as_millis().min(i64::MAX as u128) as i64;
+                let at = self.now - rng.gen_range(0..=window);
+
+                match *gen.config.data_type {
+                    DataType::Timestamp => timestamp(at),
+                    DataType::Number => DataValue::Number(Number::Integer(at)),
+                    _ => value(rng, &gen.config),
+                }
+            }
+        }
+    }
+}
+
+impl Iterator for Generator {
+    type Item = Vec<Option<DataValue>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.row())
+    }
+}
+
+fn timestamp(millis: i64) -> DataValue {
+    let millis = millis.clamp(TIMESTAMP_RANGE.start, TIMESTAMP_RANGE.end - 1);
+    DataValue::Timestamp(Timestamp::try_from_number(millis).expect("timestamp in range"))
+}
+
+fn text(text: &str, cap: u32) -> DataValue {
+    let text = match text.char_indices().nth(cap as usize) {
+        Some((end, _)) => &text[..end],
+        None => text,
+    };
+
+    DataValue::Text(Text::try_from_str(text, cap as usize).expect("text fits its column"))
+}
+
+fn alphanumeric(rng: &mut StdRng, len: usize) -> String {
+    (0..len).map(|_| rng.sample(Alphanumeric) as char).collect()
+}
+
+/// A value drawn as for `Distribution::Uniform`, with the shape of the column's logical type if
+/// it has one.
+fn value(rng: &mut StdRng, config: &DataConfig) -> DataValue {
+    match (*config.data_type, config.logical_type) {
+        (DataType::Text(cap), Some(logical_type)) => text(&logical_text(rng, logical_type), cap),
+        (data_type, _) => uniform(rng, data_type),
+    }
+}
+
+/// Text with the shape of `logical_type`, kept short so it fits the column it's named for.
+fn logical_text(rng: &mut StdRng, logical_type: LogicalType) -> String {
+    match logical_type {
+        LogicalType::Email => {
+            let local = rng.gen_range(1..=8);
+            let domain = rng.gen_range(1..=8);
+
+            format!(
+                "{}@{}.com",
+                alphanumeric(rng, local),
+                alphanumeric(rng, domain)
+            )
+        }
+        LogicalType::Phone => format!("+1{:010}", rng.gen_range(0..10_000_000_000u64)),
+        LogicalType::Url => {
+            let host = rng.gen_range(1..=8);
+            let path = rng.gen_range(0..=8);
+
+            format!(
+                "https://{}.com/{}",
+                alphanumeric(rng, host),
+                alphanumeric(rng, path)
+            )
+        }
+        LogicalType::Uuid => uuid(rng.gen()),
+    }
+}
+
+fn uuid(bits: u128) -> String {
+    let hex = format!("{:032x}", bits);
+
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// A value that fits a column of `data_type`. Numbers cover every finite variant, and text and
+/// bytes are anywhere from empty to the column's capacity.
+pub(crate) fn uniform(rng: &mut StdRng, data_type: DataType) -> DataValue {
+    match data_type {
+        DataType::O16 => DataValue::O16(O16::from_uint(rng.gen_range(1..=u16::MAX)).unwrap()),
+        DataType::O32 => DataValue::O32(O32::from_uint(rng.gen_range(1..=u32::MAX)).unwrap()),
+        DataType::O64 => DataValue::O64(O64::from_uint(rng.gen_range(1..=u64::MAX)).unwrap()),
+        DataType::Bool => DataValue::Bool(rng.gen()),
+        DataType::Number => DataValue::Number(match rng.gen_range(0..3) {
+            0 => Number::Integer(rng.gen()),
+            1 => Number::Unsigned(rng.gen()),
+            _ => Number::Float(rng.gen_range(-1e9..1e9)),
+        }),
+        DataType::Timestamp => timestamp(rng.gen_range(TIMESTAMP_RANGE)),
+        DataType::Text(cap) => {
+            let len = rng.gen_range(0..=cap as usize);
+            text(&alphanumeric(rng, len), cap)
+        }
+        DataType::Bytes(cap) => {
+            let len = rng.gen_range(0..=cap as usize);
+            let bytes = (0..len).map(|_| rng.gen()).collect::<Vec<u8>>();
+
+            DataValue::Bytes(Bytes::try_from_slice(&bytes, cap as usize).unwrap())
+        }
+    }
+}
+
+/// The `n`th value of a `Sequential` column. Text too short for `n` keeps its last digits, and
+/// bytes its last bytes, so only those columns can repeat.
+fn sequential(config: &DataConfig, n: u64) -> DataValue {
+    match *config.data_type {
+        DataType::O16 => DataValue::O16(O16::from_uint((n % u16::MAX as u64) as u16 + 1).unwrap()),
+        DataType::O32 => DataValue::O32(O32::from_uint((n % u32::MAX as u64) as u32 + 1).unwrap()),
+        DataType::O64 => DataValue::O64(O64::from_uint(n.max(1)).unwrap()),
+        DataType::Bool => DataValue::Bool(n % 2 == 1),
+        DataType::Number => DataValue::Number(Number::Unsigned(n)),
+        DataType::Timestamp => timestamp(TIMESTAMP_RANGE.start.saturating_add(n as i64 * 1000)),
+        DataType::Text(cap) => {
+            let digits = match config.logical_type {
+                Some(LogicalType::Email) => return text(&format!("user{}@example.com", n), cap),
+                Some(LogicalType::Phone) => {
+                    return text(&format!("+1{:010}", n % 10_000_000_000), cap)
+                }
+                Some(LogicalType::Url) => return text(&format!("https://example.com/{}", n), cap),
+                Some(LogicalType::Uuid) => return text(&uuid(n as u128), cap),
+                None => n.to_string(),
+            };
+
+            text(&digits[digits.len().saturating_sub(cap as usize)..], cap)
+        }
+        DataType::Bytes(cap) => {
+            let bytes = n.to_be_bytes();
+            let bytes = &bytes[bytes.len().saturating_sub(cap as usize)..];
+
+            DataValue::Bytes(Bytes::try_from_slice(bytes, cap as usize).unwrap())
+        }
+    }
+}
+
+/// What `Table::load_synthetic` inserted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct LoadReport {
+    pub inserted: usize,
+    /// Rows the table turned away, as for a key some rows already had.
+    pub rejected: usize,
+}
+
+impl Table {
+    /// Inserts `rows` rows generated as `spec` says, a batch at a time, for a load test in one
+    /// call. Columns that are unavailable are left empty.
+    pub fn load_synthetic(&self, rows: usize, spec: &DataSpec) -> Result<LoadReport, TableError> {
+        self._ensure_open()?;
+
+        let unavailable = self.unavailable_columns();
+        let mut gen = with_spec(&self.config, spec);
+        let mut report = LoadReport::default();
+        let mut left = rows;
+
+        while left > 0 {
+            let batch = (&mut gen)
+                .take(left.min(LOAD_BATCH_ROWS))
+                .map(|mut row| {
+                    for &column in unavailable.iter() {
+                        row[column] = None;
+                    }
+
+                    row
+                })
+                .collect::<Vec<_>>();
+
+            left -= batch.len();
+
+            match self.insert(batch)? {
+                InsertState::Done(handles) => report.inserted += handles.len(),
+                InsertState::Partial { handles, errors } => {
+                    report.inserted += handles.len();
+                    report.rejected += errors.len();
+                }
+            }
+        }
+
+        Ok(report)
+    }
+}
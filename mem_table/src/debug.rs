@@ -0,0 +1,293 @@
+use std::fmt::{self, Display, Write};
+
+use anyhow::Result;
+use dbexp::{block::BlockMeta, indices::CellIdx, records::RecordHandle, slot::SlotHandle};
+use primitives::idx::Gen;
+use serde::Serialize;
+
+use crate::{Table, TableError};
+
+/// Everything about one record that `Table::debug_record` could find, for support engineers to
+/// look into a row that misbehaves. Reading one part failing doesn't keep the others from being
+/// read: the error is kept in the place of what couldn't be read.
+#[derive(Debug, Clone, Serialize)]
+pub struct RecordDebug {
+    /// The record's id, as `RecordId` prints it.
+    pub record: String,
+    pub seq: u64,
+    /// The row's version token, see `Table::get_versioned`.
+    pub version: String,
+    /// The record's slot in the record store.
+    pub slot: SlotDebug,
+    /// The cells of the record, one per column, as `ColumnIndices` prints them.
+    pub indices: String,
+    pub columns: Vec<ColumnDebug>,
+}
+
+/// A slot of one of the table's stores, byte for byte, and where it lives.
+#[derive(Debug, Clone, Serialize)]
+pub struct SlotDebug {
+    pub block: usize,
+    pub slot: usize,
+    /// The file the store is persisted in, `None` for a memory-only table.
+    pub file: Option<String>,
+    /// Where the slot starts in `file`.
+    pub offset: Option<u64>,
+    pub gap: bool,
+    /// The generation of the record id the slot holds, `None` for a gap and for a slot holding no
+    /// record id, or one without a generation, as the slots of the record store do.
+    pub gen: Option<u64>,
+    /// The slot's bytes, as hex.
+    pub bytes: String,
+    pub block_meta: BlockMetaDebug,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BlockMetaDebug {
+    pub index: usize,
+    pub length: usize,
+    pub capacity: usize,
+    pub gap_count: usize,
+    pub gap_tail: Option<usize>,
+}
+
+/// What the record points at in one column.
+#[derive(Debug, Clone, Serialize)]
+pub struct ColumnDebug {
+    pub column: usize,
+    pub name: Option<String>,
+    /// The cell the record holds for the column, `None` if it leaves the column empty.
+    pub cell: Option<String>,
+    /// The generation the cell expects the column slot to have, if it has one.
+    pub cell_gen: Option<u64>,
+    pub slot: Option<SlotDebug>,
+    /// The value read back from the slot, as `DataValue` debug prints it.
+    pub value: Option<String>,
+    /// Why the slot or its value couldn't be read.
+    pub error: Option<String>,
+}
+
+impl RecordDebug {
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::to_value(self).unwrap_or_default()
+    }
+}
+
+fn gen_number(gen: Gen) -> u64 {
+    gen.into_raw().into_u64()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{:02x}", byte);
+        hex
+    })
+}
+
+impl From<BlockMeta> for BlockMetaDebug {
+    fn from(meta: BlockMeta) -> Self {
+        Self {
+            index: meta.index.into_usize(),
+            length: meta.length,
+            capacity: meta.block_capacity(),
+            gap_count: meta.gap_count,
+            gap_tail: meta.gap_tail.map(|tail| tail.into_usize()),
+        }
+    }
+}
+
+impl SlotDebug {
+    /// Reads the slot a handle points at, whatever generation it has by now.
+    fn read<T>(handle: &SlotHandle<T>, file: Option<String>) -> Result<Self> {
+        let dump = handle.slot_dump().ok_or_else(|| {
+            anyhow::anyhow!(
+                "slot {} is past the end of block {}",
+                handle.idx.into_usize(),
+                handle.block.index()
+            )
+        })?;
+        let (gap, record) = handle
+            .clone()
+            .erase_idx_gen()
+            .read_with(|slot| Ok((slot.is_gap(), slot.thin_record_id())))?;
+
+        Ok(Self {
+            block: dump.block,
+            slot: dump.slot,
+            file,
+            offset: dump.offset,
+            gap,
+            gen: record
+                .map(|record| record.gen())
+                .filter(|gen| *gen != Gen::INVALID)
+                .map(gen_number),
+            bytes: hex(&dump.bytes),
+            block_meta: dump.block_meta.into(),
+        })
+    }
+
+    fn fmt_indented(&self, f: &mut fmt::Formatter<'_>, indent: &str) -> fmt::Result {
+        write!(f, "{}block {} slot {}", indent, self.block, self.slot)?;
+
+        match (&self.file, self.offset) {
+            (Some(file), Some(offset)) => write!(f, " at {}+{}", file, offset)?,
+            (Some(file), None) => write!(f, " in {}", file)?,
+            _ => write!(f, " in memory")?,
+        }
+
+        match self.gen {
+            _ if self.gap => writeln!(f, ", a gap")?,
+            Some(gen) => writeln!(f, ", gen {}", gen)?,
+            None => writeln!(f)?,
+        }
+
+        let meta = &self.block_meta;
+
+        write!(
+            f,
+            "{}  block {}: {} of {} slots handed out, {} gaps",
+            indent, meta.index, meta.length, meta.capacity, meta.gap_count
+        )?;
+
+        if let Some(tail) = meta.gap_tail {
+            write!(f, ", last gap at {}", tail)?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "{}  bytes: {}", indent, self.bytes)
+    }
+}
+
+impl Display for RecordDebug {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "record {} (seq {}, version {})",
+            self.record, self.seq, self.version
+        )?;
+        self.slot.fmt_indented(f, "  ")?;
+        writeln!(f, "  cells: {}", self.indices)?;
+
+        for column in self.columns.iter() {
+            write!(f, "column {}", column.column)?;
+
+            if let Some(name) = column.name.as_ref() {
+                write!(f, " '{}'", name)?;
+            }
+
+            let Some(cell) = column.cell.as_ref() else {
+                writeln!(f, ": empty")?;
+                continue;
+            };
+
+            match column.cell_gen {
+                Some(gen) => writeln!(f, ": cell {}, gen {}", cell, gen)?,
+                None => writeln!(f, ": cell {}", cell)?,
+            }
+
+            if let Some(slot) = column.slot.as_ref() {
+                slot.fmt_indented(f, "  ")?;
+            }
+
+            if let Some(value) = column.value.as_ref() {
+                writeln!(f, "  value: {}", value)?;
+            }
+
+            if let Some(error) = column.error.as_ref() {
+                writeln!(f, "  error: {}", error)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Table {
+    /// Gathers everything about the record `id` into one bundle: its slot and the column slots it
+    /// points at, byte for byte, where each of them lives, their generations and block metas, and
+    /// what they decode to. `id` is the record's seq, or its id as `RecordId` prints it. Expired
+    /// rows are found too.
+    pub fn debug_record(&self, id: &str) -> Result<RecordDebug, TableError> {
+        let handle = self._find_debug_record(id)?;
+        let _swap = self.swap_gate.read_recursive();
+        let indices = handle.read_with(|slot| {
+            slot.data()
+                .copied()
+                .ok_or_else(|| TableError::not_found("record").into())
+        })?;
+
+        let mut columns = Vec::with_capacity(self.config.columns.len());
+
+        for column in 0..self.config.columns.len() {
+            columns.push(self._debug_column(column, indices.get(self._cell(column))));
+        }
+
+        Ok(RecordDebug {
+            record: self.records.record_id(&handle).to_string(),
+            seq: indices.seq(),
+            version: indices.gen().to_string(),
+            slot: SlotDebug::read(&handle, self._debug_file(None))?,
+            indices: format!("{:?}", indices),
+            columns,
+        })
+    }
+
+    fn _find_debug_record(&self, id: &str) -> Result<RecordHandle, TableError> {
+        if let Some(handle) = id.parse().ok().and_then(|seq| self.records.get_by_seq(seq)) {
+            return Ok(handle);
+        }
+
+        let mut found = None;
+
+        self.records.foreach_live(|handle, _| {
+            if found.is_none() && self.records.record_id(&handle).to_string() == id {
+                found = Some(handle);
+            }
+        });
+
+        found.ok_or_else(|| TableError::not_found(format!("record {}", id)))
+    }
+
+    /// The file of the record store, or of the store of `column`.
+    fn _debug_file(&self, column: Option<usize>) -> Option<String> {
+        let path = match column {
+            Some(column) => self.get_column_store(column).ok()?.backing_path(),
+            None => self.records.backing_path(),
+        };
+
+        path.map(|path| path.display().to_string())
+    }
+
+    fn _debug_column(&self, column: usize, cell: Option<CellIdx>) -> ColumnDebug {
+        let mut debug = ColumnDebug {
+            column,
+            name: self
+                .config
+                .columns
+                .get(column)
+                .and_then(|config| config.name)
+                .map(|name| name.to_string()),
+            cell: cell.map(|cell| format!("{:?}", cell)),
+            cell_gen: cell.and_then(|cell| cell.row().into_gen()).map(gen_number),
+            slot: None,
+            value: None,
+            error: None,
+        };
+
+        let Some(cell) = cell else {
+            return debug;
+        };
+
+        let read = self._column_handle(column, cell).and_then(|handle| {
+            debug.slot = Some(SlotDebug::read(&handle, self._debug_file(Some(column)))?);
+            self._read_cell(column, cell)
+        });
+
+        match read {
+            Ok(value) => debug.value = Some(format!("{:?}", value)),
+            Err(error) => debug.error = Some(format!("{:#}", error)),
+        }
+
+        debug
+    }
+}
@@ -0,0 +1,174 @@
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use dbexp::values::DataValue;
+use primitives::{DataType, Number, Timestamp, O64};
+
+use crate::Table;
+
+/// How many values a sequence hands out for every high-water mark it writes. A crash loses what's
+/// left of the batch, which leaves a gap in the sequence but never a value handed out twice.
+pub const SEQUENCE_BATCH: i64 = 32;
+
+/// The annotation a column's sequence keeps its high-water mark in, see `Table::meta`. Keyed by
+/// the column's cell, which stays put as the columns around it are added and dropped.
+pub(crate) fn sequence_key(cell: usize) -> String {
+    format!("sequence.{}", cell)
+}
+
+/// How a value is made up for a column a row is inserted without.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GeneratorKind {
+    /// `start`, then every `step` after it, counted per table and column. Values are unique for
+    /// the life of the table, and increase with `step` in the order they're handed out, but rows
+    /// that fail to insert and crashes leave gaps.
+    Sequence { start: i64, step: i64 },
+    /// A random id, for an `O64` column.
+    RandomO64,
+    /// The time of the insert, for a timestamp column.
+    NowTimestamp,
+}
+
+impl GeneratorKind {
+    /// The only column type the generator can fill.
+    pub fn data_type(self) -> DataType {
+        match self {
+            Self::Sequence { .. } => DataType::Number,
+            Self::RandomO64 => DataType::O64,
+            Self::NowTimestamp => DataType::Timestamp,
+        }
+    }
+
+    pub(crate) fn into_parts(generator: Option<Self>) -> (u8, i64, i64) {
+        match generator {
+            None => (0, 0, 0),
+            Some(Self::Sequence { start, step }) => (1, start, step),
+            Some(Self::RandomO64) => (2, 0, 0),
+            Some(Self::NowTimestamp) => (3, 0, 0),
+        }
+    }
+
+    pub(crate) fn try_from_parts(kind: u8, start: i64, step: i64) -> Result<Option<Self>> {
+        match kind {
+            0 => Ok(None),
+            1 => Ok(Some(Self::Sequence { start, step })),
+            2 => Ok(Some(Self::RandomO64)),
+            3 => Ok(Some(Self::NowTimestamp)),
+            other => anyhow::bail!("invalid column default {}", other),
+        }
+    }
+}
+
+/// The next value of a column's sequence and the last one its high-water mark covers.
+#[derive(Debug)]
+pub(crate) struct Sequence {
+    next: i64,
+    reserved: Option<i64>,
+}
+
+impl Table {
+    /// Fills in the columns with a default that the row has no value for. Only inserts do this,
+    /// so an update can still clear the column. A default that fails leaves the row as it was.
+    pub(crate) fn _fill_defaults(&self, values: &mut Vec<Option<DataValue>>) -> Result<()> {
+        let mut filled = Vec::new();
+
+        for column in 0..self.config.columns.len() {
+            let Some(generator) = self.config.columns.get(column).and_then(|c| c.default) else {
+                continue;
+            };
+
+            if values.get(column).is_some_and(Option::is_some) {
+                continue;
+            }
+
+            let value = match generator {
+                GeneratorKind::Sequence { start, step } => {
+                    DataValue::Number(Number::from(self._next_in_sequence(column, start, step)?))
+                }
+                GeneratorKind::RandomO64 => DataValue::O64(O64::new()),
+                GeneratorKind::NowTimestamp => DataValue::Timestamp(Timestamp::new()),
+            };
+
+            filled.push((column, value));
+        }
+
+        for (column, value) in filled {
+            if values.len() <= column {
+                values.resize(column + 1, None);
+            }
+
+            values[column] = Some(value);
+        }
+
+        Ok(())
+    }
+
+    /// Hands out the next value of a column's sequence. The high-water mark is written before any
+    /// value past the last one is handed out, so a reopened table carries on after every value
+    /// handed out before, skipping what's left of the batch.
+    fn _next_in_sequence(&self, column: usize, start: i64, step: i64) -> Result<i64> {
+        let sequence = {
+            let sequences = self.sequences.upgradable();
+
+            match sequences.get(&column) {
+                Some(sequence) => sequence.clone(),
+                None => {
+                    let next = match self.meta()?.get(&sequence_key(self._cell(column)))? {
+                        Some(DataValue::Number(Number::Integer(mark))) => {
+                            mark.checked_add(step).ok_or_else(|| {
+                                anyhow::anyhow!("sequence of column {} ran out", column)
+                            })?
+                        }
+                        Some(other) => anyhow::bail!(
+                            "sequence of column {} has a corrupt high-water mark: {:?}",
+                            column,
+                            other
+                        ),
+                        None => start,
+                    };
+
+                    let sequence = Arc::new(Mutex::new(Sequence {
+                        next,
+                        reserved: None,
+                    }));
+
+                    sequences.upgrade().insert(column, sequence.clone());
+                    sequence
+                }
+            }
+        };
+
+        let mut sequence = sequence
+            .lock()
+            .map_err(|_| anyhow::anyhow!("sequence of column {} is poisoned", column))?;
+        let value = sequence.next;
+        let next = value
+            .checked_add(step)
+            .ok_or_else(|| anyhow::anyhow!("sequence of column {} ran out", column))?;
+
+        let covered = sequence.reserved.is_some_and(|reserved| {
+            if step > 0 {
+                value <= reserved
+            } else {
+                value >= reserved
+            }
+        });
+
+        if !covered {
+            let reserved = step
+                .checked_mul(SEQUENCE_BATCH - 1)
+                .and_then(|ahead| value.checked_add(ahead))
+                .unwrap_or(if step > 0 { i64::MAX } else { i64::MIN });
+
+            self.meta()?.set(
+                &sequence_key(self._cell(column)),
+                DataValue::Number(Number::from(reserved)),
+            )?;
+            sequence.reserved = Some(reserved);
+        }
+
+        sequence.next = next;
+
+        Ok(value)
+    }
+}
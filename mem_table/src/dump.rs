@@ -0,0 +1,313 @@
+use std::{
+    fs,
+    path::{Component, Path},
+};
+
+use anyhow::Result;
+use dbexp::{object_ids::TableId, records::Records, store::Store, values::DataValue};
+use indexmap::IndexMap;
+use primitives::{
+    byte_encoding::{FromBytes, IntoBytes},
+    into_bytes, DataType, InternalPath, InternalString,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    column_file,
+    layout::{migrate_to_single_file, StorageLayout},
+    meta::{MetaTable, META_DIR},
+    DataConfig, Table, TableConfig, TableError,
+};
+
+/// Bumped whenever the layout of a dumped file changes.
+pub const DUMP_FORMAT_VERSION: u32 = 12;
+
+/// The oldest dump format that can still be restored. Version 11 dumps predate column ids, and
+/// their config reads with every column kept at the cell of its position, like its file.
+const OLDEST_RESTORED_VERSION: u32 = 11;
+
+const MANIFEST_FILE: &str = "manifest.json";
+const CONFIG_FILE: &str = "table.config";
+const RECORDS_FILE: &str = "records.store";
+
+/// Describes a table dumped with `Table::dump`. Written next to the dumped files as JSON.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DumpManifest {
+    pub format_version: u32,
+    pub table: TableId,
+    pub row_count: usize,
+    pub column_names: Vec<(String, usize)>,
+    pub files: Vec<DumpedFile>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DumpedFile {
+    /// Relative to the dump directory.
+    pub name: String,
+    pub len: u64,
+    /// FNV-1a 64 of the file's contents, as hex.
+    pub checksum: String,
+}
+
+impl DumpedFile {
+    fn new(name: String, bytes: &[u8]) -> Self {
+        Self {
+            name,
+            len: bytes.len() as u64,
+            checksum: checksum(bytes),
+        }
+    }
+
+    fn verify(&self, bytes: &[u8]) -> Result<()> {
+        if bytes.len() as u64 != self.len {
+            anyhow::bail!(
+                "{} is {} bytes but the manifest says {}",
+                self.name,
+                bytes.len(),
+                self.len
+            );
+        }
+
+        if checksum(bytes) != self.checksum {
+            anyhow::bail!("checksum mismatch for {}", self.name);
+        }
+
+        Ok(())
+    }
+}
+
+/// Spelled out rather than using `DefaultHasher`, whose output may change between Rust releases.
+fn checksum(bytes: &[u8]) -> String {
+    let hash = bytes.iter().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+    });
+
+    format!("{:016x}", hash)
+}
+
+/// Creates `dir` if needed, refusing to write into one that already has something in it.
+fn prepare_dir(dir: &Path) -> Result<()> {
+    fs::create_dir_all(dir)?;
+
+    if fs::read_dir(dir)?.next().is_some() {
+        anyhow::bail!("{} is not empty", dir.display());
+    }
+
+    Ok(())
+}
+
+/// Manifest file names come from disk, so they must stay inside the dump directory.
+fn validate_name(name: &str) -> Result<()> {
+    let path = Path::new(name);
+
+    if name.is_empty() || !path.components().all(|c| matches!(c, Component::Normal(_))) {
+        anyhow::bail!("invalid file name {:?} in dump manifest", name);
+    }
+
+    Ok(())
+}
+
+impl Table {
+    /// Copies the table, its annotations, config and column names into `dest_dir` along with a
+    /// manifest, for inspecting or restoring elsewhere with `restore_from_dump`. Writes are held
+    /// off while the stores are copied, so the dump is a consistent snapshot. Memory-only tables
+    /// are dumped in the same layout as persisted ones.
+    pub fn dump(&self, dest_dir: impl AsRef<Path>) -> Result<DumpManifest, TableError> {
+        Ok(self._dump(dest_dir.as_ref())?)
+    }
+
+    fn _dump(&self, dest_dir: &Path) -> Result<DumpManifest> {
+        prepare_dir(dest_dir)?;
+
+        let meta = self._existing_meta()?;
+        let mut files = Vec::new();
+
+        let row_count = {
+            let _writes = self.write_gate.write();
+
+            self._dump_stores(dest_dir, None, &mut files)?;
+
+            if let Some(meta) = meta.as_ref() {
+                let _meta_writes = meta.table().write_gate.write();
+
+                fs::create_dir(dest_dir.join(META_DIR))?;
+                meta.table()
+                    ._dump_stores(dest_dir, Some(META_DIR), &mut files)?;
+            }
+
+            self.row_count()
+        };
+
+        // the persistance path only means something on this machine
+        let config = TableConfig {
+            persistance: InternalPath::default(),
+            ..self.config
+        };
+        let config = into_bytes!(config, TableConfig)?;
+        fs::write(dest_dir.join(CONFIG_FILE), config)?;
+        files.push(DumpedFile::new(CONFIG_FILE.to_string(), &config));
+
+        let manifest = DumpManifest {
+            format_version: DUMP_FORMAT_VERSION,
+            table: self.id,
+            row_count,
+            column_names: self
+                .columns_by_name
+                .iter()
+                .map(|(name, idx)| (name.as_str().to_string(), *idx))
+                .collect(),
+            files,
+        };
+
+        fs::write(
+            dest_dir.join(MANIFEST_FILE),
+            serde_json::to_vec_pretty(&manifest)?,
+        )?;
+
+        Ok(manifest)
+    }
+
+    /// Checks a dump made with `dump` against its manifest, copies it into `dest_dir` and opens
+    /// the copy as a new persisted table with a fresh `TableId`.
+    pub fn restore_from_dump(
+        dump_dir: impl AsRef<Path>,
+        dest_dir: impl AsRef<Path>,
+    ) -> Result<Self, TableError> {
+        Ok(Self::_restore_from_dump(
+            dump_dir.as_ref(),
+            dest_dir.as_ref(),
+        )?)
+    }
+
+    fn _restore_from_dump(dump_dir: &Path, dest_dir: &Path) -> Result<Self> {
+        let manifest: DumpManifest =
+            serde_json::from_slice(&fs::read(dump_dir.join(MANIFEST_FILE))?)?;
+
+        if !(OLDEST_RESTORED_VERSION..=DUMP_FORMAT_VERSION).contains(&manifest.format_version) {
+            anyhow::bail!(
+                "unsupported dump format version {}, expected {} to {}",
+                manifest.format_version,
+                OLDEST_RESTORED_VERSION,
+                DUMP_FORMAT_VERSION
+            );
+        }
+
+        let mut contents = Vec::with_capacity(manifest.files.len());
+
+        for file in manifest.files.iter() {
+            validate_name(&file.name)?;
+
+            let bytes = fs::read(dump_dir.join(&file.name))?;
+            file.verify(&bytes)?;
+            contents.push(bytes);
+        }
+
+        let config = manifest
+            .files
+            .iter()
+            .position(|file| file.name == CONFIG_FILE)
+            .ok_or_else(|| anyhow::anyhow!("dump has no {}", CONFIG_FILE))?;
+
+        let mut config = {
+            let mut base = TableConfig::new([DataConfig::new(DataType::Bool)])?;
+            base.init_from_bytes(&contents[config])?;
+            base
+        };
+        config.persistance = InternalPath::new(dest_dir)?;
+
+        prepare_dir(dest_dir)?;
+
+        let id = TableId::new();
+
+        for (file, bytes) in manifest.files.iter().zip(contents) {
+            let path = dest_dir.join(&file.name);
+
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+
+            fs::write(&path, bytes)?;
+
+            if file.name == CONFIG_FILE {
+                continue;
+            } else if path.ends_with(RECORDS_FILE) {
+                Records::retag_image(&path, id)?;
+            } else {
+                Store::<DataValue>::retag_image(&path, id)?;
+            }
+        }
+
+        // dumps always hold a file per store, which a single file table is moved into
+        let config = match config.storage_layout {
+            StorageLayout::SeparateFiles => config,
+            StorageLayout::SingleFile => migrate_to_single_file(
+                &config.with_storage_layout(StorageLayout::SeparateFiles),
+                None,
+            )?,
+        };
+
+        let names = manifest
+            .column_names
+            .iter()
+            .map(|(name, idx)| Ok((InternalString::new(name)?, *idx)))
+            .collect::<Result<IndexMap<_, _>>>()?;
+
+        Ok(Table::new(id, config, Some(names))?)
+    }
+
+    /// Writes an image of the record store and every column store into `dir`, or its `sub_dir`.
+    fn _dump_stores(
+        &self,
+        dir: &Path,
+        sub_dir: Option<&str>,
+        files: &mut Vec<DumpedFile>,
+    ) -> Result<()> {
+        let name = |file: String| match sub_dir {
+            Some(sub_dir) => format!("{}/{}", sub_dir, file),
+            None => file,
+        };
+
+        let mut dumped = vec![name(RECORDS_FILE.to_string())];
+        self.records
+            .write_image(dir.join(&dumped[0]), self.config.scan)?;
+
+        // columns of persisted tables may have data on disk without having been opened yet
+        for idx in 0..self.config.columns.len() {
+            let file = name(column_file(self.config.columns.cell(idx)));
+            self.get_column_store(idx)?
+                .write_image(dir.join(&file), self.config.scan)?;
+            dumped.push(file);
+        }
+
+        for file in dumped {
+            let bytes = fs::read(dir.join(&file))?;
+            files.push(DumpedFile::new(file, &bytes));
+        }
+
+        Ok(())
+    }
+
+    /// The table's annotations, if it has any to dump.
+    fn _existing_meta(&self) -> Result<Option<MetaTable>> {
+        if self.meta.read().is_some() {
+            return Ok(Some(self.meta()?));
+        }
+
+        if self.config.persistance.is_empty() {
+            return Ok(None);
+        }
+
+        let dir = self.config.persistance.child(META_DIR)?;
+        let dir = if self.root.is_empty() {
+            dir.to_path_buf()
+        } else {
+            dir.resolve(self.root)
+        };
+
+        if dir.exists() {
+            Ok(Some(self.meta()?))
+        } else {
+            Ok(None)
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use std::{
+    any::Any,
+    fmt::{Debug, Display},
+};
+
+use dbexp::{
+    slot::StaleHandleError,
+    store::{CorruptValue, StoreFull},
+};
+
+use crate::{
+    ColumnUnavailable, CompositeKey, QueryCancelled, QueryTimeout, RowValidationError, Throttled,
+    UniqueViolation, UpdateConflict, ValueError,
+};
+
+/// What a call to a table failed with, for callers that need to tell failures apart. Errors from
+/// the stores and helpers underneath are sorted into a variant by type when converted from
+/// `anyhow::Error`, and anything that doesn't fit a narrower one is `Storage`. Converts back into
+/// `anyhow::Error` with `?`, so callers that don't care can go on as before.
+#[derive(Debug, thiserror::Error)]
+pub enum TableError {
+    /// A row, value or argument the table doesn't take, like a value of the wrong type or more
+    /// values than there are columns. Nothing was written. Made from a `RowValidationError` or
+    /// `ValueError` when there is one.
+    #[error(transparent)]
+    Validation(anyhow::Error),
+    /// Another row already holds the primary key of the row written.
+    #[error("duplicate primary key {0}")]
+    DuplicateKey(CompositeKey),
+    /// Another row already holds the value the row written has in a unique column.
+    #[error(transparent)]
+    UniqueViolation(#[from] UniqueViolation),
+    /// Other writes to the row kept getting in before the update's, see `Table::update_one`.
+    /// Nothing was written.
+    #[error(transparent)]
+    Conflict(#[from] UpdateConflict),
+    /// The table, or one of its stores, can't take any more, see `StoreFull`.
+    #[error(transparent)]
+    Capacity(anyhow::Error),
+    /// The row or column asked for isn't there, or no longer is.
+    #[error("{0} not found")]
+    NotFound(String),
+    /// The handle is to a slot removed or handed out again since.
+    #[error(transparent)]
+    Stale(#[from] StaleHandleError),
+    /// The stores failed in a way none of the other variants cover, e.g. another process holding
+    /// their lock or a callback that panicked.
+    #[error(transparent)]
+    Storage(anyhow::Error),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Corrupt(#[from] CorruptValue),
+    /// The column read is unavailable, see `OpenOptions`.
+    #[error(transparent)]
+    Unavailable(#[from] ColumnUnavailable),
+    /// The query was stopped by its token, with `QueryCancelled` or `QueryTimeout`.
+    #[error(transparent)]
+    Cancelled(anyhow::Error),
+    /// The table's rate limiter turned the call away, see `RateLimiter`. Nothing was read or
+    /// written.
+    #[error(transparent)]
+    Throttled(#[from] Throttled),
+    #[error("table is closed")]
+    Closed,
+}
+
+impl TableError {
+    pub fn invalid(message: impl Display + Debug + Send + Sync + 'static) -> Self {
+        Self::Validation(anyhow::Error::msg(message))
+    }
+
+    pub fn not_found(what: impl Display) -> Self {
+        Self::NotFound(what.to_string())
+    }
+
+    /// The error of type `E` this one was made from, if any, like `anyhow::Error::downcast_ref`.
+    pub fn downcast_ref<E>(&self) -> Option<&E>
+    where
+        E: Display + Debug + Send + Sync + 'static,
+    {
+        match self {
+            Self::Validation(error)
+            | Self::Capacity(error)
+            | Self::Storage(error)
+            | Self::Cancelled(error) => error.downcast_ref(),
+            Self::Stale(error) => (error as &dyn Any).downcast_ref(),
+            Self::Io(error) => (error as &dyn Any).downcast_ref(),
+            Self::Corrupt(error) => (error as &dyn Any).downcast_ref(),
+            Self::Unavailable(error) => (error as &dyn Any).downcast_ref(),
+            Self::Throttled(error) => (error as &dyn Any).downcast_ref(),
+            Self::UniqueViolation(error) => (error as &dyn Any).downcast_ref(),
+            Self::Conflict(error) => (error as &dyn Any).downcast_ref(),
+            Self::DuplicateKey(_) | Self::NotFound(_) | Self::Closed => None,
+        }
+    }
+
+    pub fn is<E>(&self) -> bool
+    where
+        E: Display + Debug + Send + Sync + 'static,
+    {
+        self.downcast_ref::<E>().is_some()
+    }
+}
+
+impl From<anyhow::Error> for TableError {
+    fn from(error: anyhow::Error) -> Self {
+        // the ones kept whole keep the context they were given along the way
+        if error.is::<RowValidationError>() || error.is::<ValueError>() {
+            return Self::Validation(error);
+        } else if error.is::<StoreFull>() {
+            return Self::Capacity(error);
+        } else if error.is::<QueryCancelled>() || error.is::<QueryTimeout>() {
+            return Self::Cancelled(error);
+        }
+
+        error
+            .downcast::<TableError>()
+            .or_else(|error| error.downcast().map(Self::Corrupt))
+            .or_else(|error| error.downcast().map(Self::Unavailable))
+            .or_else(|error| error.downcast().map(Self::Throttled))
+            .or_else(|error| error.downcast().map(Self::UniqueViolation))
+            .or_else(|error| error.downcast().map(Self::Conflict))
+            .or_else(|error| error.downcast().map(Self::Stale))
+            .or_else(|error| error.downcast().map(Self::Io))
+            .unwrap_or_else(Self::Storage)
+    }
+}
+
+impl From<RowValidationError> for TableError {
+    fn from(error: RowValidationError) -> Self {
+        Self::Validation(error.into())
+    }
+}
+
+impl From<ValueError> for TableError {
+    fn from(error: ValueError) -> Self {
+        Self::Validation(error.into())
+    }
+}
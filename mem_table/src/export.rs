@@ -0,0 +1,376 @@
+//! Exports tables as Arrow record batches and Parquet files, for analysts. Only built with the
+//! `arrow` feature.
+//!
+//! Columns map to Arrow types by their `DataType`:
+//!
+//! | `DataType`            | Arrow                              |
+//! |-----------------------|------------------------------------|
+//! | `O16`, `O32`, `O64`   | `FixedSizeBinary` of 2, 4, 8 bytes |
+//! | `Bool`                | `Boolean`                          |
+//! | `Number`              | `Int64`, `UInt64` or `Float64`     |
+//! | `Timestamp`           | `Timestamp(Nanosecond)`            |
+//! | `Text`                | `Utf8`                             |
+//! | `Bytes`               | `Binary`                           |
+//!
+//! A number column holds integers, unsigned integers and floats alike, while an Arrow column has
+//! one type. It's exported as the narrowest type that holds every value found in the column:
+//! `Int64` if they're all integers that fit, `UInt64` if some only fit unsigned and none are
+//! negative, and `Float64` otherwise, in which case integers beyond 2^53 lose precision. A pass over
+//! the column decides before the first batch. Empty values are Arrow nulls.
+
+use std::{fs::File, path::Path, sync::Arc, vec};
+
+use anyhow::Result;
+use arrow::{
+    array::{
+        ArrayRef, BinaryBuilder, BooleanBuilder, FixedSizeBinaryBuilder, Float64Builder,
+        Int64Builder, StringBuilder, TimestampNanosecondBuilder, UInt64Builder,
+    },
+    datatypes::{DataType as ArrowType, Field, Schema, SchemaRef, TimeUnit},
+    record_batch::RecordBatch,
+};
+use dbexp::{records::RecordHandle, values::DataValue};
+use parquet::arrow::ArrowWriter;
+use primitives::{DataType, Number};
+
+use crate::{OnCorrupt, Table, TableError};
+
+/// How many rows go in a batch unless the export says otherwise.
+pub const DEFAULT_BATCH_SIZE: usize = 8192;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExportOptions {
+    /// The columns exported, in this order. `None` exports every column.
+    pub projection: Option<Vec<usize>>,
+    /// The most rows in a batch, and so in a Parquet row group.
+    pub batch_size: usize,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self {
+            projection: None,
+            batch_size: DEFAULT_BATCH_SIZE,
+        }
+    }
+}
+
+/// What a number column is exported as, see the module docs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NumberKind {
+    Int64,
+    UInt64,
+    Float64,
+}
+
+impl NumberKind {
+    fn of(numbers: impl IntoIterator<Item = Number>) -> Self {
+        let (mut negative, mut beyond_i64) = (false, false);
+
+        for number in numbers {
+            match number {
+                Number::Integer(n) => negative |= n < 0,
+                Number::Unsigned(n) => beyond_i64 |= n > i64::MAX as u64,
+                Number::Float(_) | Number::NaN | Number::Infinity(_) => return Self::Float64,
+            }
+        }
+
+        match (negative, beyond_i64) {
+            (_, false) => Self::Int64,
+            (false, true) => Self::UInt64,
+            (true, true) => Self::Float64,
+        }
+    }
+}
+
+fn arrow_type(data_type: DataType, numbers: NumberKind) -> ArrowType {
+    match data_type {
+        DataType::O16 => ArrowType::FixedSizeBinary(2),
+        DataType::O32 => ArrowType::FixedSizeBinary(4),
+        DataType::O64 => ArrowType::FixedSizeBinary(8),
+        DataType::Bool => ArrowType::Boolean,
+        DataType::Number => match numbers {
+            NumberKind::Int64 => ArrowType::Int64,
+            NumberKind::UInt64 => ArrowType::UInt64,
+            NumberKind::Float64 => ArrowType::Float64,
+        },
+        DataType::Timestamp => ArrowType::Timestamp(TimeUnit::Nanosecond, None),
+        DataType::Text(_) => ArrowType::Utf8,
+        DataType::Bytes(_) => ArrowType::Binary,
+    }
+}
+
+/// Builds the Arrow array of one column of a batch.
+enum ColumnBuilder {
+    Oid(FixedSizeBinaryBuilder),
+    Bool(BooleanBuilder),
+    Int64(Int64Builder),
+    UInt64(UInt64Builder),
+    Float64(Float64Builder),
+    Timestamp(TimestampNanosecondBuilder),
+    Text(StringBuilder),
+    Bytes(BinaryBuilder),
+}
+
+impl ColumnBuilder {
+    fn new(arrow_type: &ArrowType, capacity: usize) -> Self {
+        match arrow_type {
+            ArrowType::FixedSizeBinary(width) => {
+                Self::Oid(FixedSizeBinaryBuilder::with_capacity(capacity, *width))
+            }
+            ArrowType::Boolean => Self::Bool(BooleanBuilder::with_capacity(capacity)),
+            ArrowType::Int64 => Self::Int64(Int64Builder::with_capacity(capacity)),
+            ArrowType::UInt64 => Self::UInt64(UInt64Builder::with_capacity(capacity)),
+            ArrowType::Timestamp(..) => {
+                Self::Timestamp(TimestampNanosecondBuilder::with_capacity(capacity))
+            }
+            ArrowType::Utf8 => Self::Text(StringBuilder::new()),
+            ArrowType::Binary => Self::Bytes(BinaryBuilder::new()),
+            _ => Self::Float64(Float64Builder::with_capacity(capacity)),
+        }
+    }
+
+    fn append(&mut self, column: usize, value: Option<&DataValue>) -> Result<()> {
+        let Some(value) = value else {
+            match self {
+                Self::Oid(builder) => builder.append_null(),
+                Self::Bool(builder) => builder.append_null(),
+                Self::Int64(builder) => builder.append_null(),
+                Self::UInt64(builder) => builder.append_null(),
+                Self::Float64(builder) => builder.append_null(),
+                Self::Timestamp(builder) => builder.append_null(),
+                Self::Text(builder) => builder.append_null(),
+                Self::Bytes(builder) => builder.append_null(),
+            }
+
+            return Ok(());
+        };
+
+        match (self, value) {
+            (Self::Oid(builder), DataValue::O16(oid)) => builder.append_value(oid.into_array())?,
+            (Self::Oid(builder), DataValue::O32(oid)) => builder.append_value(oid.into_array())?,
+            (Self::Oid(builder), DataValue::O64(oid)) => builder.append_value(oid.into_array())?,
+            (Self::Bool(builder), DataValue::Bool(val)) => builder.append_value(*val),
+            (Self::Int64(builder), DataValue::Number(number)) => {
+                builder.append_value(match *number {
+                    Number::Integer(n) => n,
+                    Number::Unsigned(n) => i64::try_from(n)?,
+                    other => anyhow::bail!("{:?} in column {} isn't an integer", other, column),
+                })
+            }
+            (Self::UInt64(builder), DataValue::Number(number)) => {
+                builder.append_value(match *number {
+                    Number::Unsigned(n) => n,
+                    Number::Integer(n) => u64::try_from(n)?,
+                    other => anyhow::bail!("{:?} in column {} isn't an integer", other, column),
+                })
+            }
+            (Self::Float64(builder), DataValue::Number(number)) => {
+                builder.append_value(f64::from(*number))
+            }
+            (Self::Timestamp(builder), DataValue::Timestamp(timestamp)) => {
+                let nanos = i64::try_from(timestamp.as_i128() * 1_000_000).map_err(|_| {
+                    anyhow::anyhow!(
+                        "timestamp in column {} is out of range for nanoseconds",
+                        column
+                    )
+                })?;
+
+                builder.append_value(nanos)
+            }
+            (Self::Text(builder), DataValue::Text(text)) => builder.append_value(text.as_str()),
+            (Self::Bytes(builder), DataValue::Bytes(bytes)) => {
+                builder.append_value(bytes.as_slice())
+            }
+            (_, other) => anyhow::bail!("column {} holds an unexpected {:?}", column, other),
+        }
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> ArrayRef {
+        match self {
+            Self::Oid(builder) => Arc::new(builder.finish()),
+            Self::Bool(builder) => Arc::new(builder.finish()),
+            Self::Int64(builder) => Arc::new(builder.finish()),
+            Self::UInt64(builder) => Arc::new(builder.finish()),
+            Self::Float64(builder) => Arc::new(builder.finish()),
+            Self::Timestamp(builder) => Arc::new(builder.finish()),
+            Self::Text(builder) => Arc::new(builder.finish()),
+            Self::Bytes(builder) => Arc::new(builder.finish()),
+        }
+    }
+}
+
+/// The rows of a table as Arrow record batches, see `to_arrow_batches`.
+pub struct ArrowBatches<'a> {
+    table: &'a Table,
+    schema: SchemaRef,
+    columns: Vec<usize>,
+    handles: vec::IntoIter<RecordHandle>,
+    batch_size: usize,
+}
+
+impl ArrowBatches<'_> {
+    pub fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn _next_batch(&mut self) -> Result<Option<RecordBatch>> {
+        let mut builders = self
+            .schema
+            .fields()
+            .iter()
+            .map(|field| ColumnBuilder::new(field.data_type(), self.batch_size))
+            .collect::<Vec<_>>();
+        let mut rows = 0;
+
+        while rows < self.batch_size {
+            let Some(handle) = self.handles.next() else {
+                break;
+            };
+
+            let values = match self.table.get_versioned(&handle) {
+                Ok((values, _)) => values,
+                Err(TableError::Corrupt(value)) => match self.table.config.on_corrupt {
+                    OnCorrupt::Skip => continue,
+                    OnCorrupt::Fail => return Err(value.into()),
+                },
+                // a row removed since it was listed isn't exported
+                Err(_) => continue,
+            };
+
+            for (builder, column) in builders.iter_mut().zip(self.columns.iter()) {
+                builder.append(*column, values.get(*column).and_then(Option::as_ref))?;
+            }
+
+            rows += 1;
+        }
+
+        if rows == 0 {
+            return Ok(None);
+        }
+
+        let arrays = builders.iter_mut().map(ColumnBuilder::finish).collect();
+
+        Ok(Some(RecordBatch::try_new(self.schema.clone(), arrays)?))
+    }
+}
+
+impl Iterator for ArrowBatches<'_> {
+    type Item = Result<RecordBatch, TableError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self._next_batch().map_err(TableError::from).transpose()
+    }
+}
+
+/// Exports the `projection` columns of `table`, or all of them, as Arrow record batches of up to
+/// `batch_size` rows, in insertion order. The rows are the ones live when this is called. Rows
+/// with a corrupt value fail the batch or are left out, as the table's `on_corrupt` says.
+pub fn to_arrow_batches<'a>(
+    table: &'a Table,
+    projection: Option<&[usize]>,
+    batch_size: usize,
+) -> Result<ArrowBatches<'a>, TableError> {
+    if batch_size == 0 {
+        return Err(TableError::invalid(
+            "a batch needs room for at least one row",
+        ));
+    }
+
+    let columns = match projection {
+        Some(projection) => projection.to_vec(),
+        None => (0..table.config.columns.len()).collect(),
+    };
+
+    let mut fields = Vec::with_capacity(columns.len());
+
+    for column in columns.iter().copied() {
+        let config = table
+            .config
+            .columns
+            .get(column)
+            .ok_or_else(|| TableError::not_found(format!("column {}", column)))?;
+
+        let numbers = match config.data_type.into_inner() {
+            DataType::Number => table._number_kind(column)?,
+            _ => NumberKind::Int64,
+        };
+
+        let name = match config.name {
+            Some(name) => name.to_string(),
+            None => format!("column_{}", column),
+        };
+
+        fields.push(Field::new(
+            name,
+            arrow_type(config.data_type.into_inner(), numbers),
+            true,
+        ));
+    }
+
+    Ok(ArrowBatches {
+        table,
+        schema: Arc::new(Schema::new(fields)),
+        columns,
+        handles: table
+            .scan_since(0)
+            .map(|(_, handle)| handle)
+            .collect::<Vec<_>>()
+            .into_iter(),
+        batch_size,
+    })
+}
+
+/// Writes the batches of `to_arrow_batches` to a new Parquet file at `path`, one row group per
+/// batch, without holding more than a batch in memory. Returns how many rows were written.
+pub fn to_parquet_file(
+    table: &Table,
+    path: impl AsRef<Path>,
+    options: &ExportOptions,
+) -> Result<usize, TableError> {
+    let batches = to_arrow_batches(table, options.projection.as_deref(), options.batch_size)?;
+
+    Ok(write_parquet(batches, path.as_ref())?)
+}
+
+fn write_parquet(batches: ArrowBatches<'_>, path: &Path) -> Result<usize> {
+    let file = File::create_new(path)?;
+    let mut writer = ArrowWriter::try_new(file, batches.schema(), None)?;
+    let mut rows = 0;
+
+    for batch in batches {
+        let batch = batch?;
+
+        rows += batch.num_rows();
+        writer.write(&batch)?;
+        writer.flush()?;
+    }
+
+    writer.close()?;
+
+    Ok(rows)
+}
+
+impl Table {
+    /// What a number column is exported as, from a pass over its values.
+    fn _number_kind(&self, column: usize) -> Result<NumberKind> {
+        let store = self.get_column_store(column)?;
+        let mut numbers = Vec::new();
+
+        for block in store.scan_blocks(self.config.scan) {
+            for handle in block.iter_live() {
+                handle.read_with(|slot| {
+                    if let Some(DataValue::Number(number)) = slot.data() {
+                        numbers.push(*number);
+                    }
+
+                    Ok(())
+                })?;
+            }
+        }
+
+        Ok(NumberKind::of(numbers))
+    }
+}
@@ -0,0 +1,215 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use dbexp::{
+    object_ids::TableId,
+    records::Records,
+    store::{Store, StoreConfig, StoreMeta, TableFile},
+    values::DataValue,
+};
+
+use crate::{
+    layout::{column_region, StorageLayout, RECORDS_REGION},
+    TableConfig,
+};
+
+/// A store file of a persisted table that doesn't match its config, found by
+/// `TableConfig::check_store_files`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StoreFileIssue {
+    /// The record store, which every opened table has, isn't there.
+    Missing(PathBuf),
+    /// A store file in the table directory that none of its columns would open.
+    Extra(PathBuf),
+    /// A store file that can't be opened as the store the config describes.
+    Incompatible { path: PathBuf, reason: String },
+}
+
+impl std::fmt::Display for StoreFileIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Missing(path) => write!(f, "missing store file {}", path.display()),
+            Self::Extra(path) => write!(f, "unexpected store file {}", path.display()),
+            Self::Incompatible { path, reason } => {
+                write!(f, "incompatible store file {}: {}", path.display(), reason)
+            }
+        }
+    }
+}
+
+pub(crate) fn resolve(config: &StoreConfig, root: Option<&Path>) -> Result<PathBuf> {
+    match root {
+        Some(root) => Ok(config.persistance.resolve(root)),
+        None if config.persistance.is_relative() => anyhow::bail!(
+            "persistance path {:?} is relative but no database root was given",
+            config.persistance
+        ),
+        None => Ok(config.persistance.as_path().to_path_buf()),
+    }
+}
+
+/// Compares the meta read from a store file to the store the table would open there.
+fn check_meta(
+    path: &Path,
+    meta: Result<StoreMeta>,
+    id: TableId,
+    config: &StoreConfig,
+) -> Option<StoreFileIssue> {
+    let reason = match meta {
+        Err(error) => error.to_string(),
+        Ok(meta) if meta.table != id => format!("belongs to table {}, not {}", meta.table, id),
+        Ok(meta) if meta.config.block_capacity != config.block_capacity => format!(
+            "has blocks of {} slots, not {}",
+            meta.config.block_capacity, config.block_capacity
+        ),
+        Ok(_) => return None,
+    };
+
+    Some(StoreFileIssue::Incompatible {
+        path: path.to_path_buf(),
+        reason,
+    })
+}
+
+impl TableConfig {
+    /// Checks the store files of the persisted table `id` against this config without opening
+    /// them, with relative paths resolved against `root`. Column stores are only created once a
+    /// column is written to, so a missing column file isn't an issue, but one the table has no
+    /// column for is. A `SingleFile` table has the regions of its table file checked instead.
+    /// Neither memory-only tables nor ones whose directory hasn't been created yet have anything
+    /// to check.
+    pub fn check_store_files(
+        &self,
+        id: TableId,
+        root: Option<&Path>,
+    ) -> Result<Vec<StoreFileIssue>> {
+        if self.persistance.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut issues = Vec::new();
+        let mut expected = Vec::new();
+
+        let records_config = self.records_store_config()?;
+        let records_path = resolve(&records_config, root)?;
+        let dir = records_path
+            .parent()
+            .unwrap_or(Path::new("/"))
+            .to_path_buf();
+
+        if !dir.is_dir() {
+            return Ok(Vec::new());
+        }
+
+        if self.storage_layout == StorageLayout::SingleFile {
+            let path = resolve(&self.table_file_config()?, root)?;
+
+            if path.exists() {
+                issues.extend(self._check_table_file(&path, id)?);
+            } else {
+                issues.push(StoreFileIssue::Missing(path.clone()));
+            }
+
+            expected.push(path);
+        } else {
+            if records_path.exists() {
+                issues.extend(check_meta(
+                    &records_path,
+                    Records::read_image_meta(&records_path),
+                    id,
+                    &records_config,
+                ));
+            } else {
+                issues.push(StoreFileIssue::Missing(records_path.clone()));
+            }
+
+            expected.push(records_path);
+
+            for column in 0..self.columns.len() {
+                let config = self.columns.get(column).unwrap();
+                let store_config = config.into_store_config(self, column)?;
+                let path = resolve(&store_config, root)?;
+
+                if path.exists() {
+                    issues.extend(check_meta(
+                        &path,
+                        Store::<DataValue>::read_image_meta(&path),
+                        id,
+                        &store_config,
+                    ));
+                }
+
+                expected.push(path);
+            }
+        }
+
+        // the annotations are a table of their own in a directory inside this one
+        let mut extra = Vec::new();
+
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+
+            if path.is_file()
+                && path.extension().is_some_and(|ext| ext == "store")
+                && !expected.contains(&path)
+            {
+                extra.push(path);
+            }
+        }
+
+        extra.sort();
+        issues.extend(extra.into_iter().map(StoreFileIssue::Extra));
+
+        Ok(issues)
+    }
+
+    /// Checks the store of every region of a table file like `check_store_files` checks store
+    /// files, along with regions no store of the table would be opened from.
+    fn _check_table_file(&self, path: &Path, id: TableId) -> Result<Vec<StoreFileIssue>> {
+        let incompatible = |reason: String| StoreFileIssue::Incompatible {
+            path: path.to_path_buf(),
+            reason,
+        };
+
+        let mut metas = match TableFile::read_metas(path) {
+            Ok(metas) => metas,
+            Err(error) => return Ok(vec![incompatible(error.to_string())]),
+        };
+
+        let mut issues = Vec::new();
+
+        match metas.remove(&RECORDS_REGION) {
+            Some(meta) => issues.extend(check_meta(
+                path,
+                Ok(meta),
+                id,
+                &self.records_store_config()?,
+            )),
+            None => issues.push(incompatible("has no record store".to_string())),
+        }
+
+        for column in 0..self.columns.len() {
+            let Some(meta) = metas.remove(&column_region(self.columns.cell(column))) else {
+                continue;
+            };
+
+            let store_config = self
+                .columns
+                .get(column)
+                .unwrap()
+                .into_store_config(self, column)?;
+            issues.extend(check_meta(path, Ok(meta), id, &store_config));
+        }
+
+        issues.extend(
+            metas
+                .into_keys()
+                .map(|region| incompatible(format!("has region {} no column opens", region))),
+        );
+
+        Ok(issues)
+    }
+}
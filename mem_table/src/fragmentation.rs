@@ -0,0 +1,96 @@
+use anyhow::Result;
+use dbexp::store::FragReport;
+use primitives::DataType;
+use serde::Serialize;
+
+use crate::{Table, TableError};
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ColumnFragReport {
+    pub column: usize,
+    pub name: Option<String>,
+    pub store: FragReport,
+    /// Bytes of text and bytes slots past the end of their values. Measured from the live values
+    /// of the loaded blocks, so it's always zero for other columns.
+    pub padding_bytes: usize,
+}
+
+/// How full the record store and every column store of a table are, from
+/// `Table::fragmentation_report`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TableFragReport {
+    pub records: FragReport,
+    pub columns: Vec<ColumnFragReport>,
+    /// Unused slots of every store, plus the padding of text and bytes values.
+    pub total_wasted_bytes: usize,
+}
+
+impl std::fmt::Display for TableFragReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} bytes wasted in total", self.total_wasted_bytes)?;
+        write!(f, "records: {}", self.records)?;
+
+        for column in self.columns.iter() {
+            // column stores are labeled with the column when they're opened
+            if column.store.label.is_none() {
+                match column.name.as_deref() {
+                    Some(name) => write!(f, "column {} ({}): ", column.column, name)?,
+                    None => write!(f, "column {}: ", column.column)?,
+                }
+            }
+
+            write!(f, "{}", column.store)?;
+
+            if column.padding_bytes > 0 {
+                writeln!(f, "  {} bytes of padding", column.padding_bytes)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Table {
+    /// How full the blocks of every store of the table are, for choosing block capacities. Slot
+    /// usage comes from the block metas, but padding needs the length of every text and bytes
+    /// value, so those columns are scanned.
+    pub fn fragmentation_report(&self) -> Result<TableFragReport, TableError> {
+        let records = self.records.fragmentation_report();
+        let mut total_wasted_bytes = records.total_wasted_bytes;
+        let mut columns = Vec::with_capacity(self.config.columns.len());
+
+        for column in 0..self.config.columns.len() {
+            let config = unsafe { self.config.columns.get_unchecked(column) };
+            let store = self.get_column_store(column)?.fragmentation_report();
+
+            let padding_bytes = match config.data_type.into_inner() {
+                DataType::Text(cap) | DataType::Bytes(cap) => {
+                    let mut padding = 0;
+
+                    self._for_each_length(column, |_, len| {
+                        padding += cap as usize - len;
+                        Ok(())
+                    })?;
+
+                    padding
+                }
+                _ => 0,
+            };
+
+            total_wasted_bytes += store.total_wasted_bytes + padding_bytes;
+
+            columns.push(ColumnFragReport {
+                column,
+                name: config.name.map(|name| name.as_str().to_string()),
+                store,
+                padding_bytes,
+            });
+        }
+
+        Ok(TableFragReport {
+            records,
+            columns,
+            total_wasted_bytes,
+        })
+    }
+}
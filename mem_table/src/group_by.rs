@@ -0,0 +1,468 @@
+//! Grouping the rows of a table by a column and aggregating every group once, within a memory
+//! budget. `Table::group_by` keeps a partial aggregate per group while it reads the rows, and when
+//! adding to them would take more memory than the budget, the partials are written to a temp file
+//! in order of group and dropped. Once every row is read, the spilled partials and those still
+//! held are merged in order of group, holding the next partial of each spill at a time, which is
+//! the same merge partials read by parallel scans would go through.
+//!
+//! Each partial of a spill is written as the length of its group's JSON, a `u32`, then the JSON,
+//! then the totals of its `Acc` and its distinct values: nothing, every value with its count, or
+//! the registers of a sketch. Numbers are written with the byte encoding of `AccessBytes`.
+
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    mem::size_of,
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use anyhow::{Context, Result};
+use dbexp::{store::CorruptValue, values::DataValue};
+use indexmap::IndexMap;
+use primitives::{
+    byte_encoding::{AccessBytes, ScalarFromBytes},
+    DataType, Number,
+};
+use serde::Serialize;
+
+use crate::{
+    view::{Acc, Distinct},
+    Aggregate, CardinalitySketch, Table, TableError, SKETCH_REGISTERS,
+};
+
+/// About how many bytes a hash map takes per entry besides its key and value, for its hash and
+/// its index.
+pub(crate) const ENTRY_OVERHEAD: usize = 2 * size_of::<usize>();
+
+/// Tells apart the spill files of every group-by in the process.
+static NEXT_SPILL: AtomicUsize = AtomicUsize::new(0);
+
+/// How much a `Table::group_by` held and spilled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct GroupByMetrics {
+    /// Rows aggregated.
+    pub rows: usize,
+    pub groups: usize,
+    /// How many times the partial groups were written to disk.
+    pub spills: usize,
+    pub spilled_bytes: u64,
+    /// The most memory the partial groups took at once, as estimated from the `size_hint` of their
+    /// groups and the size of their accumulators.
+    pub peak_bytes: usize,
+}
+
+/// What `Table::group_by` found.
+#[derive(Debug, Default)]
+pub struct GroupBy {
+    /// Every group with its aggregate, in order of group, so rows leaving the column empty come
+    /// first.
+    pub groups: Vec<(Option<DataValue>, Option<Number>)>,
+    /// The corrupt values of the rows left out, when the table's `on_corrupt` is `Skip`.
+    pub corrupt: Vec<CorruptValue>,
+    pub metrics: GroupByMetrics,
+}
+
+/// The partial groups of a spill, in order of group. The file is removed when the spill is
+/// dropped.
+#[derive(Debug)]
+struct Spill {
+    path: PathBuf,
+    len: usize,
+}
+
+impl Spill {
+    /// Sorts `groups` and writes them to a new temp file, returning the spill and its size.
+    fn write(groups: &mut [(Option<DataValue>, Acc)]) -> Result<(Self, u64)> {
+        let n = NEXT_SPILL.fetch_add(1, Ordering::Relaxed);
+        let path =
+            std::env::temp_dir().join(format!("dbexp_group_spill_{}_{}", std::process::id(), n));
+        let spill = Self {
+            path,
+            len: groups.len(),
+        };
+
+        groups.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+
+        let mut file = BufWriter::new(File::create(&spill.path)?);
+
+        for (group, acc) in groups.iter() {
+            _write_json(&mut file, group)?;
+            _write_acc(&mut file, acc)?;
+        }
+
+        file.flush()?;
+        drop(file);
+
+        let bytes = std::fs::metadata(&spill.path)?.len();
+
+        Ok((spill, bytes))
+    }
+}
+
+impl Drop for Spill {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+fn _write_json(file: &mut impl Write, value: &impl Serialize) -> Result<()> {
+    let json = serde_json::to_vec(value)?;
+
+    file.write_all(&(json.len() as u32).to_le_bytes())?;
+    file.write_all(&json)?;
+
+    Ok(())
+}
+
+fn _write_acc(file: &mut impl Write, acc: &Acc) -> Result<()> {
+    let mut write = |bytes: &[u8]| -> Result<()> { Ok(file.write_all(bytes)?) };
+
+    acc.rows.access_bytes(&mut write)?;
+    acc.values.access_bytes(&mut write)?;
+    acc.int_sum.access_bytes(&mut write)?;
+    acc.float_sum.access_bytes(&mut write)?;
+    acc.float_values.access_bytes(&mut write)?;
+
+    match &acc.distinct {
+        Distinct::None => write(&[0]),
+        Distinct::Exact(counts) => {
+            write(&[1])?;
+            (counts.len() as u64).access_bytes(&mut write)?;
+
+            for (value, count) in counts {
+                _write_json(file, value)?;
+                count.access_bytes(|bytes| Ok(file.write_all(bytes)?))?;
+            }
+
+            Ok(())
+        }
+        Distinct::Sketch(sketch) => {
+            write(&[2])?;
+            write(sketch.registers())
+        }
+    }
+}
+
+/// Reads the partials of a spill back one at a time.
+#[derive(Debug)]
+struct SpillReader {
+    file: BufReader<File>,
+    left: usize,
+}
+
+impl SpillReader {
+    fn open(spill: &Spill) -> Result<Self> {
+        let file = File::open(&spill.path)
+            .with_context(|| format!("failed to open group spill {}", spill.path.display()))?;
+
+        Ok(Self {
+            file: BufReader::new(file),
+            left: spill.len,
+        })
+    }
+
+    fn _scalar<T: ScalarFromBytes, const N: usize>(&mut self) -> Result<T> {
+        let mut bytes = [0; N];
+        self.file.read_exact(&mut bytes)?;
+
+        T::from_bytes(&bytes)
+    }
+
+    fn _json<T: serde::de::DeserializeOwned>(&mut self) -> Result<T> {
+        let mut len = [0; size_of::<u32>()];
+        self.file.read_exact(&mut len)?;
+
+        let mut json = vec![0; u32::from_le_bytes(len) as usize];
+        self.file.read_exact(&mut json)?;
+
+        Ok(serde_json::from_slice(&json)?)
+    }
+
+    fn _acc(&mut self) -> Result<Acc> {
+        // fields are read in the order they're written
+        Ok(Acc {
+            rows: self._scalar::<i64, 8>()?,
+            values: self._scalar::<i64, 8>()?,
+            int_sum: self._scalar::<i128, 16>()?,
+            float_sum: self._scalar::<f64, 8>()?,
+            float_values: self._scalar::<i64, 8>()?,
+            distinct: self._distinct()?,
+        })
+    }
+
+    fn _distinct(&mut self) -> Result<Distinct> {
+        let distinct = match self._scalar::<u8, 1>()? {
+            0 => Distinct::None,
+            1 => {
+                let len = self._scalar::<u64, 8>()?;
+                let mut counts = IndexMap::with_capacity(len as usize);
+
+                for _ in 0..len {
+                    let value = self._json::<DataValue>()?;
+                    counts.insert(value, self._scalar::<i64, 8>()?);
+                }
+
+                Distinct::Exact(counts)
+            }
+            2 => {
+                let mut registers = [0; SKETCH_REGISTERS];
+                self.file.read_exact(&mut registers)?;
+
+                Distinct::Sketch(CardinalitySketch::from_registers(registers))
+            }
+            other => anyhow::bail!("group spill is corrupt: unknown distinct kind {}", other),
+        };
+
+        Ok(distinct)
+    }
+}
+
+impl Iterator for SpillReader {
+    type Item = Result<(Option<DataValue>, Acc)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.left == 0 {
+            return None;
+        }
+
+        self.left -= 1;
+
+        Some(self._json().and_then(|group| Ok((group, self._acc()?))))
+    }
+}
+
+type Partials = Box<dyn Iterator<Item = Result<(Option<DataValue>, Acc)>>>;
+
+/// Merges partials in order of group, each source holding a group at most once.
+struct MergePartials {
+    sources: Vec<Partials>,
+    /// The next group of each source that has groups left, with the source it's from.
+    heap: BinaryHeap<Reverse<(Option<DataValue>, usize)>>,
+    /// The partial of the next group of each source.
+    next: Vec<Option<Acc>>,
+}
+
+impl MergePartials {
+    fn new(sources: Vec<Partials>) -> Result<Self> {
+        let mut merge = Self {
+            heap: BinaryHeap::with_capacity(sources.len()),
+            next: sources.iter().map(|_| None).collect(),
+            sources,
+        };
+
+        for source in 0..merge.sources.len() {
+            merge._refill(source)?;
+        }
+
+        Ok(merge)
+    }
+
+    fn _refill(&mut self, source: usize) -> Result<()> {
+        if let Some(partial) = self.sources[source].next() {
+            let (group, acc) = partial?;
+
+            self.next[source] = Some(acc);
+            self.heap.push(Reverse((group, source)));
+        }
+
+        Ok(())
+    }
+
+    fn _take(&mut self, source: usize) -> Result<Acc> {
+        let acc = self.next[source]
+            .take()
+            .expect("every queued group has a partial");
+        self._refill(source)?;
+
+        Ok(acc)
+    }
+
+    /// The next group with every partial of it merged.
+    fn next_group(&mut self) -> Result<Option<(Option<DataValue>, Acc)>> {
+        let Some(Reverse((group, source))) = self.heap.pop() else {
+            return Ok(None);
+        };
+
+        let mut acc = self._take(source)?;
+
+        while let Some(Reverse((next, _))) = self.heap.peek() {
+            if *next != group {
+                break;
+            }
+
+            let Some(Reverse((_, source))) = self.heap.pop() else {
+                break;
+            };
+
+            acc.merge(self._take(source)?);
+        }
+
+        Ok(Some((group, acc)))
+    }
+}
+
+/// The partial groups of a `Table::group_by` held in memory, with how many bytes they take.
+struct Grouping {
+    group_col: usize,
+    agg: Aggregate,
+    budget: Option<usize>,
+    groups: IndexMap<Option<DataValue>, Acc>,
+    used: usize,
+    spills: Vec<Spill>,
+    metrics: GroupByMetrics,
+}
+
+impl Grouping {
+    /// How many bytes a new group takes before any row is applied to it.
+    fn _group_size(group: &Option<DataValue>) -> usize {
+        let group = match group {
+            Some(value) => value.size_hint(),
+            None => size_of::<Option<DataValue>>(),
+        };
+
+        group + size_of::<Acc>() + ENTRY_OVERHEAD
+    }
+
+    fn _growth(&self, group: &Option<DataValue>, row: &[Option<DataValue>]) -> usize {
+        match self.groups.get(group) {
+            Some(acc) => acc.growth(self.agg, row),
+            None => Self::_group_size(group) + Acc::default().growth(self.agg, row),
+        }
+    }
+
+    fn add(&mut self, row: &[Option<DataValue>]) -> Result<()> {
+        let group = row.get(self.group_col).cloned().flatten();
+        let mut growth = self._growth(&group, row);
+
+        if let Some(budget) = self.budget {
+            // a group bigger than the whole budget is held anyway, on its own
+            if self.used + growth > budget && !self.groups.is_empty() {
+                self._spill()?;
+                growth = self._growth(&group, row);
+            }
+        }
+
+        self.groups
+            .entry(group)
+            .or_default()
+            .apply(self.agg, row, 1);
+
+        self.used += growth;
+        self.metrics.rows += 1;
+        self.metrics.peak_bytes = self.metrics.peak_bytes.max(self.used);
+
+        Ok(())
+    }
+
+    fn _spill(&mut self) -> Result<()> {
+        let mut groups = self.groups.drain(..).collect::<Vec<_>>();
+        let (spill, bytes) = Spill::write(&mut groups).context("failed to spill groups")?;
+
+        self.spills.push(spill);
+        self.used = 0;
+        self.metrics.spills += 1;
+        self.metrics.spilled_bytes += bytes;
+
+        Ok(())
+    }
+
+    /// Merges every spill with the groups still held.
+    fn finish(mut self, corrupt: Vec<CorruptValue>) -> Result<GroupBy> {
+        let mut held = self.groups.drain(..).collect::<Vec<_>>();
+        held.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+
+        let mut sources = self
+            .spills
+            .iter()
+            .map(|spill| Ok(Box::new(SpillReader::open(spill)?) as Partials))
+            .collect::<Result<Vec<_>>>()?;
+        sources.push(Box::new(held.into_iter().map(Ok)));
+
+        let mut merge = MergePartials::new(sources)?;
+        let mut groups = Vec::new();
+
+        while let Some((group, acc)) = merge.next_group()? {
+            groups.push((group, acc.result(self.agg)));
+        }
+
+        self.metrics.groups = groups.len();
+
+        Ok(GroupBy {
+            groups,
+            corrupt,
+            metrics: self.metrics,
+        })
+    }
+}
+
+impl Table {
+    /// Groups every row by its value in `group_col`, rows leaving it empty making up a group of
+    /// their own, and aggregates each group with `agg`, as a `MaterializedView` would but only
+    /// once. A row with a corrupt value fails the scan or is left out, as the table's
+    /// `on_corrupt` says.
+    ///
+    /// With a `budget` in bytes, the partial groups are spilled to disk whenever adding to them
+    /// would take more memory than that, and merged back at the end, so grouping by a column with
+    /// many distinct values doesn't hold them all at once. A single group taking more than the
+    /// budget is still held. The groups come out the same either way.
+    pub fn group_by(
+        &self,
+        group_col: usize,
+        agg: Aggregate,
+        budget: Option<usize>,
+    ) -> Result<GroupBy, TableError> {
+        self._ensure_open()?;
+
+        let schema = self.schema();
+
+        if schema.data_type(group_col).is_none() {
+            return Err(TableError::not_found(format!("column {}", group_col)));
+        }
+
+        if let Some(column) = agg.column() {
+            match schema.data_type(column) {
+                Some(DataType::Number) => {}
+                Some(_) if matches!(agg, Aggregate::CountDistinct { .. }) => {}
+                Some(other) => {
+                    return Err(TableError::invalid(format!(
+                        "can't aggregate column {} of type {:?}",
+                        column, other
+                    )))
+                }
+                None => return Err(TableError::not_found(format!("column {}", column))),
+            }
+        }
+
+        self.throttle_read(self.row_count())?;
+
+        let mut grouping = Grouping {
+            group_col,
+            agg,
+            budget,
+            groups: IndexMap::new(),
+            used: 0,
+            spills: Vec::new(),
+            metrics: GroupByMetrics::default(),
+        };
+        let mut corrupt = Vec::new();
+        let mut cancel = self._cancel_check();
+
+        for (_, handle) in self.scan_since(0) {
+            cancel.tick()?;
+
+            match self.get_versioned(&handle) {
+                Ok((values, _)) => grouping.add(&values)?,
+                Err(TableError::Corrupt(value)) => {
+                    self._skip_corrupt(value.into(), &mut corrupt)?
+                }
+                // a row removed since it was listed isn't read
+                Err(_) => continue,
+            }
+        }
+
+        Ok(grouping.finish(corrupt)?)
+    }
+}
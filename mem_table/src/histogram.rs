@@ -0,0 +1,255 @@
+use anyhow::Result;
+use dbexp::values::DataValue;
+use primitives::DataType;
+
+use crate::{Table, TableError};
+
+/// Counts of number values in equal-width buckets between a fixed `min` and `max`. Histograms
+/// over the same range can be merged, so parts of a column can be counted separately. As long as
+/// there are no more distinct values than buckets, every value is also counted exactly.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    min: f64,
+    max: f64,
+    counts: Vec<u64>,
+    /// Every distinct value with its count, sorted, until there are more of them than buckets.
+    distinct: Option<Vec<(f64, u64)>>,
+}
+
+impl Histogram {
+    pub fn new(min: f64, max: f64, buckets: u16) -> Result<Self> {
+        if buckets == 0 {
+            anyhow::bail!("a histogram needs at least one bucket");
+        }
+
+        if !min.is_finite() || !max.is_finite() || min > max {
+            anyhow::bail!("invalid histogram range {}..={}", min, max);
+        }
+
+        Ok(Self {
+            min,
+            max,
+            counts: vec![0; buckets as usize],
+            distinct: Some(Vec::new()),
+        })
+    }
+
+    fn bucket_of(&self, value: f64) -> usize {
+        let buckets = self.counts.len();
+
+        if self.max == self.min {
+            return 0;
+        }
+
+        let bucket = ((value - self.min) / (self.max - self.min) * buckets as f64) as usize;
+
+        // the max is counted in the last bucket rather than one of its own
+        bucket.min(buckets - 1)
+    }
+
+    fn add_distinct(&mut self, value: f64, count: u64) {
+        let Some(distinct) = self.distinct.as_mut() else {
+            return;
+        };
+
+        match distinct.binary_search_by(|(v, _)| v.total_cmp(&value)) {
+            Ok(idx) => distinct[idx].1 += count,
+            Err(idx) => distinct.insert(idx, (value, count)),
+        }
+
+        if distinct.len() > self.counts.len() {
+            self.distinct = None;
+        }
+    }
+
+    /// Fails for values outside the histogram's range, which includes NaN and infinities.
+    pub fn add(&mut self, value: f64) -> Result<()> {
+        if !(self.min..=self.max).contains(&value) {
+            anyhow::bail!(
+                "{} is outside the histogram range {}..={}",
+                value,
+                self.min,
+                self.max
+            );
+        }
+
+        let bucket = self.bucket_of(value);
+        self.counts[bucket] += 1;
+        self.add_distinct(value, 1);
+
+        Ok(())
+    }
+
+    /// Adds the counts of `other`, which must cover the same range with as many buckets.
+    pub fn merge(&mut self, other: &Histogram) -> Result<()> {
+        if (self.min, self.max, self.counts.len()) != (other.min, other.max, other.counts.len()) {
+            anyhow::bail!("can't merge histograms with different ranges or bucket counts");
+        }
+
+        for (count, other) in self.counts.iter_mut().zip(other.counts.iter()) {
+            *count += other;
+        }
+
+        match other.distinct.as_ref() {
+            Some(distinct) => {
+                for (value, count) in distinct.iter() {
+                    self.add_distinct(*value, *count);
+                }
+            }
+            None => self.distinct = None,
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(&self) -> HistogramResult {
+        let count = self.counts.iter().sum();
+
+        match self.distinct.as_ref() {
+            Some(distinct) => HistogramResult {
+                min: self.min,
+                max: self.max,
+                count,
+                buckets: distinct.clone(),
+                exact: true,
+            },
+            None => {
+                let width = (self.max - self.min) / self.counts.len() as f64;
+
+                HistogramResult {
+                    min: self.min,
+                    max: self.max,
+                    count,
+                    buckets: self
+                        .counts
+                        .iter()
+                        .enumerate()
+                        .map(|(idx, count)| (self.min + width * idx as f64, *count))
+                        .collect(),
+                    exact: false,
+                }
+            }
+        }
+    }
+}
+
+/// The distribution of a number column. When `exact`, `buckets` holds every distinct value with
+/// its count. Otherwise it holds the lower bound and count of equal-width buckets spanning
+/// `min..=max`, the last one including `max`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistogramResult {
+    pub min: f64,
+    pub max: f64,
+    pub count: u64,
+    pub buckets: Vec<(f64, u64)>,
+    pub exact: bool,
+}
+
+impl HistogramResult {
+    /// The value at `q` between 0 and 1 through the sorted values, that is at rank
+    /// `q * (count - 1)` rounded to the nearest. Exact histograms return that value, others
+    /// interpolate it within its bucket as if the bucket's values were spread evenly. NaN when
+    /// there are no values.
+    pub fn p(&self, q: f64) -> f64 {
+        if self.count == 0 {
+            return f64::NAN;
+        }
+
+        let rank = (q.clamp(0.0, 1.0) * (self.count - 1) as f64).round() as u64;
+        let mut seen = 0;
+
+        for (idx, (lower, count)) in self.buckets.iter().enumerate() {
+            if rank >= seen + count {
+                seen += count;
+                continue;
+            }
+
+            if self.exact {
+                return *lower;
+            }
+
+            let upper = self
+                .buckets
+                .get(idx + 1)
+                .map_or(self.max, |(upper, _)| *upper);
+            let within = (rank - seen) as f64 + 0.5;
+
+            return (lower + (upper - lower) * within / *count as f64).clamp(self.min, self.max);
+        }
+
+        self.max
+    }
+}
+
+impl Table {
+    /// Calls `f` with every finite value of a number `column`, one block at a time.
+    fn _for_each_number(
+        &self,
+        column: usize,
+        mut f: impl FnMut(usize, f64) -> Result<()>,
+    ) -> Result<()> {
+        match self.schema().data_type(column) {
+            Some(DataType::Number) => {}
+            Some(other) => anyhow::bail!("column {} is {:?}, not a number", column, other),
+            None => anyhow::bail!("no column {}", column),
+        }
+
+        let store = self.get_column_store(column)?;
+        let blocks = store.scan_blocks(self.config.scan);
+        let mut cancel = self._cancel_check();
+
+        for (idx, block) in blocks.enumerate() {
+            for handle in block.iter_live() {
+                cancel.tick()?;
+
+                let value = handle.read_with(|slot| match slot.data() {
+                    Some(DataValue::Number(number)) => Ok(Some(f64::from(*number))),
+                    _ => Ok(None),
+                })?;
+
+                if let Some(value) = value.filter(|value| value.is_finite()) {
+                    f(idx, value)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Counts the finite values of a number `column` into a histogram of `buckets` spanning their
+    /// range, which takes a first pass over the column to find. Writes are held off meanwhile.
+    /// Each block is counted into a histogram of its own, which are then merged.
+    pub fn histogram(&self, column: usize, buckets: u16) -> Result<HistogramResult, TableError> {
+        // both passes have to see the same values
+        let _writes = self.write_gate.write();
+        let (mut min, mut max) = (f64::INFINITY, f64::NEG_INFINITY);
+
+        self._for_each_number(column, |_, value| {
+            min = min.min(value);
+            max = max.max(value);
+            Ok(())
+        })?;
+
+        if min > max {
+            return Ok(Histogram::new(0.0, 0.0, buckets)?.finish());
+        }
+
+        let mut blocks = Vec::<Histogram>::new();
+
+        self._for_each_number(column, |block, value| {
+            while blocks.len() <= block {
+                blocks.push(Histogram::new(min, max, buckets)?);
+            }
+
+            blocks[block].add(value)
+        })?;
+
+        let mut total = Histogram::new(min, max, buckets)?;
+
+        for block in blocks.iter() {
+            total.merge(block)?;
+        }
+
+        Ok(total.finish())
+    }
+}
@@ -0,0 +1,392 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Result;
+use dbexp::{
+    object_ids::{RecordId, TableId},
+    slot::SlotHandle,
+    store::{CorruptValue, CountMismatch},
+};
+use primitives::ThinIdx;
+use serde::Serialize;
+
+use crate::{Table, TableError};
+
+/// Something `Table::check_integrity` found to be off.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Violation {
+    /// Which check failed, e.g. `column cell` or `primary key`.
+    pub check: &'static str,
+    /// The column involved, or `None` for the record store and the key index.
+    pub column: Option<usize>,
+    /// The position of the record involved in the record store, which is what its `RecordId`
+    /// holds besides the table.
+    pub record: Option<usize>,
+    pub expected: String,
+    pub found: String,
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.check)?;
+
+        if let Some(column) = self.column {
+            write!(f, ", column {}", column)?;
+        }
+
+        if let Some(record) = self.record {
+            write!(f, ", record {}", record)?;
+        }
+
+        write!(f, ": expected {}, found {}", self.expected, self.found)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct IntegrityReport {
+    pub table: TableId,
+    pub rows_checked: usize,
+    pub violations: Vec<Violation>,
+}
+
+impl IntegrityReport {
+    pub fn is_ok(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+impl std::fmt::Display for IntegrityReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "table {}: {} rows checked, {} violations",
+            self.table,
+            self.rows_checked,
+            self.violations.len()
+        )?;
+
+        for violation in self.violations.iter() {
+            writeln!(f, "  {}", violation)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn position(record: RecordId) -> usize {
+    Into::<ThinIdx>::into(record).into_usize()
+}
+
+impl Violation {
+    fn new(
+        check: &'static str,
+        column: Option<usize>,
+        record: Option<RecordId>,
+        expected: impl ToString,
+        found: impl ToString,
+    ) -> Self {
+        Self {
+            check,
+            column,
+            record: record.map(position),
+            expected: expected.to_string(),
+            found: found.to_string(),
+        }
+    }
+
+    fn from_counts(column: Option<usize>, mismatch: CountMismatch) -> Self {
+        let check = match column {
+            Some(_) => "column store counts",
+            None => "record store counts",
+        };
+        let what = match mismatch.block {
+            Some(block) => format!("{} of block {}", mismatch.what, block),
+            None => mismatch.what.to_string(),
+        };
+
+        Self::new(
+            check,
+            column,
+            None,
+            format!("{} {}", what, mismatch.expected),
+            format!("{} {}", what, mismatch.found),
+        )
+    }
+}
+
+impl Table {
+    /// Cross-checks the record store, the column stores and the key index against each other,
+    /// without changing anything. Writes are held off while it runs, so every violation is real
+    /// rather than a write caught halfway. The blocks of a table opened `with_checksum` are checked
+    /// against their checksums too, see `Table::scrub`.
+    pub fn check_integrity(&self) -> Result<IntegrityReport, TableError> {
+        let _writes = self.write_gate.write();
+
+        let column_count = self.config.columns.len();
+        let cells = (0..column_count)
+            .map(|column| self._cell(column))
+            .collect::<HashSet<_>>();
+        let unavailable = self.unavailable.read().clone();
+        let mut violations = Vec::new();
+        let mut live = Vec::new();
+
+        self.records
+            .foreach_live(|handle, indices| live.push((handle, *indices)));
+
+        violations.extend(
+            self.records
+                .check_counts()?
+                .into_iter()
+                .map(|mismatch| Violation::from_counts(None, mismatch)),
+        );
+
+        // every column cell a record points at, along with the record pointing at it
+        let mut referenced = HashMap::<(usize, ThinIdx, usize), RecordId>::new();
+
+        for (handle, indices) in live.iter() {
+            let record = self.records.record_id(handle);
+
+            // a record from before a column was added has fewer cells, which is fine, but the cell
+            // of a dropped column is cleared from every record
+            let stray = indices
+                .buckets()
+                .iter()
+                .enumerate()
+                .find(|(cell, held)| held.is_some() && !cells.contains(cell));
+
+            if let Some((cell, _)) = stray {
+                violations.push(Violation::new(
+                    "record columns",
+                    None,
+                    Some(record),
+                    "cells of the table's columns",
+                    format!("cell {}", cell),
+                ));
+            }
+
+            for column in 0..column_count {
+                // the values of an unavailable column are reported once, below
+                let Some(cell) = indices
+                    .get(self._cell(column))
+                    .filter(|_| !unavailable.contains_key(&column))
+                else {
+                    continue;
+                };
+
+                let row = cell.row.into_thin().into_usize();
+                let cell_violation = |expected: &str, found: String| {
+                    Violation::new("column cell", Some(column), Some(record), expected, found)
+                };
+
+                if let Some(other) = referenced.insert((column, cell.block, row), record) {
+                    violations.push(cell_violation(
+                        "a cell of its own",
+                        format!("the cell of record {}", position(other)),
+                    ));
+                }
+
+                let block = self
+                    .get_column_store(column)?
+                    .read()
+                    .blocks()
+                    .get(&cell.block)
+                    .cloned();
+
+                let Some(block) = block else {
+                    violations.push(cell_violation(
+                        "a loaded block",
+                        format!("no block {}", cell.block.into_usize()),
+                    ));
+                    continue;
+                };
+
+                if row >= block.capacity() {
+                    violations.push(cell_violation(
+                        "a slot within the block",
+                        format!("slot {} of {}", row, block.capacity()),
+                    ));
+                    continue;
+                }
+
+                let slot = SlotHandle {
+                    block,
+                    idx: cell.row.into_downgraded(),
+                };
+                let owner = slot.read_with(|slot| {
+                    Ok(slot.data().map(|_| {
+                        slot.thin_record_id()
+                            .map(|owner| RecordId::from_thin(owner, self.id))
+                    }))
+                })?;
+
+                match owner {
+                    None => violations.push(cell_violation(
+                        "a live slot",
+                        format!("a gap at slot {} of block {}", row, cell.block.into_usize()),
+                    )),
+                    Some(owner) if owner != Some(record) => violations.push(cell_violation(
+                        "a slot owned by the record",
+                        format!("a slot owned by {:?}", owner.map(position)),
+                    )),
+                    Some(_) => {
+                        if let Err(error) = self.get_column_store(column)?.read_value(&slot) {
+                            let corrupt = error.downcast::<CorruptValue>()?;
+
+                            violations.push(Violation::new(
+                                "column value",
+                                Some(column),
+                                Some(record),
+                                "a valid value",
+                                corrupt.reason,
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+
+        for (column, path) in unavailable.iter() {
+            violations.push(Violation::new(
+                "column store",
+                Some(*column),
+                None,
+                "a store file",
+                format!("missing {}", path.display()),
+            ));
+        }
+
+        let scrub = self.scrub()?;
+        let corrupt_blocks = scrub
+            .records
+            .corrupt
+            .iter()
+            .map(|corrupt| (None, corrupt))
+            .chain(scrub.columns.iter().flat_map(|column| {
+                column
+                    .store
+                    .corrupt
+                    .iter()
+                    .map(|corrupt| (Some(column.column), corrupt))
+            }));
+
+        for (column, corrupt) in corrupt_blocks {
+            violations.push(Violation::new(
+                "block checksum",
+                column,
+                None,
+                format!("checksum {:08x} of block {}", corrupt.expected, corrupt.idx),
+                format!("checksum {:08x}", corrupt.found),
+            ));
+        }
+
+        for column in 0..column_count {
+            if unavailable.contains_key(&column) {
+                continue;
+            }
+
+            let store = self.get_column_store(column)?;
+
+            violations.extend(
+                store
+                    .check_counts()?
+                    .into_iter()
+                    .map(|mismatch| Violation::from_counts(Some(column), mismatch)),
+            );
+
+            let blocks = store.read().blocks().values().cloned().collect::<Vec<_>>();
+
+            for block in blocks {
+                for slot in block.iter_live() {
+                    let row = slot.idx.into_thin().into_usize();
+
+                    if referenced.contains_key(&(column, block.index(), row)) {
+                        continue;
+                    }
+
+                    let owner = slot.read_with(|slot| Ok(slot.thin_record_id()))?;
+
+                    violations.push(Violation::new(
+                        "column slot",
+                        Some(column),
+                        owner.map(|owner| RecordId::from_thin(owner, self.id)),
+                        "a slot referenced by a record",
+                        format!("slot {} of block {}", row, block.index().into_usize()),
+                    ));
+                }
+            }
+        }
+
+        if !self.config.primary_key.is_empty() {
+            let keys = self.keys.read();
+
+            if keys.len() != live.len() {
+                violations.push(Violation::new(
+                    "primary key",
+                    None,
+                    None,
+                    format!("{} keys", live.len()),
+                    format!("{} keys", keys.len()),
+                ));
+            }
+
+            for (handle, _) in live.iter() {
+                let record = self.records.record_id(handle);
+                // a row with a broken cell was reported above, but its key can't be checked
+                let Ok((values, _)) = self.get_versioned(handle) else {
+                    continue;
+                };
+
+                let key = match self.config.primary_key.key_of(&values) {
+                    Ok(key) => key,
+                    Err(error) => {
+                        violations.push(Violation::new(
+                            "primary key",
+                            None,
+                            Some(record),
+                            "a key",
+                            error,
+                        ));
+                        continue;
+                    }
+                };
+
+                match keys.get(&key) {
+                    Some(indexed) if self.records.record_id(indexed) == record => {}
+                    Some(indexed) => violations.push(Violation::new(
+                        "primary key",
+                        None,
+                        Some(record),
+                        format!("{} to be indexed to the record", key),
+                        format!("record {}", position(self.records.record_id(indexed))),
+                    )),
+                    None => violations.push(Violation::new(
+                        "primary key",
+                        None,
+                        Some(record),
+                        format!("{} to be indexed", key),
+                        "no entry",
+                    )),
+                }
+            }
+
+            for (key, handle) in keys.iter() {
+                let record = self.records.record_id(handle);
+
+                if self.records.get(record).is_none() {
+                    violations.push(Violation::new(
+                        "primary key",
+                        None,
+                        Some(record),
+                        format!("{} to point at a live record", key),
+                        "a removed record",
+                    ));
+                }
+            }
+        }
+
+        Ok(IntegrityReport {
+            table: self.id,
+            rows_checked: live.len(),
+            violations,
+        })
+    }
+}
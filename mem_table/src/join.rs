@@ -0,0 +1,73 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use dbexp::{records::RecordHandle, values::DataValue};
+
+use crate::TableRead;
+
+/// Pairs every row of `left` with every row of `right` whose `right_column` equals its
+/// `left_column`, in the order of the left rows. Rows without a value in the column match nothing.
+/// Pass snapshots taken together with `snapshot_tables` to join the tables as of the same instant.
+/// Each side is read with its table's cancellation token, see `Table::with_cancellation`.
+pub fn join_eq(
+    left: &impl TableRead,
+    left_column: usize,
+    right: &impl TableRead,
+    right_column: usize,
+) -> Result<Vec<(RecordHandle, RecordHandle)>> {
+    Ok(join_left(left, left_column, right, right_column)?
+        .into_iter()
+        .filter_map(|(left, right)| Some((left, right?)))
+        .collect())
+}
+
+/// Like `join_eq`, but keeps the left rows nothing matched, paired with `None`.
+pub fn join_left(
+    left: &impl TableRead,
+    left_column: usize,
+    right: &impl TableRead,
+    right_column: usize,
+) -> Result<Vec<(RecordHandle, Option<RecordHandle>)>> {
+    let mut by_value = HashMap::<DataValue, Vec<RecordHandle>>::new();
+    let mut cancel = right.table()._cancel_check();
+
+    for (_, handle) in right.rows() {
+        cancel.tick()?;
+
+        // a row removed since it was listed has nothing to match
+        let Ok((mut values, _)) = right.table().get_versioned(&handle) else {
+            continue;
+        };
+
+        if let Some(value) = values.get_mut(right_column).and_then(Option::take) {
+            by_value.entry(value).or_default().push(handle);
+        }
+    }
+
+    let mut pairs = Vec::new();
+    let mut cancel = left.table()._cancel_check();
+
+    for (_, handle) in left.rows() {
+        cancel.tick()?;
+
+        let Ok((values, _)) = left.table().get_versioned(&handle) else {
+            continue;
+        };
+
+        let matches = values
+            .get(left_column)
+            .and_then(Option::as_ref)
+            .and_then(|value| by_value.get(value));
+
+        match matches {
+            Some(matches) => pairs.extend(
+                matches
+                    .iter()
+                    .map(|other| (handle.clone(), Some(other.clone()))),
+            ),
+            None => pairs.push((handle, None)),
+        }
+    }
+
+    Ok(pairs)
+}
@@ -0,0 +1,197 @@
+use std::{fs, path::Path, str::FromStr, sync::Arc};
+
+use anyhow::Result;
+use dbexp::{
+    records::Records,
+    store::{Store, StoreConfig, TableFile},
+    values::DataValue,
+};
+use primitives::InternalPath;
+
+use crate::{files::resolve, TableConfig};
+
+/// The file a `SingleFile` table keeps all of its stores in.
+pub const TABLE_FILE: &str = "table.store";
+
+/// The region of a table file the record store is kept in. Column stores follow it in the order
+/// of their cells, see `ColumnConfigs::cell`.
+pub const RECORDS_REGION: u32 = 0;
+
+pub fn column_region(cell: usize) -> u32 {
+    RECORDS_REGION + 1 + cell as u32
+}
+
+/// How the stores of a persisted table are laid out in its directory. Memory-only tables are the
+/// same either way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum StorageLayout {
+    /// The record store and every column store in files of their own, each locked and mapped on
+    /// its own.
+    #[default]
+    SeparateFiles,
+    /// Every store in a region of one `table.store` file, which is opened and locked once, for
+    /// tables with enough columns to run into file descriptor limits. The annotations are still a
+    /// table of their own in separate files.
+    SingleFile,
+}
+
+impl StorageLayout {
+    pub(crate) fn into_u8(self) -> u8 {
+        match self {
+            Self::SeparateFiles => 0,
+            Self::SingleFile => 1,
+        }
+    }
+
+    pub(crate) fn try_from_u8(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(Self::SeparateFiles),
+            1 => Ok(Self::SingleFile),
+            other => anyhow::bail!("invalid storage layout {}", other),
+        }
+    }
+}
+
+impl FromStr for StorageLayout {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "separate_files" => Ok(Self::SeparateFiles),
+            "single_file" => Ok(Self::SingleFile),
+            other => anyhow::bail!(
+                "unknown storage layout {:?}, expected separate_files or single_file",
+                other
+            ),
+        }
+    }
+}
+
+impl TableConfig {
+    /// Where the table file of a `SingleFile` table goes, with the rest of what the record store
+    /// is opened with.
+    pub fn table_file_config(&self) -> Result<StoreConfig> {
+        Ok(StoreConfig {
+            persistance: self.store_path(TABLE_FILE)?,
+            ..self.records_store_config()?
+        })
+    }
+
+    /// Opens the table file of a persisted `SingleFile` table, or `None` for any other table.
+    /// Fails if the directory holds the files of the other layout, which would otherwise be
+    /// ignored while an empty table is opened next to them.
+    pub(crate) fn _open_table_file(&self, root: Option<&Path>) -> Result<Option<Arc<TableFile>>> {
+        if self.persistance.is_empty() {
+            return Ok(None);
+        }
+
+        let table_file = resolve(&self.table_file_config()?, root)?;
+        let records = resolve(&self.records_store_config()?, root)?;
+
+        match self.storage_layout {
+            StorageLayout::SeparateFiles if table_file.exists() => anyhow::bail!(
+                "{} is there, but the table is configured with separate files",
+                table_file.display()
+            ),
+            StorageLayout::SeparateFiles => Ok(None),
+            StorageLayout::SingleFile if records.exists() => anyhow::bail!(
+                "{} is there, but the table is configured with a single file; migrate it with \
+                 migrate_to_single_file",
+                records.display()
+            ),
+            StorageLayout::SingleFile => {
+                let lock_timeout = self.records_store_config()?.lock_timeout;
+                TableFile::open(table_file, lock_timeout).map(Some)
+            }
+        }
+    }
+}
+
+/// Moves the stores of a persisted `SeparateFiles` table into a single table file, and returns
+/// the config to open it with from now on. The table must not be open, which the locks on its
+/// files make sure of. The table file is written under a temporary name and renamed into place
+/// before the separate files are removed, so an interrupted migration leaves either the old files
+/// alone or both, which opening the table refuses until one is removed.
+pub fn migrate_to_single_file(config: &TableConfig, root: Option<&Path>) -> Result<TableConfig> {
+    if config.persistance.is_empty() {
+        anyhow::bail!("memory-only tables have no files to migrate");
+    }
+
+    if config.storage_layout == StorageLayout::SingleFile {
+        anyhow::bail!("table is already in a single file");
+    }
+
+    let records_config = config.records_store_config()?;
+    let records_path = resolve(&records_config, root)?;
+    let table_path = resolve(&config.table_file_config()?, root)?;
+
+    if !records_path.exists() {
+        anyhow::bail!("{} is missing", records_path.display());
+    }
+
+    if table_path.exists() {
+        anyhow::bail!("{} is already there", table_path.display());
+    }
+
+    // opening the stores takes their locks, which fails if the table is open anywhere
+    let records = Records::new(
+        None,
+        Some(config_at(records_config, &records_path)?),
+        config.columns.cell_span(),
+    )?;
+    let mut columns = Vec::new();
+
+    for column in 0..config.columns.len() {
+        let store_config = config
+            .columns
+            .get(column)
+            .unwrap()
+            .into_store_config(config, column)?;
+        let path = resolve(&store_config, root)?;
+
+        // columns never written to have no file
+        if path.exists() {
+            let store = Store::<DataValue>::new(None, Some(config_at(store_config, &path)?))?;
+            columns.push((config.columns.cell(column), path, store));
+        }
+    }
+
+    let partial = table_path.with_extension("store.partial");
+    let _ = fs::remove_file(&partial);
+
+    let file = TableFile::open(&partial, None)?;
+
+    records.sync_all()?;
+    Records::import_image(&records_path, &file.region(RECORDS_REGION))?;
+
+    for (cell, path, store) in columns.iter() {
+        store.sync_all()?;
+        Store::<DataValue>::import_image(path, &file.region(column_region(*cell)))?;
+    }
+
+    file.sync_all()?;
+    drop(file);
+
+    fs::rename(&partial, &table_path)?;
+
+    drop(records);
+    fs::remove_file(&records_path)?;
+
+    for (_, path, store) in columns {
+        drop(store);
+        fs::remove_file(path)?;
+    }
+
+    Ok(TableConfig {
+        storage_layout: StorageLayout::SingleFile,
+        ..*config
+    })
+}
+
+/// `config` with its persistance made absolute, so the store opens without a database root.
+fn config_at(config: StoreConfig, path: &Path) -> Result<StoreConfig> {
+    Ok(StoreConfig {
+        persistance: InternalPath::new(path)?,
+        ..config
+    })
+}
@@ -0,0 +1,102 @@
+use anyhow::Result;
+use dbexp::{object_ids::RecordId, records::RecordHandle, values::DataValue};
+use primitives::DataType;
+
+use crate::{Table, TableError};
+
+/// The length of a text or bytes value, read in place without copying its contents.
+fn value_len(value: &DataValue) -> Option<usize> {
+    match value {
+        DataValue::Text(text) => Some(text.len()),
+        DataValue::Bytes(bytes) => Some(bytes.len()),
+        _ => None,
+    }
+}
+
+impl Table {
+    fn _check_length_column(&self, column: usize) -> Result<()> {
+        let Some(config) = self.config.columns.get(column) else {
+            anyhow::bail!("no column {}", column);
+        };
+
+        match config.data_type.into_inner() {
+            DataType::Text(_) | DataType::Bytes(_) => Ok(()),
+            other => anyhow::bail!("column {} is {:?}, which has no length", column, other),
+        }
+    }
+
+    /// Calls `f` with the length of every non-empty value of a text or bytes `column`.
+    pub(crate) fn _for_each_length(
+        &self,
+        column: usize,
+        mut f: impl FnMut(RecordId, usize) -> Result<()>,
+    ) -> Result<()> {
+        self._check_length_column(column)?;
+
+        let store = self.get_column_store(column)?;
+        let blocks = store.scan_blocks(self.config.scan);
+        let mut cancel = self._cancel_check();
+
+        for block in blocks {
+            for handle in block.iter_live() {
+                cancel.tick()?;
+
+                let found = handle.read_with(|slot| {
+                    Ok(slot.data().and_then(value_len).zip(slot.thin_record_id()))
+                })?;
+
+                if let Some((len, record)) = found {
+                    f(RecordId::from_thin(record, self.id), len)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The length of every non-empty value of a text or bytes `column`, in bytes.
+    pub fn lengths(&self, column: usize) -> Result<Vec<(RecordHandle, usize)>, TableError> {
+        let mut lengths = Vec::new();
+
+        self._for_each_length(column, |record, len| {
+            lengths.extend(self.records.get(record).map(|handle| (handle, len)));
+            Ok(())
+        })?;
+
+        Ok(lengths)
+    }
+
+    /// Every row whose text or bytes `column` has a length `matches` accepts. Rows leaving the
+    /// column empty never match.
+    pub fn scan_length(
+        &self,
+        column: usize,
+        matches: impl Fn(usize) -> bool,
+    ) -> Result<Vec<RecordHandle>, TableError> {
+        let mut rows = Vec::new();
+
+        self._for_each_length(column, |record, len| {
+            if matches(len) {
+                rows.extend(self.records.get(record));
+            }
+
+            Ok(())
+        })?;
+
+        Ok(rows)
+    }
+
+    /// The average length of the non-empty values of a text or bytes `column`, or `None` when
+    /// every row leaves it empty.
+    pub fn avg_length(&self, column: usize) -> Result<Option<f64>, TableError> {
+        let (mut total, mut count) = (0usize, 0usize);
+
+        self._for_each_length(column, |_, len| {
+            total += len;
+            count += 1;
+            Ok(())
+        })?;
+
+        Ok((count > 0).then(|| total as f64 / count as f64))
+    }
+}
@@ -2,15 +2,28 @@
 #![feature(step_trait)]
 #![feature(os_str_display)]
 
-use std::{any::Any, mem::MaybeUninit, num::NonZeroUsize, ops::RangeBounds, path::Path};
+use std::{
+    any::{Any, TypeId},
+    mem::MaybeUninit,
+    num::{NonZeroU8, NonZeroUsize},
+    ops::RangeBounds,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::Sender,
+        Arc, Mutex,
+    },
+    time::Duration,
+};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use dbexp::{
-    indices::{ColumnIndices, MAX_COLUMNS},
-    object_ids::TableId,
+    block::{Block, ScanOptions, SequentialBlocks},
+    indices::{CellIdx, ColumnIndices},
+    object_ids::{RecordId, TableId},
     records::{RecordHandle, Records},
     slot::SlotHandle,
-    store::{Store, StoreConfig, StoreError},
+    store::{IoIncident, Store, StoreConfig, StoreError, TableFile},
     values::DataValue,
 };
 use indexmap::IndexMap;
@@ -18,8 +31,112 @@ use primitives::{
     byte_encoding::{ByteDecoder, ByteEncoder, FromBytes, IntoBytes},
     impl_access_bytes_for_into_bytes_type,
     shared_object::SharedObject,
-    ExpectedType, InternalPath, InternalString,
+    DataType, ExpectedType, InternalPath, InternalString, Number, O16, O64,
+};
+use serde::Serialize;
+
+use crate::{
+    bloom::ColumnBlooms,
+    cardinality::ColumnSketch,
+    changes::Listeners,
+    defaults::Sequence,
+    layout::{column_region, RECORDS_REGION},
+    limits::{MAX_BLOCK_CAPACITY, MAX_COLUMNS},
+    meta::MetaTable,
+    ops::OpsLog,
+    primary_key::KeyIndex,
+    ttl::Expiry,
+    unique::{present, UniqueIndex},
+};
+
+pub use bitmap::{BoolColumnView, BITMAP_FORMAT_VERSION};
+pub use bloom::{BloomFilter, BloomProbe, EqScan, DEFAULT_BLOOM_BITS_PER_KEY};
+pub use cancel::{CancellationToken, QueryCancelled, QueryTimeout, CHECK_EVERY_SLOTS};
+pub use capacity::ColumnCapacityAdvice;
+pub use cardinality::{CardinalitySketch, SKETCH_REGISTERS};
+pub use changes::{Change, ChangeListener, ListenerId};
+pub use corrupt::{OnCorrupt, RowScan};
+pub use debug::{BlockMetaDebug, ColumnDebug, RecordDebug, SlotDebug};
+pub use defaults::{GeneratorKind, SEQUENCE_BATCH};
+pub use dump::{DumpManifest, DumpedFile, DUMP_FORMAT_VERSION};
+pub use error::TableError;
+#[cfg(feature = "arrow")]
+pub use export::{to_arrow_batches, to_parquet_file, ArrowBatches, ExportOptions};
+pub use files::StoreFileIssue;
+pub use fragmentation::{ColumnFragReport, TableFragReport};
+pub use group_by::{GroupBy, GroupByMetrics};
+pub use histogram::{Histogram, HistogramResult};
+pub use integrity::{IntegrityReport, Violation};
+pub use join::{join_eq, join_left};
+pub use layout::{migrate_to_single_file, StorageLayout};
+pub use logical::LogicalType;
+pub use memo::{TextMemo, TEXT_MEMO_CAPACITY};
+pub use migrate::{add_column, drop_column, shrink_column, ShrinkPolicy};
+pub use normalize::Normalization;
+pub use ops::MAX_LOGGED_OPS;
+pub use overflow::OverflowPolicy;
+pub use primary_key::{CompositeKey, PrimaryKey};
+pub use rate_limit::{LimiterChain, RateLimit, RateLimiter, Throttled, TokenBucket};
+pub use rebuild::RebuildReport;
+pub use recovery::{ColumnUnavailable, OpenOptions, OpenReport};
+pub use row::{ColumnValue, FromRow, TableSchemaRef, ToRow};
+pub use scrub::{ColumnScrubReport, TableScrubReport};
+pub use snapshot::{snapshot_tables, ReadSnapshot, TableRead, TableSnapshot};
+pub use sorted_runs::{Duplicate, MergedRuns, SortedRun, EXTERNAL_SORT_ROWS};
+pub use ttl::{ExpirySweeper, TTL_WINDOWS};
+pub use unique::UniqueViolation;
+pub use view::{Aggregate, MaterializedView};
+pub use wal::{
+    read_wal, WalConfig, WalRecord, WalStats, WalSyncMode, WalWriter, WAL_QUEUE_CAPACITY,
 };
+pub use window::{SortOrder, WindowFunc};
+
+pub mod bitmap;
+pub mod bloom;
+pub mod cancel;
+pub mod capacity;
+pub mod cardinality;
+pub mod changes;
+pub mod corrupt;
+#[cfg(any(test, feature = "datagen"))]
+pub mod datagen;
+pub mod debug;
+pub mod defaults;
+pub mod dump;
+pub mod error;
+#[cfg(feature = "arrow")]
+pub mod export;
+pub mod files;
+pub mod fragmentation;
+pub mod group_by;
+pub mod histogram;
+pub mod integrity;
+pub mod join;
+pub mod layout;
+pub mod length;
+pub mod limits;
+pub mod logical;
+pub mod memo;
+pub mod meta;
+pub mod migrate;
+pub mod normalize;
+pub mod ops;
+pub mod overflow;
+pub mod primary_key;
+pub mod rate_limit;
+pub mod rebuild;
+pub mod recovery;
+pub mod row;
+pub mod scrub;
+pub mod snapshot;
+pub mod sorted_runs;
+#[cfg(any(test, feature = "testing"))]
+pub mod test_util;
+pub mod ttl;
+pub mod unique;
+pub mod view;
+pub mod wal;
+pub mod window;
 
 #[derive(thiserror::Error, Debug)]
 pub enum InsertError {
@@ -36,14 +153,102 @@ pub enum InsertError {
         column: usize,
         values: Vec<Option<DataValue>>,
         #[source]
-        error: anyhow::Error,
+        error: ValueError,
     },
     #[error("no values to insert")]
     NoValues { record_handle: RecordHandle },
+    #[error("duplicate primary key {key}")]
+    DuplicateKey {
+        key: CompositeKey,
+        values: Vec<Option<DataValue>>,
+    },
+    #[error("record is invalid")]
+    InvalidRow {
+        values: Vec<Option<DataValue>>,
+        #[source]
+        error: RowValidationError,
+    },
+    #[error("column {column} already holds {value} in record {existing_record}")]
+    UniqueViolation {
+        column: usize,
+        value: DataValue,
+        existing_record: RecordId,
+    },
+    /// A column the row has no value for couldn't be given its default, e.g. a sequence that ran
+    /// out. `values` are the row as it was before any default was filled in.
+    #[error("record is missing a value its column couldn't default")]
+    DefaultFailed {
+        values: Vec<Option<DataValue>>,
+        #[source]
+        error: anyhow::Error,
+    },
+    #[error("record has an invalid primary key")]
+    InvalidKey {
+        values: Vec<Option<DataValue>>,
+        #[source]
+        error: anyhow::Error,
+    },
     #[error(transparent)]
     Unexpected(#[from] anyhow::Error),
 }
 
+/// Why a row can't be written to a table. Rows are checked before anything is allocated for them.
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum RowValidationError {
+    #[error("row has {actual} values but the table only has {expected} columns")]
+    TooManyValues { expected: usize, actual: usize },
+    #[error("column {column} ({name}) expects {expected:?} but got {actual:?}")]
+    TypeMismatch {
+        column: usize,
+        name: String,
+        expected: ExpectedType,
+        actual: ExpectedType,
+    },
+    /// Only for columns with `OverflowPolicy::RejectRow`.
+    #[error("column {column} ({name}) holds at most {cap} bytes but got {len}")]
+    TooLong {
+        column: usize,
+        name: String,
+        len: usize,
+        cap: usize,
+    },
+    #[error("column {column} ({name}) holds {logical_type} values")]
+    NotLogicalType {
+        column: usize,
+        name: String,
+        logical_type: LogicalType,
+    },
+    /// The column's store file was missing when the table was opened, see `OpenOptions`.
+    #[error("column {column} ({name}) is unavailable until it's rebuilt")]
+    Unavailable { column: usize, name: String },
+}
+
+/// Why a value can't be stored in a column, with enough detail for a client to point at the field.
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq, Serialize)]
+#[error("{} expects {expected:?}: {reason}", column.as_deref().unwrap_or("column"))]
+pub struct ValueError {
+    /// The column's name, for columns of tables opened with names.
+    pub column: Option<String>,
+    pub expected: ExpectedType,
+    pub reason: ValueErrorReason,
+}
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ValueErrorReason {
+    #[error("value is {len} bytes long but the column holds at most {cap}")]
+    TooLong { len: usize, cap: usize },
+    #[error("value can't be parsed: {message}")]
+    ParseFailed { message: String },
+    #[error("value is out of range: {message}")]
+    OutOfRange { message: String },
+    /// The column's store refused a value that converted fine.
+    #[error("value was rejected: {message}")]
+    Rejected { message: String },
+    #[error("value is not a valid {logical_type}")]
+    NotLogicalType { logical_type: LogicalType },
+}
+
 #[derive(Debug)]
 pub enum InsertState {
     Done(Vec<RecordHandle>),
@@ -53,11 +258,70 @@ pub enum InsertState {
     },
 }
 
+/// What `Table::delete_many` did, with the values of the rows it removed.
+#[derive(Debug)]
+pub enum DeleteState {
+    Done(Vec<Vec<Option<DataValue>>>),
+    Partial {
+        deleted: Vec<(usize, Vec<Option<DataValue>>)>,
+        errors: Vec<(usize, TableError)>,
+    },
+}
+
+/// A row written by `Table::insert_one`, as it was stored. Every column is there, so a caller can
+/// answer with the row without reading it back.
+#[derive(Debug, Clone)]
+pub struct InsertedRow {
+    pub handle: RecordHandle,
+    pub record_id: RecordId,
+    pub values: Vec<Option<DataValue>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpdateOutcome {
+    /// The values were applied and the record now has the contained generation.
+    Updated(O64),
+    /// The record changed since the expected generation was read, so nothing was applied. Holds the
+    /// current generation.
+    Conflict(O64),
+}
+
+/// How many times `Table::update_one` reads a row and tries to write it before giving up on a row
+/// that others keep writing.
+pub const UPDATE_ATTEMPTS: usize = 16;
+
+/// An update that kept losing to other writes of the same row, see `Table::update_one`.
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("record {record} changed during each of {attempts} attempts to update it")]
+pub struct UpdateConflict {
+    pub record: RecordId,
+    pub attempts: usize,
+}
+
 #[derive(Clone, Copy, PartialEq, Eq, Hash)]
 pub struct DataConfig {
     pub initial_block_count: Option<NonZeroUsize>,
     pub block_capacity: Option<NonZeroUsize>,
     pub data_type: ExpectedType,
+    /// Only used to name the column in errors. It isn't persisted, and is filled in from the
+    /// table's column names when it's opened.
+    pub name: Option<InternalString>,
+    /// Bits per key of the bloom filter kept for each block of a text or bytes column, if any.
+    pub bloom: Option<NonZeroU8>,
+    /// What happens to text or bytes values longer than the column holds.
+    pub overflow: OverflowPolicy,
+    /// How text is cleaned up before it's written. Only text columns can be normalized.
+    pub normalize: Normalization,
+    /// What the text of the column is, for schema introspection and, unless `check_logical_type`
+    /// is off, for refusing values that aren't one.
+    pub logical_type: Option<LogicalType>,
+    pub check_logical_type: bool,
+    /// What a row inserted without a value for the column gets instead.
+    pub default: Option<GeneratorKind>,
+    /// Keeps a sketch of the column's values for `Table::estimated_distinct`.
+    pub track_cardinality: bool,
+    /// No two rows may hold the same value in the column, see the `unique` module.
+    pub unique: bool,
 }
 
 impl_access_bytes_for_into_bytes_type!(DataConfig);
@@ -66,7 +330,21 @@ impl IntoBytes for DataConfig {
     fn encode_bytes(&self, x: &mut ByteEncoder<'_>) -> Result<()> {
         x.encode(self.initial_block_count)?;
         x.encode(self.block_capacity)?;
-        x.encode(self.data_type)
+        x.encode(self.data_type)?;
+        x.encode(self.bloom)?;
+        x.encode(self.overflow.into_u8())?;
+        x.encode(self.normalize.into_u8())?;
+        x.encode(LogicalType::into_u8(
+            self.logical_type,
+            self.check_logical_type,
+        ))?;
+
+        let (default, start, step) = GeneratorKind::into_parts(self.default);
+        x.encode(default)?;
+        x.encode(start)?;
+        x.encode(step)?;
+        x.encode(self.track_cardinality)?;
+        x.encode(self.unique)
     }
 }
 
@@ -74,7 +352,30 @@ impl FromBytes for DataConfig {
     fn decode_bytes(this: &mut Self, x: &mut ByteDecoder<'_>) -> Result<()> {
         x.decode(&mut this.initial_block_count)?;
         x.decode(&mut this.block_capacity)?;
-        x.decode(&mut this.data_type)
+        x.decode(&mut this.data_type)?;
+        x.decode(&mut this.bloom)?;
+
+        let mut overflow = 0u8;
+        x.decode(&mut overflow)?;
+        this.overflow = OverflowPolicy::try_from_u8(overflow)?;
+
+        let mut normalize = 0u8;
+        x.decode(&mut normalize)?;
+        this.normalize = Normalization::try_from_u8(normalize)?;
+
+        let mut logical_type = 0u8;
+        x.decode(&mut logical_type)?;
+        (this.logical_type, this.check_logical_type) = LogicalType::try_from_u8(logical_type)?;
+
+        let (mut default, mut start, mut step) = (0u8, 0i64, 0i64);
+        x.decode(&mut default)?;
+        x.decode(&mut start)?;
+        x.decode(&mut step)?;
+        this.default = GeneratorKind::try_from_parts(default, start, step)?;
+        x.decode(&mut this.track_cardinality)?;
+        x.decode(&mut this.unique)?;
+
+        Ok(())
     }
 }
 
@@ -83,6 +384,10 @@ impl std::fmt::Debug for DataConfig {
         let mut d = f.debug_struct("DataConfig");
         let mut full = true;
 
+        if let Some(name) = self.name {
+            d.field("name", &name);
+        }
+
         d.field("data_type", &self.data_type);
 
         if let Some(initial_block_count) = self.initial_block_count {
@@ -97,6 +402,38 @@ impl std::fmt::Debug for DataConfig {
             full = false;
         }
 
+        if let Some(bloom) = self.bloom {
+            d.field("bloom", &bloom);
+        }
+
+        if self.overflow != OverflowPolicy::Error {
+            d.field("overflow", &self.overflow);
+        }
+
+        if !self.normalize.is_none() {
+            d.field("normalize", &self.normalize);
+        }
+
+        if let Some(logical_type) = self.logical_type {
+            d.field("logical_type", &logical_type);
+
+            if !self.check_logical_type {
+                d.field("check_logical_type", &false);
+            }
+        }
+
+        if let Some(default) = self.default {
+            d.field("default", &default);
+        }
+
+        if self.track_cardinality {
+            d.field("track_cardinality", &true);
+        }
+
+        if self.unique {
+            d.field("unique", &true);
+        }
+
         if full {
             d.finish()
         } else {
@@ -111,33 +448,323 @@ impl DataConfig {
             initial_block_count: None,
             block_capacity: None,
             data_type: data_type.into(),
+            name: None,
+            bloom: None,
+            overflow: OverflowPolicy::Error,
+            normalize: Normalization::NONE,
+            logical_type: None,
+            check_logical_type: true,
+            default: None,
+            track_cardinality: false,
+            unique: false,
+        }
+    }
+
+    pub fn with_name(self, name: InternalString) -> Self {
+        Self {
+            name: Some(name),
+            ..self
+        }
+    }
+
+    pub fn with_overflow(self, overflow: OverflowPolicy) -> Self {
+        Self { overflow, ..self }
+    }
+
+    pub fn with_normalization(self, normalize: Normalization) -> Self {
+        Self { normalize, ..self }
+    }
+
+    pub fn with_logical_type(self, logical_type: LogicalType) -> Self {
+        Self {
+            logical_type: Some(logical_type),
+            ..self
+        }
+    }
+
+    pub fn with_default(self, default: GeneratorKind) -> Self {
+        Self {
+            default: Some(default),
+            ..self
+        }
+    }
+
+    /// Keeps the logical type for introspection without refusing values that aren't one, for
+    /// columns holding data from before it was checked.
+    pub fn with_logical_check(self, check_logical_type: bool) -> Self {
+        Self {
+            check_logical_type,
+            ..self
+        }
+    }
+
+    /// Keeps a sketch of the column's values, so `Table::estimated_distinct` can tell about how
+    /// many distinct values it has held without a scan. See the `cardinality` module.
+    pub fn with_cardinality_tracking(self) -> Self {
+        Self {
+            track_cardinality: true,
+            ..self
+        }
+    }
+
+    /// Refuses a row holding a value another row already holds in the column, checked against an
+    /// index of the column's values. See the `unique` module.
+    pub fn with_unique(self) -> Self {
+        Self {
+            unique: true,
+            ..self
+        }
+    }
+
+    /// Keeps a bloom filter for each block of the column, so equality scans can skip the blocks
+    /// that can't hold the value. Only text and bytes columns can be filtered.
+    pub fn with_bloom(self) -> Self {
+        self.with_bloom_bits(DEFAULT_BLOOM_BITS_PER_KEY)
+    }
+
+    /// Like `with_bloom`, trading memory for fewer false positives with more bits per key.
+    pub fn with_bloom_bits(self, bits_per_key: NonZeroU8) -> Self {
+        Self {
+            bloom: Some(bits_per_key),
+            ..self
         }
     }
 
-    pub fn into_store_config(self, table_config: &TableConfig) -> StoreConfig {
+    pub fn into_store_config(
+        self,
+        table_config: &TableConfig,
+        column: usize,
+    ) -> Result<StoreConfig> {
         let initial_block_count = self
             .initial_block_count
             .unwrap_or(table_config.initial_block_count);
 
         let block_capacity = self.block_capacity.unwrap_or(table_config.block_capacity);
 
-        StoreConfig {
+        Ok(StoreConfig {
             initial_block_count,
             block_capacity,
-            persistance: table_config.persistance,
+            persistance: table_config.store_path(column_file(table_config.columns.cell(column)))?,
+            trusted_input: table_config.trusted_input,
+            label: Some(self.store_label(column)?),
+            checksum: table_config.checksum,
+            ..StoreConfig::default()
+        })
+    }
+
+    /// What the store of the column is labeled with, so its errors and reports say which column
+    /// they're about.
+    pub fn store_label(&self, column: usize) -> Result<InternalString> {
+        match self.name {
+            Some(name) => InternalString::new(format!("column '{}' (index {})", name, column)),
+            None => InternalString::new(format!("column {}", column)),
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        self.data_type.into_inner().validate()?;
+
+        if self.bloom.is_some()
+            && !matches!(
+                self.data_type.into_inner(),
+                DataType::Text(_) | DataType::Bytes(_)
+            )
+        {
+            anyhow::bail!(
+                "bloom filters need a text or bytes column, not {:?}",
+                self.data_type
+            );
+        }
+
+        if !self.normalize.is_none() && !matches!(self.data_type.into_inner(), DataType::Text(_)) {
+            anyhow::bail!(
+                "normalization needs a text column, not {:?}",
+                self.data_type
+            );
+        }
+
+        if let Some(logical_type) = self.logical_type {
+            if !matches!(self.data_type.into_inner(), DataType::Text(_)) {
+                anyhow::bail!(
+                    "logical type {} needs a text column, not {:?}",
+                    logical_type,
+                    self.data_type
+                );
+            }
+        }
+
+        if let Some(default) = self.default {
+            if self.data_type.into_inner() != default.data_type() {
+                anyhow::bail!(
+                    "{:?} needs a {:?} column, not {:?}",
+                    default,
+                    default.data_type(),
+                    self.data_type
+                );
+            }
+
+            if let GeneratorKind::Sequence { step: 0, .. } = default {
+                anyhow::bail!("a sequence needs a step other than zero");
+            }
+        }
+
+        if let Some(block_capacity) = self.block_capacity {
+            if block_capacity.get() > MAX_BLOCK_CAPACITY {
+                anyhow::bail!(
+                    "block capacity {} exceeds MAX_BLOCK_CAPACITY ({})",
+                    block_capacity,
+                    MAX_BLOCK_CAPACITY
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Builds a value for the column. Text is normalized first, then text and bytes longer than
+    /// the column holds are refused, unless the column's overflow policy is `Truncate`, as is text
+    /// that isn't the column's logical type.
+    pub fn try_new_value<V: Any>(&self, value: V) -> Result<DataValue, ValueError> {
+        if !self.normalize.is_none() && matches!(self.data_type.into_inner(), DataType::Text(_)) {
+            let value = &value as &dyn Any;
+            let text = if let Some(val) = value.downcast_ref::<&str>() {
+                Some(*val)
+            } else {
+                value.downcast_ref::<String>().map(String::as_str)
+            };
+
+            if let Some(text) = text {
+                let normalized = self.normalize.apply(text);
+
+                // normalizing twice changes nothing, so this only recurses once
+                if normalized != text {
+                    return self.try_new_value(normalized.into_owned());
+                }
+            }
+        }
+
+        if let DataType::Text(cap) | DataType::Bytes(cap) = self.data_type.into_inner() {
+            let cap = cap as usize;
+            let value = &value as &dyn Any;
+            let text = if let Some(val) = value.downcast_ref::<&str>() {
+                Some(*val)
+            } else {
+                value.downcast_ref::<String>().map(String::as_str)
+            };
+            let bytes = if let Some(val) = value.downcast_ref::<&[u8]>() {
+                Some(*val)
+            } else {
+                value.downcast_ref::<Vec<u8>>().map(Vec::as_slice)
+            };
+
+            match (text, bytes) {
+                (Some(text), _) if text.len() > cap => {
+                    if self.overflow == OverflowPolicy::Truncate {
+                        return self.try_new_value(overflow::truncate_str(text, cap).to_string());
+                    }
+
+                    return Err(self.value_error(ValueErrorReason::TooLong {
+                        len: text.len(),
+                        cap,
+                    }));
+                }
+                (_, Some(bytes)) if bytes.len() > cap => {
+                    if self.overflow == OverflowPolicy::Truncate {
+                        return self.try_new_value(bytes[..cap].to_vec());
+                    }
+
+                    return Err(self.value_error(ValueErrorReason::TooLong {
+                        len: bytes.len(),
+                        cap,
+                    }));
+                }
+                _ => {}
+            }
+        }
+
+        if let (Some(logical_type), true) = (self.logical_type, self.check_logical_type) {
+            let value = &value as &dyn Any;
+            let text = if let Some(val) = value.downcast_ref::<&str>() {
+                Some(*val)
+            } else {
+                value.downcast_ref::<String>().map(String::as_str)
+            };
+
+            if text.is_some_and(|text| !logical_type.check(text)) {
+                return Err(self.value_error(ValueErrorReason::NotLogicalType { logical_type }));
+            }
         }
+
+        DataValue::try_from_any(self.data_type, value).map_err(|e| {
+            let message = e.to_string();
+
+            // numbers always parse, so a number that doesn't convert didn't fit
+            self.value_error(if is_number::<V>() {
+                ValueErrorReason::OutOfRange { message }
+            } else {
+                ValueErrorReason::ParseFailed { message }
+            })
+        })
     }
 
-    pub fn try_new_value<V: Any>(&self, value: V) -> Result<DataValue> {
-        DataValue::try_from_any(self.data_type, value)
+    pub fn value_error(&self, reason: ValueErrorReason) -> ValueError {
+        ValueError {
+            column: self.name.map(|name| name.as_str().to_string()),
+            expected: self.data_type,
+            reason,
+        }
     }
 
     // TODO: support custom config
 }
 
+/// The file of the column store in `cell`, inside the table's directory.
+pub(crate) fn column_file(cell: usize) -> String {
+    format!("column_{}.store", cell)
+}
+
+fn is_number<V: Any>() -> bool {
+    [
+        TypeId::of::<Number>(),
+        TypeId::of::<i8>(),
+        TypeId::of::<i16>(),
+        TypeId::of::<i32>(),
+        TypeId::of::<i64>(),
+        TypeId::of::<i128>(),
+        TypeId::of::<isize>(),
+        TypeId::of::<u8>(),
+        TypeId::of::<u16>(),
+        TypeId::of::<u32>(),
+        TypeId::of::<u64>(),
+        TypeId::of::<u128>(),
+        TypeId::of::<usize>(),
+        TypeId::of::<f32>(),
+        TypeId::of::<f64>(),
+    ]
+    .contains(&TypeId::of::<V>())
+}
+
+/// Where a column is kept, which stays the same as the columns around it are added and dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct ColumnSlot {
+    /// Names the column for as long as it exists, unlike its position.
+    id: O16,
+    /// The column's cell in the `ColumnIndices` of every record, which also numbers its store.
+    cell: u8,
+}
+
+/// Written ahead of the column count by encodings that carry the id and cell of every column.
+/// Older encodings start with the count, which is never this large. They are read with each
+/// column's cell at its position, which is where their records and store files have it.
+const COLUMN_IDS_FORMAT: usize = usize::MAX;
+
 #[derive(Clone, Copy)]
 #[repr(C)]
-pub struct ColumnConfigs(NonZeroUsize, [MaybeUninit<DataConfig>; MAX_COLUMNS]);
+pub struct ColumnConfigs(
+    NonZeroUsize,
+    [MaybeUninit<DataConfig>; MAX_COLUMNS],
+    [ColumnSlot; MAX_COLUMNS],
+);
 
 impl std::fmt::Debug for ColumnConfigs {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -157,10 +784,13 @@ impl IntoBytes for ColumnConfigs {
     fn encode_bytes(&self, x: &mut ByteEncoder<'_>) -> Result<()> {
         let column_count = self.0.get();
 
+        x.encode(COLUMN_IDS_FORMAT)?;
         x.encode(column_count)?;
 
         for i in 0..column_count {
             x.encode(unsafe { self.1.get_unchecked(i).assume_init() })?;
+            x.encode_bytes(&self.2[i].id.into_array())?;
+            x.encode(self.2[i].cell)?;
         }
 
         Ok(())
@@ -173,10 +803,50 @@ impl FromBytes for ColumnConfigs {
 
         x.decode(&mut column_count)?;
 
+        let with_ids = column_count == COLUMN_IDS_FORMAT;
+
+        if with_ids {
+            x.decode(&mut column_count)?;
+        }
+
+        if column_count == 0 || column_count > MAX_COLUMNS {
+            anyhow::bail!("invalid column count {}", column_count);
+        }
+
         this.0 = unsafe { NonZeroUsize::new_unchecked(column_count) };
+        this.2 = Self::positional_slots();
 
+        // the slots past the current column count may never have been initialized
         for i in 0..column_count {
-            x.delegate(unsafe { this.1.get_unchecked_mut(i).assume_init_mut() })?;
+            let mut config = DataConfig::new(DataType::Bool);
+            x.delegate(&mut config)?;
+            this.1[i] = MaybeUninit::new(config);
+
+            if with_ids {
+                let mut id = [0u8; 2];
+                x.read_exact(&mut id)?;
+                this.2[i].id = O16::try_from_array(id)?;
+                x.decode(&mut this.2[i].cell)?;
+            }
+        }
+
+        if !with_ids {
+            this.assign_ids(0);
+        }
+
+        let slots = &this.2[..column_count];
+
+        for (i, slot) in slots.iter().enumerate() {
+            if slot.cell as usize >= MAX_COLUMNS {
+                anyhow::bail!("column {} has cell {}", i, slot.cell);
+            }
+
+            if slots[..i]
+                .iter()
+                .any(|other| other.id == slot.id || other.cell == slot.cell)
+            {
+                anyhow::bail!("column {} shares its id or cell with another", i);
+            }
         }
 
         Ok(())
@@ -195,7 +865,7 @@ impl PartialEq for ColumnConfigs {
             let a = unsafe { self.1.get_unchecked(i).assume_init() };
             let b = unsafe { other.1.get_unchecked(i).assume_init() };
 
-            if a != b {
+            if a != b || self.2[i] != other.2[i] {
                 return false;
             }
         }
@@ -215,6 +885,7 @@ impl std::hash::Hash for ColumnConfigs {
         for i in 0..column_count {
             let data_config = unsafe { self.1.get_unchecked(i).assume_init() };
             data_config.hash(state);
+            self.2[i].hash(state);
         }
     }
 }
@@ -225,7 +896,11 @@ impl ColumnConfigs {
         let column_count = configs.len();
 
         if column_count > MAX_COLUMNS {
-            anyhow::bail!("column count exceeds maximum");
+            anyhow::bail!(
+                "column count {} exceeds MAX_COLUMNS ({})",
+                column_count,
+                MAX_COLUMNS
+            );
         } else if configs.is_empty() {
             anyhow::bail!("column count must be greater than zero");
         }
@@ -233,21 +908,125 @@ impl ColumnConfigs {
         let mut inner = [MaybeUninit::uninit(); MAX_COLUMNS];
 
         for (i, config) in configs.iter().copied().enumerate() {
+            config
+                .validate()
+                .map_err(|e| e.context(format!("invalid config for column {}", i)))?;
+
             unsafe {
                 inner.get_unchecked_mut(i).write(config);
             }
         }
 
-        Ok(Self(
+        let mut this = Self(
             unsafe { NonZeroUsize::new_unchecked(column_count) },
             inner,
-        ))
+            Self::positional_slots(),
+        );
+        this.assign_ids(0);
+
+        Ok(this)
+    }
+
+    /// Every column kept at the cell of its position, without an id yet.
+    fn positional_slots() -> [ColumnSlot; MAX_COLUMNS] {
+        std::array::from_fn(|cell| ColumnSlot {
+            id: O16::INVALID,
+            cell: cell as u8,
+        })
+    }
+
+    /// Gives the columns from `first` on ids no other column has.
+    fn assign_ids(&mut self, first: usize) {
+        for i in first..self.len() {
+            self.2[i].id = loop {
+                let id = O16::new();
+
+                if !self.2[..i].iter().any(|slot| slot.id == id) {
+                    break id;
+                }
+            };
+        }
     }
 
     pub fn len(&self) -> usize {
         self.0.get()
     }
 
+    /// The id of the column at `index`, which it keeps as columns are added and dropped.
+    pub fn id(&self, index: usize) -> Option<O16> {
+        (index < self.len()).then(|| self.2[index].id)
+    }
+
+    /// Where the column with `id` is now.
+    pub fn position(&self, id: O16) -> Option<usize> {
+        self.2[..self.len()].iter().position(|slot| slot.id == id)
+    }
+
+    /// The cell of the column at `index` in the `ColumnIndices` of a record, which is also the
+    /// number of its store. `index` has to be in bounds.
+    pub fn cell(&self, index: usize) -> usize {
+        self.2[index].cell as usize
+    }
+
+    /// How many cells a record needs to hold every column.
+    pub fn cell_span(&self) -> usize {
+        self.2[..self.len()]
+            .iter()
+            .map(|slot| slot.cell as usize + 1)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Adds a column after the last one, with a new id and the first cell no column has.
+    pub fn with_column(mut self, config: DataConfig) -> Result<Self> {
+        let index = self.len();
+
+        if index == MAX_COLUMNS {
+            anyhow::bail!(
+                "column count {} exceeds MAX_COLUMNS ({})",
+                index + 1,
+                MAX_COLUMNS
+            );
+        }
+
+        config
+            .validate()
+            .map_err(|e| e.context(format!("invalid config for column {}", index)))?;
+
+        let cell = (0..MAX_COLUMNS)
+            .find(|cell| {
+                self.2[..index]
+                    .iter()
+                    .all(|slot| slot.cell as usize != *cell)
+            })
+            .expect("a column count below MAX_COLUMNS leaves a cell free");
+
+        self.1[index] = MaybeUninit::new(config);
+        self.2[index].cell = cell as u8;
+        self.0 = unsafe { NonZeroUsize::new_unchecked(index + 1) };
+        self.assign_ids(index);
+
+        Ok(self)
+    }
+
+    /// Takes out the column at `index`, moving the ones after it up a position. Their ids and
+    /// cells stay as they were.
+    pub fn without(mut self, index: usize) -> Result<Self> {
+        let len = self.len();
+
+        if index >= len {
+            anyhow::bail!("no column {}", index);
+        } else if len == 1 {
+            anyhow::bail!("column count must be greater than zero");
+        }
+
+        self.1.copy_within(index + 1..len, index);
+        self.2.copy_within(index + 1..len, index);
+        self.0 = unsafe { NonZeroUsize::new_unchecked(len - 1) };
+
+        Ok(self)
+    }
+
     pub fn get(&self, index: usize) -> Option<&DataConfig> {
         if index < self.0.get() {
             Some(unsafe { self.get_unchecked(index) })
@@ -259,6 +1038,33 @@ impl ColumnConfigs {
     pub unsafe fn get_unchecked(&self, index: usize) -> &DataConfig {
         self.1.get_unchecked(index).assume_init_ref()
     }
+
+    /// Names the columns of `names` that are in range, leaving the others as they were.
+    pub fn with_names(mut self, names: &IndexMap<InternalString, usize>) -> Self {
+        for (name, idx) in names.iter() {
+            if *idx < self.len() {
+                let config = unsafe { self.1.get_unchecked_mut(*idx).assume_init_mut() };
+                config.name = Some(*name);
+            }
+        }
+
+        self
+    }
+
+    /// Gives the column at `index` another type, as a migration rewriting its values does.
+    pub fn with_data_type(mut self, index: usize, data_type: ExpectedType) -> Result<Self> {
+        if index >= self.len() {
+            anyhow::bail!("no column {}", index);
+        }
+
+        let config = unsafe { self.1.get_unchecked_mut(index).assume_init_mut() };
+        config.data_type = data_type;
+        config
+            .validate()
+            .map_err(|e| e.context(format!("invalid config for column {}", index)))?;
+
+        Ok(self)
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -267,6 +1073,23 @@ pub struct TableConfig {
     pub block_capacity: NonZeroUsize,
     pub persistance: InternalPath,
     pub columns: ColumnConfigs,
+    pub primary_key: PrimaryKey,
+    /// Skips checking that values read back from the column stores are valid, see
+    /// `StoreConfig::trusted_input`. Like `on_corrupt`, this is about how the table is opened, so
+    /// it isn't written out with the rest of the config.
+    pub trusted_input: bool,
+    /// What scans do about rows with a corrupt value.
+    pub on_corrupt: OnCorrupt,
+    pub storage_layout: StorageLayout,
+    /// How long rows live after they're inserted, for tables used as a cache. See `with_ttl`.
+    pub ttl: Option<Duration>,
+    /// How scans, aggregates and dumps read the column stores. Not written out either.
+    pub scan: ScanOptions,
+    /// Where inserts are logged before they're acknowledged, if anywhere. Not written out either.
+    pub wal: Option<WalConfig>,
+    /// Keeps checksums of the blocks of every store, see `StoreConfig::checksum` and
+    /// `Table::scrub`. Not written out either.
+    pub checksum: bool,
 }
 
 impl_access_bytes_for_into_bytes_type!(TableConfig);
@@ -276,7 +1099,10 @@ impl IntoBytes for TableConfig {
         x.encode(self.initial_block_count)?;
         x.encode(self.block_capacity)?;
         x.encode(self.persistance)?;
-        x.encode(self.columns)
+        x.encode(self.columns)?;
+        x.encode(self.primary_key)?;
+        x.encode(self.storage_layout.into_u8())?;
+        x.encode(self.ttl.map_or(0, |ttl| ttl.as_millis() as u64))
     }
 }
 
@@ -285,17 +1111,18 @@ impl FromBytes for TableConfig {
         x.decode(&mut this.initial_block_count)?;
         x.decode(&mut this.block_capacity)?;
         x.delegate(&mut this.persistance)?;
-        x.delegate(&mut this.columns)
-    }
-}
+        x.delegate(&mut this.columns)?;
+        x.delegate(&mut this.primary_key)?;
 
-impl From<TableConfig> for StoreConfig {
-    fn from(config: TableConfig) -> Self {
-        Self {
-            initial_block_count: config.initial_block_count,
-            block_capacity: config.block_capacity,
-            persistance: config.persistance,
-        }
+        let mut storage_layout = 0u8;
+        x.decode(&mut storage_layout)?;
+        this.storage_layout = StorageLayout::try_from_u8(storage_layout)?;
+
+        let mut ttl = 0u64;
+        x.decode(&mut ttl)?;
+        this.ttl = (ttl > 0).then(|| Duration::from_millis(ttl));
+
+        Ok(())
     }
 }
 
@@ -305,6 +1132,7 @@ impl TableConfig {
             initial_block_count,
             block_capacity,
             persistance,
+            ..
         } = StoreConfig::default();
 
         let columns = ColumnConfigs::new(columns)?;
@@ -314,6 +1142,14 @@ impl TableConfig {
             block_capacity,
             persistance,
             columns,
+            primary_key: PrimaryKey::default(),
+            trusted_input: false,
+            on_corrupt: OnCorrupt::default(),
+            storage_layout: StorageLayout::default(),
+            ttl: None,
+            scan: ScanOptions::default(),
+            wal: None,
+            checksum: false,
         })
     }
 
@@ -334,451 +1170,6739 @@ impl TableConfig {
             block_capacity,
             persistance: InternalPath::new(persistance.as_ref())?,
             columns,
+            primary_key: PrimaryKey::default(),
+            trusted_input: false,
+            on_corrupt: OnCorrupt::default(),
+            storage_layout: StorageLayout::default(),
+            ttl: None,
+            scan: ScanOptions::default(),
+            wal: None,
+            checksum: false,
         })
     }
-}
-
-#[derive(Debug, Clone)]
-pub struct Table {
-    id: TableId,
-    config: TableConfig,
-    records: Records,
-    columns: SharedObject<IndexMap<usize, Store<DataValue>>>,
-    columns_by_name: IndexMap<InternalString, usize>,
-}
 
-impl Table {
-    pub fn new(
-        id: TableId,
-        config: TableConfig,
-        name_mapping: Option<IndexMap<InternalString, usize>>,
+    /// A persisted table whose directory is relative to the database root, which is given when the
+    /// table is opened with `Table::new_in`.
+    pub fn new_persisted_relative(
+        columns: impl AsRef<[DataConfig]>,
+        persistance: impl AsRef<Path>,
     ) -> Result<Self> {
-        let column_count = config.columns.len();
-        let columns = IndexMap::with_capacity(column_count);
-        let records = Records::new(Some(id), Some(config.into()), column_count)?;
-
         Ok(Self {
-            id,
-            config,
-            records,
-            columns: SharedObject::new(columns),
-            columns_by_name: name_mapping.unwrap_or_default(),
+            persistance: InternalPath::relative(persistance.as_ref())?,
+            ..Self::new(columns)?
         })
     }
 
-    pub fn config(&self) -> &TableConfig {
+    /// Declares the columns that make up the table's primary key, in key order.
+    pub fn with_primary_key(self, columns: impl AsRef<[usize]>) -> Result<Self> {
+        let primary_key = PrimaryKey::new(columns)?;
+
+        if let Some(column) = primary_key.columns().find(|c| *c >= self.columns.len()) {
+            anyhow::bail!("primary key column {} is out of bounds", column);
+        }
+
+        Ok(Self {
+            primary_key,
+            ..self
+        })
+    }
+
+    /// Reads values back without checking them, for tables whose files nothing else writes.
+    pub fn with_trusted_input(self, trusted_input: bool) -> Self {
+        Self {
+            trusted_input,
+            ..self
+        }
+    }
+
+    /// Checks every block of the table's stores against a checksum when it's loaded, so a damaged
+    /// file fails to open instead of handing back wrong values.
+    pub fn with_checksum(self, checksum: bool) -> Self {
+        Self { checksum, ..self }
+    }
+
+    pub fn with_on_corrupt(self, on_corrupt: OnCorrupt) -> Self {
+        Self { on_corrupt, ..self }
+    }
+
+    pub fn with_storage_layout(self, storage_layout: StorageLayout) -> Self {
+        Self {
+            storage_layout,
+            ..self
+        }
+    }
+
+    /// Makes rows expire `ttl` after they're inserted, to the millisecond. Reads leave expired rows
+    /// out right away, but they're only deleted by `Table::expire_now`, so until then they're
+    /// still counted by `Table::row_count`. When a row was inserted is only kept to a window of
+    /// `ttl / TTL_WINDOWS`, by the wall clock, and it expires at the end of that.
+    pub fn with_ttl(self, ttl: Duration) -> Result<Self> {
+        if ttl < Duration::from_millis(1) {
+            anyhow::bail!("a TTL needs to be at least a millisecond, not {:?}", ttl);
+        }
+
+        Ok(Self {
+            ttl: Some(ttl),
+            ..self
+        })
+    }
+
+    /// How scans read the column stores, e.g. without prefetching where memory is tight.
+    pub fn with_scan_options(self, scan: ScanOptions) -> Self {
+        Self { scan, ..self }
+    }
+
+    /// Logs every insert to a WAL, and only acknowledges it once it's there, see `wal`. An insert
+    /// the log fails to take returns the error, though its rows are already in the table.
+    pub fn with_wal(self, wal: WalConfig) -> Self {
+        Self {
+            wal: Some(wal),
+            ..self
+        }
+    }
+
+    pub fn with_column_names(self, names: &IndexMap<InternalString, usize>) -> Self {
+        Self {
+            columns: self.columns.with_names(names),
+            ..self
+        }
+    }
+
+    /// The persisted table directory holds one file per store. Memory-only tables stay memory-only.
+    fn store_path(&self, file_name: impl AsRef<Path>) -> Result<InternalPath> {
+        if self.persistance.is_empty() {
+            Ok(self.persistance)
+        } else {
+            self.persistance.child(file_name)
+        }
+    }
+
+    pub fn records_store_config(&self) -> Result<StoreConfig> {
+        Ok(StoreConfig {
+            initial_block_count: self.initial_block_count,
+            block_capacity: self.block_capacity,
+            persistance: self.store_path("records.store")?,
+            checksum: self.checksum,
+            ..StoreConfig::default()
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct Table {
+    id: TableId,
+    config: TableConfig,
+    records: Records,
+    /// The column stores opened so far, which happens lazily. Kept sorted by column, but anything
+    /// shown to users still goes by schema order and looks stores up here.
+    columns: SharedObject<IndexMap<usize, Store<DataValue>>>,
+    columns_by_name: IndexMap<InternalString, usize>,
+    /// The file every store is kept in, for a persisted `SingleFile` table.
+    table_file: Option<Arc<TableFile>>,
+    /// The database root relative persistance paths are resolved against. Empty when the table was
+    /// opened without one.
+    root: InternalPath,
+    /// Every row by its primary key. Stays empty for tables without one.
+    keys: SharedObject<KeyIndex>,
+    /// The rows holding each value of the unique columns, by column.
+    uniques: SharedObject<IndexMap<usize, UniqueIndex>>,
+    /// The table's key-value annotations, opened on first use.
+    meta: SharedObject<Option<MetaTable>>,
+    /// The bloom filters of the columns that asked for them, by column.
+    blooms: SharedObject<IndexMap<usize, ColumnBlooms>>,
+    /// The bitmaps of the bool columns, by column.
+    bitmaps: SharedObject<IndexMap<usize, BoolColumnView>>,
+    /// The cardinality sketches of the columns that track theirs, by column.
+    sketches: SharedObject<IndexMap<usize, ColumnSketch>>,
+    /// Held shared by every write and exclusively by `dump`, so a dump never sees half a write.
+    write_gate: SharedObject<()>,
+    /// Held shared by every read of a row's values and exclusively while `rebuild_column_online`
+    /// points the rows at the store it swaps in.
+    swap_gate: SharedObject<()>,
+    /// Held by `rebuild_column_online` for the whole rebuild, so only one runs at a time.
+    rebuilds: Arc<Mutex<()>>,
+    /// The sequences of the columns defaulting to one, loaded on first use.
+    sequences: SharedObject<IndexMap<usize, Arc<Mutex<Sequence>>>>,
+    /// Called after every write, see `on_change`.
+    listeners: Listeners,
+    closed: Arc<AtomicBool>,
+    /// Stops the queries of this handle, see `with_cancellation`.
+    cancel: CancellationToken,
+    /// The operations of `insert_idempotent`, loaded on first use. Held while one runs.
+    ops: Arc<Mutex<Option<OpsLog>>>,
+    /// When the rows were inserted, for a table with a TTL.
+    expiry: Option<Arc<Mutex<Expiry>>>,
+    /// Where inserts are logged, for a table whose config asks for it.
+    wal: Option<Arc<WalWriter>>,
+    /// Where the stores of the table send their failed flushes, those opened later included.
+    io_error_sink: SharedObject<Option<Sender<IoIncident>>>,
+    /// The columns whose store files were missing when the table was opened, with the files, see
+    /// `OpenOptions`.
+    unavailable: SharedObject<IndexMap<usize, PathBuf>>,
+    /// Whether unavailable columns read as empty.
+    unavailable_as_empty: bool,
+    /// Asked before every scan and insert, see `set_rate_limiter`.
+    rate_limiter: SharedObject<Option<Arc<dyn RateLimiter>>>,
+    throttle_events: Arc<AtomicU64>,
+}
+
+impl std::fmt::Debug for Table {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        struct Columns<'a>(&'a Table);
+
+        impl std::fmt::Debug for Columns<'_> {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                let table = self.0;
+                let opened = table.columns.read();
+                let mut d = f.debug_map();
+
+                for column in 0..table.config.columns.len() {
+                    let name = table.schema().column_name(column);
+
+                    match opened.get(&column) {
+                        Some(store) => d.entry(&name, store),
+                        None => d.entry(&name, &format_args!("<not opened>")),
+                    };
+                }
+
+                d.finish()
+            }
+        }
+
+        f.debug_struct("Table")
+            .field("id", &self.id)
+            .field("config", &self.config)
+            .field("records", &self.records)
+            .field("columns", &Columns(self))
+            .field("root", &self.root)
+            .field("keys", &self.keys)
+            .field("uniques", &self.uniques)
+            .field("meta", &self.meta)
+            .field("blooms", &self.blooms)
+            .field("bitmaps", &self.bitmaps)
+            .field("sketches", &self.sketches)
+            .field("listeners", &self.listeners)
+            .field("closed", &self.closed)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Table {
+    pub fn new(
+        id: TableId,
+        config: TableConfig,
+        name_mapping: Option<IndexMap<InternalString, usize>>,
+    ) -> Result<Self, TableError> {
+        let (table, _) = Self::_new(
+            id,
+            config,
+            name_mapping,
+            InternalPath::default(),
+            &OpenOptions::default(),
+        )?;
+
+        Ok(table)
+    }
+
+    /// Opens the table with relative persistance paths resolved against the database `root`, so
+    /// the root can be moved between runs.
+    pub fn new_in(
+        id: TableId,
+        config: TableConfig,
+        name_mapping: Option<IndexMap<InternalString, usize>>,
+        root: impl AsRef<Path>,
+    ) -> Result<Self, TableError> {
+        let (table, _) = Self::_new(
+            id,
+            config,
+            name_mapping,
+            InternalPath::new(root)?,
+            &OpenOptions::default(),
+        )?;
+
+        Ok(table)
+    }
+
+    fn _new(
+        id: TableId,
+        config: TableConfig,
+        name_mapping: Option<IndexMap<InternalString, usize>>,
+        root: InternalPath,
+        options: &OpenOptions,
+    ) -> Result<(Self, OpenReport)> {
+        let config = match name_mapping.as_ref() {
+            Some(names) => config.with_column_names(names),
+            None => config,
+        };
+        let columns = IndexMap::with_capacity(config.columns.len());
+        // records hold a cell for every column, which needn't be at its position
+        let column_count = config.columns.cell_span();
+        let records_config = Some(config.records_store_config()?);
+        let table_file = config._open_table_file((!root.is_empty()).then_some(root.as_path()))?;
+
+        let records = match table_file.as_ref() {
+            Some(file) => Records::new_in_region(
+                Some(id),
+                records_config,
+                column_count,
+                file.region(RECORDS_REGION),
+            )?,
+            None if root.is_empty() => Records::new(Some(id), records_config, column_count)?,
+            None => Records::new_in(Some(id), records_config, column_count, root)?,
+        };
+
+        let mut this = Self {
+            id,
+            config,
+            records,
+            columns: SharedObject::new(columns),
+            columns_by_name: name_mapping.unwrap_or_default(),
+            table_file,
+            root,
+            keys: SharedObject::new(KeyIndex::new()),
+            uniques: SharedObject::new(IndexMap::new()),
+            meta: SharedObject::new(None),
+            blooms: SharedObject::new(IndexMap::new()),
+            bitmaps: SharedObject::new(IndexMap::new()),
+            sketches: SharedObject::new(IndexMap::new()),
+            write_gate: SharedObject::new(()),
+            swap_gate: SharedObject::new(()),
+            rebuilds: Arc::new(Mutex::new(())),
+            sequences: SharedObject::new(IndexMap::new()),
+            listeners: Listeners::default(),
+            closed: Arc::new(AtomicBool::new(false)),
+            cancel: CancellationToken::default(),
+            ops: Arc::new(Mutex::new(None)),
+            expiry: None,
+            wal: config.wal.map(WalWriter::open).transpose()?.map(Arc::new),
+            io_error_sink: SharedObject::new(None),
+            unavailable: SharedObject::new(IndexMap::new()),
+            unavailable_as_empty: options.unavailable_as_empty,
+            rate_limiter: SharedObject::new(None),
+            throttle_events: Arc::new(AtomicU64::new(0)),
+        };
+
+        // before anything opens a column store, which would create a missing one empty
+        let report = this._find_missing_columns(options)?;
+
+        this.expiry = this._load_expiry()?;
+        this._rebuild_keys()?;
+        this._build_uniques()?;
+        this._build_blooms()?;
+        this._build_bitmaps()?;
+        this._load_sketches()?;
+
+        Ok((this, report))
+    }
+
+    /// Indexes the rows already in the table by their primary key. This is also where a key
+    /// declared on a table with existing rows gets checked against them.
+    fn _rebuild_keys(&self) -> Result<()> {
+        if self.config.primary_key.is_empty() {
+            return Ok(());
+        }
+
+        let mut keys = self.keys.write();
+
+        for (seq, handle) in self.records.scan_since(0) {
+            // an expired row's key is free for a new row to take
+            if self._expired(seq) {
+                continue;
+            }
+
+            let (values, _) = self.get_versioned(&handle)?;
+            let key = self
+                .config
+                .primary_key
+                .key_of(&values)
+                .map_err(|e| e.context(format!("row {} has no primary key", seq)))?;
+
+            if keys.contains_key(&key) {
+                anyhow::bail!("table contains more than one row with primary key {}", key);
+            }
+
+            keys.insert(key, handle);
+        }
+
+        Ok(())
+    }
+
+    /// The key of a new row, as long as no other row has it already.
+    fn _unclaimed_key(
+        &self,
+        keys: &KeyIndex,
+        values: &[Option<DataValue>],
+    ) -> Result<CompositeKey> {
+        let key = self.config.primary_key.key_of(values)?;
+
+        match keys.get(&key) {
+            Some(holder) if !self._handle_expired(holder) => {
+                Err(TableError::DuplicateKey(key).into())
+            }
+            _ => Ok(key),
+        }
+    }
+
+    /// Looks up a row by the values of every primary key column.
+    pub fn find_by_pk(&self, key: &[DataValue]) -> Result<Option<RecordHandle>, TableError> {
+        let primary_key = self.config.primary_key;
+
+        if primary_key.is_empty() {
+            return Err(TableError::invalid("table has no primary key"));
+        } else if key.len() != primary_key.len() {
+            return Err(TableError::invalid(format!(
+                "expected {} primary key values but got {}",
+                primary_key.len(),
+                key.len()
+            )));
+        }
+
+        Ok(self
+            .keys
+            .read()
+            .get(&CompositeKey(key.to_vec()))
+            .filter(|handle| !self._handle_expired(handle))
+            .cloned())
+    }
+
+    /// Every row whose primary key falls in `range`, in key order.
+    pub fn scan_pk_range(
+        &self,
+        range: impl RangeBounds<CompositeKey>,
+    ) -> Vec<(CompositeKey, RecordHandle)> {
+        self.keys
+            .read()
+            .range(range)
+            .filter(|(_, handle)| !self._handle_expired(handle))
+            .map(|(key, handle)| (key.clone(), handle.clone()))
+            .collect()
+    }
+
+    /// Every row whose primary key starts with `prefix`, in key order.
+    pub fn scan_pk_prefix(
+        &self,
+        prefix: &[DataValue],
+    ) -> Result<Vec<(CompositeKey, RecordHandle)>, TableError> {
+        if prefix.len() > self.config.primary_key.len() {
+            return Err(TableError::invalid(format!(
+                "prefix has {} values but the primary key only has {} columns",
+                prefix.len(),
+                self.config.primary_key.len()
+            )));
+        }
+
+        Ok(self
+            .keys
+            .read()
+            .range(CompositeKey(prefix.to_vec())..)
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .filter(|(_, handle)| !self._handle_expired(handle))
+            .map(|(key, handle)| (key.clone(), handle.clone()))
+            .collect())
+    }
+
+    pub fn config(&self) -> &TableConfig {
         &self.config
     }
 
-    pub fn get_column_store(&self, idx: usize) -> Result<Store<DataValue>> {
-        if idx >= self.config.columns.len() {
-            anyhow::bail!("column index out of bounds");
+    /// The table's key-value annotations. They live with the table, so they are persisted next to
+    /// its stores and go away with it.
+    pub fn meta(&self) -> Result<MetaTable, TableError> {
+        if let Some(meta) = self.meta.read().as_ref() {
+            return Ok(meta.clone());
         }
 
-        let columns = self.columns.upgradable();
+        let mut slot = self.meta.write();
 
-        if let Some(store) = columns.get(&idx) {
-            return Ok(store.clone());
+        if let Some(meta) = slot.as_ref() {
+            return Ok(meta.clone());
         }
 
-        let store = Store::new(
-            Some(self.id),
-            Some(unsafe {
-                self.config
-                    .columns
-                    .get_unchecked(idx)
-                    .into_store_config(&self.config)
-            }),
+        let meta = MetaTable::open(self.id, &self.config, self.root)?;
+        meta.table()
+            .set_io_error_sink(self.io_error_sink.read().clone());
+        *slot = Some(meta.clone());
+
+        Ok(meta)
+    }
+
+    /// Sends the failed flushes and writes of every store of the table to `sink`, those of the
+    /// stores it opens later and of its annotations included. See `Store::set_io_error_sink`.
+    pub fn set_io_error_sink(&self, sink: Option<Sender<IoIncident>>) {
+        *self.io_error_sink.write() = sink.clone();
+        self.records.set_io_error_sink(sink.clone());
+
+        for store in self.columns.read().values() {
+            store.set_io_error_sink(sink.clone());
+        }
+
+        if let Some(meta) = self.meta.read().as_ref() {
+            meta.table().set_io_error_sink(sink);
+        }
+    }
+
+    /// The flushes and writes of the table's stores that failed since it was opened, those of
+    /// its annotations included. Counted whether or not the table has a sink for them.
+    pub fn flush_failures(&self) -> u64 {
+        let columns = self
+            .columns
+            .read()
+            .values()
+            .map(Store::flush_failures)
+            .sum::<u64>();
+        let meta = self
+            .meta
+            .read()
+            .as_ref()
+            .map_or(0, |meta| meta.table().flush_failures());
+
+        self.records.flush_failures() + columns + meta
+    }
+
+    /// Whether the last flush of a block of any of the table's stores failed, so changes to it
+    /// may not be in its file. A flush that goes through clears it.
+    pub fn has_failed_flush(&self) -> bool {
+        let failed = |store: &Store<_>| !store.failed_blocks().is_empty();
+
+        self.records.has_failed_flush()
+            || self.columns.read().values().any(failed)
+            || self
+                .meta
+                .read()
+                .as_ref()
+                .is_some_and(|meta| meta.table().has_failed_flush())
+    }
+
+    /// Flushes the record store, every column store opened so far and the annotations, with the
+    /// cardinality sketches that changed written to them first.
+    pub fn flush_all(&self) -> Result<(), TableError> {
+        self._save_sketches()?;
+        self.records.sync_all()?;
+
+        if let Some(meta) = self.meta.read().as_ref() {
+            meta.table()
+                .flush_all()
+                .context("failed to flush table meta")?;
+        }
+
+        let columns = self.columns.read();
+
+        for column in 0..self.config.columns.len() {
+            if let Some(store) = columns.get(&column) {
+                store
+                    .sync_all()
+                    .map_err(|e| e.context(format!("failed to flush column {}", column)))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Opens every store the table would otherwise open on first use, those of its annotations
+    /// included. The handle then keeps working through the files it holds if they're moved, which
+    /// it couldn't if it still had to open one where its config says it is.
+    pub fn open_all_stores(&self) -> Result<(), TableError> {
+        self._ensure_open()?;
+        self._column_stores(
+            &(0..self.config.columns.len())
+                .filter(|column| !self._is_unavailable(*column))
+                .collect::<Vec<_>>(),
         )?;
 
-        let mut columns = columns.upgrade();
+        let meta = self.meta()?;
+        let meta = meta.table();
+        meta._column_stores(&(0..meta.config.columns.len()).collect::<Vec<_>>())?;
+
+        Ok(())
+    }
+
+    /// Flushes the table and rejects any writes made afterwards. Its store files are unlocked, so
+    /// the table can be opened again while this handle is still around. Closing a table that is
+    /// already closed does nothing.
+    pub fn close(&self) -> Result<(), TableError> {
+        if self.closed.swap(true, Ordering::AcqRel) {
+            return Ok(());
+        }
+
+        // the sketches are written to the annotations, which are closed first
+        self._save_sketches()?;
+
+        if let Some(meta) = self.meta.read().as_ref() {
+            meta.table().close()?;
+        }
+
+        if let Some(wal) = self.wal.as_ref() {
+            wal.close()?;
+        }
+
+        self.flush_all()?;
+        self.records.release_lock()?;
+
+        for store in self.columns.read().values() {
+            store.release_lock()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Acquire)
+    }
+
+    fn _ensure_open(&self) -> Result<(), TableError> {
+        if self.is_closed() {
+            return Err(TableError::Closed);
+        }
+
+        Ok(())
+    }
+
+    /// The sequence number of the most recently inserted record, or `0` for an empty table.
+    pub fn current_seq(&self) -> u64 {
+        self.records.current_seq()
+    }
+
+    /// Every live record inserted after `seq`, in insertion order. Passing the result of an earlier
+    /// `current_seq` call yields only the records inserted since.
+    pub fn scan_since(&self, seq: u64) -> impl Iterator<Item = (u64, RecordHandle)> + '_ {
+        self.records
+            .scan_since(seq)
+            .filter(|(seq, _)| !self._expired(*seq))
+    }
+
+    /// The blocks of the table's rows, in order, for a scan reading them a block at a time with
+    /// `rows_in_block` instead of listing every row up front like `scan_since` does.
+    pub fn scan_row_blocks(&self) -> SequentialBlocks<ColumnIndices> {
+        self.records.scan_blocks(self.config.scan)
+    }
+
+    /// The live rows of a block from `scan_row_blocks` with their sequence numbers, in slot order,
+    /// which is insertion order until removed rows have their slots reused. `rows` is cleared
+    /// first, so one buffer can be used for every block.
+    pub fn rows_in_block(&self, block: &Block<ColumnIndices>, rows: &mut Vec<(u64, RecordHandle)>) {
+        rows.clear();
+        Records::live_in_block(block, rows);
+        rows.retain(|(seq, _)| !self._expired(*seq));
+    }
+
+    /// The number of live rows, without scanning.
+    pub fn row_count(&self) -> usize {
+        self.records.row_count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    pub fn contains(&self, record: RecordId) -> bool {
+        self.records.contains(record)
+    }
+
+    /// Removes a row along with its column values. Returns `false` if the row was already gone.
+    pub fn delete(&self, handle: RecordHandle) -> Result<bool, TableError> {
+        Ok(self._delete(handle, false)?.is_some())
+    }
+
+    /// Removes the row `record` along with its column values, like `delete`, and returns the
+    /// values it had, `None` for the columns it had none in.
+    pub fn delete_one(&self, record: RecordId) -> Result<Vec<Option<DataValue>>, TableError> {
+        let not_found = || TableError::not_found(format!("record {}", record));
+        let handle = self
+            .records
+            .get(record)
+            .filter(|handle| !self._handle_expired(handle))
+            .ok_or_else(not_found)?;
+
+        self._delete(handle, true)?.ok_or_else(not_found)
+    }
+
+    /// Removes the rows `records` with `delete_one`, going on past the ones that fail. Rows are
+    /// given by their position in `records`.
+    pub fn delete_many(&self, records: impl IntoIterator<Item = RecordId>) -> DeleteState {
+        let mut deleted = Vec::new();
+        let mut errors = Vec::new();
+
+        for (idx, record) in records.into_iter().enumerate() {
+            match self.delete_one(record) {
+                Ok(values) => deleted.push((idx, values)),
+                Err(err) => errors.push((idx, err)),
+            }
+        }
+
+        if errors.is_empty() {
+            DeleteState::Done(deleted.into_iter().map(|(_, values)| values).collect())
+        } else {
+            DeleteState::Partial { deleted, errors }
+        }
+    }
+
+    /// Removes a row, returning `None` if it was already gone, or the values it had. With
+    /// `with_values` they're always read, and a row that can't be read isn't removed. Without, they
+    /// may come back empty.
+    fn _delete(
+        &self,
+        handle: RecordHandle,
+        with_values: bool,
+    ) -> Result<Option<Vec<Option<DataValue>>>, TableError> {
+        self._ensure_open()?;
+        let _writes = self.write_gate.read();
+
+        let mut keys = (!self.config.primary_key.is_empty()).then(|| self.keys.write());
+        let mut uniques = self._has_unique().then(|| self.uniques.write());
+        let notify = !self.listeners.is_empty();
+
+        let values = match (with_values, keys.is_some() || uniques.is_some() || notify) {
+            (true, _) => Some(self._get_versioned(&handle, true)?.0),
+            (false, true) => self
+                ._get_versioned(&handle, true)
+                .ok()
+                .map(|(values, _)| values),
+            (false, false) => None,
+        };
+
+        let key = match keys {
+            Some(_) => values
+                .as_ref()
+                .and_then(|values| self.config.primary_key.key_of(values).ok()),
+            None => None,
+        };
+
+        let record = self.records.record_id(&handle);
+
+        if !self._remove_row(handle.clone())? {
+            return Ok(None);
+        }
+
+        // an expired row's key may have been taken by a row inserted since
+        if let (Some(keys), Some(key)) = (keys.as_mut(), key) {
+            if keys
+                .get(&key)
+                .is_some_and(|held| self.records.record_id(held) == record)
+            {
+                keys.remove(&key);
+            }
+        }
+
+        if let (Some(uniques), Some(values)) = (uniques.as_mut(), values.as_ref()) {
+            self._unique_release(uniques, record, present(values));
+        }
+
+        let values = match (notify, values) {
+            (true, Some(values)) => {
+                drop(keys);
+                drop(uniques);
+
+                let returned = match with_values {
+                    true => values.clone(),
+                    false => Vec::new(),
+                };
+
+                self.listeners.notify([Change::Deleted { handle, values }]);
+                returned
+            }
+            (_, values) => values.unwrap_or_default(),
+        };
+
+        Ok(Some(values))
+    }
+
+    /// Removes every row at once, leaving the schema, annotations and sequences of the table as
+    /// they are. Writes are held off meanwhile. The records go before the column values, and a row
+    /// is read under the lock of its record, so a read that started before either sees the whole
+    /// row or fails the way it would for a deleted one. Listeners get a single
+    /// `Change::Truncated`. Returns the number of rows removed.
+    pub fn truncate(&self) -> Result<usize, TableError> {
+        self._ensure_open()?;
+        let writes = self.write_gate.write();
+
+        // the key index holds handles, which would keep the record blocks being dropped mapped
+        self.keys.write().clear();
+
+        for index in self.uniques.write().values_mut() {
+            index.clear();
+        }
+
+        let rows = self.records.truncate()?;
+
+        // a column store that hasn't been opened yet may still have values on disk
+        for column in 0..self.config.columns.len() {
+            if !self._is_unavailable(column) {
+                self.get_column_store(column)?.truncate()?;
+            }
+        }
+
+        for blooms in self.blooms.write().values_mut() {
+            blooms.clear();
+        }
+
+        self._clear_bitmaps();
+        self._clear_sketches();
+        drop(writes);
+        self.listeners.notify([Change::Truncated { rows }]);
+
+        Ok(rows)
+    }
+
+    /// Removes a record and every column value its indices point at, without touching the key
+    /// index.
+    fn _remove_row(&self, handle: RecordHandle) -> Result<bool> {
+        let record = self.records.record_id(&handle);
+        let Some(columns) = self.records.remove(handle)? else {
+            return Ok(false);
+        };
+
+        for column in 0..self.config.columns.len() {
+            // the values of an unavailable column are gone already
+            if let (Some(cell), false) = (
+                columns.get(self._cell(column)),
+                self._is_unavailable(column),
+            ) {
+                let handle = self._column_handle(column, cell)?;
+                let block = handle.block.clone();
+
+                self.get_column_store(column)?.remove(handle);
+                self._bloom_removed(column, &block);
+            }
+        }
+
+        self._bitmap_removed(record);
+
+        Ok(true)
+    }
+
+    /// Undoes the column writes of a row that failed part way, along with the row itself. Goes by
+    /// the handles written rather than the row's indices, which may not have been updated yet.
+    fn _remove_written(
+        &self,
+        record_handle: RecordHandle,
+        written: Vec<(usize, SlotHandle<DataValue>)>,
+    ) {
+        for (column, handle) in written {
+            if let Ok(store) = self.get_column_store(column) {
+                let block = handle.block.clone();
+
+                store.remove(handle);
+                self._bloom_removed(column, &block);
+            }
+        }
+
+        self._bitmap_removed(self.records.record_id(&record_handle));
+        let _ = self.records.remove(record_handle);
+    }
+
+    pub fn get_by_seq(&self, seq: u64) -> Option<RecordHandle> {
+        self.records.get_by_seq(seq).filter(|_| !self._expired(seq))
+    }
+
+    /// Looks a live row up by its seq, or by its record id as `RecordId` prints it.
+    pub fn find_row(&self, id: &str) -> Option<RecordHandle> {
+        if let Ok(seq) = id.parse() {
+            return self.get_by_seq(seq);
+        }
+
+        let mut found = None;
+
+        self.records.foreach_live(|handle, _| {
+            if found.is_none()
+                && !self._handle_expired(&handle)
+                && self.records.record_id(&handle).to_string() == id
+            {
+                found = Some(handle);
+            }
+        });
+
+        found
+    }
+
+    /// The sequence number a record was inserted with, the inverse of `get_by_seq`.
+    pub fn seq_of(&self, handle: &RecordHandle) -> Result<u64, TableError> {
+        let seq = handle.read_with(|data| {
+            Ok(data
+                .data()
+                .ok_or_else(|| TableError::not_found("record"))?
+                .seq())
+        })?;
+
+        Ok(seq)
+    }
+
+    /// Reads every column of a record along with the record's generation, which can be handed to
+    /// `update_if` to make sure nothing changed in between.
+    pub fn get_versioned(
+        &self,
+        handle: &RecordHandle,
+    ) -> Result<(Vec<Option<DataValue>>, O64), TableError> {
+        Ok(self._get_versioned(handle, false)?)
+    }
+
+    /// Reads every column of the row `record`, `None` for the columns it has no value in. `None`
+    /// for a record that isn't there, or no longer is.
+    pub fn get_row(&self, record: RecordId) -> Result<Option<Vec<Option<DataValue>>>, TableError> {
+        let Some(handle) = self.records.get(record) else {
+            return Ok(None);
+        };
+
+        match self
+            ._get_versioned(&handle, false)
+            .map_err(TableError::from)
+        {
+            Ok((values, _)) => Ok(Some(values)),
+            // removed or expired since it was looked up
+            Err(TableError::NotFound(_) | TableError::Stale(_)) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Calls `f` with the id and values of every row, in insertion order, see `Store::scan`. The
+    /// blocks of persisted stores that aren't loaded yet are loaded first. Nothing is locked while
+    /// `f` runs, so it can read the table, and a row removed before it's reached is left out. Stops
+    /// at the first error `f` returns.
+    pub fn scan<F>(&self, mut f: F) -> Result<(), TableError>
+    where
+        F: FnMut(RecordId, Vec<Option<DataValue>>) -> Result<()>,
+    {
+        self.records.load(..)?;
+
+        for column in 0..self.config.columns.len() {
+            if !self._is_unavailable(column) {
+                self.get_column_store(column)?.load(..)?;
+            }
+        }
+
+        for (_, handle) in self.scan_since(0) {
+            let values = match self
+                ._get_versioned(&handle, false)
+                .map_err(TableError::from)
+            {
+                Ok((values, _)) => values,
+                Err(TableError::NotFound(_) | TableError::Stale(_)) => continue,
+                Err(err) => return Err(err),
+            };
+
+            f(self.records.record_id(&handle), values)?;
+        }
+
+        Ok(())
+    }
+
+    /// Reads one column of a record, without reading the others.
+    pub fn get_value(
+        &self,
+        handle: &RecordHandle,
+        column: usize,
+    ) -> Result<Option<DataValue>, TableError> {
+        if column >= self.config.columns.len() {
+            return Err(TableError::not_found(format!("column {}", column)));
+        }
+
+        let _swap = self.swap_gate.read_recursive();
+        let value = handle.read_with(|data| {
+            let columns = data
+                .data()
+                .filter(|columns| !self._expired(columns.seq()))
+                .ok_or_else(|| TableError::not_found("record"))?;
+
+            match columns.get(self._cell(column)) {
+                Some(cell) => self._read_value(column, cell),
+                None => Ok(None),
+            }
+        })?;
+
+        Ok(value)
+    }
+
+    /// Like `get_versioned`, reading an expired row as well if `expired` is set.
+    fn _get_versioned(
+        &self,
+        handle: &RecordHandle,
+        expired: bool,
+    ) -> Result<(Vec<Option<DataValue>>, O64)> {
+        let _swap = self.swap_gate.read_recursive();
+
+        handle.read_with(|data| {
+            let columns = data
+                .data()
+                .filter(|columns| expired || !self._expired(columns.seq()))
+                .ok_or_else(|| TableError::not_found("record"))?;
+
+            let mut values = Vec::with_capacity(self.config.columns.len());
+
+            for column in 0..self.config.columns.len() {
+                values.push(match columns.get(self._cell(column)) {
+                    Some(cell) => self._read_value(column, cell)?,
+                    None => None,
+                });
+            }
+
+            Ok((values, columns.gen()))
+        })
+    }
+
+    /// Replaces every column of a record, but only if its generation still matches `expected`.
+    /// Missing trailing values clear their columns. The check and the write happen under the record
+    /// slot's write lock, so of two updates made against the same generation only one applies.
+    /// Values are normalized, fitted and checked with `validate_row`, as an insert's are.
+    pub fn update_if(
+        &self,
+        handle: &RecordHandle,
+        expected: O64,
+        mut values: Vec<Option<DataValue>>,
+    ) -> Result<UpdateOutcome, TableError> {
+        self._ensure_open()?;
+        self._normalize(&mut values);
+        self._fit_overflow(&mut values)?;
+        let _writes = self.write_gate.read();
+
+        let column_count = self.config.columns.len();
+
+        self.validate_row(&values)?;
+        self._check_update(handle, &values)?;
+
+        let record = self.records.record_id(handle);
+
+        // writes to keyed tables are serialized by the key index, so the current key can't change
+        // between reading it here and the write below
+        let mut keys = (!self.config.primary_key.is_empty()).then(|| self.keys.write());
+        let mut uniques = self._has_unique().then(|| self.uniques.write());
+
+        // a row read at any other generation would make the update below a conflict anyway, and
+        // listeners must get the row the update replaced
+        let current = match keys.is_some() || uniques.is_some() || !self.listeners.is_empty() {
+            true => match self.get_versioned(handle)? {
+                (current, gen) if gen == expected => Some(current),
+                (_, gen) => return Ok(UpdateOutcome::Conflict(gen)),
+            },
+            false => None,
+        };
+        let new_values = (!self.listeners.is_empty()).then(|| self._full_row(values.clone()));
+
+        let rekey = match (keys.as_deref(), current.as_ref()) {
+            (Some(keys), Some(current)) => {
+                let old = self.config.primary_key.key_of(current)?;
+                let new = self.config.primary_key.key_of(&values)?;
+
+                if old == new {
+                    None
+                } else if keys.contains_key(&new) {
+                    return Err(TableError::DuplicateKey(new));
+                } else {
+                    Some((old, new))
+                }
+            }
+            _ => None,
+        };
+
+        let claimed = match uniques.as_deref() {
+            Some(uniques) => match self._unique_violation(uniques, Some(record), &values) {
+                Some(violation) => return Err(violation.into()),
+                None => Some(values.clone()),
+            },
+            None => None,
+        };
+
+        let mut written = Vec::new();
+        let outcome = handle.write_with(|mut data| {
+            data.update(|columns: &mut ColumnIndices| {
+                if columns.gen() != expected {
+                    return Ok(UpdateOutcome::Conflict(columns.gen()));
+                }
+
+                // everything that can fail happens before the row changes: the current cells are
+                // resolved and the columns that had no value get their slots, so a store that
+                // fails leaves the row, its generation and the indexes as they were
+                let mut current = Vec::new();
+
+                for column in 0..column_count {
+                    if let Some(cell) = columns.get(self._cell(column)) {
+                        current.push((column, self._column_handle(column, cell)?));
+                    }
+                }
+
+                for (column, value) in values.iter().enumerate() {
+                    if let (None, Some(value)) = (columns.get(self._cell(column)), value) {
+                        let data_handle = self
+                            .get_column_store(column)?
+                            .insert_one(Some(record), value.clone())
+                            .map_err(StoreError::thread_safe)?;
+
+                        written.push((column, data_handle));
+                    }
+                }
+
+                // column slots are keyed by their record within a block, so a value that replaces
+                // another is written over it; column handles are thin, which leaves nothing to fail
+                for (column, data_handle) in current {
+                    match values.get(column).cloned().flatten() {
+                        Some(value) => {
+                            let probe = self._bloom_probe(column, &value);
+                            self._sketch_insert(column, &value);
+                            self._bitmap_set(column, record, Some(&value));
+
+                            data_handle.write_with(|mut data| {
+                                data.update(|current| {
+                                    *current = value;
+                                    Ok(())
+                                })
+                            })?;
+
+                            // the old value stays in the filter until the block is rebuilt
+                            self._bloom_insert(column, &data_handle, probe);
+                            self._bloom_removed(column, &data_handle.block);
+                        }
+                        None => {
+                            columns.take(self._cell(column));
+
+                            let block = data_handle.block.clone();
+
+                            self.get_column_store(column)?.remove(data_handle);
+                            self._bloom_removed(column, &block);
+                            self._bitmap_set(column, record, None);
+                        }
+                    }
+                }
+
+                for (column, data_handle) in written.iter() {
+                    let Some(value) = &values[*column] else {
+                        continue;
+                    };
+
+                    let probe = self._bloom_probe(*column, value);
+                    self._sketch_insert(*column, value);
+                    self._bitmap_set(*column, record, Some(value));
+                    self._bloom_insert(*column, data_handle, probe);
+                    columns.replace(self._cell(*column), data_handle.clone().into())?;
+                }
+
+                let gen = columns.bump_gen();
+
+                Ok(UpdateOutcome::Updated(gen))
+            })
+        });
+
+        let outcome = match outcome {
+            Ok(outcome) => outcome,
+            Err(error) => {
+                for (column, data_handle) in written {
+                    if let Ok(store) = self.get_column_store(column) {
+                        store.remove(data_handle);
+                    }
+                }
+
+                return Err(error.into());
+            }
+        };
+
+        if let (Some(keys), Some((old, new)), UpdateOutcome::Updated(_)) =
+            (keys.as_mut(), rekey, outcome)
+        {
+            keys.remove(&old);
+            keys.insert(new, handle.clone());
+        }
+
+        if let (Some(uniques), Some(old), Some(new), UpdateOutcome::Updated(_)) =
+            (uniques.as_mut(), current.as_ref(), claimed, outcome)
+        {
+            self._unique_release(uniques, record, present(old));
+            self._unique_claim(uniques, record, &new);
+        }
+
+        if let (Some(old), Some(new), UpdateOutcome::Updated(_)) = (current, new_values, &outcome) {
+            drop(keys);
+            drop(uniques);
+            self.listeners.notify([Change::Updated {
+                handle: handle.clone(),
+                old,
+                new,
+            }]);
+        }
+
+        Ok(outcome)
+    }
+
+    /// Sets the columns in `changes` of the row `record`, `None` clearing one, and leaves its other
+    /// columns as they are. Returns the values the changed columns had, in the order of `changes`.
+    /// Values are checked as `update_if` checks them, so one that doesn't fit fails the update
+    /// before any of them is written. A row changed by someone else meanwhile has the changes
+    /// applied again to what it holds now, up to `UPDATE_ATTEMPTS` times in all, after which the
+    /// update fails with `UpdateConflict`.
+    pub fn update_one(
+        &self,
+        record: RecordId,
+        changes: Vec<(usize, Option<DataValue>)>,
+    ) -> Result<Vec<Option<DataValue>>, TableError> {
+        let column_count = self.config.columns.len();
+
+        if let Some((column, _)) = changes.iter().find(|(column, _)| *column >= column_count) {
+            return Err(TableError::not_found(format!("column {}", column)));
+        }
+
+        let handle = self
+            .records
+            .get(record)
+            .filter(|handle| !self._handle_expired(handle))
+            .ok_or_else(|| TableError::not_found(format!("record {}", record)))?;
+
+        for attempt in 0..UPDATE_ATTEMPTS {
+            // the writers it lost to get a growing head start on the next attempt
+            for _ in 0..attempt {
+                std::thread::yield_now();
+            }
+
+            let (mut values, gen) = self.get_versioned(&handle)?;
+            let previous = changes
+                .iter()
+                .map(|(column, _)| values[*column].clone())
+                .collect();
+
+            for (column, value) in changes.iter() {
+                values[*column] = value.clone();
+            }
+
+            #[cfg(test)]
+            tests::race_point(self, &handle);
+
+            if let UpdateOutcome::Updated(_) = self.update_if(&handle, gen, values)? {
+                return Ok(previous);
+            }
+        }
+
+        Err(UpdateConflict {
+            record,
+            attempts: UPDATE_ATTEMPTS,
+        }
+        .into())
+    }
+
+    /// The cell of `column` in the `ColumnIndices` of every row, see `ColumnConfigs::cell`.
+    pub(crate) fn _cell(&self, column: usize) -> usize {
+        self.config.columns.cell(column)
+    }
+
+    fn _column_handle(&self, column: usize, cell: CellIdx) -> Result<SlotHandle<DataValue>> {
+        let block = self
+            .get_column_store(column)?
+            .read()
+            .blocks()
+            .get(&cell.block)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("column block not found"))?;
+
+        // column slots don't carry a generation, so the handle has to stay thin
+        Ok(SlotHandle {
+            block,
+            idx: cell.row.into_downgraded(),
+        })
+    }
+
+    /// # Safety
+    ///
+    /// `idx` has to be in bounds of the table's columns.
+    unsafe fn _open_column_store(&self, idx: usize) -> Result<Store<DataValue>> {
+        if let Some(unavailable) = self._unavailable(idx) {
+            return Err(unavailable.into());
+        }
+
+        let config = self
+            .config
+            .columns
+            .get_unchecked(idx)
+            .into_store_config(&self.config, idx)?;
+
+        let store = if let Some(file) = self.table_file.as_ref() {
+            let region = file.region(column_region(self._cell(idx)));
+
+            Store::new_in_region(Some(self.id), Some(config), region)
+        } else if self.root.is_empty() {
+            Store::new(Some(self.id), Some(config))
+        } else {
+            Store::new_in(Some(self.id), Some(config), self.root)
+        }?;
+
+        store.set_io_error_sink(self.io_error_sink.read().clone());
+
+        Ok(store)
+    }
+
+    pub fn get_column_store(&self, idx: usize) -> Result<Store<DataValue>, TableError> {
+        if idx >= self.config.columns.len() {
+            return Err(TableError::not_found(format!("column {}", idx)));
+        }
+
+        let columns = self.columns.upgradable();
+
+        if let Some(store) = columns.get(&idx) {
+            return Ok(store.clone());
+        }
+
+        let store = unsafe { self._open_column_store(idx)? };
+
+        let mut columns = columns.upgrade();
+
+        columns.insert_sorted(idx, store.clone());
+
+        Ok(store)
+    }
+
+    pub fn get_column_by_name(&self, name: impl AsRef<str>) -> Option<Store<DataValue>> {
+        let name = InternalString::new(name.as_ref()).ok()?;
+        let idx = *self.columns_by_name.get(&name)?;
+
+        self.get_column_store(idx).ok()
+    }
+
+    /// The index of the column called `name`, if the table has one. Columns of tables opened
+    /// without names go by `column {idx}`, as in `TableSchemaRef::column_name`.
+    pub fn column_index(&self, name: impl AsRef<str>) -> Option<usize> {
+        let name = name.as_ref();
+        let schema = self.schema();
+
+        (0..schema.len()).find(|column| schema.column_name(*column) == name)
+    }
+
+    /// The id of `column`, which it keeps as the columns around it are added and dropped, so saved
+    /// queries and locators can go by it rather than by its position.
+    pub fn column_id(&self, column: usize) -> Option<O16> {
+        self.config.columns.id(column)
+    }
+
+    /// Where the column with `id` is now, see `column_id`.
+    pub fn column_position(&self, id: O16) -> Option<usize> {
+        self.config.columns.position(id)
+    }
+
+    /// The stores of `indices`, in the order given. An index asked for more than once gets its
+    /// store more than once.
+    pub fn get_column_stores(
+        &self,
+        indices: impl Into<Vec<usize>>,
+    ) -> Result<Vec<Store<DataValue>>, TableError> {
+        let indices: Vec<usize> = indices.into();
+
+        if let Some(idx) = indices
+            .iter()
+            .find(|idx| **idx >= self.config.columns.len())
+        {
+            return Err(TableError::not_found(format!("column {}", idx)));
+        }
+
+        Ok(self._column_stores(&indices)?)
+    }
+
+    pub fn get_column_store_range(
+        &self,
+        indices: impl RangeBounds<usize>,
+    ) -> Result<Vec<Store<DataValue>>, TableError> {
+        let start = match indices.start_bound() {
+            std::ops::Bound::Included(&start) => start,
+            std::ops::Bound::Excluded(&start) => start + 1,
+            std::ops::Bound::Unbounded => 0,
+        };
+
+        let end = match indices.end_bound() {
+            std::ops::Bound::Included(&end) => end + 1,
+            std::ops::Bound::Excluded(&end) => end,
+            std::ops::Bound::Unbounded => self.config.columns.len(),
+        };
+
+        if end > self.config.columns.len() {
+            return Err(TableError::not_found(format!("column {}", end - 1)));
+        }
+
+        Ok(self._column_stores(&(start..end).collect::<Vec<_>>())?)
+    }
+
+    /// The stores of in-bounds `indices` in the order given, opening the ones that aren't yet.
+    fn _column_stores(&self, indices: &[usize]) -> Result<Vec<Store<DataValue>>> {
+        let columns = self.columns.upgradable();
+
+        if indices.iter().all(|idx| columns.contains_key(idx)) {
+            return Ok(indices.iter().map(|idx| columns[idx].clone()).collect());
+        }
+
+        let mut columns = columns.upgrade();
+
+        indices
+            .iter()
+            .map(|idx| {
+                if let Some(store) = columns.get(idx) {
+                    return Ok(store.clone());
+                }
+
+                let store = unsafe { self._open_column_store(*idx)? };
+                columns.insert_sorted(*idx, store.clone());
+
+                Ok(store)
+            })
+            .collect()
+    }
+
+    /// Checks a row against the column types, so a bad row is turned away before any record or
+    /// column slot is allocated for it. Missing trailing values are allowed and left empty.
+    pub fn validate_row(&self, values: &[Option<DataValue>]) -> Result<(), RowValidationError> {
+        let columns = self.config.columns.len();
+
+        if values.len() > columns {
+            return Err(RowValidationError::TooManyValues {
+                expected: columns,
+                actual: values.len(),
+            });
+        }
+
+        for (column, value) in values.iter().enumerate() {
+            let (Some(value), Some(config)) = (value, self.config.columns.get(column)) else {
+                continue;
+            };
+
+            if self._is_unavailable(column) {
+                return Err(RowValidationError::Unavailable {
+                    column,
+                    name: self.schema().column_name(column),
+                });
+            }
+
+            let actual = value.get_type();
+
+            if !config.data_type.accepts(actual) {
+                return Err(RowValidationError::TypeMismatch {
+                    column,
+                    name: self.schema().column_name(column),
+                    expected: config.data_type,
+                    actual,
+                });
+            }
+
+            if let (Some(logical_type), true, DataValue::Text(text)) =
+                (config.logical_type, config.check_logical_type, value)
+            {
+                if !logical_type.check(text.as_str()) {
+                    return Err(RowValidationError::NotLogicalType {
+                        column,
+                        name: self.schema().column_name(column),
+                        logical_type,
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn insert_one(
+        &self,
+        mut values: Vec<Option<DataValue>>,
+    ) -> Result<InsertedRow, TableError> {
+        self._ensure_open()?;
+        self._fill_defaults(&mut values)?;
+        self._normalize(&mut values);
+        self._fit_overflow(&mut values)?;
+        self.validate_row(&values)?;
+        self.throttle_write(1)?;
+        let _writes = self.write_gate.read();
+
+        let mut keys = (!self.config.primary_key.is_empty()).then(|| self.keys.write());
+
+        let key = match keys.as_deref() {
+            Some(keys) => Some(self._unclaimed_key(keys, &values)?),
+            None => None,
+        };
+
+        let mut uniques = self._has_unique().then(|| self.uniques.write());
+
+        if let Some(violation) = uniques
+            .as_deref()
+            .and_then(|uniques| self._unique_violation(uniques, None, &values))
+        {
+            return Err(violation.into());
+        }
+
+        let (record_id, handle) = self._allocate_record()?;
+        let values = self._write_values(record_id, &handle, values)?;
+
+        if let (Some(keys), Some(key)) = (keys.as_mut(), key) {
+            keys.insert(key, handle.clone());
+        }
+
+        if let Some(uniques) = uniques.as_mut() {
+            self._unique_claim(uniques, record_id, &values);
+        }
+
+        drop(keys);
+        drop(uniques);
+
+        if self.wal.is_some() {
+            let record = WalRecord {
+                seq: self.seq_of(&handle)?,
+                values: values.clone(),
+            };
+
+            self._log_inserted(vec![(handle.clone(), record)])?;
+        }
+
+        if !self.listeners.is_empty() {
+            self._notify_inserted(&handle, Some(values.clone()));
+        }
+
+        Ok(InsertedRow {
+            handle,
+            record_id,
+            values,
+        })
+    }
+
+    /// The first phase of `insert_one`, which gives the row its record before any of its values
+    /// are written.
+    fn _allocate_record(&self) -> Result<(RecordId, RecordHandle)> {
+        self._open_expiry_window()?;
+        self.records.insert_one().map_err(StoreError::thread_safe)
+    }
+
+    /// The second phase of `insert_one`, which writes the values of a record from
+    /// `_allocate_record` and returns the row as stored, with every column. If a value can't be
+    /// written, the record is freed along with the values written before it.
+    fn _write_values(
+        &self,
+        record: RecordId,
+        record_handle: &RecordHandle,
+        values: Vec<Option<DataValue>>,
+    ) -> Result<Vec<Option<DataValue>>> {
+        if values.is_empty() {
+            return Ok(self._full_row(values));
+        }
+
+        let stores = match self._write_stores(&values) {
+            Ok(stores) => stores,
+            Err(error) => {
+                self._remove_written(record_handle.clone(), Vec::new());
+                return Err(error);
+            }
+        };
+        let mut written = Vec::with_capacity(values.len());
+
+        let res = record_handle.write_with(|mut data| {
+            data.update(|columns: &mut ColumnIndices| {
+                for (i, value) in values.iter().enumerate() {
+                    if let Some(data) = value {
+                        #[cfg(test)]
+                        tests::fail_point(i);
+
+                        let probe = self._bloom_probe(i, data);
+                        let store = stores.get(i).and_then(Option::as_ref);
+                        let store = store.expect("store exists");
+                        let data_handle = store
+                            .insert_one(Some(record), data.clone())
+                            .map_err(StoreError::thread_safe)?;
+
+                        self._bloom_insert(i, &data_handle, probe);
+                        self._sketch_insert(i, data);
+                        self._bitmap_set(i, record, Some(data));
+                        written.push((i, data_handle.clone()));
+                        columns.replace(self._cell(i), data_handle.into())?;
+                    }
+                }
+
+                Ok(())
+            })
+        });
+
+        if let Err(error) = res {
+            self._remove_written(record_handle.clone(), written);
+            return Err(error);
+        }
+
+        Ok(self._full_row(values))
+    }
+
+    fn _notify_inserted(&self, handle: &RecordHandle, values: Option<Vec<Option<DataValue>>>) {
+        if let Some(values) = values {
+            self.listeners.notify([Change::Inserted {
+                handle: handle.clone(),
+                values,
+            }]);
+        }
+    }
+
+    pub fn insert<I, U>(&self, values: I) -> Result<InsertState, TableError>
+    where
+        I: IntoIterator<Item = U>,
+        U: IntoIterator<Item = Option<DataValue>>,
+    {
+        self._ensure_open()?;
+
+        // the whole batch is paid for up front, without holding off a dump while it waits
+        let values = values.into_iter().collect::<Vec<_>>();
+        self.throttle_write(values.len())?;
+        let _writes = self.write_gate.read();
+
+        let mut all_errors = Vec::new();
+        let mut positions = Vec::new();
+        let mut valid = Vec::new();
+        // rows repeating a text store one buffer for it between them
+        let mut memo = TextMemo::new(TEXT_MEMO_CAPACITY);
+
+        // bad rows never get a record, so there's nothing to roll back for them
+        for (idx, values) in values.into_iter().enumerate() {
+            let mut values = values.into_iter().collect::<Vec<_>>();
+
+            if let Err(error) = self._fill_defaults(&mut values) {
+                all_errors.push((idx, InsertError::DefaultFailed { values, error }));
+                continue;
+            }
+
+            self._normalize(&mut values);
+
+            let valid_row = self
+                ._fit_overflow(&mut values)
+                .and_then(|()| self.validate_row(&values));
+
+            match valid_row {
+                Ok(()) => {
+                    memo.share(&mut values);
+                    positions.push(idx);
+                    valid.push(values);
+                }
+                Err(error) => all_errors.push((idx, InsertError::InvalidRow { values, error })),
+            }
+        }
+
+        self._open_expiry_window()?;
+
+        let records = self
+            .records
+            .insert_map(valid)
+            .map_err(StoreError::thread_safe)?
+            .into_iter()
+            .map(|(idx, record, record_handle, values)| {
+                (positions[idx], record, record_handle, values)
+            })
+            .collect::<Vec<_>>();
+
+        let mut all_handles = Vec::with_capacity(records.len());
+        let mut claimed = Vec::new();
+        let mut claimed_uniques = Vec::new();
+        let mut keys = (!self.config.primary_key.is_empty()).then(|| self.keys.write());
+        let mut uniques = self._has_unique().then(|| self.uniques.write());
+        let notify = !self.listeners.is_empty();
+        let mut inserted = Vec::new();
+        let mut logged = Vec::new();
+
+        // each column store grows once for the whole batch rather than a block at a time
+        let mut per_column = vec![0; self.config.columns.len()];
+
+        for (_, _, _, values) in &records {
+            for (column, value) in values.iter().enumerate() {
+                if value.is_some() {
+                    per_column[column] += 1;
+                }
+            }
+        }
+
+        for (column, count) in per_column.into_iter().enumerate() {
+            if count > 0 {
+                self.get_column_store(column)?.reserve(count)?;
+            }
+        }
+
+        let mut records = records.into_iter();
+
+        while let Some((idx, record, record_handle, values)) = records.next() {
+            let val_count = values.len();
+
+            if let Some(violation) = uniques
+                .as_deref()
+                .and_then(|uniques| self._unique_violation(uniques, None, &values))
+            {
+                let _ = self.records.remove(record_handle);
+                let UniqueViolation {
+                    column,
+                    value,
+                    existing_record,
+                } = violation;
+
+                all_errors.push((
+                    idx,
+                    InsertError::UniqueViolation {
+                        column,
+                        value,
+                        existing_record,
+                    },
+                ));
+                continue;
+            }
+
+            // keys are claimed up front so a later row in the same batch sees them
+            let key = match keys.as_mut() {
+                Some(keys) => match self._unclaimed_key(keys, &values) {
+                    Ok(key) => {
+                        keys.insert(key.clone(), record_handle.clone());
+                        Some(key)
+                    }
+                    Err(error) => {
+                        let _ = self.records.remove(record_handle);
+                        let error = match self.config.primary_key.key_of(&values) {
+                            Ok(key) => InsertError::DuplicateKey { key, values },
+                            Err(_) => InsertError::InvalidKey { values, error },
+                        };
+
+                        all_errors.push((idx, error));
+                        continue;
+                    }
+                },
+                None => None,
+            };
+
+            // as are the values of unique columns
+            let unique_claim = match uniques.as_mut() {
+                Some(uniques) => self._unique_claim(uniques, record, &values),
+                None => Vec::new(),
+            };
+
+            // Empty check
+            if val_count == 0 {
+                if self.wal.is_some() {
+                    let record = WalRecord {
+                        seq: self.seq_of(&record_handle)?,
+                        values: self._full_row(values.clone()),
+                    };
+
+                    logged.push((record_handle.clone(), record));
+                }
+
+                if notify {
+                    inserted.push(Change::Inserted {
+                        handle: record_handle.clone(),
+                        values: self._full_row(values),
+                    });
+                }
+
+                all_handles.push((idx, record_handle, vec![]));
+                continue;
+            }
+
+            let stores = self._write_stores(&values)?;
+            let handle = record_handle.clone();
+            let mut written = Vec::with_capacity(val_count);
+
+            // the written handles are kept out here so a failed or panicked write can be undone
+            let res = handle.write_with(|mut data| {
+                data.update(|columns: &mut ColumnIndices| {
+                    for (column, value) in values.iter().enumerate() {
+                        if let Some(data) = value {
+                            #[cfg(test)]
+                            tests::fail_point(column);
+
+                            let store = stores.get(column).and_then(Option::as_ref);
+                            let store = store.expect("store exists");
+                            // the column is named by the value error, so the label of its store
+                            // is only kept for anything else
+                            let data_insert_res = store.insert_one(Some(record), data.clone());
+                            let label = data_insert_res.as_ref().err().and_then(StoreError::label);
+
+                            match data_insert_res.map_err(StoreError::into_unlabeled) {
+                                Ok(data_handle) => {
+                                    self._bloom_insert(
+                                        column,
+                                        &data_handle,
+                                        self._bloom_probe(column, data),
+                                    );
+                                    self._sketch_insert(column, data);
+                                    self._bitmap_set(column, record, Some(data));
+                                    written.push((column, data_handle.clone()));
+                                    columns.replace(self._cell(column), data_handle.into())?;
+                                }
+                                Err(StoreError::InsertError(
+                                    dbexp::store::result::InsertError::InvalidValue {
+                                        error, ..
+                                    },
+                                )) => {
+                                    let error =
+                                        unsafe { self.config.columns.get_unchecked(column) }
+                                            .value_error(ValueErrorReason::Rejected {
+                                                message: error.to_string(),
+                                            });
+
+                                    return Ok(Some((column, error)));
+                                }
+                                Err(error) => return Err(error.labeled(label).thread_safe()),
+                            }
+                        }
+                    }
+
+                    Ok(None)
+                })
+            });
+
+            let column_handles = || written.iter().map(|(_, handle)| handle.clone()).collect();
+
+            let needs_rollback = match res {
+                Ok(None) => {
+                    if self.wal.is_some() {
+                        let record = WalRecord {
+                            seq: self.seq_of(&record_handle)?,
+                            values: self._full_row(values.clone()),
+                        };
+
+                        logged.push((record_handle.clone(), record));
+                    }
+
+                    if notify {
+                        inserted.push(Change::Inserted {
+                            handle: record_handle.clone(),
+                            values: self._full_row(values),
+                        });
+                    }
+
+                    all_handles.push((idx, record_handle, column_handles()));
+                    None
+                }
+                Ok(Some((column, error))) => {
+                    all_errors.push((
+                        idx,
+                        InsertError::InvalidValue {
+                            record_handle,
+                            column_handles: column_handles(),
+                            column,
+                            values,
+                            error,
+                        },
+                    ));
+
+                    None
+                }
+                Err(error) => {
+                    self._remove_written(record_handle, written);
+                    Some(error)
+                }
+            };
+
+            let failed = matches!(all_errors.last(), Some((i, _)) if *i == idx);
+
+            if let (Some(keys), Some(key)) = (keys.as_mut(), key) {
+                if needs_rollback.is_some() || failed {
+                    keys.remove(&key);
+                } else {
+                    claimed.push(key);
+                }
+            }
+
+            if let Some(uniques) = uniques.as_mut() {
+                if needs_rollback.is_some() || failed {
+                    let values = unique_claim.iter().map(|(column, value)| (*column, value));
+                    self._unique_release(uniques, record, values);
+                } else {
+                    claimed_uniques.push((record, unique_claim));
+                }
+            }
+
+            if let Some(error) = needs_rollback {
+                if let Some(keys) = keys.as_mut() {
+                    for key in claimed.drain(..) {
+                        keys.remove(&key);
+                    }
+                }
+
+                if let Some(uniques) = uniques.as_mut() {
+                    for (record, claim) in claimed_uniques.drain(..) {
+                        let values = claim.iter().map(|(column, value)| (*column, value));
+                        self._unique_release(uniques, record, values);
+                    }
+                }
+
+                // the rows after this one were claimed by `insert_map` but never written
+                for (_, _, record_handle, _) in records {
+                    let _ = self.records.remove(record_handle);
+                }
+
+                while all_handles.len() > 0 || all_errors.len() > 0 {
+                    if let Some((
+                        _,
+                        InsertError::InvalidValue { record_handle, .. }
+                        | InsertError::NoValues { record_handle }
+                        | InsertError::ColumnLengthMismatch { record_handle, .. },
+                    )) = all_errors.pop()
+                    {
+                        let _ = self._remove_row(record_handle);
+                    }
+
+                    if let Some((_, record_handle, _)) = all_handles.pop() {
+                        let _ = self._remove_row(record_handle);
+                    }
+                }
+
+                return Err(error
+                    .context("unexpected error resulted in rollback")
+                    .into());
+            }
+        }
+
+        drop(keys);
+        drop(uniques);
+
+        if let Err(error) = self._log_inserted(logged) {
+            // the rows that failed were never logged, and go with the rest
+            for (_, error) in all_errors {
+                if let InsertError::InvalidValue { record_handle, .. }
+                | InsertError::NoValues { record_handle }
+                | InsertError::ColumnLengthMismatch { record_handle, .. } = error
+                {
+                    let _ = self._remove_row(record_handle);
+                }
+            }
+
+            return Err(error);
+        }
+
+        self.listeners.notify(inserted);
+
+        all_errors.sort_by_key(|(idx, _)| *idx);
+
+        if all_errors.is_empty() {
+            Ok(InsertState::Done(
+                all_handles
+                    .into_iter()
+                    .map(|(_, handle, _)| handle)
+                    .collect(),
+            ))
+        } else {
+            Ok(InsertState::Partial {
+                handles: all_handles,
+                errors: all_errors,
+            })
+        }
+    }
+}
+
+#[allow(dead_code)]
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use dbexp::{
+        slot::CallbackPanicked,
+        store::{CorruptValue, FaultyFileOps, IoOp},
+    };
+    use primitives::{DataType, Text, Timestamp};
+
+    use super::*;
+    use crate::test_util::{assert_rows_eq, fixture_table, RowGen, TempDir};
+
+    thread_local! {
+        static PANIC_ON_COLUMN: std::cell::Cell<Option<usize>> = const { std::cell::Cell::new(None) };
+    }
+
+    thread_local! {
+        static ALLOCATIONS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+        static LIVE_BYTES: std::cell::Cell<isize> = const { std::cell::Cell::new(0) };
+    }
+
+    /// Counts the allocations of each thread, for `allocations`, and the bytes it holds, for
+    /// `live_bytes`.
+    struct CountingAllocator;
+
+    unsafe impl std::alloc::GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: std::alloc::Layout) -> *mut u8 {
+            let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+            let _ = LIVE_BYTES.try_with(|live| live.set(live.get() + layout.size() as isize));
+            std::alloc::System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: std::alloc::Layout) {
+            let _ = LIVE_BYTES.try_with(|live| live.set(live.get() - layout.size() as isize));
+            std::alloc::System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    /// What `f` returns, and how many allocations it made on this thread.
+    fn allocations<R>(f: impl FnOnce() -> R) -> (R, usize) {
+        let before = ALLOCATIONS.get();
+        let res = f();
+
+        (res, ALLOCATIONS.get() - before)
+    }
+
+    /// The bytes allocated on this thread and not freed yet.
+    fn live_bytes() -> isize {
+        LIVE_BYTES.get()
+    }
+
+    thread_local! {
+        static RACED_UPDATES: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+    }
+
+    /// Writes the row `update_one` is about to update, as another writer would, for the next
+    /// `RACED_UPDATES` attempts.
+    pub(super) fn race_point(table: &Table, handle: &RecordHandle) {
+        let races = RACED_UPDATES.get();
+
+        if races > 0 {
+            RACED_UPDATES.set(races - 1);
+
+            let (values, gen) = table.get_versioned(handle).expect("row to race");
+            table.update_if(handle, gen, values).expect("racing update");
+        }
+    }
+
+    /// Panics when a column write reaches the column set with `PANIC_ON_COLUMN`.
+    pub(super) fn fail_point(column: usize) {
+        if PANIC_ON_COLUMN.get() == Some(column) {
+            panic!("fail point hit for column {}", column);
+        }
+    }
+
+    // #[test]
+    // fn test_column_configs() {
+    //
+    // }
+
+    // #[test]
+    // fn test_table_config() {
+    //
+    // }
+
+    #[test]
+    fn test_insert_one() -> Result<()> {
+        let table = fixture_table(&[
+            ("count", DataType::Number),
+            ("flag", DataType::Bool),
+            ("label", DataType::Text(50)),
+        ]);
+
+        let row = vec![
+            Some(DataValue::try_from_any(DataType::Number, 42)?),
+            Some(DataValue::Bool(true)),
+            Some(DataValue::try_from_any(DataType::Text(50), "testing")?),
+        ];
+
+        table.insert_one(row.clone())?;
+        assert_rows_eq(&table, [row]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_insert() -> Result<()> {
+        let table = fixture_table(&[
+            ("count", DataType::Number),
+            ("flag", DataType::Bool),
+            ("label", DataType::Text(50)),
+            ("at", DataType::Timestamp),
+            ("blob", DataType::Bytes(16)),
+        ]);
+
+        let rows = RowGen::for_table(&table, 7).with_nulls(0.2).rows(100);
+
+        assert!(
+            matches!(table.insert(rows.clone())?, InsertState::Done(handles) if handles.len() == 100)
+        );
+        assert_rows_eq(&table, rows);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_text_memo() -> Result<()> {
+        let table = fixture_table(&[("label", DataType::Text(16)), ("count", DataType::Number)]);
+        let config = table.config.columns.get(0).unwrap();
+        let labels = (0..10).map(|n| format!("label {}", n)).collect::<Vec<_>>();
+        let fields = (0..100_000)
+            .map(|n| labels[n % labels.len()].as_str())
+            .collect::<Vec<_>>();
+
+        let (plain, plain_allocs) = allocations(|| {
+            fields
+                .iter()
+                .map(|field| config.try_new_value(field.to_string()))
+                .collect::<Result<Vec<_>, _>>()
+        });
+
+        let mut memo = TextMemo::new(TEXT_MEMO_CAPACITY);
+        let (memoized, memo_allocs) = allocations(|| {
+            fields
+                .iter()
+                .map(|field| memo.try_new_value(0, config, field))
+                .collect::<Result<Vec<_>, _>>()
+        });
+
+        let memoized = memoized?;
+        assert_eq!(plain?, memoized);
+        assert!(
+            plain_allocs >= 10 * memo_allocs,
+            "{} allocations without the memo, {} with it",
+            plain_allocs,
+            memo_allocs
+        );
+
+        let shared = |a: &DataValue, b: &DataValue| match (a, b) {
+            (DataValue::Text(a), DataValue::Text(b)) => a.shares_buffer(b),
+            _ => false,
+        };
+
+        assert!(shared(&memoized[0], &memoized[10]));
+        assert!(!shared(&memoized[0], &memoized[1]));
+
+        // the least recently used text is the one forgotten
+        let mut memo = TextMemo::new(2);
+        let mut convert = |text: &str| memo.try_new_value(0, config, text).unwrap();
+        let a = convert("a");
+        let b = convert("b");
+        assert!(shared(&convert("a"), &a));
+        convert("c");
+        assert!(shared(&convert("a"), &a));
+        assert!(!shared(&convert("b"), &b));
+
+        // rows inserted together store one buffer per distinct text
+        let InsertState::Done(handles) =
+            table.insert_str_rows(fields[..100].iter().map(|field| [Some(*field), Some("7")]))?
+        else {
+            panic!("rows weren't inserted");
+        };
+
+        // reads make a text of their own, so the slots are looked at instead
+        let stored = |handle: &RecordHandle| -> Result<DataValue> {
+            let cell = handle.read_with(|slot| Ok(slot.data().unwrap().get(0).unwrap()))?;
+            table
+                ._column_handle(0, cell)?
+                .read_with(|slot| Ok(slot.data().unwrap().clone()))
+        };
+
+        let first = stored(&handles[0])?;
+        assert_eq!(first, memoized[0]);
+        assert!(shared(&first, &stored(&handles[10])?));
+        assert!(!shared(&first, &stored(&handles[1])?));
+
+        let err = table
+            .insert_str_rows([[Some("label 0"), Some("seven")]])
+            .unwrap_err();
+        assert!(err.to_string().contains("row 0"), "{}", err);
+        assert_eq!(table.row_count(), 100);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_scan_since() -> Result<()> {
+        let columns = vec![DataConfig::new(DataType::Number)];
+
+        let id = TableId::new();
+        let dir = TempDir::new("mem_table_scan_since");
+        let table_config = TableConfig::new_persisted(&columns, &dir)?;
+
+        let checkpoint = {
+            let table = Table::new(id, table_config, None)?;
+
+            for n in 0..3 {
+                table.insert_one(vec![Some(DataValue::try_from_any(
+                    columns[0].data_type,
+                    n,
+                )?)])?;
+            }
+
+            table.current_seq()
+        };
+
+        assert_eq!(checkpoint, 3);
+
+        let table = Table::new(id, table_config, None)?;
+
+        assert_eq!(table.current_seq(), checkpoint);
+
+        for n in 3..5 {
+            table.insert_one(vec![Some(DataValue::try_from_any(
+                columns[0].data_type,
+                n,
+            )?)])?;
+        }
+
+        let found = table.scan_since(checkpoint).collect::<Vec<_>>();
+
+        assert_eq!(
+            found.iter().map(|(seq, _)| *seq).collect::<Vec<_>>(),
+            vec![4, 5]
+        );
+
+        for (seq, handle) in found {
+            let stored = handle.read_with(|slot| Ok(slot.data().map(|indices| indices.seq())))?;
+            assert_eq!(stored, Some(seq));
+        }
+
+        assert_eq!(table.scan_since(0).count(), 5);
+
+        drop(table);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_if() -> Result<()> {
+        let columns = vec![
+            DataConfig::new(DataType::Number),
+            DataConfig::new(DataType::Bool),
+        ];
+
+        let table = Table::new(TableId::new(), TableConfig::new(&columns)?, None)?;
+        let number = |n: i32| DataValue::try_from_any(columns[0].data_type, n);
+
+        let handle = table.insert_one(vec![Some(number(1)?)])?.handle;
+        let (values, gen) = table.get_versioned(&handle)?;
+
+        assert_eq!(values, vec![Some(number(1)?), None]);
+
+        let outcome = table.update_if(&handle, gen, vec![None, Some(DataValue::Bool(true))])?;
+
+        let UpdateOutcome::Updated(new_gen) = outcome else {
+            panic!("expected update to apply, got {:?}", outcome);
+        };
+
+        assert_ne!(new_gen, gen);
+        assert_eq!(
+            table.get_versioned(&handle)?,
+            (vec![None, Some(DataValue::Bool(true))], new_gen)
+        );
+
+        assert_eq!(
+            table.update_if(&handle, gen, vec![Some(number(2)?)])?,
+            UpdateOutcome::Conflict(new_gen)
+        );
+
+        let mismatch = table
+            .update_if(&handle, new_gen, vec![Some(DataValue::Bool(false))])
+            .unwrap_err();
+
+        assert!(matches!(
+            mismatch.downcast_ref::<RowValidationError>(),
+            Some(RowValidationError::TypeMismatch { column: 0, .. })
+        ));
+
+        let found = table.get_by_seq(1).expect("record exists");
+
+        assert_eq!(table.get_versioned(&found)?.1, new_gen);
+        assert!(table.get_by_seq(2).is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_row_count() -> Result<()> {
+        let columns = vec![DataConfig::new(DataType::Number)];
+
+        let id = TableId::new();
+        let dir = TempDir::new("mem_table_row_count");
+        let table_config = TableConfig::new_persisted(&columns, &dir)?;
+
+        let deleted = {
+            let table = Table::new(id, table_config, None)?;
+
+            assert!(table.is_empty());
+
+            let mut handles = Vec::new();
+
+            for n in 0..7 {
+                handles.push(
+                    table
+                        .insert_one(vec![Some(DataValue::try_from_any(
+                            columns[0].data_type,
+                            n,
+                        )?)])?
+                        .handle,
+                );
+            }
+
+            let records = handles
+                .iter()
+                .map(|h| table.records.record_id(h))
+                .collect::<Vec<_>>();
+
+            for n in [1, 4, 5] {
+                assert!(table.delete(handles[n].clone())?);
+            }
+
+            assert!(!table.delete(handles[1].clone())?);
+            assert_eq!(table.row_count(), 4);
+            assert!(table.contains(records[0]));
+            assert!(!table.contains(records[4]));
+            assert!(table.contains(records[6]));
+
+            // freed slots get reused
+            table.insert_one(vec![])?;
+            assert_eq!(table.row_count(), 5);
+
+            table.delete(table.get_by_seq(8).expect("record exists"))?;
+            records
+        };
+
+        let table = Table::new(id, table_config, None)?;
+
+        assert_eq!(table.row_count(), 4);
+        assert_eq!(table.scan_since(0).count(), 4);
+        assert!(table.contains(deleted[0]));
+        assert!(!table.contains(deleted[1]));
+
+        drop(table);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_relative_root() -> Result<()> {
+        let columns = vec![DataConfig::new(DataType::Number)];
+
+        let id = TableId::new();
+        let old_root = TempDir::new("mem_table_root");
+        let new_root = TempDir::new("mem_table_moved");
+        let table_config = TableConfig::new_persisted_relative(&columns, "tables/t")?;
+
+        assert!(table_config.persistance.is_relative());
+        assert!(Table::new(id, table_config, None).is_err());
+
+        {
+            let table = Table::new_in(id, table_config, None, &old_root)?;
+
+            for n in 0..3 {
+                table.insert_one(vec![Some(DataValue::try_from_any(
+                    columns[0].data_type,
+                    n,
+                )?)])?;
+            }
+
+            table.close()?;
+        }
+
+        assert!(old_root.join("tables/t/records.store").exists());
+        std::fs::rename(&old_root, &new_root)?;
+
+        let table = Table::new_in(id, table_config, None, &new_root)?;
+        let rows = table
+            .scan_since(0)
+            .map(|(_, handle)| Ok(table.get_versioned(&handle)?.0))
+            .collect::<Result<Vec<_>>>()?;
+
+        assert_eq!(rows.len(), 3);
+        assert_eq!(
+            rows[2],
+            vec![Some(DataValue::try_from_any(columns[0].data_type, 2)?)]
+        );
+        assert!(!old_root.exists());
+
+        drop(table);
+
+        Ok(())
+    }
+
+    fn name_row(columns: &[DataConfig], last: &str, first: &str) -> Result<Vec<Option<DataValue>>> {
+        Ok(vec![
+            Some(columns[0].try_new_value(first.to_string())?),
+            Some(columns[1].try_new_value(last.to_string())?),
+        ])
+    }
+
+    #[test]
+    fn test_primary_key_unique() -> Result<()> {
+        let columns = vec![
+            DataConfig::new(DataType::Text(20)),
+            DataConfig::new(DataType::Text(20)),
+        ];
+
+        let table_config = TableConfig::new(&columns)?.with_primary_key([1, 0])?;
+        let table = Table::new(TableId::new(), table_config, None)?;
+
+        let smith = table
+            .insert_one(name_row(&columns, "Smith", "Ann")?)?
+            .handle;
+        table.insert_one(name_row(&columns, "Smith", "Bob")?)?;
+
+        let err = table
+            .insert_one(name_row(&columns, "Smith", "Ann")?)
+            .unwrap_err();
+        assert!(err.to_string().contains("duplicate primary key"));
+        assert!(table.insert_one(vec![None, None]).is_err());
+
+        let res = table.insert(vec![
+            name_row(&columns, "Jones", "Cat")?,
+            name_row(&columns, "Jones", "Cat")?,
+        ])?;
+
+        match res {
+            InsertState::Partial { handles, errors } => {
+                assert_eq!(handles.len(), 1);
+                assert!(matches!(errors[0], (1, InsertError::DuplicateKey { .. })));
+            }
+            _ => panic!("expected the second row to be rejected"),
+        }
+
+        assert_eq!(table.row_count(), 3);
+
+        // moving a row onto a taken key fails, moving it somewhere free re-keys it
+        let (_, gen) = table.get_versioned(&smith)?;
+        assert!(table
+            .update_if(&smith, gen, name_row(&columns, "Smith", "Bob")?)
+            .is_err());
+
+        table.update_if(&smith, gen, name_row(&columns, "Smyth", "Ann")?)?;
+
+        let key = |last: &str, first: &str| -> Result<Vec<DataValue>> {
+            Ok(vec![
+                columns[1].try_new_value(last.to_string())?,
+                columns[0].try_new_value(first.to_string())?,
+            ])
+        };
+
+        assert!(table.find_by_pk(&key("Smith", "Ann")?)?.is_none());
+        assert!(table.find_by_pk(&key("Smyth", "Ann")?)?.is_some());
+
+        let bob = table
+            .find_by_pk(&key("Smith", "Bob")?)?
+            .expect("row exists");
+        assert!(table.delete(bob)?);
+        assert!(table.find_by_pk(&key("Smith", "Bob")?)?.is_none());
+        table.insert_one(name_row(&columns, "Smith", "Bob")?)?;
+
+        let report = table.check_integrity()?;
+        assert!(report.is_ok(), "{}", report);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_primary_key_prefix_scan() -> Result<()> {
+        let columns = vec![
+            DataConfig::new(DataType::Text(20)),
+            DataConfig::new(DataType::Text(20)),
+        ];
+
+        let table_config = TableConfig::new(&columns)?.with_primary_key([1, 0])?;
+        let table = Table::new(TableId::new(), table_config, None)?;
+
+        for (last, first) in [
+            ("Smith", "Cat"),
+            ("Jones", "Ann"),
+            ("Smith", "Ann"),
+            ("Smyth", "Ann"),
+            ("Smith", "Bob"),
+        ] {
+            table.insert_one(name_row(&columns, last, first)?)?;
+        }
+
+        let smiths = table
+            .scan_pk_prefix(&[columns[1].try_new_value("Smith".to_string())?])?
+            .into_iter()
+            .map(|(key, _)| key.0[1].to_string())
+            .collect::<Vec<_>>();
+
+        assert_eq!(smiths, vec!["Ann", "Bob", "Cat"]);
+        assert_eq!(table.scan_pk_range(..).len(), 5);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_primary_key_reopen() -> Result<()> {
+        let columns = vec![
+            DataConfig::new(DataType::Text(20)),
+            DataConfig::new(DataType::Text(20)),
+        ];
+
+        let id = TableId::new();
+        let dir = TempDir::new("mem_table_pk");
+        let unkeyed = TableConfig::new_persisted(&columns, &dir)?;
+        let keyed = unkeyed.with_primary_key([1, 0])?;
+
+        {
+            let table = Table::new(id, keyed, None)?;
+
+            table.insert_one(name_row(&columns, "Smith", "Ann")?)?;
+            table.insert_one(name_row(&columns, "Jones", "Bob")?)?;
+            table.close()?;
+        }
+
+        let table = Table::new(id, keyed, None)?;
+        let jones = [
+            columns[1].try_new_value("Jones".to_string())?,
+            columns[0].try_new_value("Bob".to_string())?,
+        ];
+        let handle = table.find_by_pk(&jones)?.expect("row exists");
+
+        assert_eq!(
+            table.get_versioned(&handle)?.0,
+            name_row(&columns, "Jones", "Bob")?
+        );
+        assert!(table
+            .insert_one(name_row(&columns, "Jones", "Bob")?)
+            .is_err());
+        // the handle still maps the record store, so its lock is only let go of by closing
+        table.close()?;
+
+        // declaring the key on rows that already break it reports the offending key
+        {
+            let table = Table::new(id, unkeyed, None)?;
+
+            table.insert_one(name_row(&columns, "Smith", "Ann")?)?;
+            table.close()?;
+        }
+
+        let err = Table::new(id, keyed, None).unwrap_err();
+        assert!(err.to_string().contains("(Smith, Ann)"), "{}", err);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ttl() -> Result<()> {
+        use std::time::{Duration, Instant};
+
+        let columns = vec![DataConfig::new(DataType::Number)];
+        let ttl = Duration::from_millis(200);
+        let config = TableConfig::new(&columns)?
+            .with_primary_key([0])?
+            .with_ttl(ttl)?;
+        let table = Table::new(TableId::new(), config, None)?;
+        let number = |n: i64| vec![Some(DataValue::Number(n.into()))];
+        let key = |n: i64| [DataValue::Number(n.into())];
+
+        let old = table.insert_one(number(1))?;
+        table.insert_one(number(2))?;
+        assert_eq!(table.get_versioned(&old.handle)?.0, number(1));
+
+        // rows expire within a window of their TTL
+        std::thread::sleep(ttl + ttl / TTL_WINDOWS as u32 + Duration::from_millis(20));
+        table.insert_one(number(3))?;
+
+        // reads leave expired rows out before the sweep deletes them
+        assert!(table.get_versioned(&old.handle).is_err());
+        assert!(table.find_by_pk(&key(1))?.is_none());
+        assert!(table.get_by_seq(1).is_none());
+        assert_eq!(table.read_rows()?.rows.len(), 1);
+        assert_eq!(table.row_count(), 3);
+
+        // and their keys are free to take again
+        let taken = table.insert_one(number(2))?;
+
+        assert_eq!(table.expire_now()?, 2);
+        assert_eq!(table.row_count(), 2);
+        assert_eq!(table.records.gap_count(), 2);
+        assert_eq!(table.find_by_pk(&key(2))?, Some(taken.handle));
+        assert_eq!(table.expire_now()?, 0);
+
+        // the slots of expired rows are reused
+        table.insert_one(number(4))?;
+        assert_eq!(table.records.gap_count(), 1);
+
+        let _sweeper = table.expire_every(Duration::from_millis(10));
+        let deadline = Instant::now() + Duration::from_secs(10);
+
+        while table.row_count() > 0 && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        assert_eq!(table.row_count(), 0);
+        assert!(TableConfig::new(&columns)?
+            .with_ttl(Duration::ZERO)
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_truncate() -> Result<()> {
+        let columns = vec![
+            DataConfig::new(DataType::Text(20)).with_bloom(),
+            DataConfig::new(DataType::Text(20)),
+        ];
+
+        let id = TableId::new();
+        let dir = TempDir::new("mem_table_truncate");
+        let config = TableConfig {
+            block_capacity: NonZeroUsize::new(4).unwrap(),
+            ..TableConfig::new_persisted(&columns, &dir)?.with_primary_key([0])?
+        };
+        let names = (0..10).map(|n| format!("name {}", n)).collect::<Vec<_>>();
+
+        let old = {
+            let table = Table::new(id, config, None)?;
+
+            for name in names.iter() {
+                table.insert_one(name_row(&columns, "x", name)?)?;
+            }
+
+            let truncated = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+            let listener = truncated.clone();
+
+            table.on_change(move |change| {
+                if let Change::Truncated { rows } = change {
+                    listener.fetch_add(*rows, Ordering::SeqCst);
+                }
+            });
+
+            let old = table.scan_since(0).next().expect("rows were inserted").1;
+
+            assert_eq!(table.truncate()?, 10);
+            assert_eq!(truncated.load(Ordering::SeqCst), 10);
+            assert!(table.is_empty());
+            assert!(table.get_versioned(&old).is_err());
+
+            // the old handle is into the first block, so every block past it is dropped
+            let report = table.fragmentation_report()?;
+
+            assert_eq!(report.records.blocks.len(), 1);
+            assert!(report
+                .columns
+                .iter()
+                .all(|column| column.store.blocks.len() == 1));
+
+            table.close()?;
+            old
+        };
+
+        let table = Table::new(id, config, None)?;
+
+        assert_eq!(table.row_count(), 0);
+        assert_eq!(table.scan_since(0).count(), 0);
+        assert_eq!(table.current_seq(), 10);
+        assert!(table.get_versioned(&old).is_err());
+
+        // the keys went along with the rows
+        let row = name_row(&columns, "x", &names[0])?;
+        let key = [row[0].clone().unwrap()];
+        assert!(table.find_by_pk(&key)?.is_none());
+
+        let inserted = table.insert_one(row.clone())?;
+
+        assert_eq!(table.get_versioned(&inserted.handle)?.0, row);
+        assert_eq!(table.scan_eq(0, &key[0])?.rows.len(), 1);
+
+        let report = table.check_integrity()?;
+        assert!(report.is_ok(), "{}", report);
+
+        drop(table);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_insert_idempotent() -> Result<()> {
+        let columns = vec![DataConfig::new(DataType::Number)];
+        let id = TableId::new();
+        let dir = TempDir::new("mem_table_idempotent");
+        let config = TableConfig::new_persisted(&columns, &dir)?;
+        let number = |n: i64| vec![Some(DataValue::Number(n.into()))];
+        let op = O64::new();
+
+        let first = {
+            let table = Table::new(id, config, None)?;
+            let first = table.insert_idempotent(op, number(1))?;
+
+            // the retry comes back with the first row, not its own values
+            let replayed = table.insert_idempotent(op, number(2))?;
+            assert_eq!(replayed.record_id, first.record_id);
+            assert_eq!(replayed.values, number(1));
+            assert_eq!(table.row_count(), 1);
+
+            table.close()?;
+            first
+        };
+
+        // the operations are persisted along with the rows
+        let table = Table::new(id, config, None)?;
+
+        assert_eq!(table.insert_idempotent(op, number(3))?.values, number(1));
+        assert_eq!(table.row_count(), 1);
+
+        // duplicates racing each other insert once, and all get the same row
+        let racing = O64::new();
+        let shared = &table;
+        let rows = std::thread::scope(|scope| {
+            let threads = (0..8)
+                .map(|n| scope.spawn(move || shared.insert_idempotent(racing, number(10 + n))))
+                .collect::<Vec<_>>();
+
+            threads
+                .into_iter()
+                .map(|thread| Ok(thread.join().unwrap()?))
+                .collect::<Result<Vec<_>>>()
+        })?;
+
+        assert!(rows.iter().all(|row| row.record_id == rows[0].record_id));
+        assert_eq!(table.row_count(), 2);
+
+        // once forgotten, an op id inserts again
+        assert_eq!(table.evict_ops(1)?, 1);
+        assert_eq!(
+            table.insert_idempotent(racing, number(4))?.record_id,
+            rows[0].record_id
+        );
+
+        let inserted = table.insert_idempotent(op, number(5))?;
+        assert_ne!(inserted.record_id, first.record_id);
+        assert_eq!(inserted.values, number(5));
+        assert_eq!(table.row_count(), 3);
+
+        // the row of a remembered op id is gone
+        table.delete(rows[0].handle.clone())?;
+        assert!(table.insert_idempotent(racing, number(6)).is_err());
+
+        drop(table);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_insert_panic_rolls_back() -> Result<()> {
+        let columns = vec![
+            DataConfig::new(DataType::Number),
+            DataConfig::new(DataType::Bool),
+            DataConfig::new(DataType::Number),
+        ];
+
+        let table_config = TableConfig::new(&columns)?;
+        let table = Table::new(TableId::new(), table_config, None)?;
+
+        let row = |n: i64| -> Result<Vec<Option<DataValue>>> {
+            Ok(vec![
+                Some(columns[0].try_new_value(n)?),
+                Some(DataValue::Bool(true)),
+                Some(columns[2].try_new_value(n)?),
+            ])
+        };
+
+        let column_lens = |table: &Table| -> Result<Vec<usize>> {
+            (0..columns.len())
+                .map(|column| Ok(table.get_column_store(column)?.read().meta().len()))
+                .collect()
+        };
+
+        table.insert_one(row(1)?)?;
+        let before = column_lens(&table)?;
+
+        PANIC_ON_COLUMN.set(Some(1));
+        let err = table.insert(vec![row(2)?, row(3)?]).unwrap_err();
+        let err_one = table.insert_one(row(4)?).unwrap_err();
+        PANIC_ON_COLUMN.set(None);
+
+        assert!(
+            err.downcast_ref::<CallbackPanicked>().is_some(),
+            "{:?}",
+            err
+        );
+        assert!(err_one.downcast_ref::<CallbackPanicked>().is_some());
+        assert_eq!(table.row_count(), 1);
+        assert_eq!(column_lens(&table)?, before);
+
+        let inserted = table.insert_one(row(5)?)?;
+        assert_eq!(table.row_count(), 2);
+        assert_eq!(inserted.values, row(5)?);
+        assert_eq!(table.get_versioned(&inserted.handle)?.0, row(5)?);
+
+        let report = table.check_integrity()?;
+        assert!(report.is_ok(), "{}", report);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_meta_overwrite_and_remove() -> Result<()> {
+        let columns = vec![DataConfig::new(DataType::Text(20))];
+        let table = Table::new(TableId::new(), TableConfig::new(&columns)?, None)?;
+        let meta = table.meta()?;
+
+        assert_eq!(meta.get("schema_version")?, None);
+
+        meta.set("schema_version", DataValue::Number(1i64.into()))?;
+        table
+            .meta()?
+            .set("schema_version", DataValue::Number(2i64.into()))?;
+        meta.set(
+            "source",
+            DataValue::Text(primitives::Text::try_from_str("import.csv", 20)?),
+        )?;
+
+        assert_eq!(
+            meta.get("schema_version")?,
+            Some(DataValue::Number(2i64.into()))
+        );
+        assert_eq!(
+            meta.iter()?.map(|(key, _)| key).collect::<Vec<_>>(),
+            vec!["schema_version", "source"]
+        );
+
+        assert!(meta.remove("schema_version")?);
+        assert!(!meta.remove("schema_version")?);
+        assert_eq!(meta.get("schema_version")?, None);
+        assert_eq!(meta.iter()?.count(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_meta_reopen() -> Result<()> {
+        let columns = vec![DataConfig::new(DataType::Text(20))];
+        let id = TableId::new();
+        let dir = TempDir::new("mem_table_meta");
+        let config = TableConfig::new_persisted(&columns, &dir)?;
+        let watermark = DataValue::Number(1.5f64.into());
+
+        {
+            let table = Table::new(id, config, None)?;
+            let meta = table.meta()?;
+
+            meta.set("watermark", DataValue::Number(1i64.into()))?;
+            meta.set("watermark", watermark.clone())?;
+            meta.set("dropped", DataValue::Number(0i64.into()))?;
+            meta.remove("dropped")?;
+            table.close()?;
+
+            assert!(meta.set("late", DataValue::Number(0i64.into())).is_err());
+        }
+
+        let table = Table::new(id, config, None)?;
+        let meta = table.meta()?;
+
+        assert_eq!(meta.get("watermark")?, Some(watermark));
+        assert_eq!(meta.get("dropped")?, None);
+        assert_eq!(meta.iter()?.count(), 1);
+        assert!(table.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dump_round_trip() -> Result<()> {
+        let columns = vec![
+            DataConfig::new(DataType::Number),
+            DataConfig::new(DataType::Bool),
+            DataConfig::new(DataType::Text(20)),
+            DataConfig::new(DataType::Bytes(16)),
+        ];
+        let names = ["n", "even", "label", "blob"]
+            .iter()
+            .enumerate()
+            .map(|(idx, name)| Ok((InternalString::new(name)?, idx)))
+            .collect::<Result<IndexMap<_, _>>>()?;
+
+        let table = Table::new(TableId::new(), TableConfig::new(&columns)?, Some(names))?;
+
+        for n in 0..300usize {
+            let handle = table
+                .insert_one(vec![
+                    Some(DataValue::try_from_any(DataType::Number, n)?),
+                    Some(DataValue::Bool(n % 2 == 0)),
+                    Some(DataValue::Text(primitives::Text::try_from_str(
+                        &format!("row {}", n),
+                        20,
+                    )?)),
+                    (n % 3 == 0)
+                        .then(|| -> Result<DataValue> {
+                            Ok(DataValue::Bytes(primitives::Bytes::try_from_slice(
+                                &n.to_le_bytes(),
+                                16,
+                            )?))
+                        })
+                        .transpose()?,
+                ])?
+                .handle;
+
+            if n % 7 == 0 {
+                table.delete(handle)?;
+            }
+        }
+
+        table.meta()?.set(
+            "ticket",
+            DataValue::try_from_any(DataType::Number, 42usize)?,
+        )?;
+
+        let dump_dir = TempDir::new("mem_table_dump");
+        let restore_dir = TempDir::new("mem_table_restore");
+
+        let manifest = table.dump(&dump_dir)?;
+        assert_eq!(manifest.row_count, table.row_count());
+        assert!(table.dump(&dump_dir).is_err(), "dump dir is not empty");
+
+        let restored = Table::restore_from_dump(&dump_dir, &restore_dir)?;
+        assert_ne!(restored.id, table.id);
+        assert_eq!(restored.row_count(), table.row_count());
+        assert_eq!(restored.config().columns, table.config().columns);
+        assert_eq!(restored.columns_by_name, table.columns_by_name);
+        assert_eq!(
+            restored.meta()?.get("ticket")?,
+            Some(DataValue::try_from_any(DataType::Number, 42usize)?)
+        );
+
+        let rows = |table: &Table| -> Result<Vec<_>> {
+            table
+                .scan_since(0)
+                .map(|(seq, handle)| Ok((seq, table.get_versioned(&handle)?.0)))
+                .collect()
+        };
+        assert_eq!(rows(&restored)?, rows(&table)?);
+
+        for table in [&table, &restored] {
+            let report = table.check_integrity()?;
+            assert!(report.is_ok(), "{}", report);
+        }
+
+        // a tampered file is caught before anything is restored
+        let column = dump_dir.join("column_2.store");
+        let mut bytes = std::fs::read(&column)?;
+        *bytes.last_mut().unwrap() ^= 0xff;
+        std::fs::write(&column, bytes)?;
+
+        let other_dir = restore_dir.with_extension("tampered");
+        let err = Table::restore_from_dump(&dump_dir, &other_dir).unwrap_err();
+        assert!(err.to_string().contains("checksum mismatch"), "{}", err);
+        assert!(!other_dir.exists());
+
+        Ok(())
+    }
+
+    fn email(n: usize) -> Result<DataValue> {
+        Ok(DataValue::Text(primitives::Text::try_from_str(
+            &format!("user{}@example.com", n),
+            40,
+        )?))
+    }
+
+    #[test]
+    fn test_scan_eq_limit() -> Result<()> {
+        let table = fixture_table(&[("n", DataType::Number)]);
+        let block_capacity = table.config().block_capacity.get();
+
+        let rows = (0..100_000usize)
+            .map(|n| {
+                Ok(vec![Some(DataValue::try_from_any(
+                    DataType::Number,
+                    n % 10,
+                )?)])
+            })
+            .collect::<Result<Vec<_>>>()?;
+        assert!(matches!(table.insert(rows)?, InsertState::Done(_)));
+
+        let three = DataValue::try_from_any(DataType::Number, 3)?;
+        let limited = table.scan_eq_limit(0, &three, 5)?;
+
+        assert_eq!(limited.rows.len(), 5);
+        assert!(limited.limited);
+        assert_eq!(limited.blocks_scanned, 1);
+
+        for handle in limited.rows.iter() {
+            assert_eq!(table.get_versioned(handle)?.0[0], Some(three.clone()));
+        }
+
+        let full = table.scan_eq(0, &three)?;
+
+        assert_eq!(full.rows.len(), 10_000);
+        assert!(!full.limited);
+        assert_eq!(full.blocks_scanned, 100_000usize.div_ceil(block_capacity));
+
+        // the last row is a 9, so a limit of every 9 is met on the last slot of the last block
+        let nine = DataValue::try_from_any(DataType::Number, 9)?;
+        let exact = table.scan_eq_limit(0, &nine, 10_000)?;
+
+        assert_eq!(exact.rows.len(), 10_000);
+        assert!(exact.limited);
+        assert_eq!(exact.blocks_scanned, full.blocks_scanned);
+
+        // a limit the column can't fill reads every block
+        let ten = DataValue::try_from_any(DataType::Number, 10)?;
+        let missing = table.scan_eq_limit(0, &ten, 5)?;
+
+        assert!(missing.rows.is_empty());
+        assert!(!missing.limited);
+        assert_eq!(missing.blocks_scanned, full.blocks_scanned);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bloom_skips_blocks() -> Result<()> {
+        let columns = vec![
+            DataConfig::new(DataType::Text(40)).with_bloom(),
+            DataConfig::new(DataType::Number),
+        ];
+        let table = Table::new(TableId::new(), TableConfig::new(&columns)?, None)?;
+        let block_capacity = table.config().block_capacity.get();
+
+        for n in 0..block_capacity * 4 {
+            table.insert_one(vec![
+                Some(email(n)?),
+                Some(DataValue::try_from_any(DataType::Number, n)?),
+            ])?;
+        }
+
+        assert!(table.bloom_byte_count() > 0);
+
+        let present = table.scan_eq(0, &email(block_capacity + 3)?)?;
+        assert_eq!(present.rows.len(), 1);
+        assert_eq!(
+            table.get_versioned(&present.rows[0])?.0[1],
+            Some(DataValue::try_from_any(
+                DataType::Number,
+                block_capacity + 3
+            )?)
+        );
+
+        let (mut skipped, mut scanned) = (0, 0);
+
+        for n in 0..200 {
+            let absent = table.scan_eq(0, &email(1_000_000 + n)?)?;
+            assert!(absent.rows.is_empty());
+
+            skipped += absent.blocks_skipped;
+            scanned += absent.blocks_scanned;
+        }
+
+        // 10 bits per key gives about a 1% false positive rate
+        assert!(
+            skipped * 100 >= (skipped + scanned) * 95,
+            "{} / {}",
+            skipped,
+            scanned
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bloom_follows_updates_and_deletes() -> Result<()> {
+        let columns = vec![DataConfig::new(DataType::Text(40)).with_bloom()];
+        let table = Table::new(TableId::new(), TableConfig::new(&columns)?, None)?;
+
+        let handles = (0..100)
+            .map(|n| Ok(table.insert_one(vec![Some(email(n)?)])?.handle))
+            .collect::<Result<Vec<_>>>()?;
+
+        let (_, gen) = table.get_versioned(&handles[0])?;
+        table.update_if(&handles[0], gen, vec![Some(email(500)?)])?;
+
+        assert!(table.scan_eq(0, &email(0)?)?.rows.is_empty());
+        let found = table.scan_eq(0, &email(500)?)?.rows;
+        assert_eq!(found.len(), 1);
+        assert_eq!(
+            table.records.record_id(&found[0]),
+            table.records.record_id(&handles[0])
+        );
+
+        // removing most of the block rebuilds its filter without losing the values left
+        for handle in handles[1..80].iter() {
+            table.delete(handle.clone())?;
+        }
+
+        for n in 80..100 {
+            assert_eq!(table.scan_eq(0, &email(n)?)?.rows.len(), 1, "{}", n);
+        }
+
+        assert!(table.scan_eq(0, &email(1)?)?.rows.is_empty());
+
+        // only text and bytes can be filtered
+        assert!(TableConfig::new([DataConfig::new(DataType::Number).with_bloom()]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_typed_rows() -> Result<()> {
+        let columns = vec![
+            DataConfig::new(DataType::Bool),
+            DataConfig::new(DataType::Number),
+            DataConfig::new(DataType::Number),
+            DataConfig::new(DataType::Number),
+            DataConfig::new(DataType::Text(20)),
+            DataConfig::new(DataType::Bytes(20)),
+            DataConfig::new(DataType::Timestamp),
+            DataConfig::new(DataType::Text(20)),
+        ];
+
+        let name_mapping = [
+            "active", "delta", "count", "score", "name", "raw", "seen", "note",
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(idx, name)| Ok((InternalString::new(name)?, idx)))
+        .collect::<Result<IndexMap<_, _>>>()?;
+
+        let table = Table::new(
+            TableId::new(),
+            TableConfig::new(&columns)?,
+            Some(name_mapping),
+        )?;
+
+        type Row = (
+            bool,
+            i64,
+            u64,
+            f64,
+            String,
+            Vec<u8>,
+            Timestamp,
+            Option<String>,
+        );
+
+        let row: Row = (
+            true,
+            -7,
+            u64::MAX,
+            2.5,
+            "alice".to_string(),
+            vec![1, 2, 3],
+            Timestamp::new(),
+            None,
+        );
+
+        let handle = table.insert_typed(row.clone())?;
+        assert_eq!(table.get_typed::<Row>(&handle)?, row);
+
+        // the wrong type for a column is caught before anything is written
+        let err = table
+            .insert_typed((
+                true,
+                "oops".to_string(),
+                1u64,
+                1.0,
+                String::new(),
+                vec![],
+                Timestamp::new(),
+                None::<String>,
+            ))
+            .unwrap_err();
+        assert!(err.to_string().contains("`delta`"), "{}", err);
+        assert_eq!(table.row_count(), 1);
+
+        let err = table.insert_typed((true, 1i64)).unwrap_err();
+        assert!(err.to_string().contains("`count`"), "{}", err);
+
+        let err = table
+            .get_typed::<(bool, i64, u64, f64, String, Vec<u8>, Timestamp, String)>(&handle)
+            .unwrap_err();
+        assert!(err.to_string().contains("`note`"), "{}", err);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_row() -> Result<()> {
+        let columns = vec![
+            DataConfig::new(DataType::Number),
+            DataConfig::new(DataType::Text(10)),
+        ];
+
+        let name_mapping = [
+            (InternalString::new("count")?, 0),
+            (InternalString::new("label")?, 1),
+        ]
+        .into_iter()
+        .collect();
+
+        let table = Table::new(
+            TableId::new(),
+            TableConfig::new(&columns)?,
+            Some(name_mapping),
+        )?;
+
+        let count = |n: u64| columns[0].try_new_value(n).map(Some);
+        let label = |s: &str| Text::try_from_str(s, 8).map(|s| Some(DataValue::Text(s)));
+
+        // shorter text fits a longer column
+        assert_eq!(table.validate_row(&[count(1)?, label("a")?]), Ok(()));
+
+        let err = table
+            .insert_one(vec![count(1)?, Some(DataValue::Bool(true))])
+            .unwrap_err();
+
+        assert_eq!(
+            err.downcast_ref::<RowValidationError>(),
+            Some(&RowValidationError::TypeMismatch {
+                column: 1,
+                name: "label".to_string(),
+                expected: ExpectedType::new(DataType::Text(10)),
+                actual: ExpectedType::new(DataType::Bool),
+            })
+        );
+
+        let InsertState::Partial { handles, errors } = table.insert(vec![
+            vec![count(1)?, label("a")?],
+            vec![Some(DataValue::Bool(false)), label("b")?],
+            vec![count(3)?, label("c")?, None],
+            vec![count(4)?],
+        ])?
+        else {
+            panic!("expected a partial insert");
+        };
+
+        assert_eq!(
+            handles.iter().map(|(idx, ..)| *idx).collect::<Vec<_>>(),
+            vec![0, 3]
+        );
+        assert!(matches!(
+            errors.as_slice(),
+            [
+                (
+                    1,
+                    InsertError::InvalidRow {
+                        error: RowValidationError::TypeMismatch { column: 0, .. },
+                        ..
+                    }
+                ),
+                (
+                    2,
+                    InsertError::InvalidRow {
+                        error: RowValidationError::TooManyValues {
+                            expected: 2,
+                            actual: 3
+                        },
+                        ..
+                    }
+                ),
+            ]
+        ));
+
+        // the bad rows were turned away before any record slot was claimed for them
+        assert_eq!(table.records.gap_count(), 0);
+        assert_eq!(table.row_count(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_column_store_labels() -> Result<()> {
+        let columns = [
+            DataConfig::new(DataType::Text(120)),
+            DataConfig::new(DataType::Number),
+        ];
+        let name_mapping = [(InternalString::new("email")?, 0)].into_iter().collect();
+
+        let table = Table::new(
+            TableId::new(),
+            TableConfig::new(columns)?,
+            Some(name_mapping),
+        )?;
+        let row = table.insert_one(vec![
+            Some(DataValue::try_from_any(
+                DataType::Text(120),
+                "a@example.com",
+            )?),
+            Some(DataValue::try_from_any(DataType::Number, 1)?),
+        ])?;
+
+        // a second value for the same record is refused by the column store
+        let record = Some(row.record_id);
+        let err = table
+            .get_column_store(0)?
+            .insert_one(record, DataValue::try_from_any(DataType::Text(120), "b")?)
+            .unwrap_err();
+
+        assert_eq!(err.label().as_deref(), Some("column 'email' (index 0)"));
+        assert_eq!(
+            err.to_string(),
+            "column 'email' (index 0): record already exists"
+        );
+        assert!(matches!(
+            err.into_unlabeled(),
+            StoreError::InsertError(dbexp::store::InsertError::AlreadyExists { .. })
+        ));
+
+        let err = table
+            .get_column_store(1)?
+            .insert_one(record, DataValue::try_from_any(DataType::Number, 2)?)
+            .map_err(StoreError::thread_safe)
+            .unwrap_err();
+
+        assert_eq!(err.to_string(), "column 1: record already exists");
+
+        let store = table.get_column_store(0)?;
+
+        assert!(format!("{:?}", store).contains("label: \"column 'email' (index 0)\""));
+        assert!(
+            format!("{:?}", store.read().blocks().values().next().unwrap())
+                .contains("label: \"column 'email' (index 0)\"")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_value_errors() -> Result<()> {
+        let columns = [
+            DataConfig::new(DataType::Text(120)),
+            DataConfig::new(DataType::Number),
+        ];
+        let name_mapping = [
+            (InternalString::new("email")?, 0),
+            (InternalString::new("age")?, 1),
+        ]
+        .into_iter()
+        .collect();
+
+        let table = Table::new(
+            TableId::new(),
+            TableConfig::new(columns)?,
+            Some(name_mapping),
+        )?;
+        let (email, age) = (
+            table.config().columns.get(0).unwrap(),
+            table.config().columns.get(1).unwrap(),
+        );
+
+        let err = email
+            .try_new_value(format!("{}@example.com", "x".repeat(118)))
+            .unwrap_err();
+
+        assert_eq!(
+            err,
+            ValueError {
+                column: Some("email".to_string()),
+                expected: ExpectedType::new(DataType::Text(120)),
+                reason: ValueErrorReason::TooLong { len: 130, cap: 120 },
+            }
+        );
+
+        let err = age.try_new_value("forty two").unwrap_err();
+
+        assert_eq!(
+            err,
+            ValueError {
+                column: Some("age".to_string()),
+                expected: ExpectedType::new(DataType::Number),
+                reason: ValueErrorReason::ParseFailed {
+                    message: primitives::Number::try_from_str("forty two")
+                        .unwrap_err()
+                        .to_string(),
+                },
+            }
+        );
+
+        // configs that never went through a table have no name to give
+        assert_eq!(
+            columns[1].try_new_value("forty two").unwrap_err().column,
+            None
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_table_errors() -> Result<()> {
+        let columns = vec![DataConfig::new(DataType::Number)];
+        let config = TableConfig::new(&columns)?.with_primary_key([0])?;
+        let table = Table::new(TableId::new(), config, None)?;
+        let number = |n: i64| Some(DataValue::Number(n.into()));
+
+        let inserted = table.insert_one(vec![number(1)])?;
+        let (_, gen) = table.get_versioned(&inserted.handle)?;
+
+        assert!(matches!(
+            table.get_column_store(1),
+            Err(TableError::NotFound(_))
+        ));
+        assert!(matches!(
+            table.insert_one(vec![number(2), number(3)]),
+            Err(TableError::Validation(_))
+        ));
+        assert!(matches!(
+            table.insert_one(vec![number(1)]),
+            Err(TableError::DuplicateKey(key)) if key.0 == [DataValue::Number(1i64.into())]
+        ));
+        assert!(matches!(
+            table.find_by_pk(&[]),
+            Err(TableError::Validation(_))
+        ));
+
+        let err = table
+            .update_if(&inserted.handle, gen, vec![number(1), number(2)])
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<RowValidationError>(),
+            Some(&RowValidationError::TooManyValues {
+                expected: 1,
+                actual: 2
+            })
+        );
+
+        // errors passed along as `anyhow::Error` are sorted back into their variant
+        let err = TableError::from(anyhow::Error::from(
+            table.insert_one(vec![number(1)]).unwrap_err(),
+        ));
+        assert!(matches!(err, TableError::DuplicateKey(_)), "{}", err);
+
+        table.close()?;
+        assert!(matches!(
+            table.insert_one(vec![number(2)]),
+            Err(TableError::Closed)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fragmentation_report() -> Result<()> {
+        let table = fixture_table(&[("label", DataType::Text(32)), ("n", DataType::Number)]);
+        let block_capacity = table.config().block_capacity.get();
+        let row = |n: usize| -> Result<_> {
+            Ok(vec![
+                Some(DataValue::try_from_any(DataType::Text(32), "abc")?),
+                Some(DataValue::try_from_any(DataType::Number, n)?),
+            ])
+        };
+
+        let handles = (0..block_capacity * 3)
+            .map(|n| Ok(table.insert_one(row(n)?)?.handle))
+            .collect::<Result<Vec<_>>>()?;
+
+        // hollow out the middle block, keeping every fourth row
+        let mut deleted = 0;
+
+        for (n, handle) in handles.into_iter().enumerate() {
+            if n / block_capacity == 1 && n % 4 != 0 {
+                table.delete(handle)?;
+                deleted += 1;
+            }
+        }
+
+        let live = block_capacity * 3 - deleted;
+        let report = table.fragmentation_report()?;
+
+        for store in std::iter::once(&report.records).chain(report.columns.iter().map(|c| &c.store))
+        {
+            assert_eq!(store.gap_count(), deleted);
+            assert!(store
+                .blocks
+                .iter()
+                .any(|block| block.gaps == deleted && block.live == block_capacity - deleted));
+
+            let capacity = store
+                .blocks
+                .iter()
+                .map(|block| block.capacity)
+                .sum::<usize>();
+            assert_eq!(store.avg_utilization, live as f64 / capacity as f64);
+        }
+
+        assert_eq!(report.columns[0].name.as_deref(), Some("label"));
+        assert_eq!(report.columns[0].padding_bytes, live * (32 - 3));
+        assert_eq!(report.columns[1].padding_bytes, 0);
+        assert!(report.to_string().contains("column 'label' (index 0): "));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_integrity() -> Result<()> {
+        let columns = vec![
+            DataConfig::new(DataType::Text(20)),
+            DataConfig::new(DataType::Text(20)),
+        ];
+        let table_config = TableConfig::new(&columns)?.with_primary_key([1, 0])?;
+        let table = Table::new(TableId::new(), table_config, None)?;
+
+        let mut handles = Vec::new();
+
+        for n in 0..40 {
+            handles.push(
+                table
+                    .insert_one(name_row(&columns, &format!("L{}", n), "F")?)?
+                    .handle,
+            );
+        }
+
+        for handle in handles.drain(..10) {
+            table.delete(handle)?;
+        }
+
+        let (_, gen) = table.get_versioned(&handles[0])?;
+        table.update_if(&handles[0], gen, name_row(&columns, "Moved", "F")?)?;
+
+        let report = table.check_integrity()?;
+        assert!(report.is_ok(), "{}", report);
+        assert_eq!(report.rows_checked, 30);
+
+        let checks = |report: &IntegrityReport| {
+            report
+                .violations
+                .iter()
+                .map(|violation| violation.check)
+                .collect::<Vec<_>>()
+        };
+
+        // a column value removed behind the record's back
+        let cell = handles[1].read_with(|slot| Ok(slot.data().unwrap().get(1).unwrap()))?;
+        table
+            .get_column_store(1)?
+            .remove(table._column_handle(1, cell)?);
+
+        let report = table.check_integrity()?;
+        assert!(checks(&report).contains(&"column cell"), "{}", report);
+        assert!(!checks(&report).contains(&"column store counts"));
+        assert_eq!(
+            report.violations[0].record,
+            Some(
+                Into::<primitives::ThinIdx>::into(table.records.record_id(&handles[1]))
+                    .into_usize()
+            )
+        );
+
+        // a column value no record points at
+        table
+            .get_column_store(0)?
+            .insert_one(None, columns[0].try_new_value("stray")?)
+            .map_err(StoreError::thread_safe)?;
+
+        let report = table.check_integrity()?;
+        assert!(checks(&report).contains(&"column slot"), "{}", report);
+
+        // a key index that lost its entries, noticed for every row but the unreadable one
+        table.keys.write().clear();
+
+        let report = table.check_integrity()?;
+        assert_eq!(
+            checks(&report)
+                .iter()
+                .filter(|check| **check == "primary key")
+                .count(),
+            30,
+            "{}",
+            report
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_corrupt_text() -> Result<()> {
+        let columns = vec![
+            DataConfig::new(DataType::Text(20)),
+            DataConfig::new(DataType::Text(20)),
+        ];
+        let open = |config: TableConfig| -> Result<(Table, Vec<RecordHandle>)> {
+            let table = Table::new(TableId::new(), config, None)?;
+            let handles = (0..5)
+                .map(|n| {
+                    Ok(table
+                        .insert_one(name_row(&columns, &format!("L{}", n), "F")?)?
+                        .handle)
+                })
+                .collect::<Result<Vec<_>>>()?;
+
+            // garbage over the first byte of the second row's last name, which leaves a
+            // continuation byte with nothing to continue
+            let cell = handles[1].read_with(|slot| Ok(slot.data().unwrap().get(1).unwrap()))?;
+            table._column_handle(1, cell)?.write_with(|mut data| {
+                data.update(|value| {
+                    if let DataValue::Text(text) = value {
+                        unsafe { text.as_str_mut().as_bytes_mut()[0] = 0x80 };
+                    }
+                    Ok(())
+                })
+            })?;
+
+            Ok((table, handles))
+        };
+
+        let (strict, handles) = open(TableConfig::new(&columns)?)?;
+
+        let err = strict.get_versioned(&handles[1]).unwrap_err();
+        let corrupt = err.downcast_ref::<CorruptValue>().expect("a CorruptValue");
+        assert!(corrupt.reason.contains("not valid UTF-8"), "{}", corrupt);
+        assert!(strict.get_versioned(&handles[0]).is_ok());
+        assert!(strict.read_rows().unwrap_err().is::<CorruptValue>());
+
+        let report = strict.check_integrity()?;
+        assert_eq!(report.violations.len(), 1, "{}", report);
+        assert_eq!(report.violations[0].check, "column value");
+        assert_eq!(report.violations[0].column, Some(1));
+
+        let (skipping, handles) =
+            open(TableConfig::new(&columns)?.with_on_corrupt(OnCorrupt::Skip))?;
+
+        let scan = skipping.read_rows()?;
+        assert_eq!(scan.rows.len(), 4);
+        assert!(scan.rows.iter().all(|(handle, _)| *handle != handles[1]));
+        assert_eq!(scan.corrupt.len(), 1);
+        assert_eq!(scan.corrupt[0].slot, 1);
+
+        // a single row can't be skipped
+        assert!(skipping.get_versioned(&handles[1]).is_err());
+
+        let (trusted, handles) = open(TableConfig::new(&columns)?.with_trusted_input(true))?;
+        assert!(trusted.get_versioned(&handles[1]).is_ok());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_debug_record() -> Result<()> {
+        let columns = vec![
+            DataConfig::new(DataType::Text(20)),
+            DataConfig::new(DataType::Text(20)),
+        ];
+        let table = Table::new(TableId::new(), TableConfig::new(&columns)?, None)?;
+        let rows = (0..3)
+            .map(|n| Ok(table.insert_one(name_row(&columns, &format!("L{}", n), "F")?)?))
+            .collect::<Result<Vec<_>>>()?;
+
+        // the same damage as in `test_corrupt_text`, to the last name of the second row
+        let cell = rows[1]
+            .handle
+            .read_with(|slot| Ok(slot.data().unwrap().get(1).unwrap()))?;
+        table._column_handle(1, cell)?.write_with(|mut data| {
+            data.update(|value| {
+                if let DataValue::Text(text) = value {
+                    unsafe { text.as_str_mut().as_bytes_mut()[0] = 0x80 };
+                }
+                Ok(())
+            })
+        })?;
+
+        let seq = table.seq_of(&rows[1].handle)?;
+        let debug = table.debug_record(&seq.to_string())?;
+
+        assert_eq!(debug.seq, seq);
+        assert_eq!(debug.columns.len(), 2);
+        assert!(!debug.slot.bytes.is_empty());
+        // the record store keeps no record ids in its slots, so there's no generation to show
+        assert!(!debug.slot.gap);
+        assert_eq!(debug.slot.gen, None);
+        assert_eq!(debug.slot.file, None);
+
+        // the column left alone still decodes
+        let first = columns[0].try_new_value("F".to_string())?;
+        assert_eq!(debug.columns[0].value, Some(format!("{:?}", first)));
+        assert_eq!(debug.columns[0].error, None);
+
+        let damaged = &debug.columns[1];
+        assert_eq!(damaged.value, None);
+        assert!(
+            damaged
+                .error
+                .as_deref()
+                .is_some_and(|error| error.contains("not valid UTF-8")),
+            "{:?}",
+            damaged
+        );
+        assert!(damaged
+            .slot
+            .as_ref()
+            .is_some_and(|slot| !slot.bytes.is_empty()));
+
+        let json = debug.to_json();
+        assert_eq!(json["seq"], seq);
+        assert!(json["columns"][1]["error"].is_string());
+        assert!(json["columns"][0]["error"].is_null());
+
+        let text = debug.to_string();
+        assert!(text.contains("not valid UTF-8"), "{}", text);
+
+        // found by its record id too
+        let by_id = table.debug_record(&debug.record)?;
+        assert_eq!(by_id.seq, seq);
+        assert!(matches!(
+            table.debug_record("nope"),
+            Err(TableError::NotFound(_))
+        ));
+
+        Ok(())
+    }
+
+    #[cfg(feature = "arrow")]
+    #[test]
+    fn test_parquet_round_trip() -> Result<()> {
+        use arrow::array::{Array, BooleanArray, Int64Array, StringArray};
+        use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+        let columns = vec![
+            DataConfig::new(DataType::Number),
+            DataConfig::new(DataType::Text(20)),
+            DataConfig::new(DataType::Bool),
+        ];
+        let table = Table::new(TableId::new(), TableConfig::new(&columns)?, None)?;
+        let rows = (0..10i64)
+            .map(|n| {
+                Ok(vec![
+                    Some(DataValue::try_from_any(DataType::Number, n - 5)?),
+                    (n % 3 != 0)
+                        .then(|| columns[1].try_new_value(format!("row {}", n)))
+                        .transpose()?,
+                    Some(DataValue::Bool(n % 2 == 0)),
+                ])
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        table.insert(rows)?;
+
+        let dir = TempDir::new("mem_table_export");
+        let path = dir.join("table.parquet");
+        let options = ExportOptions {
+            batch_size: 4,
+            ..ExportOptions::default()
+        };
+
+        assert_eq!(to_parquet_file(&table, &path, &options)?, 10);
+
+        // each exported batch is a row group, which the reader would merge back into one batch
+        let builder = ParquetRecordBatchReaderBuilder::try_new(std::fs::File::open(&path)?)?;
+        assert_eq!(builder.metadata().num_row_groups(), 3);
+
+        let reader = builder.with_batch_size(options.batch_size).build()?;
+        let batches = reader.collect::<Result<Vec<_>, _>>()?;
+
+        assert_eq!(batches.len(), 3);
+
+        let mut n = 0i64;
+
+        for batch in batches.iter() {
+            let numbers = batch
+                .column(0)
+                .as_any()
+                .downcast_ref::<Int64Array>()
+                .unwrap();
+            let texts = batch
+                .column(1)
+                .as_any()
+                .downcast_ref::<StringArray>()
+                .unwrap();
+            let bools = batch
+                .column(2)
+                .as_any()
+                .downcast_ref::<BooleanArray>()
+                .unwrap();
+
+            for row in 0..batch.num_rows() {
+                assert_eq!(numbers.value(row), n - 5);
+                assert_eq!(texts.is_null(row), n % 3 == 0);
+                if n % 3 != 0 {
+                    assert_eq!(texts.value(row), format!("row {}", n));
+                }
+                assert_eq!(bools.value(row), n % 2 == 0);
+                n += 1;
+            }
+        }
+
+        assert_eq!(n, 10);
+
+        // a projection picks and orders the columns, and one past the end isn't there
+        let projected = to_arrow_batches(&table, Some(&[2, 0]), 100)?;
+        assert_eq!(projected.schema().field(0).name(), "column_2");
+        assert_eq!(projected.count(), 1);
+        assert!(matches!(
+            to_arrow_batches(&table, Some(&[3]), 100),
+            Err(TableError::NotFound(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_single_file_layout() -> Result<()> {
+        let columns = vec![DataConfig::new(DataType::Number); 8];
+        let rows = (0..20)
+            .map(|n| {
+                (0..columns.len())
+                    .map(|c| Ok(Some(DataValue::try_from_any(DataType::Number, n * 10 + c)?)))
+                    .collect::<Result<Vec<_>>>()
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        // the files under `dir` this process has open, and the ones it has mapped
+        let usage = |dir: &Path| -> Result<(usize, usize)> {
+            let mut fds = 0;
+
+            for entry in std::fs::read_dir("/proc/self/fd")? {
+                if std::fs::read_link(entry?.path()).is_ok_and(|link| link.starts_with(dir)) {
+                    fds += 1;
+                }
+            }
+
+            let maps = std::fs::read_to_string("/proc/self/maps")?;
+            let mapped = maps
+                .lines()
+                .filter_map(|line| line.split_whitespace().nth(5))
+                .filter(|path| Path::new(path).starts_with(dir))
+                .collect::<std::collections::HashSet<_>>();
+
+            Ok((fds, mapped.len()))
+        };
+
+        let id = TableId::new();
+        let dir = TempDir::new("mem_table_single_file");
+        let single = TableConfig::new_persisted(&columns, dir.join("single"))?
+            .with_storage_layout(StorageLayout::SingleFile);
+        let separate = TableConfig::new_persisted(&columns, dir.join("separate"))?;
+
+        {
+            let table = Table::new(id, single, None)?;
+            table.insert(rows.clone())?;
+
+            assert_eq!(usage(&dir.join("single"))?, (1, 1));
+            assert!(single.check_store_files(id, None)?.is_empty());
+            table.close()?;
+        }
+
+        let table = Table::new(id, single, None)?;
+        assert_rows_eq(&table, rows.clone());
+        assert!(table.check_integrity()?.is_ok());
+        drop(table);
+
+        // the other layout refuses to open next to a table file
+        let single_as_separate = single.with_storage_layout(StorageLayout::SeparateFiles);
+        assert!(Table::new(id, single_as_separate, None).is_err());
+
+        {
+            let table = Table::new(id, separate, None)?;
+            table.insert(rows.clone())?;
+
+            assert_eq!(usage(&dir.join("separate"))?, (9, 9));
+
+            // the stores are locked while the table is open
+            assert!(migrate_to_single_file(&separate, None).is_err());
+            table.close()?;
+        }
+
+        let migrated = migrate_to_single_file(&separate, None)?;
+        assert_eq!(migrated.storage_layout, StorageLayout::SingleFile);
+        assert!(!dir.join("separate/records.store").exists());
+        assert!(Table::new(id, separate, None).is_err());
+
+        let table = Table::new(id, migrated, None)?;
+        assert_rows_eq(&table, rows);
+        assert!(table.check_integrity()?.is_ok());
+        assert_eq!(usage(&dir.join("separate"))?, (1, 1));
+        drop(table);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_column_order_is_stable() -> Result<()> {
+        let id = TableId::new();
+        let spec = [
+            ("a", DataType::Number),
+            ("b", DataType::Bool),
+            ("c", DataType::Number),
+        ];
+        let open = || -> Result<Table> {
+            let columns = spec.map(|(_, data_type)| DataConfig::new(data_type));
+            let names = spec
+                .iter()
+                .enumerate()
+                .map(|(idx, (name, _))| Ok((InternalString::new(name)?, idx)))
+                .collect::<Result<_>>()?;
+
+            Ok(Table::new(id, TableConfig::new(columns)?, Some(names))?)
+        };
+
+        let rows = (0..10)
+            .map(|n| -> Result<_> {
+                Ok(vec![
+                    Some(DataValue::try_from_any(DataType::Number, n)?),
+                    Some(DataValue::Bool(n % 2 == 0)),
+                    None,
+                ])
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let in_order = open()?;
+
+        for row in rows.iter() {
+            in_order.insert_one(row.clone())?;
+        }
+
+        // the last column's store gets opened before any of the others
+        let out_of_order = open()?;
+        out_of_order.get_column_by_name("c").expect("column c");
+
+        for row in rows.iter() {
+            out_of_order.insert_one(row.clone())?;
+        }
+
+        assert_eq!(format!("{:?}", in_order), format!("{:?}", out_of_order));
+
+        let lens = out_of_order
+            .get_column_stores([2, 0, 2])?
+            .iter()
+            .map(|store| store.read().meta().len())
+            .collect::<Vec<_>>();
+
+        assert_eq!(lens, [0, 10, 0]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_length_scans() -> Result<()> {
+        let columns = vec![
+            DataConfig::new(DataType::Text(64)),
+            DataConfig::new(DataType::Bytes(64)),
+            DataConfig::new(DataType::Number),
+        ];
+        let table = Table::new(TableId::new(), TableConfig::new(&columns)?, None)?;
+
+        let handles = (0..300usize)
+            .map(|n| {
+                let text = (n % 7 != 0)
+                    .then(|| Text::try_from_str(&"x".repeat(n % 61), 64))
+                    .transpose()?;
+                let bytes = primitives::Bytes::try_from_slice(&vec![7; (n * 13) % 64], 64)?;
+
+                Ok(table
+                    .insert_one(vec![
+                        text.map(DataValue::Text),
+                        Some(DataValue::Bytes(bytes)),
+                        Some(DataValue::try_from_any(DataType::Number, n)?),
+                    ])?
+                    .handle)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        // the naive way, copying every value out of the table
+        let naive = |column: usize| -> Result<Vec<(u64, usize)>> {
+            let mut lengths = Vec::new();
+
+            for handle in handles.iter() {
+                let len = match &table.get_versioned(handle)?.0[column] {
+                    Some(DataValue::Text(text)) => text.as_str().len(),
+                    Some(DataValue::Bytes(bytes)) => bytes.as_slice().len(),
+                    _ => continue,
+                };
+
+                lengths.push((table.seq_of(handle)?, len));
+            }
+
+            lengths.sort();
+            Ok(lengths)
+        };
+
+        for column in 0..2 {
+            let mut fast = table
+                .lengths(column)?
+                .into_iter()
+                .map(|(handle, len)| Ok((table.seq_of(&handle)?, len)))
+                .collect::<Result<Vec<_>>>()?;
+            fast.sort();
+
+            let naive = naive(column)?;
+            assert_eq!(fast, naive);
+
+            let long = table.scan_length(column, |len| len > 40)?;
+            assert_eq!(
+                long.len(),
+                naive.iter().filter(|(_, len)| *len > 40).count()
+            );
+
+            let avg = naive.iter().map(|(_, len)| *len).sum::<usize>() as f64 / naive.len() as f64;
+            assert_eq!(table.avg_length(column)?, Some(avg));
+        }
+
+        assert!(table.lengths(2).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_query_cancellation() -> Result<()> {
+        use std::time::{Duration, Instant};
+
+        let columns = vec![DataConfig::new(DataType::Text(8))];
+        let table = Table::new(TableId::new(), TableConfig::new(&columns)?, None)?;
+        let text = Text::try_from_str("abc", 8)?;
+
+        for _ in 0..2000 {
+            table.insert_one(vec![Some(DataValue::Text(text.clone()))])?;
+        }
+
+        // the whole scan would take two seconds
+        let slow = |_: usize| {
+            std::thread::sleep(Duration::from_millis(1));
+            true
+        };
+
+        let timeout = Duration::from_millis(50);
+        let timed = table.with_cancellation(CancellationToken::with_timeout(timeout));
+        let started = Instant::now();
+        let err = timed.scan_length(0, slow).unwrap_err();
+
+        assert_eq!(
+            err.downcast_ref::<QueryTimeout>(),
+            Some(&QueryTimeout { timeout })
+        );
+        assert!(started.elapsed() < timeout + Duration::from_millis(500));
+
+        // the scan let go of the column store on its way out
+        drop(table.get_column_store(0)?.write());
+        table.insert_one(vec![Some(DataValue::Text(text.clone()))])?;
+
+        let token = CancellationToken::new();
+        let cancelled = table.with_cancellation(token.clone());
+
+        assert_eq!(
+            cancelled
+                .scan_eq(0, &DataValue::Text(text.clone()))?
+                .rows
+                .len(),
+            2001
+        );
+
+        token.cancel();
+
+        for err in [
+            cancelled
+                .scan_eq(0, &DataValue::Text(text.clone()))
+                .unwrap_err(),
+            cancelled.read_rows().unwrap_err(),
+            cancelled.avg_length(0).unwrap_err(),
+            TableError::from(join_eq(&cancelled, 0, &table, 0).unwrap_err()),
+        ] {
+            assert!(err.is::<QueryCancelled>(), "{}", err);
+        }
+
+        // the table it was made from goes on as before
+        assert_eq!(table.read_rows()?.rows.len(), 2001);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_column_config_limits() {
+        let too_many = vec![DataConfig::new(DataType::Bool); MAX_COLUMNS + 1];
+        let err = ColumnConfigs::new(&too_many).unwrap_err();
+        assert!(err.to_string().contains("MAX_COLUMNS"));
+
+        let too_long = [DataConfig::new(DataType::Text(
+            limits::MAX_TEXT_LEN as u32 + 1,
+        ))];
+        let err = ColumnConfigs::new(too_long).unwrap_err();
+        assert!(format!("{:#}", err).contains("MAX_TEXT_LEN"));
+
+        let too_long = [DataConfig::new(DataType::Bytes(
+            limits::MAX_BYTES_LEN as u32 + 1,
+        ))];
+        let err = ColumnConfigs::new(too_long).unwrap_err();
+        assert!(format!("{:#}", err).contains("MAX_BYTES_LEN"));
+
+        assert_eq!(limits::limits().max_columns, MAX_COLUMNS);
+    }
+
+    #[test]
+    fn test_zero_capacity_columns() -> Result<()> {
+        use primitives::{Bytes, CastKind};
+
+        for empty in [DataType::Text(0), DataType::Bytes(0)] {
+            assert!(empty.validate().is_err());
+            assert!(ColumnConfigs::new([DataConfig::new(empty)]).is_err());
+            assert_eq!(DataType::Text(8).can_cast_to(empty), CastKind::Forbidden);
+            assert_eq!(empty.can_cast_to(empty), CastKind::Forbidden);
+            assert!(DataValue::try_from_any(empty, "").is_err());
+            assert!(DataValue::try_from_any(DataType::Text(8), "a")?
+                .try_cast(empty)
+                .is_err());
+        }
+
+        assert!(Text::try_from_str("", 0).is_err());
+        assert!(Bytes::new(0).is_err());
+
+        // a capacity of 1 is the smallest there is, and still holds the empty string
+        let table = fixture_table(&[("label", DataType::Text(1))]);
+        let empty = DataValue::try_from_any(DataType::Text(1), "")?;
+        table.insert_one(vec![Some(empty.clone())])?;
+
+        assert_eq!(table.read_rows()?.rows[0].1[0], Some(empty));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_materialized_view() -> Result<()> {
+        use rand::{rngs::StdRng, Rng, SeedableRng};
+
+        let source = fixture_table(&[("region", DataType::Text(8)), ("amount", DataType::Number)]);
+        let counts = fixture_table(&[("region", DataType::Text(8)), ("count", DataType::Number)]);
+        let avgs = fixture_table(&[("region", DataType::Text(8)), ("avg", DataType::Number)]);
+
+        let row = |rng: &mut StdRng| -> Result<Vec<Option<DataValue>>> {
+            let region = ["north", "south", "east", "west"][rng.gen_range(0..4)];
+
+            Ok(vec![
+                rng.gen_bool(0.9)
+                    .then(|| DataValue::try_from_any(DataType::Text(8), region))
+                    .transpose()?,
+                rng.gen_bool(0.8)
+                    .then(|| DataValue::try_from_any(DataType::Number, rng.gen_range(-50i64..50)))
+                    .transpose()?,
+            ])
+        };
+
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut live = Vec::new();
+
+        // the view picks up the rows already there
+        for _ in 0..10 {
+            live.push(source.insert_one(row(&mut rng)?)?.handle);
+        }
+
+        let count_view = MaterializedView::new(&source, 0, Aggregate::Count, &counts)?;
+        let avg_view = MaterializedView::new(&source, 0, Aggregate::Avg(1), &avgs)?;
+
+        for _ in 0..100 {
+            match rng.gen_range(0..4) {
+                0 | 1 => live.push(source.insert_one(row(&mut rng)?)?.handle),
+                2 if !live.is_empty() => {
+                    let handle = live.swap_remove(rng.gen_range(0..live.len()));
+                    assert!(source.delete(handle)?);
+                }
+                3 if !live.is_empty() => {
+                    let handle = &live[rng.gen_range(0..live.len())];
+                    let (_, gen) = source.get_versioned(handle)?;
+                    source.update_if(handle, gen, row(&mut rng)?)?;
+                }
+                _ => {}
+            }
+        }
+
+        count_view.verify()?;
+        avg_view.verify()?;
+
+        for table in [&source, &counts, &avgs] {
+            let report = table.check_integrity()?;
+            assert!(report.is_ok(), "{}", report);
+        }
+
+        let groups = live
+            .iter()
+            .map(|handle| Ok(source.get_versioned(handle)?.0[0].clone()))
+            .collect::<Result<std::collections::HashSet<_>>>()?;
+
+        assert_eq!(counts.row_count(), groups.len());
+        assert_eq!(avgs.row_count(), groups.len());
+
+        // a rebuilt view matches the incrementally maintained one
+        avg_view.rebuild()?;
+        avg_view.verify()?;
+        assert_eq!(avgs.row_count(), groups.len());
+
+        // truncating the source leaves every group empty
+        drop(live);
+        source.truncate()?;
+        count_view.verify()?;
+        avg_view.verify()?;
+        assert!(counts.is_empty());
+        assert!(avgs.is_empty());
+
+        assert!(MaterializedView::new(&source, 0, Aggregate::Sum(0), &counts).is_err());
+        assert!(MaterializedView::new(&source, 0, Aggregate::Count, &source).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_window_functions() -> Result<()> {
+        use crate::window::{self, SortOrder, WindowFunc};
+
+        let text = |s: &str| Some(DataValue::Text(Text::try_from_str(s, 8).unwrap()));
+        let int = |n: i64| Some(DataValue::Number(Number::Integer(n)));
+
+        // (region, day, amount), out of order
+        let rows = vec![
+            vec![text("west"), int(2), int(5)],
+            vec![text("east"), int(3), int(30)],
+            vec![text("east"), int(1), int(10)],
+            vec![text("west"), int(1), None],
+            vec![text("east"), int(2), int(20)],
+            vec![text("west"), int(3), int(7)],
+        ];
+
+        let funcs = [
+            WindowFunc::RowNumber,
+            WindowFunc::RunningSum(2),
+            WindowFunc::Lag(2, 1),
+            WindowFunc::Lead(2, 1),
+        ];
+        let out = window::apply(rows, Some(0), &[(1, SortOrder::Asc)], &funcs)?;
+
+        assert_eq!(
+            out,
+            vec![
+                vec![
+                    text("east"),
+                    int(1),
+                    int(10),
+                    int(1),
+                    int(10),
+                    None,
+                    int(20)
+                ],
+                vec![
+                    text("east"),
+                    int(2),
+                    int(20),
+                    int(2),
+                    int(30),
+                    int(10),
+                    int(30)
+                ],
+                vec![
+                    text("east"),
+                    int(3),
+                    int(30),
+                    int(3),
+                    int(60),
+                    int(20),
+                    None
+                ],
+                vec![text("west"), int(1), None, int(1), None, None, int(5)],
+                vec![text("west"), int(2), int(5), int(2), int(5), None, int(7)],
+                vec![text("west"), int(3), int(7), int(3), int(12), int(5), None],
+            ]
+        );
+
+        // descending and without partitions, lagging past the start of the rows
+        let rows = out.into_iter().map(|row| row[..3].to_vec()).collect();
+        let out = window::apply(
+            rows,
+            None,
+            &[(1, SortOrder::Desc), (0, SortOrder::Asc)],
+            &[WindowFunc::RunningCount, WindowFunc::Lag(0, 2)],
+        )?;
+
+        let tail = out.iter().map(|row| row[3..].to_vec()).collect::<Vec<_>>();
+        assert_eq!(
+            tail,
+            vec![
+                vec![int(1), None],
+                vec![int(2), None],
+                vec![int(3), text("east")],
+                vec![int(4), text("west")],
+                vec![int(5), text("east")],
+                vec![int(6), text("west")],
+            ]
+        );
+
+        let overflowing = vec![vec![int(1), int(i64::MAX)], vec![int(2), int(1)]];
+        let err = window::apply(
+            overflowing,
+            None,
+            &[(0, SortOrder::Asc)],
+            &[WindowFunc::RunningSum(1)],
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("overflow"), "{}", err);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_histogram_percentiles() -> Result<()> {
+        use rand::{rngs::StdRng, Rng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(11);
+        let table = fixture_table(&[("n", DataType::Number)]);
+
+        // skewed towards small values, like most latencies
+        let mut values = (0..100_000)
+            .map(|_| rng.gen::<f64>().powi(3) * 1000.0)
+            .collect::<Vec<_>>();
+        let rows = values
+            .iter()
+            .map(|n| Ok(vec![Some(DataValue::try_from_any(DataType::Number, *n)?)]))
+            .collect::<Result<Vec<_>>>()?;
+        assert!(matches!(table.insert(rows)?, InsertState::Done(_)));
+
+        let histogram = table.histogram(0, 200)?;
+        values.sort_by(f64::total_cmp);
+
+        assert!(!histogram.exact);
+        assert_eq!(histogram.count, 100_000);
+        assert_eq!(histogram.buckets.len(), 200);
+
+        let width = (histogram.max - histogram.min) / 200.0;
+
+        for q in [0.0, 0.25, 0.5, 0.9, 0.95, 0.99, 1.0] {
+            let exact = values[(q * (values.len() - 1) as f64).round() as usize];
+            let estimate = histogram.p(q);
+
+            assert!(
+                (estimate - exact).abs() <= width,
+                "p{}: estimated {} but is {}",
+                q * 100.0,
+                estimate,
+                exact
+            );
+        }
+
+        // fewer distinct values than buckets are counted exactly
+        let small = fixture_table(&[("n", DataType::Number)]);
+        let rows = (0..1000)
+            .map(|n| {
+                Ok(vec![Some(DataValue::try_from_any(
+                    DataType::Number,
+                    n % 7,
+                )?)])
+            })
+            .collect::<Result<Vec<_>>>()?;
+        assert!(matches!(small.insert(rows)?, InsertState::Done(_)));
+
+        let histogram = small.histogram(0, 16)?;
+
+        assert!(histogram.exact);
+        assert_eq!(histogram.buckets.len(), 7);
+        assert_eq!(histogram.p(0.5), 3.0);
+        assert_eq!(histogram.p(0.99), 6.0);
+        assert_eq!(histogram.p(0.0), 0.0);
+
+        // merging gives the same counts however the parts are grouped
+        for distinct in [5, 1000] {
+            let mut parts = (0..3)
+                .map(|_| {
+                    let mut part = Histogram::new(0.0, distinct as f64, 8)?;
+
+                    for _ in 0..500 {
+                        part.add(rng.gen_range(0..=distinct) as f64)?;
+                    }
+
+                    Ok(part)
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let c = parts.pop().unwrap();
+            let b = parts.pop().unwrap();
+            let a = parts.pop().unwrap();
+
+            let mut left = a.clone();
+            left.merge(&b)?;
+            left.merge(&c)?;
+
+            let mut right = b.clone();
+            right.merge(&c)?;
+            let mut grouped = a.clone();
+            grouped.merge(&right)?;
+
+            assert_eq!(left, grouped);
+            assert_eq!(left.finish().count, 1500);
+            assert_eq!(left.finish().exact, distinct < 8);
+        }
+
+        assert!(Histogram::new(0.0, 1.0, 8)?
+            .merge(&Histogram::new(0.0, 2.0, 8)?)
+            .is_err());
+        assert!(table.histogram(1, 8).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_overflow_policy() -> Result<()> {
+        let text = |s: &str, cap| {
+            Ok::<_, anyhow::Error>(Some(DataValue::Text(Text::try_from_str(s, cap)?)))
+        };
+        let n = |n: u64| DataValue::try_from_any(DataType::Number, n).map(Some);
+
+        // é straddles the column's capacity, so truncating can't keep any of it
+        let long = "abcdefgé!";
+        let rows = || -> Result<Vec<Vec<Option<DataValue>>>> {
+            Ok(vec![
+                vec![text("ok", 8)?, n(0)?],
+                vec![text(long, 40)?, n(1)?],
+                vec![text("short", 8)?, n(2)?],
+                vec![text("fits", 40)?, n(3)?],
+            ])
+        };
+
+        let mut inserted = Vec::new();
+
+        for policy in [
+            OverflowPolicy::Error,
+            OverflowPolicy::Truncate,
+            OverflowPolicy::RejectRow,
+        ] {
+            let columns = [
+                DataConfig::new(DataType::Text(8)).with_overflow(policy),
+                DataConfig::new(DataType::Number),
+            ];
+            let table = Table::new(TableId::new(), TableConfig::new(columns)?, None)?;
+
+            let built = columns[0].try_new_value(long);
+            let single = table.insert_one(rows()?.swap_remove(1));
+
+            let errors = match table.insert(rows()?)? {
+                InsertState::Done(_) => Vec::new(),
+                InsertState::Partial { errors, .. } => errors,
+            };
+
+            let labels = table
+                .scan_since(0)
+                .map(
+                    |(_, handle)| match table.get_versioned(&handle)?.0.swap_remove(0) {
+                        Some(DataValue::Text(text)) => Ok(text.as_str().to_string()),
+                        other => anyhow::bail!("unexpected label {:?}", other),
+                    },
+                )
+                .collect::<Result<Vec<_>>>()?;
+
+            match policy {
+                OverflowPolicy::Error => {
+                    assert!(matches!(
+                        built,
+                        Err(ValueError {
+                            reason: ValueErrorReason::TooLong { len: 10, cap: 8 },
+                            ..
+                        })
+                    ));
+                    assert!(single.is_err());
+                    assert!(matches!(
+                        errors.as_slice(),
+                        [
+                            (
+                                1,
+                                InsertError::InvalidRow {
+                                    error: RowValidationError::TypeMismatch { column: 0, .. },
+                                    ..
+                                }
+                            ),
+                            (
+                                3,
+                                InsertError::InvalidRow {
+                                    error: RowValidationError::TypeMismatch { column: 0, .. },
+                                    ..
+                                }
+                            ),
+                        ]
+                    ));
+                }
+                OverflowPolicy::Truncate => {
+                    assert_eq!(
+                        built.map_err(anyhow::Error::from)?,
+                        columns[0].try_new_value("abcdefg")?
+                    );
+                    assert!(single.is_ok());
+                    assert!(errors.is_empty());
+                }
+                OverflowPolicy::RejectRow => {
+                    assert!(built.is_err());
+                    assert_eq!(
+                        single.unwrap_err().downcast_ref::<RowValidationError>(),
+                        Some(&RowValidationError::TooLong {
+                            column: 0,
+                            name: "column 0".to_string(),
+                            len: 10,
+                            cap: 8,
+                        })
+                    );
+                    assert!(matches!(
+                        errors.as_slice(),
+                        [(
+                            1,
+                            InsertError::InvalidRow {
+                                error: RowValidationError::TooLong {
+                                    len: 10,
+                                    cap: 8,
+                                    ..
+                                },
+                                ..
+                            }
+                        )]
+                    ));
+                }
+            }
+
+            inserted.push(labels);
+        }
+
+        assert_eq!(inserted[0], vec!["ok", "short"]);
+        assert_eq!(
+            inserted[1],
+            vec!["abcdefg", "ok", "abcdefg", "short", "fits"]
+        );
+        assert_eq!(inserted[2], vec!["ok", "short", "fits"]);
+
+        // the policy survives being written out with the rest of the config
+        let config = TableConfig::new([
+            DataConfig::new(DataType::Text(8)).with_overflow(OverflowPolicy::RejectRow)
+        ])?;
+        let mut decoded = TableConfig::new([DataConfig::new(DataType::Bool)])?;
+        decoded.init_from_bytes(&primitives::into_bytes!(config, TableConfig)?)?;
+
+        assert_eq!(
+            decoded.columns.get(0).unwrap().overflow,
+            OverflowPolicy::RejectRow
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_overflow() -> Result<()> {
+        let long = || DataValue::Text(Text::try_from_str("abcdefghij", 40).unwrap());
+
+        for policy in [OverflowPolicy::Truncate, OverflowPolicy::RejectRow] {
+            let columns = [DataConfig::new(DataType::Text(8)).with_overflow(policy)];
+            let table = Table::new(TableId::new(), TableConfig::new(columns)?, None)?;
+            let row = table.insert_one(vec![Some(columns[0].try_new_value("ok")?)])?;
+
+            let updated = table.update_one(row.record_id, vec![(0, Some(long()))]);
+            let (stored, _) = table.get_versioned(&row.handle)?;
+
+            match policy {
+                OverflowPolicy::Truncate => {
+                    assert!(updated.is_ok());
+                    assert_eq!(stored, vec![Some(columns[0].try_new_value("abcdefgh")?)]);
+                }
+                _ => {
+                    assert_eq!(
+                        updated.unwrap_err().downcast_ref::<RowValidationError>(),
+                        Some(&RowValidationError::TooLong {
+                            column: 0,
+                            name: "column 0".to_string(),
+                            len: 10,
+                            cap: 8,
+                        })
+                    );
+                    assert_eq!(stored, vec![Some(columns[0].try_new_value("ok")?)]);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_normalization() -> Result<()> {
+        let all = Normalization {
+            trim: true,
+            collapse_whitespace: true,
+            lowercase: true,
+        };
+        let trim = Normalization {
+            trim: true,
+            ..Normalization::NONE
+        };
+
+        assert_eq!(all.apply("  Alice \t Smith\n"), "alice smith");
+        assert_eq!(trim.apply("  Alice \t Smith\n"), "Alice \t Smith");
+        assert_eq!(
+            Normalization {
+                collapse_whitespace: true,
+                ..Normalization::NONE
+            }
+            .apply("  Alice  "),
+            " Alice "
+        );
+        assert!(matches!(
+            all.apply("alice smith"),
+            std::borrow::Cow::Borrowed(_)
+        ));
+
+        // only fits once trimmed
+        let name = DataConfig::new(DataType::Text(5)).with_normalization(trim);
+        assert_eq!(
+            name.try_new_value("  Alice ")?,
+            name.try_new_value("Alice")?
+        );
+        assert!(matches!(
+            name.try_new_value("  Alice B"),
+            Err(ValueError {
+                reason: ValueErrorReason::TooLong { len: 7, cap: 5 },
+                ..
+            })
+        ));
+
+        let bytes = DataConfig::new(DataType::Bytes(5)).with_normalization(trim);
+        assert!(TableConfig::new([bytes]).is_err());
+
+        let columns = [DataConfig::new(DataType::Text(20)).with_normalization(all)];
+        let table = Table::new(TableId::new(), TableConfig::new(columns)?, None)?;
+        let text =
+            |s: &str| Ok::<_, anyhow::Error>(Some(DataValue::Text(Text::try_from_str(s, 20)?)));
+
+        let inserted = table.insert_one(vec![text(" Alice  SMITH ")?])?;
+        assert_eq!(inserted.values, vec![text("alice smith")?]);
+
+        table.insert(vec![vec![text("BOB\tJones")?]])?;
+
+        let (_, gen) = table.get_versioned(&inserted.handle)?;
+        table.update_if(&inserted.handle, gen, vec![text("  ALICE   jones")?])?;
+
+        let stored = table
+            .scan_since(0)
+            .map(|(_, handle)| Ok(table.get_versioned(&handle)?.0))
+            .collect::<Result<Vec<_>>>()?;
+
+        assert_eq!(
+            stored,
+            vec![vec![text("alice jones")?], vec![text("bob jones")?]]
+        );
+
+        // the options survive being written out with the rest of the config
+        let config = TableConfig::new(columns)?;
+        let mut decoded = TableConfig::new([DataConfig::new(DataType::Bool)])?;
+        decoded.init_from_bytes(&primitives::into_bytes!(config, TableConfig)?)?;
+
+        assert_eq!(decoded.columns.get(0).unwrap().normalize, all);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_logical_types() -> Result<()> {
+        let cases = [
+            (LogicalType::Email, "foobar@example.com", true),
+            (LogicalType::Email, "first.last+tag@mail.example.co", true),
+            (LogicalType::Email, "not-an-email", false),
+            (LogicalType::Email, "foo@bar", false),
+            (LogicalType::Email, "foo @example.com", false),
+            (LogicalType::Phone, "123-456-7890", true),
+            (LogicalType::Phone, "+44 (20) 7946 0958", true),
+            (LogicalType::Phone, "12345", false),
+            (LogicalType::Phone, "call me", false),
+            (LogicalType::Url, "https://example.com/a?b=c", true),
+            (LogicalType::Url, "ftp://example.com", false),
+            (
+                LogicalType::Uuid,
+                "67e55044-10b1-426f-9247-bb680e5fe0c8",
+                true,
+            ),
+            (LogicalType::Uuid, "67e5504410b1426f9247bb680e5fe0c8", false),
+        ];
+
+        for (logical_type, text, valid) in cases {
+            assert_eq!(
+                logical_type.check(text),
+                valid,
+                "{} {:?}",
+                logical_type,
+                text
+            );
+        }
+
+        let email = DataConfig::new(LogicalType::Email.base_type())
+            .with_logical_type(LogicalType::Email)
+            .with_name(InternalString::new("email")?);
+        let phone = DataConfig::new(LogicalType::Phone.base_type())
+            .with_logical_type(LogicalType::Phone)
+            .with_logical_check(false);
+
+        assert!(email.try_new_value("foobar@example.com").is_ok());
+        assert_eq!(
+            email.try_new_value("not-an-email").unwrap_err(),
+            ValueError {
+                column: Some("email".to_string()),
+                expected: email.data_type,
+                reason: ValueErrorReason::NotLogicalType {
+                    logical_type: LogicalType::Email
+                },
+            }
+        );
+        assert!(phone.try_new_value("call me").is_ok());
+
+        let number = DataConfig::new(DataType::Number).with_logical_type(LogicalType::Email);
+        assert!(TableConfig::new([number]).is_err());
+
+        let columns = [email, phone];
+        let table = Table::new(TableId::new(), TableConfig::new(columns)?, None)?;
+        let text = |s: &str, cap: u32| {
+            Ok::<_, anyhow::Error>(Some(DataValue::Text(Text::try_from_str(s, cap as usize)?)))
+        };
+
+        table.insert_one(vec![text("foobar@example.com", 120)?, text("call me", 20)?])?;
+
+        // values built without the column's config are still checked
+        let err = table
+            .insert_one(vec![text("not-an-email", 120)?])
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RowValidationError>(),
+            Some(RowValidationError::NotLogicalType {
+                column: 0,
+                logical_type: LogicalType::Email,
+                ..
+            })
+        ));
+
+        let InsertState::Partial { handles, errors } = table.insert(vec![
+            vec![text("other@example.com", 120)?],
+            vec![text("foo@bar", 120)?],
+        ])?
+        else {
+            panic!("expected a partial insert");
+        };
+
+        assert_eq!(handles.len(), 1);
+        assert_eq!(errors.iter().map(|(i, _)| *i).collect::<Vec<_>>(), vec![1]);
+        assert_eq!(table.row_count(), 2);
+
+        // updates are checked as inserts are
+        let (_, first) = table.scan_since(0).next().expect("a row");
+        let (before, gen) = table.get_versioned(&first)?;
+        let err = table
+            .update_if(&first, gen, vec![text("not-an-email", 120)?])
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RowValidationError>(),
+            Some(RowValidationError::NotLogicalType {
+                column: 0,
+                logical_type: LogicalType::Email,
+                ..
+            })
+        ));
+        assert_eq!(table.get_versioned(&first)?, (before, gen));
+
+        // the logical type and whether it's checked survive being written out
+        let config = TableConfig::new(columns)?;
+        let mut decoded = TableConfig::new([DataConfig::new(DataType::Bool)])?;
+        decoded.init_from_bytes(&primitives::into_bytes!(config, TableConfig)?)?;
+
+        let (email, phone) = (
+            decoded.columns.get(0).unwrap(),
+            decoded.columns.get(1).unwrap(),
+        );
+        assert_eq!(
+            (email.logical_type, email.check_logical_type),
+            (Some(LogicalType::Email), true)
+        );
+        assert_eq!(
+            (phone.logical_type, phone.check_logical_type),
+            (Some(LogicalType::Phone), false)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_snapshot_join() -> Result<()> {
+        use std::{
+            sync::{
+                atomic::{AtomicBool, Ordering},
+                Arc,
+            },
+            thread,
+            time::{Duration, Instant},
+        };
+
+        let orders = fixture_table(&[("id", DataType::Number)]);
+        let items = fixture_table(&[("order", DataType::Number)]);
+        let number = |n: usize| DataValue::try_from_any(DataType::Number, n);
+
+        for n in 0..200 {
+            orders.insert_one(vec![Some(number(n)?)])?;
+            items.insert_one(vec![Some(number(n)?)])?;
+        }
+
+        // every order is inserted before its item, so an item without its order is half a pair
+        let stop = Arc::new(AtomicBool::new(false));
+        let writer = thread::spawn({
+            let (orders, items, stop) = (orders.clone(), items.clone(), stop.clone());
+
+            move || -> Result<()> {
+                let mut n = 200;
+
+                while !stop.load(Ordering::Relaxed) {
+                    orders.insert_one(vec![Some(number(n)?)])?;
+                    items.insert_one(vec![Some(number(n)?)])?;
+                    n += 1;
+
+                    // each join takes longer the more rows there are, so a writer going flat out
+                    // would grow the tables faster with every join
+                    thread::sleep(Duration::from_millis(1));
+                }
+
+                Ok(())
+            }
+        });
+
+        // the orders are read before the items, so an item inserted in between goes unmatched
+        // unless both are read as of the same instant
+        for _ in 0..50 {
+            let snapshot = snapshot_tables([("orders", &orders), ("items", &items)]);
+            let (orders, items) = (
+                snapshot.get("orders").unwrap(),
+                snapshot.get("items").unwrap(),
+            );
+
+            let joined = join_left(items, 0, orders, 0)?;
+
+            assert!(joined.iter().all(|(_, order)| order.is_some()));
+            assert_eq!(joined.len() as u64, items.seq());
+        }
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let mut seen_half_pair = false;
+
+        while !seen_half_pair && Instant::now() < deadline {
+            seen_half_pair = join_left(&items, 0, &orders, 0)?
+                .iter()
+                .any(|(_, order)| order.is_none());
+        }
+
+        stop.store(true, Ordering::Relaxed);
+        writer.join().expect("writer panicked")?;
+
+        assert!(seen_half_pair, "live tables never showed half a pair");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_column_defaults() -> Result<()> {
+        use std::{collections::BTreeSet, thread};
+
+        let columns = vec![
+            DataConfig::new(DataType::Number).with_default(GeneratorKind::Sequence {
+                start: 1000,
+                step: 5,
+            }),
+            DataConfig::new(DataType::O64).with_default(GeneratorKind::RandomO64),
+            DataConfig::new(DataType::Timestamp).with_default(GeneratorKind::NowTimestamp),
+            DataConfig::new(DataType::Text(20)),
+        ];
+        let id = TableId::new();
+        let dir = TempDir::new("mem_table_defaults");
+        let config = TableConfig::new_persisted(&columns, &dir)?;
+        let sequence = |row: &InsertedRow| match row.values[0] {
+            Some(DataValue::Number(Number::Integer(n))) => n,
+            ref other => panic!("expected a sequence value, got {:?}", other),
+        };
+
+        let last = {
+            let table = Table::new(id, config, None)?;
+            let inserters = (0..4)
+                .map(|_| {
+                    let table = table.clone();
+
+                    thread::spawn(move || -> Result<Vec<i64>> {
+                        (0..50)
+                            .map(|_| Ok(sequence(&table.insert_one(vec![])?)))
+                            .collect()
+                    })
+                })
+                .collect::<Vec<_>>();
+            let mut handed_out = BTreeSet::new();
+
+            for inserter in inserters {
+                let values = inserter.join().expect("inserter panicked")?;
+
+                assert!(values.windows(2).all(|pair| pair[0] < pair[1]));
+                handed_out.extend(values);
+            }
+
+            assert!(handed_out
+                .iter()
+                .copied()
+                .eq((0..200).map(|n| 1000 + n * 5)));
+
+            let row = table.insert_one(vec![None, None, None, None])?;
+            assert!(matches!(row.values[1], Some(DataValue::O64(_))));
+            assert!(matches!(row.values[2], Some(DataValue::Timestamp(_))));
+            assert_eq!(row.values[3], None);
+
+            // a value given for the column wins over its default
+            let given = table.insert_one(vec![Some(DataValue::Number(7i64.into()))])?;
+            assert_eq!(sequence(&given), 7);
+            assert_ne!(given.values[1], row.values[1]);
+
+            table.close()?;
+            sequence(&row)
+        };
+
+        // what was left of the batch is skipped, but nothing handed out comes round again
+        let table = Table::new(id, config, None)?;
+        let next = sequence(&table.insert_one(vec![])?);
+        assert!(next > last);
+        assert!(next <= last + SEQUENCE_BATCH * 5);
+
+        table.close()?;
+
+        let mistyped = DataConfig::new(DataType::Text(8)).with_default(GeneratorKind::RandomO64);
+        assert!(TableConfig::new(vec![mistyped]).is_err());
+
+        let standing_still = DataConfig::new(DataType::Number)
+            .with_default(GeneratorKind::Sequence { start: 0, step: 0 });
+        assert!(TableConfig::new(vec![standing_still]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_default_fails_one_row() -> Result<()> {
+        // the sequence has nothing after its first value, so it can't hand that one out
+        let columns = vec![
+            DataConfig::new(DataType::Number).with_default(GeneratorKind::Sequence {
+                start: i64::MAX,
+                step: 1,
+            }),
+            DataConfig::new(DataType::Bool),
+        ];
+        let table = Table::new(TableId::new(), TableConfig::new(&columns)?, None)?;
+        let number = |n: i64| Some(DataValue::Number(n.into()));
+
+        let InsertState::Partial { handles, errors } = table.insert(vec![
+            vec![number(1), Some(DataValue::Bool(true))],
+            vec![None, Some(DataValue::Bool(false))],
+            vec![number(3)],
+        ])?
+        else {
+            panic!("expected a partial insert");
+        };
+
+        assert_eq!(handles.len(), 2);
+        assert!(matches!(
+            errors.as_slice(),
+            [(1, InsertError::DefaultFailed { values, .. })]
+                if *values == vec![None, Some(DataValue::Bool(false))]
+        ));
+        assert_eq!(table.row_count(), 2);
+
+        Ok(())
+    }
+
+    /// A coarse comparison of scans with and without prefetching, for running by hand with
+    /// `cargo test --release -p mem_table bench_scan_prefetch -- --ignored --nocapture`. The file is
+    /// only cold the first time it's scanned, so the caches are best dropped in between, e.g.
+    /// with `echo 3 > /proc/sys/vm/drop_caches`, by running it once per setting with `PREFETCH`
+    /// set to `0` or `1`.
+    #[test]
+    #[ignore]
+    fn bench_scan_prefetch() -> Result<()> {
+        let columns = vec![DataConfig::new(DataType::Number)];
+        let id = TableId::new();
+        let dir = TempDir::new("mem_table_bench_prefetch");
+        let config = TableConfig::new_persisted(&columns, &dir)?;
+
+        {
+            let table = Table::new(id, config, None)?;
+            let rows = (0..2_000_000i64)
+                .map(|n| vec![Some(DataValue::Number(n.into()))])
+                .collect::<Vec<_>>();
+
+            table.insert(rows)?;
+            table.close()?;
+        }
+
+        let settings = match std::env::var("PREFETCH").as_deref() {
+            Ok("0") => vec![false],
+            Ok(_) => vec![true],
+            Err(_) => vec![false, true],
+        };
+
+        for prefetch in settings {
+            let scan = ScanOptions::default().with_prefetch(prefetch);
+            let table = Table::new(id, config.with_scan_options(scan), None)?;
+            let started = std::time::Instant::now();
+            let histogram = table.histogram(0, 64)?;
+
+            println!(
+                "prefetch {}: {} values in {:?}",
+                prefetch,
+                histogram.count,
+                started.elapsed()
+            );
+
+            table.close()?;
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_wal_replay_order() -> Result<()> {
+        let columns = vec![DataConfig::new(DataType::Number)];
+        let dir = TempDir::new("mem_table_wal");
+        let path = dir.join("wal.log");
+        let config = TableConfig::new(&columns)?.with_wal(WalConfig::new(&path)?);
+        let table = Table::new(TableId::new(), config, None)?;
+        let number = |n: i64| vec![Some(DataValue::Number(n.into()))];
+
+        let mut handles = Vec::new();
+
+        for n in 0..5 {
+            handles.push(table.insert_one(number(n))?.handle);
+        }
+
+        let InsertState::Done(batch) = table.insert((5..10).map(number))? else {
+            panic!("expected the whole batch to be inserted");
+        };
+        handles.extend(batch);
+
+        // each insert is acknowledged only once it's logged, the batch as one commit
+        assert_eq!(table.wal().map(|wal| wal.stats().commits), Some(6));
+
+        let expected = handles
+            .iter()
+            .map(|handle| {
+                Ok(WalRecord {
+                    seq: table.seq_of(handle)?,
+                    values: table.get_versioned(handle)?.0,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        table.close()?;
+
+        let records = read_wal(&path)?;
+
+        assert_eq!(records, expected);
+        assert!(records.windows(2).all(|pair| pair[0].seq < pair[1].seq));
+
+        // replaying the log into a fresh table gives back the same rows, in the same order
+        let replayed = Table::new(TableId::new(), TableConfig::new(&columns)?, None)?;
+        replayed.insert(records.into_iter().map(|record| record.values))?;
+
+        let rows = replayed
+            .read_rows()?
+            .rows
+            .into_iter()
+            .map(|(_, values)| values);
+
+        assert_eq!(
+            rows.collect::<Vec<_>>(),
+            (0..10).map(number).collect::<Vec<_>>()
+        );
+
+        Ok(())
+    }
+
+    /// An insert the log can't take isn't kept, since a replay wouldn't bring it back.
+    #[test]
+    fn test_wal_unlogged_insert_is_undone() -> Result<()> {
+        let columns = vec![DataConfig::new(DataType::Number)];
+        let dir = TempDir::new("mem_table_wal_undo");
+        let path = dir.join("wal.log");
+        let config = TableConfig::new(&columns)?
+            .with_primary_key([0])?
+            .with_wal(WalConfig::new(&path)?);
+        let table = Table::new(TableId::new(), config, None)?;
+        let number = |n: i64| vec![Some(DataValue::Number(n.into()))];
+
+        table.insert_one(number(1))?;
+        table.wal().expect("wal").close()?;
+
+        assert!(table.insert_one(number(2)).is_err());
+        assert!(table.insert((3..6).map(number)).is_err());
+        assert_eq!(table.row_count(), 1);
+        assert_eq!(table.scan_since(0).count(), 1);
+
+        // the keys of the rows taken out went with them
+        assert!(table.check_integrity()?.is_ok());
+
+        table.close()?;
+
+        Ok(())
+    }
+
+    /// Writes that were never acknowledged may be lost in a crash, but acknowledged ones survive
+    /// it: a group torn by a crash is left out when the log is read back, and only that group.
+    #[test]
+    fn test_wal_crash_window() -> Result<()> {
+        let dir = TempDir::new("mem_table_wal_crash");
+        let path = dir.join("wal.log");
+        let wal = WalWriter::open(WalConfig::new(&path)?)?;
+        let acked = (0..3u64)
+            .map(|seq| WalRecord {
+                seq,
+                values: vec![Some(DataValue::Number((seq as i64).into()))],
+            })
+            .collect::<Vec<_>>();
+
+        for record in acked.iter() {
+            wal.commit(std::slice::from_ref(record))?;
+        }
+
+        wal.close()?;
+        assert!(wal.commit(&acked).is_err());
+
+        // the crash cuts the next group short, after its length but before all of its bytes
+        let torn = serde_json::to_vec(&WalRecord {
+            seq: 3,
+            values: vec![None],
+        })?;
+        let mut file = std::fs::OpenOptions::new().append(true).open(&path)?;
+        std::io::Write::write_all(&mut file, &(torn.len() as u32).to_le_bytes())?;
+        std::io::Write::write_all(&mut file, &torn[..torn.len() / 2])?;
+        drop(file);
+
+        assert_eq!(read_wal(&path)?, acked);
+
+        // even when the length itself is torn
+        let file = std::fs::OpenOptions::new().write(true).open(&path)?;
+        let acked_len = std::fs::metadata(&path)?.len() - 4 - torn.len() as u64 / 2;
+        file.set_len(acked_len + 2)?;
+        drop(file);
+
+        assert_eq!(read_wal(&path)?, acked);
+
+        Ok(())
+    }
+
+    /// Compares 10k small inserts from several threads syncing every commit with syncing them in
+    /// groups. Ignored since it syncs thousands of times, which is slow on real disks.
+    #[test]
+    #[ignore]
+    fn test_wal_group_commit_throughput() -> Result<()> {
+        const THREADS: usize = 8;
+        const INSERTS: usize = 10_000;
+
+        let columns = vec![DataConfig::new(DataType::Number)];
+        let mut runs = Vec::new();
+
+        for sync_mode in [WalSyncMode::PerCommit, WalSyncMode::Grouped] {
+            let dir = TempDir::new("mem_table_wal_bench");
+            let path = dir.join("wal.log");
+            let wal = WalConfig::new(&path)?.with_sync_mode(sync_mode);
+            let table = Table::new(
+                TableId::new(),
+                TableConfig::new(&columns)?.with_wal(wal),
+                None,
+            )?;
+            let started = std::time::Instant::now();
+
+            std::thread::scope(|scope| {
+                for thread in 0..THREADS {
+                    let table = &table;
+
+                    scope.spawn(move || {
+                        for n in (thread..INSERTS).step_by(THREADS) {
+                            table
+                                .insert_one(vec![Some(DataValue::Number((n as i64).into()))])
+                                .unwrap();
+                        }
+                    });
+                }
+            });
+
+            let elapsed = started.elapsed();
+            let stats = table.wal().map(WalWriter::stats).unwrap_or_default();
+
+            println!(
+                "{:?}: {} inserts in {:?}, {:?}",
+                sync_mode, INSERTS, elapsed, stats
+            );
+
+            table.close()?;
+            assert_eq!(read_wal(&path)?.len(), INSERTS);
+
+            runs.push((elapsed, stats));
+        }
+
+        let (per_commit, grouped) = (runs[0], runs[1]);
+
+        assert_eq!(per_commit.1.groups, INSERTS);
+        assert!(grouped.1.groups * 2 < INSERTS, "{:?}", grouped.1);
+        assert!(
+            grouped.0 < per_commit.0,
+            "{:?} vs {:?}",
+            grouped.0,
+            per_commit.0
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_cardinality_estimate() -> Result<()> {
+        let distinct = 50_000;
+        let mut sketch = CardinalitySketch::new();
+
+        for i in 0..1_000_000i64 {
+            sketch.insert(&DataValue::Number((i * 7919 % distinct).into()));
+        }
+
+        let estimate = sketch.estimate() as f64;
+        let error = (estimate - distinct as f64).abs() / distinct as f64;
+        assert!(
+            error < 0.05,
+            "estimated {} of {} distinct values",
+            estimate,
+            distinct
+        );
+
+        let columns = vec![
+            DataConfig::new(DataType::Text(16)).with_cardinality_tracking(),
+            DataConfig::new(DataType::Number),
+        ];
+        let table = Table::new(TableId::new(), TableConfig::new(&columns)?, None)?;
+
+        table.insert((0..20_000).map(|i| {
+            vec![
+                Some(DataValue::Text(
+                    Text::try_from_str(&format!("user{}", i % 2_000), 16).unwrap(),
+                )),
+                Some(DataValue::Number((i as i64).into())),
+            ]
+        }))?;
+
+        let estimate = table.estimated_distinct(0)? as f64;
+        assert!(
+            (estimate - 2_000.0).abs() / 2_000.0 < 0.05,
+            "estimated {}",
+            estimate
+        );
+        assert!(matches!(
+            table.estimated_distinct(1),
+            Err(TableError::Validation(_))
+        ));
+
+        // removed values keep counting, until a truncate starts the sketch over
+        for (_, handle) in table.scan_since(0).take(10_000).collect::<Vec<_>>() {
+            table.delete(handle)?;
+        }
+        assert_eq!(table.estimated_distinct(0)? as f64, estimate);
+
+        table.truncate()?;
+        assert_eq!(table.estimated_distinct(0)?, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_cardinality_reopen() -> Result<()> {
+        let columns = vec![DataConfig::new(DataType::Number).with_cardinality_tracking()];
+        let id = TableId::new();
+        let dir = TempDir::new("mem_table_cardinality");
+        let config = TableConfig::new_persisted(&columns, &dir)?;
+        let row = |i: i64| vec![Some(DataValue::Number((i % 300).into()))];
+
+        let estimate = {
+            let table = Table::new(id, config, None)?;
+            table.insert((0..3_000).map(row))?;
+            let estimate = table.estimated_distinct(0)?;
+            table.close()?;
+            estimate
+        };
+
+        // rows inserted after the sketch was last written are added back when it's loaded
+        let estimate = {
+            let table = Table::new(id, config, None)?;
+            assert_eq!(table.estimated_distinct(0)?, estimate);
+
+            table.insert((3_000i64..6_000).map(|i| vec![Some(DataValue::Number(i.into()))]))?;
+            table.records.sync_all()?;
+            table.get_column_store(0)?.sync_all()?;
+            table.estimated_distinct(0)?
+        };
+
+        let table = Table::new(id, config, None)?;
+        assert_eq!(table.estimated_distinct(0)?, estimate);
+        table.close()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_distinct_view() -> Result<()> {
+        let source = fixture_table(&[("region", DataType::Text(8)), ("user", DataType::Number)]);
+        let exact = fixture_table(&[("region", DataType::Text(8)), ("users", DataType::Number)]);
+        let estimated =
+            fixture_table(&[("region", DataType::Text(8)), ("users", DataType::Number)]);
+        let row = |region: &str, user: i64| -> Result<_> {
+            Ok(vec![
+                Some(DataValue::try_from_any(
+                    DataType::Text(8),
+                    region.to_string(),
+                )?),
+                Some(DataValue::Number(user.into())),
+            ])
+        };
+
+        let mut handles = Vec::new();
+
+        for user in 0..40 {
+            handles.push(source.insert_one(row("north", user % 10)?)?.handle);
+        }
+
+        let exact_view = MaterializedView::new(
+            &source,
+            0,
+            Aggregate::CountDistinct {
+                column: 1,
+                exact: true,
+            },
+            &exact,
+        )?;
+        let estimated_view = MaterializedView::new(
+            &source,
+            0,
+            Aggregate::CountDistinct {
+                column: 1,
+                exact: false,
+            },
+            &estimated,
+        )?;
+
+        let users = |table: &Table| -> Result<Option<DataValue>> {
+            let (_, handle) = table.scan_since(0).next().expect("a group row");
+            Ok(table.get_versioned(&handle)?.0[1].clone())
+        };
+
+        assert_eq!(users(&exact)?, Some(DataValue::Number(10i64.into())));
+        assert_eq!(users(&estimated)?, Some(DataValue::Number(10i64.into())));
+
+        // every row holding users 0 and 1 goes
+        for handle in handles
+            .iter()
+            .step_by(10)
+            .chain(handles.iter().skip(1).step_by(10))
+        {
+            source.delete(handle.clone())?;
+        }
+
+        exact_view.flush()?;
+        estimated_view.flush()?;
+        assert_eq!(users(&exact)?, Some(DataValue::Number(8i64.into())));
+        assert_eq!(users(&estimated)?, Some(DataValue::Number(10i64.into())));
+        exact_view.verify()?;
+        estimated_view.verify()?;
+
+        estimated_view.rebuild()?;
+        assert_eq!(users(&estimated)?, Some(DataValue::Number(8i64.into())));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_flush_failures() -> Result<()> {
+        let columns = vec![DataConfig::new(DataType::Number)];
+        let id = TableId::new();
+        let dir = TempDir::new("mem_table_flush_failures");
+        let table = Table::new(id, TableConfig::new_persisted(&columns, &dir)?, None)?;
+        let (sink, incidents) = std::sync::mpsc::channel();
+        let faulty = FaultyFileOps::new();
+
+        // set before the column is opened, so it's handed on when it is
+        table.set_io_error_sink(Some(sink));
+        table.insert_one(vec![Some(DataValue::Number(1i64.into()))])?;
+
+        let store = table.get_column_store(0)?;
+        store.set_file_ops(faulty.clone());
+        faulty.fail(true);
+
+        let err = table.flush_all().unwrap_err();
+        assert!(
+            matches!(&err, TableError::Io(io) if io.raw_os_error() == Some(FaultyFileOps::EIO)),
+            "{:?}",
+            err
+        );
+        assert_eq!(table.flush_failures(), 1);
+        assert_eq!(store.failed_blocks(), vec![0]);
+
+        let incident = incidents.try_recv()?;
+        assert_eq!(incident.op, IoOp::Flush);
+        assert_eq!(incident.block, Some(0));
+        assert!(incident.path.is_some_and(|path| path.starts_with(&dir)));
+
+        assert!(table.has_failed_flush());
+
+        faulty.fail(false);
+        table.flush_all()?;
+        assert!(store.failed_blocks().is_empty());
+        assert!(!table.has_failed_flush());
+        assert_eq!(table.flush_failures(), 1);
+
+        // the flush made as the table goes away still gets to the sink
+        faulty.fail(true);
+        drop(store);
+        drop(table);
+        assert!(incidents
+            .try_iter()
+            .any(|incident| incident.block == Some(0)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_missing_columns() -> Result<()> {
+        let columns = vec![
+            DataConfig::new(DataType::Number),
+            DataConfig::new(DataType::Text(16)),
+            DataConfig::new(DataType::Bool),
+        ];
+        let id = TableId::new();
+        let dir = TempDir::new("mem_table_missing_columns");
+        let config = TableConfig::new_persisted(&columns, &dir)?;
+        let row = |n: i64| -> Result<Vec<Option<DataValue>>> {
+            Ok(vec![
+                Some(DataValue::Number(n.into())),
+                Some(columns[1].try_new_value(format!("row {}", n))?),
+                Some(DataValue::Bool(n % 2 == 0)),
+            ])
+        };
+
+        let table = Table::new(id, config, None)?;
+
+        for n in 0..10 {
+            table.insert_one(row(n)?)?;
+        }
+
+        table.close()?;
+
+        let missing = dir.join("column_1.store");
+        std::fs::remove_file(&missing)?;
+
+        // rows have values in the column, so a plain open doesn't read it as empty
+        let err = Table::new(id, config, None).unwrap_err();
+        assert!(
+            matches!(&err, TableError::Unavailable(unavailable) if unavailable.column == 1),
+            "{:?}",
+            err
+        );
+
+        let options = OpenOptions {
+            allow_missing_columns: true,
+            ..OpenOptions::default()
+        };
+        let (table, report) = Table::open_with_options(id, config, None, options.clone())?;
+        assert!(report.is_degraded());
+        assert_eq!(report.missing_columns, vec![(1, missing.clone())]);
+        assert_eq!(table.unavailable_columns(), vec![1]);
+
+        let (_, handle) = table.scan_since(0).next().expect("a row");
+        assert_eq!(
+            table.get_value(&handle, 0)?,
+            Some(DataValue::Number(0i64.into()))
+        );
+        assert_eq!(table.get_value(&handle, 2)?, Some(DataValue::Bool(true)));
+        assert!(matches!(
+            table.get_value(&handle, 1),
+            Err(TableError::Unavailable(_))
+        ));
+        assert!(matches!(
+            table.get_versioned(&handle),
+            Err(TableError::Unavailable(_))
+        ));
+
+        // writing to the column is turned away, writing around it isn't
+        assert!(matches!(
+            table.insert_one(row(10)?),
+            Err(TableError::Validation(_))
+        ));
+        table.insert_one(vec![
+            Some(DataValue::Number(10i64.into())),
+            None,
+            Some(DataValue::Bool(true)),
+        ])?;
+
+        let integrity = table.check_integrity()?;
+        assert_eq!(integrity.violations.len(), 1);
+        assert_eq!(integrity.violations[0].check, "column store");
+        assert_eq!(integrity.violations[0].column, Some(1));
+        table.close()?;
+
+        let (table, _) = Table::open_with_options(
+            id,
+            config,
+            None,
+            OpenOptions {
+                unavailable_as_empty: true,
+                ..options
+            },
+        )?;
+        let rows = table.read_rows()?.rows;
+        assert_eq!(rows.len(), 11);
+        assert!(rows.iter().all(|(_, values)| values[1].is_none()));
+
+        table.rebuild_column(1)?;
+        assert!(table.unavailable_columns().is_empty());
+        assert!(table.check_integrity()?.is_ok());
+        table.insert_one(row(11)?)?;
+        table.close()?;
+
+        // once rebuilt the table opens as usual, with the old values gone from the column
+        let table = Table::new(id, config, None)?;
+        let rows = table
+            .read_rows()?
+            .rows
+            .into_iter()
+            .map(|(_, values)| values)
+            .collect::<Vec<_>>();
+        assert_eq!(rows.len(), 12);
+        assert_eq!(
+            rows[0],
+            vec![
+                Some(DataValue::Number(0i64.into())),
+                None,
+                Some(DataValue::Bool(true)),
+            ]
+        );
+        assert_eq!(rows[11], row(11)?);
+        table.close()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rate_limiter() -> Result<()> {
+        use std::time::Instant;
+
+        let columns = vec![DataConfig::new(DataType::Number)];
+        let table = Table::new(TableId::new(), TableConfig::new(&columns)?, None)?;
+        let row = |n: i64| vec![Some(DataValue::Number(n.into()))];
+
+        // without a limiter nothing is held or counted
+        for n in 0..100 {
+            table.insert_one(row(n))?;
+        }
+
+        assert_eq!(table.throttle_events(), 0);
+
+        // a burst of 10, then 100 rows a second, waiting as long as it takes
+        let limit = RateLimit::per_sec(100.0).with_burst(10.0);
+        let bucket = TokenBucket::new(None, Some(limit)).with_max_wait(Duration::from_secs(1));
+        table.set_rate_limiter(Some(Arc::new(bucket)));
+
+        let started = Instant::now();
+
+        for n in 0..30 {
+            table.insert_one(row(n))?;
+        }
+
+        // the 20 rows past the burst take at least 200ms
+        assert!(started.elapsed() >= Duration::from_millis(190));
+        assert!(table.throttle_events() >= 19);
+        assert_eq!(table.row_count(), 130);
+
+        // reads have no limit of their own
+        assert_eq!(table.read_rows()?.rows.len(), 130);
+
+        // turned away rather than held, with nothing written
+        let bucket = TokenBucket::new(Some(RateLimit::per_sec(1.0)), Some(RateLimit::per_sec(1.0)));
+        table.set_rate_limiter(Some(Arc::new(bucket)));
+        let events = table.throttle_events();
+
+        table.insert_one(row(0))?;
+
+        let err = table.insert_one(row(1)).unwrap_err();
+        assert!(
+            matches!(&err, TableError::Throttled(throttled) if throttled.retry_after > Duration::ZERO),
+            "{:?}",
+            err
+        );
+        assert!(matches!(
+            table.insert((0..5).map(row)),
+            Err(TableError::Throttled(_))
+        ));
+        assert_eq!(table.row_count(), 131);
+
+        // a scan costs every row, more than the burst, so it waits for a full bucket
+        assert_eq!(table.read_rows()?.rows.len(), 131);
+        assert!(matches!(table.read_rows(), Err(TableError::Throttled(_))));
+        assert_eq!(table.throttle_events(), events + 3);
+
+        table.set_rate_limiter(None);
+        table.insert_one(row(1))?;
+        assert_eq!(table.throttle_events(), events + 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sorted_runs() -> Result<()> {
+        use std::collections::HashMap;
+
+        use rand::{rngs::StdRng, Rng, SeedableRng};
+
+        const ROWS: usize = 200_000;
+
+        let columns = vec![DataConfig::new(DataType::Text(8))];
+        let table = Table::new(TableId::new(), TableConfig::new(&columns)?, None)?;
+        let mut rng = StdRng::seed_from_u64(1435);
+        let mut counts = HashMap::new();
+        let mut rows = Vec::with_capacity(ROWS);
+
+        // drawn from fewer values than rows, so plenty of them repeat
+        for _ in 0..ROWS {
+            let text = format!("{:06}", rng.gen_range(0..150_000));
+            *counts.entry(text.clone()).or_insert(0) += 1;
+            rows.push(vec![Some(columns[0].try_new_value(text)?)]);
+        }
+
+        assert!(matches!(table.insert(rows)?, InsertState::Done(_)));
+
+        let store = table.get_column_store(0)?;
+        let runs = sorted_runs::build(&store, 5_000)?;
+        assert_eq!(runs.len(), ROWS / 5_000);
+        assert!(runs.iter().all(|run| run.path().exists()));
+
+        let paths = runs
+            .iter()
+            .map(|run| run.path().to_path_buf())
+            .collect::<Vec<_>>();
+
+        // merging holds the next entry of each run, not the column
+        let start = live_bytes();
+        let mut peak = 0;
+        let mut previous: Option<DataValue> = None;
+        let mut merged = 0;
+
+        for entry in sorted_runs::merge(runs)? {
+            let (value, _) = entry?;
+
+            if let Some(previous) = previous.as_ref() {
+                assert!(*previous <= value, "{:?} came before {:?}", previous, value);
+            }
+
+            previous = Some(value);
+            merged += 1;
+            peak = peak.max(live_bytes() - start);
+        }
+
+        assert_eq!(merged, ROWS);
+        assert!(peak < 1 << 20, "merge held {} bytes", peak);
+        assert!(paths.iter().all(|path| !path.exists()));
+
+        // equal values come out next to each other
+        let expected = counts.values().filter(|&&count| count > 1).count();
+        let found =
+            sorted_runs::duplicates(sorted_runs::merge(sorted_runs::build(&store, 5_000)?)?)?;
+        assert_eq!(found.len(), expected);
+
+        for dup in &found {
+            let DataValue::Text(text) = &dup.value else {
+                panic!("not text: {:?}", dup.value);
+            };
+
+            assert_eq!(dup.records.len(), counts[text.as_str()]);
+        }
+
+        // the column is bigger than what's sorted in memory
+        const _: () = assert!(ROWS > EXTERNAL_SORT_ROWS);
+        assert_eq!(table.find_duplicates(0)?, found);
+
+        // and a small one gives the same answer
+        let small = Table::new(TableId::new(), TableConfig::new(&columns)?, None)?;
+
+        for text in ["b", "a", "b", "c", "b", "a"] {
+            small.insert_one(vec![Some(columns[0].try_new_value(text)?)])?;
+        }
+
+        let found = small.find_duplicates(0)?;
+        assert_eq!(
+            found
+                .iter()
+                .map(|dup| (dup.value.to_string(), dup.records.len()))
+                .collect::<Vec<_>>(),
+            vec![("a".to_string(), 2), ("b".to_string(), 3)]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_datagen() -> Result<()> {
+        use std::{collections::HashMap, time::Duration};
+
+        use crate::datagen::{self, ColumnSpec, DataSpec, Distribution};
+
+        let columns = vec![
+            DataConfig::new(DataType::O64),
+            DataConfig::new(DataType::Text(6)),
+            DataConfig::new(LogicalType::Email.base_type()).with_logical_type(LogicalType::Email),
+            DataConfig::new(LogicalType::Uuid.base_type()).with_logical_type(LogicalType::Uuid),
+            DataConfig::new(DataType::Bytes(4)),
+            DataConfig::new(DataType::Timestamp),
+            DataConfig::new(DataType::Number),
+        ];
+        let config = TableConfig::new(&columns)?.with_primary_key([0])?;
+        let spec = DataSpec::new(1436)
+            .with_nulls(0.1)
+            .with_column(1, ColumnSpec::new(Distribution::Zipf { s: 1.2 }))
+            .with_column(
+                5,
+                ColumnSpec::new(Distribution::RecentTimestamps {
+                    window: Duration::from_secs(3600),
+                }),
+            )
+            .with_column(6, ColumnSpec::new(Distribution::Sequential).with_nulls(0.0))
+            .with_now(1_700_000_000_000);
+
+        // the same spec gives the same rows, and another seed others
+        let rows = datagen::with_spec(&config, &spec)
+            .take(1000)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rows,
+            datagen::with_spec(&config, &spec)
+                .take(1000)
+                .collect::<Vec<_>>()
+        );
+        assert_ne!(
+            datagen::for_table(&config, 1).take(10).collect::<Vec<_>>(),
+            datagen::for_table(&config, 2).take(10).collect::<Vec<_>>()
+        );
+
+        for (n, row) in rows.iter().enumerate() {
+            assert!(row[0].is_some(), "key columns are never empty");
+            assert_eq!(
+                row[6],
+                Some(DataValue::Number(Number::Unsigned(n as u64 + 1)))
+            );
+
+            if let Some(DataValue::Timestamp(at)) = &row[5] {
+                let at = at.timestamp_millis();
+                assert!((1_700_000_000_000 - 3_600_000..=1_700_000_000_000).contains(&at));
+            }
+        }
+
+        // the most common values of a zipf column come up far more than the least common
+        let mut counts = HashMap::new();
+
+        for row in datagen::with_spec(&config, &spec).take(20_000) {
+            if let Some(value) = row[1].clone() {
+                *counts.entry(value).or_insert(0usize) += 1;
+            }
+        }
+
+        let mut counts = counts.into_values().collect::<Vec<_>>();
+        counts.sort_unstable_by(|a, b| b.cmp(a));
+
+        let head = counts.iter().take(10).sum::<usize>();
+        let tail = counts.iter().rev().take(counts.len() / 2).sum::<usize>();
+        assert!(counts.len() <= datagen::ZIPF_POOL);
+        assert!(
+            head > tail,
+            "head {} isn't heavier than tail {}",
+            head,
+            tail
+        );
+
+        // every generated row passes validation
+        let table = Table::new(TableId::new(), config, None)?;
+        let report = table.load_synthetic(100_000, &spec)?;
+
+        assert_eq!(report.inserted, 100_000);
+        assert_eq!(report.rejected, 0);
+        assert_eq!(table.row_count(), 100_000);
+
+        // a second load repeats the keys of the first unless it starts past them
+        let again = table.load_synthetic(10, &spec)?;
+        assert_eq!((again.inserted, again.rejected), (0, 10));
+
+        let past = table.load_synthetic(10, &spec.clone().with_sequence_start(100_001))?;
+        assert_eq!((past.inserted, past.rejected), (10, 0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rebuild_column_online() -> Result<()> {
+        use std::{sync::mpsc, sync::Once, thread, time::Duration};
+
+        const ROWS: usize = 5000;
+        const CONCURRENT: usize = 2000;
+
+        let columns = vec![
+            DataConfig::new(DataType::Number),
+            DataConfig::new(DataType::Text(16)),
+        ];
+        let table = Table::new(TableId::new(), TableConfig::new(&columns)?, None)?;
+        let row = |n: usize, label: &str| -> Result<Vec<Option<DataValue>>> {
+            Ok(vec![
+                Some(DataValue::try_from_any(DataType::Number, n)?),
+                Some(DataValue::Text(Text::try_from_str(label, 16)?)),
+            ])
+        };
+
+        table.insert(
+            (0..ROWS)
+                .map(|n| row(n, &format!("row {}", n)))
+                .collect::<Result<Vec<_>>>()?,
+        )?;
+
+        // the first value copied holds the rebuild until the writer is done, so every write below
+        // lands while the rebuild runs, and one blocked by it fails the test instead of hanging
+        let (start_tx, start_rx) = mpsc::channel();
+        let (done_tx, done_rx) = mpsc::channel();
+        let writer = thread::spawn({
+            let table = table.clone();
+
+            move || -> Result<()> {
+                start_rx.recv()?;
+
+                for batch in 0..CONCURRENT / 100 {
+                    let rows = (0..100)
+                        .map(|n| row(ROWS + batch * 100 + n, &format!("new {}", n)))
+                        .collect::<Result<Vec<_>>>()?;
+
+                    table.insert(rows)?;
+                }
+
+                // rows copied already are written again
+                for seq in 1..=10 {
+                    let handle = table.get_by_seq(seq).expect("row is there");
+                    let (_, gen) = table.get_versioned(&handle)?;
+
+                    table.update_if(&handle, gen, row(seq as usize - 1, "changed")?)?;
+                }
+
+                table.delete(table.get_by_seq(11).expect("row is there"))?;
+                done_tx.send(())?;
+
+                Ok(())
+            }
+        });
+
+        let once = Once::new();
+        let report = table.rebuild_column_online(1, |value| {
+            once.call_once(|| {
+                start_tx.send(()).expect("writer is waiting");
+                done_rx
+                    .recv_timeout(Duration::from_secs(30))
+                    .expect("writes went ahead during the rebuild");
+            });
+
+            let DataValue::Text(text) = value else {
+                anyhow::bail!("not text: {:?}", value);
+            };
+
+            Ok(DataValue::Text(Text::try_from_str(
+                &text.as_str().to_uppercase(),
+                16,
+            )?))
+        })?;
+
+        writer.join().expect("writer panicked")?;
+
+        // the row deleted before it was copied isn't, and only the first row was written to
+        // after it was copied
+        assert_eq!(report.snapshot_rows, ROWS - 1);
+        assert_eq!(report.caught_up_rows, CONCURRENT);
+        assert_eq!(report.swap_rows, 1);
+        assert_eq!(table.row_count(), ROWS + CONCURRENT - 1);
 
-        columns.insert(idx, store.clone());
+        let mut labels = 0;
 
-        Ok(store)
-    }
+        for (_, handle) in table.scan_since(0) {
+            let values = table.get_versioned(&handle)?.0;
+            let Some(DataValue::Text(label)) = &values[1] else {
+                panic!("row lost its label: {:?}", values);
+            };
 
-    pub fn get_column_by_name(&self, name: impl AsRef<str>) -> Option<Store<DataValue>> {
-        let name = InternalString::new(name.as_ref()).ok()?;
-        let idx = *self.columns_by_name.get(&name)?;
+            assert_eq!(label.as_str(), label.as_str().to_uppercase());
+            labels += 1;
+        }
 
-        self.get_column_store(idx).ok()
+        assert_eq!(labels, ROWS + CONCURRENT - 1);
+
+        let changed = table.get_value(&table.get_by_seq(3).expect("row is there"), 1)?;
+        assert_eq!(
+            changed,
+            Some(DataValue::Text(Text::try_from_str("CHANGED", 16)?))
+        );
+
+        // a transform that fails leaves the column as it was
+        let err = table.rebuild_column_online(1, |_| anyhow::bail!("no"));
+        assert!(err.is_err());
+        assert_eq!(
+            table.get_value(&table.get_by_seq(3).expect("row is there"), 1)?,
+            changed
+        );
+
+        // the rebuilt store takes writes like the old one
+        table.insert_one(row(0, "after")?)?;
+        assert_eq!(table.row_count(), ROWS + CONCURRENT);
+
+        let key = TableConfig::new(&columns)?.with_primary_key([1])?;
+        let keyed = Table::new(TableId::new(), key, None)?;
+        assert!(matches!(
+            keyed.rebuild_column_online(1, |value| Ok(value.clone())),
+            Err(TableError::Validation(_))
+        ));
+
+        Ok(())
     }
 
-    pub fn get_column_stores(
-        &self,
-        indices: impl Into<Vec<usize>>,
-    ) -> Result<Vec<Store<DataValue>>> {
-        let mut indices: Vec<usize> = indices.into();
-        indices.dedup();
-        indices.sort_unstable();
+    #[test]
+    fn test_rebuild_persisted_column_online() -> Result<()> {
+        let columns = vec![
+            DataConfig::new(DataType::Number),
+            DataConfig::new(DataType::Text(16)),
+        ];
+        let id = TableId::new();
+        let dir = TempDir::new("mem_table_rebuild_persisted");
+        let config = TableConfig::new_persisted(&columns, &dir)?.with_checksum(true);
+        let row = |n: usize| -> Result<Vec<Option<DataValue>>> {
+            let label = Text::try_from_str(&format!("row {}", n), 16)?;
+
+            Ok(vec![
+                Some(DataValue::try_from_any(DataType::Number, n)?),
+                Some(DataValue::Text(label)),
+            ])
+        };
 
-        if let Some(&idx) = indices.last() {
-            if idx >= self.config.columns.len() {
-                anyhow::bail!("column index out of bounds");
+        {
+            let table = Table::new(id, config, None)?;
+            table.insert((0..512).map(row).collect::<Result<Vec<_>>>()?)?;
+
+            for seq in (1..=512).filter(|seq| seq % 4 != 0) {
+                table.delete(table.get_by_seq(seq).expect("row is there"))?;
             }
-        }
 
-        let count = indices.len();
+            let blocks = table
+                .get_column_store(1)?
+                .fragmentation_report()
+                .blocks
+                .len();
+
+            table.rebuild_column_online(1, |value| {
+                let DataValue::Text(text) = value else {
+                    anyhow::bail!("not text: {:?}", value);
+                };
+
+                Ok(DataValue::Text(Text::try_from_str(
+                    &text.as_str().to_uppercase(),
+                    16,
+                )?))
+            })?;
 
-        let mut stores = Vec::with_capacity(count);
-        let mut missing = Vec::with_capacity(count);
+            assert!(
+                table
+                    .get_column_store(1)?
+                    .fragmentation_report()
+                    .blocks
+                    .len()
+                    < blocks
+            );
+            assert!(!dir.join("column_1.rebuild").exists());
+
+            // the rebuilt store takes writes like the old one
+            table.insert_one(row(512)?)?;
+            table.close()?;
+        }
 
-        let columns = self.columns.upgradable();
+        // the rebuilt store is the one on disk, checksums and all
+        let table = Table::new(id, config, None)?;
+        let labels = table
+            .scan_since(0)
+            .map(|(_, handle)| Ok(table.get_value(&handle, 1)?))
+            .collect::<Result<Vec<_>>>()?;
+
+        assert_eq!(labels.len(), 129);
+        assert_eq!(
+            labels[0],
+            Some(DataValue::Text(Text::try_from_str("ROW 3", 16)?))
+        );
+        assert_eq!(
+            labels[128],
+            Some(DataValue::Text(Text::try_from_str("row 512", 16)?))
+        );
+        assert!(table.check_integrity()?.is_ok());
+        assert!(table.scrub()?.is_ok());
+
+        drop(table);
 
-        for idx in indices {
-            if let Some(store) = columns.get(&idx) {
-                stores.push(store.clone());
-            } else {
-                missing.push(idx);
-            }
+        Ok(())
+    }
+
+    #[test]
+    fn test_group_by_spills() -> Result<()> {
+        const KEYS: usize = 100_000;
+        const REPEATED: usize = 20_000;
+        const BUDGET: usize = 1 << 20;
+
+        let table = fixture_table(&[("key", DataType::Number), ("amount", DataType::Number)]);
+        let number = |n: usize| DataValue::try_from_any(DataType::Number, n);
+
+        // the first keys come up again once they've been spilled, so their partials are merged
+        let keys = (0..KEYS).chain(0..REPEATED).collect::<Vec<_>>();
+
+        for batch in keys.chunks(10_000) {
+            table.insert(
+                batch
+                    .iter()
+                    .map(|&key| Ok(vec![Some(number(key)?), Some(number(key % 7)?)]))
+                    .collect::<Result<Vec<_>>>()?,
+            )?;
         }
 
-        if missing.is_empty() {
-            return Ok(stores);
+        for agg in [
+            Aggregate::Sum(1),
+            Aggregate::CountDistinct {
+                column: 1,
+                exact: true,
+            },
+        ] {
+            let unbounded = table.group_by(0, agg, None)?;
+            let bounded = table.group_by(0, agg, Some(BUDGET))?;
+
+            assert_eq!(unbounded.metrics.spills, 0);
+            assert_eq!(unbounded.groups.len(), KEYS);
+            assert!(bounded.metrics.spills > 1);
+            assert!(bounded.metrics.spilled_bytes > 0);
+            assert!(bounded.metrics.peak_bytes <= BUDGET);
+            assert!(unbounded.metrics.peak_bytes > BUDGET);
+            assert_eq!(bounded.metrics.rows, KEYS + REPEATED);
+            assert_eq!(bounded.metrics.groups, KEYS);
+            assert_eq!(bounded.groups, unbounded.groups);
         }
 
-        let mut columns = columns.upgrade();
+        let sums = table.group_by(0, Aggregate::Sum(1), Some(BUDGET))?;
 
-        for idx in missing {
-            let store = Store::new(
-                Some(self.id),
-                Some(unsafe {
-                    self.config
-                        .columns
-                        .get_unchecked(idx)
-                        .into_store_config(&self.config)
-                }),
-            )?;
+        // key 8 is in two rows, key 20_001 in one
+        assert_eq!(sums.groups[8], (Some(number(8)?), Some(Number::Integer(2))));
+        assert_eq!(
+            sums.groups[20_001],
+            (Some(number(20_001)?), Some(Number::Integer(20_001 % 7)))
+        );
 
-            columns.insert(idx, store.clone());
-            stores.push(store);
-        }
+        assert!(table.group_by(2, Aggregate::Count, None).is_err());
 
-        Ok(stores)
+        Ok(())
     }
 
-    pub fn get_column_store_range(
-        &self,
-        indices: impl RangeBounds<usize>,
-    ) -> Result<Vec<Store<DataValue>>> {
-        let start = match indices.start_bound() {
-            std::ops::Bound::Included(&start) => start,
-            std::ops::Bound::Excluded(&start) => start + 1,
-            std::ops::Bound::Unbounded => 0,
-        };
+    #[test]
+    fn test_bool_bitmaps() -> Result<()> {
+        let table = fixture_table(&[
+            ("n", DataType::Number),
+            ("flag", DataType::Bool),
+            ("other", DataType::Bool),
+        ]);
+        let mut rows = RowGen::for_table(&table, 1440).with_nulls(0.2);
+
+        for _ in 0..5 {
+            table.insert((0..1000).map(|_| rows.row()).collect::<Vec<_>>())?;
+        }
 
-        let end = match indices.end_bound() {
-            std::ops::Bound::Included(&end) => end + 1,
-            std::ops::Bound::Excluded(&end) => end,
-            std::ops::Bound::Unbounded => self.config.columns.len(),
-        };
+        let handles = table.scan_since(0).map(|(_, h)| h).collect::<Vec<_>>();
 
-        if end > self.config.columns.len() {
-            anyhow::bail!("column index out of bounds");
+        for handle in handles.iter().step_by(7) {
+            let (_, gen) = table.get_versioned(handle)?;
+            table.update_if(handle, gen, rows.row())?;
         }
 
-        let count = end - start;
+        for handle in handles.iter().skip(3).step_by(11) {
+            table.delete(handle.clone())?;
+        }
 
-        let mut stores = Vec::with_capacity(count);
-        let mut missing = Vec::with_capacity(count);
+        let bool_of = |value: Option<DataValue>| match value {
+            Some(DataValue::Bool(value)) => Some(value),
+            _ => None,
+        };
+        let flag = table.bool_view(1)?;
+        let other = table.bool_view(2)?;
+        let (mut present, mut trues) = (0, 0);
+
+        // the bits of every row are the values the rows hold, whatever was written to them
+        for (_, handle) in table.scan_since(0) {
+            let row = table.row_position(&handle);
+            let a = bool_of(table.get_value(&handle, 1)?);
+            let b = bool_of(table.get_value(&handle, 2)?);
+
+            assert_eq!(flag.get(row), a);
+            assert_eq!(other.get(row), b);
+            assert_eq!(flag.and(&other).get(row), a.zip(b).map(|(a, b)| a && b));
+            assert_eq!(
+                flag.or(&other).get(row),
+                a.into_iter().chain(b).reduce(|a, b| a || b)
+            );
+            assert_eq!(flag.where_eq(false).get(row), a.map(|a| !a));
+
+            present += a.is_some() as usize;
+            trues += (a == Some(true)) as usize;
+        }
 
-        let columns = self.columns.upgradable();
+        assert_eq!(flag.iter_bits().count(), present);
+        assert_eq!(flag.count_true(), trues);
+        assert!(trues > 0 && trues < present);
 
-        for idx in start..end {
-            if let Some(store) = columns.get(&idx) {
-                stores.push(store.clone());
-            } else {
-                missing.push(idx);
+        // built over from the stores, the bitmaps are the ones kept up to date by the writes
+        table._build_bitmaps()?;
+        assert!(table.bool_view(1)?.iter_bits().eq(flag.iter_bits()));
+
+        let last_block = flag.iter_bits().last().unwrap().0 / flag.block_capacity();
+        let mut loaded = BoolColumnView::new(flag.block_capacity());
+
+        for block in 0..=last_block {
+            if let Some(bytes) = flag.block_bytes(block) {
+                assert_eq!(bytes[0], BITMAP_FORMAT_VERSION);
+                loaded.load_block(block, &bytes)?;
             }
         }
 
-        if missing.is_empty() {
-            return Ok(stores);
-        }
+        assert!(loaded.iter_bits().eq(flag.iter_bits()));
 
-        let mut columns = columns.upgrade();
+        let mut bytes = flag.block_bytes(0).unwrap();
+        bytes[0] += 1;
+        assert!(loaded.load_block(0, &bytes).is_err());
+        assert!(loaded.load_block(0, &bytes[1..]).is_err());
 
-        for idx in missing {
-            let store = Store::new(
-                Some(self.id),
-                Some(unsafe {
-                    self.config
-                        .columns
-                        .get_unchecked(idx)
-                        .into_store_config(&self.config)
-                }),
-            )?;
+        assert!(matches!(table.bool_view(0), Err(TableError::Validation(_))));
+
+        table.truncate()?;
+        assert_eq!(table.bool_view(1)?.iter_bits().count(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_capacity_report_and_shrink() -> Result<()> {
+        let columns = vec![
+            DataConfig::new(DataType::Number),
+            DataConfig::new(DataType::Text(1000)),
+        ];
+        let table = Table::new(TableId::new(), TableConfig::new(&columns)?, None)?;
+
+        // one value of every length from 1 to 100
+        table.insert(
+            (1..=100)
+                .map(|n| {
+                    Ok(vec![
+                        Some(DataValue::try_from_any(DataType::Number, n)?),
+                        Some(DataValue::Text(Text::try_from_str(&"x".repeat(n), 1000)?)),
+                    ])
+                })
+                .collect::<Result<Vec<_>>>()?,
+        )?;
 
-            columns.insert(idx, store.clone());
-            stores.push(store);
+        let report = table.capacity_report()?;
+        assert_eq!(report.len(), 1);
+
+        let advice = &report[0];
+        assert_eq!(advice.column, 1);
+        assert_eq!(advice.declared_cap, 1000);
+        assert_eq!(advice.values, 100);
+        assert_eq!(advice.observed_max, 100);
+        assert_eq!(advice.p99_len, 99);
+        // 99 with a quarter more is 124, rounded up to a multiple of 8
+        assert_eq!(advice.recommended_cap, 128);
+        assert_eq!(advice.potential_savings_bytes, (1000 - 128) * 100);
+        assert!(advice.to_string().contains("capacity 128 would do"));
+
+        let padding = |table: &Table| -> Result<usize> {
+            Ok(table.fragmentation_report()?.columns[1].padding_bytes)
+        };
+        let texts = |table: &Table| -> Result<Vec<Option<DataValue>>> {
+            table
+                .scan_since(0)
+                .map(|(_, handle)| Ok(table.get_value(&handle, 1)?))
+                .collect()
+        };
+        let before = texts(&table)?;
+        let wasted = padding(&table)?;
+        assert_eq!(wasted, 1000 * 100 - 5050);
+
+        // a value too long for the new capacity fails the shrink and leaves the column as it was
+        assert!(shrink_column(&table, 1, 50, ShrinkPolicy::Fail).is_err());
+        assert_eq!(texts(&table)?, before);
+
+        let (shrunk, rebuilt) = shrink_column(&table, 1, 128, ShrinkPolicy::Fail)?;
+        assert_eq!(rebuilt.snapshot_rows, 100);
+        assert_eq!(
+            shrunk.config.columns.get(1).unwrap().data_type.into_inner(),
+            DataType::Text(128)
+        );
+        assert_eq!(padding(&shrunk)?, 128 * 100 - 5050);
+        assert_eq!(wasted - padding(&shrunk)?, advice.potential_savings_bytes);
+
+        for (before, after) in before.iter().zip(texts(&shrunk)?) {
+            match (before, after) {
+                (Some(DataValue::Text(before)), Some(DataValue::Text(after))) => {
+                    assert_eq!(before.as_str(), after.as_str());
+                }
+                other => panic!("not a text value: {:?}", other),
+            }
         }
 
-        Ok(stores)
+        // with nothing left to save, the column keeps the capacity it has
+        let advice = &shrunk.capacity_report()?[0];
+        assert_eq!(advice.recommended_cap, 128);
+        assert_eq!(advice.potential_savings_bytes, 0);
+
+        let (truncated, _) = shrink_column(&shrunk, 1, 50, ShrinkPolicy::Truncate)?;
+        assert!(texts(&truncated)?.iter().all(|value| match value {
+            Some(DataValue::Text(text)) => text.len() <= 50,
+            _ => false,
+        }));
+        assert_eq!(truncated.capacity_report()?[0].observed_max, 50);
+
+        assert!(matches!(
+            shrink_column(&truncated, 0, 8, ShrinkPolicy::Fail),
+            Err(TableError::Validation(_))
+        ));
+        assert!(matches!(
+            shrink_column(&truncated, 1, 50, ShrinkPolicy::Fail),
+            Err(TableError::Validation(_))
+        ));
+        assert!(matches!(
+            shrink_column(&truncated, 1, 0, ShrinkPolicy::Fail),
+            Err(TableError::Validation(_))
+        ));
+
+        Ok(())
     }
 
-    pub fn insert_one(&self, values: Vec<Option<DataValue>>) -> Result<RecordHandle> {
-        let val_count = values.len();
+    #[test]
+    fn test_drop_then_add_column() -> Result<()> {
+        let names = ["a", "b", "c"]
+            .into_iter()
+            .enumerate()
+            .map(|(idx, name)| Ok((InternalString::new(name)?, idx)))
+            .collect::<Result<IndexMap<_, _>>>()?;
+        let id = TableId::new();
+        let dir = TempDir::new("mem_table_drop_add");
+        let config = TableConfig::new_persisted(
+            [
+                DataConfig::new(DataType::Number),
+                DataConfig::new(DataType::Text(8)),
+                DataConfig::new(DataType::Number),
+            ],
+            &dir,
+        )?;
+        let table = Table::new(id, config, Some(names))?;
+        let number = |n: i64| Some(DataValue::try_integer_from_number(n).unwrap());
+        let rows = |table: &Table| -> Result<Vec<Vec<Option<DataValue>>>> {
+            table
+                .scan_since(0)
+                .map(|(_, handle)| Ok(table.get_versioned(&handle)?.0))
+                .collect()
+        };
 
-        // Empty check
-        if val_count == 0 {
-            let (_, record_handle) = self.records.insert_one().map_err(StoreError::thread_safe)?;
-            return Ok(record_handle);
-        // Out of bounds check
-        } else if val_count > self.config.columns.len() {
-            anyhow::bail!("value count exceeds column count");
+        for n in 1..=2 {
+            table.insert_one(vec![
+                number(n),
+                Some(DataValue::Text(Text::try_from_str("text", 8)?)),
+                number(n * 10),
+            ])?;
         }
 
-        let (record, record_handle) = self.records.insert_one().map_err(StoreError::thread_safe)?;
+        let (dropped_id, c_id) = (table.column_id(1).unwrap(), table.column_id(2).unwrap());
+
+        let dropped = drop_column(&table, 1)?;
+        assert!(table.is_closed());
+        assert_eq!(dropped.column_position(c_id), Some(1));
+        assert_eq!(dropped.column_position(dropped_id), None);
+        assert_eq!(dropped.column_index("c"), Some(1));
+        assert_eq!(
+            rows(&dropped)?,
+            vec![vec![number(1), number(10)], vec![number(2), number(20)]]
+        );
+
+        // the new column takes the cell the dropped one left, which no row holds anymore
+        let added = add_column(
+            &dropped,
+            DataConfig::new(DataType::Number).with_name(InternalString::new("d")?),
+        )?;
+        let d_id = added.column_id(2).unwrap();
+        assert_ne!(d_id, dropped_id);
+        assert_eq!(added.config.columns.cell(2), 1);
+        assert_eq!(added.column_index("d"), Some(2));
+
+        added.insert_one(vec![number(3), number(30), number(300)])?;
+        // an older row takes a value in the new column as well
+        let first = added
+            .records
+            .record_id(&added.scan_since(0).next().unwrap().1);
+        added.update_one(first, vec![(2, number(100))])?;
 
-        let stores = self.get_column_store_range(..values.len())?;
+        let expected = vec![
+            vec![number(1), number(10), number(100)],
+            vec![number(2), number(20), None],
+            vec![number(3), number(30), number(300)],
+        ];
+        assert_eq!(rows(&added)?, expected);
+        assert!(added.check_integrity()?.is_ok());
+
+        // the columns' ids and cells are written out with the config, as a dump writes it
+        let config = {
+            let written = TableConfig {
+                persistance: InternalPath::default(),
+                ..added.config
+            };
+            let mut decoded = TableConfig::new([DataConfig::new(DataType::Bool)])?;
+            decoded.init_from_bytes(&primitives::into_bytes!(written, TableConfig)?)?;
+
+            for column in 0..3 {
+                assert_eq!(decoded.columns.id(column), written.columns.id(column));
+                assert_eq!(decoded.columns.cell(column), written.columns.cell(column));
+            }
 
-        record_handle.write_with(|mut data| {
-            data.update(|columns: &mut ColumnIndices| {
-                for (i, value) in values.into_iter().enumerate() {
-                    if let Some(data) = value {
-                        let store = stores.get(i).expect("store exists");
-                        let data_handle = store
-                            .insert_one(Some(record), data)
-                            .map_err(StoreError::thread_safe)?;
+            TableConfig {
+                persistance: added.config.persistance,
+                ..decoded
+            }
+        };
+        added.close()?;
+
+        let reopened = Table::new(id, config, None)?;
+        assert_eq!(rows(&reopened)?, expected);
+        assert_eq!(reopened.column_position(c_id), Some(1));
+        assert_eq!(reopened.column_position(d_id), Some(2));
+
+        // key columns stay, and so does the last column left
+        let keyed = TableConfig::new_persisted(
+            [
+                DataConfig::new(DataType::Number),
+                DataConfig::new(DataType::Number),
+            ],
+            dir.join("keyed"),
+        )?
+        .with_primary_key([1])?;
+        let keyed = Table::new(TableId::new(), keyed, None)?;
+        assert!(matches!(
+            drop_column(&keyed, 1),
+            Err(TableError::Validation(_))
+        ));
+        let keyed = drop_column(&keyed, 0)?;
+        assert_eq!(keyed.config.primary_key.columns().collect::<Vec<_>>(), [0]);
+        assert!(drop_column(&keyed, 0).is_err());
+
+        let memory = fixture_table(&[("a", DataType::Number), ("b", DataType::Number)]);
+        assert!(drop_column(&memory, 1).is_err());
+
+        reopened.close()?;
+        keyed.close()?;
 
-                        columns.replace(i, data_handle.into())?;
-                    }
+        Ok(())
+    }
+
+    #[test]
+    fn test_column_configs_without_ids() -> Result<()> {
+        /// `ColumnConfigs` as encoded before columns had ids.
+        struct Positional(Vec<DataConfig>);
+
+        impl IntoBytes for Positional {
+            const BYTE_COUNT: usize = std::mem::size_of::<ColumnConfigs>();
+
+            fn encode_bytes(&self, x: &mut ByteEncoder<'_>) -> Result<()> {
+                x.encode(self.0.len())?;
+
+                for config in self.0.iter() {
+                    x.encode(*config)?;
                 }
 
                 Ok(())
-            })
-        })?;
+            }
+        }
 
-        Ok(record_handle)
+        let configs = vec![
+            DataConfig::new(DataType::Number),
+            DataConfig::new(DataType::Text(8)),
+            DataConfig::new(DataType::Bool),
+        ];
+        let mut decoded = ColumnConfigs::new([DataConfig::new(DataType::Bool)])?;
+        decoded.init_from_bytes(&Positional(configs.clone()).into_vec()?)?;
+
+        // every column is where its position puts it, which is where the rows have it
+        assert_eq!(decoded.len(), 3);
+        assert_eq!(decoded.cell_span(), 3);
+
+        for (column, config) in configs.iter().enumerate() {
+            assert_eq!(decoded.get(column), Some(config));
+            assert_eq!(decoded.cell(column), column);
+            assert_eq!(decoded.position(decoded.id(column).unwrap()), Some(column));
+        }
+
+        Ok(())
     }
 
-    pub fn insert<I, U>(&self, values: I) -> Result<InsertState, anyhow::Error>
-    where
-        I: IntoIterator<Item = U>,
-        U: IntoIterator<Item = Option<DataValue>>,
-    {
-        let records = self
-            .records
-            .insert_map(values)
-            .map_err(StoreError::thread_safe)?;
+    #[test]
+    fn test_scrub_block_checksums() -> Result<()> {
+        use std::os::unix::fs::FileExt;
 
-        let mut all_handles = Vec::with_capacity(records.len());
-        let mut all_errors = Vec::new();
-        let expected = self.config.columns.len();
+        use primitives::ThinIdx;
 
-        for (idx, record, record_handle, values) in records {
-            let val_count = values.len();
+        let columns = vec![
+            DataConfig::new(DataType::Number),
+            DataConfig::new(DataType::Text(16)),
+        ];
+        let id = TableId::new();
+        let dir = TempDir::new("mem_table_scrub");
+        let config = TableConfig::new_persisted(&columns, &dir)?.with_checksum(true);
+        let table = Table::new(id, config, None)?;
+
+        for n in 0..10i64 {
+            table.insert_one(vec![
+                Some(DataValue::Number(n.into())),
+                Some(columns[1].try_new_value(format!("row {}", n))?),
+            ])?;
+        }
 
-            // Empty check
-            if val_count == 0 {
-                all_handles.push((idx, record_handle, vec![]));
-                continue;
-            // Out of bounds check
-            } else if val_count > expected {
-                all_errors.push((
-                    idx,
-                    InsertError::ColumnLengthMismatch {
-                        record_handle,
-                        expected,
-                        values,
-                    },
-                ));
+        table.flush_all()?;
+
+        let report = table.scrub()?;
+        assert!(report.is_ok(), "{}", report);
+        assert!(report.records.verified > 0);
+        assert_eq!(report.columns.len(), 2);
+        assert!(report
+            .columns
+            .iter()
+            .all(|column| column.store.verified > 0 && column.store.unchecked == 0));
+
+        // the store's file is mapped, so the byte flipped is the last of the first block, in a slot
+        // no row was put in, for the damage to only show in the block's checksum
+        let meta = *table.get_column_store(0)?.read().meta();
+        let offset = meta.block_offset::<DataValue>(ThinIdx::new(0))
+            + meta.block_size_as_bytes::<DataValue>()
+            - 1;
+        let flip = || -> Result<()> {
+            let file = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(dir.join("column_0.store"))?;
+            let mut byte = [0u8];
+
+            file.read_exact_at(&mut byte, offset as u64)?;
+            byte[0] ^= 0xff;
+            file.write_all_at(&byte, offset as u64)?;
+
+            Ok(())
+        };
 
-                continue;
-            }
+        flip()?;
+
+        let report = table.scrub()?;
+        assert!(!report.is_ok());
+        assert!(report.records.is_ok());
+        assert_eq!(report.columns[0].store.corrupt.len(), 1);
+        assert_eq!(report.columns[0].store.corrupt[0].idx, 0);
+
+        let integrity = table.check_integrity()?;
+        assert_eq!(integrity.violations.len(), 1);
+        assert_eq!(integrity.violations[0].check, "block checksum");
+        assert_eq!(integrity.violations[0].column, Some(0));
+
+        flip()?;
+        assert!(table.scrub()?.is_ok());
+        assert!(table.check_integrity()?.is_ok());
+        table.close()?;
+
+        // a table without checksums has nothing to check
+        let table = Table::new(id, TableConfig::new_persisted(&columns, &dir)?, None)?;
+        table.flush_all()?;
+        let report = table.scrub()?;
+        assert!(report.is_ok());
+        assert_eq!(report.records.verified, 0);
+        assert!(report.records.unchecked > 0);
+        table.close()?;
 
-            let stores = self.get_column_store_range(..values.len())?;
-            let handle = record_handle.clone();
-            let needs_rollback = handle.write_with(|mut data| {
-                data.update(|columns: &mut ColumnIndices| {
-                    let mut column_handles = Vec::with_capacity(val_count);
+        Ok(())
+    }
 
-                    for (column, value) in values.iter().enumerate() {
-                        if let Some(data) = value {
-                            let store = stores.get(column).expect("store exists");
-                            let data_insert_res = store.insert_one(Some(record), data.clone());
+    #[test]
+    fn test_delete_one() -> Result<()> {
+        let columns = vec![
+            DataConfig::new(DataType::Number),
+            DataConfig::new(DataType::Text(16)),
+        ];
+        let table = Table::new(TableId::new(), TableConfig::new(&columns)?, None)?;
+        let live = |column: usize| -> Result<usize> {
+            Ok(table
+                .get_column_store(column)?
+                .fragmentation_report()
+                .blocks
+                .iter()
+                .map(|block| block.live)
+                .sum())
+        };
 
-                            match data_insert_res {
-                                Ok(data_handle) => {
-                                    column_handles.push(data_handle.clone());
-                                    columns.replace(column, data_handle.into())?;
-                                }
-                                Err(StoreError::InsertError(
-                                    dbexp::store::result::InsertError::InvalidValue {
-                                        error, ..
-                                    },
-                                )) => {
-                                    all_errors.push((
-                                        idx,
-                                        InsertError::InvalidValue {
-                                            record_handle,
-                                            column_handles,
-                                            column,
-                                            values: values.clone(),
-                                            error,
-                                        },
-                                    ));
-
-                                    return Ok(None);
-                                }
-                                Err(error) => {
-                                    return Ok(Some((
-                                        column,
-                                        error,
-                                        record_handle,
-                                        column_handles,
-                                    )));
-                                }
-                            }
-                        }
-                    }
+        let rows = (0..6i64)
+            .map(|n| {
+                // odd rows never get a text value
+                let text = match n % 2 {
+                    0 => Some(columns[1].try_new_value(format!("row {}", n))?),
+                    _ => None,
+                };
 
-                    all_handles.push((idx, record_handle, column_handles));
-                    Ok(None)
-                })
-            })?;
+                table.insert_one(vec![Some(DataValue::Number(n.into())), text])
+            })
+            .collect::<Result<Vec<_>, _>>()?;
 
-            if let Some((_, _, record_handle, column_handles)) = needs_rollback {
-                for handle in column_handles {
-                    let _ = handle.remove_self();
-                }
+        assert_eq!(table.delete_one(rows[0].record_id)?, rows[0].values);
+        assert_eq!(table.delete_one(rows[1].record_id)?, rows[1].values);
+        assert_eq!(rows[1].values[1], None);
+        assert!(!table.contains(rows[0].record_id));
+        assert_eq!(table.row_count(), 4);
+        assert_eq!((live(0)?, live(1)?), (4, 2));
 
-                let _ = record_handle.remove_self();
+        let err = table.delete_one(rows[0].record_id).unwrap_err();
+        assert!(matches!(err, TableError::NotFound(_)), "{:?}", err);
 
-                while all_handles.len() > 0 || all_errors.len() > 0 {
-                    if let Some((_, error)) = all_errors.pop() {
-                        match error {
-                            InsertError::InvalidValue {
-                                record_handle,
-                                column_handles,
-                                ..
-                            } => {
-                                for handle in column_handles {
-                                    let _ = handle.remove_self();
-                                }
+        let records = [rows[2].record_id, rows[0].record_id, rows[3].record_id];
+        let DeleteState::Partial { deleted, errors } = table.delete_many(records) else {
+            panic!("expected the removed row to fail");
+        };
+        assert_eq!(
+            deleted,
+            vec![(0, rows[2].values.clone()), (2, rows[3].values.clone())]
+        );
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, 1);
+
+        let DeleteState::Done(deleted) = table.delete_many([rows[4].record_id, rows[5].record_id])
+        else {
+            panic!("expected every row to be removed");
+        };
+        assert_eq!(
+            deleted,
+            vec![rows[4].values.clone(), rows[5].values.clone()]
+        );
+        assert!(table.is_empty());
+        assert_eq!((live(0)?, live(1)?), (0, 0));
+        assert!(table.check_integrity()?.is_ok());
 
-                                let _ = record_handle.remove_self();
-                            }
-                            InsertError::NoValues { record_handle } => {
-                                let _ = record_handle.remove_self();
-                            }
-                            _ => {}
-                        }
-                    }
+        Ok(())
+    }
 
-                    if let Some((_, record_handle, column_handles)) = all_handles.pop() {
-                        for handle in column_handles {
-                            let _ = handle.remove_self();
-                        }
+    #[test]
+    fn test_get_row() -> Result<()> {
+        let columns = vec![
+            DataConfig::new(DataType::Number),
+            DataConfig::new(DataType::Text(16)),
+        ];
+        let table = Table::new(TableId::new(), TableConfig::new(&columns)?, None)?;
+
+        // the text column's store isn't opened until a value is written to it
+        let first = table.insert_one(vec![Some(DataValue::Number(1i64.into()))])?;
+        assert_eq!(
+            table.get_row(first.record_id)?,
+            Some(vec![Some(DataValue::Number(1i64.into())), None])
+        );
+
+        let second = table.insert_one(vec![
+            Some(DataValue::Number(2i64.into())),
+            Some(columns[1].try_new_value("two")?),
+        ])?;
+        assert_eq!(table.get_row(second.record_id)?, Some(second.values));
 
-                        let _ = record_handle.remove_self();
-                    }
-                }
+        table.delete(first.handle)?;
+        assert_eq!(table.get_row(first.record_id)?, None);
 
-                anyhow::bail!("unexpected error resulted in rollback")
-            }
-        }
+        let other = Table::new(TableId::new(), TableConfig::new(&columns)?, None)?;
+        assert_eq!(other.get_row(second.record_id)?, None);
 
-        if all_errors.is_empty() {
-            Ok(InsertState::Done(
-                all_handles
-                    .into_iter()
-                    .map(|(_, handle, _)| handle)
-                    .collect(),
-            ))
-        } else {
-            Ok(InsertState::Partial {
-                handles: all_handles,
-                errors: all_errors,
-            })
-        }
+        Ok(())
     }
-}
 
-#[allow(dead_code)]
-#[cfg(test)]
-mod tests {
-    use anyhow::Result;
-    use primitives::DataType;
+    #[test]
+    fn test_update_one() -> Result<()> {
+        let columns = vec![
+            DataConfig::new(DataType::Number),
+            DataConfig::new(DataType::Text(16)),
+        ];
+        let table = Table::new(TableId::new(), TableConfig::new(&columns)?, None)?;
+        let number = |n: i64| Some(DataValue::Number(n.into()));
+        let text = |text: &str| -> Result<Option<DataValue>> {
+            Ok(Some(columns[1].try_new_value(text.to_string())?))
+        };
 
-    use super::*;
+        let row = table.insert_one(vec![number(1)])?;
+
+        // a column without a value gets one, and the one changed keeps the record
+        assert_eq!(
+            table.update_one(row.record_id, vec![(1, text("one")?)])?,
+            vec![None]
+        );
+        assert_eq!(
+            table.update_one(row.record_id, vec![(0, number(2)), (1, None)])?,
+            vec![number(1), text("one")?]
+        );
+        assert_eq!(table.get_row(row.record_id)?, Some(vec![number(2), None]));
+
+        // nothing is written unless every value fits
+        let err = table
+            .update_one(row.record_id, vec![(1, text("two")?), (0, text("two")?)])
+            .unwrap_err();
+        assert!(matches!(err, TableError::Validation(_)), "{:?}", err);
+        assert!(table
+            .update_one(row.record_id, vec![(2, number(3))])
+            .is_err());
+        assert_eq!(table.get_row(row.record_id)?, Some(vec![number(2), None]));
+
+        // a row written by someone else between the read and the write is read again
+        RACED_UPDATES.set(UPDATE_ATTEMPTS - 1);
+        assert_eq!(
+            table.update_one(row.record_id, vec![(0, number(3))])?,
+            vec![number(2)]
+        );
+        assert_eq!(table.get_row(row.record_id)?, Some(vec![number(3), None]));
+
+        // but only so many times
+        RACED_UPDATES.set(UPDATE_ATTEMPTS);
+        let err = table
+            .update_one(row.record_id, vec![(0, number(4))])
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<UpdateConflict>(),
+            Some(&UpdateConflict {
+                record: row.record_id,
+                attempts: UPDATE_ATTEMPTS,
+            })
+        );
+        assert!(matches!(err, TableError::Conflict(_)), "{:?}", err);
+        assert_eq!(table.get_row(row.record_id)?, Some(vec![number(3), None]));
 
-    // #[test]
-    // fn test_column_configs() {
-    //
-    // }
+        table.delete(row.handle)?;
+        let err = table
+            .update_one(row.record_id, vec![(0, number(3))])
+            .unwrap_err();
+        assert!(matches!(err, TableError::NotFound(_)), "{:?}", err);
 
-    // #[test]
-    // fn test_table_config() {
-    //
-    // }
+        Ok(())
+    }
 
     #[test]
-    fn test_insert_one() -> Result<()> {
+    fn test_scan() -> Result<()> {
         let columns = vec![
             DataConfig::new(DataType::Number),
-            DataConfig::new(DataType::Bool),
-            DataConfig::new(DataType::Text(50)),
+            DataConfig::new(DataType::Text(16)),
         ];
 
-        let table_config = TableConfig::new(&columns)?;
-        let table = Table::new(TableId::new(), table_config, None)?;
+        let id = TableId::new();
+        let dir = TempDir::new("mem_table_scan");
+        let table_config = TableConfig::new_persisted(&columns, &dir)?;
 
-        assert_eq!(table.config, table_config);
+        let expected = {
+            let table = Table::new(id, table_config, None)?;
+            let mut rows = Vec::new();
 
-        table.insert_one(vec![
-            Some(DataValue::try_from_any(columns[0].data_type, 42)?),
-            Some(DataValue::Bool(true)),
-            Some(DataValue::try_from_any(columns[2].data_type, "testing")?),
-        ])?;
+            for n in 0..5i64 {
+                let row = table.insert_one(vec![
+                    Some(DataValue::Number(n.into())),
+                    Some(columns[1].try_new_value(n.to_string())?),
+                ])?;
+
+                rows.push((row.record_id, row.handle, row.values));
+            }
+
+            let (_, handle, _) = rows.remove(2);
+            table.delete(handle)?;
+
+            rows.into_iter()
+                .map(|(record, _, values)| (record, values))
+                .collect::<Vec<_>>()
+        };
+
+        // reopened, so the stores are read back from disk as the scan goes
+        let table = Table::new(id, table_config, None)?;
+        let mut seen = Vec::new();
+
+        table.scan(|record, values| {
+            // reading the same row again from the callback doesn't deadlock
+            assert_eq!(table.get_row(record)?.as_ref(), Some(&values));
+
+            seen.push((record, values));
+            Ok(())
+        })?;
+
+        assert_eq!(seen, expected);
+
+        let mut visited = 0;
+        let res = table.scan(|_, _| {
+            visited += 1;
+            anyhow::ensure!(visited < 3, "stop");
+            Ok(())
+        });
+
+        assert!(res.is_err());
+        assert_eq!(visited, 3);
 
-        println!("{:#?}", table);
+        drop(table);
 
         Ok(())
     }
 
     #[test]
-    fn test_insert() -> Result<()> {
+    fn test_unique_column() -> Result<()> {
         let columns = vec![
+            DataConfig::new(DataType::Text(32)).with_unique(),
             DataConfig::new(DataType::Number),
-            DataConfig::new(DataType::Bool),
-            DataConfig::new(DataType::Text(50)),
         ];
 
-        let table_config = TableConfig::new(&columns)?;
-        let table = Table::new(TableId::new(), table_config, None)?;
-
-        assert_eq!(table.config, table_config);
+        let id = TableId::new();
+        let dir = TempDir::new("mem_table_unique");
+        let table_config = TableConfig::new_persisted(&columns, &dir)?;
+
+        let email =
+            |email: &str| -> Result<DataValue> { Ok(columns[0].try_new_value(email.to_string())?) };
+        let row = |address: &str, n: i64| -> Result<Vec<Option<DataValue>>> {
+            Ok(vec![
+                Some(email(address)?),
+                Some(DataValue::Number(n.into())),
+            ])
+        };
 
-        const ROW_COUNT: usize = 10;
-        let alphabet = "abcdefghijklmnopqrstuvwxyz";
+        let (ada, bob) = {
+            let table = Table::new(id, table_config, None)?;
+            let ada = table.insert_one(row("ada@example.com", 1)?)?;
+
+            let Err(TableError::UniqueViolation(violation)) =
+                table.insert_one(row("ada@example.com", 2)?)
+            else {
+                panic!("expected a unique violation");
+            };
+            assert_eq!(
+                violation,
+                UniqueViolation {
+                    column: 0,
+                    value: email("ada@example.com")?,
+                    existing_record: ada.record_id,
+                }
+            );
+
+            // rows without a value don't take part
+            table.insert_one(vec![None, Some(DataValue::Number(3i64.into()))])?;
+            table.insert_one(vec![None, Some(DataValue::Number(4i64.into()))])?;
+
+            // a batch is checked against the rows before each row in it too
+            let InsertState::Partial { handles, errors } = table.insert(vec![
+                row("bob@example.com", 5)?,
+                row("ada@example.com", 6)?,
+                row("bob@example.com", 7)?,
+            ])?
+            else {
+                panic!("expected a partial insert");
+            };
+
+            assert_eq!(handles.len(), 1);
+            let bob = table.records.record_id(&handles[0].1);
+
+            assert!(matches!(
+                &errors[..],
+                [
+                    (1, InsertError::UniqueViolation { existing_record: first, .. }),
+                    (2, InsertError::UniqueViolation { existing_record: second, .. }),
+                ] if *first == ada.record_id && *second == bob
+            ));
+
+            // inserts rolled back part way give their values back
+            PANIC_ON_COLUMN.set(Some(1));
+            assert!(table
+                .insert(vec![row("cy@example.com", 8)?, row("di@example.com", 9)?])
+                .is_err());
+            assert!(table.insert_one(row("ed@example.com", 10)?).is_err());
+            PANIC_ON_COLUMN.set(None);
+
+            assert_eq!(table.find_unique(0, &email("cy@example.com")?)?, None);
+            assert_eq!(table.find_unique(0, &email("ed@example.com")?)?, None);
+            table.insert_one(row("cy@example.com", 8)?)?;
+
+            // an update can't take another row's value, but keeps its own
+            assert!(matches!(
+                table.update_one(bob, vec![(0, Some(email("ada@example.com")?))]),
+                Err(TableError::UniqueViolation(_))
+            ));
+            table.update_one(bob, vec![(1, Some(DataValue::Number(50i64.into())))])?;
+            table.update_one(bob, vec![(0, Some(email("bo@example.com")?))])?;
+
+            assert_eq!(table.find_unique(0, &email("bob@example.com")?)?, None);
+            assert_eq!(table.find_unique(0, &email("bo@example.com")?)?, Some(bob));
+            table.insert_one(row("bob@example.com", 11)?)?;
+
+            // a deleted row's value is free again
+            table.delete_one(ada.record_id)?;
+            let ada = table.insert_one(row("ada@example.com", 12)?)?;
+
+            table.close()?;
+            (ada.record_id, bob)
+        };
 
-        let mut n = 0;
-        let rows = std::iter::repeat_with(|| {
-            let idx = n / ROW_COUNT;
-            let row = vec![
-                Some(DataValue::try_from_any(columns[0].data_type, n)?),
-                Some(DataValue::Bool(idx % 2 == 0)),
-                Some(DataValue::try_from_any(
-                    columns[2].data_type,
-                    &alphabet[idx..idx + 1],
-                )?),
-            ];
+        // the index is built again when the table is opened
+        let table = Table::new(id, table_config, None)?;
 
-            n += 10;
-            Ok(row)
-        })
-        .take(ROW_COUNT)
-        .collect::<Result<Vec<_>>>()?;
+        assert_eq!(table.find_unique(0, &email("ada@example.com")?)?, Some(ada));
+        assert_eq!(table.find_unique(0, &email("bo@example.com")?)?, Some(bob));
+        assert!(matches!(
+            table.insert_one(row("bo@example.com", 13)?),
+            Err(TableError::UniqueViolation(_))
+        ));
+        assert!(matches!(
+            table.find_unique(1, &DataValue::Number(1i64.into())),
+            Err(TableError::Validation(_))
+        ));
 
-        let result = table.insert(rows)?;
+        let report = table.check_integrity()?;
+        assert!(report.is_ok(), "{}", report);
 
-        println!("{:#?}", result);
-        println!("##############################################################");
-        println!("##############################################################");
-        println!("##############################################################");
-        println!("{:#?}", table);
+        drop(table);
 
         Ok(())
     }
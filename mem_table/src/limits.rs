@@ -0,0 +1,25 @@
+use primitives::{bytes::Bytes, text::Text};
+use serde::Serialize;
+
+pub use dbexp::{block::MAX_BLOCK_CAPACITY, indices::MAX_COLUMNS};
+
+pub const MAX_TEXT_LEN: usize = Text::MAX_LEN;
+pub const MAX_BYTES_LEN: usize = Bytes::MAX_LEN;
+
+/// The hard limits of a table, for validating a schema before building it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub struct Limits {
+    pub max_columns: usize,
+    pub max_text_len: usize,
+    pub max_bytes_len: usize,
+    pub max_block_capacity: usize,
+}
+
+pub const fn limits() -> Limits {
+    Limits {
+        max_columns: MAX_COLUMNS,
+        max_text_len: MAX_TEXT_LEN,
+        max_bytes_len: MAX_BYTES_LEN,
+        max_block_capacity: MAX_BLOCK_CAPACITY,
+    }
+}
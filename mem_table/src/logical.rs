@@ -0,0 +1,174 @@
+use std::str::FromStr;
+
+use primitives::DataType;
+use serde::Serialize;
+
+/// What a text column holds beyond being text, which values are checked against when
+/// `DataConfig::check_logical_type` is set, both when a value is
+/// built for the column and when a row is validated. The checks are structural, to catch values that are
+/// plainly something else, not to prove an address exists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogicalType {
+    /// `local@domain.tld`, without whitespace, where the domain has at least two labels.
+    Email,
+    /// 7 to 15 digits, optionally led by `+`, and separated by spaces, dashes, dots or parentheses.
+    Phone,
+    /// An `http` or `https` URL with a host.
+    Url,
+    /// The hyphenated form, as in `67e55044-10b1-426f-9247-bb680e5fe0c8`.
+    Uuid,
+}
+
+impl LogicalType {
+    /// The column type a schema gets when it names the logical type.
+    pub const fn base_type(self) -> DataType {
+        match self {
+            Self::Email => DataType::Text(120),
+            Self::Phone => DataType::Text(20),
+            Self::Url => DataType::Text(2048),
+            Self::Uuid => DataType::Text(36),
+        }
+    }
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Email => "email",
+            Self::Phone => "phone",
+            Self::Url => "url",
+            Self::Uuid => "uuid",
+        }
+    }
+
+    /// Whether `text` has the shape of the logical type.
+    pub fn check(self, text: &str) -> bool {
+        match self {
+            Self::Email => is_email(text),
+            Self::Phone => is_phone(text),
+            Self::Url => is_url(text),
+            Self::Uuid => is_uuid(text),
+        }
+    }
+
+    /// Encodes an optional logical type, with the high bit set when it isn't checked.
+    pub(crate) fn into_u8(logical_type: Option<Self>, checked: bool) -> u8 {
+        let kind = match logical_type {
+            None => return 0,
+            Some(Self::Email) => 1,
+            Some(Self::Phone) => 2,
+            Some(Self::Url) => 3,
+            Some(Self::Uuid) => 4,
+        };
+
+        if checked {
+            kind
+        } else {
+            kind | 0x80
+        }
+    }
+
+    pub(crate) fn try_from_u8(value: u8) -> anyhow::Result<(Option<Self>, bool)> {
+        let logical_type = match value & 0x7f {
+            0 => None,
+            1 => Some(Self::Email),
+            2 => Some(Self::Phone),
+            3 => Some(Self::Url),
+            4 => Some(Self::Uuid),
+            other => anyhow::bail!("invalid logical type {}", other),
+        };
+
+        Ok((logical_type, value & 0x80 == 0))
+    }
+}
+
+impl std::fmt::Display for LogicalType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for LogicalType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "email" => Ok(Self::Email),
+            "phone" => Ok(Self::Phone),
+            "url" => Ok(Self::Url),
+            "uuid" => Ok(Self::Uuid),
+            other => anyhow::bail!(
+                "unknown logical type {:?}, expected email, phone, url or uuid",
+                other
+            ),
+        }
+    }
+}
+
+fn is_email(text: &str) -> bool {
+    let Some((local, domain)) = text.rsplit_once('@') else {
+        return false;
+    };
+
+    if local.is_empty() || local.len() > 64 || local.contains('@') {
+        return false;
+    }
+
+    if local.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return false;
+    }
+
+    let labels = domain.split('.').collect::<Vec<_>>();
+
+    labels.len() >= 2
+        && labels.iter().all(|label| {
+            !label.is_empty()
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_alphanumeric() || c == '-')
+        })
+        && labels.last().is_some_and(|tld| tld.chars().count() >= 2)
+}
+
+fn is_phone(text: &str) -> bool {
+    let rest = text.strip_prefix('+').unwrap_or(text);
+    let mut digits = 0;
+
+    for c in rest.chars() {
+        match c {
+            '0'..='9' => digits += 1,
+            ' ' | '-' | '.' | '(' | ')' => {}
+            _ => return false,
+        }
+    }
+
+    (7..=15).contains(&digits)
+}
+
+fn is_url(text: &str) -> bool {
+    let lower = text.get(..8).unwrap_or(text).to_ascii_lowercase();
+
+    let rest = if lower.starts_with("https://") {
+        &text[8..]
+    } else if lower.starts_with("http://") {
+        &text[7..]
+    } else {
+        return false;
+    };
+
+    let host = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    let host = host.rsplit_once('@').map_or(host, |(_, host)| host);
+
+    !host.is_empty()
+        && !host.starts_with(':')
+        && !text.chars().any(|c| c.is_whitespace() || c.is_control())
+}
+
+fn is_uuid(text: &str) -> bool {
+    let groups = text.split('-').collect::<Vec<_>>();
+
+    groups.len() == 5
+        && groups
+            .iter()
+            .zip([8, 4, 4, 4, 12])
+            .all(|(group, len)| group.len() == len && group.chars().all(|c| c.is_ascii_hexdigit()))
+}
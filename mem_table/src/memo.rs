@@ -0,0 +1,133 @@
+use anyhow::{Context, Result};
+use dbexp::values::DataValue;
+use indexmap::IndexMap;
+
+use crate::{DataConfig, InsertState, Table, TableError, ValueError};
+
+/// How many texts a batch insert remembers per column.
+pub const TEXT_MEMO_CAPACITY: usize = 1024;
+
+/// Remembers the values built for the texts of a batch, per column, so a text repeated across
+/// rows is built once and the rows after it get clones sharing its buffer. Keeps the `capacity`
+/// texts of each column used last, forgetting the others.
+#[derive(Debug)]
+pub struct TextMemo {
+    capacity: usize,
+    columns: Vec<IndexMap<Box<str>, DataValue>>,
+}
+
+impl TextMemo {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            columns: Vec::new(),
+        }
+    }
+
+    /// The value `config` builds from `text` for `column`, which is only built the first time.
+    /// Texts that don't convert aren't remembered, and fail every time.
+    pub fn try_new_value(
+        &mut self,
+        column: usize,
+        config: &DataConfig,
+        text: &str,
+    ) -> Result<DataValue, ValueError> {
+        if let Some(value) = self._get(column, text) {
+            return Ok(value);
+        }
+
+        let value = config.try_new_value(text.to_string())?;
+        self._remember(column, text, value.clone());
+
+        Ok(value)
+    }
+
+    /// Swaps the text values of a row for ones remembered equal to them, which share their
+    /// buffer, and remembers the others.
+    pub(crate) fn share(&mut self, values: &mut [Option<DataValue>]) {
+        for (column, value) in values.iter_mut().enumerate() {
+            let Some(DataValue::Text(text)) = value else {
+                continue;
+            };
+
+            match self._get(column, text.as_str()) {
+                Some(DataValue::Text(shared)) if shared.capacity() == text.capacity() => {
+                    *text = shared;
+                }
+                Some(_) => {}
+                None => {
+                    let shared = DataValue::Text(text.clone());
+                    self._remember(column, text.as_str(), shared);
+                }
+            }
+        }
+    }
+
+    fn _get(&mut self, column: usize, text: &str) -> Option<DataValue> {
+        let texts = self.columns.get_mut(column)?;
+        let idx = texts.get_index_of(text)?;
+        let last = texts.len() - 1;
+
+        texts.move_index(idx, last);
+        texts.get_index(last).map(|(_, value)| value.clone())
+    }
+
+    fn _remember(&mut self, column: usize, text: &str, value: DataValue) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if self.columns.len() <= column {
+            self.columns.resize_with(column + 1, IndexMap::new);
+        }
+
+        let texts = &mut self.columns[column];
+
+        if texts.len() >= self.capacity {
+            texts.shift_remove_index(0);
+        }
+
+        texts.insert(text.into(), value);
+    }
+}
+
+impl Table {
+    /// Inserts rows of text fields, like the records of a CSV file, each converted for its column
+    /// with `DataConfig::try_new_value`. A field repeated across the batch is converted once, and
+    /// its rows share the value. A field that doesn't convert fails the batch before anything is
+    /// inserted.
+    pub fn insert_str_rows<'a, I, U>(&self, rows: I) -> Result<InsertState, TableError>
+    where
+        I: IntoIterator<Item = U>,
+        U: IntoIterator<Item = Option<&'a str>>,
+    {
+        let mut memo = TextMemo::new(TEXT_MEMO_CAPACITY);
+        let mut converted = Vec::new();
+
+        for (idx, row) in rows.into_iter().enumerate() {
+            let mut values = Vec::new();
+
+            for (column, field) in row.into_iter().enumerate() {
+                let Some(field) = field else {
+                    values.push(None);
+                    continue;
+                };
+
+                let config = self
+                    .config
+                    .columns
+                    .get(column)
+                    .ok_or_else(|| TableError::not_found(format!("column {}", column)))?;
+                let value = memo
+                    .try_new_value(column, config, field)
+                    .with_context(|| format!("row {} doesn't convert", idx))?;
+
+                values.push(Some(value));
+            }
+
+            converted.push(values);
+        }
+
+        self.insert(converted)
+    }
+}
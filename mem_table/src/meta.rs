@@ -0,0 +1,143 @@
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use dbexp::{object_ids::TableId, values::DataValue};
+use primitives::{Bytes, DataType, InternalPath, Text};
+
+use crate::{DataConfig, OpenOptions, Table, TableConfig, UpdateOutcome};
+
+pub const META_KEY_LEN: usize = 256;
+pub const META_VALUE_LEN: usize = 4096;
+
+/// The directory annotations are persisted in, inside the table's own.
+pub(crate) const META_DIR: &str = "meta";
+
+const KEY_COLUMN: usize = 0;
+const VALUE_COLUMN: usize = 1;
+
+/// Small key-value annotations kept next to a table's data, like schema versions or import
+/// watermarks. Values are stored serde encoded, so any `DataValue` that fits in
+/// `META_VALUE_LEN` bytes once encoded can be kept. Setting a key that exists replaces its value.
+#[derive(Debug, Clone)]
+pub struct MetaTable {
+    table: Table,
+    /// Serializes `set`, so two writers can't both insert the same new key.
+    write_lock: Arc<Mutex<()>>,
+}
+
+impl MetaTable {
+    /// Opens the annotations of the table with `parent` config. They are persisted in a `meta`
+    /// directory inside the table's own, or kept in memory if the table is.
+    pub(crate) fn open(id: TableId, parent: &TableConfig, root: InternalPath) -> Result<Self> {
+        let columns = [
+            DataConfig::new(DataType::Text(META_KEY_LEN as u32)),
+            DataConfig::new(DataType::Bytes(META_VALUE_LEN as u32)),
+        ];
+
+        let config = TableConfig {
+            persistance: if parent.persistance.is_empty() {
+                parent.persistance
+            } else {
+                parent.persistance.child(META_DIR)?
+            },
+            ..TableConfig::new(columns)?
+        }
+        .with_primary_key([KEY_COLUMN])?;
+
+        let (table, _) = Table::_new(id, config, None, root, &OpenOptions::default())?;
+
+        Ok(Self {
+            table,
+            write_lock: Arc::new(Mutex::new(())),
+        })
+    }
+
+    pub(crate) fn table(&self) -> &Table {
+        &self.table
+    }
+
+    fn _key(key: &str) -> Result<DataValue> {
+        Ok(DataValue::Text(
+            Text::try_from_str(key, META_KEY_LEN).map_err(|e| {
+                e.context(format!("meta key is longer than {} bytes", META_KEY_LEN))
+            })?,
+        ))
+    }
+
+    fn _decode(value: Option<DataValue>) -> Result<DataValue> {
+        match value {
+            Some(DataValue::Bytes(bytes)) => Ok(serde_json::from_slice(bytes.as_slice())?),
+            other => anyhow::bail!("meta value is corrupt: {:?}", other),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Result<Option<DataValue>> {
+        let Some(handle) = self.table.find_by_pk(&[Self::_key(key)?])? else {
+            return Ok(None);
+        };
+
+        let (mut values, _) = self.table.get_versioned(&handle)?;
+
+        Ok(Some(Self::_decode(values.swap_remove(VALUE_COLUMN))?))
+    }
+
+    pub fn set(&self, key: &str, value: DataValue) -> Result<()> {
+        let key = Self::_key(key)?;
+        let encoded = serde_json::to_vec(&value)?;
+        let encoded = Bytes::try_from_slice(&encoded, META_VALUE_LEN).map_err(|e| {
+            e.context(format!(
+                "meta value is {} bytes once encoded, max is {}",
+                encoded.len(),
+                META_VALUE_LEN
+            ))
+        })?;
+
+        let values = vec![Some(key.clone()), Some(DataValue::Bytes(encoded))];
+        let _guard = self.write_lock.lock().unwrap_or_else(|e| e.into_inner());
+
+        match self.table.find_by_pk(&[key])? {
+            Some(handle) => {
+                let (_, gen) = self.table.get_versioned(&handle)?;
+
+                match self.table.update_if(&handle, gen, values)? {
+                    UpdateOutcome::Updated(_) => Ok(()),
+                    UpdateOutcome::Conflict(_) => anyhow::bail!("meta value changed during set"),
+                }
+            }
+            None => {
+                self.table.insert_one(values)?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Returns `false` if there was nothing stored under `key`.
+    pub fn remove(&self, key: &str) -> Result<bool> {
+        let _guard = self.write_lock.lock().unwrap_or_else(|e| e.into_inner());
+
+        match self.table.find_by_pk(&[Self::_key(key)?])? {
+            Some(handle) => Ok(self.table.delete(handle)?),
+            None => Ok(false),
+        }
+    }
+
+    /// Every key and value, ordered by key.
+    pub fn iter(&self) -> Result<impl Iterator<Item = (String, DataValue)>> {
+        let mut entries = Vec::new();
+
+        for (key, handle) in self.table.scan_pk_range(..) {
+            let Some(DataValue::Text(name)) = key.0.first() else {
+                anyhow::bail!("meta key is corrupt: {}", key);
+            };
+
+            let (mut values, _) = self.table.get_versioned(&handle)?;
+
+            entries.push((
+                name.as_str().to_string(),
+                Self::_decode(values.swap_remove(VALUE_COLUMN))?,
+            ));
+        }
+
+        Ok(entries.into_iter())
+    }
+}
@@ -0,0 +1,250 @@
+//! Changes to the columns of a table: adding and dropping them, and rewriting the values already
+//! in one.
+//!
+//! Columns keep their id and their cell in the rows' `ColumnIndices` when the columns around them
+//! are added and dropped, see `ColumnConfigs::cell`. Only their position moves, so the rows written
+//! before a change read the same afterwards.
+
+use dbexp::{indices::ColumnIndices, values::DataValue};
+use indexmap::IndexMap;
+use primitives::{Bytes, DataType, ExpectedType, InternalString, Text};
+
+use crate::{
+    cardinality::sketch_key, defaults::sequence_key, overflow::truncate_str,
+    primary_key::PrimaryKey, ColumnConfigs, DataConfig, OpenOptions, RebuildReport, Table,
+    TableConfig, TableError,
+};
+
+/// What `shrink_column` does with a value longer than the new capacity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ShrinkPolicy {
+    /// The shrink stops, leaving the column as it was.
+    #[default]
+    Fail,
+    /// The value is cut down to the new capacity, text on a character boundary.
+    Truncate,
+}
+
+/// Rewrites every value of the text or bytes `column` at `new_cap`, smaller than the column's
+/// capacity, with `Table::rebuild_column_online`, so the same tables can be shrunk as rebuilt.
+/// Values that don't fit fail the shrink or are truncated, as `policy` says.
+///
+/// Returns a handle to the table whose config has the column at `new_cap`, which the catalog
+/// should record. Handles to the table from before still take values as long as the old capacity,
+/// so they're best replaced by the returned one.
+pub fn shrink_column(
+    table: &Table,
+    column: usize,
+    new_cap: u32,
+    policy: ShrinkPolicy,
+) -> Result<(Table, RebuildReport), TableError> {
+    let Some(config) = table.config.columns.get(column) else {
+        return Err(TableError::not_found(format!("column {}", column)));
+    };
+
+    let new_type = match config.data_type.into_inner() {
+        DataType::Text(cap) if new_cap < cap => DataType::Text(new_cap),
+        DataType::Bytes(cap) if new_cap < cap => DataType::Bytes(new_cap),
+        DataType::Text(cap) | DataType::Bytes(cap) => {
+            return Err(TableError::invalid(format!(
+                "column {} holds {} bytes, which {} doesn't shrink",
+                column, cap, new_cap
+            )))
+        }
+        other => {
+            return Err(TableError::invalid(format!(
+                "column {} is {:?}, which has no capacity",
+                column, other
+            )))
+        }
+    };
+
+    new_type.validate().map_err(TableError::invalid)?;
+
+    let cap = new_cap as usize;
+    let report = table.rebuild_column_online(column, |value| {
+        let too_long = |len: usize| {
+            anyhow::anyhow!(
+                "a value of {} bytes doesn't fit in column {} at {} bytes",
+                len,
+                column,
+                cap
+            )
+        };
+
+        Ok(match value {
+            DataValue::Text(text) => {
+                if text.len() > cap && policy == ShrinkPolicy::Fail {
+                    return Err(too_long(text.len()));
+                }
+
+                DataValue::Text(Text::try_from_str(truncate_str(text.as_str(), cap), cap)?)
+            }
+            DataValue::Bytes(bytes) => {
+                if bytes.len() > cap && policy == ShrinkPolicy::Fail {
+                    return Err(too_long(bytes.len()));
+                }
+
+                let len = bytes.len().min(cap);
+                DataValue::Bytes(Bytes::try_from_slice(&bytes.as_slice()[..len], cap)?)
+            }
+            other => other.clone(),
+        })
+    })?;
+
+    let mut config = table.config;
+    config.columns = config
+        .columns
+        .with_data_type(column, ExpectedType::new(new_type))
+        .map_err(TableError::invalid)?;
+
+    Ok((
+        Table {
+            config,
+            ..table.clone()
+        },
+        report,
+    ))
+}
+
+/// Adds a column after the last one, with an id of its own, see `Table::column_id`. The rows
+/// already in the table read empty in it, as its default only fills the rows inserted afterwards.
+/// The column is named after `config.name`, if it has one.
+///
+/// The table is closed and opened again with the column, so only persisted tables can add one.
+/// Returns the reopened table, whose config the catalog should record. Handles to the table from
+/// before are closed.
+pub fn add_column(table: &Table, config: DataConfig) -> Result<Table, TableError> {
+    let columns = table
+        .config
+        .columns
+        .with_column(config)
+        .map_err(TableError::invalid)?;
+    let mut names = table.columns_by_name.clone();
+
+    if let Some(name) = config.name {
+        if names.insert(name, columns.len() - 1).is_some() {
+            return Err(TableError::invalid(format!(
+                "the table has a column named {} already",
+                name
+            )));
+        }
+    }
+
+    _reopen_with(table, columns, table.config.primary_key, names, |_| Ok(()))
+}
+
+/// Drops `column`, moving the columns after it up a position while keeping their ids. Its values
+/// are deleted and taken out of every row, so a column added later can have its cell. Columns of
+/// the primary key can't be dropped, nor can a table's only column.
+///
+/// Like `add_column`, this closes and reopens the table, which has to be persisted.
+pub fn drop_column(table: &Table, column: usize) -> Result<Table, TableError> {
+    let Some(config) = table.config.columns.get(column).copied() else {
+        return Err(TableError::not_found(format!("column {}", column)));
+    };
+
+    if table.config.primary_key.columns().any(|key| key == column) {
+        return Err(TableError::invalid(format!(
+            "column {} is part of the primary key",
+            column
+        )));
+    }
+
+    let columns = table
+        .config
+        .columns
+        .without(column)
+        .map_err(TableError::invalid)?;
+    let primary_key = PrimaryKey::new(
+        table
+            .config
+            .primary_key
+            .columns()
+            .map(|key| if key > column { key - 1 } else { key })
+            .collect::<Vec<_>>(),
+    )
+    .map_err(TableError::invalid)?;
+    let names = table
+        .columns_by_name
+        .iter()
+        .filter(|(_, idx)| **idx != column)
+        .map(|(name, idx)| (*name, if *idx > column { idx - 1 } else { *idx }))
+        .collect();
+
+    _reopen_with(table, columns, primary_key, names, |table| {
+        let cell = table._cell(column);
+        let mut rows = Vec::new();
+
+        table.records.foreach_live(|handle, indices| {
+            if indices.get(cell).is_some() {
+                rows.push(handle);
+            }
+        });
+
+        for handle in rows {
+            handle.write_with(|mut data| {
+                data.update(|indices: &mut ColumnIndices| {
+                    indices.take(cell);
+                    Ok(())
+                })
+            })?;
+        }
+
+        if !table._is_unavailable(column) {
+            table.get_column_store(column)?.truncate()?;
+        }
+
+        // so closing doesn't write the sketch back
+        table.sketches.write().shift_remove(&column);
+
+        if config.default.is_some() || config.track_cardinality {
+            let meta = table.meta()?;
+
+            meta.remove(&sequence_key(cell))?;
+            meta.remove(&sketch_key(cell))?;
+        }
+
+        Ok(())
+    })
+}
+
+/// Runs `prepare` on the table with writes held off, then closes it and opens it again with
+/// `columns`, `primary_key` and `names`.
+fn _reopen_with(
+    table: &Table,
+    columns: ColumnConfigs,
+    primary_key: PrimaryKey,
+    names: IndexMap<InternalString, usize>,
+    prepare: impl FnOnce(&Table) -> Result<(), TableError>,
+) -> Result<Table, TableError> {
+    table._ensure_open()?;
+
+    if table.config.persistance.is_empty() {
+        return Err(TableError::invalid(
+            "only persisted tables can add or drop columns, since they're reopened",
+        ));
+    }
+
+    {
+        let _writes = table.write_gate.write();
+        prepare(table)?;
+    }
+
+    table.close()?;
+
+    let config = TableConfig {
+        columns,
+        primary_key,
+        ..table.config
+    };
+    let (reopened, _) = Table::_new(
+        table.id,
+        config,
+        Some(names),
+        table.root,
+        &OpenOptions::default(),
+    )?;
+
+    Ok(reopened)
+}
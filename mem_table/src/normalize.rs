@@ -0,0 +1,130 @@
+use std::borrow::Cow;
+
+use dbexp::values::DataValue;
+use primitives::Text;
+
+use crate::{DataConfig, Table};
+
+/// Cleanups applied to text on its way into a column, before its length is checked, so a value
+/// that only fits once trimmed is accepted. They apply to every write of the column, but not to
+/// values a scan compares it against, which have to be normalized the same way to match, with
+/// `DataConfig::normalized`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Normalization {
+    /// Leading and trailing whitespace is removed.
+    pub trim: bool,
+    /// Every run of whitespace is replaced with a single space.
+    pub collapse_whitespace: bool,
+    pub lowercase: bool,
+}
+
+impl Normalization {
+    pub const NONE: Self = Self {
+        trim: false,
+        collapse_whitespace: false,
+        lowercase: false,
+    };
+
+    pub fn is_none(self) -> bool {
+        self == Self::NONE
+    }
+
+    pub(crate) fn into_u8(self) -> u8 {
+        self.trim as u8 | (self.collapse_whitespace as u8) << 1 | (self.lowercase as u8) << 2
+    }
+
+    pub(crate) fn try_from_u8(value: u8) -> anyhow::Result<Self> {
+        if value > 0b111 {
+            anyhow::bail!("invalid normalization flags {:#b}", value);
+        }
+
+        Ok(Self {
+            trim: value & 1 != 0,
+            collapse_whitespace: value & 0b10 != 0,
+            lowercase: value & 0b100 != 0,
+        })
+    }
+
+    /// The normalized form of `text`, borrowed from it unless more than trimming was needed.
+    pub fn apply(self, text: &str) -> Cow<'_, str> {
+        let mut text = Cow::Borrowed(if self.trim { text.trim() } else { text });
+
+        if self.collapse_whitespace && has_run_to_collapse(&text) {
+            let mut collapsed = String::with_capacity(text.len());
+            let mut in_run = false;
+
+            for c in text.chars() {
+                if c.is_whitespace() {
+                    if !in_run {
+                        collapsed.push(' ');
+                    }
+
+                    in_run = true;
+                } else {
+                    collapsed.push(c);
+                    in_run = false;
+                }
+            }
+
+            text = Cow::Owned(collapsed);
+        }
+
+        if self.lowercase && text.chars().any(char::is_uppercase) {
+            text = Cow::Owned(text.to_lowercase());
+        }
+
+        text
+    }
+}
+
+/// Whether collapsing whitespace changes `text`, which is when any whitespace isn't a lone space.
+fn has_run_to_collapse(text: &str) -> bool {
+    let mut prev_ws = false;
+
+    text.chars().any(|c| {
+        let ws = c.is_whitespace();
+        let collapses = ws && (prev_ws || c != ' ');
+        prev_ws = ws;
+        collapses
+    })
+}
+
+impl DataConfig {
+    /// `value` as the column stores it, normalized as the column asks. A value keeps its capacity
+    /// unless lowercasing made it longer. Anything but text comes back as it was. Queries don't
+    /// normalize what they compare the column with unless they ask for it with `normalized(col)`.
+    pub fn normalized(&self, value: DataValue) -> DataValue {
+        let DataValue::Text(text) = &value else {
+            return value;
+        };
+
+        if self.normalize.is_none() {
+            return value;
+        }
+
+        let normalized = self.normalize.apply(text.as_str());
+
+        if normalized == text.as_str() {
+            return value;
+        }
+
+        // the capacity fits, so this only fails on a bug
+        match Text::try_from_str(&normalized, text.capacity().max(normalized.len())) {
+            Ok(normalized) => DataValue::Text(normalized),
+            Err(_) => value,
+        }
+    }
+}
+
+impl Table {
+    /// Normalizes the text values of a row as their columns ask, see `DataConfig::normalized`.
+    pub(crate) fn _normalize(&self, values: &mut [Option<DataValue>]) {
+        for (column, value) in values.iter_mut().enumerate() {
+            let Some(config) = self.config.columns.get(column) else {
+                continue;
+            };
+
+            *value = value.take().map(|value| config.normalized(value));
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::Result;
+use dbexp::values::DataValue;
+use primitives::{Number, O64};
+
+use crate::{meta::MetaTable, InsertedRow, Table, TableError};
+
+/// How many operations a table remembers. Inserting past it forgets the oldest.
+pub const MAX_LOGGED_OPS: usize = 100_000;
+
+/// What the operations are kept under in the table's annotations, followed by the op id.
+const OP_KEY_PREFIX: &str = "op:";
+
+/// The operations of `Table::insert_idempotent`, by op id and by the sequence number of the row
+/// each inserted, which unlike its record id is never handed out again. Persisted in the table's
+/// annotations, and read back from them on first use.
+#[derive(Debug, Default)]
+pub(crate) struct OpsLog {
+    by_op: HashMap<O64, u64>,
+    by_seq: BTreeMap<u64, O64>,
+}
+
+impl OpsLog {
+    fn load(meta: &MetaTable) -> Result<Self> {
+        let mut log = Self::default();
+
+        for (key, value) in meta.iter()? {
+            let Some(op_id) = key.strip_prefix(OP_KEY_PREFIX) else {
+                continue;
+            };
+
+            let seq = match value {
+                DataValue::Number(Number::Unsigned(seq)) => seq,
+                other => anyhow::bail!("operation {} is corrupt: {:?}", op_id, other),
+            };
+
+            log.insert(op_id.parse()?, seq);
+        }
+
+        Ok(log)
+    }
+
+    fn insert(&mut self, op_id: O64, seq: u64) {
+        self.by_op.insert(op_id, seq);
+        self.by_seq.insert(seq, op_id);
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.by_op.len()
+    }
+}
+
+fn op_key(op_id: O64) -> String {
+    format!("{}{}", OP_KEY_PREFIX, op_id)
+}
+
+impl Table {
+    /// Inserts a row once per `op_id`, so a client retrying an insert it never heard back from
+    /// doesn't add it twice. Inserting with an op id already used returns the row it inserted,
+    /// whatever `values` are this time, and fails if that row was deleted since. Inserts with
+    /// the same op id wait for each other, so only one of them inserts.
+    ///
+    /// Only the last `MAX_LOGGED_OPS` operations are remembered, see `evict_ops`, and an op id
+    /// forgotten inserts a new row again. The operation is logged after its row is inserted, so a
+    /// crash in between forgets it as well.
+    pub fn insert_idempotent(
+        &self,
+        op_id: O64,
+        values: Vec<Option<DataValue>>,
+    ) -> Result<InsertedRow, TableError> {
+        self._ensure_open()?;
+
+        let mut ops = self.ops.lock().unwrap_or_else(|e| e.into_inner());
+        let ops = self._loaded_ops(&mut ops)?;
+
+        if let Some(&seq) = ops.by_op.get(&op_id) {
+            let handle = self.get_by_seq(seq).ok_or_else(|| {
+                TableError::not_found(format!("the row inserted by operation {}", op_id))
+            })?;
+            let (values, _) = self.get_versioned(&handle)?;
+
+            return Ok(InsertedRow {
+                record_id: self.records.record_id(&handle),
+                handle,
+                values,
+            });
+        }
+
+        let inserted = self.insert_one(values)?;
+        let seq = self.seq_of(&inserted.handle)?;
+
+        self.meta()?
+            .set(&op_key(op_id), DataValue::Number(Number::from(seq)))?;
+        ops.insert(op_id, seq);
+
+        if ops.len() > MAX_LOGGED_OPS {
+            self._evict_ops(ops, MAX_LOGGED_OPS)?;
+        }
+
+        Ok(inserted)
+    }
+
+    /// Forgets every operation of `insert_idempotent` but the `keep` latest. Returns how many were
+    /// forgotten.
+    pub fn evict_ops(&self, keep: usize) -> Result<usize, TableError> {
+        let mut ops = self.ops.lock().unwrap_or_else(|e| e.into_inner());
+        let ops = self._loaded_ops(&mut ops)?;
+
+        Ok(self._evict_ops(ops, keep)?)
+    }
+
+    fn _loaded_ops<'a>(&self, ops: &'a mut Option<OpsLog>) -> Result<&'a mut OpsLog> {
+        if ops.is_none() {
+            *ops = Some(OpsLog::load(&self.meta()?)?);
+        }
+
+        Ok(ops.as_mut().unwrap())
+    }
+
+    fn _evict_ops(&self, ops: &mut OpsLog, keep: usize) -> Result<usize> {
+        let meta = self.meta()?;
+        let mut evicted = 0;
+
+        while ops.len() > keep {
+            let Some((_, op_id)) = ops.by_seq.pop_first() else {
+                break;
+            };
+
+            ops.by_op.remove(&op_id);
+            meta.remove(&op_key(op_id))?;
+            evicted += 1;
+        }
+
+        Ok(evicted)
+    }
+}
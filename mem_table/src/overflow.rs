@@ -0,0 +1,125 @@
+use std::str::FromStr;
+
+use dbexp::values::DataValue;
+use primitives::{Bytes, DataType, Text};
+
+use crate::{RowValidationError, Table};
+
+/// What happens to a text or bytes value longer than its column holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum OverflowPolicy {
+    /// The value is refused, and with it the write.
+    #[default]
+    Error,
+    /// The value is cut down to the column's capacity, text on a character boundary.
+    Truncate,
+    /// The row the value is in is refused with `RowValidationError::TooLong`, which a batch insert
+    /// reports for that row while inserting the others.
+    RejectRow,
+}
+
+impl OverflowPolicy {
+    pub(crate) fn into_u8(self) -> u8 {
+        match self {
+            Self::Error => 0,
+            Self::Truncate => 1,
+            Self::RejectRow => 2,
+        }
+    }
+
+    pub(crate) fn try_from_u8(value: u8) -> anyhow::Result<Self> {
+        match value {
+            0 => Ok(Self::Error),
+            1 => Ok(Self::Truncate),
+            2 => Ok(Self::RejectRow),
+            other => anyhow::bail!("invalid overflow policy {}", other),
+        }
+    }
+}
+
+impl FromStr for OverflowPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "error" => Ok(Self::Error),
+            "truncate" => Ok(Self::Truncate),
+            "reject_row" => Ok(Self::RejectRow),
+            other => anyhow::bail!(
+                "unknown overflow policy {:?}, expected error, truncate or reject_row",
+                other
+            ),
+        }
+    }
+}
+
+/// The longest prefix of `text` that fits in `cap` bytes without splitting a character.
+pub(crate) fn truncate_str(text: &str, cap: usize) -> &str {
+    if text.len() <= cap {
+        return text;
+    }
+
+    let mut end = cap;
+
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    &text[..end]
+}
+
+impl Table {
+    /// Fits the text and bytes values of a row built for wider columns into the table's columns,
+    /// as their overflow policies say. Values longer than a column under `Error` are left alone,
+    /// for `validate_row` to refuse as before.
+    pub(crate) fn _fit_overflow(
+        &self,
+        values: &mut [Option<DataValue>],
+    ) -> Result<(), RowValidationError> {
+        for (column, value) in values.iter_mut().enumerate() {
+            let (Some(data), Some(config)) = (value.as_ref(), self.config.columns.get(column))
+            else {
+                continue;
+            };
+
+            let policy = config.overflow;
+
+            if policy == OverflowPolicy::Error || config.data_type.accepts(data.get_type()) {
+                continue;
+            }
+
+            let (len, cap) = match (data, config.data_type.into_inner()) {
+                (DataValue::Text(text), DataType::Text(cap)) => (text.len(), cap as usize),
+                (DataValue::Bytes(bytes), DataType::Bytes(cap)) => (bytes.len(), cap as usize),
+                _ => continue,
+            };
+
+            if len > cap && policy == OverflowPolicy::RejectRow {
+                return Err(RowValidationError::TooLong {
+                    column,
+                    name: self.schema().column_name(column),
+                    len,
+                    cap,
+                });
+            }
+
+            // the capacities fit, so these only fail on a bug
+            let fitted = match data {
+                DataValue::Text(text) => {
+                    Text::try_from_str(truncate_str(text.as_str(), cap), cap).map(DataValue::Text)
+                }
+                DataValue::Bytes(bytes) => {
+                    Bytes::try_from_slice(&bytes.as_slice()[..len.min(cap)], cap)
+                        .map(DataValue::Bytes)
+                }
+                _ => continue,
+            };
+
+            if let Ok(fitted) = fitted {
+                *value = Some(fitted);
+            }
+        }
+
+        Ok(())
+    }
+}
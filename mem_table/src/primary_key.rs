@@ -0,0 +1,153 @@
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use dbexp::{records::RecordHandle, values::DataValue};
+use primitives::{
+    byte_encoding::{ByteDecoder, ByteEncoder, FromBytes, IntoBytes},
+    impl_access_bytes_for_into_bytes_type,
+};
+
+use crate::limits::MAX_COLUMNS;
+
+/// The columns making up a table's primary key, in key order. An empty key means the table only
+/// has its synthetic `RecordId`.
+#[derive(Clone, Copy)]
+pub struct PrimaryKey(u8, [u8; MAX_COLUMNS]);
+
+impl Default for PrimaryKey {
+    fn default() -> Self {
+        Self(0, [0; MAX_COLUMNS])
+    }
+}
+
+impl std::fmt::Debug for PrimaryKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.columns()).finish()
+    }
+}
+
+impl PartialEq for PrimaryKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+impl Eq for PrimaryKey {}
+
+impl std::hash::Hash for PrimaryKey {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.as_slice().hash(state)
+    }
+}
+
+impl_access_bytes_for_into_bytes_type!(PrimaryKey);
+
+impl IntoBytes for PrimaryKey {
+    fn encode_bytes(&self, x: &mut ByteEncoder<'_>) -> Result<()> {
+        x.encode(self.0)?;
+        x.encode_bytes(self.as_slice())
+    }
+}
+
+impl FromBytes for PrimaryKey {
+    fn decode_bytes(this: &mut Self, x: &mut ByteDecoder<'_>) -> Result<()> {
+        x.decode(&mut this.0)?;
+
+        if this.0 as usize > MAX_COLUMNS {
+            anyhow::bail!("primary key has {} columns, max is {}", this.0, MAX_COLUMNS);
+        }
+
+        x.read_exact(&mut this.1[..this.0 as usize])
+    }
+}
+
+impl PrimaryKey {
+    pub fn new(columns: impl AsRef<[usize]>) -> Result<Self> {
+        let columns = columns.as_ref();
+
+        if columns.len() > MAX_COLUMNS {
+            anyhow::bail!(
+                "primary key column count {} exceeds MAX_COLUMNS ({})",
+                columns.len(),
+                MAX_COLUMNS
+            );
+        }
+
+        let mut inner = [0; MAX_COLUMNS];
+
+        for (i, column) in columns.iter().copied().enumerate() {
+            if column >= MAX_COLUMNS {
+                anyhow::bail!("primary key column {} is out of bounds", column);
+            }
+
+            if columns[..i].contains(&column) {
+                anyhow::bail!("primary key lists column {} more than once", column);
+            }
+
+            inner[i] = column as u8;
+        }
+
+        Ok(Self(columns.len() as u8, inner))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0 as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn columns(&self) -> impl Iterator<Item = usize> + '_ {
+        self.as_slice().iter().map(|column| *column as usize)
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        &self.1[..self.0 as usize]
+    }
+
+    /// Builds the key of a row from its values. Every key column has to have a value.
+    pub fn key_of(&self, values: &[Option<DataValue>]) -> Result<CompositeKey> {
+        self.columns()
+            .map(|column| match values.get(column) {
+                Some(Some(value)) => Ok(value.clone()),
+                _ => Err(anyhow::anyhow!("primary key column {} is null", column)),
+            })
+            .collect::<Result<Vec<_>>>()
+            .map(CompositeKey)
+    }
+}
+
+/// The values of a row's primary key columns, in key order.
+///
+/// Keys compare column by column, left to right, and the first column that differs decides. Each
+/// column compares by `DataValue`'s order: values of the same type by value (numbers numerically,
+/// text and bytes lexicographically by byte), and values of different types by the type's position
+/// in `DataValue`. A key that is a prefix of another sorts before it, so every key starting with a
+/// given prefix is in one contiguous range that starts at the prefix itself.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CompositeKey(pub Vec<DataValue>);
+
+impl std::fmt::Display for CompositeKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "(")?;
+
+        for (i, value) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+
+            write!(f, "{}", value)?;
+        }
+
+        write!(f, ")")
+    }
+}
+
+impl CompositeKey {
+    pub fn starts_with(&self, prefix: &[DataValue]) -> bool {
+        self.0.starts_with(prefix)
+    }
+}
+
+pub(crate) type KeyIndex = BTreeMap<CompositeKey, RecordHandle>;
@@ -0,0 +1,218 @@
+//! Rate limiting of a table's reads and writes, so one busy table can't starve the others sharing
+//! a machine. A table given a `RateLimiter` with `Table::set_rate_limiter` asks it before every
+//! scan and insert, with the rows read or written as the cost. Without one, which is the default,
+//! nothing is asked and nothing is counted.
+
+use std::{
+    fmt,
+    sync::{atomic::Ordering, Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{Table, TableError};
+
+/// A read or write turned away by a `RateLimiter`.
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("throttled, retry after {retry_after:?}")]
+pub struct Throttled {
+    /// How long until the same read or write would go ahead.
+    pub retry_after: Duration,
+}
+
+/// Decides whether reads and writes of a table may go ahead, see `Table::set_rate_limiter`. Both
+/// hooks either return once the call may go ahead, with how long they held it, or fail with
+/// `Throttled`. They're called once per scan or insert, never per row.
+pub trait RateLimiter: fmt::Debug + Send + Sync {
+    /// Before reading about `cost` rows.
+    fn before_read(&self, cost: u64) -> Result<Duration, Throttled>;
+    /// Before writing `cost` rows.
+    fn before_write(&self, cost: u64) -> Result<Duration, Throttled>;
+}
+
+/// How fast rows may be read or written: `per_sec` rows a second on average, and up to `burst`
+/// at once after a quiet spell.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    pub per_sec: f64,
+    pub burst: f64,
+}
+
+impl RateLimit {
+    /// A limit whose burst is a second's worth of rows.
+    pub fn per_sec(per_sec: f64) -> Self {
+        Self {
+            per_sec,
+            burst: per_sec,
+        }
+    }
+
+    pub fn with_burst(self, burst: f64) -> Self {
+        Self { burst, ..self }
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    limit: RateLimit,
+    /// Below zero while calls that were held are still being paid for.
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl Bucket {
+    fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            tokens: limit.burst,
+            refilled: Instant::now(),
+        }
+    }
+
+    /// Takes `cost` tokens, returning how long until they're there, or `Throttled` without taking
+    /// any if that's longer than `max_wait`. A cost over the burst goes ahead once the bucket is
+    /// full, leaving it in debt.
+    fn take(&mut self, cost: f64, max_wait: Duration) -> Result<Duration, Throttled> {
+        let now = Instant::now();
+        let refill = now.duration_since(self.refilled).as_secs_f64() * self.limit.per_sec;
+
+        self.tokens = (self.tokens + refill).min(self.limit.burst);
+        self.refilled = now;
+
+        let missing = cost.min(self.limit.burst) - self.tokens;
+        let wait = match missing > 0.0 {
+            true => {
+                Duration::try_from_secs_f64(missing / self.limit.per_sec).unwrap_or(Duration::MAX)
+            }
+            false => Duration::ZERO,
+        };
+
+        if wait > max_wait {
+            return Err(Throttled { retry_after: wait });
+        }
+
+        self.tokens -= cost;
+
+        Ok(wait)
+    }
+}
+
+/// The default `RateLimiter`: a token bucket each for reads and writes, refilled at their
+/// `RateLimit`. A call the bucket can't pay for yet waits for it if that's no longer than
+/// `max_wait`, and is turned away with `Throttled` otherwise. With the default `max_wait` of zero
+/// nothing ever waits.
+#[derive(Debug)]
+pub struct TokenBucket {
+    reads: Option<Mutex<Bucket>>,
+    writes: Option<Mutex<Bucket>>,
+    max_wait: Duration,
+}
+
+impl TokenBucket {
+    /// Limits reads and writes as given, leaving those without a limit alone.
+    pub fn new(reads: Option<RateLimit>, writes: Option<RateLimit>) -> Self {
+        Self {
+            reads: reads.map(|limit| Mutex::new(Bucket::new(limit))),
+            writes: writes.map(|limit| Mutex::new(Bucket::new(limit))),
+            max_wait: Duration::ZERO,
+        }
+    }
+
+    pub fn with_max_wait(self, max_wait: Duration) -> Self {
+        Self { max_wait, ..self }
+    }
+
+    fn _take(&self, bucket: Option<&Mutex<Bucket>>, cost: u64) -> Result<Duration, Throttled> {
+        let Some(bucket) = bucket else {
+            return Ok(Duration::ZERO);
+        };
+
+        // the lock isn't held while waiting, the tokens are already taken
+        let wait = bucket
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take(cost as f64, self.max_wait)?;
+
+        if !wait.is_zero() {
+            thread::sleep(wait);
+        }
+
+        Ok(wait)
+    }
+}
+
+impl RateLimiter for TokenBucket {
+    fn before_read(&self, cost: u64) -> Result<Duration, Throttled> {
+        self._take(self.reads.as_ref(), cost)
+    }
+
+    fn before_write(&self, cost: u64) -> Result<Duration, Throttled> {
+        self._take(self.writes.as_ref(), cost)
+    }
+}
+
+/// Asks every limiter in turn, as for a table limited on its own and along with the rest of its
+/// database. The first to turn a call away stops it, though the limiters before it have counted
+/// it already.
+#[derive(Debug, Clone, Default)]
+pub struct LimiterChain(pub Vec<Arc<dyn RateLimiter>>);
+
+impl RateLimiter for LimiterChain {
+    fn before_read(&self, cost: u64) -> Result<Duration, Throttled> {
+        self.0.iter().try_fold(Duration::ZERO, |held, limiter| {
+            Ok(held + limiter.before_read(cost)?)
+        })
+    }
+
+    fn before_write(&self, cost: u64) -> Result<Duration, Throttled> {
+        self.0.iter().try_fold(Duration::ZERO, |held, limiter| {
+            Ok(held + limiter.before_write(cost)?)
+        })
+    }
+}
+
+impl Table {
+    /// Has the reads and writes of the table asked `limiter` first, see `RateLimiter`, or nothing
+    /// with `None`. Shared by every handle of the table.
+    pub fn set_rate_limiter(&self, limiter: Option<Arc<dyn RateLimiter>>) {
+        *self.rate_limiter.write() = limiter;
+    }
+
+    pub fn rate_limiter(&self) -> Option<Arc<dyn RateLimiter>> {
+        self.rate_limiter.read().clone()
+    }
+
+    /// How many reads and writes the table's rate limiter held or turned away.
+    pub fn throttle_events(&self) -> u64 {
+        self.throttle_events.load(Ordering::Acquire)
+    }
+
+    /// Asks the rate limiter before reading about `cost` rows. Scans of the table ask on their
+    /// own; this is for callers reading it through `scan_since` or `scan_row_blocks`.
+    pub fn throttle_read(&self, cost: usize) -> Result<(), TableError> {
+        self._throttle(cost, |limiter, cost| limiter.before_read(cost))
+    }
+
+    /// Asks the rate limiter before writing `cost` rows.
+    pub fn throttle_write(&self, cost: usize) -> Result<(), TableError> {
+        self._throttle(cost, |limiter, cost| limiter.before_write(cost))
+    }
+
+    fn _throttle(
+        &self,
+        cost: usize,
+        hook: impl FnOnce(&dyn RateLimiter, u64) -> Result<Duration, Throttled>,
+    ) -> Result<(), TableError> {
+        let Some(limiter) = self.rate_limiter() else {
+            return Ok(());
+        };
+
+        let res = hook(limiter.as_ref(), cost as u64);
+
+        if !matches!(res, Ok(held) if held.is_zero()) {
+            self.throttle_events.fetch_add(1, Ordering::AcqRel);
+        }
+
+        res.map(|_| ()).map_err(Into::into)
+    }
+}
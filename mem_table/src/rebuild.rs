@@ -0,0 +1,364 @@
+//! Rebuilding a column store with every value transformed, without holding off writes for the
+//! whole rebuild. `Table::rebuild_column_online` copies the values of a snapshot of the rows into
+//! a new store, catches up on the rows inserted meanwhile with `scan_since`, then takes the write
+//! gate only long enough to copy the rows written since, point every row at the new store and
+//! swap it in. The old store goes away once the last handle to it is dropped.
+//!
+//! Reads of a row's values hold the swap gate shared, so none sees a row pointing into the new
+//! store before it's swapped in.
+//!
+//! The new store of a persisted column is built in memory. At the swap it's written out next to
+//! the column's file before any row points at it, then moved over the file and opened from there.
+
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use dbexp::{
+    indices::{CellIdx, ColumnIndices},
+    records::RecordHandle,
+    slot::SlotHandle,
+    store::{Store, StoreError},
+    values::DataValue,
+};
+use indexmap::IndexMap;
+use primitives::O64;
+use serde::Serialize;
+
+use crate::{files::resolve, Table, TableError};
+
+/// How many rows a catch-up pass may copy for the rebuild to stop catching up and swap.
+const CATCH_UP_ROWS: usize = 1000;
+
+/// How many catch-up passes a rebuild makes at most before swapping anyway, for a table written
+/// to faster than it's copied.
+const CATCH_UP_PASSES: usize = 8;
+
+/// What `Table::rebuild_column_online` copied, and when.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct RebuildReport {
+    /// Rows copied from the snapshot the rebuild started from.
+    pub snapshot_rows: usize,
+    /// Rows inserted during the rebuild, copied while catching up.
+    pub caught_up_rows: usize,
+    pub catch_up_passes: usize,
+    /// Rows written since the last catch-up pass, copied while writes were held off for the swap.
+    pub swap_rows: usize,
+}
+
+/// A row as it was when its value was copied to the new store.
+struct Copied {
+    handle: RecordHandle,
+    cell: Option<CellIdx>,
+    gen: O64,
+    /// Where the transformed value went, `None` for a row without a value in the column.
+    new: Option<SlotHandle<DataValue>>,
+}
+
+impl Table {
+    /// Replaces the store of `column` with one holding `transform` of every value, as for a
+    /// change of encoding. Writes go ahead while the new store is built and only wait for the
+    /// swap at the end, which copies the rows written since the last catch-up pass and points
+    /// every row at the new store. Reads wait for the swap as well. Listeners aren't told, and
+    /// row generations stay as they were.
+    ///
+    /// Transformed values have to pass `validate_row`, and the rebuild stops at the first that
+    /// doesn't or the first `transform` fails, leaving the column as it was. Primary key columns
+    /// can't be rebuilt, and neither can columns of `SingleFile` tables, whose stores share a
+    /// file that can't be swapped one store at a time.
+    pub fn rebuild_column_online(
+        &self,
+        column: usize,
+        transform: impl Fn(&DataValue) -> Result<DataValue>,
+    ) -> Result<RebuildReport, TableError> {
+        self._ensure_open()?;
+
+        if column >= self.config.columns.len() {
+            return Err(TableError::not_found(format!("column {}", column)));
+        }
+
+        if let Some(unavailable) = self._unavailable(column) {
+            return Err(unavailable.into());
+        }
+
+        if self.config.primary_key.columns().any(|key| key == column) {
+            return Err(TableError::invalid(format!(
+                "column {} is part of the primary key",
+                column
+            )));
+        }
+
+        if self.table_file.is_some() {
+            return Err(TableError::invalid(
+                "columns of single-file tables can't be rebuilt online",
+            ));
+        }
+
+        let _rebuild = self.rebuilds.lock().unwrap_or_else(|e| e.into_inner());
+
+        // the old store has to be open for the swap to replace it
+        self.get_column_store(column)?;
+
+        let persisted = !self.config.persistance.is_empty();
+        let store = match persisted {
+            true => self._scratch_store(column)?,
+            false => unsafe { self._open_column_store(column)? },
+        };
+        let mut report = RebuildReport::default();
+        let mut copied = IndexMap::new();
+        let mut seq = self.current_seq();
+
+        for (row, handle) in self.records.scan_since(0) {
+            if row > seq {
+                break;
+            }
+
+            if let Some(copy) = self._copy_value(column, handle, &store, &transform)? {
+                copied.insert(row, copy);
+                report.snapshot_rows += 1;
+            }
+        }
+
+        while report.catch_up_passes < CATCH_UP_PASSES {
+            let since = seq;
+            let mut rows = 0;
+
+            seq = self.current_seq();
+            report.catch_up_passes += 1;
+
+            for (row, handle) in self.records.scan_since(since) {
+                if copied.contains_key(&row) {
+                    continue;
+                }
+
+                if let Some(copy) = self._copy_value(column, handle, &store, &transform)? {
+                    copied.insert(row, copy);
+                    rows += 1;
+                }
+            }
+
+            report.caught_up_rows += rows;
+
+            if rows <= CATCH_UP_ROWS {
+                break;
+            }
+        }
+
+        let _writes = self.write_gate.write();
+        let mut live = Vec::new();
+
+        self.records
+            .foreach_live(|handle, indices| live.push((indices.seq(), handle)));
+
+        // every row written since it was copied, or never seen, is copied again before any row is
+        // pointed at the new store, so a failed copy leaves the column as it was
+        let mut seen = HashSet::with_capacity(live.len());
+
+        for (row, handle) in live {
+            seen.insert(row);
+
+            let current = handle.read_with(|data| {
+                Ok(data
+                    .data()
+                    .map(|indices| (indices.get(self._cell(column)), indices.gen())))
+            })?;
+
+            let stale = match (copied.get(&row), current) {
+                (_, None) => continue,
+                (Some(copy), Some((cell, gen))) => copy.cell != cell || copy.gen != gen,
+                (None, Some(_)) => true,
+            };
+
+            if !stale {
+                continue;
+            }
+
+            if let Some(copy) = self._copy_value(column, handle, &store, &transform)? {
+                if let Some(old) = copied.insert(row, copy).and_then(|old| old.new) {
+                    store.remove(old);
+                }
+
+                report.swap_rows += 1;
+            }
+        }
+
+        copied.retain(|row, copy| match seen.contains(row) {
+            true => true,
+            false => {
+                if let Some(new) = copy.new.take() {
+                    store.remove(new);
+                }
+
+                false
+            }
+        });
+
+        let _reads = self.swap_gate.write();
+
+        let image = match persisted {
+            true => Some(self._write_image(column, &store)?),
+            false => None,
+        };
+
+        let pointed = self._point_at(column, copied.values(), |copy| {
+            copy.new.clone().map(CellIdx::from)
+        });
+
+        let swapped = match (pointed, image) {
+            (Ok(()), Some(image)) => self._swap_file(column, &image),
+            (Ok(()), None) => Ok(store),
+            (Err(err), image) => {
+                if let Some(image) = image {
+                    let _ = fs::remove_file(image);
+                }
+
+                Err(err)
+            }
+        };
+
+        let store = match swapped {
+            Ok(store) => store,
+            Err(err) => {
+                // the rows pointed at the new store already go back to the old one
+                let _ = self._point_at(column, copied.values(), |copy| copy.cell);
+                return Err(err.into());
+            }
+        };
+
+        self.columns.write().insert(column, store);
+        self._build_blooms()?;
+        self._build_bitmaps()?;
+        self._build_uniques()?;
+        self._rebuild_sketch(column)?;
+
+        Ok(report)
+    }
+
+    /// A memory-only store laid out like the one of `column`, to rebuild a persisted column in.
+    fn _scratch_store(&self, column: usize) -> Result<Store<DataValue>> {
+        let mut config = unsafe { self.config.columns.get_unchecked(column) }
+            .into_store_config(&self.config, column)?;
+        config.persistance = Default::default();
+
+        Store::new(Some(self.id), Some(config))
+    }
+
+    /// Where the store of a persisted `column` is kept.
+    fn _column_path(&self, column: usize) -> Result<PathBuf> {
+        let config = unsafe { self.config.columns.get_unchecked(column) }
+            .into_store_config(&self.config, column)?;
+
+        let root = (!self.root.is_empty()).then_some(self.root.as_path());
+
+        resolve(&config, root)
+    }
+
+    /// Writes the rebuilt store of a persisted `column` next to the column's file, returning
+    /// where.
+    fn _write_image(&self, column: usize, store: &Store<DataValue>) -> Result<PathBuf> {
+        let image = self._column_path(column)?.with_extension("rebuild");
+
+        // left behind by a rebuild that didn't get to its swap
+        if image.exists() {
+            fs::remove_file(&image)?;
+        }
+
+        store.write_image(&image, self.config.scan)?;
+
+        Ok(image)
+    }
+
+    /// Moves the image of a rebuilt column over the column's file and opens it from there. The old
+    /// store keeps the file it had mapped until the last handle to it is dropped.
+    fn _swap_file(&self, column: usize, image: &Path) -> Result<Store<DataValue>> {
+        if let Err(err) = fs::rename(image, self._column_path(column)?) {
+            let _ = fs::remove_file(image);
+            return Err(err.into());
+        }
+
+        unsafe { self._open_column_store(column) }
+    }
+
+    /// Copies `transform` of a row's value in `column` to `store`. `None` for a row removed
+    /// before it was read.
+    fn _copy_value(
+        &self,
+        column: usize,
+        handle: RecordHandle,
+        store: &Store<DataValue>,
+        transform: &impl Fn(&DataValue) -> Result<DataValue>,
+    ) -> Result<Option<Copied>> {
+        let read = handle.read_with(|data| {
+            let Some(indices) = data.data() else {
+                return Ok(None);
+            };
+
+            let cell = indices.get(self._cell(column));
+            let value = cell.map(|cell| self._read_cell(column, cell));
+
+            Ok(Some((cell, indices.gen(), value)))
+        });
+
+        // a handle to a row removed since it was listed may fail to read at all
+        let Ok(Some((cell, gen, value))) = read else {
+            return Ok(None);
+        };
+
+        let new = match value.transpose()? {
+            Some(value) => {
+                let value = transform(&value)?;
+                let mut row = vec![None; column + 1];
+
+                row[column] = Some(value);
+                self.validate_row(&row)?;
+
+                let value = row.pop().flatten().expect("the value is last");
+                let record = self.records.record_id(&handle);
+
+                Some(
+                    store
+                        .insert_one(Some(record), value)
+                        .map_err(StoreError::thread_safe)?,
+                )
+            }
+            None => None,
+        };
+
+        Ok(Some(Copied {
+            handle,
+            cell,
+            gen,
+            new,
+        }))
+    }
+
+    /// Points the rows of `copied` at the cells `cell` gives them in `column`.
+    fn _point_at<'a>(
+        &self,
+        column: usize,
+        copied: impl Iterator<Item = &'a Copied>,
+        cell: impl Fn(&Copied) -> Option<CellIdx>,
+    ) -> Result<()> {
+        for copy in copied {
+            let cell = cell(copy);
+
+            copy.handle.write_with(|mut data| {
+                data.update(|indices: &mut ColumnIndices| {
+                    match cell {
+                        Some(cell) => indices.replace(self._cell(column), cell)?,
+                        None => {
+                            indices.take(self._cell(column));
+                        }
+                    }
+
+                    Ok(())
+                })
+            })?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,253 @@
+//! Opening a table some column store files are missing from, as after a partial restore. Column
+//! stores are only created once a column is written to, so a missing file is only missing if a
+//! row has a value in its column. Opened with `OpenOptions::allow_missing_columns`, such a table
+//! keeps the rest of its data readable: the columns whose files are gone are unavailable, reading
+//! them fails with `ColumnUnavailable` or reads empty, and writing to them is rejected, until
+//! `Table::rebuild_column` gives them an empty store.
+
+use std::path::PathBuf;
+
+use anyhow::Result;
+use dbexp::{
+    indices::{CellIdx, ColumnIndices},
+    object_ids::TableId,
+    records::RecordHandle,
+    store::Store,
+    values::DataValue,
+};
+use indexmap::IndexMap;
+use primitives::{InternalPath, InternalString};
+
+use crate::{files, Table, TableConfig, TableError};
+
+/// How `Table::open_with_options` opens a table.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OpenOptions {
+    /// The database root relative persistance paths are resolved against, as for
+    /// `Table::new_in`.
+    pub root: Option<PathBuf>,
+    /// Opens the table even if rows have values in columns whose store files are missing, with
+    /// those columns unavailable. Otherwise opening it fails with `ColumnUnavailable`.
+    pub allow_missing_columns: bool,
+    /// Reads the values of unavailable columns as empty, rather than failing the read with
+    /// `ColumnUnavailable`.
+    pub unavailable_as_empty: bool,
+}
+
+/// What `Table::open_with_options` found while opening a table.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OpenReport {
+    /// The columns opened unavailable, with the store files that were missing.
+    pub missing_columns: Vec<(usize, PathBuf)>,
+}
+
+impl OpenReport {
+    /// Whether some columns were opened unavailable.
+    pub fn is_degraded(&self) -> bool {
+        !self.missing_columns.is_empty()
+    }
+}
+
+/// A column whose store file was missing when its table was opened, see `OpenOptions`.
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+#[error("column {column} is unavailable, its store file {} is missing", path.display())]
+pub struct ColumnUnavailable {
+    pub column: usize,
+    pub path: PathBuf,
+}
+
+impl Table {
+    /// Like `new_in`, or `new` without a root, opening the table as `options` say and reporting
+    /// the column store files it found missing.
+    pub fn open_with_options(
+        id: TableId,
+        config: TableConfig,
+        name_mapping: Option<IndexMap<InternalString, usize>>,
+        options: OpenOptions,
+    ) -> Result<(Self, OpenReport), TableError> {
+        let root = match options.root.as_ref() {
+            Some(root) => InternalPath::new(root)?,
+            None => InternalPath::default(),
+        };
+
+        Ok(Self::_new(id, config, name_mapping, root, &options)?)
+    }
+
+    /// Finds the columns rows have values in whose store files are missing, before anything
+    /// opens their stores, which would create them empty. They're made unavailable if `options`
+    /// allow it. A `SingleFile` table keeps its columns in its table file, which is there or not
+    /// as a whole.
+    pub(crate) fn _find_missing_columns(&self, options: &OpenOptions) -> Result<OpenReport> {
+        if self.table_file.is_some() || self.config.persistance.is_empty() {
+            return Ok(OpenReport::default());
+        }
+
+        let root = (!self.root.is_empty()).then(|| self.root.as_path());
+        let mut missing = IndexMap::new();
+
+        for column in 0..self.config.columns.len() {
+            let Some(config) = self.config.columns.get(column) else {
+                continue;
+            };
+            let config = config.into_store_config(&self.config, column)?;
+            let path = files::resolve(&config, root)?;
+
+            if !path.exists() {
+                missing.insert(column, path);
+            }
+        }
+
+        if missing.is_empty() {
+            return Ok(OpenReport::default());
+        }
+
+        // a column never written to has no file either
+        let mut referenced = vec![false; self.config.columns.len()];
+
+        self.records.foreach_live(|_, indices| {
+            for column in missing.keys() {
+                referenced[*column] |= indices.get(self._cell(*column)).is_some();
+            }
+        });
+
+        missing.retain(|column, _| referenced[*column]);
+
+        if let Some((column, path)) = missing.iter().next() {
+            if !options.allow_missing_columns {
+                return Err(ColumnUnavailable {
+                    column: *column,
+                    path: path.clone(),
+                }
+                .into());
+            }
+        }
+
+        *self.unavailable.write() = missing.clone();
+
+        Ok(OpenReport {
+            missing_columns: missing.into_iter().collect(),
+        })
+    }
+
+    /// The columns opened unavailable and not rebuilt since, see `OpenOptions`.
+    pub fn unavailable_columns(&self) -> Vec<usize> {
+        self.unavailable.read().keys().copied().collect()
+    }
+
+    pub(crate) fn _is_unavailable(&self, column: usize) -> bool {
+        self.unavailable.read().contains_key(&column)
+    }
+
+    /// The error for reading or writing an unavailable column, `None` for any other.
+    pub(crate) fn _unavailable(&self, column: usize) -> Option<ColumnUnavailable> {
+        self.unavailable
+            .read()
+            .get(&column)
+            .map(|path| ColumnUnavailable {
+                column,
+                path: path.clone(),
+            })
+    }
+
+    /// Reads the value a record's cell points at, or nothing for an unavailable column if the
+    /// table was opened to read those as empty.
+    pub(crate) fn _read_value(&self, column: usize, cell: CellIdx) -> Result<Option<DataValue>> {
+        match self._unavailable(column) {
+            Some(_) if self.unavailable_as_empty => Ok(None),
+            Some(unavailable) => Err(unavailable.into()),
+            None => Ok(Some(self._read_cell(column, cell)?)),
+        }
+    }
+
+    /// The stores a row of `values` is written to, by column, without the unavailable columns
+    /// it has no values for. Rows with values for those are turned away by `validate_row`.
+    pub(crate) fn _write_stores(
+        &self,
+        values: &[Option<DataValue>],
+    ) -> Result<Vec<Option<Store<DataValue>>>> {
+        if self.unavailable.read().is_empty() {
+            let stores = self.get_column_store_range(..values.len())?;
+            return Ok(stores.into_iter().map(Some).collect());
+        }
+
+        (0..values.len())
+            .map(|column| match self._is_unavailable(column) {
+                true => Ok(None),
+                false => Ok(Some(self.get_column_store(column)?)),
+            })
+            .collect()
+    }
+
+    /// Fails an update of a row that has a value in an unavailable column or would be given one,
+    /// since replacing the row's values takes the store of every column it touches.
+    pub(crate) fn _check_update(
+        &self,
+        handle: &RecordHandle,
+        values: &[Option<DataValue>],
+    ) -> Result<()> {
+        if self.unavailable.read().is_empty() {
+            return Ok(());
+        }
+
+        let current = handle.read_with(|data| Ok(data.data().copied()))?;
+
+        for column in self.unavailable_columns() {
+            let written = values.get(column).is_some_and(Option::is_some);
+            let held = current.is_some_and(|indices| indices.get(self._cell(column)).is_some());
+
+            if written || held {
+                return Err(self
+                    ._unavailable(column)
+                    .expect("column is unavailable")
+                    .into());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Gives an unavailable column an empty store so it can be written to again. The values rows
+    /// had in it are gone, and are taken out of them, so every row reads empty in the column until
+    /// it's written. A column that isn't unavailable is left alone.
+    pub fn rebuild_column(&self, column: usize) -> Result<(), TableError> {
+        self._ensure_open()?;
+
+        if column >= self.config.columns.len() {
+            return Err(TableError::not_found(format!("column {}", column)));
+        }
+
+        if !self._is_unavailable(column) {
+            return Err(TableError::invalid(format!(
+                "column {} isn't unavailable",
+                column
+            )));
+        }
+
+        let _writes = self.write_gate.write();
+        let mut rows = Vec::new();
+
+        self.records.foreach_live(|handle, indices| {
+            if indices.get(self._cell(column)).is_some() {
+                rows.push(handle);
+            }
+        });
+
+        for handle in rows {
+            handle.write_with(|mut data| {
+                data.update(|columns: &mut ColumnIndices| {
+                    columns.take(self._cell(column));
+                    Ok(())
+                })
+            })?;
+        }
+
+        self.unavailable.write().shift_remove(&column);
+        self.get_column_store(column)?;
+        self._build_blooms()?;
+        self._build_bitmaps()?;
+        self._build_uniques()?;
+        self._reset_sketch(column);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,342 @@
+//! Maps plain Rust values to and from table rows, for code embedding a table that would rather not
+//! build `DataValue`s by hand.
+//!
+//! ```
+//! use dbexp::object_ids::TableId;
+//! use indexmap::IndexMap;
+//! use mem_table::{DataConfig, Table, TableConfig};
+//! use primitives::{DataType, InternalString};
+//!
+//! # fn main() -> anyhow::Result<()> {
+//! let mut name_mapping = IndexMap::new();
+//! let columns = ["email", "first", "last", "phone"]
+//!     .into_iter()
+//!     .enumerate()
+//!     .map(|(idx, name)| {
+//!         name_mapping.insert(InternalString::new(name)?, idx);
+//!         Ok(DataConfig::new(DataType::Text(100)))
+//!     })
+//!     .collect::<anyhow::Result<Vec<_>>>()?;
+//!
+//! let users = Table::new(TableId::new(), TableConfig::new(&columns)?, Some(name_mapping))?;
+//!
+//! let handle = users.insert_typed((
+//!     "foobar@example.com".to_string(),
+//!     "Foo".to_string(),
+//!     Some("Bar".to_string()),
+//!     None::<String>,
+//! ))?;
+//!
+//! let (email, first, last, phone): (String, String, Option<String>, Option<String>) =
+//!     users.get_typed(&handle)?;
+//!
+//! assert_eq!(email, "foobar@example.com");
+//! assert_eq!(first, "Foo");
+//! assert_eq!(last.as_deref(), Some("Bar"));
+//! assert_eq!(phone, None);
+//!
+//! // a column that can't be left empty, or a row of the wrong length, names the column
+//! let err = users.get_typed::<(String, String, String, String)>(&handle).unwrap_err();
+//! assert!(err.to_string().contains("phone"));
+//! # Ok(())
+//! # }
+//! ```
+
+use anyhow::Result;
+use dbexp::{records::RecordHandle, values::DataValue};
+use indexmap::IndexMap;
+use primitives::{Bytes, DataType, InternalString, Number, Text, Timestamp};
+
+use crate::{Table, TableConfig, TableError};
+
+/// The column types and names of a table, borrowed from it for converting rows.
+#[derive(Debug, Clone, Copy)]
+pub struct TableSchemaRef<'a> {
+    config: &'a TableConfig,
+    names: &'a IndexMap<InternalString, usize>,
+}
+
+impl<'a> TableSchemaRef<'a> {
+    pub fn len(&self) -> usize {
+        self.config.columns.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn data_type(&self, column: usize) -> Option<DataType> {
+        Some(self.config.columns.get(column)?.data_type.into_inner())
+    }
+
+    /// The column's name, or `column {idx}` for tables opened without names.
+    pub fn column_name(&self, column: usize) -> String {
+        self.names
+            .iter()
+            .find(|(_, idx)| **idx == column)
+            .map(|(name, _)| name.as_str().to_string())
+            .unwrap_or_else(|| format!("column {}", column))
+    }
+
+    fn _check_arity(&self, arity: usize) -> Result<()> {
+        if arity > self.len() {
+            anyhow::bail!(
+                "row has {} values but the table only has {} columns",
+                arity,
+                self.len()
+            );
+        }
+
+        if arity < self.len() {
+            anyhow::bail!(
+                "row has {} values, leaving `{}` and every column after it unmapped",
+                arity,
+                self.column_name(arity)
+            );
+        }
+
+        Ok(())
+    }
+
+    fn _to_value<T: ColumnValue>(&self, column: usize, value: &T) -> Result<Option<DataValue>> {
+        let data_type = self
+            .data_type(column)
+            .ok_or_else(|| anyhow::anyhow!("no column {}", column))?;
+
+        value
+            .to_value(data_type)
+            .map_err(|e| anyhow::anyhow!("`{}`: {}", self.column_name(column), e))
+    }
+
+    fn _from_value<T: ColumnValue>(&self, column: usize, value: Option<DataValue>) -> Result<T> {
+        T::from_value(value).map_err(|e| anyhow::anyhow!("`{}`: {}", self.column_name(column), e))
+    }
+}
+
+/// A Rust type that can be stored in a single column. Conversions are strict, so an `i64` only
+/// goes into a number column and a `String` only into a text one.
+pub trait ColumnValue: Sized {
+    fn to_value(&self, data_type: DataType) -> Result<Option<DataValue>>;
+
+    fn from_value(value: Option<DataValue>) -> Result<Self>;
+}
+
+fn mismatch<T>(data_type: DataType) -> anyhow::Error {
+    anyhow::anyhow!(
+        "a {:?} column can't hold {}",
+        data_type,
+        std::any::type_name::<T>()
+    )
+}
+
+fn unexpected<T>(value: Option<DataValue>) -> anyhow::Error {
+    match value {
+        Some(value) => anyhow::anyhow!(
+            "{:?} can't be read as {}",
+            value,
+            std::any::type_name::<T>()
+        ),
+        None => anyhow::anyhow!(
+            "column is empty, read it as Option<{}>",
+            std::any::type_name::<T>()
+        ),
+    }
+}
+
+impl ColumnValue for bool {
+    fn to_value(&self, data_type: DataType) -> Result<Option<DataValue>> {
+        match data_type {
+            DataType::Bool => Ok(Some(DataValue::Bool(*self))),
+            _ => Err(mismatch::<Self>(data_type)),
+        }
+    }
+
+    fn from_value(value: Option<DataValue>) -> Result<Self> {
+        match value {
+            Some(DataValue::Bool(value)) => Ok(value),
+            other => Err(unexpected::<Self>(other)),
+        }
+    }
+}
+
+impl ColumnValue for i64 {
+    fn to_value(&self, data_type: DataType) -> Result<Option<DataValue>> {
+        match data_type {
+            DataType::Number => Ok(Some(DataValue::Number(Number::Integer(*self)))),
+            _ => Err(mismatch::<Self>(data_type)),
+        }
+    }
+
+    fn from_value(value: Option<DataValue>) -> Result<Self> {
+        match value {
+            Some(DataValue::Number(Number::Integer(value))) => Ok(value),
+            Some(DataValue::Number(Number::Unsigned(value))) => Ok(value.try_into()?),
+            other => Err(unexpected::<Self>(other)),
+        }
+    }
+}
+
+impl ColumnValue for u64 {
+    fn to_value(&self, data_type: DataType) -> Result<Option<DataValue>> {
+        match data_type {
+            DataType::Number => Ok(Some(DataValue::Number(Number::Unsigned(*self)))),
+            _ => Err(mismatch::<Self>(data_type)),
+        }
+    }
+
+    fn from_value(value: Option<DataValue>) -> Result<Self> {
+        match value {
+            Some(DataValue::Number(Number::Unsigned(value))) => Ok(value),
+            Some(DataValue::Number(Number::Integer(value))) => Ok(value.try_into()?),
+            other => Err(unexpected::<Self>(other)),
+        }
+    }
+}
+
+impl ColumnValue for f64 {
+    fn to_value(&self, data_type: DataType) -> Result<Option<DataValue>> {
+        match data_type {
+            DataType::Number => Ok(Some(DataValue::try_from(*self)?)),
+            _ => Err(mismatch::<Self>(data_type)),
+        }
+    }
+
+    fn from_value(value: Option<DataValue>) -> Result<Self> {
+        match value {
+            Some(DataValue::Number(value)) => Ok(value.into()),
+            other => Err(unexpected::<Self>(other)),
+        }
+    }
+}
+
+impl ColumnValue for String {
+    fn to_value(&self, data_type: DataType) -> Result<Option<DataValue>> {
+        match data_type {
+            DataType::Text(cap) => Ok(Some(DataValue::Text(Text::try_from_str(
+                self,
+                cap as usize,
+            )?))),
+            _ => Err(mismatch::<Self>(data_type)),
+        }
+    }
+
+    fn from_value(value: Option<DataValue>) -> Result<Self> {
+        match value {
+            Some(DataValue::Text(value)) => Ok(value.as_str().to_string()),
+            other => Err(unexpected::<Self>(other)),
+        }
+    }
+}
+
+impl ColumnValue for Vec<u8> {
+    fn to_value(&self, data_type: DataType) -> Result<Option<DataValue>> {
+        match data_type {
+            DataType::Bytes(cap) => Ok(Some(DataValue::Bytes(Bytes::try_from_slice(
+                self,
+                cap as usize,
+            )?))),
+            _ => Err(mismatch::<Self>(data_type)),
+        }
+    }
+
+    fn from_value(value: Option<DataValue>) -> Result<Self> {
+        match value {
+            Some(DataValue::Bytes(value)) => Ok(value.as_slice().to_vec()),
+            other => Err(unexpected::<Self>(other)),
+        }
+    }
+}
+
+impl ColumnValue for Timestamp {
+    fn to_value(&self, data_type: DataType) -> Result<Option<DataValue>> {
+        match data_type {
+            DataType::Timestamp => Ok(Some(DataValue::Timestamp(*self))),
+            _ => Err(mismatch::<Self>(data_type)),
+        }
+    }
+
+    fn from_value(value: Option<DataValue>) -> Result<Self> {
+        match value {
+            Some(DataValue::Timestamp(value)) => Ok(value),
+            other => Err(unexpected::<Self>(other)),
+        }
+    }
+}
+
+impl<T: ColumnValue> ColumnValue for Option<T> {
+    fn to_value(&self, data_type: DataType) -> Result<Option<DataValue>> {
+        match self {
+            Some(value) => value.to_value(data_type),
+            None => Ok(None),
+        }
+    }
+
+    fn from_value(value: Option<DataValue>) -> Result<Self> {
+        match value {
+            Some(value) => Ok(Some(T::from_value(Some(value))?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// A value that can be written as a whole row, one column per field in column order.
+pub trait ToRow {
+    fn to_row(&self, schema: &TableSchemaRef) -> Result<Vec<Option<DataValue>>>;
+}
+
+/// A value that can be read back from a whole row, one column per field in column order.
+pub trait FromRow: Sized {
+    fn from_row(row: Vec<Option<DataValue>>, schema: &TableSchemaRef) -> Result<Self>;
+}
+
+macro_rules! impl_row_for_tuple {
+    ($arity:literal => $($idx:tt $ty:ident),+) => {
+        impl<$($ty: ColumnValue),+> ToRow for ($($ty,)+) {
+            fn to_row(&self, schema: &TableSchemaRef) -> Result<Vec<Option<DataValue>>> {
+                schema._check_arity($arity)?;
+
+                Ok(vec![$(schema._to_value($idx, &self.$idx)?),+])
+            }
+        }
+
+        impl<$($ty: ColumnValue),+> FromRow for ($($ty,)+) {
+            fn from_row(row: Vec<Option<DataValue>>, schema: &TableSchemaRef) -> Result<Self> {
+                schema._check_arity($arity)?;
+
+                let mut row = row.into_iter();
+
+                Ok(($(schema._from_value::<$ty>($idx, row.next().flatten())?,)+))
+            }
+        }
+    };
+}
+
+impl_row_for_tuple!(1 => 0 A);
+impl_row_for_tuple!(2 => 0 A, 1 B);
+impl_row_for_tuple!(3 => 0 A, 1 B, 2 C);
+impl_row_for_tuple!(4 => 0 A, 1 B, 2 C, 3 D);
+impl_row_for_tuple!(5 => 0 A, 1 B, 2 C, 3 D, 4 E);
+impl_row_for_tuple!(6 => 0 A, 1 B, 2 C, 3 D, 4 E, 5 F);
+impl_row_for_tuple!(7 => 0 A, 1 B, 2 C, 3 D, 4 E, 5 F, 6 G);
+impl_row_for_tuple!(8 => 0 A, 1 B, 2 C, 3 D, 4 E, 5 F, 6 G, 7 H);
+
+impl Table {
+    pub fn schema(&self) -> TableSchemaRef<'_> {
+        TableSchemaRef {
+            config: &self.config,
+            names: &self.columns_by_name,
+        }
+    }
+
+    pub fn insert_typed<T: ToRow>(&self, row: T) -> Result<RecordHandle, TableError> {
+        let values = row.to_row(&self.schema()).map_err(TableError::Validation)?;
+
+        Ok(self.insert_one(values)?.handle)
+    }
+
+    pub fn get_typed<T: FromRow>(&self, handle: &RecordHandle) -> Result<T, TableError> {
+        let (values, _) = self.get_versioned(handle)?;
+
+        T::from_row(values, &self.schema()).map_err(TableError::Validation)
+    }
+}
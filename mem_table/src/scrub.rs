@@ -0,0 +1,72 @@
+use dbexp::store::ScrubReport;
+use serde::Serialize;
+
+use crate::{Table, TableError};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ColumnScrubReport {
+    pub column: usize,
+    pub name: Option<String>,
+    pub store: ScrubReport,
+}
+
+/// What scrubbing the record store and every column store of a table found, from `Table::scrub`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TableScrubReport {
+    pub records: ScrubReport,
+    pub columns: Vec<ColumnScrubReport>,
+}
+
+impl TableScrubReport {
+    /// Whether every block checked matched its checksum.
+    pub fn is_ok(&self) -> bool {
+        self.records.is_ok() && self.columns.iter().all(|column| column.store.is_ok())
+    }
+}
+
+impl std::fmt::Display for TableScrubReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "records: {}", self.records)?;
+
+        for column in self.columns.iter() {
+            // column stores are labeled with the column when they're opened
+            if column.store.label.is_none() {
+                match column.name.as_deref() {
+                    Some(name) => write!(f, "column {} ({}): ", column.column, name)?,
+                    None => write!(f, "column {}: ", column.column)?,
+                }
+            }
+
+            write!(f, "{}", column.store)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Table {
+    /// Reads every block of the table's stores back from their files and checks them against the
+    /// checksums flushed with them, see `Store::scrub`. Only a persisted table opened
+    /// `with_checksum` has any to check, and blocks written since the last flush are skipped.
+    /// Unavailable columns are left out.
+    pub fn scrub(&self) -> Result<TableScrubReport, TableError> {
+        let records = self.records.scrub()?;
+        let mut columns = Vec::with_capacity(self.config.columns.len());
+
+        for column in 0..self.config.columns.len() {
+            if self._is_unavailable(column) {
+                continue;
+            }
+
+            let config = unsafe { self.config.columns.get_unchecked(column) };
+
+            columns.push(ColumnScrubReport {
+                column,
+                name: config.name.map(|name| name.as_str().to_string()),
+                store: self.get_column_store(column)?.scrub()?,
+            });
+        }
+
+        Ok(TableScrubReport { records, columns })
+    }
+}
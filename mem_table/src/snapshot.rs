@@ -0,0 +1,162 @@
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use dbexp::{block::Block, indices::ColumnIndices, records::RecordHandle, values::DataValue};
+use indexmap::IndexMap;
+
+use crate::{EqScan, Table, TableError};
+
+/// A table as it was when the snapshot was taken, as far as which rows it has goes. Rows inserted
+/// since are left out, but rows updated or deleted since are read as they are now, since values
+/// aren't versioned. Nothing is copied, so a snapshot costs one sequence number.
+#[derive(Debug, Clone)]
+pub struct TableSnapshot {
+    table: Table,
+    seq: u64,
+}
+
+impl TableSnapshot {
+    /// The sequence number of the last row the snapshot sees.
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+}
+
+/// A consistent cut across several tables, taken with `snapshot_tables`. A row written to one of
+/// them before the cut is seen along with every row written to the others before it, so a report
+/// reading them one after the other can't see half of a write spanning them.
+#[derive(Debug, Clone, Default)]
+pub struct ReadSnapshot {
+    tables: IndexMap<String, TableSnapshot>,
+}
+
+impl ReadSnapshot {
+    pub fn get(&self, name: &str) -> Option<&TableSnapshot> {
+        self.tables.get(name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &TableSnapshot)> {
+        self.tables
+            .iter()
+            .map(|(name, snapshot)| (name.as_str(), snapshot))
+    }
+
+    pub fn len(&self) -> usize {
+        self.tables.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tables.is_empty()
+    }
+}
+
+/// Snapshots every table at the same instant. Writes to all of them are held off only while
+/// their last sequence numbers are read, after the writes already under way have finished.
+pub fn snapshot_tables<'a>(tables: impl IntoIterator<Item = (&'a str, &'a Table)>) -> ReadSnapshot {
+    let tables = tables.into_iter().collect::<Vec<_>>();
+
+    // gates are taken in table order so two snapshots can't wait on each other, and once per
+    // table since the same table may be listed twice
+    let gates = tables
+        .iter()
+        .map(|(_, table)| (table.id, *table))
+        .collect::<BTreeMap<_, _>>();
+    let held = gates
+        .values()
+        .map(|table| table.write_gate.write())
+        .collect::<Vec<_>>();
+
+    let tables = tables
+        .into_iter()
+        .map(|(name, table)| {
+            let snapshot = TableSnapshot {
+                table: table.clone(),
+                seq: table.current_seq(),
+            };
+
+            (name.to_string(), snapshot)
+        })
+        .collect();
+
+    drop(held);
+
+    ReadSnapshot { tables }
+}
+
+impl Table {
+    /// Snapshots this table alone, see `snapshot_tables` for several at once.
+    pub fn snapshot(&self) -> TableSnapshot {
+        let _writes = self.write_gate.write();
+
+        TableSnapshot {
+            table: self.clone(),
+            seq: self.current_seq(),
+        }
+    }
+}
+
+/// What the query functions read rows from: a table as it is, or a snapshot of one.
+pub trait TableRead {
+    fn table(&self) -> &Table;
+
+    /// The last sequence number to read rows up to, or `None` for every row.
+    fn seq_limit(&self) -> Option<u64>;
+
+    /// Every row to read, in insertion order.
+    fn rows(&self) -> Vec<(u64, RecordHandle)> {
+        let rows = self.table().scan_since(0);
+
+        match self.seq_limit() {
+            Some(limit) => rows.take_while(|(seq, _)| *seq <= limit).collect(),
+            None => rows.collect(),
+        }
+    }
+
+    /// Like `Table::rows_in_block`, without the rows left out of a snapshot.
+    fn rows_in_block(&self, block: &Block<ColumnIndices>, rows: &mut Vec<(u64, RecordHandle)>) {
+        self.table().rows_in_block(block, rows);
+
+        if let Some(limit) = self.seq_limit() {
+            rows.retain(|(seq, _)| *seq <= limit);
+        }
+    }
+
+    /// Whether the row is one to read. Rows that are gone aren't.
+    fn is_visible(&self, handle: &RecordHandle) -> bool {
+        match self.table().seq_of(handle) {
+            Ok(seq) => self.seq_limit().is_none_or(|limit| seq <= limit),
+            Err(_) => false,
+        }
+    }
+
+    /// Like `Table::scan_eq`, without the rows left out of a snapshot.
+    fn scan_eq(&self, column: usize, value: &DataValue) -> Result<EqScan, TableError> {
+        let mut scan = self.table().scan_eq(column, value)?;
+
+        if self.seq_limit().is_some() {
+            scan.rows.retain(|handle| self.is_visible(handle));
+        }
+
+        Ok(scan)
+    }
+}
+
+impl TableRead for Table {
+    fn table(&self) -> &Table {
+        self
+    }
+
+    fn seq_limit(&self) -> Option<u64> {
+        None
+    }
+}
+
+impl TableRead for TableSnapshot {
+    fn table(&self) -> &Table {
+        &self.table
+    }
+
+    fn seq_limit(&self) -> Option<u64> {
+        Some(self.seq)
+    }
+}
@@ -0,0 +1,325 @@
+//! Sorting a column's values without holding them all in memory, for checks that need the whole
+//! column in order. `build` reads a column store and writes its values, with the records holding
+//! them, in sorted runs of `run_size` to temp files, and `merge` streams every run back in order
+//! with a k-way merge, holding one entry of each run at a time. Equal values come out next to each
+//! other, which is how `duplicates` finds them.
+//!
+//! Each entry of a run is written as the length of its value, a `u32` in little endian, then the
+//! value as JSON, then the bytes of its `ThinRecordId`. Every record in a run is from the same
+//! table, so the table is kept with the run rather than with each entry.
+
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    mem::size_of,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use anyhow::{Context, Result};
+use dbexp::{
+    block::ScanOptions,
+    object_ids::{RecordId, TableId, ThinRecordId},
+    store::Store,
+    values::DataValue,
+};
+use primitives::ThinIdx;
+
+use crate::{Table, TableError};
+
+/// How many values `Table::find_duplicates` sorts in memory. Columns with more are sorted in runs
+/// of a quarter as many.
+pub const EXTERNAL_SORT_ROWS: usize = 100_000;
+
+/// Tells apart the run files of every sort in the process.
+static NEXT_RUN: AtomicUsize = AtomicUsize::new(0);
+
+/// The entries of a run written by `build`, in order. The file is removed when the run is
+/// dropped.
+#[derive(Debug)]
+pub struct SortedRun {
+    path: PathBuf,
+    table: TableId,
+    len: usize,
+}
+
+impl SortedRun {
+    /// Sorts `entries` and writes them to a new temp file.
+    fn write(table: TableId, entries: &mut [(DataValue, RecordId)]) -> Result<Self> {
+        let n = NEXT_RUN.fetch_add(1, Ordering::Relaxed);
+        let path = std::env::temp_dir().join(format!(
+            "dbexp_sorted_run_{}_{}_{}",
+            std::process::id(),
+            table,
+            n
+        ));
+        let run = Self {
+            path,
+            table,
+            len: entries.len(),
+        };
+
+        entries.sort_unstable();
+
+        let mut file = BufWriter::new(File::create(&run.path)?);
+
+        for (value, record) in entries.iter() {
+            let json = serde_json::to_vec(value)?;
+
+            file.write_all(&(json.len() as u32).to_le_bytes())?;
+            file.write_all(&json)?;
+            file.write_all(&record.into_thin().into_array())?;
+        }
+
+        file.flush()?;
+
+        Ok(run)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl Drop for SortedRun {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Reads every value of `store` with the record holding it, and writes them out in sorted runs of
+/// `run_size`. Only `run_size` values are held at a time. Blocks are read one after the other, so
+/// values written to the store meanwhile may or may not be in the runs.
+pub fn build(store: &Store<DataValue>, run_size: usize) -> Result<Vec<SortedRun>> {
+    if run_size == 0 {
+        anyhow::bail!("run size must be at least 1");
+    }
+
+    let table = store.read().meta().table;
+    let mut runs = Vec::new();
+    let mut entries = Vec::with_capacity(run_size);
+
+    _for_each_entry(store, |value, record| {
+        entries.push((value, record));
+
+        if entries.len() == run_size {
+            runs.push(SortedRun::write(table, &mut entries)?);
+            entries.clear();
+        }
+
+        Ok(())
+    })?;
+
+    if !entries.is_empty() {
+        runs.push(SortedRun::write(table, &mut entries)?);
+    }
+
+    Ok(runs)
+}
+
+/// Calls `f` with every value of `store` that's held by a record, and the record.
+fn _for_each_entry(
+    store: &Store<DataValue>,
+    mut f: impl FnMut(DataValue, RecordId) -> Result<()>,
+) -> Result<()> {
+    let table = store.read().meta().table;
+
+    for block in store.scan_blocks(ScanOptions::default()) {
+        for handle in block.iter_live() {
+            let record = handle.read_with(|slot| Ok(slot.thin_record_id()))?;
+
+            if let (Some(record), Some(value)) = (record, store.read_value(&handle)?) {
+                f(value, RecordId::from_thin(record, table))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads the entries of a run back one at a time.
+#[derive(Debug)]
+struct RunReader {
+    file: BufReader<File>,
+    table: TableId,
+    left: usize,
+}
+
+impl RunReader {
+    fn open(run: &SortedRun) -> Result<Self> {
+        let file = File::open(&run.path)
+            .with_context(|| format!("failed to open sorted run {}", run.path.display()))?;
+
+        Ok(Self {
+            file: BufReader::new(file),
+            table: run.table,
+            left: run.len,
+        })
+    }
+
+    fn next(&mut self) -> Result<Option<(DataValue, RecordId)>> {
+        if self.left == 0 {
+            return Ok(None);
+        }
+
+        let mut len = [0; size_of::<u32>()];
+        self.file.read_exact(&mut len)?;
+
+        let mut json = vec![0; u32::from_le_bytes(len) as usize];
+        self.file.read_exact(&mut json)?;
+
+        let mut index = [0; size_of::<u64>()];
+        self.file.read_exact(&mut index)?;
+
+        self.left -= 1;
+
+        // record ids made by `RecordId::new` have no generation, which `ThinRecordId::from_array`
+        // won't take
+        let record = match ThinRecordId::from_array(index) {
+            Some(record) => record,
+            None => ThinRecordId::new(ThinIdx::try_from_array(index)?),
+        };
+
+        Ok(Some((
+            serde_json::from_slice(&json)?,
+            RecordId::from_thin(record, self.table),
+        )))
+    }
+}
+
+/// The entries of every run merged in order, see `merge`.
+#[derive(Debug)]
+pub struct MergedRuns {
+    readers: Vec<RunReader>,
+    /// The next entry of each run that has entries left, with the run it's from.
+    heap: BinaryHeap<Reverse<(DataValue, RecordId, usize)>>,
+    /// Kept so the files are there until the merge is done.
+    _runs: Vec<SortedRun>,
+}
+
+impl MergedRuns {
+    fn _refill(&mut self, run: usize) -> Result<()> {
+        if let Some((value, record)) = self.readers[run].next()? {
+            self.heap.push(Reverse((value, record, run)));
+        }
+
+        Ok(())
+    }
+}
+
+impl Iterator for MergedRuns {
+    type Item = Result<(DataValue, RecordId)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Reverse((value, record, run)) = self.heap.pop()?;
+
+        if let Err(err) = self._refill(run) {
+            // a run that can't be read can't be merged in order past this point
+            self.heap.clear();
+            return Some(Err(err));
+        }
+
+        Some(Ok((value, record)))
+    }
+}
+
+/// Merges `runs` into one stream of entries sorted by value, then record. Only the next entry of
+/// each run is held, and the run files are removed once the merge is dropped.
+pub fn merge(runs: Vec<SortedRun>) -> Result<MergedRuns> {
+    let readers = runs
+        .iter()
+        .map(RunReader::open)
+        .collect::<Result<Vec<_>>>()?;
+    let mut merged = MergedRuns {
+        heap: BinaryHeap::with_capacity(readers.len()),
+        readers,
+        _runs: runs,
+    };
+
+    for run in 0..merged.readers.len() {
+        merged._refill(run)?;
+    }
+
+    Ok(merged)
+}
+
+/// A value held by more than one record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Duplicate {
+    pub value: DataValue,
+    pub records: Vec<RecordId>,
+}
+
+/// The values that come up more than once in `entries`, which have to be sorted by value, with the
+/// records holding each. Only the entries of one value are held at a time.
+pub fn duplicates(
+    entries: impl IntoIterator<Item = Result<(DataValue, RecordId)>>,
+) -> Result<Vec<Duplicate>> {
+    let mut found = Vec::new();
+    let mut current: Option<Duplicate> = None;
+
+    for entry in entries {
+        let (value, record) = entry?;
+
+        match current.as_mut() {
+            Some(dup) if dup.value == value => dup.records.push(record),
+            _ => {
+                if let Some(dup) = current.replace(Duplicate {
+                    value,
+                    records: vec![record],
+                }) {
+                    if dup.records.len() > 1 {
+                        found.push(dup);
+                    }
+                }
+            }
+        }
+    }
+
+    found.extend(current.filter(|dup| dup.records.len() > 1));
+
+    Ok(found)
+}
+
+impl Table {
+    /// The values more than one row holds in `column`, with the records of the rows, as a unique
+    /// index on the column would find them. A column of up to `EXTERNAL_SORT_ROWS` values is
+    /// sorted in memory, and a bigger one through sorted runs on disk, so checking a column after
+    /// a bulk load doesn't hold it all at once.
+    pub fn find_duplicates(&self, column: usize) -> Result<Vec<Duplicate>, TableError> {
+        self._ensure_open()?;
+
+        if column >= self.config.columns.len() {
+            return Err(TableError::not_found(format!("column {}", column)));
+        }
+
+        let store = self.get_column_store(column)?;
+
+        if store.read().meta().item_count <= EXTERNAL_SORT_ROWS {
+            let mut entries = Vec::new();
+
+            _for_each_entry(&store, |value, record| {
+                entries.push((value, record));
+                Ok(())
+            })?;
+            entries.sort_unstable();
+
+            return Ok(duplicates(entries.into_iter().map(Ok))?);
+        }
+
+        let runs = build(&store, EXTERNAL_SORT_ROWS / 4)
+            .map_err(|e| e.context(format!("failed to sort column {}", column)))?;
+
+        Ok(duplicates(merge(runs)?)?)
+    }
+}
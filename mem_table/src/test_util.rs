@@ -0,0 +1,117 @@
+//! Fixtures for tests of tables and of the crates built on them. Built for this crate's own tests,
+//! and for other crates with the `testing` feature.
+
+use dbexp::values::DataValue;
+use indexmap::IndexMap;
+use primitives::{DataType, InternalString};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{datagen, DataConfig, Table, TableConfig};
+
+pub use dbexp::test_util::TempDir;
+
+/// A memory-only table with one column per `(name, data_type)` of `spec`, in order.
+pub fn fixture_table(spec: &[(&str, DataType)]) -> Table {
+    let columns = spec
+        .iter()
+        .map(|(_, data_type)| DataConfig::new(*data_type))
+        .collect::<Vec<_>>();
+
+    let names = spec
+        .iter()
+        .enumerate()
+        .map(|(idx, (name, _))| Ok((InternalString::new(name)?, idx)))
+        .collect::<anyhow::Result<IndexMap<_, _>>>()
+        .expect("valid column names");
+
+    let config = TableConfig::new(columns).expect("valid fixture columns");
+
+    Table::new(dbexp::object_ids::TableId::new(), config, Some(names)).expect("fixture table")
+}
+
+/// Deterministic pseudo-random rows for a schema. The same seed gives the same rows.
+#[derive(Debug, Clone)]
+pub struct RowGen {
+    rng: StdRng,
+    schema: Vec<DataType>,
+    null_chance: f64,
+}
+
+impl RowGen {
+    pub fn new(schema: impl IntoIterator<Item = DataType>, seed: u64) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(seed),
+            schema: schema.into_iter().collect(),
+            null_chance: 0.0,
+        }
+    }
+
+    pub fn for_table(table: &Table, seed: u64) -> Self {
+        let schema = table.schema();
+
+        Self::new(
+            (0..schema.len()).filter_map(|col| schema.data_type(col)),
+            seed,
+        )
+    }
+
+    /// Leaves each column empty with the given chance, between 0 and 1.
+    pub fn with_nulls(self, null_chance: f64) -> Self {
+        Self {
+            null_chance: null_chance.clamp(0.0, 1.0),
+            ..self
+        }
+    }
+
+    /// A row with at least one value, since tables turn away rows without any.
+    pub fn row(&mut self) -> Vec<Option<DataValue>> {
+        let mut row = (0..self.schema.len())
+            .map(|col| {
+                if self.rng.gen_bool(self.null_chance) {
+                    None
+                } else {
+                    Some(self.value(self.schema[col]))
+                }
+            })
+            .collect::<Vec<_>>();
+
+        if !row.is_empty() && row.iter().all(Option::is_none) {
+            let col = self.rng.gen_range(0..row.len());
+            row[col] = Some(self.value(self.schema[col]));
+        }
+
+        row
+    }
+
+    pub fn rows(&mut self, count: usize) -> Vec<Vec<Option<DataValue>>> {
+        (0..count).map(|_| self.row()).collect()
+    }
+
+    /// A value that fits a column of `data_type`, as for `datagen::Distribution::Uniform`.
+    pub fn value(&mut self, data_type: DataType) -> DataValue {
+        datagen::uniform(&mut self.rng, data_type)
+    }
+}
+
+impl Iterator for RowGen {
+    type Item = Vec<Option<DataValue>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.row())
+    }
+}
+
+/// Asserts the table holds exactly the `expected` rows, in any order.
+#[track_caller]
+pub fn assert_rows_eq(table: &Table, expected: impl IntoIterator<Item = Vec<Option<DataValue>>>) {
+    let mut actual = table
+        .scan_since(0)
+        .map(|(_, handle)| table.get_versioned(&handle).expect("row is readable").0)
+        .collect::<Vec<_>>();
+    let mut expected = expected.into_iter().collect::<Vec<_>>();
+
+    actual.sort();
+    expected.sort();
+
+    assert_eq!(actual, expected, "table rows differ from the expected rows");
+}
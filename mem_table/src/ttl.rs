@@ -0,0 +1,240 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        mpsc::{self, RecvTimeoutError},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use dbexp::{records::RecordHandle, values::DataValue};
+use primitives::Number;
+
+use crate::{meta::MetaTable, Table, TableError};
+
+/// How many windows the TTL of a table is split into. A row is taken to be inserted when its
+/// window ends, so it expires up to `ttl / TTL_WINDOWS` late, but never early.
+pub const TTL_WINDOWS: u64 = 16;
+
+/// What the windows are kept under in the table's annotations, followed by their first seq.
+const WINDOW_KEY_PREFIX: &str = "ttl:";
+
+/// The wall clock, in milliseconds. It's what survives a restart, at the cost of rows expiring
+/// early or late by however much the clock is moved.
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as u64)
+}
+
+fn window_key(seq: u64) -> String {
+    format!("{}{}", WINDOW_KEY_PREFIX, seq)
+}
+
+/// When the rows of a table with a TTL were inserted, to the window: by the sequence number of
+/// the first row of each window, the time it opened at. A window is opened by the first insert
+/// after the last one is over, before that insert gets its sequence numbers, so every row of a
+/// window was inserted before the window ends. Persisted in the table's annotations.
+#[derive(Debug, Clone)]
+pub(crate) struct Expiry {
+    ttl_ms: u64,
+    window_ms: u64,
+    windows: BTreeMap<u64, u64>,
+}
+
+impl Expiry {
+    fn load(ttl: Duration, meta: &MetaTable, current_seq: u64) -> Result<Self> {
+        let ttl_ms = ttl.as_millis() as u64;
+        let mut expiry = Self {
+            ttl_ms,
+            window_ms: (ttl_ms / TTL_WINDOWS).max(1),
+            windows: BTreeMap::new(),
+        };
+
+        for (key, value) in meta.iter()? {
+            let Some(seq) = key.strip_prefix(WINDOW_KEY_PREFIX) else {
+                continue;
+            };
+
+            let opened = match value {
+                DataValue::Number(Number::Unsigned(opened)) => opened,
+                other => anyhow::bail!("TTL window {} is corrupt: {:?}", seq, other),
+            };
+
+            expiry.windows.insert(seq.parse()?, opened);
+        }
+
+        // rows from before the table had a TTL are as old as the first time it saw them
+        let first = expiry.windows.keys().next().copied();
+
+        if current_seq > 0 && first.is_none_or(|seq| seq > 1) {
+            let now = now_ms();
+
+            meta.set(&window_key(1), DataValue::Number(Number::from(now)))?;
+            expiry.windows.insert(1, now);
+        }
+
+        Ok(expiry)
+    }
+
+    fn _expires_at(&self, opened: u64) -> u64 {
+        opened + self.window_ms + self.ttl_ms
+    }
+
+    fn is_expired(&self, seq: u64, now: u64) -> bool {
+        self.windows
+            .range(..=seq)
+            .next_back()
+            .is_some_and(|(_, &opened)| self._expires_at(opened) <= now)
+    }
+
+    /// Opens a window starting at `seq` if the last one is over. Returns when it opened if it did.
+    fn open_window(&mut self, seq: u64, now: u64) -> Option<u64> {
+        match self.windows.values().next_back() {
+            Some(&opened) if now < opened + self.window_ms => None,
+            _ => {
+                self.windows.insert(seq, now);
+                Some(now)
+            }
+        }
+    }
+}
+
+impl Table {
+    /// The windows of a table whose config has a TTL, read back from its annotations.
+    pub(crate) fn _load_expiry(&self) -> Result<Option<Arc<Mutex<Expiry>>>> {
+        let Some(ttl) = self.config.ttl else {
+            return Ok(None);
+        };
+
+        let expiry = Expiry::load(ttl, &self.meta()?, self.current_seq())?;
+
+        Ok(Some(Arc::new(Mutex::new(expiry))))
+    }
+
+    /// Opens a window for the rows about to be inserted if the last one is over. Called before
+    /// they get their sequence numbers.
+    pub(crate) fn _open_expiry_window(&self) -> Result<()> {
+        let Some(expiry) = self.expiry.as_ref() else {
+            return Ok(());
+        };
+
+        let mut expiry = expiry.lock().unwrap_or_else(|e| e.into_inner());
+        let seq = self.current_seq() + 1;
+
+        if let Some(opened) = expiry.open_window(seq, now_ms()) {
+            self.meta()?
+                .set(&window_key(seq), DataValue::Number(Number::from(opened)))?;
+        }
+
+        Ok(())
+    }
+
+    /// Whether the row inserted with `seq` has outlived the table's TTL.
+    pub(crate) fn _expired(&self, seq: u64) -> bool {
+        self.expiry.as_ref().is_some_and(|expiry| {
+            expiry
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .is_expired(seq, now_ms())
+        })
+    }
+
+    pub(crate) fn _handle_expired(&self, handle: &RecordHandle) -> bool {
+        self.expiry.is_some() && self.seq_of(handle).is_ok_and(|seq| self._expired(seq))
+    }
+
+    /// Deletes the rows that outlived the table's TTL, which reads have left out since they
+    /// expired. They go through `delete` one at a time, so writes carry on in between and the key
+    /// index, bloom filters and listeners hear of them like of any other delete. Returns how many
+    /// were deleted. A table without a TTL has nothing to expire.
+    pub fn expire_now(&self) -> Result<usize, TableError> {
+        self._ensure_open()?;
+
+        let Some(expiry) = self.expiry.as_ref() else {
+            return Ok(0);
+        };
+
+        let now = now_ms();
+        let expired = {
+            // reads check the windows while holding a record, so they aren't held while scanning
+            let expiry = expiry.lock().unwrap_or_else(|e| e.into_inner()).clone();
+
+            self.records
+                .scan_since(0)
+                .filter(|(seq, _)| expiry.is_expired(*seq, now))
+                .map(|(_, handle)| handle)
+                .collect::<Vec<_>>()
+        };
+
+        let mut deleted = 0;
+
+        for handle in expired {
+            if self.delete(handle)? {
+                deleted += 1;
+            }
+        }
+
+        // the rows of expired windows are gone, but new rows may still go in the last one
+        let mut expiry = expiry.lock().unwrap_or_else(|e| e.into_inner());
+        let last = expiry.windows.keys().next_back().copied();
+        let over = expiry
+            .windows
+            .iter()
+            .filter(|(&seq, &opened)| Some(seq) != last && expiry._expires_at(opened) <= now)
+            .map(|(&seq, _)| seq)
+            .collect::<Vec<_>>();
+
+        if !over.is_empty() {
+            let meta = self.meta()?;
+
+            for seq in over {
+                meta.remove(&window_key(seq))?;
+                expiry.windows.remove(&seq);
+            }
+        }
+
+        Ok(deleted)
+    }
+
+    /// Runs `expire_now` every `interval` on a thread of its own, until the returned sweeper is
+    /// dropped or the table closed. A sweep that fails is tried again the next time.
+    pub fn expire_every(&self, interval: Duration) -> ExpirySweeper {
+        let (stop, stopped) = mpsc::channel();
+        let table = self.clone();
+
+        let worker = std::thread::spawn(move || loop {
+            match stopped.recv_timeout(interval) {
+                Err(RecvTimeoutError::Timeout) if !table.is_closed() => {
+                    let _ = table.expire_now();
+                }
+                _ => break,
+            }
+        });
+
+        ExpirySweeper {
+            stop,
+            worker: Some(worker),
+        }
+    }
+}
+
+/// Sweeps a table's expired rows in the background, see `Table::expire_every`. Stops once
+/// dropped, waiting for a sweep under way to finish.
+#[derive(Debug)]
+pub struct ExpirySweeper {
+    stop: mpsc::Sender<()>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl Drop for ExpirySweeper {
+    fn drop(&mut self) {
+        let _ = self.stop.send(());
+
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
@@ -0,0 +1,192 @@
+//! Unique columns, declared with `DataConfig::with_unique`, whose values no two rows may share.
+//! Every unique column keeps an index of its values to the rows holding them, built from its store
+//! when the table is opened and kept up to date by every write, so a value is checked without a
+//! scan. Rows without a value in a unique column don't take part, and the value of a row that
+//! expired is free for another row to take, as its primary key is.
+
+use anyhow::Result;
+use dbexp::{object_ids::RecordId, values::DataValue};
+use indexmap::IndexMap;
+
+use crate::{Table, TableError};
+
+/// The row holding each value of a unique column.
+pub(crate) type UniqueIndex = IndexMap<DataValue, RecordId>;
+
+/// Another row already holds the value a row written has in a unique column.
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+#[error("column {column} already holds {value} in record {existing_record}")]
+pub struct UniqueViolation {
+    pub column: usize,
+    pub value: DataValue,
+    pub existing_record: RecordId,
+}
+
+/// The values of a row by column, leaving out the columns it has none in.
+pub(crate) fn present(values: &[Option<DataValue>]) -> impl Iterator<Item = (usize, &DataValue)> {
+    values
+        .iter()
+        .enumerate()
+        .filter_map(|(column, value)| value.as_ref().map(|value| (column, value)))
+}
+
+impl Table {
+    /// Whether any column of the table is unique, so writes know to take the unique indexes.
+    pub(crate) fn _has_unique(&self) -> bool {
+        (0..self.config.columns.len())
+            .any(|column| unsafe { self.config.columns.get_unchecked(column) }.unique)
+    }
+
+    /// Looks up the row holding `value` in the unique `column`.
+    pub fn find_unique(
+        &self,
+        column: usize,
+        value: &DataValue,
+    ) -> Result<Option<RecordId>, TableError> {
+        let Some(config) = self.config.columns.get(column) else {
+            return Err(TableError::not_found(format!("column {}", column)));
+        };
+
+        if !config.unique {
+            return Err(TableError::invalid(format!(
+                "column {} isn't unique",
+                column
+            )));
+        }
+
+        Ok(self
+            .uniques
+            .read()
+            .get(&column)
+            .and_then(|index| index.get(value))
+            .copied()
+            .filter(|holder| self._holds_unique(*holder)))
+    }
+
+    /// Builds the index of every unique column from the values already stored. This is also where
+    /// a column made unique on a table with existing rows gets checked against them.
+    pub(crate) fn _build_uniques(&self) -> Result<()> {
+        let mut uniques = self.uniques.write();
+        uniques.clear();
+
+        for column in 0..self.config.columns.len() {
+            let config = unsafe { self.config.columns.get_unchecked(column) };
+
+            if !config.unique || self._is_unavailable(column) {
+                continue;
+            }
+
+            let mut index = UniqueIndex::new();
+            let store = self.get_column_store(column)?;
+            store.load(..)?;
+            let inner = store.read();
+
+            for block in inner.blocks().values() {
+                for handle in block.iter_live() {
+                    let slot = handle.read_with(|slot| {
+                        Ok(slot
+                            .thin_record_id()
+                            .zip(slot.data().cloned())
+                            .map(|(thin, value)| (RecordId::from_thin(thin, self.id), value)))
+                    })?;
+
+                    let Some((record, value)) = slot else {
+                        continue;
+                    };
+
+                    match index.get(&value) {
+                        Some(holder) if self._holds_unique(*holder) => {
+                            if self._holds_unique(record) {
+                                anyhow::bail!(
+                                    "table contains more than one row with {} in unique column {}",
+                                    value,
+                                    column
+                                );
+                            }
+                        }
+                        _ => {
+                            index.insert(value, record);
+                        }
+                    }
+                }
+            }
+
+            uniques.insert(column, index);
+        }
+
+        Ok(())
+    }
+
+    /// Whether a row in a unique index still holds its value, rather than having expired.
+    fn _holds_unique(&self, record: RecordId) -> bool {
+        self.records
+            .get(record)
+            .is_some_and(|handle| !self._handle_expired(&handle))
+    }
+
+    /// The first value of `values` another row than `record` holds in a unique column.
+    pub(crate) fn _unique_violation(
+        &self,
+        uniques: &IndexMap<usize, UniqueIndex>,
+        record: Option<RecordId>,
+        values: &[Option<DataValue>],
+    ) -> Option<UniqueViolation> {
+        for (column, index) in uniques.iter() {
+            let Some(Some(value)) = values.get(*column) else {
+                continue;
+            };
+
+            match index.get(value) {
+                Some(holder) if Some(*holder) != record && self._holds_unique(*holder) => {
+                    return Some(UniqueViolation {
+                        column: *column,
+                        value: value.clone(),
+                        existing_record: *holder,
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        None
+    }
+
+    /// Gives `record` the values of `values` in the unique columns, returning them by column so
+    /// they can be released again if the write doesn't go through.
+    pub(crate) fn _unique_claim(
+        &self,
+        uniques: &mut IndexMap<usize, UniqueIndex>,
+        record: RecordId,
+        values: &[Option<DataValue>],
+    ) -> Vec<(usize, DataValue)> {
+        let mut claimed = Vec::new();
+
+        for (column, index) in uniques.iter_mut() {
+            if let Some(Some(value)) = values.get(*column) {
+                index.insert(value.clone(), record);
+                claimed.push((*column, value.clone()));
+            }
+        }
+
+        claimed
+    }
+
+    /// Takes the values of `values` out of the unique indexes, where `record` still holds them.
+    pub(crate) fn _unique_release<'a>(
+        &self,
+        uniques: &mut IndexMap<usize, UniqueIndex>,
+        record: RecordId,
+        values: impl IntoIterator<Item = (usize, &'a DataValue)>,
+    ) {
+        for (column, value) in values {
+            let Some(index) = uniques.get_mut(&column) else {
+                continue;
+            };
+
+            // an expired row's value may have been taken by a row inserted since
+            if index.get(value) == Some(&record) {
+                index.swap_remove(value);
+            }
+        }
+    }
+}
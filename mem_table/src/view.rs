@@ -0,0 +1,582 @@
+//! Tables kept up to date with an aggregate of another table, grouped by one of its columns.
+//!
+//! A `MaterializedView` listens to the changes of its source table and applies them to one row of
+//! its target table per group, holding the group value and the aggregate. Changes are applied on a
+//! worker thread, so reads of the target can lag behind the source until `flush` is called.
+
+use std::{
+    mem::size_of,
+    sync::{mpsc, Arc, Mutex},
+    thread::JoinHandle,
+};
+
+use anyhow::Result;
+use dbexp::{records::RecordHandle, values::DataValue};
+use indexmap::IndexMap;
+use primitives::{DataType, Number};
+
+use crate::{
+    group_by::ENTRY_OVERHEAD, CardinalitySketch, Change, ListenerId, Table, UpdateOutcome,
+    SKETCH_REGISTERS,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregate {
+    /// Rows in the group.
+    Count,
+    /// The sum of the non-empty values of a number column, empty while there aren't any.
+    Sum(usize),
+    /// The average of the non-empty values of a number column, empty while there aren't any.
+    Avg(usize),
+    /// How many distinct non-empty values a column of any type has. With `exact`, every value of
+    /// the group is kept with how many rows hold it. Without, the group keeps a
+    /// `CardinalitySketch` of a fixed size instead, whose estimate keeps counting the values of
+    /// rows that left the group until the view is rebuilt.
+    CountDistinct { column: usize, exact: bool },
+}
+
+impl Aggregate {
+    pub(crate) fn column(&self) -> Option<usize> {
+        match self {
+            Self::Count => None,
+            Self::Sum(column) | Self::Avg(column) => Some(*column),
+            Self::CountDistinct { column, .. } => Some(*column),
+        }
+    }
+}
+
+/// The values a group has seen, for `Aggregate::CountDistinct`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) enum Distinct {
+    #[default]
+    None,
+    Exact(IndexMap<DataValue, i64>),
+    Sketch(CardinalitySketch),
+}
+
+impl Distinct {
+    fn apply(&mut self, exact: bool, value: &DataValue, sign: i64) {
+        if let Self::None = self {
+            *self = if exact {
+                Self::Exact(IndexMap::new())
+            } else {
+                Self::Sketch(CardinalitySketch::new())
+            };
+        }
+
+        match self {
+            Self::Exact(counts) => {
+                let count = counts.entry(value.clone()).or_default();
+                *count += sign;
+
+                if *count == 0 {
+                    counts.shift_remove(value);
+                }
+            }
+            // a sketch can't take a value back out
+            Self::Sketch(sketch) if sign > 0 => sketch.insert(value),
+            Self::Sketch(_) | Self::None => {}
+        }
+    }
+
+    /// About how many bytes adding `value` would grow the values kept by.
+    fn growth(&self, exact: bool, value: &DataValue) -> usize {
+        let entry = || value.size_hint() + size_of::<i64>() + ENTRY_OVERHEAD;
+
+        match self {
+            Self::None if exact => entry(),
+            Self::None => SKETCH_REGISTERS,
+            Self::Exact(counts) if !counts.contains_key(value) => entry(),
+            Self::Exact(_) | Self::Sketch(_) => 0,
+        }
+    }
+
+    /// Adds the values `other` has seen.
+    fn merge(&mut self, other: Self) {
+        match (self, other) {
+            (_, Self::None) => {}
+            (this @ Self::None, other) => *this = other,
+            (Self::Exact(counts), Self::Exact(theirs)) => {
+                for (value, count) in theirs {
+                    *counts.entry(value).or_default() += count;
+                }
+
+                counts.retain(|_, count| *count != 0);
+            }
+            (Self::Sketch(sketch), Self::Sketch(theirs)) => sketch.merge(&theirs),
+            // the groups of one aggregate are either all exact or all sketched
+            (Self::Exact(_) | Self::Sketch(_), _) => {}
+        }
+    }
+
+    fn count(&self) -> i64 {
+        match self {
+            Self::None => 0,
+            Self::Exact(counts) => counts.len() as i64,
+            Self::Sketch(sketch) => sketch.estimate() as i64,
+        }
+    }
+}
+
+/// The running totals of a group. Integers are summed exactly, so that removing a row takes back
+/// exactly what adding it put in.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Acc {
+    pub(crate) rows: i64,
+    pub(crate) values: i64,
+    pub(crate) int_sum: i128,
+    pub(crate) float_sum: f64,
+    pub(crate) float_values: i64,
+    pub(crate) distinct: Distinct,
+}
+
+impl Acc {
+    pub(crate) fn apply(&mut self, agg: Aggregate, row: &[Option<DataValue>], sign: i64) {
+        self.rows += sign;
+
+        let Some(Some(value)) = agg.column().and_then(|col| row.get(col)) else {
+            return;
+        };
+
+        if let Aggregate::CountDistinct { exact, .. } = agg {
+            self.values += sign;
+            self.distinct.apply(exact, value, sign);
+            return;
+        }
+
+        let DataValue::Number(number) = value else {
+            return;
+        };
+
+        self.values += sign;
+
+        match *number {
+            Number::Integer(i) => self.int_sum += sign as i128 * i as i128,
+            Number::Unsigned(u) => self.int_sum += sign as i128 * u as i128,
+            other => {
+                self.float_sum += sign as f64 * f64::from(other);
+                self.float_values += sign;
+            }
+        }
+    }
+
+    /// About how many bytes applying `row` would grow the accumulator by, which is only ever
+    /// the distinct values it keeps.
+    pub(crate) fn growth(&self, agg: Aggregate, row: &[Option<DataValue>]) -> usize {
+        let Aggregate::CountDistinct { column, exact } = agg else {
+            return 0;
+        };
+
+        match row.get(column) {
+            Some(Some(value)) => self.distinct.growth(exact, value),
+            _ => 0,
+        }
+    }
+
+    /// Adds the rows applied to `other`, as if they had been applied to `self`, for putting
+    /// together the partial groups of the same rows read in parts.
+    pub(crate) fn merge(&mut self, other: Self) {
+        self.rows += other.rows;
+        self.values += other.values;
+        self.int_sum += other.int_sum;
+        self.float_sum += other.float_sum;
+        self.float_values += other.float_values;
+        self.distinct.merge(other.distinct);
+    }
+
+    pub(crate) fn result(&self, agg: Aggregate) -> Option<Number> {
+        let sum = || {
+            if self.float_values != 0 {
+                Number::from(self.int_sum as f64 + self.float_sum)
+            } else if let Ok(i) = i64::try_from(self.int_sum) {
+                Number::Integer(i)
+            } else if let Ok(u) = u64::try_from(self.int_sum) {
+                Number::Unsigned(u)
+            } else {
+                Number::from(self.int_sum as f64)
+            }
+        };
+
+        match agg {
+            Aggregate::Count => Some(Number::Integer(self.rows)),
+            Aggregate::CountDistinct { .. } => Some(Number::Integer(self.distinct.count())),
+            _ if self.values == 0 => None,
+            Aggregate::Sum(_) => Some(sum()),
+            Aggregate::Avg(_) => Some(Number::from(f64::from(sum()) / self.values as f64)),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Group {
+    acc: Acc,
+    /// The group's row of the target table, once it has one.
+    row: Option<RecordHandle>,
+}
+
+enum Msg {
+    Change(Change),
+    Flush(mpsc::Sender<()>),
+    Stop,
+}
+
+struct Shared {
+    source: Table,
+    target: Table,
+    group_col: usize,
+    agg: Aggregate,
+    groups: Mutex<IndexMap<Option<DataValue>, Group>>,
+    /// The first error the worker ran into, returned by the next `flush`.
+    error: Mutex<Option<anyhow::Error>>,
+}
+
+/// Keeps `target` holding one `[group, aggregate]` row per distinct value of the source's
+/// `group_col`, rows leaving it empty making up a group of their own. The target should only be
+/// written by the view.
+pub struct MaterializedView {
+    shared: Arc<Shared>,
+    sender: mpsc::Sender<Msg>,
+    listener: ListenerId,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl std::fmt::Debug for MaterializedView {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MaterializedView")
+            .field("group_col", &self.shared.group_col)
+            .field("agg", &self.shared.agg)
+            .field("groups", &self.shared.groups.lock().unwrap().len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl MaterializedView {
+    /// Fills `target` from the current rows of `source` and keeps it up to date from then on. The
+    /// target needs the group column's type for its first column and a number column second.
+    pub fn new(source: &Table, group_col: usize, agg: Aggregate, target: &Table) -> Result<Self> {
+        if source.id == target.id {
+            anyhow::bail!("a view can't write to its own source table");
+        }
+
+        let source_schema = source.schema();
+        let target_schema = target.schema();
+
+        let Some(group_type) = source_schema.data_type(group_col) else {
+            anyhow::bail!("no column {} to group by", group_col);
+        };
+
+        if let Some(column) = agg.column() {
+            match source_schema.data_type(column) {
+                Some(DataType::Number) => {}
+                Some(_) if matches!(agg, Aggregate::CountDistinct { .. }) => {}
+                Some(other) => {
+                    anyhow::bail!("can't aggregate column {} of type {:?}", column, other)
+                }
+                None => anyhow::bail!("no column {} to aggregate", column),
+            }
+        }
+
+        if target_schema.len() != 2
+            || target_schema.data_type(0) != Some(group_type)
+            || target_schema.data_type(1) != Some(DataType::Number)
+        {
+            anyhow::bail!(
+                "target table must have a {:?} column followed by a number column",
+                group_type
+            );
+        }
+
+        let shared = Arc::new(Shared {
+            source: source.clone(),
+            target: target.clone(),
+            group_col,
+            agg,
+            groups: Mutex::new(IndexMap::new()),
+            error: Mutex::new(None),
+        });
+
+        let (sender, receiver) = mpsc::channel();
+
+        let worker = {
+            let shared = shared.clone();
+            std::thread::spawn(move || shared.run(receiver))
+        };
+
+        // no write can land between the listener starting and the initial fill reading the source
+        let _writes = source.write_gate.write();
+
+        let listener = {
+            let sender = sender.clone();
+            source.on_change(move |change| {
+                let _ = sender.send(Msg::Change(change.clone()));
+            })
+        };
+
+        let view = Self {
+            shared,
+            sender,
+            listener,
+            worker: Some(worker),
+        };
+
+        view.shared._rebuild()?;
+        drop(_writes);
+
+        Ok(view)
+    }
+
+    /// Waits for every change made to the source so far to reach the target. Returns the error
+    /// the worker ran into while applying them, if any, after which `rebuild` starts over.
+    pub fn flush(&self) -> Result<()> {
+        let (done, wait) = mpsc::channel();
+
+        self.sender
+            .send(Msg::Flush(done))
+            .map_err(|_| anyhow::anyhow!("view worker stopped"))?;
+        wait.recv()
+            .map_err(|_| anyhow::anyhow!("view worker stopped"))?;
+
+        match self.shared.error.lock().unwrap().take() {
+            Some(error) => Err(error.context("failed to apply source changes to the view")),
+            None => Ok(()),
+        }
+    }
+
+    /// Recomputes every group from the source and rewrites the target with them, blocking writes
+    /// to the source meanwhile.
+    pub fn rebuild(&self) -> Result<()> {
+        let _writes = self.shared.source.write_gate.write();
+
+        // anything still queued is part of what's about to be recomputed
+        let _ = self.flush();
+
+        self.shared._rebuild()
+    }
+
+    /// Checks the target against groups recomputed from the source, blocking writes to the source
+    /// meanwhile. Fails on the first group that differs.
+    pub fn verify(&self) -> Result<()> {
+        let _writes = self.shared.source.write_gate.write();
+        self.flush()?;
+
+        let shared = &self.shared;
+        let expected = shared._compute()?;
+
+        let mut actual = IndexMap::new();
+
+        for (_, handle) in shared.target.scan_since(0) {
+            let (mut values, _) = shared.target.get_versioned(&handle)?;
+            let value = values.pop().flatten();
+            let group = values.pop().flatten();
+
+            if actual.insert(group.clone(), value).is_some() {
+                anyhow::bail!("target has more than one row for group {:?}", group);
+            }
+        }
+
+        // a sketch still counts the values of rows that left the group, which a recomputed one
+        // doesn't, so only its groups are checked
+        let estimated = matches!(shared.agg, Aggregate::CountDistinct { exact: false, .. });
+
+        for (group, acc) in expected.iter() {
+            let want = acc.result(shared.agg).map(DataValue::Number);
+
+            match actual.shift_remove(group) {
+                None => anyhow::bail!("target has no row for group {:?}", group),
+                Some(got) if !estimated && !same_value(&got, &want) => anyhow::bail!(
+                    "group {:?} is {:?} in the target but {:?} in the source",
+                    group,
+                    got,
+                    want
+                ),
+                Some(_) => {}
+            }
+        }
+
+        if let Some((group, _)) = actual.first() {
+            anyhow::bail!("target has a row for group {:?}, which has no rows", group);
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for MaterializedView {
+    fn drop(&mut self) {
+        self.shared.source.remove_listener(self.listener);
+        let _ = self.sender.send(Msg::Stop);
+
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Floats are summed in a different order incrementally than when recomputed, so they only have
+/// to be close.
+fn same_value(a: &Option<DataValue>, b: &Option<DataValue>) -> bool {
+    match (a, b) {
+        (Some(DataValue::Number(a)), Some(DataValue::Number(b))) => {
+            let (a, b) = (f64::from(*a), f64::from(*b));
+            a == b || (a - b).abs() <= 1e-9 * a.abs().max(b.abs())
+        }
+        (a, b) => a == b,
+    }
+}
+
+impl Shared {
+    fn run(&self, receiver: mpsc::Receiver<Msg>) {
+        for msg in receiver {
+            match msg {
+                Msg::Change(change) => {
+                    if let Err(error) = self._apply(change) {
+                        self.error.lock().unwrap().get_or_insert(error);
+                    }
+                }
+                Msg::Flush(done) => {
+                    let _ = done.send(());
+                }
+                Msg::Stop => break,
+            }
+        }
+    }
+
+    fn _group_of(&self, row: &[Option<DataValue>]) -> Option<DataValue> {
+        row.get(self.group_col).cloned().flatten()
+    }
+
+    fn _apply(&self, change: Change) -> Result<()> {
+        let mut groups = self.groups.lock().unwrap();
+
+        let touched = match change {
+            Change::Inserted { values, .. } => {
+                let group = self._group_of(&values);
+                groups
+                    .entry(group.clone())
+                    .or_default()
+                    .acc
+                    .apply(self.agg, &values, 1);
+                vec![group]
+            }
+            Change::Deleted { values, .. } => {
+                let group = self._group_of(&values);
+                groups
+                    .entry(group.clone())
+                    .or_default()
+                    .acc
+                    .apply(self.agg, &values, -1);
+                vec![group]
+            }
+            Change::Updated { old, new, .. } => {
+                let (old_group, new_group) = (self._group_of(&old), self._group_of(&new));
+                groups
+                    .entry(old_group.clone())
+                    .or_default()
+                    .acc
+                    .apply(self.agg, &old, -1);
+                groups
+                    .entry(new_group.clone())
+                    .or_default()
+                    .acc
+                    .apply(self.agg, &new, 1);
+
+                if old_group == new_group {
+                    vec![old_group]
+                } else {
+                    vec![old_group, new_group]
+                }
+            }
+            Change::Truncated { .. } => {
+                // every group is left empty, which removes its row
+                for group in groups.values_mut() {
+                    group.acc = Acc::default();
+                }
+
+                groups.keys().cloned().collect()
+            }
+        };
+
+        for group in touched {
+            self._sync(&mut groups, group)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes a group's aggregate to its target row, removing the row once the group is empty.
+    fn _sync(
+        &self,
+        groups: &mut IndexMap<Option<DataValue>, Group>,
+        key: Option<DataValue>,
+    ) -> Result<()> {
+        let Some(group) = groups.get_mut(&key) else {
+            return Ok(());
+        };
+
+        if group.acc.rows <= 0 {
+            if let Some(row) = group.row.take() {
+                self.target.delete(row)?;
+            }
+
+            groups.shift_remove(&key);
+            return Ok(());
+        }
+
+        let values = vec![key, group.acc.result(self.agg).map(DataValue::Number)];
+
+        match group.row.as_ref() {
+            Some(row) => {
+                let (_, gen) = self.target.get_versioned(row)?;
+
+                if let UpdateOutcome::Conflict(_) = self.target.update_if(row, gen, values)? {
+                    anyhow::bail!("target row was written outside the view");
+                }
+            }
+            None => group.row = Some(self.target.insert_one(values)?.handle),
+        }
+
+        Ok(())
+    }
+
+    /// Every group of the source as it is now. Callers hold the source's write gate.
+    fn _compute(&self) -> Result<IndexMap<Option<DataValue>, Acc>> {
+        let mut groups = IndexMap::<_, Acc>::new();
+
+        for (_, handle) in self.source.scan_since(0) {
+            let (values, _) = self.source.get_versioned(&handle)?;
+
+            groups
+                .entry(self._group_of(&values))
+                .or_default()
+                .apply(self.agg, &values, 1);
+        }
+
+        Ok(groups)
+    }
+
+    /// Replaces every row of the target with freshly computed groups. Callers hold the source's
+    /// write gate and have flushed the worker.
+    fn _rebuild(&self) -> Result<()> {
+        let mut groups = self.groups.lock().unwrap();
+        let rows = self
+            .target
+            .scan_since(0)
+            .map(|(_, handle)| handle)
+            .collect::<Vec<_>>();
+
+        for row in rows {
+            self.target.delete(row)?;
+        }
+
+        groups.clear();
+
+        for (key, acc) in self._compute()? {
+            groups.insert(key.clone(), Group { acc, row: None });
+            self._sync(&mut groups, key)?;
+        }
+
+        *self.error.lock().unwrap() = None;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,335 @@
+//! A write-ahead log of the rows inserted into a table, with group commit: inserts hand their
+//! records to a writer thread, which writes every record queued up to `max_batch_bytes` or
+//! `max_batch_delay` in one go, syncs the file once for all of them, and only then lets the inserts
+//! return. An acknowledged insert is as durable as if it had synced on its own, but many inserts
+//! share the cost of the sync.
+//!
+//! Each record is written as its length, a `u32` in little endian, followed by the record as JSON.
+//! A crash can leave the last group half written, which `read_wal` leaves out, since none of its
+//! inserts were acknowledged. Only inserts are logged so far.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, SyncSender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use dbexp::{records::RecordHandle, values::DataValue};
+use primitives::InternalPath;
+use serde::{Deserialize, Serialize};
+
+use crate::{unique::present, Table, TableError};
+
+/// How many commits can be waiting for the writer before inserts block on handing theirs over.
+pub const WAL_QUEUE_CAPACITY: usize = 1024;
+
+/// When the log is synced to disk, which is when inserts are acknowledged.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum WalSyncMode {
+    /// Every commit is written and synced on its own.
+    PerCommit,
+    /// Commits queued together are written and synced as a group.
+    #[default]
+    Grouped,
+    /// Commits are written in groups but never synced, leaving it to the OS. Acknowledged inserts
+    /// survive the process crashing but not the machine.
+    None,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct WalConfig {
+    /// The log file, created if it isn't there and appended to if it is.
+    pub path: InternalPath,
+    /// A group stops taking commits once it has this many bytes.
+    pub max_batch_bytes: usize,
+    /// How long a group waits for more commits after its first.
+    pub max_batch_delay: Duration,
+    pub sync_mode: WalSyncMode,
+}
+
+impl WalConfig {
+    pub fn new(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self {
+            path: InternalPath::new(path.as_ref())?,
+            max_batch_bytes: 1 << 20,
+            max_batch_delay: Duration::from_millis(2),
+            sync_mode: WalSyncMode::default(),
+        })
+    }
+
+    pub fn with_max_batch_bytes(self, max_batch_bytes: usize) -> Self {
+        Self {
+            max_batch_bytes,
+            ..self
+        }
+    }
+
+    pub fn with_max_batch_delay(self, max_batch_delay: Duration) -> Self {
+        Self {
+            max_batch_delay,
+            ..self
+        }
+    }
+
+    pub fn with_sync_mode(self, sync_mode: WalSyncMode) -> Self {
+        Self { sync_mode, ..self }
+    }
+}
+
+/// A row as it was inserted.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WalRecord {
+    pub seq: u64,
+    pub values: Vec<Option<DataValue>>,
+}
+
+/// How much the writer has written, for telling how well commits are grouped.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WalStats {
+    pub commits: usize,
+    /// How many times the log was written to, and synced unless the sync mode is `None`.
+    pub groups: usize,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    commits: AtomicUsize,
+    groups: AtomicUsize,
+}
+
+/// The records of one commit, and where the writer acknowledges it.
+struct Commit {
+    bytes: Vec<u8>,
+    ack: SyncSender<Result<(), String>>,
+}
+
+/// Writes the log from a thread of its own, see the module docs. Dropping it flushes what's queued.
+#[derive(Debug)]
+pub struct WalWriter {
+    config: WalConfig,
+    queue: Mutex<Option<SyncSender<Commit>>>,
+    thread: Mutex<Option<JoinHandle<()>>>,
+    counters: Arc<Counters>,
+}
+
+impl WalWriter {
+    pub fn open(config: WalConfig) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(config.path.as_path())
+            .with_context(|| format!("failed to open WAL {}", config.path.as_path().display()))?;
+        let (queue, commits) = mpsc::sync_channel(WAL_QUEUE_CAPACITY);
+        let counters = Arc::new(Counters::default());
+        let writer_counters = counters.clone();
+        let thread = thread::Builder::new()
+            .name("wal-writer".to_string())
+            .spawn(move || write_groups(file, config, commits, &writer_counters))?;
+
+        Ok(Self {
+            config,
+            queue: Mutex::new(Some(queue)),
+            thread: Mutex::new(Some(thread)),
+            counters,
+        })
+    }
+
+    pub fn config(&self) -> &WalConfig {
+        &self.config
+    }
+
+    pub fn stats(&self) -> WalStats {
+        WalStats {
+            commits: self.counters.commits.load(Ordering::Relaxed),
+            groups: self.counters.groups.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Logs `records` as one commit, returning once they're as durable as the sync mode makes them.
+    pub fn commit(&self, records: &[WalRecord]) -> Result<()> {
+        let mut bytes = Vec::new();
+
+        for record in records {
+            let encoded = serde_json::to_vec(record)?;
+            let len = u32::try_from(encoded.len()).context("WAL record is too large")?;
+
+            bytes.extend_from_slice(&len.to_le_bytes());
+            bytes.extend_from_slice(&encoded);
+        }
+
+        let queue = self
+            .queue
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("WAL is closed"))?;
+        let (ack, acked) = mpsc::sync_channel(1);
+
+        queue
+            .send(Commit { bytes, ack })
+            .map_err(|_| anyhow::anyhow!("WAL writer has stopped"))?;
+
+        match acked.recv() {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(error)) => anyhow::bail!("failed to write WAL: {}", error),
+            Err(_) => anyhow::bail!("WAL writer stopped before acknowledging the commit"),
+        }
+    }
+
+    /// Writes the commits still queued, the last partial group included, and stops the writer.
+    /// Commits made afterwards fail. Closing twice does nothing.
+    pub fn close(&self) -> Result<()> {
+        drop(self.queue.lock().unwrap_or_else(|e| e.into_inner()).take());
+
+        let thread = self.thread.lock().unwrap_or_else(|e| e.into_inner()).take();
+
+        if let Some(thread) = thread {
+            thread
+                .join()
+                .map_err(|_| anyhow::anyhow!("WAL writer panicked"))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for WalWriter {
+    fn drop(&mut self) {
+        if let Err(err) = self.close() {
+            eprintln!("WARNING: failed to close WAL: {:?}", err);
+        }
+    }
+}
+
+/// The writer thread, which runs until every sender of `commits` is gone.
+fn write_groups(mut file: File, config: WalConfig, commits: Receiver<Commit>, counters: &Counters) {
+    while let Ok(first) = commits.recv() {
+        let mut size = first.bytes.len();
+        let mut group = vec![first];
+
+        if config.sync_mode != WalSyncMode::PerCommit {
+            let deadline = Instant::now() + config.max_batch_delay;
+
+            while size < config.max_batch_bytes {
+                let wait = deadline.saturating_duration_since(Instant::now());
+
+                match commits.recv_timeout(wait) {
+                    Ok(commit) => {
+                        size += commit.bytes.len();
+                        group.push(commit);
+                    }
+                    Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => break,
+                }
+            }
+        }
+
+        let res = write_group(&mut file, config.sync_mode, &group).map_err(|e| e.to_string());
+
+        counters.groups.fetch_add(1, Ordering::Relaxed);
+        counters.commits.fetch_add(group.len(), Ordering::Relaxed);
+
+        for commit in group {
+            // an insert that stopped waiting doesn't need to hear back
+            let _ = commit.ack.send(res.clone());
+        }
+    }
+}
+
+fn write_group(file: &mut File, sync_mode: WalSyncMode, group: &[Commit]) -> io::Result<()> {
+    let buf = group
+        .iter()
+        .flat_map(|commit| commit.bytes.iter().copied())
+        .collect::<Vec<_>>();
+
+    file.write_all(&buf)?;
+
+    match sync_mode {
+        WalSyncMode::PerCommit | WalSyncMode::Grouped => file.sync_data(),
+        WalSyncMode::None => Ok(()),
+    }
+}
+
+/// Reads back the records of a log in the order they were committed. A record cut short at the
+/// end of the file is what a crash in the middle of a group leaves, and is left out along with
+/// anything after it.
+pub fn read_wal(path: impl AsRef<Path>) -> Result<Vec<WalRecord>> {
+    let bytes = std::fs::read(path.as_ref())?;
+    let mut records = Vec::new();
+    let mut rest = bytes.as_slice();
+
+    while rest.len() >= 4 {
+        let (len, tail) = rest.split_at(4);
+        let len = u32::from_le_bytes(len.try_into()?) as usize;
+
+        if tail.len() < len {
+            break;
+        }
+
+        let (encoded, tail) = tail.split_at(len);
+
+        match serde_json::from_slice(encoded) {
+            Ok(record) => records.push(record),
+            // a torn record has its length written but not all of its bytes
+            Err(_) if tail.is_empty() => break,
+            Err(e) => {
+                return Err(e).with_context(|| format!("WAL record {} is corrupt", records.len()))
+            }
+        }
+
+        rest = tail;
+    }
+
+    Ok(records)
+}
+
+impl Table {
+    /// Logs the inserted rows as one commit if the table has a WAL, waiting for it to be
+    /// acknowledged. A commit the log turns down takes the rows out again, keys and unique values
+    /// included, so the table never keeps a row a replay wouldn't bring back.
+    pub(crate) fn _log_inserted(
+        &self,
+        rows: Vec<(RecordHandle, WalRecord)>,
+    ) -> Result<(), TableError> {
+        let Some(wal) = self.wal.as_ref().filter(|_| !rows.is_empty()) else {
+            return Ok(());
+        };
+
+        let records: Vec<_> = rows.iter().map(|(_, record)| record.clone()).collect();
+        let Err(error) = wal.commit(&records) else {
+            return Ok(());
+        };
+
+        let mut keys = (!self.config.primary_key.is_empty()).then(|| self.keys.write());
+        let mut uniques = self._has_unique().then(|| self.uniques.write());
+
+        for (handle, WalRecord { values, .. }) in rows {
+            let record = self.records.record_id(&handle);
+
+            if let (Some(keys), Ok(key)) = (keys.as_mut(), self.config.primary_key.key_of(&values))
+            {
+                keys.remove(&key);
+            }
+
+            if let Some(uniques) = uniques.as_mut() {
+                self._unique_release(uniques, record, present(&values));
+            }
+
+            let _ = self._remove_row(handle);
+        }
+
+        Err(error.into())
+    }
+
+    /// The table's WAL, if its config asks for one.
+    pub fn wal(&self) -> Option<&WalWriter> {
+        self.wal.as_deref()
+    }
+}
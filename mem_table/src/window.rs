@@ -0,0 +1,164 @@
+//! Window functions over rows read from a table: row numbers, running totals and values from
+//! neighbouring rows, computed per partition in a given order.
+
+use std::cmp::Ordering;
+
+use anyhow::Result;
+use dbexp::values::DataValue;
+use primitives::Number;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SortOrder {
+    /// Empty values first.
+    #[default]
+    Asc,
+    /// Empty values last.
+    Desc,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WindowFunc {
+    /// The row's position in its partition, starting at 1.
+    RowNumber,
+    /// The sum of a number column up to and including the row, empty until the first value.
+    RunningSum(usize),
+    /// Rows up to and including the row.
+    RunningCount,
+    /// A column of the row `n` rows back in the partition, empty before the partition starts.
+    Lag(usize, usize),
+    /// A column of the row `n` rows ahead in the partition, empty past the partition's end.
+    Lead(usize, usize),
+}
+
+type Row = Vec<Option<DataValue>>;
+
+fn compare(
+    a: &Row,
+    b: &Row,
+    partition_by: Option<usize>,
+    order_by: &[(usize, SortOrder)],
+) -> Ordering {
+    fn value(row: &Row, col: usize) -> Option<&DataValue> {
+        row.get(col).and_then(Option::as_ref)
+    }
+
+    partition_by
+        .map(|col| (col, SortOrder::Asc))
+        .iter()
+        .chain(order_by)
+        .map(|(col, order)| {
+            // a column holds values of one type, so only NaN, which isn't a valid number, fails to
+            // compare
+            let ordering = DataValue::try_cmp_nullable(value(a, *col), value(b, *col))
+                .unwrap_or(Ordering::Equal);
+
+            match order {
+                SortOrder::Asc => ordering,
+                SortOrder::Desc => ordering.reverse(),
+            }
+        })
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+/// Adds two numbers, failing rather than wrapping or losing the result to infinity.
+fn checked_add(a: Number, b: Number) -> Result<Number> {
+    let sum = match (a, b) {
+        (Number::Integer(a), Number::Integer(b)) => a.checked_add(b).map(Number::Integer),
+        (Number::Unsigned(a), Number::Unsigned(b)) => a.checked_add(b).map(Number::Unsigned),
+        (Number::Integer(i), Number::Unsigned(u)) | (Number::Unsigned(u), Number::Integer(i)) => {
+            let sum = i as i128 + u as i128;
+
+            i64::try_from(sum)
+                .map(Number::Integer)
+                .or_else(|_| u64::try_from(sum).map(Number::Unsigned))
+                .ok()
+        }
+        (a, b) => Some(Number::from(f64::from(a) + f64::from(b))).filter(Number::is_valid),
+    };
+
+    sum.ok_or_else(|| anyhow::anyhow!("overflow adding {} and {}", a, b))
+}
+
+/// Sorts `rows` by `partition_by` and then `order_by`, and appends one value per function of
+/// `funcs` to every row. Rows already in that order are left as they are, so a sorted scan can be
+/// passed straight in. Ties keep their relative order.
+pub fn apply(
+    mut rows: Vec<Row>,
+    partition_by: Option<usize>,
+    order_by: &[(usize, SortOrder)],
+    funcs: &[WindowFunc],
+) -> Result<Vec<Row>> {
+    let cmp = |a: &Row, b: &Row| compare(a, b, partition_by, order_by);
+
+    if !rows.is_sorted_by(|a, b| cmp(a, b).is_le()) {
+        rows.sort_by(cmp);
+    }
+
+    // short rows are padded so the computed values line up in the same columns for every row
+    let width = rows.iter().map(Vec::len).max().unwrap_or(0);
+    let partition_of = |row: &Row| partition_by.and_then(|col| row.get(col).cloned().flatten());
+    let mut start = 0;
+
+    while start < rows.len() {
+        let partition = partition_of(&rows[start]);
+        let len = rows[start..]
+            .iter()
+            .take_while(|row| partition_of(row) == partition)
+            .count();
+
+        apply_partition(&mut rows[start..start + len], width, funcs)?;
+        start += len;
+    }
+
+    Ok(rows)
+}
+
+fn apply_partition(rows: &mut [Row], width: usize, funcs: &[WindowFunc]) -> Result<()> {
+    // the neighbours of a row are read before anything is appended to them
+    let mut computed = vec![Vec::with_capacity(funcs.len()); rows.len()];
+
+    for func in funcs {
+        let mut sum = None::<Number>;
+
+        for (idx, computed) in computed.iter_mut().enumerate() {
+            let at = |idx: Option<usize>, col: usize| {
+                idx.and_then(|idx| rows.get(idx))
+                    .and_then(|row| row.get(col).cloned().flatten())
+            };
+
+            let value = match *func {
+                WindowFunc::RowNumber | WindowFunc::RunningCount => {
+                    Some(DataValue::Number(Number::Integer(idx as i64 + 1)))
+                }
+                WindowFunc::RunningSum(col) => {
+                    match at(Some(idx), col) {
+                        Some(DataValue::Number(number)) => {
+                            sum = Some(match sum {
+                                Some(sum) => checked_add(sum, number)?,
+                                None => number,
+                            });
+                        }
+                        Some(other) => {
+                            anyhow::bail!("can't sum column {} of type {:?}", col, other.get_type())
+                        }
+                        None => {}
+                    }
+
+                    sum.map(DataValue::Number)
+                }
+                WindowFunc::Lag(col, n) => at(idx.checked_sub(n), col),
+                WindowFunc::Lead(col, n) => at(idx.checked_add(n), col),
+            };
+
+            computed.push(value);
+        }
+    }
+
+    for (row, computed) in rows.iter_mut().zip(computed) {
+        row.resize(width, None);
+        row.extend(computed);
+    }
+
+    Ok(())
+}
@@ -11,6 +11,10 @@ impl Bytes {
 
     #[must_use]
     pub fn new(cap: usize) -> Result<Self> {
+        if cap == 0 {
+            anyhow::bail!("Bytes buffer capacity has to be at least 1");
+        }
+
         if cap > Self::MAX_LEN {
             anyhow::bail!("Bytes buffer capacity is too large");
         }
@@ -3,6 +3,8 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     byte_encoding::{ByteEncoder, IntoBytes, ScalarFromBytes},
+    bytes::Bytes,
+    text::Text,
     Number, Timestamp, O16, O32, O64,
 };
 
@@ -147,11 +149,104 @@ impl DataType {
         Ok(count)
     }
 
+    /// Checks the capacity of a text or bytes type, which has to hold at least one byte and at
+    /// most `MAX_LEN` of them.
+    pub fn validate(self) -> Result<()> {
+        match self {
+            Self::Text(0) | Self::Bytes(0) => {
+                anyhow::bail!(
+                    "{:?} can't hold anything, its capacity has to be at least 1",
+                    self
+                )
+            }
+            Self::Text(len) if len as usize > Text::MAX_LEN => {
+                anyhow::bail!("Text({}) exceeds MAX_TEXT_LEN ({})", len, Text::MAX_LEN)
+            }
+            Self::Bytes(len) if len as usize > Bytes::MAX_LEN => {
+                anyhow::bail!("Bytes({}) exceeds MAX_BYTES_LEN ({})", len, Bytes::MAX_LEN)
+            }
+            _ => Ok(()),
+        }
+    }
+
     pub fn into_base62(self) -> String {
         let bytes = self.into_array();
         let n = u64::from_ne_bytes(bytes);
         base62::encode(n)
     }
+
+    /// What casting a value of this type to `target` does, as `DataValue::try_cast` does it,
+    /// without a value to try it on. Nothing casts to a type that doesn't `validate`.
+    pub fn can_cast_to(self, target: DataType) -> CastKind {
+        use CastKind::*;
+
+        if target.validate().is_err() {
+            return Forbidden;
+        }
+
+        if self == target {
+            return Identity;
+        }
+
+        match (self, target) {
+            (Self::O16 | Self::O32 | Self::O64 | Self::Bool, _) => Forbidden,
+            (Self::Number, Self::Bool | Self::Timestamp | Self::Text(_) | Self::Bytes(_)) => Lossy,
+            (Self::Number, _) => Forbidden,
+            (Self::Timestamp, Self::Number) => Lossless,
+            (Self::Timestamp, Self::Text(_)) => Lossy,
+            (Self::Timestamp, _) => Forbidden,
+            (Self::Text(_), Self::Number) => Lossy,
+            // bytes have to be valid UTF-8 to become text
+            (Self::Bytes(_), Self::Text(_)) => Lossy,
+            (Self::Text(cap) | Self::Bytes(cap), Self::Text(to) | Self::Bytes(to)) if cap <= to => {
+                Lossless
+            }
+            (Self::Text(_) | Self::Bytes(_), Self::Text(_) | Self::Bytes(_)) => Lossy,
+            (Self::Text(_) | Self::Bytes(_), _) => Forbidden,
+        }
+    }
+
+    /// One type of every kind, with a small and a large capacity for text and bytes, which is
+    /// enough to tell every cast apart.
+    pub fn representatives() -> [DataType; 10] {
+        [
+            Self::O16,
+            Self::O32,
+            Self::O64,
+            Self::Bool,
+            Self::Number,
+            Self::Timestamp,
+            Self::Text(16),
+            Self::Text(64),
+            Self::Bytes(16),
+            Self::Bytes(64),
+        ]
+    }
+
+    /// `can_cast_to` between every pair of `representatives`, by the type cast from.
+    pub fn cast_matrix() -> Vec<(DataType, DataType, CastKind)> {
+        let types = Self::representatives();
+
+        types
+            .iter()
+            .flat_map(|from| types.iter().map(|to| (*from, *to, from.can_cast_to(*to))))
+            .collect()
+    }
+}
+
+/// What casting a value from one type to another does, see `DataType::can_cast_to`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CastKind {
+    /// The types are the same, so every value is kept as it is.
+    Identity,
+    /// Every value can be cast, and comes back the same when cast back.
+    Lossless,
+    /// Values can be cast, but some fail, e.g. text that isn't a number or doesn't fit, and some
+    /// change on the way, like numbers cast to bool.
+    Lossy,
+    /// No value can be cast.
+    Forbidden,
 }
 
 /// A wrapper around `DataType` that represents an expected type. The inner `DataType`
@@ -215,6 +310,16 @@ impl ExpectedType {
         self == val.into()
     }
 
+    /// Whether a value of type `actual` can be stored where this type is expected. Text and bytes
+    /// fit as long as their capacity is no larger than the expected one.
+    pub fn accepts(self, actual: impl Into<ExpectedType>) -> bool {
+        match (self.0, actual.into().0) {
+            (DataType::Text(expected), DataType::Text(actual))
+            | (DataType::Bytes(expected), DataType::Bytes(actual)) => actual <= expected,
+            (expected, actual) => expected == actual,
+        }
+    }
+
     pub fn into_inner(self) -> DataType {
         self.0
     }
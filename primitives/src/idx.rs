@@ -203,10 +203,11 @@ impl Idx {
         }
     }
 
+    /// The generation id, or `Gen::INVALID` for an index made without one, as by `ThinIdx::new`.
     pub fn into_gen(self) -> Gen {
         let mut bytes = OID_INIT;
         bytes.copy_from_slice(&self.0 .0.get().to_ne_bytes()[..2]);
-        Gen::from_array(bytes).unwrap()
+        Gen::from_array(bytes).unwrap_or(Gen::INVALID)
     }
 
     pub fn into_u64(self) -> u64 {
@@ -49,7 +49,7 @@ impl Gen {
     pub const NIL: Option<Self> = None;
 
     pub fn new() -> Self {
-        Self(O16::new())
+        Self(O16::new_gen())
     }
 
     pub fn into_array(&self) -> [u8; 2] {
@@ -122,7 +122,7 @@ macro_rules! impl_ops {
         }
 
         impl std::iter::Step for $ty {
-            fn steps_between(start: &Self, end: &Self) -> Option<usize> {
+            fn steps_between(start: &Self, end: &Self) -> (usize, Option<usize>) {
                 usize::steps_between(&start.into_usize(), &end.into_usize())
             }
 
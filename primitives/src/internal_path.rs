@@ -1,5 +1,9 @@
 use std::{
-    collections::HashMap, ffi::OsStr, mem::MaybeUninit, os::unix::ffi::OsStrExt, path::Path,
+    collections::HashMap,
+    ffi::OsStr,
+    mem::MaybeUninit,
+    os::unix::ffi::OsStrExt,
+    path::{Component, Path, PathBuf},
 };
 
 use anyhow::Result;
@@ -12,12 +16,24 @@ use crate::{
 
 const MAX_LEN: usize = 4096;
 
+/// Set on paths that are resolved against a database root instead of being used as given.
+const RELATIVE: u8 = 0b1;
+
+/// The encoded length shares its word with a version and the flags above. Paths written before the
+/// version existed decode as version 0 with no flags set, which is the same as an absolute path.
+const ENCODING_VERSION: u64 = 1;
+const VERSION_SHIFT: u32 = 48;
+const FLAGS_SHIFT: u32 = 56;
+const LEN_MASK: u64 = (1 << VERSION_SHIFT) - 1;
+
+/// An interned path. The interned bytes start with the path's flags, followed by the path itself,
+/// so the handle stays a single fat pointer.
 #[derive(Clone, Copy)]
-pub struct InternalPath(&'static Path);
+pub struct InternalPath(&'static [u8]);
 
 impl Default for InternalPath {
     fn default() -> Self {
-        Self(Path::new(OsStr::new("")))
+        Self(&[0])
     }
 }
 
@@ -49,7 +65,7 @@ impl std::fmt::Debug for InternalPath {
 
 impl PartialEq for InternalPath {
     fn eq(&self, other: &Self) -> bool {
-        self.as_slice() == other.as_slice()
+        self.0 == other.0
     }
 }
 
@@ -57,7 +73,7 @@ impl Eq for InternalPath {}
 
 impl std::hash::Hash for InternalPath {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        self.as_slice().hash(state)
+        self.0.hash(state)
     }
 }
 
@@ -65,7 +81,11 @@ impl_access_bytes_for_into_bytes_type!(InternalPath);
 
 impl IntoBytes for InternalPath {
     fn encode_bytes(&self, x: &mut ByteEncoder<'_>) -> Result<()> {
-        x.encode(self.len())?;
+        let header = self.len() as u64
+            | ENCODING_VERSION << VERSION_SHIFT
+            | (self.flags() as u64) << FLAGS_SHIFT;
+
+        x.encode(header as usize)?;
         x.encode_bytes(self.as_slice())?;
         Ok(())
     }
@@ -75,8 +95,25 @@ impl FromBytes for InternalPath {
     fn decode_bytes(this: &mut Self, x: &mut ByteDecoder<'_>) -> Result<()> {
         use std::cell::RefCell;
 
-        let mut len = 0usize;
-        x.decode(&mut len)?;
+        let mut header = 0usize;
+        x.decode(&mut header)?;
+
+        let header = header as u64;
+        let version = (header >> VERSION_SHIFT) & 0xff;
+        let flags = (header >> FLAGS_SHIFT) as u8;
+        let len = (header & LEN_MASK) as usize;
+
+        if version > ENCODING_VERSION {
+            anyhow::bail!("unsupported path encoding version {}", version);
+        }
+
+        if flags & !RELATIVE != 0 {
+            anyhow::bail!("unknown path flags {:#b}", flags);
+        }
+
+        if len > MAX_LEN {
+            anyhow::bail!("encoded path is {} bytes, max is {}", len, MAX_LEN);
+        }
 
         thread_local! {
             static BUF: RefCell<Vec<u8>> = RefCell::new(Vec::with_capacity(MAX_LEN));
@@ -90,7 +127,7 @@ impl FromBytes for InternalPath {
             x.read_exact(&mut buf[..])?;
 
             let path = Path::new(OsStr::from_bytes(&buf[..len]));
-            let interned = InternalPath::new(path)?;
+            let interned = InternalPath::_intern(path, flags)?;
 
             *this = interned;
 
@@ -100,8 +137,8 @@ impl FromBytes for InternalPath {
 }
 
 impl InternalPath {
-    fn interned_store() -> &'static RwLock<HashMap<u64, &'static OsStr>> {
-        static mut INTERNED: MaybeUninit<RwLock<HashMap<u64, &'static OsStr>>> =
+    fn interned_store() -> &'static RwLock<HashMap<u64, &'static [u8]>> {
+        static mut INTERNED: MaybeUninit<RwLock<HashMap<u64, &'static [u8]>>> =
             MaybeUninit::uninit();
 
         static INIT: Once = Once::new();
@@ -114,32 +151,109 @@ impl InternalPath {
         unsafe { &*INTERNED.as_ptr() }
     }
 
+    /// A path that is used as given.
     pub fn new(p: impl AsRef<Path>) -> Result<Self> {
-        use std::hash::{DefaultHasher, Hash, Hasher};
+        Self::_intern(p.as_ref(), 0)
+    }
 
+    /// A path that is resolved against the database root when it's opened, so the whole root can
+    /// be moved without rewriting anything stored under it.
+    pub fn relative(p: impl AsRef<Path>) -> Result<Self> {
         let p = p.as_ref();
+
+        if p.has_root() {
+            anyhow::bail!("relative path must not start at the root: {:?}", p);
+        }
+
+        Self::_intern(p, RELATIVE)
+    }
+
+    fn _intern(p: &Path, flags: u8) -> Result<Self> {
+        use std::hash::{DefaultHasher, Hash, Hasher};
+
+        Self::_validate(p)?;
+
         let mut hasher = DefaultHasher::new();
 
         let store = Self::interned_store().upgradable_read();
 
+        flags.hash(&mut hasher);
         p.hash(&mut hasher);
         let id = hasher.finish();
 
         if let Some(interned) = store.get(&id) {
-            Ok(Self(Path::new(*interned)))
+            Ok(Self(interned))
         } else {
             let mut store = RwLockUpgradableReadGuard::upgrade(store);
-            let leaked = &*Box::leak(p.as_os_str().to_owned().into_boxed_os_str());
+
+            let mut bytes = Vec::with_capacity(p.as_os_str().len() + 1);
+            bytes.push(flags);
+            bytes.extend_from_slice(p.as_os_str().as_bytes());
+
+            let leaked = &*Box::leak(bytes.into_boxed_slice());
 
             store.insert(id, leaked);
             drop(store);
 
-            Ok(Self(Path::new(leaked)))
+            Ok(Self(leaked))
+        }
+    }
+
+    fn _validate(p: &Path) -> Result<()> {
+        let bytes = p.as_os_str().as_bytes();
+
+        if bytes.len() > MAX_LEN {
+            anyhow::bail!("path is {} bytes, max is {}", bytes.len(), MAX_LEN);
+        }
+
+        if bytes.contains(&0) {
+            anyhow::bail!("path contains a NUL byte: {:?}", p);
+        }
+
+        if p.components().any(|c| c == Component::ParentDir) {
+            anyhow::bail!("path must not contain `..`: {:?}", p);
+        }
+
+        Ok(())
+    }
+
+    /// Joins `p` onto this path, keeping it relative if this one is.
+    pub fn child(&self, p: impl AsRef<Path>) -> Result<Self> {
+        let p = p.as_ref();
+
+        if p.has_root() {
+            anyhow::bail!("child path must not start at the root: {:?}", p);
+        }
+
+        Self::_intern(&self.as_path().join(p), self.flags())
+    }
+
+    /// Where the path points once `root` is taken into account. Paths that aren't relative ignore
+    /// the root.
+    pub fn resolve(&self, root: impl AsRef<Path>) -> PathBuf {
+        if self.is_relative() {
+            root.as_ref().join(self.as_path())
+        } else {
+            self.as_path().to_path_buf()
         }
     }
 
+    /// Whether the path is resolved against a database root.
+    pub fn is_relative(&self) -> bool {
+        self.flags() & RELATIVE != 0
+    }
+
+    /// Whether the path is used as given, without a database root.
+    pub fn is_absolute(&self) -> bool {
+        !self.is_relative()
+    }
+
+    fn flags(&self) -> u8 {
+        self.0[0]
+    }
+
     pub fn len(&self) -> usize {
-        self.0.as_os_str().len()
+        self.0.len() - 1
     }
 
     pub fn is_empty(&self) -> bool {
@@ -147,10 +261,49 @@ impl InternalPath {
     }
 
     pub fn as_path(&self) -> &Path {
-        self.0
+        Path::new(OsStr::from_bytes(self.as_slice()))
     }
 
     pub fn as_slice(&self) -> &[u8] {
-        self.0.as_os_str().as_bytes()
+        &self.0[1..]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relative_resolve() -> Result<()> {
+        let path = InternalPath::relative("t1")?.child("records.store")?;
+
+        assert!(path.is_relative());
+        assert_eq!(path.as_path(), Path::new("t1/records.store"));
+        assert_eq!(path.resolve("/db"), Path::new("/db/t1/records.store"));
+        assert_ne!(path, InternalPath::new("t1/records.store")?);
+
+        let absolute = InternalPath::new("/var/t1")?;
+
+        assert!(absolute.is_absolute());
+        assert_eq!(absolute.resolve("/db"), Path::new("/var/t1"));
+
+        let decoded = InternalPath::from_bytes(&InternalPath::relative("a/b")?.into_vec()?)?;
+        assert_eq!(decoded, InternalPath::relative("a/b")?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_validation() -> Result<()> {
+        let err = InternalPath::relative("t1/../t2").unwrap_err();
+        assert!(err.to_string().contains(".."));
+
+        let err = InternalPath::new("/db/t\0").unwrap_err();
+        assert!(err.to_string().contains("NUL"));
+
+        assert!(InternalPath::relative("/db/t1").is_err());
+        assert!(InternalPath::relative("t1")?.child("/etc").is_err());
+
+        Ok(())
     }
 }
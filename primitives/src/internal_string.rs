@@ -39,6 +39,12 @@ impl std::fmt::Debug for InternalString {
     }
 }
 
+impl std::fmt::Display for InternalString {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.as_str().fmt(f)
+    }
+}
+
 impl PartialEq for InternalString {
     fn eq(&self, other: &Self) -> bool {
         self.as_str() == other.as_str()
@@ -9,12 +9,15 @@ use std::{
     alloc::{AllocError, Allocator, Layout},
     mem::ManuallyDrop,
     ptr::NonNull,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
 };
 
 use anyhow::Result;
 use indexmap::IndexMap;
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 
 pub mod byte_encoding;
 pub mod bytes;
@@ -30,13 +33,13 @@ pub mod timestamp;
 pub mod vector;
 
 pub use bytes::Bytes;
-pub use data::{DataType, ExpectedType};
+pub use data::{CastKind, DataType, ExpectedType};
 pub use idx::{Idx, ThinIdx};
 pub use internal_path::InternalPath;
 pub use internal_string::InternalString;
 pub use number::Number;
 pub use oid::{O16, O32, O64};
-pub use shared_object::SharedObject;
+pub use shared_object::{LockFairness, SharedObject};
 pub use text::Text;
 pub use timestamp::Timestamp;
 pub use vector::Vector;
@@ -51,7 +54,19 @@ pub use vector::Vector;
 ///
 /// The previous example is sound because the pool itself is responsible for managing
 /// the lifetime of the items, and the items should not be allowed to outlive the pool.
+///
+/// # Safety
+///
+/// `T` and `U` must be the same type up to lifetimes, or at least have the same layout with
+/// every bit pattern of `T` valid for `U`. Differing sizes are rejected at compile time.
 pub unsafe fn force_transmute<T, U>(value: T) -> U {
+    const {
+        assert!(
+            std::mem::size_of::<T>() == std::mem::size_of::<U>(),
+            "force_transmute between types of different sizes"
+        )
+    };
+
     union Transmute<T, U> {
         from: ManuallyDrop<T>,
         to: ManuallyDrop<U>,
@@ -97,12 +112,46 @@ impl From<anyhow::Error> for RecyclerError {
     }
 }
 
-/// An allocator that recycles memory blocks for a given layout.
-pub struct Recycler(StackMap);
+/// An allocator that recycles memory blocks. A block goes back on the stack of the layout it was
+/// allocated with, and is handed out again for that layout or, once that stack is empty, for any
+/// layout it's large and aligned enough for, along with its whole size. A stack holds at most as
+/// many blocks as the limit allows, past which deallocated blocks are freed.
+pub struct Recycler {
+    stacks: StackMap,
+    /// The most blocks a stack holds, `usize::MAX` for no limit.
+    limit: Arc<AtomicUsize>,
+    /// The layout each block handed out for a smaller one was allocated with, by its address, so
+    /// it's recycled and freed as what it is whatever layout it comes back with.
+    lent: Arc<Mutex<IndexMap<usize, Layout>>>,
+}
 
 impl Recycler {
     pub fn new(stack_map: IndexMap<Layout, StackEntry>) -> Self {
-        Self(Arc::new(RwLock::new(stack_map)))
+        Self {
+            stacks: Arc::new(RwLock::new(stack_map)),
+            limit: Arc::new(AtomicUsize::new(usize::MAX)),
+            lent: Arc::new(Mutex::new(IndexMap::new())),
+        }
+    }
+
+    /// Holds at most `limit` blocks on each stack, see `set_limit`.
+    pub fn with_limit(self, limit: usize) -> Self {
+        self.set_limit(Some(limit));
+        self
+    }
+
+    /// Holds at most `limit` blocks on each stack from now on, or any number with `None`. Stacks
+    /// already past it keep their blocks until `trim` is called.
+    pub fn set_limit(&self, limit: Option<usize>) {
+        self.limit
+            .store(limit.unwrap_or(usize::MAX), Ordering::Relaxed);
+    }
+
+    pub fn limit(&self) -> Option<usize> {
+        match self.limit.load(Ordering::Relaxed) {
+            usize::MAX => None,
+            limit => Some(limit),
+        }
     }
 
     pub fn access_stack<F, E>(
@@ -116,12 +165,12 @@ impl Recycler {
     {
         let stack = {
             if let Some(found) = {
-                let guard = self.0.try_read().ok_or(RecyclerError::Unavailable)?;
+                let guard = self.stacks.try_read().ok_or(RecyclerError::Unavailable)?;
                 guard.get(&layout).map(Arc::clone)
             } {
                 found
             } else {
-                let mut guard = self.0.try_write().ok_or(RecyclerError::Unavailable)?;
+                let mut guard = self.stacks.try_write().ok_or(RecyclerError::Unavailable)?;
                 let new = Arc::new(RwLock::new(Vec::new()));
                 guard.insert(layout, Arc::clone(&new));
                 new
@@ -135,21 +184,42 @@ impl Recycler {
     }
 
     pub fn clear(&self) {
-        let mut guard = self.0.write();
+        let mut guard = self.stacks.write();
         guard.clear();
     }
 
+    /// Frees the blocks of every stack past the limit, returning how many were freed.
+    pub fn trim(&self) -> usize {
+        let limit = self.limit.load(Ordering::Relaxed);
+        let stacks = self.stacks.read();
+        let mut freed = 0;
+
+        for (layout, stack) in stacks.iter() {
+            let mut stack = stack.write();
+
+            while stack.len() > limit {
+                let block = stack.pop().expect("stack is past the limit");
+                unsafe { std::alloc::dealloc(block.inner.as_ptr() as *mut u8, *layout) };
+                freed += 1;
+            }
+
+            stack.shrink_to_fit();
+        }
+
+        freed
+    }
+
     pub fn reserve<T>(&self, count: usize) -> Result<(), RecyclerError> {
         let layout = Layout::new::<T>();
 
         let stack = {
             if let Some(found) = {
-                let guard = self.0.try_read().ok_or(RecyclerError::Unavailable)?;
+                let guard = self.stacks.try_read().ok_or(RecyclerError::Unavailable)?;
                 guard.get(&layout).map(Arc::clone)
             } {
                 found
             } else {
-                let mut guard = self.0.try_write().ok_or(RecyclerError::Unavailable)?;
+                let mut guard = self.stacks.try_write().ok_or(RecyclerError::Unavailable)?;
                 let new = Arc::new(RwLock::new(Vec::new()));
                 guard.insert(layout, Arc::clone(&new));
                 new
@@ -161,17 +231,55 @@ impl Recycler {
 
         Ok(())
     }
+
+    /// Takes a block recycled for a larger layout `layout` fits in, from the smallest one that
+    /// has any, and remembers what it was allocated with.
+    fn _recycle_larger(&self, layout: Layout) -> Option<NonNull<[u8]>> {
+        let mut larger = {
+            let stacks = self.stacks.try_read()?;
+
+            stacks
+                .iter()
+                .filter(|(other, _)| {
+                    **other != layout
+                        && other.size() >= layout.size()
+                        && other.align() >= layout.align()
+                })
+                .map(|(other, stack)| (*other, Arc::clone(stack)))
+                .collect::<Vec<_>>()
+        };
+
+        larger.sort_by_key(|(other, _)| (other.size(), other.align()));
+
+        for (other, stack) in larger {
+            let Some(block) = stack.try_write().and_then(|mut stack| stack.pop()) else {
+                continue;
+            };
+
+            self.lent
+                .lock()
+                .insert(block.inner.as_ptr() as *mut u8 as usize, other);
+
+            return Some(block.inner);
+        }
+
+        None
+    }
 }
 
 impl Clone for Recycler {
     fn clone(&self) -> Self {
-        Self(Arc::clone(&self.0))
+        Self {
+            stacks: Arc::clone(&self.stacks),
+            limit: Arc::clone(&self.limit),
+            lent: Arc::clone(&self.lent),
+        }
     }
 }
 
 impl PartialEq for Recycler {
     fn eq(&self, other: &Self) -> bool {
-        Arc::ptr_eq(&self.0, &other.0)
+        Arc::ptr_eq(&self.stacks, &other.stacks)
     }
 }
 
@@ -179,7 +287,7 @@ impl Eq for Recycler {}
 
 impl std::hash::Hash for Recycler {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        let uint_ptr = Arc::as_ptr(&self.0) as usize;
+        let uint_ptr = Arc::as_ptr(&self.stacks) as usize;
         uint_ptr.hash(state)
     }
 }
@@ -192,7 +300,7 @@ impl Default for Recycler {
 
 impl std::fmt::Debug for Recycler {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let map_guard = self.0.read();
+        let map_guard = self.stacks.read();
 
         if f.alternate() {
             return write!(f, "{:#?}", map_guard);
@@ -251,37 +359,153 @@ unsafe impl Allocator for Recycler {
 
         match recycled {
             Ok(Some(ptr)) => Ok(ptr.inner),
-            Ok(None) => inner_allocate(layout),
-            Err(err) => match err {
-                RecyclerError::Unavailable => inner_allocate(layout),
-                RecyclerError::Unexpected(err) => {
-                    eprintln!("Recycler error: {:?}", err);
-                    Err(AllocError)
-                }
+            Ok(None) | Err(RecyclerError::Unavailable) => match self._recycle_larger(layout) {
+                Some(ptr) => Ok(ptr),
+                None => inner_allocate(layout),
             },
+            Err(RecyclerError::Unexpected(err)) => {
+                eprintln!("Recycler error: {:?}", err);
+                Err(AllocError)
+            }
         }
     }
 
     unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        // a block handed out for a smaller layout goes back as the one it was allocated with
+        let layout = self
+            .lent
+            .lock()
+            .swap_remove(&(ptr.as_ptr() as usize))
+            .unwrap_or(layout);
+        let limit = self.limit.load(Ordering::Relaxed);
+
         let res = self.access_stack(layout, |stack| {
             let mut guard = stack.try_write().ok_or(RecyclerError::Unavailable)?;
-
-            guard.push(UnsafeNonNull {
+            let block = UnsafeNonNull {
                 inner: NonNull::new_unchecked(std::slice::from_raw_parts_mut(
                     ptr.as_ptr(),
                     layout.size(),
                 )),
-            });
+            };
+
+            // handed back to be freed
+            if guard.len() >= limit {
+                return Ok(Some(block));
+            }
+
+            guard.push(block);
 
             Result::<_, RecyclerError>::Ok(None)
         });
 
-        if let Err(err) = res {
-            if let RecyclerError::Unexpected(err) = err {
-                eprintln!("Recycler error: {:?}", err);
+        match res {
+            Ok(None) => {}
+            Ok(Some(_)) => std::alloc::dealloc(ptr.as_ptr(), layout),
+            Err(err) => {
+                if let RecyclerError::Unexpected(err) = err {
+                    eprintln!("Recycler error: {:?}", err);
+                }
+
+                std::alloc::dealloc(ptr.as_ptr(), layout);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stack_len(recycler: &Recycler, layout: Layout) -> usize {
+        recycler
+            .stacks
+            .read()
+            .get(&layout)
+            .map_or(0, |stack| stack.read().len())
+    }
+
+    #[test]
+    fn test_recycle_larger_layout() -> Result<()> {
+        let recycler = Recycler::default();
+        let large = Layout::from_size_align(128, 16)?;
+        let small = Layout::from_size_align(96, 8)?;
+
+        let block = recycler.allocate(large)?;
+        unsafe { recycler.deallocate(block.cast(), large) };
+
+        // the small layout has no stack of its own yet, so it takes the large block, whole
+        let reused = recycler.allocate(small)?;
+        assert_eq!(reused.cast::<u8>(), block.cast::<u8>());
+        assert_eq!(reused.len(), 128);
+
+        // and it goes back on the stack it came from, whatever it's handed back as
+        unsafe { recycler.deallocate(reused.cast(), small) };
+        assert_eq!(stack_len(&recycler, large), 1);
+        assert_eq!(stack_len(&recycler, small), 0);
+
+        // too large, or aligned more than the block, isn't served from it
+        let larger = recycler.allocate(Layout::from_size_align(256, 8)?)?;
+        let aligned = recycler.allocate(Layout::from_size_align(64, 32)?)?;
+        assert_ne!(larger.cast::<u8>(), block.cast::<u8>());
+        assert_ne!(aligned.cast::<u8>(), block.cast::<u8>());
+        assert_eq!(stack_len(&recycler, large), 1);
+
+        unsafe {
+            recycler.deallocate(larger.cast(), Layout::from_size_align(256, 8)?);
+            recycler.deallocate(aligned.cast(), Layout::from_size_align(64, 32)?);
+        }
+
+        // the exact layout still gets it back
+        assert_eq!(recycler.allocate(large)?.cast::<u8>(), block.cast::<u8>());
+        unsafe { recycler.deallocate(block.cast(), large) };
+
+        recycler.set_limit(Some(0));
+        assert_eq!(recycler.trim(), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_recycler_limit() -> Result<()> {
+        let recycler = Recycler::default().with_limit(2);
+        let layout = Layout::from_size_align(64, 8)?;
+
+        for round in 0..100 {
+            let blocks = (0..(round % 7) + 1)
+                .map(|_| recycler.allocate(layout))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            for block in blocks {
+                unsafe { recycler.deallocate(block.cast(), layout) };
             }
 
-            std::alloc::dealloc(ptr.as_ptr(), layout);
+            assert!(stack_len(&recycler, layout) <= 2);
+        }
+
+        assert_eq!(stack_len(&recycler, layout), 2);
+
+        // without a limit every block is kept, until one is set and the stacks trimmed to it
+        recycler.set_limit(None);
+
+        let blocks = (0..5)
+            .map(|_| recycler.allocate(layout))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for block in blocks {
+            unsafe { recycler.deallocate(block.cast(), layout) };
         }
+
+        assert_eq!(stack_len(&recycler, layout), 5);
+
+        recycler.set_limit(Some(1));
+        assert_eq!(stack_len(&recycler, layout), 5);
+        assert_eq!(recycler.trim(), 4);
+        assert_eq!(stack_len(&recycler, layout), 1);
+        assert_eq!(recycler.limit(), Some(1));
+
+        recycler.set_limit(Some(0));
+        recycler.trim();
+
+        Ok(())
     }
 }
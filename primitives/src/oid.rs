@@ -3,6 +3,10 @@ use std::num::{NonZeroU16, NonZeroU32, NonZeroU64};
 use anyhow::Result;
 use base62::{decode, encode};
 
+pub mod source;
+
+pub use source::{gen_source, set_gen_source, FastCounter, GenSource, SecureRandom, Seeded};
+
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[repr(transparent)]
 pub struct O16(NonZeroU16);
@@ -21,6 +25,21 @@ impl O16 {
         Self(unsafe { NonZeroU16::new_unchecked(id) })
     }
 
+    /// A generation id drawn from the process wide `GenSource`.
+    pub fn new_gen() -> Self {
+        Self::new_with(gen_source())
+    }
+
+    pub fn new_with(source: &(impl GenSource + ?Sized)) -> Self {
+        let mut id = source.next_u64() as u16;
+
+        while id == u16::MIN || id == u16::MAX {
+            id = source.next_u64() as u16;
+        }
+
+        Self(unsafe { NonZeroU16::new_unchecked(id) })
+    }
+
     pub fn from_uint(id: impl Into<u16>) -> Option<Self> {
         Some(Self(NonZeroU16::new(id.into())?))
     }
@@ -100,7 +119,7 @@ impl<'de> serde::Deserialize<'de> for O16 {
 
         match decode(&s) {
             Ok(v) => {
-                if v > u16::MIN as u128 {
+                if v == 0 || v > u16::MAX as u128 {
                     Err(serde::de::Error::custom("value out of range"))
                 } else {
                     Ok(O16(unsafe { NonZeroU16::new_unchecked(v as u16) }))
@@ -129,6 +148,21 @@ impl O32 {
         Self(unsafe { NonZeroU32::new_unchecked(id) })
     }
 
+    /// A generation id drawn from the process wide `GenSource`.
+    pub fn new_gen() -> Self {
+        Self::new_with(gen_source())
+    }
+
+    pub fn new_with(source: &(impl GenSource + ?Sized)) -> Self {
+        let mut id = source.next_u64() as u32;
+
+        while id == u32::MIN || id == u32::MAX {
+            id = source.next_u64() as u32;
+        }
+
+        Self(unsafe { NonZeroU32::new_unchecked(id) })
+    }
+
     pub fn from_uint(id: impl Into<u32>) -> Option<Self> {
         Some(Self(NonZeroU32::new(id.into())?))
     }
@@ -208,7 +242,7 @@ impl<'de> serde::Deserialize<'de> for O32 {
 
         match decode(&s) {
             Ok(v) => {
-                if v > u32::MIN as u128 {
+                if v == 0 || v > u32::MAX as u128 {
                     Err(serde::de::Error::custom("value out of range"))
                 } else {
                     Ok(O32(unsafe { NonZeroU32::new_unchecked(v as u32) }))
@@ -237,6 +271,21 @@ impl O64 {
         Self(unsafe { NonZeroU64::new_unchecked(id) })
     }
 
+    /// A generation id drawn from the process wide `GenSource`.
+    pub fn new_gen() -> Self {
+        Self::new_with(gen_source())
+    }
+
+    pub fn new_with(source: &(impl GenSource + ?Sized)) -> Self {
+        let mut id = source.next_u64();
+
+        while id == u64::MIN || id == u64::MAX {
+            id = source.next_u64();
+        }
+
+        Self(unsafe { NonZeroU64::new_unchecked(id) })
+    }
+
     pub fn from_uint(id: impl Into<u64>) -> Option<Self> {
         Some(Self(NonZeroU64::new(id.into())?))
     }
@@ -304,6 +353,18 @@ impl std::fmt::Display for O64 {
     }
 }
 
+impl std::str::FromStr for O64 {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match decode(s) {
+            Ok(v) if v > u64::MAX as u128 => anyhow::bail!("value out of range"),
+            Ok(v) => Self::try_from_uint(v as u64),
+            Err(e) => anyhow::bail!(e.to_string()),
+        }
+    }
+}
+
 impl serde::Serialize for O64 {
     fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
         serializer.serialize_str(&encode(self.0.get()))
@@ -316,7 +377,7 @@ impl<'de> serde::Deserialize<'de> for O64 {
 
         match decode(&s) {
             Ok(v) => {
-                if v > u64::MIN as u128 {
+                if v == 0 || v > u64::MAX as u128 {
                     Err(serde::de::Error::custom("value out of range"))
                 } else {
                     Ok(O64(unsafe { NonZeroU64::new_unchecked(v as u64) }))
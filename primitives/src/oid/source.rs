@@ -0,0 +1,182 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    OnceLock,
+};
+
+use anyhow::Result;
+
+/// Where generation ids come from.
+///
+/// Generations only need to be unique for as long as a slot lives, which is all stale handle
+/// detection relies on, so they don't need a secure source. Ids that identify something outside of
+/// the current process (`RecordId`, `TableId`, ...) keep using `O16::new`/`O32::new`/`O64::new`,
+/// which always draw from `SecureRandom`.
+pub trait GenSource: Send + Sync {
+    fn next_u64(&self) -> u64;
+}
+
+/// The thread local RNG. This is the default source.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SecureRandom;
+
+impl GenSource for SecureRandom {
+    fn next_u64(&self) -> u64 {
+        rand::random()
+    }
+}
+
+/// A process wide counter XORed with a random session seed. Values are only unique within the
+/// process, and for the narrower ids only within a window of `2^bits` consecutive values.
+#[derive(Debug)]
+pub struct FastCounter {
+    counter: AtomicU64,
+    seed: u64,
+}
+
+impl FastCounter {
+    pub fn new() -> Self {
+        Self::with_seed(rand::random())
+    }
+
+    pub fn with_seed(seed: u64) -> Self {
+        Self {
+            counter: AtomicU64::new(0),
+            seed,
+        }
+    }
+}
+
+impl Default for FastCounter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GenSource for FastCounter {
+    fn next_u64(&self) -> u64 {
+        self.counter.fetch_add(1, Ordering::Relaxed) ^ self.seed
+    }
+}
+
+/// A deterministic sequence (splitmix64) for tests.
+#[derive(Debug)]
+pub struct Seeded {
+    state: AtomicU64,
+}
+
+impl Seeded {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            state: AtomicU64::new(seed),
+        }
+    }
+}
+
+impl GenSource for Seeded {
+    fn next_u64(&self) -> u64 {
+        const GAMMA: u64 = 0x9E37_79B9_7F4A_7C15;
+
+        let mut z = self
+            .state
+            .fetch_add(GAMMA, Ordering::Relaxed)
+            .wrapping_add(GAMMA);
+
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
+
+static GEN_SOURCE: OnceLock<Box<dyn GenSource>> = OnceLock::new();
+
+/// Installs the process wide generation source. This has to happen before the first generation is
+/// handed out, since handles made with one source can't be checked against another.
+pub fn set_gen_source(source: impl GenSource + 'static) -> Result<()> {
+    GEN_SOURCE
+        .set(Box::new(source))
+        .map_err(|_| anyhow::anyhow!("generation source is already set"))
+}
+
+pub fn gen_source() -> &'static dyn GenSource {
+    GEN_SOURCE.get_or_init(|| Box::new(SecureRandom)).as_ref()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::{O16, O64};
+
+    #[test]
+    fn test_fast_counter_skips_sentinels() {
+        // both seeds make the raw counter land on a sentinel straight away
+        for seed in [0, u16::MAX as u64] {
+            let source = FastCounter::with_seed(seed);
+            let mut seen = HashSet::new();
+
+            for _ in 0..(u16::MAX as usize - 1) {
+                let id = O16::new_with(&source);
+
+                assert_ne!(id, O16::INVALID);
+                assert!(seen.insert(id), "duplicate id {:?}", id);
+            }
+        }
+    }
+
+    #[test]
+    fn test_seeded_is_deterministic() {
+        let a = Seeded::new(42);
+        let b = Seeded::new(42);
+        let c = Seeded::new(43);
+
+        let a = (0..16).map(|_| O64::new_with(&a)).collect::<Vec<_>>();
+        let b = (0..16).map(|_| O64::new_with(&b)).collect::<Vec<_>>();
+        let c = (0..16).map(|_| O64::new_with(&c)).collect::<Vec<_>>();
+
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+    }
+
+    /// A coarse comparison of the sources on the generations a multi-million row insert draws,
+    /// for running by hand with
+    /// `cargo test --release -p primitives bench_gen_sources -- --ignored --nocapture`. Every row
+    /// draws an `O64` for its record and a `Gen` for the slot of each of its columns. The process
+    /// wide source can only be set once, so both are drawn from directly instead of through a
+    /// table.
+    #[test]
+    #[ignore]
+    fn bench_gen_sources() {
+        const ROWS: usize = 4_000_000;
+        const COLUMNS: usize = 4;
+
+        let insert = |source: &dyn GenSource| {
+            let started = std::time::Instant::now();
+
+            for _ in 0..ROWS {
+                std::hint::black_box(O64::new_with(source));
+
+                for _ in 0..COLUMNS {
+                    std::hint::black_box(O16::new_with(source));
+                }
+            }
+
+            started.elapsed()
+        };
+
+        let sources: [(&str, &dyn GenSource); 2] = [
+            ("SecureRandom", &SecureRandom),
+            ("FastCounter", &FastCounter::new()),
+        ];
+
+        for (name, source) in sources {
+            println!(
+                "{}: {} rows of {} columns in {:?}",
+                name,
+                ROWS,
+                COLUMNS,
+                insert(source)
+            );
+        }
+    }
+}
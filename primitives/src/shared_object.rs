@@ -1,9 +1,47 @@
-use std::sync::{Arc, Weak};
+//! Shared, lock protected objects. Which lock an operation takes decides who waits on whom, so
+//! stick to one kind per operation:
+//!
+//! - `read` for lookups and scans that may run alongside other readers. A reader queues behind a
+//!   writer that is already waiting.
+//! - `read_recursive` for readers that may already hold a read of the same object on this thread,
+//!   like slot handles read from inside a block scan, and for `Debug`/`Hash`/`Ord`. These jump
+//!   ahead of waiting writers, so a steady stream of them can starve writers.
+//! - `upgradable` for check-then-write operations, like loading missing blocks. Only one upgradable
+//!   read is held at a time, so don't use it for plain reads.
+//! - `write` for everything that mutates.
+//!
+//! By default locks are released the cheap way, which favours throughput but lets a thread that
+//! keeps relocking win over threads that have been waiting. The `_fair` variants, and
+//! `LockFairness::Latency` for the ones taking a `LockFairness`, hand the lock to the longest
+//! waiting thread instead.
+
+use std::{
+    mem::ManuallyDrop,
+    sync::{Arc, Weak},
+};
 
 use anyhow::Result;
 use parking_lot::{RwLock, RwLockReadGuard, RwLockUpgradableReadGuard, RwLockWriteGuard};
 use serde::{Deserialize, Serialize};
 
+/// How a lock is handed over when it's released.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LockFairness {
+    /// Released without regard for who has waited the longest. Fastest, but under sustained load
+    /// one side, usually writers, can be kept waiting indefinitely.
+    #[default]
+    Throughput,
+    /// Released to the longest waiting thread, and long reads step aside for waiting writers every
+    /// so often. Bounds how long anyone waits at the cost of more context switches.
+    Latency,
+}
+
+impl LockFairness {
+    pub fn is_fair(self) -> bool {
+        self == Self::Latency
+    }
+}
+
 #[derive(Default)]
 #[repr(transparent)]
 pub struct SharedObject<T: 'static>(Arc<RwLock<T>>);
@@ -83,6 +121,32 @@ impl<T> SharedObject<T> {
         f(&mut *self.0.write())
     }
 
+    /// Like `read`, but the lock is released fairly once the guard is dropped.
+    pub fn read_fair(&self) -> SharedObjectFairReadGuard<'_, T> {
+        self.read_with_fairness(LockFairness::Latency)
+    }
+
+    /// Like `write`, but the lock is released fairly once the guard is dropped.
+    pub fn write_fair(&self) -> SharedObjectFairWriteGuard<'_, T> {
+        self.write_with_fairness(LockFairness::Latency)
+    }
+
+    /// A `read` or `read_fair`, depending on `fairness`.
+    pub fn read_with_fairness(&self, fairness: LockFairness) -> SharedObjectFairReadGuard<'_, T> {
+        SharedObjectFairReadGuard {
+            guard: ManuallyDrop::new(self.0.read()),
+            fairness,
+        }
+    }
+
+    /// A `write` or `write_fair`, depending on `fairness`.
+    pub fn write_with_fairness(&self, fairness: LockFairness) -> SharedObjectFairWriteGuard<'_, T> {
+        SharedObjectFairWriteGuard {
+            guard: ManuallyDrop::new(self.0.write()),
+            fairness,
+        }
+    }
+
     pub fn upgradable(&self) -> SharedObjectReadGuard<'_, T> {
         SharedObjectReadGuard(self.0.upgradable_read())
     }
@@ -201,3 +265,87 @@ impl<'a, T> AsMut<T> for SharedObjectWriteGuard<'a, T> {
         &mut *self.0
     }
 }
+
+pub struct SharedObjectFairReadGuard<'a, T> {
+    guard: ManuallyDrop<RwLockReadGuard<'a, T>>,
+    fairness: LockFairness,
+}
+
+impl<'a, T> SharedObjectFairReadGuard<'a, T> {
+    /// Lets a waiting writer in before carrying on, if the guard is fair. What was read before may
+    /// have changed afterwards.
+    pub fn bump(&mut self) {
+        if self.fairness.is_fair() {
+            RwLockReadGuard::bump(&mut self.guard);
+        }
+    }
+}
+
+impl<'a, T> Drop for SharedObjectFairReadGuard<'a, T> {
+    fn drop(&mut self) {
+        let guard = unsafe { ManuallyDrop::take(&mut self.guard) };
+
+        if self.fairness.is_fair() {
+            RwLockReadGuard::unlock_fair(guard);
+        } else {
+            drop(guard);
+        }
+    }
+}
+
+impl<'a, T> std::ops::Deref for SharedObjectFairReadGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+impl<'a, T> AsRef<T> for SharedObjectFairReadGuard<'a, T> {
+    fn as_ref(&self) -> &T {
+        &self.guard
+    }
+}
+
+pub struct SharedObjectFairWriteGuard<'a, T> {
+    guard: ManuallyDrop<RwLockWriteGuard<'a, T>>,
+    fairness: LockFairness,
+}
+
+impl<'a, T> Drop for SharedObjectFairWriteGuard<'a, T> {
+    fn drop(&mut self) {
+        let guard = unsafe { ManuallyDrop::take(&mut self.guard) };
+
+        if self.fairness.is_fair() {
+            RwLockWriteGuard::unlock_fair(guard);
+        } else {
+            drop(guard);
+        }
+    }
+}
+
+impl<'a, T> std::ops::Deref for SharedObjectFairWriteGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+impl<'a, T> std::ops::DerefMut for SharedObjectFairWriteGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.guard
+    }
+}
+
+impl<'a, T> AsRef<T> for SharedObjectFairWriteGuard<'a, T> {
+    fn as_ref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<'a, T> AsMut<T> for SharedObjectFairWriteGuard<'a, T> {
+    fn as_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
@@ -1,16 +1,20 @@
-use super::bytes::Bytes;
+use std::sync::Arc;
+
+use super::{bytes::Bytes, number::Number};
 use anyhow::Result;
 
+/// Clones share the buffer, so a value repeated across many rows is only stored once. Changing
+/// a shared one copies it first.
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[repr(transparent)]
-pub struct Text(Bytes);
+pub struct Text(Arc<Bytes>);
 
 impl Text {
     pub const MAX_LEN: usize = Bytes::MAX_LEN;
 
     #[must_use]
     pub fn new(cap: usize) -> Result<Self> {
-        Ok(Self(Bytes::new(cap)?))
+        Ok(Self(Arc::new(Bytes::new(cap)?)))
     }
 
     #[must_use]
@@ -19,7 +23,23 @@ impl Text {
             anyhow::bail!("Text buffer is too small for string");
         }
 
-        Ok(Self(Bytes::try_from_slice(value.as_bytes(), cap)?))
+        Ok(Self(Arc::new(Bytes::try_from_slice(
+            value.as_bytes(),
+            cap,
+        )?)))
+    }
+
+    /// Like `try_from_str`, cutting `value` short at the last character boundary within `cap`
+    /// rather than failing when it doesn't fit, so the text kept is always valid UTF-8. Returns
+    /// how many bytes were cut off along with it.
+    pub fn try_from_str_truncated(value: &str, cap: usize) -> Result<(Self, usize)> {
+        let mut end = value.len().min(cap);
+
+        while !value.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        Ok((Self::try_from_str(&value[..end], cap)?, value.len() - end))
     }
 
     #[must_use]
@@ -31,21 +51,67 @@ impl Text {
         // SAFETY: bytes is guaranteed to be valid UTF-8
         std::str::from_utf8(bytes)?;
 
-        Ok(Self(Bytes::try_from_slice(bytes, cap)?))
+        Ok(Self(Arc::new(Bytes::try_from_slice(bytes, cap)?)))
+    }
+
+    /// Rebuilds a value from the first `len` bytes of what was stored for it. Unlike
+    /// `try_from_slice`, the bytes come from a file rather than a caller, so they're checked
+    /// the same way, but a length that doesn't fit them is reported rather than trusted.
+    pub fn from_stored_bytes(bytes: &[u8], len: u32, cap: usize) -> Result<Self> {
+        let bytes = Self::_stored_prefix(bytes, len, cap)?;
+
+        if let Err(error) = std::str::from_utf8(bytes) {
+            anyhow::bail!(
+                "stored text is not valid UTF-8 after {} bytes",
+                error.valid_up_to()
+            );
+        }
+
+        Ok(Self(Arc::new(Bytes::try_from_slice(bytes, cap)?)))
+    }
+
+    /// Like `from_stored_bytes`, without checking the bytes are UTF-8.
+    ///
+    /// # Safety
+    ///
+    /// The first `len` bytes have to be valid UTF-8, as they are when they were written from a
+    /// `Text` and haven't been touched since.
+    pub unsafe fn from_stored_bytes_unchecked(bytes: &[u8], len: u32, cap: usize) -> Result<Self> {
+        let bytes = Self::_stored_prefix(bytes, len, cap)?;
+
+        Ok(Self(Arc::new(Bytes::try_from_slice(bytes, cap)?)))
+    }
+
+    fn _stored_prefix(bytes: &[u8], len: u32, cap: usize) -> Result<&[u8]> {
+        let len = len as usize;
+
+        if len > cap {
+            anyhow::bail!("stored text length {} exceeds its capacity {}", len, cap);
+        }
+
+        bytes.get(..len).ok_or_else(|| {
+            anyhow::anyhow!(
+                "stored text length {} exceeds the {} bytes stored",
+                len,
+                bytes.len()
+            )
+        })
     }
 
     #[must_use]
     pub fn try_from_i128(value: i128, cap: usize) -> Result<Self> {
-        let mut num = itoa::Buffer::new();
-        let value = num.format(value);
+        Self::try_from_i128_with(value, cap, 10, 0)
+    }
 
-        if value.len() > cap as usize {
-            anyhow::bail!("Text buffer is too small for this i128");
-        }
+    /// Formats `value` in the given radix (10 or 16), left-padding it with zeros to at least
+    /// `pad_to` characters. The sign counts towards the width and stays in front of the padding,
+    /// so `-5` padded to 4 becomes `-005`.
+    pub fn try_from_i128_with(value: i128, cap: usize, radix: u32, pad_to: usize) -> Result<Self> {
+        Self::_try_from_integer(value < 0, value.unsigned_abs(), cap, radix, pad_to)
+    }
 
-        let mut buf = Self::new(cap)?;
-        buf.try_push_str(value)?;
-        Ok(buf)
+    pub fn try_from_u128(value: u128, cap: usize) -> Result<Self> {
+        Self::_try_from_integer(false, value, cap, 10, 0)
     }
 
     #[must_use]
@@ -53,8 +119,12 @@ impl Text {
         let mut num = ryu::Buffer::new();
         let value = num.format(value);
 
-        if value.len() > cap as usize {
-            anyhow::bail!("Text buffer is too small for this f64");
+        if value.len() > cap {
+            anyhow::bail!(
+                "formatted f64 is {} characters but Text capacity is {}",
+                value.len(),
+                cap
+            );
         }
 
         let mut buf = Self::new(cap)?;
@@ -62,6 +132,72 @@ impl Text {
         Ok(buf)
     }
 
+    /// Formats a number the same way its `Display` impl does, without going through a `String`.
+    pub fn try_from_number(value: &Number, cap: usize) -> Result<Self> {
+        match *value {
+            Number::NaN => Self::try_from_str("NaN", cap),
+            Number::Infinity(true) => Self::try_from_str("Infinity", cap),
+            Number::Infinity(false) => Self::try_from_str("-Infinity", cap),
+            Number::Float(x) => Self::try_from_f64(x, cap),
+            Number::Integer(x) => Self::try_from_i128(x as i128, cap),
+            Number::Unsigned(x) => Self::try_from_u128(x as u128, cap),
+        }
+    }
+
+    fn _try_from_integer(
+        negative: bool,
+        mut magnitude: u128,
+        cap: usize,
+        radix: u32,
+        pad_to: usize,
+    ) -> Result<Self> {
+        const DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+        if radix != 10 && radix != 16 {
+            anyhow::bail!("unsupported radix {} (expected 10 or 16)", radix);
+        }
+
+        // u128::MAX is 39 digits in base 10
+        let mut digits = [0u8; 39];
+        let mut start = digits.len();
+
+        loop {
+            start -= 1;
+            digits[start] = DIGITS[(magnitude % radix as u128) as usize];
+            magnitude /= radix as u128;
+
+            if magnitude == 0 {
+                break;
+            }
+        }
+
+        let digits = &digits[start..];
+        let sign = negative as usize;
+        let width = pad_to.max(sign + digits.len());
+
+        if width > cap {
+            anyhow::bail!(
+                "formatted integer is {} characters but Text capacity is {}",
+                width,
+                cap
+            );
+        }
+
+        let mut buf = Self::new(cap)?;
+
+        if negative {
+            buf.try_push_str("-")?;
+        }
+
+        for _ in sign + digits.len()..width {
+            buf.try_push_str("0")?;
+        }
+
+        // SAFETY: every byte comes from `DIGITS`, which is ASCII
+        buf.try_push_str(unsafe { std::str::from_utf8_unchecked(digits) })?;
+        Ok(buf)
+    }
+
     #[inline(always)]
     pub fn len(&self) -> usize {
         self.0.len()
@@ -89,15 +225,20 @@ impl Text {
 
     #[inline(always)]
     pub fn clear(&mut self) {
-        self.0.clear();
+        Arc::make_mut(&mut self.0).clear();
     }
 
     pub fn try_push_str(&mut self, value: impl AsRef<str>) -> Result<()> {
-        self.0.try_push_bytes(value.as_ref().as_bytes())
+        Arc::make_mut(&mut self.0).try_push_bytes(value.as_ref().as_bytes())
     }
 
     pub fn into_bytes(self) -> Bytes {
-        self.0
+        Arc::unwrap_or_clone(self.0)
+    }
+
+    /// Whether `self` and `other` are clones sharing one buffer.
+    pub fn shares_buffer(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
     }
 
     pub fn as_str(&self) -> &str {
@@ -107,7 +248,7 @@ impl Text {
 
     pub fn as_str_mut(&mut self) -> &mut str {
         // SAFETY: Text is guaranteed to be valid UTF-8
-        unsafe { std::str::from_utf8_unchecked_mut(self.0 .0.as_slice_mut()) }
+        unsafe { std::str::from_utf8_unchecked_mut(Arc::make_mut(&mut self.0).0.as_slice_mut()) }
     }
 
     pub fn as_bytes(&self) -> &[u8] {
@@ -168,3 +309,112 @@ impl std::fmt::Write for Text {
         self.try_push_str(s).map_err(|_| std::fmt::Error)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_stored_bytes() -> Result<()> {
+        let stored = b"caf\xc3\xa9\0\0\0";
+
+        assert_eq!(Text::from_stored_bytes(stored, 5, 8)?.as_str(), "café");
+        assert_eq!(Text::from_stored_bytes(stored, 5, 8)?.capacity(), 8);
+        assert!(Text::from_stored_bytes(stored, 9, 16).is_err());
+        assert!(Text::from_stored_bytes(stored, 5, 4).is_err());
+
+        // a cut through the middle of a character, as a torn write would leave it
+        let err = Text::from_stored_bytes(stored, 4, 8).unwrap_err();
+        assert!(err.to_string().contains("after 3 bytes"), "{}", err);
+
+        let text = unsafe { Text::from_stored_bytes_unchecked(stored, 5, 8)? };
+        assert_eq!(text.as_str(), "café");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_try_from_i128() -> Result<()> {
+        assert_eq!(Text::try_from_i128(0, 1)?.as_str(), "0");
+        assert_eq!(Text::try_from_i128(-42, 3)?.as_str(), "-42");
+        assert_eq!(
+            Text::try_from_i128(i128::MIN, 40)?.as_str(),
+            i128::MIN.to_string()
+        );
+        assert_eq!(
+            Text::try_from_u128(u128::MAX, 39)?.as_str(),
+            u128::MAX.to_string()
+        );
+
+        assert_eq!(
+            Text::try_from_i128(-42, 2).unwrap_err().to_string(),
+            "formatted integer is 3 characters but Text capacity is 2"
+        );
+
+        assert!(Text::try_from_i128(i128::MIN, 39).is_err());
+        assert!(Text::try_from_u128(u128::MAX, 38).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_try_from_i128_with() -> Result<()> {
+        assert_eq!(Text::try_from_i128_with(255, 2, 16, 0)?.as_str(), "ff");
+        assert_eq!(Text::try_from_i128_with(-255, 3, 16, 0)?.as_str(), "-ff");
+        assert_eq!(Text::try_from_i128_with(7, 4, 10, 4)?.as_str(), "0007");
+        assert_eq!(Text::try_from_i128_with(-5, 4, 10, 4)?.as_str(), "-005");
+        assert_eq!(Text::try_from_i128_with(12345, 5, 10, 3)?.as_str(), "12345");
+        assert_eq!(
+            Text::try_from_i128_with(i128::MIN, 33, 16, 0)?.as_str(),
+            "-80000000000000000000000000000000"
+        );
+
+        assert!(Text::try_from_i128_with(7, 3, 10, 4).is_err());
+        assert!(Text::try_from_i128_with(-5, 3, 10, 3).is_ok());
+        assert!(Text::try_from_i128_with(7, 4, 8, 0).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_try_from_number() -> Result<()> {
+        assert_eq!(Text::try_from_f64(1.5, 3)?.as_str(), "1.5");
+        assert!(Text::try_from_f64(1.5, 2).is_err());
+
+        for number in [
+            Number::NaN,
+            Number::Infinity(false),
+            Number::Float(-0.25),
+            Number::Integer(i64::MIN),
+            Number::Unsigned(u64::MAX),
+        ] {
+            assert_eq!(
+                Text::try_from_number(&number, 32)?.as_str(),
+                number.to_string()
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_try_from_str_truncated() -> Result<()> {
+        let (text, dropped) = Text::try_from_str_truncated("café", 8)?;
+        assert_eq!((text.as_str(), text.capacity(), dropped), ("café", 8, 0));
+
+        // "é" takes two bytes, and isn't split to fill the last one
+        let (text, dropped) = Text::try_from_str_truncated("café", 4)?;
+        assert_eq!((text.as_str(), text.capacity(), dropped), ("caf", 4, 2));
+
+        let (text, dropped) = Text::try_from_str_truncated("ab🦀", 5)?;
+        assert_eq!((text.as_str(), dropped), ("ab", 4));
+
+        let (text, dropped) = Text::try_from_str_truncated("🦀", 3)?;
+        assert_eq!((text.as_str(), dropped), ("", 4));
+
+        // a capacity that can't hold anything is refused rather than cut to
+        assert!(Text::try_from_str_truncated("x", 0).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,221 @@
+use std::{
+    panic::{catch_unwind, AssertUnwindSafe},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use anyhow::Result;
+use dbexp::{records::RecordHandle, values::DataValue};
+use indexmap::IndexMap;
+use mem_table::{InsertedRow, Table, TableError, UpdateOutcome, ValueError};
+use primitives::O64;
+use rocket::{
+    figment::Figment,
+    http::{Header, Status},
+    serde::json::Json,
+    tokio::sync::{mpsc, oneshot},
+};
+use serde::Deserialize;
+
+use crate::{
+    error::{table_error_status, TooManyRequests},
+    rows::Tables,
+};
+
+/// How long clients turned away by a full queue are asked to wait before retrying.
+const RETRY_AFTER_SECS: u32 = 1;
+
+/// Read from the `async_table` section of the Rocket config.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct AsyncTableConfig {
+    /// Threads running writes, per table.
+    pub workers: usize,
+    /// Writes waiting for a worker, per table, before new ones are turned away.
+    pub queue_depth: usize,
+}
+
+impl Default for AsyncTableConfig {
+    fn default() -> Self {
+        Self {
+            workers: 4,
+            queue_depth: 1024,
+        }
+    }
+}
+
+impl AsyncTableConfig {
+    pub fn from_figment(figment: &Figment) -> Self {
+        figment.extract_inner("async_table").unwrap_or_default()
+    }
+}
+
+#[derive(Debug)]
+pub enum AsyncTableError {
+    /// The queue is full. Nothing was done, so the write can be retried.
+    Busy,
+    /// The workers are gone, or one panicked while running the write.
+    Closed,
+    Failed(TableError),
+}
+
+type Job = Box<dyn FnOnce(&Table) + Send>;
+
+/// Runs a table's writes on a small pool of dedicated threads, so async handlers don't block
+/// executor threads on lock contention or page faults. Writes wait in a bounded queue, and are
+/// turned away with `AsyncTableError::Busy` once it's full instead of piling up in memory.
+#[derive(Debug, Clone)]
+pub struct AsyncTable {
+    table: Table,
+    queue: mpsc::Sender<Job>,
+}
+
+impl AsyncTable {
+    pub fn new(table: Table, config: AsyncTableConfig) -> Self {
+        let (queue, jobs) = mpsc::channel::<Job>(config.queue_depth.max(1));
+        let jobs = Arc::new(Mutex::new(jobs));
+
+        for _ in 0..config.workers.max(1) {
+            let (table, jobs) = (table.clone(), jobs.clone());
+
+            // workers exit once every sender is dropped and the queue is drained
+            thread::spawn(move || loop {
+                let job = jobs
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .blocking_recv();
+
+                let Some(job) = job else {
+                    break;
+                };
+
+                // a panicking write drops its reply, which the caller sees as `Closed`
+                let _ = catch_unwind(AssertUnwindSafe(|| job(&table)));
+            });
+        }
+
+        Self { table, queue }
+    }
+
+    pub fn table(&self) -> &Table {
+        &self.table
+    }
+
+    /// Queues `f` without waiting for it to run. Fails right away if the queue is full.
+    pub fn submit<F, R>(
+        &self,
+        f: F,
+    ) -> Result<oneshot::Receiver<Result<R, TableError>>, AsyncTableError>
+    where
+        F: FnOnce(&Table) -> Result<R, TableError> + Send + 'static,
+        R: Send + 'static,
+    {
+        let (reply, result) = oneshot::channel();
+        let job: Job = Box::new(move |table| {
+            let _ = reply.send(f(table));
+        });
+
+        match self.queue.try_send(job) {
+            Ok(()) => Ok(result),
+            Err(mpsc::error::TrySendError::Full(_)) => Err(AsyncTableError::Busy),
+            Err(mpsc::error::TrySendError::Closed(_)) => Err(AsyncTableError::Closed),
+        }
+    }
+
+    pub async fn run<F, R>(&self, f: F) -> Result<R, AsyncTableError>
+    where
+        F: FnOnce(&Table) -> Result<R, TableError> + Send + 'static,
+        R: Send + 'static,
+    {
+        match self.submit(f)?.await {
+            Ok(res) => res.map_err(AsyncTableError::Failed),
+            Err(_) => Err(AsyncTableError::Closed),
+        }
+    }
+
+    pub async fn insert_one(
+        &self,
+        values: Vec<Option<DataValue>>,
+    ) -> Result<InsertedRow, AsyncTableError> {
+        self.run(move |table| table.insert_one(values)).await
+    }
+
+    pub async fn update_if(
+        &self,
+        handle: RecordHandle,
+        expected: O64,
+        values: Vec<Option<DataValue>>,
+    ) -> Result<UpdateOutcome, AsyncTableError> {
+        self.run(move |table| table.update_if(&handle, expected, values))
+            .await
+    }
+}
+
+/// The async side of every served table, keyed by name like `Tables`.
+#[derive(Debug, Clone, Default)]
+pub struct AsyncTables(pub IndexMap<String, AsyncTable>);
+
+impl AsyncTables {
+    pub fn new(tables: &Tables, config: AsyncTableConfig) -> Self {
+        Self(
+            tables
+                .0
+                .iter()
+                .map(|(name, table)| (name.clone(), AsyncTable::new(table.clone(), config)))
+                .collect(),
+        )
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Result<&AsyncTable, WriteError> {
+        self.0.get(name).ok_or(WriteError::Status(Status::NotFound))
+    }
+}
+
+/// Sent instead of running a write when the table's queue is full.
+#[derive(Responder)]
+#[response(status = 503)]
+pub struct Busy {
+    inner: (),
+    retry_after: Header<'static>,
+}
+
+/// Sent when a value of the body can't be stored in its column, naming the column and why.
+#[derive(Responder)]
+#[response(status = 422)]
+pub struct InvalidValue(Json<ValueError>);
+
+#[derive(Responder)]
+pub enum WriteError {
+    Busy(Busy),
+    Throttled(TooManyRequests),
+    InvalidValue(InvalidValue),
+    Status(Status),
+}
+
+impl From<ValueError> for WriteError {
+    fn from(err: ValueError) -> Self {
+        Self::InvalidValue(InvalidValue(Json(err)))
+    }
+}
+
+impl From<Status> for WriteError {
+    fn from(status: Status) -> Self {
+        Self::Status(status)
+    }
+}
+
+impl From<AsyncTableError> for WriteError {
+    fn from(err: AsyncTableError) -> Self {
+        match err {
+            AsyncTableError::Busy => Self::Busy(Busy {
+                inner: (),
+                retry_after: Header::new("Retry-After", RETRY_AFTER_SECS.to_string()),
+            }),
+            AsyncTableError::Closed => Self::Status(Status::ServiceUnavailable),
+            AsyncTableError::Failed(TableError::Throttled(throttled)) => {
+                Self::Throttled(throttled.into())
+            }
+            AsyncTableError::Failed(err) => Self::Status(table_error_status(&err)),
+        }
+    }
+}
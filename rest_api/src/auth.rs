@@ -1,6 +1,9 @@
 use rocket::fairing::{ self, Fairing, Info, Kind };
-use rocket::request::Request;
+use rocket::figment::Figment;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
 use rocket::{Build, Orbit, Response, Rocket};
+use serde::Deserialize;
 
 pub struct AuthFairing;
 
@@ -30,3 +33,74 @@ impl Fairing for AuthFairing {
     }
 
 }
+
+/// Read from the `auth` section of the Rocket config.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    /// The bearer token that lets a request into the admin scope. Without one, nothing is let in.
+    pub admin_token: Option<String>,
+    /// The bearer token that lets a request into the write scope. The admin token does too.
+    pub write_token: Option<String>,
+}
+
+impl AuthConfig {
+    pub fn from_figment(figment: &Figment) -> Self {
+        figment.extract_inner("auth").unwrap_or_default()
+    }
+}
+
+/// Guards the routes of the admin scope: the request has to carry the configured `admin_token` as
+/// `Authorization: Bearer <token>`. Without the header it's `401 Unauthorized`, and with another
+/// token, or none configured, `403 Forbidden`.
+pub struct AdminScope;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AdminScope {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        check_token(req, |config| [config.admin_token.as_deref(), None]).map(|()| AdminScope)
+    }
+}
+
+/// Guards the routes that change rows in place, like the admin scope but let in by the
+/// `write_token` as well as the `admin_token`.
+pub struct WriteScope;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for WriteScope {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        check_token(req, |config| {
+            [config.admin_token.as_deref(), config.write_token.as_deref()]
+        })
+        .map(|()| WriteScope)
+    }
+}
+
+/// Lets the request in if its bearer token is one of those `accepted` picks from the config.
+fn check_token<'r>(
+    req: &'r Request<'_>,
+    accepted: impl FnOnce(&'r AuthConfig) -> [Option<&'r str>; 2],
+) -> Outcome<(), ()> {
+    let Some(token) = req
+        .headers()
+        .get_one("Authorization")
+        .and_then(|value| value.trim().strip_prefix("Bearer "))
+    else {
+        return Outcome::Error((Status::Unauthorized, ()));
+    };
+
+    let accepted = match req.rocket().state::<AuthConfig>() {
+        Some(config) => accepted(config),
+        None => [None, None],
+    };
+
+    if accepted.contains(&Some(token.trim())) {
+        Outcome::Success(())
+    } else {
+        Outcome::Error((Status::Forbidden, ()))
+    }
+}
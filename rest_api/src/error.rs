@@ -0,0 +1,44 @@
+use mem_table::{TableError, Throttled};
+use rocket::http::{Header, Status};
+
+/// The status a request is answered with when the table fails it with `error`. Every table error
+/// the handlers run into is mapped here, so none of them look at what went wrong themselves.
+pub fn table_error_status(error: &TableError) -> Status {
+    match error {
+        TableError::Validation(_) => Status::UnprocessableEntity,
+        TableError::DuplicateKey(_)
+        | TableError::UniqueViolation(_)
+        | TableError::Stale(_)
+        | TableError::Conflict(_) => Status::Conflict,
+        TableError::Capacity(_) => Status::InsufficientStorage,
+        TableError::NotFound(_) => Status::NotFound,
+        TableError::Throttled(_) => Status::TooManyRequests,
+        TableError::Cancelled(_) | TableError::Closed | TableError::Unavailable(_) => {
+            Status::ServiceUnavailable
+        }
+        TableError::Storage(_) | TableError::Io(_) | TableError::Corrupt(_) => {
+            Status::InternalServerError
+        }
+    }
+}
+
+/// Sent when the table's rate limiter turns a request away, with a `Retry-After` header saying how
+/// long it asked for, in whole seconds.
+#[derive(Responder)]
+#[response(status = 429)]
+pub struct TooManyRequests {
+    inner: (),
+    retry_after: Header<'static>,
+}
+
+impl From<Throttled> for TooManyRequests {
+    fn from(throttled: Throttled) -> Self {
+        let wait = throttled.retry_after;
+        let secs = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+
+        Self {
+            inner: (),
+            retry_after: Header::new("Retry-After", secs.max(1).to_string()),
+        }
+    }
+}
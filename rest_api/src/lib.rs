@@ -2,7 +2,15 @@
 extern crate rocket;
 mod logging;
 mod auth;
+pub mod async_table;
+pub mod error;
+pub mod query;
+pub mod rate_limit;
+pub mod rows;
+pub mod schema;
+mod shutdown;
 
+use hcl_schemas::AuditLog;
 use rocket::{serde::json::Json, Build, Rocket};
 use serde::Deserialize;
 
@@ -33,20 +41,1524 @@ fn post(body: Json<PostBody>) {
 
 // #[launch]
 pub fn rocket() -> Rocket<Build> {
-    rocket
-        ::build()
+    rocket_with_tables(rows::Tables::default())
+}
+
+pub fn rocket_with_tables(tables: rows::Tables) -> Rocket<Build> {
+    mount_tables(rocket::build(), tables)
+}
+
+/// Like `mount_database`, with an empty audit log kept in memory.
+pub fn mount_tables(rocket: Rocket<Build>, tables: rows::Tables) -> Rocket<Build> {
+    let audit = AuditLog::open(None).expect("an audit log kept in memory opens");
+    mount_database(rocket, tables, audit)
+}
+
+/// Serves `tables` from `rocket`, with the write queues sized from its `async_table` config and the
+/// tables rate limited as its `rate_limit` config says. `audit` is the log `GET /audit` reads, the
+/// one of the database the tables are from.
+pub fn mount_database(
+    rocket: Rocket<Build>,
+    tables: rows::Tables,
+    audit: AuditLog,
+) -> Rocket<Build> {
+    let config = async_table::AsyncTableConfig::from_figment(rocket.figment());
+    let writers = async_table::AsyncTables::new(&tables, config);
+    let query = query::QueryConfig::from_figment(rocket.figment());
+    let prepared = query::PreparedCache::new(query.prepared_cache_size);
+    let auth_config = auth::AuthConfig::from_figment(rocket.figment());
+
+    rate_limit::RateLimitConfig::from_figment(rocket.figment()).apply(&tables);
+
+    let rocket = rocket
+        .manage(tables)
+        .manage(writers)
+        .manage(query)
+        .manage(prepared)
+        .manage(auth_config)
+        .manage(audit)
+        .manage(schema::Catalog::default())
         .attach(logging::LoggingFairing)
         .attach(auth::AuthFairing)
-        .mount("/", routes![index, path, post])
+        .attach(shutdown::ShutdownFairing)
+        .mount(
+            "/",
+            routes![
+                index,
+                path,
+                post,
+                rows::get_tables,
+                rows::head_rows,
+                rows::get_rows,
+                rows::get_row,
+                rows::post_query,
+                rows::post_group_by,
+                rows::get_metrics,
+                rows::get_health,
+                rows::get_audit,
+                rows::post_row,
+                rows::put_row,
+                rows::delete_row,
+                rows::patch_row,
+                schema::post_schema_hcl,
+                schema::post_schema_json
+            ],
+        );
+
+    #[cfg(debug_assertions)]
+    let rocket = rocket.mount(
+        "/debug",
+        routes![
+            rows::get_integrity,
+            rows::get_scrub,
+            rows::get_casts,
+            rows::get_record_debug,
+            rows::post_synthetic
+        ],
+    );
+
+    rocket
 }
 
 #[cfg(test)]
 mod tests {
+    use dbexp::test_util::TempDir;
+    use rocket::{figment::Figment, http::Header};
+
     use super::*;
 
+    /// The config of a server that lets writes in with the `writer` token.
+    fn writer_figment() -> Figment {
+        rocket::Config::figment().merge(("auth.write_token", "writer"))
+    }
+
+    fn write_auth() -> Header<'static> {
+        Header::new("Authorization", "Bearer writer")
+    }
+
     #[test]
     fn it_works() {
         let result = path("test");
         assert_eq!(result, "test");
     }
+
+    #[test]
+    fn test_conditional_put() -> anyhow::Result<()> {
+        use dbexp::{object_ids::TableId, values::DataValue};
+        use mem_table::{DataConfig, Table, TableConfig};
+        use primitives::DataType;
+        use rocket::{
+            http::{ContentType, Header, Status},
+            local::blocking::{Client, LocalResponse},
+        };
+
+        let columns = vec![DataConfig::new(DataType::Number), DataConfig::new(DataType::Bool)];
+        let table = Table::new(TableId::new(), TableConfig::new(&columns)?, None)?;
+
+        table.insert_one(vec![
+            Some(DataValue::try_from_any(columns[0].data_type, 1)?),
+            Some(DataValue::Bool(false)),
+        ])?;
+
+        let mut tables = rows::Tables::default();
+        tables.0.insert("items".to_string(), table);
+
+        let alice = Client::tracked(mount_tables(
+            rocket::custom(writer_figment()),
+            tables.clone(),
+        ))?;
+        let bob = Client::tracked(mount_tables(rocket::custom(writer_figment()), tables))?;
+
+        let read = |client: &Client| {
+            let res = client.get("/tables/items/rows/1").dispatch();
+            assert_eq!(res.status(), Status::Ok);
+            res.headers().get_one("ETag").expect("etag").to_string()
+        };
+
+        let alice_etag = read(&alice);
+        let bob_etag = read(&bob);
+
+        assert_eq!(alice_etag, bob_etag);
+
+        let anonymous = alice
+            .put("/tables/items/rows/1")
+            .header(ContentType::JSON)
+            .header(Header::new("If-Match", alice_etag.clone()))
+            .body("[2, true]")
+            .dispatch();
+
+        assert_eq!(anonymous.status(), Status::Unauthorized);
+
+        let missing = alice
+            .put("/tables/items/rows/1")
+            .header(ContentType::JSON)
+            .header(write_auth())
+            .body("[2, true]")
+            .dispatch();
+
+        assert_eq!(missing.status(), Status::PreconditionRequired);
+
+        fn put<'c>(client: &'c Client, etag: &str, body: &str) -> LocalResponse<'c> {
+            client
+                .put("/tables/items/rows/1")
+                .header(ContentType::JSON)
+                .header(write_auth())
+                .header(Header::new("If-Match", etag.to_string()))
+                .body(body)
+                .dispatch()
+        }
+
+        let alice_res = put(&alice, &alice_etag, "[2, true]");
+        let bob_res = put(&bob, &bob_etag, "[3, false]");
+
+        assert_eq!(alice_res.status(), Status::Ok);
+        assert_eq!(bob_res.status(), Status::PreconditionFailed);
+
+        let new_etag = alice_res.headers().get_one("ETag").expect("etag").to_string();
+
+        assert_eq!(alice_res.into_string().as_deref(), Some("[2,true]"));
+        assert_ne!(new_etag, alice_etag);
+        assert_eq!(read(&bob), new_etag);
+        assert_eq!(
+            bob.get("/tables/items/rows/1").dispatch().into_string().as_deref(),
+            Some("[2,true]")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_shutdown_flushes_tables() -> anyhow::Result<()> {
+        use dbexp::{object_ids::TableId, values::DataValue};
+        use mem_table::{DataConfig, Table, TableConfig};
+        use primitives::DataType;
+        use rocket::{http::Status, local::blocking::Client};
+
+        let columns = vec![DataConfig::new(DataType::Number)];
+
+        let id = TableId::new();
+        let dir = TempDir::new("rest_api_shutdown");
+        let table_config = TableConfig::new_persisted(&columns, &dir)?;
+        let table = Table::new(id, table_config, None)?;
+
+        let mut tables = rows::Tables::default();
+        tables.0.insert("items".to_string(), table.clone());
+
+        let client = Client::tracked(rocket_with_tables(tables))?;
+
+        for n in 0..3 {
+            table.insert_one(vec![Some(DataValue::try_from_any(columns[0].data_type, n)?)])?;
+        }
+
+        assert_eq!(client.get("/tables/items/rows/3").dispatch().status(), Status::Ok);
+
+        // keep the terminated rocket around so nothing gets flushed by being dropped
+        let rocket = client.terminate();
+
+        assert!(table.is_closed());
+        assert!(table.close().is_ok());
+        assert!(table.insert_one(vec![]).is_err());
+
+        let reopened = Table::new(id, table_config, None)?;
+        let mut found = Vec::new();
+
+        for (_, handle) in reopened.scan_since(0) {
+            found.push(reopened.get_versioned(&handle)?.0);
+        }
+
+        assert_eq!(
+            found,
+            (0..3)
+                .map(|n| Ok(vec![Some(DataValue::try_from_any(columns[0].data_type, n)?)]))
+                .collect::<anyhow::Result<Vec<_>>>()?
+        );
+
+        drop(reopened);
+        drop(rocket);
+        drop(table);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_row_count() -> anyhow::Result<()> {
+        use dbexp::{object_ids::TableId, values::DataValue};
+        use mem_table::{DataConfig, Table, TableConfig};
+        use primitives::DataType;
+        use rocket::{http::Status, local::blocking::Client, serde::json::serde_json};
+
+        let columns = vec![DataConfig::new(DataType::Number)];
+        let table = Table::new(TableId::new(), TableConfig::new(&columns)?, None)?;
+
+        let mut handles = Vec::new();
+
+        for n in 0..5 {
+            handles.push(
+                table
+                    .insert_one(vec![Some(DataValue::try_from_any(columns[0].data_type, n)?)])?
+                    .handle,
+            );
+        }
+
+        table.delete(handles.remove(1))?;
+        table.delete(handles.remove(2))?;
+
+        let mut tables = rows::Tables::default();
+        tables.0.insert("items".to_string(), table);
+
+        let client = Client::tracked(rocket_with_tables(tables))?;
+
+        let res = client.head("/tables/items/rows").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        assert_eq!(res.headers().get_one("X-Total-Count"), Some("3"));
+
+        let res = client.get("/tables/items/rows?count_only=true").dispatch();
+        assert_eq!(res.headers().get_one("X-Total-Count"), Some("3"));
+        assert_eq!(res.into_string(), None);
+
+        let res = client.get("/tables/items/rows").dispatch();
+        assert_eq!(res.headers().get_one("X-Total-Count"), Some("3"));
+        assert_eq!(res.into_string().as_deref(), Some("[1,3,5]"));
+
+        let res = client.get("/debug/tables/items/integrity").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        let report = res.into_json::<serde_json::Value>().expect("integrity report");
+        assert_eq!(report["rows_checked"], 3);
+        assert_eq!(report["violations"], serde_json::json!([]));
+
+        let casts = client.get("/debug/casts").dispatch();
+        let casts = casts.into_json::<serde_json::Value>().expect("cast matrix");
+        assert_eq!(casts.as_array().map(Vec::len), Some(100));
+        assert_eq!(
+            casts[0],
+            serde_json::json!({ "from": "O16", "to": "O16", "kind": "identity" })
+        );
+
+        assert_eq!(
+            client.head("/tables/missing/rows").dispatch().status(),
+            Status::NotFound
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_value_is_422() -> anyhow::Result<()> {
+        use dbexp::object_ids::TableId;
+        use mem_table::{DataConfig, Table, TableConfig};
+        use primitives::{DataType, InternalString};
+        use rocket::{
+            http::{ContentType, Status},
+            local::blocking::Client,
+            serde::json::{serde_json, Value},
+        };
+
+        let columns = vec![DataConfig::new(DataType::Text(120))];
+        let name_mapping = [(InternalString::new("email")?, 0)].into_iter().collect();
+        let table = Table::new(
+            TableId::new(),
+            TableConfig::new(&columns)?,
+            Some(name_mapping),
+        )?;
+
+        let mut tables = rows::Tables::default();
+        tables.0.insert("users".to_string(), table);
+
+        let client = Client::tracked(mount_tables(rocket::custom(writer_figment()), tables))?;
+        let email = format!("{}@example.com", "x".repeat(118));
+
+        let res = client
+            .post("/tables/users/rows")
+            .header(write_auth())
+            .header(ContentType::JSON)
+            .body(serde_json::to_string(&[&email])?)
+            .dispatch();
+
+        assert_eq!(res.status(), Status::UnprocessableEntity);
+        assert_eq!(
+            serde_json::from_str::<Value>(&res.into_string().unwrap_or_default())?,
+            serde_json::json!({
+                "column": "email",
+                "expected": { "Text": 120 },
+                "reason": { "kind": "too_long", "len": 130, "cap": 120 },
+            })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_post_returns_stored_row() -> anyhow::Result<()> {
+        use dbexp::object_ids::TableId;
+        use mem_table::{DataConfig, OverflowPolicy, Table, TableConfig};
+        use primitives::DataType;
+        use rocket::{
+            http::{ContentType, Status},
+            local::blocking::Client,
+            serde::json::{serde_json, Value},
+        };
+
+        let columns = vec![
+            DataConfig::new(DataType::Text(5)).with_overflow(OverflowPolicy::Truncate),
+            DataConfig::new(DataType::Number),
+            DataConfig::new(DataType::Bool),
+        ];
+        let table = Table::new(TableId::new(), TableConfig::new(&columns)?, None)?;
+
+        let mut tables = rows::Tables::default();
+        tables.0.insert("items".to_string(), table.clone());
+
+        let client = Client::tracked(mount_tables(rocket::custom(writer_figment()), tables))?;
+
+        // the stored row comes back as the table has it, without a second read
+        let res = client
+            .post("/tables/items/rows")
+            .header(write_auth())
+            .header(ContentType::JSON)
+            .body(r#"["truncated", 7]"#)
+            .dispatch();
+
+        assert_eq!(res.status(), Status::Created);
+        assert_eq!(res.headers().get_one("Location"), Some("/tables/items/rows/1"));
+        assert_eq!(
+            serde_json::from_str::<Value>(&res.into_string().unwrap_or_default())?,
+            serde_json::json!(["trunc", 7, null])
+        );
+        assert_eq!(table.row_count(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rows_in_plain_form() -> anyhow::Result<()> {
+        use dbexp::object_ids::TableId;
+        use mem_table::{DataConfig, OverflowPolicy, Table, TableConfig};
+        use primitives::{DataType, O64};
+        use rocket::{
+            http::{ContentType, Status},
+            local::blocking::Client,
+            serde::json::{serde_json, Value},
+        };
+
+        let columns = vec![
+            DataConfig::new(DataType::Timestamp),
+            DataConfig::new(DataType::Bytes(8)).with_overflow(OverflowPolicy::Truncate),
+            DataConfig::new(DataType::O64),
+        ];
+        let table = Table::new(TableId::new(), TableConfig::new(&columns)?, None)?;
+
+        let mut tables = rows::Tables::default();
+        tables.0.insert("items".to_string(), table);
+
+        let client = Client::tracked(mount_tables(rocket::custom(writer_figment()), tables))?;
+        let post = |body: Value| {
+            client
+                .post("/tables/items/rows")
+                .header(write_auth())
+                .header(ContentType::JSON)
+                .body(body.to_string())
+                .dispatch()
+        };
+        let id = O64::new();
+
+        // timestamps are read as millis too, and bytes are base64, cut to the column by its policy
+        let res = post(serde_json::json!([
+            1_700_000_000_123i64,
+            "AP9hYmNkZWZnaA==",
+            id.to_string()
+        ]));
+        assert_eq!(res.status(), Status::Created);
+        assert_eq!(
+            serde_json::from_str::<Value>(&res.into_string().unwrap_or_default())?,
+            serde_json::json!([
+                "2023-11-14T22:13:20.123+00:00",
+                "AP9hYmNkZWY=",
+                id.to_string()
+            ])
+        );
+
+        // a value of another type isn't converted to the column's
+        for row in [serde_json::json!(["now"]), serde_json::json!([null, 7])] {
+            assert_eq!(post(row).status(), Status::UnprocessableEntity);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_logical_types() -> anyhow::Result<()> {
+        use dbexp::object_ids::TableId;
+        use mem_table::{DataConfig, LogicalType, Table, TableConfig};
+        use primitives::InternalString;
+        use rocket::{
+            http::{ContentType, Status},
+            local::blocking::Client,
+            serde::json::{serde_json, Value},
+        };
+
+        let columns = vec![
+            DataConfig::new(LogicalType::Email.base_type()).with_logical_type(LogicalType::Email),
+            DataConfig::new(LogicalType::Phone.base_type())
+                .with_logical_type(LogicalType::Phone)
+                .with_logical_check(false),
+        ];
+        let name_mapping = [
+            (InternalString::new("email")?, 0),
+            (InternalString::new("phone")?, 1),
+        ]
+        .into_iter()
+        .collect();
+        let table = Table::new(
+            TableId::new(),
+            TableConfig::new(&columns)?,
+            Some(name_mapping),
+        )?;
+
+        let mut tables = rows::Tables::default();
+        tables.0.insert("users".to_string(), table.clone());
+
+        let client = Client::tracked(mount_tables(rocket::custom(writer_figment()), tables))?;
+        let post = |body: &str| {
+            client
+                .post("/tables/users/rows")
+                .header(write_auth())
+                .header(ContentType::JSON)
+                .body(body)
+                .dispatch()
+        };
+
+        // the phone column isn't checked, so anything that fits goes
+        assert_eq!(
+            post(r#"["foobar@example.com", "call me"]"#).status(),
+            Status::Created
+        );
+
+        let res = post(r#"["not-an-email", "123-456-7890"]"#);
+        assert_eq!(res.status(), Status::UnprocessableEntity);
+        assert_eq!(
+            serde_json::from_str::<Value>(&res.into_string().unwrap_or_default())?,
+            serde_json::json!({
+                "column": "email",
+                "expected": { "Text": 120 },
+                "reason": { "kind": "not_logical_type", "logical_type": "email" },
+            })
+        );
+        assert_eq!(table.row_count(), 1);
+
+        let res = client.get("/tables").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        assert_eq!(
+            serde_json::from_str::<Value>(&res.into_string().unwrap_or_default())?,
+            serde_json::json!([{
+                "name": "users",
+                "columns": [
+                    {
+                        "name": "email",
+                        "data_type": { "Text": 120 },
+                        "logical_type": "email",
+                        "validate": true,
+                    },
+                    {
+                        "name": "phone",
+                        "data_type": { "Text": 20 },
+                        "logical_type": "phone",
+                        "validate": false,
+                    },
+                ],
+            }])
+        );
+
+        Ok(())
+    }
+
+    #[rocket::async_test]
+    async fn test_full_write_queue_is_503() -> anyhow::Result<()> {
+        use dbexp::{object_ids::TableId, values::DataValue};
+        use mem_table::{DataConfig, Table, TableConfig};
+        use primitives::DataType;
+        use rocket::{
+            http::{ContentType, Status},
+            local::asynchronous::Client,
+        };
+
+        let columns = vec![DataConfig::new(DataType::Number)];
+        let table = Table::new(TableId::new(), TableConfig::new(&columns)?, None)?;
+
+        let mut tables = rows::Tables::default();
+        tables.0.insert("items".to_string(), table.clone());
+
+        let figment = writer_figment()
+            .merge(("async_table.workers", 1))
+            .merge(("async_table.queue_depth", 1));
+        let client = Client::tracked(mount_tables(rocket::custom(figment), tables)).await?;
+        let writer = client
+            .rocket()
+            .state::<async_table::AsyncTables>()
+            .expect("write queues")
+            .0["items"]
+            .clone();
+
+        let value = DataValue::try_from_any(columns[0].data_type, 1)?;
+
+        // hold the column store so the one worker gets stuck on the first insert, and the second
+        // fills the queue
+        let store = table.get_column_store(0)?;
+        let guard = store.write();
+        let (started, wait_started) = std::sync::mpsc::channel();
+
+        let first = writer
+            .submit({
+                let value = value.clone();
+                move |table| {
+                    started.send(()).unwrap();
+                    table.insert_one(vec![Some(value)])
+                }
+            })
+            .expect("queue has room");
+        wait_started.recv()?;
+
+        let second = writer
+            .submit(move |table| table.insert_one(vec![Some(value)]))
+            .expect("queue has room");
+
+        let res = client
+            .post("/tables/items/rows")
+            .header(write_auth())
+            .header(ContentType::JSON)
+            .body("[3]")
+            .dispatch()
+            .await;
+
+        assert_eq!(res.status(), Status::ServiceUnavailable);
+        assert_eq!(res.headers().get_one("Retry-After"), Some("1"));
+
+        drop(guard);
+        first.await??;
+        second.await??;
+
+        let res = client
+            .post("/tables/items/rows")
+            .header(write_auth())
+            .header(ContentType::JSON)
+            .body("[3]")
+            .dispatch()
+            .await;
+
+        assert_eq!(res.status(), Status::Created);
+        assert_eq!(res.headers().get_one("Location"), Some("/tables/items/rows/3"));
+        assert_eq!(res.into_string().await.as_deref(), Some("[3]"));
+        assert_eq!(table.row_count(), 3);
+
+        Ok(())
+    }
+
+    #[rocket::async_test]
+    async fn test_concurrent_async_inserts() -> anyhow::Result<()> {
+        use std::collections::HashSet;
+
+        use async_table::{AsyncTable, AsyncTableConfig, AsyncTableError};
+        use dbexp::{object_ids::TableId, values::DataValue};
+        use mem_table::{DataConfig, Table, TableConfig};
+        use primitives::DataType;
+        use rocket::{futures::future::join_all, tokio::task::yield_now};
+
+        const ROWS: u64 = 10_000;
+
+        let columns = vec![DataConfig::new(DataType::Number)];
+        let table = Table::new(TableId::new(), TableConfig::new(&columns)?, None)?;
+        let writer = AsyncTable::new(
+            table.clone(),
+            AsyncTableConfig {
+                workers: 4,
+                queue_depth: 64,
+            },
+        );
+
+        let data_type = columns[0].data_type;
+        let inserts = (0..ROWS).map(|n| {
+            let writer = writer.clone();
+
+            async move {
+                let value = DataValue::try_from_any(data_type, n)?;
+
+                // a full queue turns the insert away without doing it, so it's safe to retry
+                loop {
+                    match writer.insert_one(vec![Some(value.clone())]).await {
+                        Err(AsyncTableError::Busy) => yield_now().await,
+                        Err(err) => anyhow::bail!("insert {} failed: {:?}", n, err),
+                        Ok(handle) => return Ok(handle),
+                    }
+                }
+            }
+        });
+
+        for res in join_all(inserts).await {
+            res?;
+        }
+
+        assert_eq!(table.row_count(), ROWS as usize);
+
+        let mut seen = HashSet::new();
+
+        for (_, handle) in table.scan_since(0) {
+            let (values, _) = table.get_versioned(&handle)?;
+            assert!(seen.insert(values[0].clone().expect("value")));
+        }
+
+        assert_eq!(seen.len(), ROWS as usize);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_query_timeout_is_503() -> anyhow::Result<()> {
+        use dbexp::{object_ids::TableId, values::DataValue};
+        use mem_table::{DataConfig, Table, TableConfig};
+        use primitives::DataType;
+        use rocket::{http::Status, local::blocking::Client};
+
+        let columns = vec![DataConfig::new(DataType::Number)];
+        let table = Table::new(TableId::new(), TableConfig::new(&columns)?, None)?;
+
+        for n in 0..3 {
+            table.insert_one(vec![Some(DataValue::try_from_any(columns[0].data_type, n)?)])?;
+        }
+
+        let mut tables = rows::Tables::default();
+        tables.0.insert("items".to_string(), table);
+
+        let figment = rocket::Config::figment().merge(("query.timeout_ms", 0));
+        let client = Client::tracked(mount_tables(rocket::custom(figment), tables))?;
+
+        let res = client.get("/tables/items/rows").dispatch();
+        assert_eq!(res.status(), Status::ServiceUnavailable);
+
+        // counting doesn't scan, so it isn't stopped
+        let res = client.get("/tables/items/rows?count_only=true").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        assert_eq!(res.headers().get_one("X-Total-Count"), Some("3"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_idempotent_post() -> anyhow::Result<()> {
+        use dbexp::object_ids::TableId;
+        use mem_table::{DataConfig, Table, TableConfig};
+        use primitives::DataType;
+        use rocket::{
+            http::{ContentType, Header, Status},
+            local::blocking::Client,
+        };
+
+        let columns = vec![DataConfig::new(DataType::Number)];
+        let table = Table::new(TableId::new(), TableConfig::new(&columns)?, None)?;
+
+        let mut tables = rows::Tables::default();
+        tables.0.insert("items".to_string(), table.clone());
+
+        let client = Client::tracked(mount_tables(rocket::custom(writer_figment()), tables))?;
+        let post = |key: &str, body: &str| {
+            let res = client
+                .post("/tables/items/rows")
+                .header(write_auth())
+                .header(ContentType::JSON)
+                .header(Header::new("Idempotency-Key", key.to_string()))
+                .body(body)
+                .dispatch();
+
+            assert_eq!(res.status(), Status::Created);
+            (
+                res.headers().get_one("Location").map(str::to_string),
+                res.into_string(),
+            )
+        };
+
+        let first = post("retry-me", "[1]");
+
+        // a retry gets the row stored the first time, even with another body
+        assert_eq!(post("retry-me", "[2]"), first);
+        assert_eq!(table.row_count(), 1);
+
+        assert_ne!(post("another", "[1]"), first);
+        assert_eq!(table.row_count(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_table_error_status() {
+        use dbexp::{
+            object_ids::{RecordId, TableId},
+            slot::StaleHandleError,
+            store::{CorruptValue, StoreFull},
+            values::DataValue,
+        };
+        use mem_table::{
+            ColumnUnavailable, CompositeKey, TableError, Throttled, UniqueViolation, UpdateConflict,
+        };
+        use primitives::ThinIdx;
+        use rocket::http::Status;
+
+        use crate::error::table_error_status;
+
+        let cases = [
+            (
+                TableError::invalid("value count exceeds column count"),
+                Status::UnprocessableEntity,
+            ),
+            (
+                TableError::DuplicateKey(CompositeKey(vec![])),
+                Status::Conflict,
+            ),
+            (
+                TableError::UniqueViolation(UniqueViolation {
+                    column: 0,
+                    value: DataValue::Bool(true),
+                    existing_record: RecordId::new(ThinIdx::new(0), TableId::new()),
+                }),
+                Status::Conflict,
+            ),
+            (
+                TableError::Stale(StaleHandleError::Removed {
+                    idx: ThinIdx::new(0),
+                }),
+                Status::Conflict,
+            ),
+            (
+                TableError::Conflict(UpdateConflict {
+                    record: RecordId::new(ThinIdx::new(0), TableId::new()),
+                    attempts: 16,
+                }),
+                Status::Conflict,
+            ),
+            (
+                TableError::Capacity(
+                    StoreFull {
+                        reason: "region is full".to_string(),
+                    }
+                    .into(),
+                ),
+                Status::InsufficientStorage,
+            ),
+            (TableError::not_found("column 3"), Status::NotFound),
+            (
+                TableError::Cancelled(mem_table::QueryCancelled.into()),
+                Status::ServiceUnavailable,
+            ),
+            (TableError::Closed, Status::ServiceUnavailable),
+            (
+                TableError::Unavailable(ColumnUnavailable {
+                    column: 1,
+                    path: "column_1.store".into(),
+                }),
+                Status::ServiceUnavailable,
+            ),
+            (
+                TableError::Throttled(Throttled {
+                    retry_after: std::time::Duration::from_millis(1500),
+                }),
+                Status::TooManyRequests,
+            ),
+            (
+                TableError::Storage(anyhow::anyhow!("callback panicked")),
+                Status::InternalServerError,
+            ),
+            (
+                TableError::Io(std::io::ErrorKind::NotFound.into()),
+                Status::InternalServerError,
+            ),
+            (
+                TableError::Corrupt(CorruptValue {
+                    block: 0,
+                    slot: 0,
+                    reason: "invalid UTF-8".to_string(),
+                }),
+                Status::InternalServerError,
+            ),
+        ];
+
+        for (error, status) in cases {
+            assert_eq!(table_error_status(&error), status, "{}", error);
+        }
+
+        // errors from below the table are sorted by type, not by what they say
+        let error = TableError::from(anyhow::Error::from(mem_table::QueryTimeout {
+            timeout: std::time::Duration::ZERO,
+        }));
+        assert_eq!(table_error_status(&error), Status::ServiceUnavailable);
+    }
+
+    #[test]
+    fn test_record_debug_needs_admin() -> anyhow::Result<()> {
+        use dbexp::{object_ids::TableId, values::DataValue};
+        use mem_table::{DataConfig, Table, TableConfig};
+        use primitives::DataType;
+        use rocket::{
+            http::{Header, Status},
+            local::blocking::Client,
+            serde::json::Value,
+        };
+
+        let columns = vec![DataConfig::new(DataType::Number)];
+        let table = Table::new(TableId::new(), TableConfig::new(&columns)?, None)?;
+        table.insert_one(vec![Some(DataValue::try_from_any(
+            columns[0].data_type,
+            7,
+        )?)])?;
+
+        let mut tables = rows::Tables::default();
+        tables.0.insert("items".to_string(), table);
+
+        let figment = rocket::Config::figment().merge(("auth.admin_token", "secret"));
+        let client = Client::tracked(mount_tables(rocket::custom(figment), tables))?;
+        let get = |token: Option<&str>| {
+            let mut req = client.get("/debug/tables/items/records/1");
+
+            if let Some(token) = token {
+                req = req.header(Header::new("Authorization", format!("Bearer {}", token)));
+            }
+
+            req.dispatch()
+        };
+
+        assert_eq!(get(None).status(), Status::Unauthorized);
+        assert_eq!(get(Some("guess")).status(), Status::Forbidden);
+
+        let res = get(Some("secret"));
+        assert_eq!(res.status(), Status::Ok);
+
+        let debug = res.into_json::<Value>().expect("a JSON bundle");
+        assert_eq!(debug["seq"], 1);
+        assert!(debug["columns"][0]["value"].is_string());
+
+        let res = client
+            .get("/debug/tables/items/records/2")
+            .header(Header::new("Authorization", "Bearer secret"))
+            .dispatch();
+        assert_eq!(res.status(), Status::NotFound);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_delete_and_patch_rows() -> anyhow::Result<()> {
+        use dbexp::{object_ids::TableId, values::DataValue};
+        use mem_table::{DataConfig, Table, TableConfig};
+        use primitives::DataType;
+        use rocket::{
+            http::{ContentType, Header, Status},
+            local::blocking::Client,
+            serde::json::{serde_json::json, Value},
+        };
+
+        let columns = vec![
+            DataConfig::new(DataType::Number),
+            DataConfig::new(DataType::Bool),
+        ];
+        let table = Table::new(TableId::new(), TableConfig::new(&columns)?, None)?;
+
+        for n in [1, 2] {
+            table.insert_one(vec![
+                Some(DataValue::try_from_any(columns[0].data_type, n)?),
+                Some(DataValue::Bool(false)),
+            ])?;
+        }
+
+        let mut tables = rows::Tables::default();
+        tables.0.insert("items".to_string(), table.clone());
+
+        let figment = rocket::Config::figment().merge(("auth.write_token", "writer"));
+        let client = Client::tracked(mount_tables(rocket::custom(figment), tables))?;
+        let bearer = |token: &str| Header::new("Authorization", format!("Bearer {}", token));
+
+        assert_eq!(
+            client.delete("/tables/items/rows/1").dispatch().status(),
+            Status::Unauthorized
+        );
+        assert_eq!(
+            client
+                .delete("/tables/items/rows/1")
+                .header(bearer("guess"))
+                .dispatch()
+                .status(),
+            Status::Forbidden
+        );
+
+        let res = client
+            .delete("/tables/items/rows/1")
+            .header(bearer("writer"))
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        assert_eq!(res.into_json::<Value>(), Some(json!([1, false])));
+        assert!(table.get_by_seq(1).is_none());
+
+        // the row is gone, so deleting it again finds nothing
+        let res = client
+            .delete("/tables/items/rows/1")
+            .header(bearer("writer"))
+            .dispatch();
+        assert_eq!(res.status(), Status::NotFound);
+
+        let patch = |body: Value, token: &str| {
+            client
+                .patch("/tables/items/rows/2")
+                .header(ContentType::JSON)
+                .header(bearer(token))
+                .body(body.to_string())
+                .dispatch()
+        };
+
+        assert_eq!(
+            patch(json!({ "column 1": true }), "guess").status(),
+            Status::Forbidden
+        );
+
+        let res = patch(json!({ "column 1": true }), "writer");
+        assert_eq!(res.status(), Status::Ok);
+        let etag = res.headers().get_one("ETag").map(str::to_string);
+        assert_eq!(res.into_json::<Value>(), Some(json!([2, true])));
+
+        // the answer is the row as a later read finds it
+        let res = client.get("/tables/items/rows/2").dispatch();
+        assert_eq!(res.headers().get_one("ETag").map(str::to_string), etag);
+        assert_eq!(res.into_json::<Value>(), Some(json!([2, true])));
+
+        // one value that doesn't convert keeps the others from being written too. Any non-empty
+        // text converts to `true`, so the bad value is one no column takes
+        let res = patch(json!({ "column 0": 5, "column 1": ["nope"] }), "writer");
+        assert_eq!(res.status(), Status::UnprocessableEntity);
+
+        let res = patch(json!({ "column 9": 5 }), "writer");
+        assert_eq!(res.status(), Status::UnprocessableEntity);
+
+        let handle = table.get_by_seq(2).expect("row 2 is still there");
+        assert_eq!(
+            table.get_versioned(&handle)?.0,
+            vec![
+                Some(DataValue::try_from_any(columns[0].data_type, 2)?),
+                Some(DataValue::Bool(true)),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_prepared_query() -> anyhow::Result<()> {
+        use dbexp::{object_ids::TableId, values::DataValue};
+        use mem_table::{DataConfig, Table, TableConfig};
+        use primitives::{DataType, InternalString};
+        use rocket::{
+            http::{ContentType, Status},
+            local::blocking::Client,
+            serde::json::{serde_json::json, Value},
+        };
+
+        let columns = vec![
+            DataConfig::new(DataType::Number),
+            DataConfig::new(DataType::Text(8)),
+        ];
+        let names = [
+            (InternalString::new("age")?, 0),
+            (InternalString::new("status")?, 1),
+        ];
+        let table = Table::new(
+            TableId::new(),
+            TableConfig::new(&columns)?,
+            Some(names.into_iter().collect()),
+        )?;
+
+        for (age, status) in [(20, "active"), (40, "active"), (50, "gone")] {
+            table.insert_one(vec![
+                Some(DataValue::try_from_any(columns[0].data_type, age)?),
+                Some(DataValue::try_from_any(columns[1].data_type, status)?),
+            ])?;
+        }
+
+        let mut tables = rows::Tables::default();
+        tables.0.insert("users".to_string(), table);
+
+        let client = Client::tracked(rocket_with_tables(tables))?;
+        let run = |body: Value| {
+            client
+                .post("/tables/users/query")
+                .header(ContentType::JSON)
+                .body(body.to_string())
+                .dispatch()
+        };
+        let text = "age > ${min_age} && status == ${status}";
+
+        let res = run(json!({ "query": text, "params": { "min_age": 30, "status": "active" } }));
+        assert_eq!(res.status(), Status::Ok);
+        assert_eq!(res.into_json::<Value>(), Some(json!([2])));
+
+        let res = run(json!({ "query": text, "params": { "min_age": 10, "status": "active" } }));
+        assert_eq!(res.into_json::<Value>(), Some(json!([1, 2])));
+
+        // the second request bound the query prepared by the first
+        let prepared = client
+            .rocket()
+            .state::<query::PreparedCache>()
+            .expect("the cache is managed");
+        assert_eq!(prepared.compiles(), 1);
+
+        let error = |body: Value| {
+            let res = run(body);
+            assert_eq!(res.status(), Status::UnprocessableEntity);
+            res.into_json::<Value>().expect("a JSON error")
+        };
+
+        let err = error(json!({ "query": text, "params": { "min_age": 30 } }));
+        assert_eq!(err, json!({ "kind": "missing", "param": "status" }));
+
+        let err = error(json!({
+            "query": text,
+            "params": { "min_age": 30, "status": "active", "limit": 1 }
+        }));
+        assert_eq!(err, json!({ "kind": "extra", "param": "limit" }));
+
+        let err = error(json!({ "query": text, "params": { "min_age": "old", "status": "a" } }));
+        assert_eq!(err["kind"], "mismatched");
+        assert_eq!(err["param"], "min_age");
+
+        let err = error(json!({
+            "query": text,
+            "params": { "min_age": 30, "status": "much too long" }
+        }));
+        assert_eq!(
+            err,
+            json!({ "kind": "too_long", "param": "status", "len": 13, "cap": 8 })
+        );
+
+        let err = error(json!({ "query": "height > 1" }));
+        assert_eq!(err, json!({ "kind": "unknown_column", "column": "height" }));
+        assert_eq!(prepared.compiles(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_health_follows_failed_flushes() -> anyhow::Result<()> {
+        use dbexp::{object_ids::TableId, store::FaultyFileOps, values::DataValue};
+        use mem_table::{DataConfig, Table, TableConfig};
+        use primitives::DataType;
+        use rocket::{
+            http::Status,
+            local::blocking::Client,
+            serde::json::{serde_json::json, Value},
+        };
+
+        let columns = vec![DataConfig::new(DataType::Number)];
+        let id = TableId::new();
+        let dir = TempDir::new("rest_api_health");
+        let table = Table::new(id, TableConfig::new_persisted(&columns, &dir)?, None)?;
+
+        table.insert_one(vec![Some(DataValue::try_from_any(
+            columns[0].data_type,
+            1,
+        )?)])?;
+
+        let mut tables = rows::Tables::default();
+        tables.0.insert("items".to_string(), table.clone());
+
+        let client = Client::tracked(rocket_with_tables(tables))?;
+        let health = || {
+            let response = client.get("/health").dispatch();
+            (response.status(), response.into_json::<Value>())
+        };
+        let flush_failures = || {
+            client
+                .get("/tables/items/metrics")
+                .dispatch()
+                .into_json::<Value>()
+                .map(|metrics| metrics["flush_failures_total"].clone())
+        };
+
+        assert_eq!(health().0, Status::Ok);
+        assert_eq!(flush_failures(), Some(json!(0)));
+
+        let faulty = FaultyFileOps::new();
+        table.get_column_store(0)?.set_file_ops(faulty.clone());
+        faulty.fail(true);
+        assert!(table.flush_all().is_err());
+
+        assert_eq!(
+            health(),
+            (
+                Status::ServiceUnavailable,
+                Some(json!({ "status": "unhealthy", "failed_tables": ["items"] }))
+            )
+        );
+        assert_eq!(flush_failures(), Some(json!(1)));
+
+        // a flush that goes through makes it healthy again, though the failure stays counted
+        faulty.fail(false);
+        table.flush_all()?;
+        assert_eq!(health().0, Status::Ok);
+        assert_eq!(flush_failures(), Some(json!(1)));
+
+        drop(client);
+        drop(table);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rate_limited_writes() -> anyhow::Result<()> {
+        use dbexp::object_ids::TableId;
+        use mem_table::{DataConfig, Table, TableConfig};
+        use primitives::DataType;
+        use rocket::{
+            http::{ContentType, Status},
+            local::blocking::Client,
+            serde::json::{serde_json::json, Value},
+        };
+
+        let columns = vec![DataConfig::new(DataType::Number)];
+        let table = Table::new(TableId::new(), TableConfig::new(&columns)?, None)?;
+        let other = Table::new(TableId::new(), TableConfig::new(&columns)?, None)?;
+
+        let mut tables = rows::Tables::default();
+        tables.0.insert("items".to_string(), table.clone());
+        tables.0.insert("other".to_string(), other.clone());
+
+        let figment = writer_figment()
+            .merge(("rate_limit.tables.items.writes_per_sec", 1))
+            .merge(("rate_limit.tables.items.burst", 3));
+        let client = Client::tracked(mount_tables(rocket::custom(figment), tables))?;
+        let post = |table: &str| {
+            client
+                .post(format!("/tables/{}/rows", table))
+                .header(write_auth())
+                .header(ContentType::JSON)
+                .body("[1]")
+                .dispatch()
+        };
+
+        for _ in 0..3 {
+            assert_eq!(post("items").status(), Status::Created);
+        }
+
+        let res = post("items");
+        assert_eq!(res.status(), Status::TooManyRequests);
+        assert_eq!(res.headers().get_one("Retry-After"), Some("1"));
+        assert_eq!(table.row_count(), 3);
+
+        // a table without a budget of its own isn't held back by one that's used up
+        for _ in 0..10 {
+            assert_eq!(post("other").status(), Status::Created);
+        }
+
+        let throttled = client
+            .get("/tables/items/metrics")
+            .dispatch()
+            .into_json::<Value>()
+            .map(|metrics| metrics["throttle_events_total"].clone());
+        assert_eq!(throttled, Some(json!(1)));
+        assert_eq!(other.throttle_events(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_synthetic_load() -> anyhow::Result<()> {
+        use dbexp::object_ids::TableId;
+        use mem_table::{DataConfig, Table, TableConfig};
+        use primitives::{DataType, InternalString};
+        use rocket::{
+            http::{ContentType, Header, Status},
+            local::blocking::Client,
+            serde::json::{serde_json::json, Value},
+        };
+
+        let columns = vec![
+            DataConfig::new(DataType::O64),
+            DataConfig::new(DataType::Text(12)),
+        ];
+        let name_mapping = [
+            (InternalString::new("id")?, 0),
+            (InternalString::new("status")?, 1),
+        ]
+        .into_iter()
+        .collect();
+        let table = Table::new(
+            TableId::new(),
+            TableConfig::new(&columns)?.with_primary_key([0])?,
+            Some(name_mapping),
+        )?;
+
+        let mut tables = rows::Tables::default();
+        tables.0.insert("items".to_string(), table.clone());
+
+        let figment = rocket::Config::figment().merge(("auth.admin_token", "secret"));
+        let client = Client::tracked(mount_tables(rocket::custom(figment), tables))?;
+        let post = |token: &str, body: Value| {
+            client
+                .post("/debug/tables/items/synthetic")
+                .header(ContentType::JSON)
+                .header(Header::new("Authorization", format!("Bearer {}", token)))
+                .body(body.to_string())
+                .dispatch()
+        };
+        let body = json!({
+            "rows": 500,
+            "seed": 7,
+            "columns": { "status": { "distribution": "zipf", "s": 1.1, "null_chance": 0.2 } },
+        });
+
+        assert_eq!(post("guess", body.clone()).status(), Status::Forbidden);
+        assert_eq!(table.row_count(), 0);
+
+        let res = post("secret", body);
+        assert_eq!(res.status(), Status::Ok);
+        assert_eq!(
+            res.into_json::<Value>(),
+            Some(json!({ "inserted": 500, "rejected": 0 }))
+        );
+        assert_eq!(table.row_count(), 500);
+
+        let res = post(
+            "secret",
+            json!({ "rows": 1, "columns": { "nope": { "distribution": "uniform" } } }),
+        );
+        assert_eq!(res.status(), Status::UnprocessableEntity);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_group_by_route() -> anyhow::Result<()> {
+        use dbexp::{object_ids::TableId, values::DataValue};
+        use mem_table::{DataConfig, Table, TableConfig};
+        use primitives::{DataType, InternalString};
+        use rocket::{
+            http::{ContentType, Status},
+            local::blocking::Client,
+            serde::json::{serde_json::json, Value},
+        };
+
+        let columns = vec![
+            DataConfig::new(DataType::Number),
+            DataConfig::new(DataType::Number),
+        ];
+        let name_mapping = [
+            (InternalString::new("region")?, 0),
+            (InternalString::new("amount")?, 1),
+        ]
+        .into_iter()
+        .collect();
+        let table = Table::new(TableId::new(), TableConfig::new(&columns)?, Some(name_mapping))?;
+
+        for n in 0..300 {
+            table.insert_one(vec![
+                Some(DataValue::try_from_any(DataType::Number, n % 3)?),
+                Some(DataValue::try_from_any(DataType::Number, n)?),
+            ])?;
+        }
+
+        let mut tables = rows::Tables::default();
+        tables.0.insert("sales".to_string(), table);
+
+        let client = Client::tracked(rocket_with_tables(tables))?;
+        let post = |body: Value| {
+            client
+                .post("/tables/sales/group_by")
+                .header(ContentType::JSON)
+                .body(body.to_string())
+                .dispatch()
+        };
+
+        let res = post(json!({ "group_by": "region", "aggregate": "count" }));
+        assert_eq!(res.status(), Status::Ok);
+
+        let body = res.into_json::<Value>().expect("json body");
+        assert_eq!(body["groups"], json!([[0, 100], [1, 100], [2, 100]]));
+        assert_eq!(body["metrics"]["spills"], json!(0));
+
+        // a budget smaller than one group spills before every new group
+        let res = post(json!({
+            "group_by": "region",
+            "aggregate": "sum",
+            "column": "amount",
+            "budget_bytes": 1,
+        }));
+        assert_eq!(res.status(), Status::Ok);
+
+        let body = res.into_json::<Value>().expect("json body");
+        assert_eq!(body["groups"], json!([[0, 14850], [1, 14950], [2, 15050]]));
+        assert_eq!(body["metrics"]["spills"], json!(299));
+
+        let res = post(json!({ "group_by": "region", "aggregate": "sum" }));
+        assert_eq!(res.status(), Status::UnprocessableEntity);
+
+        let res = post(json!({ "group_by": "nope", "aggregate": "count" }));
+        assert_eq!(res.status(), Status::UnprocessableEntity);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_audit_route() -> anyhow::Result<()> {
+        use hcl_schemas::AuditOp;
+        use rocket::{
+            http::{Header, Status},
+            local::blocking::Client,
+            serde::json::{serde_json::json, Value},
+        };
+
+        let audit = AuditLog::open(None)?;
+        audit.record(AuditOp::Truncate, "items", "", Some("ops"), || Ok(()))?;
+
+        let figment = rocket::Config::figment().merge(("auth.admin_token", "secret"));
+        let client = Client::tracked(mount_database(
+            rocket::custom(figment),
+            rows::Tables::default(),
+            audit,
+        ))?;
+        let get = |uri: &str| {
+            client
+                .get(uri.to_string())
+                .header(Header::new("Authorization", "Bearer secret"))
+                .dispatch()
+        };
+
+        assert_eq!(
+            client.get("/audit").dispatch().status(),
+            Status::Unauthorized
+        );
+
+        let res = get("/audit");
+        assert_eq!(res.status(), Status::Ok);
+
+        let body = res.into_json::<Value>().expect("json body");
+        assert_eq!(body.as_array().map(Vec::len), Some(2));
+        assert_eq!(body[0]["operation"], json!("truncate"));
+        assert_eq!(body[0]["principal"], json!("ops"));
+        assert_eq!(body[0]["phase"], json!("intent"));
+        assert_eq!(body[1]["phase"], json!("succeeded"));
+        assert_eq!(body[1]["intent"], json!(1));
+
+        let res = get("/audit?since=2999-01-01T00:00:00Z");
+        assert_eq!(res.into_json::<Value>(), Some(json!([])));
+
+        assert_eq!(
+            get("/audit?since=yesterday").status(),
+            Status::UnprocessableEntity
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_post_schema() -> anyhow::Result<()> {
+        use rocket::{
+            http::{ContentType, Header, Status},
+            local::blocking::Client,
+            serde::json::{serde_json::json, Value},
+        };
+
+        let figment = rocket::Config::figment().merge(("auth.admin_token", "secret"));
+        let client = Client::tracked(mount_tables(
+            rocket::custom(figment),
+            rows::Tables::default(),
+        ))?;
+        let post = |content_type: ContentType, body: &str| {
+            client
+                .post("/schema")
+                .header(content_type)
+                .header(Header::new("Authorization", "Bearer secret"))
+                .body(body)
+                .dispatch()
+        };
+
+        let hcl = r#"
+            table "users" {
+                email = unique(Email)
+                first = Text(100)
+                seed  = [{ email = "a@b.co", first = "A" }]
+            }
+        "#;
+
+        let res = post(ContentType::Plain, hcl);
+        assert_eq!(res.status(), Status::Created);
+
+        let body = res.into_json::<Value>().expect("json body");
+        assert_eq!(body[0]["name"], json!("users"));
+        assert_eq!(body[0]["columns"][0]["name"], json!("email"));
+        assert_eq!(body[0]["columns"][1]["name"], json!("first"));
+        assert_eq!(body[0]["columns"][1]["data_type"], json!({ "Text": 100 }));
+
+        let catalog = client.rocket().state::<schema::Catalog>().unwrap();
+        assert_eq!(catalog.0.read().len(), 1);
+        assert_eq!(catalog.0.read()[0].row_count(), 1);
+
+        // a table that exists already isn't replaced, and neither are the others created
+        let again = r#"
+            table "orders" {
+                total = Number
+            }
+
+            table "users" {
+                email = Email
+            }
+        "#;
+
+        let res = post(ContentType::Plain, again);
+        assert_eq!(res.status(), Status::Conflict);
+        assert_eq!(
+            res.into_json::<Value>().expect("json body"),
+            json!({ "existing": ["users"] })
+        );
+        assert_eq!(catalog.0.read().len(), 1);
+        assert_eq!(catalog.0.read()[0].row_count(), 1);
+
+        let json = json!({
+            "tables": {
+                "orders": {
+                    "id": "Number({ default = sequence() })",
+                    "total": "Number",
+                    "primary_key": ["id"]
+                }
+            }
+        });
+
+        let res = post(ContentType::JSON, &json.to_string());
+        assert_eq!(res.status(), Status::Created);
+
+        let body = res.into_json::<Value>().expect("json body");
+        assert_eq!(body[0]["name"], json!("orders"));
+        assert_eq!(body[0]["columns"].as_array().map(Vec::len), Some(2));
+        assert_eq!(catalog.0.read().len(), 2);
+
+        let res = post(ContentType::Plain, r#"table "notes" { body = Txt(10) }"#);
+        assert_eq!(res.status(), Status::UnprocessableEntity);
+
+        let body = res.into_json::<Value>().expect("json body");
+        assert_eq!(body["blocks"][0]["table"], json!("notes"));
+        assert_eq!(body["blocks"][0]["attribute"], json!("body"));
+
+        // a seed row that isn't inserted fails the table rather than leaving it short, since a
+        // top level domain needs two letters
+        let short_seed = r#"
+            table "people" {
+                email = Email
+                seed  = [{ email = "a@b.c" }]
+            }
+        "#;
+
+        let res = post(ContentType::Plain, short_seed);
+        assert_eq!(res.status(), Status::UnprocessableEntity);
+        assert_eq!(catalog.0.read().len(), 2);
+
+        let res = client
+            .post("/schema")
+            .header(ContentType::Plain)
+            .body(hcl)
+            .dispatch();
+        assert_eq!(res.status(), Status::Unauthorized);
+
+        Ok(())
+    }
 }
@@ -0,0 +1,245 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use anyhow::Result;
+use dbexp::values::DataValue;
+use hcl_queries::{prepare, BindError, Param, Predicate, PrepareError, PreparedQuery};
+use indexmap::IndexMap;
+use mem_table::{CancellationToken, Table, TableError};
+use primitives::DataType;
+use rocket::{
+    figment::Figment,
+    http::Status,
+    serde::json::{Json, Value},
+    tokio::task::spawn_blocking,
+};
+use serde::Deserialize;
+
+use crate::error::{table_error_status, TooManyRequests};
+
+/// Read from the `query` section of the Rocket config.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct QueryConfig {
+    /// How long a request may spend reading a table before it's stopped with
+    /// `503 Service Unavailable`.
+    pub timeout_ms: u64,
+    /// How many prepared queries are kept, see `PreparedCache`.
+    pub prepared_cache_size: usize,
+    /// How much memory a group-by may hold before spilling to disk, see `Table::group_by`. A
+    /// request can ask for another budget, and without one groups are never spilled.
+    pub group_by_budget_bytes: Option<usize>,
+}
+
+impl Default for QueryConfig {
+    fn default() -> Self {
+        Self {
+            timeout_ms: 30_000,
+            prepared_cache_size: 128,
+            group_by_budget_bytes: Some(64 << 20),
+        }
+    }
+}
+
+impl QueryConfig {
+    pub fn from_figment(figment: &Figment) -> Self {
+        figment.extract_inner("query").unwrap_or_default()
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+}
+
+/// Cancels its token when dropped, which is what happens to the future of a request whose client
+/// went away before it was answered.
+struct CancelOnDrop(CancellationToken);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.cancel();
+    }
+}
+
+/// Runs `f` off the executor with `table` stopped by the configured timeout, or as soon as the
+/// request is dropped. Whatever `f` read is thrown away if it's stopped.
+pub async fn run_query<F, R>(config: &QueryConfig, table: &Table, f: F) -> Result<R, QueryError>
+where
+    F: FnOnce(&Table) -> Result<R, TableError> + Send + 'static,
+    R: Send + 'static,
+{
+    let token = CancellationToken::with_timeout(config.timeout());
+    // cancelling the token once the query is done anyway changes nothing
+    let _disconnected = CancelOnDrop(token.clone());
+    let table = table.with_cancellation(token);
+
+    let res = spawn_blocking(move || f(&table))
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(res?)
+}
+
+/// The queries prepared by the query route, keyed by table and query text, so a query sent again
+/// with other parameters isn't parsed again. Only the `prepared_cache_size` queries used last are
+/// kept.
+#[derive(Debug)]
+pub struct PreparedCache {
+    capacity: usize,
+    /// In the order they were last used, oldest first.
+    entries: Mutex<IndexMap<(String, String), Arc<PreparedQuery>>>,
+    compiles: AtomicUsize,
+}
+
+impl PreparedCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Mutex::new(IndexMap::new()),
+            compiles: AtomicUsize::new(0),
+        }
+    }
+
+    /// How many times a query had to be prepared because it wasn't cached.
+    pub fn compiles(&self) -> usize {
+        self.compiles.load(Ordering::Relaxed)
+    }
+
+    pub fn get_or_prepare(
+        &self,
+        name: &str,
+        table: &Table,
+        text: &str,
+    ) -> Result<Arc<PreparedQuery>, PrepareError> {
+        let key = (name.to_string(), text.to_string());
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+
+        if let Some(query) = entries.shift_remove(&key) {
+            entries.insert(key, query.clone());
+            return Ok(query);
+        }
+
+        let query = Arc::new(prepare(table, text)?);
+        self.compiles.fetch_add(1, Ordering::Relaxed);
+        entries.insert(key, query.clone());
+
+        while entries.len() > self.capacity {
+            entries.shift_remove_index(0);
+        }
+
+        Ok(query)
+    }
+}
+
+/// The body of the query route: a query and the values of its parameters.
+#[derive(Debug, Deserialize)]
+pub struct QueryRequest {
+    pub query: String,
+    #[serde(default)]
+    pub params: IndexMap<String, Value>,
+}
+
+/// Sent when a query can't be prepared or its parameters can't be bound, saying why.
+#[derive(Responder)]
+pub enum QueryError {
+    #[response(status = 422)]
+    Prepare(Json<PrepareError>),
+    #[response(status = 422)]
+    Bind(Json<BindError>),
+    Throttled(TooManyRequests),
+    Status(Status),
+}
+
+impl From<PrepareError> for QueryError {
+    fn from(err: PrepareError) -> Self {
+        Self::Prepare(Json(err))
+    }
+}
+
+impl From<BindError> for QueryError {
+    fn from(err: BindError) -> Self {
+        Self::Bind(Json(err))
+    }
+}
+
+impl From<TableError> for QueryError {
+    fn from(err: TableError) -> Self {
+        match err {
+            TableError::Throttled(throttled) => Self::Throttled(throttled.into()),
+            err => Self::Status(table_error_status(&err)),
+        }
+    }
+}
+
+impl From<Status> for QueryError {
+    fn from(status: Status) -> Self {
+        Self::Status(status)
+    }
+}
+
+/// Converts the JSON value of a parameter. Values of the wrong kind are converted to the type
+/// they look like, so binding them reports the mismatch, and text keeps its own length, so
+/// binding it reports text too long for the column.
+pub fn param_from_json(param: &Param, value: Value) -> Result<DataValue, BindError> {
+    let expected = param.column.data_type;
+    let value = match value {
+        Value::Bool(val) => Ok(DataValue::Bool(val)),
+        Value::Number(val) => {
+            let ty = match expected {
+                DataType::Timestamp => expected,
+                _ => DataType::Number,
+            };
+
+            if let Some(val) = val.as_u64() {
+                DataValue::try_from_any(ty, val)
+            } else if let Some(val) = val.as_i64() {
+                DataValue::try_from_any(ty, val)
+            } else {
+                DataValue::try_from_any(ty, val.as_f64().unwrap_or(f64::NAN))
+            }
+        }
+        Value::String(val) => {
+            let ty = match expected {
+                DataType::Timestamp => expected,
+                _ => DataType::Text(val.len().max(1) as u32),
+            };
+
+            DataValue::try_from_any(ty, val)
+        }
+        other => Err(anyhow::anyhow!("unsupported value: {}", other)),
+    };
+
+    value.map_err(|err| BindError::Unparsable {
+        param: param.name.clone(),
+        expected: expected.into(),
+        message: err.to_string(),
+    })
+}
+
+/// Converts `params` and binds them to `query`. A name the query has no parameter for is an error
+/// before anything is converted.
+pub fn bind_json(
+    query: &PreparedQuery,
+    params: &IndexMap<String, Value>,
+) -> Result<Predicate, BindError> {
+    let mut values = Vec::with_capacity(params.len());
+
+    for (name, value) in params {
+        let param = query
+            .params()
+            .iter()
+            .find(|param| param.name == *name)
+            .ok_or_else(|| BindError::Extra {
+                param: name.clone(),
+            })?;
+
+        values.push((name.as_str(), param_from_json(param, value.clone())?));
+    }
+
+    query.bind(&values)
+}
@@ -0,0 +1,87 @@
+use std::{sync::Arc, time::Duration};
+
+use indexmap::IndexMap;
+use mem_table::{LimiterChain, RateLimit, RateLimiter, TokenBucket};
+use rocket::figment::Figment;
+use serde::Deserialize;
+
+use crate::rows::Tables;
+
+/// A budget of rows read and written a second. Reads or writes without one aren't limited.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(default)]
+pub struct Budget {
+    pub reads_per_sec: Option<f64>,
+    pub writes_per_sec: Option<f64>,
+    /// How many rows may be read, or written, at once after a quiet spell. A second's worth if
+    /// left out.
+    pub burst: Option<f64>,
+}
+
+impl Budget {
+    fn is_empty(&self) -> bool {
+        self.reads_per_sec.is_none() && self.writes_per_sec.is_none()
+    }
+
+    fn limit(&self, per_sec: Option<f64>) -> Option<RateLimit> {
+        let limit = RateLimit::per_sec(per_sec?);
+
+        Some(match self.burst {
+            Some(burst) => limit.with_burst(burst),
+            None => limit,
+        })
+    }
+
+    fn limiter(&self, max_wait: Duration) -> Option<Arc<dyn RateLimiter>> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let bucket = TokenBucket::new(
+            self.limit(self.reads_per_sec),
+            self.limit(self.writes_per_sec),
+        );
+
+        Some(Arc::new(bucket.with_max_wait(max_wait)))
+    }
+}
+
+/// Read from the `rate_limit` section of the Rocket config. Nothing is limited by default.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    /// Shared by every table served.
+    pub database: Budget,
+    /// Each table's own, by name, on top of the database's.
+    pub tables: IndexMap<String, Budget>,
+    /// How long a read or write over budget waits for it, before it's turned away with
+    /// `429 Too Many Requests`. Zero turns it away right away.
+    pub max_wait_ms: u64,
+}
+
+impl RateLimitConfig {
+    pub fn from_figment(figment: &Figment) -> Self {
+        figment.extract_inner("rate_limit").unwrap_or_default()
+    }
+
+    /// Gives every table in `tables` the limiters it's configured with, replacing the one it had.
+    /// Tables nothing is configured for are left as they are.
+    pub fn apply(&self, tables: &Tables) {
+        let max_wait = Duration::from_millis(self.max_wait_ms);
+        let database = self.database.limiter(max_wait);
+
+        for (name, table) in tables.0.iter() {
+            let own = self
+                .tables
+                .get(name)
+                .and_then(|budget| budget.limiter(max_wait));
+            let limiters = own.into_iter().chain(database.clone()).collect::<Vec<_>>();
+
+            match limiters.len() {
+                0 => {}
+                1 => table.set_rate_limiter(limiters.into_iter().next()),
+                _ => table.set_rate_limiter(Some(Arc::new(LimiterChain(limiters)))),
+            }
+        }
+    }
+}
@@ -0,0 +1,772 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use dbexp::values::{
+    plain::{decode_base64, PlainValue, TypedValue},
+    DataValue,
+};
+use hcl_queries::execute;
+use hcl_schemas::{AuditEntry, AuditLog};
+use indexmap::IndexMap;
+use mem_table::{
+    datagen::{ColumnSpec, DataSpec, Distribution, LoadReport},
+    Aggregate, ColumnCapacityAdvice, DataConfig, GroupByMetrics, IntegrityReport, LogicalType,
+    Table, TableError, TableFragReport, TableScrubReport, UpdateOutcome, ValueError,
+    ValueErrorReason,
+};
+use primitives::{CastKind, DataType, ExpectedType, Timestamp, O64};
+use rocket::{
+    http::{Header, Status},
+    request::{FromRequest, Outcome, Request},
+    response::status::Created,
+    serde::json::{serde_json, Json, Value},
+    State,
+};
+use serde::{de::DeserializeSeed, Deserialize, Serialize};
+
+use crate::{
+    async_table::{AsyncTables, WriteError},
+    auth::{AdminScope, WriteScope},
+    error::table_error_status,
+    query::{bind_json, run_query, PreparedCache, QueryConfig, QueryError, QueryRequest},
+};
+
+/// The tables served by the API, keyed by name.
+#[derive(Debug, Clone, Default)]
+pub struct Tables(pub IndexMap<String, Table>);
+
+impl Tables {
+    fn get(&self, name: &str) -> Result<&Table, Status> {
+        self.0.get(name).ok_or(Status::NotFound)
+    }
+}
+
+#[derive(Responder)]
+pub struct Versioned {
+    inner: Json<Vec<Value>>,
+    etag: Header<'static>,
+}
+
+impl Versioned {
+    fn new(values: Vec<Value>, gen: O64) -> Self {
+        Self {
+            inner: Json(values),
+            etag: Header::new("ETag", format!("\"{}\"", gen)),
+        }
+    }
+}
+
+#[derive(Responder)]
+pub struct Counted<R> {
+    inner: R,
+    total: Header<'static>,
+}
+
+impl<R> Counted<R> {
+    fn new(inner: R, count: usize) -> Self {
+        Self {
+            inner,
+            total: Header::new("X-Total-Count", count.to_string()),
+        }
+    }
+}
+
+#[derive(Responder)]
+pub enum RowList {
+    CountOnly(()),
+    Seqs(Json<Vec<u64>>),
+}
+
+/// The version token a client read a row with. Updates without one are rejected with
+/// `428 Precondition Required`.
+pub struct IfMatch(O64);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IfMatch {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let Some(value) = req.headers().get_one("If-Match") else {
+            return Outcome::Error((Status::PreconditionRequired, ()));
+        };
+
+        let token = value.trim().trim_matches('"');
+
+        match token.parse() {
+            Ok(gen) => Outcome::Success(IfMatch(gen)),
+            // a token we never handed out can't match the current version
+            Err(_) => Outcome::Error((Status::PreconditionFailed, ())),
+        }
+    }
+}
+
+/// The `Idempotency-Key` a client sent along with an insert, as the op id it's logged under. Any
+/// text will do as a key, since it's hashed.
+pub struct IdempotencyKey(O64);
+
+impl IdempotencyKey {
+    /// FNV-1a, which unlike the std hasher is the same across builds. Op ids are persisted.
+    fn op_id(key: &str) -> O64 {
+        let hash = key.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+        });
+
+        O64::from_uint(hash.clamp(1, u64::MAX - 1)).unwrap()
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IdempotencyKey {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match req.headers().get_one("Idempotency-Key") {
+            Some(key) => Outcome::Success(IdempotencyKey(Self::op_id(key.trim()))),
+            None => Outcome::Forward(Status::Ok),
+        }
+    }
+}
+
+/// A value in the plain form, see `dbexp::values::plain`, with `null` for a missing one.
+fn value_to_json(value: Option<&DataValue>) -> Value {
+    // the plain form is made of what JSON has, so this doesn't fail
+    serde_json::to_value(PlainValue::new(value)).unwrap_or(Value::Null)
+}
+
+fn row_to_json(values: &[Option<DataValue>]) -> Vec<Value> {
+    values
+        .iter()
+        .map(|value| value_to_json(value.as_ref()))
+        .collect()
+}
+
+/// Reads a value sent in the plain form as a value of the column. Text, and bytes once decoded,
+/// are built by the column, so its normalization, overflow policy and logical type apply to them.
+fn value_from_json(config: &DataConfig, value: Value) -> Result<Option<DataValue>, ValueError> {
+    let parse_failed =
+        |message: String| config.value_error(ValueErrorReason::ParseFailed { message });
+
+    match (config.data_type.into_inner(), value) {
+        (_, Value::Null) => Ok(None),
+        (DataType::Text(_), Value::String(text)) => config.try_new_value(text).map(Some),
+        (DataType::Bytes(_), Value::String(text)) => {
+            let bytes = decode_base64(&text).map_err(parse_failed)?;
+
+            config.try_new_value(bytes).map(Some)
+        }
+        (_, value) => TypedValue(config.data_type)
+            .deserialize(value)
+            .map_err(|e| parse_failed(e.to_string())),
+    }
+}
+
+#[derive(Serialize)]
+pub struct ColumnSchema {
+    name: String,
+    data_type: ExpectedType,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    logical_type: Option<LogicalType>,
+    /// Only sent for columns with a logical type.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    validate: Option<bool>,
+}
+
+#[derive(Serialize)]
+pub struct TableSchema {
+    name: String,
+    columns: Vec<ColumnSchema>,
+}
+
+impl TableSchema {
+    pub(crate) fn new(name: &str, table: &Table) -> Self {
+        let schema = table.schema();
+        let columns = table.config().columns;
+
+        Self {
+            name: name.to_string(),
+            columns: (0..columns.len())
+                .filter_map(|column| {
+                    let config = columns.get(column)?;
+
+                    Some(ColumnSchema {
+                        name: schema.column_name(column),
+                        data_type: config.data_type,
+                        logical_type: config.logical_type,
+                        validate: config.logical_type.map(|_| config.check_logical_type),
+                    })
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Describes every table served, with its columns in order.
+#[get("/tables")]
+pub fn get_tables(tables: &State<Tables>) -> Json<Vec<TableSchema>> {
+    Json(
+        tables
+            .0
+            .iter()
+            .map(|(name, table)| TableSchema::new(name, table))
+            .collect(),
+    )
+}
+
+#[head("/tables/<table>/rows")]
+pub fn head_rows(tables: &State<Tables>, table: &str) -> Result<Counted<()>, Status> {
+    Ok(Counted::new((), tables.get(table)?.row_count()))
+}
+
+/// Lists the sequence numbers of every row, which are the ids the row routes take. With
+/// `count_only=true` only the `X-Total-Count` header is sent, without scanning the table. A scan
+/// running past the query timeout is stopped with `503 Service Unavailable`, and one turned away
+/// by the table's rate limiter with `429 Too Many Requests`.
+#[get("/tables/<table>/rows?<count_only>")]
+pub async fn get_rows(
+    tables: &State<Tables>,
+    query: &State<QueryConfig>,
+    table: &str,
+    count_only: Option<bool>,
+) -> Result<Counted<RowList>, QueryError> {
+    let table = tables.get(table)?;
+
+    if count_only.unwrap_or(false) {
+        return Ok(Counted::new(RowList::CountOnly(()), table.row_count()));
+    }
+
+    let seqs = run_query(query, table, |table| {
+        table.throttle_read(table.row_count())?;
+
+        let seqs = table
+            .scan_since(0)
+            .map(|(seq, _)| table.cancellation().check().map(|()| seq))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(seqs)
+    })
+    .await?;
+    let count = seqs.len();
+
+    Ok(Counted::new(RowList::Seqs(Json(seqs)), count))
+}
+
+/// Lists the sequence numbers of the rows matching a query, sent as `{"query": ..., "params":
+/// {...}}`. The query is prepared once and cached, see `PreparedCache`, and its parameters are
+/// bound on every request, so user input never ends up in the text of a query. A query that
+/// can't be prepared or bound is `422 Unprocessable Entity`, saying why. Rows are read a block at
+/// a time and listed in the order they're stored, see `hcl_queries::execute`.
+#[post("/tables/<table>/query", format = "json", data = "<body>")]
+pub async fn post_query(
+    tables: &State<Tables>,
+    query: &State<QueryConfig>,
+    prepared: &State<PreparedCache>,
+    table: &str,
+    body: Json<QueryRequest>,
+) -> Result<Counted<Json<Vec<u64>>>, QueryError> {
+    let name = table;
+    let table = tables.get(table)?;
+    let prepared = prepared.get_or_prepare(name, table, &body.query)?;
+    let predicate = bind_json(&prepared, &body.params)?;
+
+    let seqs = run_query(query, table, move |table| {
+        execute(table.clone(), predicate)
+            .map(|row| Ok(row?.seq()))
+            .collect::<Result<Vec<_>, TableError>>()
+    })
+    .await?;
+    let count = seqs.len();
+
+    Ok(Counted::new(Json(seqs), count))
+}
+
+/// What `post_group_by` computes for each group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GroupAggregate {
+    Count,
+    Sum,
+    Avg,
+    /// Counted exactly, see `Aggregate::CountDistinct`.
+    CountDistinct,
+}
+
+/// The body of `post_group_by`. Every aggregate but `count` needs a `column`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct GroupByRequest {
+    group_by: String,
+    aggregate: GroupAggregate,
+    #[serde(default)]
+    column: Option<String>,
+    /// Replaces the configured `group_by_budget_bytes` for this request.
+    #[serde(default)]
+    budget_bytes: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub struct GroupByResponse {
+    /// `[group, aggregate]` pairs in order of group.
+    groups: Vec<(Value, Value)>,
+    metrics: GroupByMetrics,
+}
+
+/// Groups the rows of a table by a column and aggregates every group, see `Table::group_by`,
+/// answering with the groups and how much the query spilled to disk. A column the table doesn't
+/// have, or an aggregate left without one, is `422 Unprocessable Entity`.
+#[post("/tables/<table>/group_by", format = "json", data = "<body>")]
+pub async fn post_group_by(
+    tables: &State<Tables>,
+    query: &State<QueryConfig>,
+    table: &str,
+    body: Json<GroupByRequest>,
+) -> Result<Json<GroupByResponse>, QueryError> {
+    let table = tables.get(table)?;
+    let column = |name: &str| table.column_index(name).ok_or(Status::UnprocessableEntity);
+    let group_col = column(&body.group_by)?;
+
+    let agg = match (body.aggregate, body.column.as_deref()) {
+        (GroupAggregate::Count, _) => Aggregate::Count,
+        (_, None) => return Err(Status::UnprocessableEntity.into()),
+        (GroupAggregate::Sum, Some(name)) => Aggregate::Sum(column(name)?),
+        (GroupAggregate::Avg, Some(name)) => Aggregate::Avg(column(name)?),
+        (GroupAggregate::CountDistinct, Some(name)) => Aggregate::CountDistinct {
+            column: column(name)?,
+            exact: true,
+        },
+    };
+
+    let budget = body.budget_bytes.or(query.group_by_budget_bytes);
+    let grouped = run_query(query, table, move |table| {
+        table.group_by(group_col, agg, budget)
+    })
+    .await?;
+
+    let groups = grouped
+        .groups
+        .into_iter()
+        .map(|(group, value)| {
+            (
+                value_to_json(group.as_ref()),
+                value_to_json(value.map(DataValue::Number).as_ref()),
+            )
+        })
+        .collect();
+
+    Ok(Json(GroupByResponse {
+        groups,
+        metrics: grouped.metrics,
+    }))
+}
+
+#[get("/tables/<table>/rows/<seq>")]
+pub fn get_row(tables: &State<Tables>, table: &str, seq: u64) -> Result<Versioned, Status> {
+    let table = tables.get(table)?;
+    let handle = table.get_by_seq(seq).ok_or(Status::NotFound)?;
+
+    let (values, gen) = table
+        .get_versioned(&handle)
+        .map_err(|err| table_error_status(&err))?;
+
+    Ok(Versioned::new(row_to_json(&values), gen))
+}
+
+#[derive(Serialize)]
+pub struct TableMetrics {
+    row_count: usize,
+    fragmentation: TableFragReport,
+    /// Flushes and writes of the table's stores that failed since it was opened.
+    flush_failures_total: u64,
+    /// Reads and writes the table's rate limiter held or turned away.
+    throttle_events_total: u64,
+    /// How much of their capacity the table's text and bytes columns use.
+    capacity: Vec<ColumnCapacityAdvice>,
+}
+
+#[get("/tables/<table>/metrics")]
+pub fn get_metrics(tables: &State<Tables>, table: &str) -> Result<Json<TableMetrics>, Status> {
+    let table = tables.get(table)?;
+
+    Ok(Json(TableMetrics {
+        row_count: table.row_count(),
+        fragmentation: table
+            .fragmentation_report()
+            .map_err(|err| table_error_status(&err))?,
+        flush_failures_total: table.flush_failures(),
+        throttle_events_total: table.throttle_events(),
+        capacity: table
+            .capacity_report()
+            .map_err(|err| table_error_status(&err))?,
+    }))
+}
+
+#[derive(Serialize)]
+pub struct Health {
+    status: &'static str,
+    /// The tables whose last flush failed, so their changes may not be on disk.
+    failed_tables: Vec<String>,
+}
+
+/// Unhealthy, with 503, while the last flush of any table failed.
+#[get("/health")]
+pub fn get_health(tables: &State<Tables>) -> (Status, Json<Health>) {
+    let failed_tables = tables
+        .0
+        .iter()
+        .filter(|(_, table)| table.has_failed_flush())
+        .map(|(name, _)| name.clone())
+        .collect::<Vec<_>>();
+
+    if failed_tables.is_empty() {
+        (
+            Status::Ok,
+            Json(Health {
+                status: "ok",
+                failed_tables,
+            }),
+        )
+    } else {
+        (
+            Status::ServiceUnavailable,
+            Json(Health {
+                status: "unhealthy",
+                failed_tables,
+            }),
+        )
+    }
+}
+
+/// The entries of the audit log appended at or after `since`, an RFC 3339 time, or every entry
+/// without one. A `since` that isn't one is `422 Unprocessable Entity`. Only for the admin scope.
+#[get("/audit?<since>")]
+pub fn get_audit(
+    _admin: AdminScope,
+    audit: &State<AuditLog>,
+    since: Option<&str>,
+) -> Result<Json<Vec<AuditEntry>>, Status> {
+    let since = match since {
+        Some(since) => Timestamp::try_from_str(since).map_err(|_| Status::UnprocessableEntity)?,
+        None => Timestamp::default(),
+    };
+
+    audit
+        .since(since)
+        .map(Json)
+        .map_err(|_| Status::InternalServerError)
+}
+
+/// Runs `Table::check_integrity`, which holds off writes to the table while it runs. Only mounted in
+/// debug builds.
+#[get("/tables/<table>/integrity")]
+pub fn get_integrity(tables: &State<Tables>, table: &str) -> Result<Json<IntegrityReport>, Status> {
+    let table = tables.get(table)?;
+
+    table
+        .check_integrity()
+        .map(Json)
+        .map_err(|err| table_error_status(&err))
+}
+
+/// Runs `Table::scrub`, which reads every flushed block of the table back from disk to check its
+/// checksum. Only mounted in debug builds.
+#[get("/tables/<table>/scrub")]
+pub fn get_scrub(tables: &State<Tables>, table: &str) -> Result<Json<TableScrubReport>, Status> {
+    let table = tables.get(table)?;
+
+    table
+        .scrub()
+        .map(Json)
+        .map_err(|err| table_error_status(&err))
+}
+
+/// Everything about one record, see `Table::debug_record`, for support engineers to look into a
+/// row that misbehaves. `id` is the record's seq or its record id. Only mounted in debug builds,
+/// and only for the admin scope.
+#[get("/tables/<table>/records/<id>")]
+pub fn get_record_debug(
+    _admin: AdminScope,
+    tables: &State<Tables>,
+    table: &str,
+    id: &str,
+) -> Result<Json<Value>, Status> {
+    let table = tables.get(table)?;
+
+    table
+        .debug_record(id)
+        .map(|debug| Json(debug.to_json()))
+        .map_err(|err| table_error_status(&err))
+}
+
+/// How a column of a synthetic load is drawn, see `datagen::Distribution`.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(tag = "distribution", rename_all = "snake_case")]
+pub enum SyntheticDistribution {
+    Uniform,
+    Zipf { s: f64 },
+    Sequential,
+    RecentTimestamps { window_secs: u64 },
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct SyntheticColumn {
+    #[serde(flatten)]
+    distribution: SyntheticDistribution,
+    null_chance: Option<f64>,
+}
+
+impl From<SyntheticColumn> for ColumnSpec {
+    fn from(column: SyntheticColumn) -> Self {
+        let distribution = match column.distribution {
+            SyntheticDistribution::Uniform => Distribution::Uniform,
+            SyntheticDistribution::Zipf { s } => Distribution::Zipf { s },
+            SyntheticDistribution::Sequential => Distribution::Sequential,
+            SyntheticDistribution::RecentTimestamps { window_secs } => {
+                Distribution::RecentTimestamps {
+                    window: Duration::from_secs(window_secs),
+                }
+            }
+        };
+
+        Self {
+            distribution,
+            null_chance: column.null_chance,
+        }
+    }
+}
+
+/// The body of `post_synthetic`. Columns are keyed by name, and those left out are drawn as
+/// `datagen::DataSpec` leaves them.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct SyntheticLoad {
+    rows: usize,
+    seed: u64,
+    null_chance: f64,
+    sequence_start: Option<u64>,
+    columns: IndexMap<String, SyntheticColumn>,
+}
+
+/// Inserts generated rows into a table for a load test, see `Table::load_synthetic`, and answers
+/// with how many were inserted. A column name the table doesn't have is
+/// `422 Unprocessable Entity`. Only mounted in debug builds, and only for the admin scope.
+#[post("/tables/<table>/synthetic", format = "json", data = "<body>")]
+pub async fn post_synthetic(
+    _admin: AdminScope,
+    writers: &State<AsyncTables>,
+    table: &str,
+    body: Json<SyntheticLoad>,
+) -> Result<Json<LoadReport>, WriteError> {
+    let writer = writers.get(table)?;
+    let body = body.into_inner();
+    let mut spec = DataSpec::new(body.seed).with_nulls(body.null_chance);
+
+    if let Some(start) = body.sequence_start {
+        spec = spec.with_sequence_start(start);
+    }
+
+    for (name, column) in body.columns {
+        let idx = writer
+            .table()
+            .column_index(&name)
+            .ok_or(Status::UnprocessableEntity)?;
+
+        spec = spec.with_column(idx, column.into());
+    }
+
+    let rows = body.rows;
+    let report = writer
+        .run(move |table| table.load_synthetic(rows, &spec))
+        .await?;
+
+    Ok(Json(report))
+}
+
+#[derive(Serialize)]
+pub struct Cast {
+    from: DataType,
+    to: DataType,
+    kind: CastKind,
+}
+
+/// Which values can be cast between which types, see `DataType::can_cast_to`. Only mounted in
+/// debug builds.
+#[get("/casts")]
+pub fn get_casts() -> Json<Vec<Cast>> {
+    Json(
+        DataType::cast_matrix()
+            .into_iter()
+            .map(|(from, to, kind)| Cast { from, to, kind })
+            .collect(),
+    )
+}
+
+/// Converts a row sent as a JSON array, padding left out trailing columns with `null`.
+fn row_from_json(table: &Table, body: Vec<Value>) -> Result<Vec<Option<DataValue>>, WriteError> {
+    let columns = table.config().columns;
+
+    if body.len() > columns.len() {
+        return Err(Status::UnprocessableEntity.into());
+    }
+
+    let mut values = Vec::with_capacity(columns.len());
+
+    for column in 0..columns.len() {
+        let config = columns.get(column).ok_or(Status::UnprocessableEntity)?;
+        let value = body.get(column).cloned().unwrap_or(Value::Null);
+
+        values.push(value_from_json(config, value)?);
+    }
+
+    Ok(values)
+}
+
+/// Inserts a row, answering with the row as it was stored and its location. Writes go through
+/// the table's write queue, and are turned away with `503 Service Unavailable` and a `Retry-After`
+/// header when it's full. Sent again with the same `Idempotency-Key` header, the insert answers
+/// with the row it stored the first time instead of storing another, see
+/// `Table::insert_idempotent`. Only for the write scope.
+#[post("/tables/<table>/rows", format = "json", data = "<body>")]
+pub async fn post_row(
+    _write: WriteScope,
+    writers: &State<AsyncTables>,
+    table: &str,
+    idempotency_key: Option<IdempotencyKey>,
+    body: Json<Vec<Value>>,
+) -> Result<Created<Json<Vec<Value>>>, WriteError> {
+    let writer = writers.get(table)?;
+    let values = row_from_json(writer.table(), body.into_inner())?;
+
+    let (seq, inserted) = writer
+        .run(move |table| {
+            let inserted = match idempotency_key {
+                Some(IdempotencyKey(op_id)) => table.insert_idempotent(op_id, values)?,
+                None => table.insert_one(values)?,
+            };
+
+            Ok((table.seq_of(&inserted.handle)?, inserted))
+        })
+        .await?;
+
+    Ok(Created::new(format!("/tables/{}/rows/{}", table, seq))
+        .body(Json(row_to_json(&inserted.values))))
+}
+
+/// Replaces a row, answering with the row as it's stored now. The `If-Match` header has to carry
+/// the row's current ETag, and a row written by someone else in the meantime is
+/// `412 Precondition Failed`. Only for the write scope.
+#[put("/tables/<table>/rows/<seq>", format = "json", data = "<body>")]
+pub async fn put_row(
+    _write: WriteScope,
+    writers: &State<AsyncTables>,
+    table: &str,
+    seq: u64,
+    if_match: IfMatch,
+    body: Json<Vec<Value>>,
+) -> Result<Versioned, WriteError> {
+    let writer = writers.get(table)?;
+    let handle = writer.table().get_by_seq(seq).ok_or(Status::NotFound)?;
+
+    // columns left out of the body are cleared, same as sending `null`
+    let values = row_from_json(writer.table(), body.into_inner())?;
+
+    // the row is read back since the table normalizes what it stores
+    let updated = writer
+        .run(move |table| {
+            let outcome = table.update_if(&handle, if_match.0, values)?;
+
+            match outcome {
+                UpdateOutcome::Updated(_) => table.get_versioned(&handle).map(Some),
+                UpdateOutcome::Conflict(_) => Ok(None),
+            }
+        })
+        .await?;
+
+    match updated {
+        Some((values, gen)) => Ok(Versioned::new(row_to_json(&values), gen)),
+        None => Err(Status::PreconditionFailed.into()),
+    }
+}
+
+/// Deletes a row, answering with the row as it was. `id` is the row's seq or its record id, and
+/// one that isn't there, or no longer is, is `404 Not Found`. Only for the write scope.
+#[delete("/tables/<table>/rows/<id>")]
+pub async fn delete_row(
+    _write: WriteScope,
+    writers: &State<AsyncTables>,
+    table: &str,
+    id: &str,
+) -> Result<Json<Vec<Value>>, WriteError> {
+    let writer = writers.get(table)?;
+    let handle = writer.table().find_row(id).ok_or(Status::NotFound)?;
+
+    let values = writer
+        .run(move |table| {
+            let (values, _) = table.get_versioned(&handle)?;
+
+            match table.delete(handle)? {
+                true => Ok(values),
+                false => Err(TableError::not_found("row")),
+            }
+        })
+        .await?;
+
+    Ok(Json(row_to_json(&values)))
+}
+
+/// Converts the columns of a partial row sent as a JSON object keyed by column name. A name the
+/// table doesn't have is `422 Unprocessable Entity`.
+fn changes_from_json(
+    table: &Table,
+    body: IndexMap<String, Value>,
+) -> Result<Vec<(usize, Option<DataValue>)>, WriteError> {
+    let columns = table.config().columns;
+    let mut changes = Vec::with_capacity(body.len());
+
+    for (name, value) in body {
+        let column = table
+            .column_index(&name)
+            .ok_or(Status::UnprocessableEntity)?;
+        let config = columns.get(column).ok_or(Status::UnprocessableEntity)?;
+
+        changes.push((column, value_from_json(config, value)?));
+    }
+
+    Ok(changes)
+}
+
+/// Changes the columns named in the body, a JSON object, and answers with the row as it's stored
+/// now. `null` clears a column, and the columns left out keep their values. Every value is
+/// converted before anything is written and the row is written all at once, so a value that
+/// doesn't convert leaves the row as it was. A row written by someone else in the meantime is
+/// `409 Conflict`. Only for the write scope.
+#[patch("/tables/<table>/rows/<id>", format = "json", data = "<body>")]
+pub async fn patch_row(
+    _write: WriteScope,
+    writers: &State<AsyncTables>,
+    table: &str,
+    id: &str,
+    body: Json<IndexMap<String, Value>>,
+) -> Result<Versioned, WriteError> {
+    let writer = writers.get(table)?;
+    let handle = writer.table().find_row(id).ok_or(Status::NotFound)?;
+    let changes = changes_from_json(writer.table(), body.into_inner())?;
+
+    // the row is read back since the table normalizes what it stores
+    let updated = writer
+        .run(move |table| {
+            let (mut values, gen) = table.get_versioned(&handle)?;
+
+            for (column, value) in changes {
+                values[column] = value;
+            }
+
+            match table.update_if(&handle, gen, values)? {
+                UpdateOutcome::Updated(_) => table.get_versioned(&handle).map(Some),
+                UpdateOutcome::Conflict(_) => Ok(None),
+            }
+        })
+        .await?;
+
+    match updated {
+        Some((values, gen)) => Ok(Versioned::new(row_to_json(&values), gen)),
+        None => Err(Status::Conflict.into()),
+    }
+}
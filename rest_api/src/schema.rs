@@ -0,0 +1,201 @@
+use dbexp::object_ids::TableId;
+use hcl_schemas::{parse_hcl, BlockError, SchemaError};
+use indexmap::IndexMap;
+use mem_table::Table;
+use primitives::{shared_object::SharedObject, InternalString};
+use rocket::{
+    response::status::Created,
+    serde::json::{serde_json, Json, Value},
+    State,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth::AdminScope,
+    rows::{TableSchema, Tables},
+};
+
+/// The tables created through `POST /schema`, keyed by name.
+#[derive(Default)]
+pub struct Catalog(pub SharedObject<IndexMap<InternalString, Table>>);
+
+/// A schema given as JSON rather than HCL, with the attributes of each table by table name. A
+/// column is a string holding its type as it's written in HCL, as in `"Text(100, { trim = true })"`,
+/// and the other attributes, `primary_key`, `validate` and `seed`, are their values.
+#[derive(Deserialize)]
+pub struct SchemaJson {
+    tables: IndexMap<String, IndexMap<String, Value>>,
+}
+
+#[derive(Serialize)]
+pub struct InvalidSchema {
+    message: String,
+    /// The table blocks that didn't parse, when it was them.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    blocks: Vec<BlockError>,
+}
+
+#[derive(Serialize)]
+pub struct SchemaConflict {
+    /// The tables of the schema that already exist, or that it defines more than once.
+    existing: Vec<String>,
+}
+
+#[derive(Responder)]
+pub enum SchemaUploadError {
+    #[response(status = 422)]
+    Invalid(Json<InvalidSchema>),
+    #[response(status = 409)]
+    Conflict(Json<SchemaConflict>),
+}
+
+impl SchemaUploadError {
+    fn invalid(message: impl ToString) -> Self {
+        Self::Invalid(Json(InvalidSchema {
+            message: message.to_string(),
+            blocks: Vec::new(),
+        }))
+    }
+}
+
+impl From<SchemaError> for SchemaUploadError {
+    fn from(err: SchemaError) -> Self {
+        let message = err.to_string();
+        let blocks = match err {
+            SchemaError::Blocks(blocks) => blocks,
+            SchemaError::Syntax(_) => Vec::new(),
+        };
+
+        Self::Invalid(Json(InvalidSchema { message, blocks }))
+    }
+}
+
+/// Creates the tables of an HCL schema, see `create_tables`. Only for the admin scope.
+#[post("/schema", format = "text/plain", data = "<hcl>")]
+pub fn post_schema_hcl(
+    _admin: AdminScope,
+    tables: &State<Tables>,
+    catalog: &State<Catalog>,
+    hcl: String,
+) -> Result<Created<Json<Vec<TableSchema>>>, SchemaUploadError> {
+    create_tables(tables, catalog, &hcl)
+}
+
+/// Creates the tables of a schema given as JSON, see `SchemaJson`. Only for the admin scope.
+#[post("/schema", format = "json", data = "<schema>")]
+pub fn post_schema_json(
+    _admin: AdminScope,
+    tables: &State<Tables>,
+    catalog: &State<Catalog>,
+    schema: Json<SchemaJson>,
+) -> Result<Created<Json<Vec<TableSchema>>>, SchemaUploadError> {
+    let hcl = schema_to_hcl(&schema).map_err(SchemaUploadError::invalid)?;
+    create_tables(tables, catalog, &hcl)
+}
+
+/// Creates every table `hcl` defines and adds them to the catalog, answering with their columns.
+/// A schema that doesn't parse is `422 Unprocessable Entity`, and one defining a table that's
+/// already served or in the catalog is `409 Conflict`. Either way none of its tables are created.
+fn create_tables(
+    tables: &Tables,
+    catalog: &Catalog,
+    hcl: &str,
+) -> Result<Created<Json<Vec<TableSchema>>>, SchemaUploadError> {
+    let defs = parse_hcl(hcl)?;
+
+    // held until the tables are added, so two uploads of the same table can't both get through
+    let mut catalog = catalog.0.write();
+    let mut existing = Vec::new();
+
+    for (i, def) in defs.iter().enumerate() {
+        let taken = tables.0.contains_key(def.name())
+            || catalog.keys().any(|name| name.as_str() == def.name())
+            || defs[..i].iter().any(|other| other.name() == def.name());
+
+        if taken && !existing.iter().any(|name| name == def.name()) {
+            existing.push(def.name().to_string());
+        }
+    }
+
+    if !existing.is_empty() {
+        return Err(SchemaUploadError::Conflict(Json(SchemaConflict {
+            existing,
+        })));
+    }
+
+    let created = defs
+        .iter()
+        .map(|def| {
+            let name = InternalString::new(def.name())?;
+            Ok((name, def.create_table(TableId::new())?))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()
+        .map_err(|e| SchemaUploadError::invalid(format!("{:#}", e)))?;
+
+    let schemas = created
+        .iter()
+        .map(|(name, table)| TableSchema::new(name.as_str(), table))
+        .collect();
+
+    catalog.extend(created);
+
+    Ok(Created::new("/tables").body(Json(schemas)))
+}
+
+/// Writes a schema given as JSON as the HCL it stands for. Names and column types are checked to
+/// be one of each, so neither can end the table they're in.
+fn schema_to_hcl(schema: &SchemaJson) -> Result<String, String> {
+    let mut out = String::new();
+
+    for (table, attributes) in schema.tables.iter() {
+        if !is_identifier(table) {
+            return Err(format!("{:?} isn't a valid table name", table));
+        }
+
+        out.push_str(&format!("table \"{}\" {{\n", table));
+
+        for (key, value) in attributes {
+            if !is_identifier(key) {
+                return Err(format!("{:?} of table {} isn't a valid name", key, table));
+            }
+
+            let expr = match value {
+                Value::String(column_type) => {
+                    let single = hcl::from_str::<hcl::Body>(&format!("x = {}\n", column_type))
+                        .is_ok_and(|body| {
+                            body.iter().count() == 1 && body.attributes().count() == 1
+                        });
+
+                    if !single {
+                        return Err(format!(
+                            "column {} of table {} has an invalid type: {}",
+                            key, table, column_type
+                        ));
+                    }
+
+                    column_type.clone()
+                }
+                // JSON values are HCL too, once their strings can't be read as templates
+                value => serde_json::to_string(value)
+                    .map_err(|e| e.to_string())?
+                    .replace("${", "$${")
+                    .replace("%{", "%%{"),
+            };
+
+            out.push_str(&format!("  {} = {}\n", key, expr));
+        }
+
+        out.push_str("}\n");
+    }
+
+    Ok(out)
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
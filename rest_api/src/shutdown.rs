@@ -0,0 +1,38 @@
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::{Orbit, Rocket};
+
+use crate::rows::Tables;
+
+/// Closes every managed table when the server shuts down, so the stores are flushed in a known
+/// order instead of whenever their mappings happen to be dropped.
+pub struct ShutdownFairing;
+
+#[rocket::async_trait]
+impl Fairing for ShutdownFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "Shutdown Fairing",
+            kind: Kind::Shutdown,
+        }
+    }
+
+    async fn on_shutdown(&self, rocket: &Rocket<Orbit>) {
+        let Some(tables) = rocket.state::<Tables>().cloned() else {
+            return;
+        };
+
+        let closed = rocket::tokio::task::spawn_blocking(move || {
+            for (name, table) in tables.0.iter() {
+                match table.close() {
+                    Ok(_) => println!("Closed table: {}", name),
+                    Err(err) => eprintln!("WARNING: failed to close table {}: {:?}", name, err),
+                }
+            }
+        })
+        .await;
+
+        if let Err(err) = closed {
+            eprintln!("WARNING: failed to close tables: {:?}", err);
+        }
+    }
+}
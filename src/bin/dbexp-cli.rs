@@ -0,0 +1,219 @@
+//! Inspects a database directory without changing it: its schema, and the row count, integrity
+//! and stats of its tables.
+//!
+//! There's no catalog on disk yet, so the directory is read as `hcl_schemas::open` is given one:
+//! the tables are the ones defined in `schema.hcl` at its root, each persisted under
+//! `tables/<name>`, and a table's id is read from the meta of its record store. Tables are opened
+//! with `hcl_schemas::open`, which locks the database, so the CLI fails against a database a
+//! server has open rather than reading it from under the server.
+//!
+//! `integrity` and `stats` read the values of a table, which text and bytes columns can't give back
+//! outside of the process that wrote them: their slots hold pointers to buffers that are gone once
+//! it exits. Both refuse such tables instead of reading through those pointers.
+//!
+//! `head`, `query` and `export` aren't available yet. They'd write rows out, and there's no row
+//! formatter for them to share, nor a read-only open mode that takes shared locks and never
+//! flushes.
+
+use std::{
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand};
+use dbexp::records::Records;
+use hcl_schemas::{open, parse_hcl_file, validate, CatalogTable, ColumnDef, TableDef};
+use indexmap::IndexMap;
+use mem_table::{Table, TableConfig};
+use primitives::{DataType, InternalPath};
+
+const SCHEMA_FILE: &str = "schema.hcl";
+const TABLES_DIR: &str = "tables";
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "dbexp-cli",
+    about = "Inspects a database directory without changing it",
+    after_help = "Not available yet: head, query and export."
+)]
+struct Cli {
+    /// The database directory, with the schema.hcl it was created from at its root.
+    root: PathBuf,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Lists the tables and their columns, and what `validate` finds wrong with them.
+    Schema,
+    /// Prints how many rows a table has.
+    Count { table: String },
+    /// Cross-checks the records, columns and keys of a table, failing if anything is off.
+    Integrity { table: String },
+    /// Prints the row count, fragmentation and column capacity of a table as JSON.
+    Stats { table: String },
+}
+
+/// The catalog of the database at `root`, leaving out the tables defined in its schema that
+/// haven't been created yet.
+fn read_catalog(root: &Path, defs: &[TableDef]) -> Result<Vec<CatalogTable>> {
+    let mut catalog = Vec::with_capacity(defs.len());
+
+    for def in defs.iter() {
+        let names = def
+            .columns()
+            .iter()
+            .enumerate()
+            .map(|(idx, column)| (*column.name(), idx))
+            .collect::<IndexMap<_, _>>();
+        let config = def.table_config()?;
+        let config = TableConfig {
+            persistance: InternalPath::relative(Path::new(TABLES_DIR).join(def.name()))?,
+            columns: config.columns.with_names(&names),
+            ..config
+        };
+
+        let records = config.records_store_config()?.persistance.resolve(root);
+
+        if !records.exists() {
+            continue;
+        }
+
+        let meta = Records::read_image_meta(&records)
+            .with_context(|| format!("can't read the id of table {}", def.name()))?;
+
+        catalog.push(CatalogTable {
+            name: def.name().to_string(),
+            id: meta.table,
+            config,
+        });
+    }
+
+    Ok(catalog)
+}
+
+/// The type of `column` as it's written in the schema.
+fn type_name(column: &ColumnDef) -> String {
+    match column.logical_type() {
+        Some(logical_type) => logical_type.to_string(),
+        None => format!("{:?}", column.data_type()).replace("DataType::", ""),
+    }
+}
+
+fn print_schema(root: &Path, defs: &[TableDef], catalog: &[CatalogTable]) -> Result<bool> {
+    for def in defs.iter() {
+        match catalog.iter().find(|table| table.name == def.name()) {
+            Some(table) => println!("table {} ({})", def.name(), table.id),
+            None => println!("table {} (not created)", def.name()),
+        }
+
+        for column in def.columns().iter() {
+            let key = def.primary_key().contains(column.name());
+
+            println!(
+                "  {} = {}{}{}",
+                column.name(),
+                type_name(column),
+                if column.unique() { ", unique" } else { "" },
+                if key { ", primary key" } else { "" },
+            );
+        }
+    }
+
+    let report = validate(root.join(SCHEMA_FILE), catalog, Some(root))?;
+
+    if !report.is_ok() {
+        print!("{}", report);
+    }
+
+    Ok(report.is_ok())
+}
+
+/// Fails for a table with text or bytes columns, whose values can't be read from here.
+fn check_values_readable(defs: &[TableDef], name: &str) -> Result<()> {
+    let unreadable = defs
+        .iter()
+        .filter(|def| def.name() == name)
+        .flat_map(|def| def.columns().iter())
+        .find(|column| matches!(column.data_type(), DataType::Text(_) | DataType::Bytes(_)));
+
+    if let Some(column) = unreadable {
+        anyhow::bail!(
+            "can't read the values of {}, column {} is {} and only the process that wrote it can",
+            name,
+            column.name(),
+            type_name(column)
+        );
+    }
+
+    Ok(())
+}
+
+/// Opens the table `name` of the database at `root` and runs `f` on it.
+fn with_table(
+    root: &Path,
+    catalog: &[CatalogTable],
+    name: &str,
+    f: impl FnOnce(&Table) -> Result<bool>,
+) -> Result<bool> {
+    if !catalog.iter().any(|table| table.name == name) {
+        anyhow::bail!("no table {} in {}", name, root.display());
+    }
+
+    // drift is reported by `schema`, the tables are opened as they're on disk either way
+    let db = open(root.join(SCHEMA_FILE), catalog, Some(root), true)?;
+
+    f(db.table(name)?)
+}
+
+fn run(cli: Cli) -> Result<bool> {
+    let root = cli.root.as_path();
+    let defs = parse_hcl_file(root.join(SCHEMA_FILE))?;
+    let catalog = read_catalog(root, &defs)?;
+
+    match cli.command {
+        Command::Schema => print_schema(root, &defs, &catalog),
+        Command::Count { table } => with_table(root, &catalog, &table, |table| {
+            println!("{}", table.row_count());
+            Ok(true)
+        }),
+        Command::Integrity { table } => {
+            check_values_readable(&defs, &table)?;
+
+            with_table(root, &catalog, &table, |table| {
+                let report = table.check_integrity()?;
+
+                println!("{}", serde_json::to_string_pretty(&report)?);
+                Ok(report.is_ok())
+            })
+        }
+        Command::Stats { table } => {
+            check_values_readable(&defs, &table)?;
+
+            with_table(root, &catalog, &table, |table| {
+                let stats = serde_json::json!({
+                    "row_count": table.row_count(),
+                    "fragmentation": table.fragmentation_report()?,
+                    "capacity": table.capacity_report()?,
+                });
+
+                println!("{}", serde_json::to_string_pretty(&stats)?);
+                Ok(true)
+            })
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    match run(Cli::parse()) {
+        Ok(true) => ExitCode::SUCCESS,
+        // the command ran, and found something wrong
+        Ok(false) => ExitCode::from(2),
+        Err(error) => {
+            eprintln!("error: {:#}", error);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -2,39 +2,27 @@ use dbexp::object_ids::TableId;
 
 use anyhow::Result;
 use hcl_schemas::parse_hcl;
-use indexmap::IndexMap;
-use mem_table::{DataConfig, Table, TableConfig};
 
 fn main() -> anyhow::Result<()> {
     let hcl = r#"
         table "users" {
-            email = Email
+            email = unique(Email)
             first = Text(100)
             last  = Text(100)
             phone = Phone
+
+            rows {
+                row {
+                    email = "seed@example.com"
+                    first = "Seed"
+                }
+            }
         }
     "#;
 
     let tables = parse_hcl(hcl)?
-        .into_iter()
-        .map(|table_def| {
-            let id = TableId::new();
-            let mut name_mapping = IndexMap::new();
-
-            let columns = table_def
-                .columns()
-                .iter()
-                .enumerate()
-                .map(|(idx, column_def)| {
-                    name_mapping.insert(*column_def.name(), idx);
-                    DataConfig::new(column_def.data_type())
-                })
-                .collect::<Vec<_>>();
-
-            let config = TableConfig::new(&columns)?;
-
-            Table::new(id, config, Some(name_mapping))
-        })
+        .iter()
+        .map(|table_def| table_def.create_table(TableId::new()))
         .collect::<Result<Vec<_>>>()?;
 
     println!("{:#?}", tables);
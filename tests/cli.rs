@@ -0,0 +1,162 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+use anyhow::Result;
+use dbexp::{object_ids::TableId, values::DataValue};
+use hcl_schemas::{open, CatalogTable};
+use mem_table::{DataConfig, TableConfig};
+use primitives::DataType;
+
+fn cli(root: &Path, args: &[&str]) -> Result<Output> {
+    Ok(Command::new(env!("CARGO_BIN_EXE_dbexp-cli"))
+        .arg(root)
+        .args(args)
+        .output()?)
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+/// A database with three rows in `users` and two in `visits`, and `orders` defined but never
+/// created.
+fn fixture() -> Result<(PathBuf, Vec<CatalogTable>)> {
+    let dir = std::env::temp_dir().join(format!("dbexp_cli_{}", TableId::new()));
+    fs::create_dir_all(&dir)?;
+    fs::write(
+        dir.join("schema.hcl"),
+        r#"
+            table "users" {
+                age  = Number
+                name = Text(20)
+            }
+
+            table "orders" {
+                total = Number
+            }
+
+            table "visits" {
+                user  = Number
+                pages = Number
+            }
+        "#,
+    )?;
+
+    let catalog = vec![
+        CatalogTable {
+            name: "users".to_string(),
+            id: TableId::new(),
+            config: TableConfig::new_persisted_relative(
+                [
+                    DataConfig::new(DataType::Number),
+                    DataConfig::new(DataType::Text(20)),
+                ],
+                "tables/users",
+            )?,
+        },
+        CatalogTable {
+            name: "visits".to_string(),
+            id: TableId::new(),
+            config: TableConfig::new_persisted_relative(
+                [
+                    DataConfig::new(DataType::Number),
+                    DataConfig::new(DataType::Number),
+                ],
+                "tables/visits",
+            )?,
+        },
+    ];
+
+    let db = open(dir.join("schema.hcl"), &catalog, Some(&dir), true)?;
+    let users = db.table("users")?;
+
+    for (age, name) in [(31, "ada"), (42, "grace"), (27, "linus")] {
+        users.insert_one(vec![
+            Some(DataValue::try_from_any(DataType::Number, age)?),
+            Some(DataValue::try_from_any(
+                DataType::Text(20),
+                name.to_string(),
+            )?),
+        ])?;
+    }
+
+    let visits = db.table("visits")?;
+
+    for (user, pages) in [(1, 4), (2, 9)] {
+        visits.insert_one(vec![
+            Some(DataValue::try_from_any(DataType::Number, user)?),
+            Some(DataValue::try_from_any(DataType::Number, pages)?),
+        ])?;
+    }
+
+    users.close()?;
+    visits.close()?;
+
+    Ok((dir, catalog))
+}
+
+#[test]
+fn test_inspect_database() -> Result<()> {
+    let (dir, catalog) = fixture()?;
+    let schema = cli(&dir, &["schema"])?;
+    let printed = stdout(&schema);
+    assert!(
+        printed.contains(&format!("table users ({})", catalog[0].id)),
+        "{}",
+        printed
+    );
+    assert!(printed.contains("  name = Text(20)"), "{}", printed);
+    assert!(
+        printed.contains("table orders (not created)"),
+        "{}",
+        printed
+    );
+    // a table in the schema but not on disk is drift, which `schema` reports and fails on
+    assert!(printed.contains("defined in the schema but not in the catalog"));
+    assert_eq!(schema.status.code(), Some(2));
+
+    let count = cli(&dir, &["count", "users"])?;
+    assert!(count.status.success());
+    assert_eq!(stdout(&count).trim(), "3");
+
+    let integrity = cli(&dir, &["integrity", "visits"])?;
+    assert!(integrity.status.success(), "{}", stdout(&integrity));
+    assert!(stdout(&integrity).contains("\"rows_checked\": 2"));
+
+    let stats = cli(&dir, &["stats", "visits"])?;
+    assert!(stats.status.success());
+    assert!(stdout(&stats).contains("\"row_count\": 2"));
+
+    // the text values of `users` can't be read back from another process
+    for args in [&["integrity", "users"][..], &["stats", "users"]] {
+        let refused = cli(&dir, args)?;
+        assert_eq!(refused.status.code(), Some(1), "{:?}", args);
+        assert!(String::from_utf8_lossy(&refused.stderr).contains("column name is Text(20)"));
+    }
+
+    for args in [&["count", "orders"][..], &["count", "nope"]] {
+        assert_eq!(cli(&dir, args)?.status.code(), Some(1), "{:?}", args);
+    }
+
+    let head = cli(&dir, &["head", "users"])?;
+    assert!(!head.status.success());
+    assert!(String::from_utf8_lossy(&head.stderr).contains("unexpected argument 'head'"));
+
+    let help = cli(&dir, &["--help"])?;
+    assert!(stdout(&help).contains("Not available yet: head, query and export."));
+
+    // nothing is read from under a process that has the database open
+    let db = open(dir.join("schema.hcl"), &catalog, Some(&dir), true)?;
+    let locked = cli(&dir, &["count", "users"])?;
+    assert!(!locked.status.success());
+    drop(db);
+
+    assert_eq!(stdout(&cli(&dir, &["count", "users"])?).trim(), "3");
+
+    fs::remove_dir_all(&dir)?;
+
+    Ok(())
+}